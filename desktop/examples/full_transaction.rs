@@ -0,0 +1,80 @@
+//! Sign every output of a transaction under a single confirmation
+//! Runs against a [`DryRunTransport`], which answers every command with a well formed placeholder, and prints the
+//! APDUs it would send. To talk to a real device open it with `LedgerDevice::open` and use [`SignerMode::Device`], so
+//! that every signature is verified.
+
+use tari_crypto::{
+    keys::PublicKey,
+    ristretto::{RistrettoPublicKey, RistrettoSecretKey},
+    tari_utilities::hex::Hex,
+};
+use tari_ledger::{
+    address::{Network, TariAddress},
+    device::LedgerDevice,
+    domains::SCRIPT_CHALLENGE_LABEL,
+    dry_run::{DryRunLog, DryRunTransport},
+    fee::FeeCalculator,
+    hashing::{Challenge, DomainSeparatedConsensusHasher, TransactionHashDomain},
+    signer::{LedgerTransactionSigner, OutputToSign, SignerMode},
+};
+
+fn main() {
+    let log = DryRunLog::new();
+    let device = LedgerDevice::from_transport(DryRunTransport::new(log.clone()));
+    let signer = LedgerTransactionSigner::new(&device, FeeCalculator::new(5))
+        .with_mode(SignerMode::Offline)
+        .with_display_listener(|summary| println!("check that the device shows the digest {}", summary.fingerprint()));
+
+    // The device shows each recipient address before signing its output, these stand in for real ones
+    let recipient = |n: u64| {
+        let public_key = RistrettoPublicKey::from_secret_key(&RistrettoSecretKey::from(n));
+        Some(TariAddress::new(public_key, Network::Esmeralda))
+    };
+    // So do these for the script challenges the wallet computes
+    let challenge = |n: u64| -> Challenge {
+        DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(SCRIPT_CHALLENGE_LABEL)
+            .chain(&n)
+            .finalize_challenge()
+    };
+    let outputs = [
+        OutputToSign {
+            value: 1_000_000,
+            is_change: false,
+            features_and_scripts_size: 40,
+            challenge: challenge(1),
+            recipient: recipient(1),
+            features_title: None,
+        },
+        OutputToSign {
+            value: 250_000,
+            is_change: false,
+            features_and_scripts_size: 40,
+            challenge: challenge(2),
+            recipient: recipient(2),
+            features_title: None,
+        },
+        OutputToSign {
+            value: 48_000,
+            is_change: true,
+            features_and_scripts_size: 40,
+            challenge: challenge(3),
+            recipient: None,
+            features_title: None,
+        },
+    ];
+    let num_inputs = 2;
+    println!("fee: {} uT", signer.fee(num_inputs, &outputs));
+    let signed = signer
+        .sign_outputs(num_inputs, &outputs)
+        .expect("the transaction was not signed");
+    for (output, signature) in outputs.iter().zip(&signed.signatures) {
+        println!(
+            "{} uT: key {}, signature {}",
+            output.value,
+            signature.public_key.to_hex(),
+            signature.s.to_hex()
+        );
+    }
+
+    print!("{}", log.to_text());
+}
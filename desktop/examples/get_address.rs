@@ -0,0 +1,26 @@
+//! Derive the wallet address of a device
+//! Runs against a [`DryRunTransport`], which answers every command with a well formed placeholder, and prints the
+//! APDUs it would send. To talk to a real device open it with `LedgerDevice::open` instead.
+
+use tari_ledger::{
+    address::{Network, TariAddress},
+    device::{Capabilities, KeyBranch, LedgerDevice},
+    dry_run::{DryRunLog, DryRunTransport},
+    export::{key_path, public_key},
+};
+
+fn main() {
+    let log = DryRunLog::new();
+    let device = LedgerDevice::from_transport(DryRunTransport::new(log.clone()));
+    device
+        .require(Capabilities::PUBLIC_KEY_EXPORT)
+        .expect("the app cannot export public keys");
+
+    let key = public_key(&device, 0, KeyBranch::CommitmentMask, 0).expect("the device returned no public key");
+    let address = TariAddress::new(key, Network::Esmeralda);
+    println!("key path: {}", key_path(0, KeyBranch::CommitmentMask, 0));
+    println!("address: {}", address.to_hex());
+    println!("emoji address: {}", address.to_emoji_string());
+
+    print!("{}", log.to_text());
+}
@@ -0,0 +1,35 @@
+//! Sign a script challenge
+//! Runs against a [`DryRunTransport`], which answers every command with a well formed placeholder, and prints the
+//! APDUs it would send. To talk to a real device open it with `LedgerDevice::open` and use [`SignerMode::Device`], so
+//! that the signature is verified.
+
+use tari_crypto::tari_utilities::hex::Hex;
+use tari_ledger::{
+    device::LedgerDevice,
+    domains::SCRIPT_MESSAGE_LABEL,
+    dry_run::{DryRunLog, DryRunTransport},
+    fee::FeeCalculator,
+    hashing::{DomainSeparatedConsensusHasher, TransactionHashDomain},
+    signer::{LedgerTransactionSigner, SignerMode},
+};
+
+fn main() {
+    let log = DryRunLog::new();
+    let device = LedgerDevice::from_transport(DryRunTransport::new(log.clone()));
+    let handshake = device.handshake().expect("the handshake failed");
+    println!("app version: {}", handshake.app_version);
+
+    let signer = LedgerTransactionSigner::new(&device, FeeCalculator::new(5)).with_mode(SignerMode::Offline);
+    // Only a challenge hashed under a signing label can be signed, this one stands in for a real script message
+    let challenge = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(SCRIPT_MESSAGE_LABEL)
+        .chain(&42u64)
+        .finalize_challenge();
+    let signature = signer
+        .sign_script_message(&challenge)
+        .expect("the device did not sign the challenge");
+    println!("public key: {}", signature.public_key.to_hex());
+    println!("public nonce: {}", signature.public_nonce.to_hex());
+    println!("signature: {}", signature.s.to_hex());
+
+    print!("{}", log.to_text());
+}
@@ -0,0 +1,353 @@
+//! Tari addresses of device derived keys
+//! An address is the public key followed by a checksum byte that also encodes the network, and is usually shown as
+//! an Emoji ID: one emoji per byte, taken from the same 256 emoji dictionary the Tari wallets use.
+
+use std::{fmt, str::FromStr};
+
+use tari_crypto::{
+    ristretto::RistrettoPublicKey,
+    tari_utilities::{
+        hex::{from_hex, to_hex},
+        ByteArray,
+    },
+};
+
+use crate::errors::AddressError;
+
+/// The emoji dictionary, indexed by byte value
+pub const EMOJI: [char; 256] = [
+    '🌀', '🌂', '🌈', '🌊', '🌋', '🌍', '🌙', '🌝', '🌞', '🌟', '🌠', '🌰', '🌴', '🌵', '🌷', '🌸', '🌹', '🌻', '🌽',
+    '🍀', '🍁', '🍄', '🍅', '🍆', '🍇', '🍈', '🍉', '🍊', '🍋', '🍌', '🍍', '🍎', '🍐', '🍑', '🍒', '🍓', '🍔', '🍕',
+    '🍗', '🍚', '🍞', '🍟', '🍠', '🍣', '🍦', '🍩', '🍪', '🍫', '🍬', '🍭', '🍯', '🍰', '🍳', '🍴', '🍵', '🍶', '🍷',
+    '🍸', '🍹', '🍺', '🍼', '🎀', '🎁', '🎂', '🎃', '🎄', '🎈', '🎉', '🎒', '🎓', '🎠', '🎡', '🎢', '🎣', '🎤', '🎥',
+    '🎧', '🎨', '🎩', '🎪', '🎬', '🎭', '🎮', '🎰', '🎱', '🎲', '🎳', '🎵', '🎷', '🎸', '🎹', '🎺', '🎻', '🎼', '🎽',
+    '🎾', '🎿', '🏀', '🏁', '🏆', '🏈', '🏉', '🏠', '🏥', '🏦', '🏭', '🏰', '🐀', '🐉', '🐊', '🐌', '🐍', '🐎', '🐐',
+    '🐑', '🐓', '🐖', '🐗', '🐘', '🐙', '🐚', '🐛', '🐜', '🐝', '🐞', '🐢', '🐣', '🐨', '🐩', '🐪', '🐬', '🐭', '🐮',
+    '🐯', '🐰', '🐲', '🐳', '🐴', '🐵', '🐶', '🐷', '🐸', '🐺', '🐻', '🐼', '🐽', '🐾', '👀', '👅', '👑', '👒', '👓',
+    '👔', '👕', '👖', '👗', '👘', '👙', '👚', '👛', '👞', '👟', '👠', '👡', '👢', '👣', '👹', '👻', '👽', '👾', '👿',
+    '💀', '💄', '💈', '💉', '💊', '💋', '💌', '💍', '💎', '💐', '💔', '💕', '💘', '💡', '💣', '💤', '💦', '💨', '💩',
+    '💭', '💯', '💰', '💳', '💸', '💺', '💻', '💼', '📈', '📉', '📌', '📎', '📚', '📝', '📡', '📣', '📱', '📷', '🔋',
+    '🔌', '🔎', '🔑', '🔔', '🔥', '🔦', '🔧', '🔨', '🔩', '🔪', '🔫', '🔬', '🔭', '🔮', '🔱', '🗽', '😂', '😇', '😈',
+    '😉', '😍', '😎', '😱', '😷', '😹', '😻', '😿', '🚀', '🚁', '🚂', '🚌', '🚑', '🚒', '🚓', '🚕', '🚗', '🚜', '🚢',
+    '🚦', '🚧', '🚨', '🚪', '🚫', '🚲', '🚽', '🚿', '🛁',
+];
+
+/// The network an address is valid on
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Network {
+    #[default]
+    Mainnet,
+    Stagenet,
+    Nextnet,
+    Localnet,
+    Igor,
+    Esmeralda,
+}
+
+impl Network {
+    pub const fn as_byte(self) -> u8 {
+        match self {
+            Network::Mainnet => 0x00,
+            Network::Stagenet => 0x01,
+            Network::Nextnet => 0x02,
+            Network::Localnet => 0x10,
+            Network::Igor => 0x24,
+            Network::Esmeralda => 0x26,
+        }
+    }
+}
+
+impl TryFrom<u8> for Network {
+    type Error = AddressError;
+
+    fn try_from(v: u8) -> Result<Self, Self::Error> {
+        match v {
+            0x00 => Ok(Network::Mainnet),
+            0x01 => Ok(Network::Stagenet),
+            0x02 => Ok(Network::Nextnet),
+            0x10 => Ok(Network::Localnet),
+            0x24 => Ok(Network::Igor),
+            0x26 => Ok(Network::Esmeralda),
+            _ => Err(AddressError::InvalidNetworkOrChecksum),
+        }
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Network::Mainnet => "mainnet",
+            Network::Stagenet => "stagenet",
+            Network::Nextnet => "nextnet",
+            Network::Localnet => "localnet",
+            Network::Igor => "igor",
+            Network::Esmeralda => "esmeralda",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TariAddress {
+    network: Network,
+    public_key: RistrettoPublicKey,
+}
+
+impl TariAddress {
+    pub const ENCODED_LENGTH: usize = 33;
+
+    pub fn new(public_key: RistrettoPublicKey, network: Network) -> Self {
+        Self { network, public_key }
+    }
+
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    pub fn public_key(&self) -> &RistrettoPublicKey {
+        &self.public_key
+    }
+
+    /// The public key followed by its checksum XORed with the network byte
+    pub fn to_bytes(&self) -> [u8; Self::ENCODED_LENGTH] {
+        let mut bytes = [0u8; Self::ENCODED_LENGTH];
+        bytes[0..32].copy_from_slice(self.public_key.as_bytes());
+        bytes[32] = checksum(&bytes[0..32]) ^ self.network.as_byte();
+        bytes
+    }
+
+    /// Decode an address, recovering the network from the checksum byte
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, AddressError> {
+        if bytes.len() != Self::ENCODED_LENGTH {
+            return Err(AddressError::InvalidSize);
+        }
+        let network = Network::try_from(checksum(&bytes[0..32]) ^ bytes[32])?;
+        let public_key = RistrettoPublicKey::from_bytes(&bytes[0..32]).map_err(|_| AddressError::InvalidPublicKey)?;
+        Ok(Self { network, public_key })
+    }
+
+    pub fn to_emoji_string(&self) -> String {
+        self.to_bytes().iter().map(|b| EMOJI[*b as usize]).collect()
+    }
+
+    pub fn from_emoji_string(emoji: &str) -> Result<Self, AddressError> {
+        let bytes = emoji
+            .chars()
+            .map(|c| {
+                EMOJI
+                    .iter()
+                    .position(|e| *e == c)
+                    .map(|i| i as u8)
+                    .ok_or(AddressError::InvalidEmoji)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::from_bytes(&bytes)
+    }
+
+    pub fn to_hex(&self) -> String {
+        to_hex(&self.to_bytes())
+    }
+}
+
+/// Addresses are shown as Emoji IDs
+impl fmt::Display for TariAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_emoji_string())
+    }
+}
+
+/// Parse an Emoji ID or a hex encoded address
+impl FromStr for TariAddress {
+    type Err = AddressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match from_hex(s) {
+            Ok(bytes) => Self::from_bytes(&bytes),
+            Err(_) => Self::from_emoji_string(s),
+        }
+    }
+}
+
+/// The Damm checksum over GF(2^8) that the Tari wallets use
+fn checksum(data: &[u8]) -> u8 {
+    // x^8 + x^4 + x^3 + x + 1, without the x^8 term
+    const MASK: u8 = 0x1b;
+    data.iter().fold(0u8, |result, digit| {
+        let result = result ^ digit;
+        if result & 0x80 != 0 {
+            (result << 1) ^ MASK
+        } else {
+            result << 1
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use tari_crypto::{keys::PublicKey, ristretto::RistrettoSecretKey};
+
+    use super::*;
+
+    const NETWORKS: [Network; 6] = [
+        Network::Mainnet,
+        Network::Stagenet,
+        Network::Nextnet,
+        Network::Localnet,
+        Network::Igor,
+        Network::Esmeralda,
+    ];
+
+    fn address(n: u64, network: Network) -> TariAddress {
+        TariAddress::new(
+            RistrettoPublicKey::from_secret_key(&RistrettoSecretKey::from(n)),
+            network,
+        )
+    }
+
+    /// The encoding of the identity key, whose checksum is known, on `network` with `tamper` applied to its bytes
+    fn tampered<F: FnOnce(&mut [u8; TariAddress::ENCODED_LENGTH])>(
+        network: Network,
+        tamper: F,
+    ) -> [u8; TariAddress::ENCODED_LENGTH] {
+        let mut bytes = TariAddress::new(RistrettoPublicKey::default(), network).to_bytes();
+        tamper(&mut bytes);
+        bytes
+    }
+
+    #[test]
+    fn emoji_are_unique() {
+        for (i, emoji) in EMOJI.iter().enumerate() {
+            assert!(!EMOJI[i + 1..].contains(emoji), "{} is in the dictionary twice", emoji);
+        }
+    }
+
+    #[test]
+    fn networks_round_trip() {
+        for network in NETWORKS {
+            assert_eq!(Network::try_from(network.as_byte()), Ok(network));
+        }
+        assert_eq!(Network::try_from(0x03), Err(AddressError::InvalidNetworkOrChecksum));
+        assert_eq!(Network::default(), Network::Mainnet);
+        assert_eq!(Network::Esmeralda.to_string(), "esmeralda");
+    }
+
+    #[test]
+    fn addresses_round_trip() {
+        for (n, network) in NETWORKS.into_iter().enumerate() {
+            let address = address(n as u64 + 1, network);
+            let bytes = address.to_bytes();
+            assert_eq!(&bytes[..32], address.public_key().as_bytes());
+            assert_eq!(TariAddress::from_bytes(&bytes), Ok(address.clone()));
+
+            let emoji = address.to_emoji_string();
+            assert_eq!(emoji.chars().count(), TariAddress::ENCODED_LENGTH);
+            assert_eq!(address.to_string(), emoji);
+            assert_eq!(TariAddress::from_emoji_string(&emoji), Ok(address.clone()));
+            assert_eq!(emoji.parse::<TariAddress>(), Ok(address.clone()));
+
+            let hex = address.to_hex();
+            assert_eq!(hex.len(), 2 * TariAddress::ENCODED_LENGTH);
+            assert_eq!(hex.parse::<TariAddress>(), Ok(address.clone()));
+            assert_eq!(address.network(), network);
+        }
+    }
+
+    #[test]
+    fn the_network_is_in_the_checksum() {
+        let mainnet = address(1, Network::Mainnet).to_bytes();
+        let esmeralda = address(1, Network::Esmeralda).to_bytes();
+        assert_eq!(mainnet[..32], esmeralda[..32]);
+        assert_eq!(mainnet[32] ^ esmeralda[32], Network::Esmeralda.as_byte());
+    }
+
+    #[test]
+    fn a_changed_byte_is_rejected() {
+        // Any bit flipped in the first bytes of the key moves the checksum off every network
+        for network in [Network::Mainnet, Network::Esmeralda] {
+            for position in 0..23 {
+                for bit in 0..8 {
+                    let bytes = tampered(network, |bytes| bytes[position] ^= 1 << bit);
+                    assert_eq!(
+                        TariAddress::from_bytes(&bytes),
+                        Err(AddressError::InvalidNetworkOrChecksum),
+                        "{} bit {} of byte {}",
+                        network,
+                        bit,
+                        position
+                    );
+                }
+            }
+        }
+        // As is a checksum byte that matches no network
+        let bytes = tampered(Network::Mainnet, |bytes| bytes[32] ^= 0x03);
+        assert_eq!(
+            TariAddress::from_bytes(&bytes),
+            Err(AddressError::InvalidNetworkOrChecksum)
+        );
+    }
+
+    #[test]
+    fn swapped_bytes_are_rejected() {
+        let mut key = [0u8; 32];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = i as u8 + 1;
+        }
+        for network in NETWORKS {
+            let mut bytes = [0u8; TariAddress::ENCODED_LENGTH];
+            bytes[..32].copy_from_slice(&key);
+            bytes[32] = checksum(&key) ^ network.as_byte();
+            for position in 0..31 {
+                let mut swapped = bytes;
+                swapped.swap(position, position + 1);
+                assert_eq!(
+                    TariAddress::from_bytes(&swapped),
+                    Err(AddressError::InvalidNetworkOrChecksum),
+                    "{} bytes {} and {}",
+                    network,
+                    position,
+                    position + 1
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_addresses_are_rejected() {
+        let address = address(1, Network::Mainnet);
+        let bytes = address.to_bytes();
+        assert_eq!(TariAddress::from_bytes(&bytes[..32]), Err(AddressError::InvalidSize));
+        assert_eq!(
+            TariAddress::from_bytes(&[bytes.as_slice(), &[0]].concat()),
+            Err(AddressError::InvalidSize)
+        );
+
+        let emoji = address.to_emoji_string();
+        let mut short = emoji.clone();
+        short.pop();
+        assert_eq!(TariAddress::from_emoji_string(&short), Err(AddressError::InvalidSize));
+        assert_eq!(
+            TariAddress::from_emoji_string(&format!("{}a", short)),
+            Err(AddressError::InvalidEmoji)
+        );
+        assert_eq!("not an address".parse::<TariAddress>(), Err(AddressError::InvalidEmoji));
+
+        // A valid checksum over bytes that are not a point
+        let mut bytes = [0xffu8; TariAddress::ENCODED_LENGTH];
+        bytes[32] = checksum(&bytes[..32]) ^ Network::Mainnet.as_byte();
+        assert_eq!(TariAddress::from_bytes(&bytes), Err(AddressError::InvalidPublicKey));
+    }
+
+    #[test]
+    fn the_checksum_is_the_wallets_damm_checksum() {
+        assert_eq!(checksum(&[]), 0);
+        assert_eq!(checksum(&[0x01]), 0x02);
+        // The high bit is reduced by the polynomial
+        assert_eq!(checksum(&[0x80]), 0x1b);
+        assert_eq!(checksum(&[0x01, 0x02]), 0x00);
+    }
+}
@@ -0,0 +1,368 @@
+//! What the device reports about itself
+//! The Ledger OS answers a few commands of its own: `GET_APP_AND_VERSION` names the app that is open and the
+//! dashboard's `GET_VERSION` describes the firmware. Neither goes through the Tari app, so both are exchanged on the
+//! bare transport, outside of any authenticated session. The OS offers no way to read the remaining storage or the
+//! installed apps without the Ledger manager's secure channel, so a report only covers what the device tells anyone.
+//!
+//! The Tari app's own `GetVersion` reports its [`AppSettings`]. Requests the device cannot show the user anything
+//! meaningful about, such as signing a raw challenge, are refused until the user enables them in the app's settings
+//! menu, the way other hardware wallet apps gate blind signing. Apps with [`Capabilities::SETTINGS_CHANGES`] also let
+//! the host change them with [`change_app_settings`], each change confirmed on the device, so that support can walk a
+//! user through them from the command line.
+
+use std::fmt;
+
+use ledger_transport::APDUCommand;
+use tari_crypto::tari_utilities::hex::to_hex;
+use tari_ledger_protocol::{
+    GET_SETTINGS_RESPONSE_LENGTH,
+    P1_SETTINGS_GET,
+    P1_SETTINGS_SET,
+    SETTINGS_ALL,
+    SETTING_BLIND_SIGNING,
+    SETTING_EXPERT_MODE,
+    SETTING_MICRO_TARI,
+    SET_SETTINGS_RESPONSE_LENGTH,
+    SW_OK,
+};
+
+use crate::{
+    device::{Capabilities, HandshakeInfo, Instruction, LedgerDevice},
+    errors::DeviceError,
+};
+
+/// The name the Tari app is installed under
+pub const TARI_APP_NAME: &str = "tari";
+/// The name the OS reports while no app is open
+pub const DASHBOARD_APP_NAME: &str = "BOLOS";
+
+const CLA_OS: u8 = 0xb0;
+const INS_GET_APP_AND_VERSION: u8 = 0x01;
+const CLA_DASHBOARD: u8 = 0xe0;
+const INS_GET_VERSION: u8 = 0x01;
+
+/// The app that is open, as reported by the OS
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AppInfo {
+    pub name: String,
+    pub version: String,
+    pub flags: Vec<u8>,
+}
+
+impl AppInfo {
+    /// Parse a `[format][name length][name][version length][version][flags length][flags]` response
+    pub fn from_response(data: &[u8]) -> Result<Self, DeviceError> {
+        let mut reader = Reader(data);
+        reader.take(1)?;
+        Ok(Self {
+            name: reader.string()?,
+            version: reader.string()?,
+            flags: reader.field().unwrap_or_default().to_vec(),
+        })
+    }
+
+    pub fn is_dashboard(&self) -> bool {
+        self.name == DASHBOARD_APP_NAME
+    }
+
+    pub fn is_tari(&self) -> bool {
+        self.name == TARI_APP_NAME
+    }
+}
+
+/// The firmware of the device, only reported while the dashboard is open
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FirmwareInfo {
+    /// Identifies the device model and hardware revision
+    pub target_id: u32,
+    /// The version of the secure element firmware, e.g. `2.1.0`
+    pub se_version: String,
+    pub flags: Vec<u8>,
+    /// The version of the MCU firmware, which older devices do not report
+    pub mcu_version: Option<String>,
+}
+
+impl FirmwareInfo {
+    /// Parse a `[target id][SE version length][SE version][flags length][flags][MCU version length][MCU version]`
+    /// response
+    pub fn from_response(data: &[u8]) -> Result<Self, DeviceError> {
+        let mut reader = Reader(data);
+        let mut target_id = [0u8; 4];
+        target_id.copy_from_slice(reader.take(4)?);
+        let se_version = reader.string()?;
+        let flags = reader.field()?.to_vec();
+        // The MCU version is zero terminated on some firmware
+        let mcu_version = reader
+            .field()
+            .ok()
+            .map(|version| String::from_utf8_lossy(version).trim_end_matches('\0').to_string());
+        Ok(Self {
+            target_id: u32::from_be_bytes(target_id),
+            se_version,
+            flags,
+            mcu_version,
+        })
+    }
+}
+
+/// The settings the user enabled in the Tari app
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AppSettings {
+    /// Allows `Instruction::Sign` to sign challenges the device cannot show anything about
+    pub blind_signing: bool,
+    /// Allows `Instruction::BPData` to multiply any scalar with the app key
+    pub expert_mode: bool,
+    /// Shows amounts in microTari rather than in Tari
+    pub micro_tari: bool,
+}
+
+impl AppSettings {
+    /// What an app that predates the settings allows
+    pub const UNRESTRICTED: Self = Self {
+        blind_signing: true,
+        expert_mode: true,
+        micro_tari: false,
+    };
+
+    pub fn from_flags(flags: u8) -> Self {
+        Self {
+            blind_signing: flags & SETTING_BLIND_SIGNING != 0,
+            expert_mode: flags & SETTING_EXPERT_MODE != 0,
+            micro_tari: flags & SETTING_MICRO_TARI != 0,
+        }
+    }
+
+    pub fn to_flags(&self) -> u8 {
+        [
+            (self.blind_signing, SETTING_BLIND_SIGNING),
+            (self.expert_mode, SETTING_EXPERT_MODE),
+            (self.micro_tari, SETTING_MICRO_TARI),
+        ]
+        .iter()
+        .filter(|(enabled, _)| *enabled)
+        .fold(0, |flags, (_, setting)| flags | setting)
+    }
+
+    /// Whether every `SETTING_*` flag in `setting` is enabled
+    pub fn allows(&self, setting: u8) -> bool {
+        setting & !self.to_flags() == 0
+    }
+}
+
+impl fmt::Display for AppSettings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let on_off = |enabled: bool| if enabled { "on" } else { "off" };
+        write!(
+            f,
+            "blind signing {}, expert mode {}, microTari amounts {}",
+            on_off(self.blind_signing),
+            on_off(self.expert_mode),
+            on_off(self.micro_tari)
+        )
+    }
+}
+
+/// Whether the Tari app is open, and what it reports if it is
+#[derive(Clone, Debug)]
+pub enum TariAppState {
+    Open {
+        handshake: HandshakeInfo,
+        capabilities: Capabilities,
+        settings: AppSettings,
+    },
+    /// The dashboard or another app is open, so it cannot be told whether the Tari app is installed
+    NotOpen,
+}
+
+/// Everything the device reports without the Ledger manager
+#[derive(Clone, Debug)]
+pub struct FullDeviceReport {
+    pub app: AppInfo,
+    pub firmware: Option<FirmwareInfo>,
+    pub tari_app: TariAppState,
+}
+
+impl fmt::Display for FullDeviceReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "open app: {} {}", self.app.name, self.app.version)?;
+        writeln!(f, "app flags: {}", to_hex(&self.app.flags))?;
+        match &self.firmware {
+            Some(firmware) => {
+                writeln!(f, "target id: {:#010x}", firmware.target_id)?;
+                writeln!(f, "SE firmware: {}", firmware.se_version)?;
+                writeln!(
+                    f,
+                    "MCU firmware: {}",
+                    firmware.mcu_version.as_deref().unwrap_or("unknown")
+                )?;
+                writeln!(f, "device flags: {}", to_hex(&firmware.flags))?;
+            },
+            None => writeln!(f, "firmware: only reported from the dashboard")?,
+        }
+        match &self.tari_app {
+            TariAppState::Open {
+                handshake,
+                capabilities,
+                settings,
+            } => {
+                writeln!(f, "Tari app: open, version {}", handshake.app_version)?;
+                writeln!(f, "min client version: {}", handshake.min_client_version)?;
+                writeln!(f, "capabilities: {}", capabilities)?;
+                write!(f, "settings: {}", settings)
+            },
+            TariAppState::NotOpen => write!(f, "Tari app: not open"),
+        }
+    }
+}
+
+/// The app that is open on the device
+pub fn app_info(device: &LedgerDevice) -> Result<AppInfo, DeviceError> {
+    AppInfo::from_response(&os_command(device, CLA_OS, INS_GET_APP_AND_VERSION)?)
+}
+
+/// The firmware of the device, or `None` if an app is open and the dashboard cannot answer
+pub fn firmware_info(device: &LedgerDevice) -> Result<Option<FirmwareInfo>, DeviceError> {
+    match os_command(device, CLA_DASHBOARD, INS_GET_VERSION) {
+        Ok(data) => FirmwareInfo::from_response(&data).map(Some),
+        Err(DeviceError::Status(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Collect a [`FullDeviceReport`], running the handshake if the Tari app is open
+pub fn full_device_report(device: &LedgerDevice) -> Result<FullDeviceReport, DeviceError> {
+    let app = app_info(device)?;
+    let firmware = if app.is_dashboard() {
+        firmware_info(device)?
+    } else {
+        None
+    };
+    let tari_app = if app.is_tari() {
+        TariAppState::Open {
+            handshake: device.handshake()?,
+            capabilities: device.capabilities()?,
+            settings: app_settings(device)?,
+        }
+    } else {
+        TariAppState::NotOpen
+    };
+    Ok(FullDeviceReport {
+        app,
+        firmware,
+        tari_app,
+    })
+}
+
+/// The settings the user enabled in the Tari app, which has to be open
+pub fn app_settings(device: &LedgerDevice) -> Result<AppSettings, DeviceError> {
+    if !device.capabilities()?.contains(Capabilities::APP_SETTINGS) {
+        return Ok(AppSettings::UNRESTRICTED);
+    }
+    let response = device.send(Instruction::GetVersion, 0x00, 0x00, vec![])?;
+    // `[format][name length][name][version length][version][settings]`
+    let mut reader = Reader(&response);
+    reader.take(1)?;
+    reader.field()?;
+    reader.field()?;
+    Ok(AppSettings::from_flags(reader.take(1)?[0]))
+}
+
+/// Fail with [`DeviceError::SettingDisabled`] unless the user enabled `setting`, one of the `SETTING_*` flags, so
+/// that the user is told what to enable instead of the device refusing with a bare status word
+pub fn require_setting(device: &LedgerDevice, setting: u8) -> Result<(), DeviceError> {
+    if app_settings(device)?.allows(setting) {
+        Ok(())
+    } else {
+        Err(DeviceError::SettingDisabled(setting_name(setting)))
+    }
+}
+
+pub fn setting_name(setting: u8) -> &'static str {
+    match setting {
+        SETTING_BLIND_SIGNING => "blind signing",
+        SETTING_EXPERT_MODE => "expert mode",
+        SETTING_MICRO_TARI => "microTari amounts",
+        _ => "a setting",
+    }
+}
+
+/// Every `SETTING_*` flag, in the order the app's settings menu asks about them
+pub const SETTINGS: [u8; 3] = [SETTING_BLIND_SIGNING, SETTING_EXPERT_MODE, SETTING_MICRO_TARI];
+
+/// The settings of the Tari app, with the `SETTING_*` flags it lets a host enable
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SettingsReport {
+    pub settings: AppSettings,
+    pub host_enabled: u8,
+}
+
+/// The settings of the Tari app and which of them a host may enable
+pub fn settings_report(device: &LedgerDevice) -> Result<SettingsReport, DeviceError> {
+    device.require(Capabilities::SETTINGS_CHANGES)?;
+    let response = device.send(Instruction::AppSettings, P1_SETTINGS_GET, 0x00, vec![])?;
+    let payload = device.response_payload(&response, GET_SETTINGS_RESPONSE_LENGTH)?;
+    Ok(SettingsReport {
+        settings: AppSettings::from_flags(payload[0]),
+        host_enabled: payload[1],
+    })
+}
+
+/// Enable the `SETTING_*` flags of `enable` and disable those of `disable`, once the user confirms every change on the
+/// device, returning the settings the app has then. Any setting can be disabled, but only those the app reports in
+/// [`SettingsReport::host_enabled`] enabled.
+pub fn change_app_settings(device: &LedgerDevice, enable: u8, disable: u8) -> Result<AppSettings, DeviceError> {
+    let report = settings_report(device)?;
+    if (enable | disable) & !SETTINGS_ALL != 0 {
+        return Err(DeviceError::Unsupported("settings this client does not know of"));
+    }
+    if let Some(setting) = (0..8)
+        .map(|bit| 1u8 << bit)
+        .find(|setting| enable & setting & !report.host_enabled != 0)
+    {
+        return Err(DeviceError::SettingDisabled(setting_name(setting)));
+    }
+    let response = device.send(Instruction::AppSettings, P1_SETTINGS_SET, 0x00, vec![
+        enable,
+        enable | disable,
+    ])?;
+    let payload = device.response_payload(&response, SET_SETTINGS_RESPONSE_LENGTH)?;
+    Ok(AppSettings::from_flags(payload[0]))
+}
+
+fn os_command(device: &LedgerDevice, cla: u8, ins: u8) -> Result<Vec<u8>, DeviceError> {
+    let command = APDUCommand {
+        cla,
+        ins,
+        p1: 0x00,
+        p2: 0x00,
+        data: vec![],
+    };
+    let answer = device.transport().exchange(&command)?;
+    match answer.retcode() {
+        SW_OK => Ok(answer.data().to_vec()),
+        sw => Err(DeviceError::Status(sw)),
+    }
+}
+
+/// Reads the length prefixed fields of an OS response
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], DeviceError> {
+        if self.0.len() < length {
+            return Err(DeviceError::InvalidResponse("the device info response is truncated"));
+        }
+        let (field, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Ok(field)
+    }
+
+    fn field(&mut self) -> Result<&'a [u8], DeviceError> {
+        let length = self.take(1)?[0];
+        self.take(usize::from(length))
+    }
+
+    fn string(&mut self) -> Result<String, DeviceError> {
+        Ok(String::from_utf8_lossy(self.field()?).into_owned())
+    }
+}
@@ -0,0 +1,102 @@
+//! The balance of the wallet, from what the host can see
+//! The host cannot see the chain, so the balance is built from the console wallet's export of its unspent outputs and
+//! from the messages of interactive transactions still under way. Every exported output is checked against the device
+//! like the outputs of a sweep, and only those the device can spend count. An output with a maturity above the tip
+//! height is time locked. A [`SenderMessage`] this wallet sent is pending out, its amount and fee, and one it received
+//! is pending in, until the wallet's export reflects the transaction.
+
+use std::fmt;
+
+use serde_json::json;
+
+use crate::{
+    errors::SweepError,
+    signer::LedgerTransactionSigner,
+    sweep::{scan_outputs, SweepInput},
+    transaction_protocol::SenderMessage,
+    units::AmountFormat,
+};
+
+/// The balance of the wallet, in microTari
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Balance {
+    /// Spendable now
+    pub available: u64,
+    /// Spendable once the chain reaches the maturity of the outputs
+    pub time_locked: u64,
+    /// The amounts of transactions being received
+    pub pending_in: u64,
+    /// The amounts and fees of transactions being sent
+    pub pending_out: u64,
+    /// Exported outputs the device cannot spend, from another seed or a different index, which count towards nothing
+    pub foreign: u64,
+}
+
+impl Balance {
+    pub fn to_json(&self) -> String {
+        let balance = json!({
+            "available": self.available,
+            "time_locked": self.time_locked,
+            "pending_in": self.pending_in,
+            "pending_out": self.pending_out,
+            "foreign": self.foreign,
+        });
+        serde_json::to_string_pretty(&balance).expect("a balance always serializes")
+    }
+
+    /// The balance for the user, with the amounts in `format`
+    pub fn to_text(&self, format: &AmountFormat) -> String {
+        let mut text = format!(
+            "available:   {}\ntime locked: {}\npending in:  {}\npending out: {}",
+            format.format(self.available),
+            format.format(self.time_locked),
+            format.format(self.pending_in),
+            format.format(self.pending_out)
+        );
+        if self.foreign > 0 {
+            text.push_str(&format!(
+                "\n{} in outputs the device cannot spend",
+                format.format(self.foreign)
+            ));
+        }
+        text
+    }
+}
+
+impl fmt::Display for Balance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_text(&AmountFormat::default()))
+    }
+}
+
+/// The balance of `outputs` at `tip_height`, with the transactions of `incoming` being received and those of
+/// `outgoing` being sent
+pub fn balance(
+    signer: &LedgerTransactionSigner,
+    outputs: Vec<SweepInput>,
+    tip_height: u64,
+    incoming: &[SenderMessage],
+    outgoing: &[SenderMessage],
+) -> Result<Balance, SweepError> {
+    // Nothing is dust here, every output the device can spend counts
+    let scan = scan_outputs(signer, outputs, 0)?;
+    let mut balance = Balance::default();
+    for output in scan.inputs.iter().chain(&scan.dust) {
+        if output.maturity > tip_height {
+            balance.time_locked = balance.time_locked.saturating_add(output.value);
+        } else {
+            balance.available = balance.available.saturating_add(output.value);
+        }
+    }
+    balance.foreign = scan
+        .foreign
+        .iter()
+        .fold(0u64, |total, output| total.saturating_add(output.value));
+    balance.pending_in = incoming
+        .iter()
+        .fold(0u64, |total, message| total.saturating_add(message.amount));
+    balance.pending_out = outgoing.iter().fold(0u64, |total, message| {
+        total.saturating_add(message.amount).saturating_add(message.fee)
+    });
+    Ok(balance)
+}
@@ -0,0 +1,113 @@
+//! The wallet birthday and derivation metadata
+//! A wallet recovered from its seed has to scan the chain for its outputs, and without knowing when it was created the
+//! scan starts at genesis. The app records the block height the wallet was created at, its birthday, next to the seed
+//! it belongs to, and the host keeps a copy in its state store as the [`BIRTHDAY_CHECKPOINT`] so that recovery scans
+//! can start from it even without the device. [`WalletMetadata`] bundles the birthday with the derivation paths of
+//! every key branch, everything a wallet needs to recover the keys of an account besides the seed.
+
+use tari_ledger_protocol::{
+    GET_BIRTHDAY_RESPONSE_LENGTH,
+    P1_BIRTHDAY_GET,
+    P1_BIRTHDAY_SET,
+    SET_BIRTHDAY_RESPONSE_LENGTH,
+};
+
+use crate::{
+    device::{Capabilities, Instruction, KeyBranch, LedgerDevice},
+    errors::{DeviceError, StoreError},
+    export::branch_path,
+    state_store::{LedgerStateStore, ScanCheckpoint},
+    wallet::wallet_fingerprint,
+};
+
+/// The checkpoint the birthday is kept under. A birthday has no block hash, it is all zeros.
+pub const BIRTHDAY_CHECKPOINT: &str = "birthday";
+
+/// The birthday the app recorded for the seed it is unlocked with, if any
+pub fn wallet_birthday(device: &LedgerDevice) -> Result<Option<u64>, DeviceError> {
+    device.require(Capabilities::WALLET_BIRTHDAY)?;
+    let response = device.send(Instruction::WalletBirthday, P1_BIRTHDAY_GET, 0x00, vec![])?;
+    let payload = device.response_payload(&response, GET_BIRTHDAY_RESPONSE_LENGTH)?;
+    let mut height = [0u8; 8];
+    height.copy_from_slice(payload);
+    // Nothing is created at genesis, so 0 means no birthday was recorded
+    Ok(Some(u64::from_le_bytes(height)).filter(|height| *height > 0))
+}
+
+/// Record `height` as the birthday of the seed the device is unlocked with, once the user confirms it on the device.
+/// A height of 0 clears the birthday.
+pub fn set_wallet_birthday(device: &LedgerDevice, height: u64) -> Result<(), DeviceError> {
+    device.require(Capabilities::WALLET_BIRTHDAY)?;
+    let response = device.send(
+        Instruction::WalletBirthday,
+        P1_BIRTHDAY_SET,
+        0x00,
+        height.to_le_bytes().to_vec(),
+    )?;
+    device.response_payload(&response, SET_BIRTHDAY_RESPONSE_LENGTH)?;
+    Ok(())
+}
+
+/// The birthday the host kept in `store`, if any
+pub fn stored_birthday(store: &dyn LedgerStateStore) -> Result<Option<u64>, StoreError> {
+    Ok(store
+        .checkpoint(BIRTHDAY_CHECKPOINT)?
+        .map(|checkpoint| checkpoint.height))
+}
+
+/// Keep `height` in `store` as the birthday of the wallet the store belongs to
+pub fn persist_birthday(store: &dyn LedgerStateStore, height: u64) -> Result<(), StoreError> {
+    store.set_checkpoint(BIRTHDAY_CHECKPOINT, &ScanCheckpoint {
+        height,
+        block_hash: [0u8; 32],
+    })
+}
+
+/// The height a recovery scan of the wallet of `store` starts at: its birthday, or genesis if it has none
+pub fn recovery_start_height(store: &dyn LedgerStateStore) -> Result<u64, StoreError> {
+    Ok(stored_birthday(store)?.unwrap_or(0))
+}
+
+/// What a wallet needs besides the seed to recover the keys of an account
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WalletMetadata {
+    /// Identifies the seed without revealing any key, see [`wallet_fingerprint`]
+    pub fingerprint: String,
+    pub birthday: Option<u64>,
+    pub account: u32,
+}
+
+impl WalletMetadata {
+    /// The derivation path of every key branch of the account
+    pub fn branch_paths(&self) -> Vec<(KeyBranch, String)> {
+        KeyBranch::ALL
+            .iter()
+            .map(|branch| (*branch, branch_path(self.account, *branch)))
+            .collect()
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> String {
+        let branches = self
+            .branch_paths()
+            .into_iter()
+            .map(|(branch, path)| serde_json::json!({ "branch": branch.name(), "path": path }))
+            .collect::<Vec<_>>();
+        let metadata = serde_json::json!({
+            "wallet_fingerprint": self.fingerprint,
+            "birthday": self.birthday,
+            "account": self.account,
+            "branches": branches,
+        });
+        serde_json::to_string_pretty(&metadata).expect("a JSON value always serializes")
+    }
+}
+
+/// The metadata of `account` of the wallet the device is unlocked with
+pub fn wallet_metadata(device: &LedgerDevice, account: u32) -> Result<WalletMetadata, DeviceError> {
+    Ok(WalletMetadata {
+        fingerprint: wallet_fingerprint(device)?,
+        birthday: wallet_birthday(device)?,
+        account,
+    })
+}
@@ -0,0 +1,82 @@
+//! Blinded public key requests
+//! A `GetPublicKeys` request names the account and index of every key in the clear, so whoever watches the USB link
+//! learns which keys the wallet uses and can follow them on chain. A blinded request masks the path with a secret
+//! only the host and the app can derive, from a fresh host key and the app key, and the app offsets the key it returns
+//! by a factor derived from the same secret. The host removes the offset, the link only ever carries one-time values.
+//!
+//! The app key has to be known beforehand, e.g. from an authenticated session. A key fetched over the same link only
+//! protects against an observer that does not also tamper with it.
+
+use tari_crypto::{
+    keys::PublicKey,
+    ristretto::{RistrettoPublicKey, RistrettoSecretKey},
+    tari_utilities::ByteArray,
+};
+use tari_ledger_protocol::{
+    KeyBranch,
+    BLINDED_KEY_LABEL,
+    BLINDED_PATH_LABEL,
+    BLINDED_PATH_LENGTH,
+    GET_BLINDED_PUBLIC_KEY_RESPONSE_LENGTH,
+};
+
+use crate::{
+    errors::DeviceError,
+    hashing::{DomainSeparatedConsensusHasher, TransactionHashDomain},
+};
+
+/// One `GetBlindedPublicKey` request and what it takes to unblind its response
+pub struct BlindedKeyRequest {
+    host_key: RistrettoPublicKey,
+    shared_secret: RistrettoPublicKey,
+    path: [u8; BLINDED_PATH_LENGTH],
+}
+
+impl BlindedKeyRequest {
+    /// A request for the key at `index` in `branch` of `account`. `host_secret` has to be a fresh random key.
+    pub fn new(
+        host_secret: &RistrettoSecretKey,
+        app_public_key: &RistrettoPublicKey,
+        account: u32,
+        branch: KeyBranch,
+        index: u32,
+    ) -> Self {
+        let mut path = [0u8; BLINDED_PATH_LENGTH];
+        path[0] = branch.as_byte();
+        path[1..5].copy_from_slice(&account.to_le_bytes());
+        path[5..9].copy_from_slice(&index.to_le_bytes());
+        Self {
+            host_key: RistrettoPublicKey::from_secret_key(host_secret),
+            shared_secret: app_public_key * host_secret,
+            path,
+        }
+    }
+
+    /// The `[host public key][masked path]` data of the request
+    pub fn to_request(&self) -> Vec<u8> {
+        let mask = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(BLINDED_PATH_LABEL)
+            .chain(&self.shared_secret)
+            .chain(&self.host_key)
+            .finalize();
+        let mut data = self.host_key.as_bytes().to_vec();
+        data.extend(self.path.iter().zip(mask.iter()).map(|(p, m)| p ^ m));
+        data
+    }
+
+    /// The public key in the payload of the response, with the blinding removed
+    pub fn unblind(&self, payload: &[u8]) -> Result<RistrettoPublicKey, DeviceError> {
+        if payload.len() != GET_BLINDED_PUBLIC_KEY_RESPONSE_LENGTH - 1 {
+            return Err(DeviceError::InvalidResponse("wrong blinded key length"));
+        }
+        let blinded = RistrettoPublicKey::from_bytes(payload)
+            .map_err(|_| DeviceError::InvalidResponse("the device returned an invalid public key"))?;
+        let factor = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(BLINDED_KEY_LABEL)
+            .chain(&self.shared_secret)
+            .chain(&self.host_key)
+            .chain(&self.path)
+            .finalize();
+        let factor = RistrettoSecretKey::from_bytes(&factor)
+            .map_err(|_| DeviceError::InvalidResponse("the blinding factor is not a scalar"))?;
+        Ok(&blinded - &RistrettoPublicKey::from_secret_key(&factor))
+    }
+}
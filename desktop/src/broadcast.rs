@@ -0,0 +1,401 @@
+//! Broadcasting finished transactions to a base node
+//! [`BaseNodeClient`] talks to the gRPC interface of a Tari base node, `tari.rpc.BaseNode`: it submits a
+//! [`FinalizedTransaction`] to the mempool and polls where the node knows it to be by the signature of its kernel,
+//! until it is mined. The messages in [`proto`] are the part of the node's `base_node.proto`, `transaction.proto` and
+//! `types.proto` these two calls need, written out by hand, so no protobuf compiler is needed to build the client.
+//!
+//! The node checks everything a transaction carries. The one [`send`](crate::transaction_protocol::send) finishes has
+//! neither range proofs nor the script signatures of its inputs yet, so a node answers it with
+//! [`SubmitResult::Rejected`] until the wallet has added them. The client and its status tracking work for any
+//! transaction, e.g. one the wallet finished and submitted itself.
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use tari_crypto::{ristretto::RistrettoSchnorr, tari_utilities::ByteArray};
+use tokio::runtime::Runtime;
+use tonic::{
+    client::Grpc,
+    codec::ProstCodec,
+    codegen::http::uri::PathAndQuery,
+    transport::{Channel, Endpoint},
+};
+
+use crate::{
+    errors::BroadcastError,
+    script::{Opcode, TariScript},
+    transaction_protocol::FinalizedTransaction,
+};
+
+/// Where a base node listens for gRPC unless configured otherwise
+pub const DEFAULT_BASE_NODE_GRPC: &str = "http://127.0.0.1:18142";
+/// How often [`BaseNodeClient::wait_until_mined`] asks the node about the transaction
+pub const STATE_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+const SUBMIT_TRANSACTION: &str = "/tari.rpc.BaseNode/SubmitTransaction";
+const TRANSACTION_STATE: &str = "/tari.rpc.BaseNode/TransactionState";
+
+/// The messages of the base node's gRPC interface that the client sends and receives
+pub mod proto {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Signature {
+        #[prost(bytes = "vec", tag = "1")]
+        pub public_nonce: Vec<u8>,
+        #[prost(bytes = "vec", tag = "2")]
+        pub signature: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ComAndPubSignature {
+        #[prost(bytes = "vec", tag = "1")]
+        pub ephemeral_commitment: Vec<u8>,
+        #[prost(bytes = "vec", tag = "2")]
+        pub ephemeral_pubkey: Vec<u8>,
+        #[prost(bytes = "vec", tag = "3")]
+        pub u_a: Vec<u8>,
+        #[prost(bytes = "vec", tag = "4")]
+        pub u_x: Vec<u8>,
+        #[prost(bytes = "vec", tag = "5")]
+        pub u_y: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct OutputFeatures {
+        #[prost(uint32, tag = "1")]
+        pub version: u32,
+        #[prost(uint32, tag = "2")]
+        pub output_type: u32,
+        #[prost(uint64, tag = "3")]
+        pub maturity: u64,
+        #[prost(bytes = "vec", tag = "4")]
+        pub coinbase_extra: Vec<u8>,
+        #[prost(uint32, tag = "6")]
+        pub range_proof_type: u32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct TransactionInput {
+        #[prost(bytes = "vec", tag = "2")]
+        pub commitment: Vec<u8>,
+        #[prost(bytes = "vec", tag = "4")]
+        pub script: Vec<u8>,
+        #[prost(bytes = "vec", tag = "5")]
+        pub input_data: Vec<u8>,
+        #[prost(message, optional, tag = "7")]
+        pub script_signature: Option<ComAndPubSignature>,
+        #[prost(bytes = "vec", tag = "8")]
+        pub sender_offset_public_key: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct TransactionOutput {
+        #[prost(message, optional, tag = "1")]
+        pub features: Option<OutputFeatures>,
+        #[prost(bytes = "vec", tag = "2")]
+        pub commitment: Vec<u8>,
+        #[prost(bytes = "vec", tag = "5")]
+        pub script: Vec<u8>,
+        #[prost(bytes = "vec", tag = "6")]
+        pub sender_offset_public_key: Vec<u8>,
+        #[prost(message, optional, tag = "7")]
+        pub metadata_signature: Option<ComAndPubSignature>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct TransactionKernel {
+        #[prost(uint32, tag = "1")]
+        pub features: u32,
+        #[prost(uint64, tag = "2")]
+        pub fee: u64,
+        #[prost(uint64, tag = "3")]
+        pub lock_height: u64,
+        #[prost(bytes = "vec", tag = "6")]
+        pub excess: Vec<u8>,
+        #[prost(message, optional, tag = "7")]
+        pub excess_sig: Option<Signature>,
+        #[prost(uint32, tag = "9")]
+        pub version: u32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct AggregateBody {
+        #[prost(message, repeated, tag = "1")]
+        pub inputs: Vec<TransactionInput>,
+        #[prost(message, repeated, tag = "2")]
+        pub outputs: Vec<TransactionOutput>,
+        #[prost(message, repeated, tag = "3")]
+        pub kernels: Vec<TransactionKernel>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Transaction {
+        #[prost(bytes = "vec", tag = "1")]
+        pub offset: Vec<u8>,
+        #[prost(message, optional, tag = "2")]
+        pub body: Option<AggregateBody>,
+        #[prost(bytes = "vec", tag = "3")]
+        pub script_offset: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct SubmitTransactionRequest {
+        #[prost(message, optional, tag = "1")]
+        pub transaction: Option<Transaction>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct SubmitTransactionResponse {
+        /// A [`super::SubmitResult`]
+        #[prost(int32, tag = "1")]
+        pub result: i32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct TransactionStateRequest {
+        #[prost(message, optional, tag = "1")]
+        pub excess_sig: Option<Signature>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct TransactionStateResponse {
+        /// A [`super::TransactionLocation`]
+        #[prost(int32, tag = "1")]
+        pub result: i32,
+    }
+}
+
+/// What the base node made of a submitted transaction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SubmitResult {
+    /// The transaction is in the mempool
+    Accepted,
+    /// The node cannot take it yet, e.g. because an input is still maturing or the node is syncing
+    NotProcessableAtThisTime,
+    AlreadyMined,
+    /// The transaction is invalid, or double spends an input
+    Rejected,
+}
+
+impl SubmitResult {
+    fn from_proto(result: i32) -> Result<Self, BroadcastError> {
+        match result {
+            1 => Ok(SubmitResult::Accepted),
+            2 => Ok(SubmitResult::NotProcessableAtThisTime),
+            3 => Ok(SubmitResult::AlreadyMined),
+            4 => Ok(SubmitResult::Rejected),
+            result => Err(BroadcastError::UnexpectedAnswer(format!("submit result {}", result))),
+        }
+    }
+}
+
+/// Where the base node knows a transaction to be, by the signature of its kernel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransactionLocation {
+    /// The node has not seen it
+    Unknown,
+    Mempool,
+    Mined,
+    /// The node saw it and dropped it from the mempool, e.g. because it was replaced or the mempool was full
+    NotStored,
+}
+
+impl TransactionLocation {
+    fn from_proto(result: i32) -> Result<Self, BroadcastError> {
+        match result {
+            0 => Ok(TransactionLocation::Unknown),
+            1 => Ok(TransactionLocation::Mempool),
+            2 => Ok(TransactionLocation::Mined),
+            3 => Ok(TransactionLocation::NotStored),
+            result => Err(BroadcastError::UnexpectedAnswer(format!(
+                "transaction location {}",
+                result
+            ))),
+        }
+    }
+}
+
+impl From<&RistrettoSchnorr> for proto::Signature {
+    fn from(signature: &RistrettoSchnorr) -> Self {
+        Self {
+            public_nonce: signature.get_public_nonce().as_bytes().to_vec(),
+            signature: signature.get_signature().as_bytes().to_vec(),
+        }
+    }
+}
+
+impl From<&FinalizedTransaction> for proto::Transaction {
+    /// The transaction with every field it has. The script of each input and output is the `PushPubKey` of its script
+    /// key, the outputs carry the default features, see
+    /// [`DEFAULT_OUTPUT_FEATURES`](crate::signer::DEFAULT_OUTPUT_FEATURES), and only the sender half of their metadata
+    /// signatures, made with the identity as the ephemeral commitment.
+    fn from(transaction: &FinalizedTransaction) -> Self {
+        let inputs = transaction
+            .inputs
+            .iter()
+            .map(|input| proto::TransactionInput {
+                commitment: input.commitment.as_bytes().to_vec(),
+                script: TariScript::new(vec![Opcode::PushPubKey(input.script_public_key.clone())]).to_bytes(),
+                ..Default::default()
+            })
+            .collect();
+        let outputs = transaction
+            .outputs
+            .iter()
+            .map(|output| proto::TransactionOutput {
+                features: Some(proto::OutputFeatures::default()),
+                commitment: output.commitment.as_bytes().to_vec(),
+                script: TariScript::new(vec![Opcode::PushPubKey(output.script_public_key.clone())]).to_bytes(),
+                sender_offset_public_key: output.metadata_signature.sender_offset_public_key.as_bytes().to_vec(),
+                metadata_signature: Some(proto::ComAndPubSignature {
+                    ephemeral_pubkey: output
+                        .metadata_signature
+                        .signature
+                        .get_public_nonce()
+                        .as_bytes()
+                        .to_vec(),
+                    u_y: output.metadata_signature.signature.get_signature().as_bytes().to_vec(),
+                    ..Default::default()
+                }),
+            })
+            .collect();
+        let kernel = proto::TransactionKernel {
+            features: 0,
+            fee: transaction.kernel.fee,
+            lock_height: transaction.kernel.lock_height,
+            excess: transaction.kernel.excess.as_bytes().to_vec(),
+            excess_sig: Some((&transaction.kernel.signature).into()),
+            version: 0,
+        };
+        Self {
+            offset: transaction.offset.as_bytes().to_vec(),
+            body: Some(proto::AggregateBody {
+                inputs,
+                outputs,
+                kernels: vec![kernel],
+            }),
+            script_offset: transaction.script_offset.as_bytes().to_vec(),
+        }
+    }
+}
+
+/// A connection to the gRPC interface of a base node. The calls block until the node answers.
+pub struct BaseNodeClient {
+    runtime: Runtime,
+    channel: Channel,
+}
+
+impl BaseNodeClient {
+    /// Connect to the node at `url`, e.g. [`DEFAULT_BASE_NODE_GRPC`]
+    pub fn connect(url: &str) -> Result<Self, BroadcastError> {
+        let runtime = Runtime::new().map_err(|e| BroadcastError::Connect(e.to_string()))?;
+        let endpoint = Endpoint::from_shared(url.to_string()).map_err(|e| BroadcastError::Connect(e.to_string()))?;
+        let channel = runtime
+            .block_on(endpoint.connect())
+            .map_err(|e| BroadcastError::Connect(e.to_string()))?;
+        Ok(Self { runtime, channel })
+    }
+
+    /// Submit `transaction` to the mempool of the node
+    pub fn submit(&self, transaction: &FinalizedTransaction) -> Result<SubmitResult, BroadcastError> {
+        self.submit_transaction(transaction.into())
+    }
+
+    pub fn submit_transaction(&self, transaction: proto::Transaction) -> Result<SubmitResult, BroadcastError> {
+        let response: proto::SubmitTransactionResponse =
+            self.call(SUBMIT_TRANSACTION, proto::SubmitTransactionRequest {
+                transaction: Some(transaction),
+            })?;
+        SubmitResult::from_proto(response.result)
+    }
+
+    /// Where the node knows the transaction whose kernel carries `kernel_signature` to be
+    pub fn transaction_state(
+        &self,
+        kernel_signature: &RistrettoSchnorr,
+    ) -> Result<TransactionLocation, BroadcastError> {
+        let response: proto::TransactionStateResponse =
+            self.call(TRANSACTION_STATE, proto::TransactionStateRequest {
+                excess_sig: Some(kernel_signature.into()),
+            })?;
+        TransactionLocation::from_proto(response.result)
+    }
+
+    /// Poll the node every [`STATE_POLL_INTERVAL`] until the transaction whose kernel carries `kernel_signature` is
+    /// mined, telling `on_change` every time it moved. Fails with [`BroadcastError::Dropped`] once the node no longer
+    /// stores it, and with [`BroadcastError::NotMined`] if it is not mined within `timeout`.
+    pub fn wait_until_mined(
+        &self,
+        kernel_signature: &RistrettoSchnorr,
+        timeout: Duration,
+        mut on_change: impl FnMut(TransactionLocation),
+    ) -> Result<(), BroadcastError> {
+        let started = Instant::now();
+        let mut last = None;
+        loop {
+            let location = self.transaction_state(kernel_signature)?;
+            if last != Some(location) {
+                on_change(location);
+                last = Some(location);
+            }
+            match location {
+                TransactionLocation::Mined => return Ok(()),
+                TransactionLocation::NotStored => return Err(BroadcastError::Dropped),
+                TransactionLocation::Unknown | TransactionLocation::Mempool => {},
+            }
+            if started.elapsed() >= timeout {
+                return Err(BroadcastError::NotMined(timeout));
+            }
+            thread::sleep(STATE_POLL_INTERVAL);
+        }
+    }
+
+    fn call<Q, A>(&self, method: &'static str, request: Q) -> Result<A, BroadcastError>
+    where
+        Q: prost::Message + Send + Sync + 'static,
+        A: prost::Message + Default + Send + Sync + 'static,
+    {
+        self.runtime.block_on(async {
+            let mut grpc = Grpc::new(self.channel.clone());
+            grpc.ready().await.map_err(|e| BroadcastError::Connect(e.to_string()))?;
+            let response = grpc
+                .unary(
+                    tonic::Request::new(request),
+                    PathAndQuery::from_static(method),
+                    ProstCodec::default(),
+                )
+                .await
+                .map_err(|status| BroadcastError::Rpc(status.message().to_string()))?;
+            Ok(response.into_inner())
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use prost::Message;
+
+    use super::*;
+
+    #[test]
+    fn answers_are_decoded() {
+        assert_eq!(SubmitResult::from_proto(1).unwrap(), SubmitResult::Accepted);
+        assert_eq!(SubmitResult::from_proto(4).unwrap(), SubmitResult::Rejected);
+        assert!(SubmitResult::from_proto(0).is_err());
+        assert_eq!(TransactionLocation::from_proto(2).unwrap(), TransactionLocation::Mined);
+        assert!(TransactionLocation::from_proto(7).is_err());
+    }
+
+    #[test]
+    fn messages_use_the_field_numbers_of_the_node() {
+        let request = proto::TransactionStateRequest {
+            excess_sig: Some(proto::Signature {
+                public_nonce: vec![1],
+                signature: vec![2],
+            }),
+        };
+        // Field 1, length delimited, holding fields 1 and 2 of the signature
+        assert_eq!(request.encode_to_vec(), vec![0x0a, 6, 0x0a, 1, 1, 0x12, 1, 2]);
+    }
+}
@@ -0,0 +1,288 @@
+//! Compact CBOR encoding of the documents passed between hosts
+//! JSON is easy to read but large, and every byte counts when a document travels as QR codes to an air-gapped host.
+//! Every document can be encoded as CBOR instead, with keys, hashes and signatures as raw bytes rather than hex, which
+//! about halves its size. A CBOR document starts with the self-describe tag, so it is told apart from JSON by its first
+//! bytes, and carries a second tag naming the kind of document, so a document of one kind is never read as another.
+//!
+//! Any JSON document converts to CBOR under [`JSON_DOCUMENT_TAG`] and back without changing a value: lowercase hex
+//! strings become byte strings and turn back into the same hex.
+
+use ciborium::value::{Integer, Value};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Map, Number};
+use tari_crypto::tari_utilities::hex::{from_hex, to_hex};
+
+use crate::errors::CborError;
+
+/// Marks the bytes as CBOR, RFC 8949 section 3.4.6
+pub const SELF_DESCRIBE_TAG: u64 = 55799;
+/// Any JSON document converted as is, e.g. a signed transaction for the console wallet
+pub const JSON_DOCUMENT_TAG: u64 = 0x5441_0001;
+/// A [`MultisigDocument`](crate::multisig::MultisigDocument)
+pub const MULTISIG_DOCUMENT_TAG: u64 = 0x5441_0002;
+
+/// The encoding of the self-describe tag, the first bytes of every CBOR document
+const SELF_DESCRIBE_PREFIX: [u8; 3] = [0xd9, 0xd9, 0xf7];
+
+/// Whether `bytes` are a CBOR document rather than JSON
+pub fn is_cbor(bytes: &[u8]) -> bool {
+    bytes.starts_with(&SELF_DESCRIBE_PREFIX)
+}
+
+/// The kind tag of the CBOR document `bytes`
+pub fn document_kind(bytes: &[u8]) -> Result<u64, CborError> {
+    read_tagged(bytes).map(|(kind, _)| kind)
+}
+
+/// Encode `value` as a CBOR document of kind `tag`
+pub fn encode<T: Serialize>(tag: u64, value: &T) -> Vec<u8> {
+    let value = Value::serialized(value).expect("a document always serializes");
+    write_tagged(tag, value)
+}
+
+/// Decode the CBOR document `bytes`, which has to be of kind `tag`
+pub fn decode<T: DeserializeOwned>(tag: u64, bytes: &[u8]) -> Result<T, CborError> {
+    let (kind, value) = read_tagged(bytes)?;
+    if kind != tag {
+        return Err(CborError::WrongKind {
+            expected: tag,
+            found: kind,
+        });
+    }
+    value.deserialized().map_err(|e| CborError::Parse(e.to_string()))
+}
+
+/// Convert the JSON document `json` to CBOR
+pub fn json_to_cbor(json: &str) -> Result<Vec<u8>, CborError> {
+    let document: serde_json::Value = serde_json::from_str(json).map_err(|e| CborError::Parse(e.to_string()))?;
+    Ok(write_tagged(JSON_DOCUMENT_TAG, from_json(document)))
+}
+
+/// Convert a JSON document that was converted to CBOR with [`json_to_cbor`] back to JSON
+pub fn cbor_to_json(bytes: &[u8]) -> Result<String, CborError> {
+    let (kind, value) = read_tagged(bytes)?;
+    if kind != JSON_DOCUMENT_TAG {
+        return Err(CborError::WrongKind {
+            expected: JSON_DOCUMENT_TAG,
+            found: kind,
+        });
+    }
+    let document = to_json(value)?;
+    Ok(serde_json::to_string_pretty(&document).expect("a JSON value always serializes"))
+}
+
+/// `bytes` as JSON, converting them first if they are a CBOR document
+pub fn read_json(bytes: &[u8]) -> Result<String, CborError> {
+    if is_cbor(bytes) {
+        cbor_to_json(bytes)
+    } else {
+        String::from_utf8(bytes.to_vec()).map_err(|_| CborError::Parse("the document is neither JSON nor CBOR".into()))
+    }
+}
+
+fn write_tagged(tag: u64, value: Value) -> Vec<u8> {
+    let document = Value::Tag(SELF_DESCRIBE_TAG, Box::new(Value::Tag(tag, Box::new(value))));
+    let mut bytes = Vec::new();
+    ciborium::ser::into_writer(&document, &mut bytes).expect("writing to memory never fails");
+    bytes
+}
+
+fn read_tagged(bytes: &[u8]) -> Result<(u64, Value), CborError> {
+    let document: Value = ciborium::de::from_reader(bytes).map_err(|e| CborError::Parse(e.to_string()))?;
+    match document {
+        Value::Tag(SELF_DESCRIBE_TAG, inner) => match *inner {
+            Value::Tag(kind, value) => Ok((kind, *value)),
+            _ => Err(CborError::MissingTag),
+        },
+        _ => Err(CborError::MissingTag),
+    }
+}
+
+fn from_json(value: serde_json::Value) -> Value {
+    match value {
+        serde_json::Value::Null => Value::Null,
+        serde_json::Value::Bool(b) => Value::Bool(b),
+        serde_json::Value::Number(n) => match (n.as_u64(), n.as_i64(), n.as_f64()) {
+            (Some(u), _, _) => Value::Integer(u.into()),
+            (_, Some(i), _) => Value::Integer(i.into()),
+            (_, _, f) => Value::Float(f.expect("a JSON number is an integer or a float")),
+        },
+        serde_json::Value::String(s) => match hex_bytes(&s) {
+            Some(bytes) => Value::Bytes(bytes),
+            None => Value::Text(s),
+        },
+        serde_json::Value::Array(items) => Value::Array(items.into_iter().map(from_json).collect()),
+        serde_json::Value::Object(fields) => Value::Map(
+            fields
+                .into_iter()
+                .map(|(key, value)| (Value::Text(key), from_json(value)))
+                .collect(),
+        ),
+    }
+}
+
+fn to_json(value: Value) -> Result<serde_json::Value, CborError> {
+    let invalid = |what: &str| CborError::Parse(format!("{} cannot be converted to JSON", what));
+    Ok(match value {
+        Value::Null => serde_json::Value::Null,
+        Value::Bool(b) => serde_json::Value::Bool(b),
+        Value::Integer(i) => integer_to_json(i).ok_or_else(|| invalid("an integer out of range"))?,
+        Value::Float(f) => serde_json::Value::Number(Number::from_f64(f).ok_or_else(|| invalid("a non-finite float"))?),
+        Value::Bytes(bytes) => serde_json::Value::String(to_hex(&bytes)),
+        Value::Text(s) => serde_json::Value::String(s),
+        Value::Array(items) => serde_json::Value::Array(items.into_iter().map(to_json).collect::<Result<_, _>>()?),
+        Value::Map(fields) => serde_json::Value::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| match key {
+                    Value::Text(key) => Ok((key, to_json(value)?)),
+                    _ => Err(invalid("a map key that is not text")),
+                })
+                .collect::<Result<Map<_, _>, _>>()?,
+        ),
+        _ => return Err(invalid("a tagged value")),
+    })
+}
+
+fn integer_to_json(integer: Integer) -> Option<serde_json::Value> {
+    let integer = i128::from(integer);
+    u64::try_from(integer)
+        .map(Number::from)
+        .or_else(|_| i64::try_from(integer).map(Number::from))
+        .ok()
+        .map(serde_json::Value::Number)
+}
+
+/// `s` as bytes if it is lowercase hex, which turns back into the same string
+fn hex_bytes(s: &str) -> Option<Vec<u8>> {
+    let lowercase = s.bytes().all(|c| c.is_ascii_digit() || (b'a'..=b'f').contains(&c));
+    if s.is_empty() || !lowercase {
+        return None;
+    }
+    from_hex(s).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Document {
+        name: String,
+        #[serde(with = "serde_bytes")]
+        key: Vec<u8>,
+        amounts: Vec<u64>,
+        note: Option<String>,
+    }
+
+    fn document() -> Document {
+        Document {
+            name: "payment".to_string(),
+            key: vec![0xab; 32],
+            amounts: vec![0, 1, u64::MAX],
+            note: None,
+        }
+    }
+
+    #[test]
+    fn documents_round_trip_under_their_tag() {
+        let bytes = encode(MULTISIG_DOCUMENT_TAG, &document());
+        assert!(is_cbor(&bytes));
+        assert_eq!(document_kind(&bytes).unwrap(), MULTISIG_DOCUMENT_TAG);
+        assert_eq!(decode::<Document>(MULTISIG_DOCUMENT_TAG, &bytes).unwrap(), document());
+    }
+
+    #[test]
+    fn a_document_is_not_read_as_another_kind() {
+        let bytes = encode(MULTISIG_DOCUMENT_TAG, &document());
+        assert!(matches!(
+            decode::<Document>(JSON_DOCUMENT_TAG, &bytes),
+            Err(CborError::WrongKind {
+                expected: JSON_DOCUMENT_TAG,
+                found: MULTISIG_DOCUMENT_TAG
+            })
+        ));
+        assert!(matches!(cbor_to_json(&bytes), Err(CborError::WrongKind { .. })));
+        // The fields of another document do not fill this one
+        let bytes = encode(MULTISIG_DOCUMENT_TAG, &vec![1u8, 2, 3]);
+        assert!(matches!(
+            decode::<Document>(MULTISIG_DOCUMENT_TAG, &bytes),
+            Err(CborError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn a_document_needs_both_tags() {
+        let mut untagged = Vec::new();
+        ciborium::ser::into_writer(&Value::Integer(1.into()), &mut untagged).unwrap();
+        assert!(!is_cbor(&untagged));
+        assert!(matches!(document_kind(&untagged), Err(CborError::MissingTag)));
+
+        let mut no_kind = Vec::new();
+        let value = Value::Tag(SELF_DESCRIBE_TAG, Box::new(Value::Integer(1.into())));
+        ciborium::ser::into_writer(&value, &mut no_kind).unwrap();
+        assert!(is_cbor(&no_kind));
+        assert!(matches!(document_kind(&no_kind), Err(CborError::MissingTag)));
+
+        let bytes = encode(JSON_DOCUMENT_TAG, &document());
+        assert!(matches!(
+            document_kind(&bytes[..bytes.len() - 1]),
+            Err(CborError::Parse(_))
+        ));
+        assert!(matches!(document_kind(b"{}"), Err(CborError::Parse(_))));
+    }
+
+    #[test]
+    fn json_round_trips_without_changing_a_value() {
+        let json = r#"{"empty":"","hex":"00ff","key":"abababababababababababababababababababababababababababababababab","mixed":"ABcd","negative":-5,"nested":[null,true,1.5,{"odd":"abc"}],"text":"payment to bob","large":18446744073709551615}"#;
+        let document: serde_json::Value = serde_json::from_str(json).unwrap();
+        let bytes = json_to_cbor(json).unwrap();
+        assert!(is_cbor(&bytes));
+        assert_eq!(document_kind(&bytes).unwrap(), JSON_DOCUMENT_TAG);
+        let back: serde_json::Value = serde_json::from_str(&cbor_to_json(&bytes).unwrap()).unwrap();
+        assert_eq!(back, document);
+        assert_eq!(read_json(&bytes).unwrap(), cbor_to_json(&bytes).unwrap());
+        assert_eq!(read_json(json.as_bytes()).unwrap(), json);
+    }
+
+    #[test]
+    fn hex_is_sent_as_bytes() {
+        let key = "ab".repeat(32);
+        let bytes = json_to_cbor(&format!(r#"{{"key":"{}"}}"#, key)).unwrap();
+        let (_, value) = read_tagged(&bytes).unwrap();
+        assert_eq!(
+            value,
+            Value::Map(vec![(Value::Text("key".into()), Value::Bytes(vec![0xab; 32]))])
+        );
+        // About half the size of the JSON
+        assert!(bytes.len() < key.len() / 2 + 16, "{} bytes", bytes.len());
+
+        assert_eq!(hex_bytes("00ff"), Some(vec![0x00, 0xff]));
+        assert_eq!(hex_bytes("00FF"), None);
+        assert_eq!(hex_bytes("abc"), None);
+        assert_eq!(hex_bytes(""), None);
+        assert_eq!(hex_bytes("payment"), None);
+    }
+
+    #[test]
+    fn values_json_cannot_hold_are_refused() {
+        let refused = [
+            Value::Tag(7, Box::new(Value::Null)),
+            Value::Integer((-1i128 - i64::MAX as i128 - 1).try_into().unwrap()),
+            Value::Float(f64::NAN),
+            Value::Map(vec![(Value::Integer(1.into()), Value::Null)]),
+        ];
+        for value in refused {
+            let bytes = write_tagged(JSON_DOCUMENT_TAG, value.clone());
+            assert!(
+                matches!(cbor_to_json(&bytes), Err(CborError::Parse(_))),
+                "{:?} was converted",
+                value
+            );
+        }
+        assert!(matches!(json_to_cbor("{"), Err(CborError::Parse(_))));
+        assert!(matches!(read_json(&[0xff, 0xfe]), Err(CborError::Parse(_))));
+    }
+}
@@ -0,0 +1,153 @@
+//! Authenticated sessions between the host and the app
+//! On a shared or hostile machine anything with access to the USB stack can rewrite APDUs in flight. After
+//! `Instruction::OpenSession` both sides hold a key agreed over Diffie-Hellman, and every command and response carries
+//! a MAC under it, so tampering with either is detected. The app proves it holds the app key by signing the exchange,
+//! and a host that knows the key from an earlier session can pin it to rule out a device in the middle.
+
+use std::fmt;
+
+use ledger_transport::{APDUAnswer, APDUCommand};
+use tari_crypto::{
+    keys::PublicKey,
+    ristretto::{RistrettoPublicKey, RistrettoSchnorr, RistrettoSecretKey},
+    tari_utilities::ByteArray,
+};
+use tari_ledger_protocol::{
+    MAC_DIRECTION_COMMAND,
+    MAC_DIRECTION_RESPONSE,
+    OPEN_SESSION_RESPONSE_LENGTH,
+    PAIRING_SECRET_LABEL,
+    SESSION_AUTH_LABEL,
+    SESSION_KEY_LABEL,
+    SESSION_MAC_LABEL,
+    SESSION_MAC_LENGTH,
+};
+
+use crate::{
+    errors::DeviceError,
+    hashing::{DomainSeparatedConsensusHasher, TransactionHashDomain},
+    redact::{short_hex, Redacted},
+    verify::verify_script_signature,
+};
+
+/// The host side of an open session
+#[derive(Clone)]
+pub struct SecureChannel {
+    key: [u8; 32],
+    counter: u32,
+    app_public_key: RistrettoPublicKey,
+    challenge: [u8; 32],
+}
+
+impl fmt::Debug for SecureChannel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SecureChannel")
+            .field("key", &Redacted)
+            .field("counter", &self.counter)
+            .field("app_public_key", &self.app_public_key)
+            .field("challenge", &short_hex(&self.challenge))
+            .finish()
+    }
+}
+
+impl SecureChannel {
+    /// Complete the key agreement from the payload of the `OpenSession` response. `host_secret` is the ephemeral key
+    /// whose public key was sent; `expected_key`, if given, is the app key the device has to prove it holds.
+    pub fn from_response(
+        host_secret: &RistrettoSecretKey,
+        payload: &[u8],
+        expected_key: Option<&RistrettoPublicKey>,
+    ) -> Result<Self, DeviceError> {
+        if payload.len() != OPEN_SESSION_RESPONSE_LENGTH - 1 {
+            return Err(DeviceError::InvalidResponse("wrong session response length"));
+        }
+        let key_at = |i: usize| RistrettoPublicKey::from_bytes(&payload[i * 32..(i + 1) * 32]);
+        let invalid = |_| DeviceError::InvalidResponse("the session response holds an invalid key");
+        let app_ephemeral_key = key_at(0).map_err(invalid)?;
+        let app_public_key = key_at(1).map_err(invalid)?;
+        let s = RistrettoSecretKey::from_bytes(&payload[64..96]).map_err(invalid)?;
+        let public_nonce = key_at(3).map_err(invalid)?;
+        let signature = RistrettoSchnorr::new(public_nonce, s);
+
+        let host_key = RistrettoPublicKey::from_secret_key(host_secret);
+        let challenge = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(SESSION_AUTH_LABEL)
+            .chain(&host_key)
+            .chain(&app_ephemeral_key)
+            .finalize();
+        if !verify_script_signature(&app_public_key, &signature, &challenge) {
+            return Err(DeviceError::AuthenticationFailed);
+        }
+        if expected_key.map(|key| *key != app_public_key).unwrap_or(false) {
+            return Err(DeviceError::AuthenticationFailed);
+        }
+
+        let shared_secret = &app_ephemeral_key * host_secret;
+        let key = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(SESSION_KEY_LABEL)
+            .chain(&shared_secret)
+            .chain(&host_key)
+            .chain(&app_ephemeral_key)
+            .finalize();
+        Ok(Self {
+            key,
+            counter: 0,
+            app_public_key,
+            challenge,
+        })
+    }
+
+    /// The challenge the app signed to open the session, fresh for every session
+    pub fn challenge(&self) -> &[u8; 32] {
+        &self.challenge
+    }
+
+    /// The mask of a pairing secret sent in this session
+    pub fn pairing_mask(&self) -> [u8; 32] {
+        DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(PAIRING_SECRET_LABEL)
+            .chain(&self.key)
+            .finalize()
+    }
+
+    /// The app key the device proved it holds when the session was opened
+    pub fn app_public_key(&self) -> &RistrettoPublicKey {
+        &self.app_public_key
+    }
+
+    /// Append the MAC of `command` to its data
+    pub fn seal(&self, command: &mut APDUCommand<Vec<u8>>) {
+        let header = [command.cla, command.ins, command.p1, command.p2];
+        let mac = self.mac(MAC_DIRECTION_COMMAND, &header, &command.data);
+        command.data.extend_from_slice(&mac);
+    }
+
+    /// Check and strip the MAC of the answer to the last sealed command. Every answer moves the session on, so a
+    /// replayed or reordered answer fails too.
+    pub fn open(&mut self, answer: APDUAnswer<Vec<u8>>) -> Result<APDUAnswer<Vec<u8>>, DeviceError> {
+        let data = answer.data();
+        if data.len() < SESSION_MAC_LENGTH {
+            return Err(DeviceError::AuthenticationFailed);
+        }
+        let (data, mac) = data.split_at(data.len() - SESSION_MAC_LENGTH);
+        let [sw1, sw2] = answer.retcode().to_be_bytes();
+        if self.mac(MAC_DIRECTION_RESPONSE, &[sw1, sw2, 0, 0], data) != mac {
+            return Err(DeviceError::AuthenticationFailed);
+        }
+        self.counter = self.counter.wrapping_add(1);
+
+        let mut unsealed = data.to_vec();
+        unsealed.extend_from_slice(&[sw1, sw2]);
+        APDUAnswer::from_answer(unsealed).map_err(|_| DeviceError::InvalidResponse("the answer has no status word"))
+    }
+
+    fn mac(&self, direction: u8, header: &[u8; 4], data: &[u8]) -> [u8; SESSION_MAC_LENGTH] {
+        let hash = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(SESSION_MAC_LABEL)
+            .chain(&self.key)
+            .chain(&self.counter)
+            .chain(&direction)
+            .chain_fixed(header)
+            .chain_bytes(data)
+            .finalize();
+        let mut mac = [0u8; SESSION_MAC_LENGTH];
+        mac.copy_from_slice(&hash[..SESSION_MAC_LENGTH]);
+        mac
+    }
+}
@@ -0,0 +1,70 @@
+//! Output commitments created on the device
+//! Transactions with many change or split outputs need a commitment per output. They are requested in batches of
+//! [`MAX_COMMITMENTS_PER_REQUEST`] so that a transaction costs a handful of round trips rather than one per output.
+
+use tari_crypto::{ristretto::pedersen::PedersenCommitment, tari_utilities::ByteArray};
+use tari_ledger_protocol::{
+    batch_commitment_response_length,
+    BATCH_COMMITMENT_ENTRY_LENGTH,
+    MAX_COMMITMENTS_PER_REQUEST,
+};
+
+use crate::{
+    device::{Capabilities, DerivationVersion, Instruction, LedgerDevice},
+    errors::DeviceError,
+};
+
+/// A value to commit to, masked with the key at `index`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CommitmentRequest {
+    /// In microTari
+    pub value: u64,
+    pub index: u32,
+}
+
+/// Commit to every request, returning the commitments in the same order
+pub fn batch_commitments(
+    device: &LedgerDevice,
+    requests: &[CommitmentRequest],
+) -> Result<Vec<PedersenCommitment>, DeviceError> {
+    batch_commitments_with_version(device, requests, DerivationVersion::Legacy)
+}
+
+/// Commit to every request with masks derived under `version`, returning the commitments in the same order
+pub fn batch_commitments_with_version(
+    device: &LedgerDevice,
+    requests: &[CommitmentRequest],
+    version: DerivationVersion,
+) -> Result<Vec<PedersenCommitment>, DeviceError> {
+    device.require(Capabilities::BATCH_COMMITMENTS)?;
+    let p2 = device.derivation_p2(version)?;
+    let mut commitments = Vec::with_capacity(requests.len());
+    let batches = requests
+        .chunks(usize::from(MAX_COMMITMENTS_PER_REQUEST))
+        .collect::<Vec<_>>();
+    for window in batches.chunks(device.pipeline_depth()) {
+        let commands = window
+            .iter()
+            .map(|batch| {
+                // Cannot truncate, the batch is at most MAX_COMMITMENTS_PER_REQUEST long
+                let mut data = Vec::with_capacity(1 + BATCH_COMMITMENT_ENTRY_LENGTH * batch.len());
+                data.push(batch.len() as u8);
+                for request in *batch {
+                    data.extend_from_slice(&request.value.to_le_bytes());
+                    data.extend_from_slice(&request.index.to_le_bytes());
+                }
+                (0x00, p2, data)
+            })
+            .collect();
+        let responses = device.send_pipelined(Instruction::BatchCommitment, commands)?;
+        for (batch, response) in window.iter().zip(responses) {
+            let payload = device.response_payload(&response, batch_commitment_response_length(batch.len() as u8))?;
+            for bytes in payload.chunks(32) {
+                let commitment = PedersenCommitment::from_bytes(bytes)
+                    .map_err(|_| DeviceError::InvalidResponse("the device returned an invalid commitment"))?;
+                commitments.push(commitment);
+            }
+        }
+    }
+    Ok(commitments)
+}
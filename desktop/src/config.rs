@@ -0,0 +1,438 @@
+//! Host configuration, `~/.tari-ledger/config.toml`
+//! The file holds named profiles, each with the network, account defaults, transport and timeouts to use, and one of
+//! them is picked with `--profile`. A missing file or profile falls back to the built in defaults.
+//!
+//! The file may be encrypted with a passphrase, in which case it starts with [`ENCRYPTED_CONFIG_MAGIC`] followed by
+//! an Argon2 salt, an XChaCha20-Poly1305 nonce and the encrypted TOML.
+
+use std::{collections::BTreeMap, fs, path::Path, str::FromStr, time::Duration};
+
+use argon2::Argon2;
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    XChaCha20Poly1305,
+    XNonce,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    address::Network,
+    errors::ConfigError,
+    fee::DEFAULT_FEE_PER_GRAM,
+    permissions::{InstructionClass, RequestQuota},
+    policy::TwoManRule,
+    signer::DEFAULT_SESSION_EXPIRY,
+    transport::HidFilter,
+    units::{AmountFormat, Separators, Unit},
+};
+
+/// The profile used when none is selected and the file does not name a default
+pub const DEFAULT_PROFILE: &str = "default";
+/// Prefix of an encrypted configuration file
+pub const ENCRYPTED_CONFIG_MAGIC: &[u8; 8] = b"TLCONF\x00\x01";
+
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 24;
+
+/// How to reach the device
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportKind {
+    /// The default unless the build lacks the `hid` feature
+    #[cfg_attr(feature = "hid", default)]
+    Hid,
+    /// `/dev/hidraw*` without hidapi, needs the `hidraw-direct` feature on Linux
+    #[cfg_attr(not(feature = "hid"), default)]
+    Hidraw,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Timeouts {
+    /// How long to wait for the user to unlock the device, in seconds
+    pub unlock_secs: u64,
+    /// How long a signing session stays valid, in seconds
+    pub session_expiry_secs: u64,
+    /// How long a handshake is reused by later invocations, in seconds, 0 to always perform it
+    pub session_token_secs: u64,
+    /// How long a command passed on by the daemon may wait for the device, the user's confirmation included, in
+    /// seconds, 0 for no limit
+    pub command_secs: u64,
+}
+
+impl Timeouts {
+    pub fn unlock(&self) -> Duration {
+        Duration::from_secs(self.unlock_secs)
+    }
+
+    pub fn session_expiry(&self) -> Duration {
+        Duration::from_secs(self.session_expiry_secs)
+    }
+
+    pub fn session_token(&self) -> Duration {
+        Duration::from_secs(self.session_token_secs)
+    }
+
+    pub fn command(&self) -> Option<Duration> {
+        (self.command_secs > 0).then_some(Duration::from_secs(self.command_secs))
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            unlock_secs: 120,
+            session_expiry_secs: DEFAULT_SESSION_EXPIRY.as_secs(),
+            session_token_secs: 120,
+            command_secs: 300,
+        }
+    }
+}
+
+/// A client of the daemon that identifies with a token
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DaemonClient {
+    /// The SHA-256 hash in hex of the token of the client
+    pub token_sha256: String,
+    /// The classes of instructions the client may send, `read-only`, `signing` or `raw`
+    pub allow: Vec<InstructionClass>,
+    /// How many commands, and how many of them that need confirmation, the client may send a minute, any if unset
+    pub quota: Option<RequestQuota>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    pub network: Network,
+    /// The BIP44 account used unless a command is given another one
+    pub account: u32,
+    /// In microTari
+    pub fee_per_gram: u64,
+    /// Refuse to sign transactions with a higher fee, in microTari
+    pub max_fee: Option<u64>,
+    pub transport: TransportKind,
+    /// Which USB devices are taken for a Ledger, e.g. to use a developer board or a compatible clone
+    pub hid_filter: HidFilter,
+    pub timeouts: Timeouts,
+    /// The fingerprint of the seed the profile belongs to, if pinned. Commands refuse to run while the device is
+    /// unlocked with another seed, e.g. the hidden wallet behind a second PIN.
+    pub wallet: Option<String>,
+    /// The unit amounts are shown in, `T`, `mT` or `uT`
+    pub units: Unit,
+    /// The locale whose digit grouping and decimal separator amounts are written in, e.g. `de_CH`, the one of the
+    /// environment if unset. `C` writes plain numbers.
+    pub locale: Option<String>,
+    /// Large transactions need the confirmation of a second operator on the host before they reach the device
+    pub two_man_rule: Option<TwoManRule>,
+    /// The clients of the daemon other than the owner of its socket, by name
+    pub daemon_clients: BTreeMap<String, DaemonClient>,
+    /// The classes of instructions the owner of the socket may send without a token once `daemon_clients` are
+    /// configured, none if unset. Without clients the owner may send anything.
+    pub daemon_owner_allow: Vec<InstructionClass>,
+}
+
+impl Profile {
+    /// How amounts are written for the user, in `units` if set and the unit of the profile otherwise
+    pub fn amount_format(&self, units: Option<Unit>) -> AmountFormat {
+        let separators = self
+            .locale
+            .as_deref()
+            .map_or_else(Separators::from_env, Separators::for_locale);
+        AmountFormat::new(units.unwrap_or(self.units), separators)
+    }
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self {
+            network: Network::default(),
+            account: 0,
+            fee_per_gram: DEFAULT_FEE_PER_GRAM,
+            max_fee: None,
+            transport: TransportKind::default(),
+            hid_filter: HidFilter::default(),
+            timeouts: Timeouts::default(),
+            wallet: None,
+            units: Unit::default(),
+            locale: None,
+            two_man_rule: None,
+            daemon_clients: BTreeMap::new(),
+            daemon_owner_allow: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// The profile to use when `--profile` is not given
+    pub default_profile: Option<String>,
+    pub profiles: BTreeMap<String, Profile>,
+    /// Labels for the seeds seen on the device, by fingerprint, e.g. to tell the main wallet and a decoy apart
+    pub wallets: BTreeMap<String, String>,
+}
+
+impl Config {
+    /// Load the configuration at `path`, decrypting it with `passphrase` if it is encrypted. A missing file is the
+    /// default configuration.
+    pub fn load<P: AsRef<Path>>(path: P, passphrase: Option<&str>) -> Result<Self, ConfigError> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+        if !is_encrypted(&bytes) {
+            let contents = String::from_utf8(bytes).map_err(|_| ConfigError::Parse("not valid UTF-8".to_string()))?;
+            return contents.parse();
+        }
+        let passphrase = passphrase.ok_or(ConfigError::PassphraseRequired)?;
+        let contents = decrypt(ENCRYPTED_CONFIG_MAGIC, &bytes, passphrase)?;
+        String::from_utf8(contents)
+            .map_err(|_| ConfigError::Parse("not valid UTF-8".to_string()))?
+            .parse()
+    }
+
+    /// Write the configuration to `path`, encrypted if a `passphrase` is given
+    pub fn save<P: AsRef<Path>>(&self, path: P, passphrase: Option<&str>) -> Result<(), ConfigError> {
+        if let Some(parent) = path.as_ref().parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let contents = toml::to_string_pretty(self).map_err(|e| ConfigError::Parse(e.to_string()))?;
+        let bytes = match passphrase {
+            Some(passphrase) => encrypt(ENCRYPTED_CONFIG_MAGIC, contents.as_bytes(), passphrase)?,
+            None => contents.into_bytes(),
+        };
+        fs::write(path, bytes)?;
+        Ok(())
+    }
+
+    /// The profile called `name`, or the default profile if no name is given
+    pub fn profile(&self, name: Option<&str>) -> Result<Profile, ConfigError> {
+        let selected = name.or(self.default_profile.as_deref());
+        match selected {
+            Some(name) => match self.profiles.get(name) {
+                Some(profile) => Ok(profile.clone()),
+                // The built in default is fine when nothing has been configured for it
+                None if name == DEFAULT_PROFILE => Ok(Profile::default()),
+                None => Err(ConfigError::UnknownProfile(name.to_string())),
+            },
+            None => Ok(self.profiles.get(DEFAULT_PROFILE).cloned().unwrap_or_default()),
+        }
+    }
+
+    /// The label of the seed with `fingerprint`, if it has one
+    pub fn wallet_label(&self, fingerprint: &str) -> Option<&str> {
+        self.wallets.get(fingerprint).map(String::as_str)
+    }
+}
+
+impl FromStr for Config {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        toml::from_str(s).map_err(|e| ConfigError::Parse(e.to_string()))
+    }
+}
+
+pub fn is_encrypted(bytes: &[u8]) -> bool {
+    bytes.starts_with(ENCRYPTED_CONFIG_MAGIC)
+}
+
+/// Encrypt `plaintext` with `passphrase` into `magic`, an Argon2 salt, an XChaCha20-Poly1305 nonce and the ciphertext
+pub(crate) fn encrypt(magic: &[u8], plaintext: &[u8], passphrase: &str) -> Result<Vec<u8>, ConfigError> {
+    let mut salt = [0u8; SALT_LENGTH];
+    OsRng.fill_bytes(&mut salt);
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher(passphrase, &salt)?
+        .encrypt(&nonce, plaintext)
+        .map_err(|_| ConfigError::Encryption)?;
+
+    let mut bytes = magic.to_vec();
+    bytes.extend_from_slice(&salt);
+    bytes.extend_from_slice(&nonce);
+    bytes.extend_from_slice(&ciphertext);
+    Ok(bytes)
+}
+
+fn decrypt(magic: &[u8], bytes: &[u8], passphrase: &str) -> Result<Vec<u8>, ConfigError> {
+    let header_length = magic.len() + SALT_LENGTH + NONCE_LENGTH;
+    if bytes.len() < header_length {
+        return Err(ConfigError::Parse("the encrypted file is truncated".to_string()));
+    }
+    let (salt, rest) = bytes[magic.len()..].split_at(SALT_LENGTH);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LENGTH);
+    cipher(passphrase, salt)?
+        .decrypt(XNonce::from_slice(nonce), ciphertext)
+        // Authentication fails for a wrong passphrase and for a tampered file alike
+        .map_err(|_| ConfigError::Encryption)
+}
+
+fn cipher(passphrase: &str, salt: &[u8]) -> Result<XChaCha20Poly1305, ConfigError> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|_| ConfigError::Encryption)?;
+    Ok(XChaCha20Poly1305::new(&key.into()))
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// A path in the temporary directory for the test `name`, with nothing at it yet
+    fn path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("tari-ledger-config-{}-{}.toml", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn config() -> Config {
+        let mut config = Config::default();
+        config.profiles.insert("test".to_string(), Profile {
+            network: Network::Esmeralda,
+            account: 3,
+            max_fee: Some(10_000),
+            units: Unit::Tari,
+            locale: Some("de_CH".to_string()),
+            ..Profile::default()
+        });
+        config.default_profile = Some("test".to_string());
+        config.wallets.insert("0a1b2c3d".to_string(), "main wallet".to_string());
+        config
+    }
+
+    #[test]
+    fn a_profile_fills_in_the_defaults() {
+        let config = r#"
+            default_profile = "test"
+
+            [profiles.test]
+            network = "esmeralda"
+            account = 3
+            units = "T"
+
+            [profiles.test.timeouts]
+            unlock_secs = 30
+        "#
+        .parse::<Config>()
+        .unwrap();
+        let profile = config.profile(None).unwrap();
+        assert_eq!(profile.network, Network::Esmeralda);
+        assert_eq!(profile.account, 3);
+        assert_eq!(profile.units, Unit::Tari);
+        assert_eq!(profile.fee_per_gram, DEFAULT_FEE_PER_GRAM);
+        assert_eq!(profile.timeouts.unlock(), Duration::from_secs(30));
+        assert_eq!(profile.timeouts.session_expiry(), DEFAULT_SESSION_EXPIRY);
+        assert_eq!(profile.transport, TransportKind::default());
+    }
+
+    #[test]
+    fn profiles_are_selected_by_name() {
+        let config = config();
+        assert_eq!(config.profile(Some("test")).unwrap().account, 3);
+        assert_eq!(config.profile(None).unwrap().account, 3);
+        // The built in default needs no entry
+        assert_eq!(config.profile(Some(DEFAULT_PROFILE)).unwrap(), Profile::default());
+        assert!(matches!(
+            config.profile(Some("missing")),
+            Err(ConfigError::UnknownProfile(name)) if name == "missing"
+        ));
+        assert_eq!(Config::default().profile(None).unwrap(), Profile::default());
+
+        // A default profile that is not defined is an error like any other
+        let config = Config {
+            default_profile: Some("gone".to_string()),
+            ..Config::default()
+        };
+        assert!(matches!(config.profile(None), Err(ConfigError::UnknownProfile(_))));
+    }
+
+    #[test]
+    fn a_saved_configuration_loads_as_it_was() {
+        let path = path("plain");
+        assert_eq!(Config::load(&path, None).unwrap(), Config::default());
+        config().save(&path, None).unwrap();
+        assert!(!is_encrypted(&fs::read(&path).unwrap()));
+        assert_eq!(Config::load(&path, None).unwrap(), config());
+        // A passphrase is not needed for a plain file
+        assert_eq!(Config::load(&path, Some("unused")).unwrap(), config());
+        assert_eq!(config().wallet_label("0a1b2c3d"), Some("main wallet"));
+        assert_eq!(config().wallet_label("ffffffff"), None);
+    }
+
+    #[test]
+    fn an_encrypted_configuration_needs_its_passphrase() {
+        let path = path("encrypted");
+        config().save(&path, Some("passphrase")).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        assert!(is_encrypted(&bytes));
+        assert!(!String::from_utf8_lossy(&bytes).contains("esmeralda"));
+
+        assert_eq!(Config::load(&path, Some("passphrase")).unwrap(), config());
+        assert!(matches!(
+            Config::load(&path, None),
+            Err(ConfigError::PassphraseRequired)
+        ));
+        assert!(matches!(
+            Config::load(&path, Some("wrong")),
+            Err(ConfigError::Encryption)
+        ));
+
+        let mut tampered = bytes.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        fs::write(&path, tampered).unwrap();
+        assert!(matches!(
+            Config::load(&path, Some("passphrase")),
+            Err(ConfigError::Encryption)
+        ));
+
+        fs::write(&path, &bytes[..ENCRYPTED_CONFIG_MAGIC.len() + SALT_LENGTH]).unwrap();
+        assert!(matches!(
+            Config::load(&path, Some("passphrase")),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn every_encryption_is_salted_afresh() {
+        let first = encrypt(ENCRYPTED_CONFIG_MAGIC, b"same", "passphrase").unwrap();
+        let second = encrypt(ENCRYPTED_CONFIG_MAGIC, b"same", "passphrase").unwrap();
+        assert_ne!(first, second);
+        assert_eq!(decrypt(ENCRYPTED_CONFIG_MAGIC, &first, "passphrase").unwrap(), b"same");
+        assert_eq!(decrypt(ENCRYPTED_CONFIG_MAGIC, &second, "passphrase").unwrap(), b"same");
+    }
+
+    #[test]
+    fn a_malformed_file_is_refused() {
+        let path = path("malformed");
+        fs::write(&path, [0xff, 0xfe]).unwrap();
+        assert!(matches!(Config::load(&path, None), Err(ConfigError::Parse(_))));
+        fs::write(&path, "[profiles.test]\naccount = \"three\"").unwrap();
+        assert!(matches!(Config::load(&path, None), Err(ConfigError::Parse(_))));
+        assert!(matches!(
+            "[profiles.test]\nunits = \"BTC\"".parse::<Config>(),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn timeouts_of_zero_mean_no_limit() {
+        let timeouts = Timeouts {
+            command_secs: 0,
+            ..Timeouts::default()
+        };
+        assert_eq!(timeouts.command(), None);
+        assert_eq!(Timeouts::default().command(), Some(Duration::from_secs(300)));
+        assert_eq!(Timeouts::default().session_token(), Duration::from_secs(120));
+    }
+
+    #[test]
+    fn the_profile_locale_sets_the_separators() {
+        let profile = config().profile(None).unwrap();
+        let format = profile.amount_format(None);
+        assert_eq!(format, AmountFormat::new(Unit::Tari, Separators::for_locale("de_CH")));
+        assert_eq!(profile.amount_format(Some(Unit::MilliTari)).unit, Unit::MilliTari);
+    }
+}
@@ -0,0 +1,310 @@
+//! Conformance cases for the app's APDU parser
+//! The app and the host are built from separate codebases that only meet on the wire. The corpus generated here lists,
+//! for every case, the exact command bytes a host encoder produces, the layout of the response the app has to return,
+//! the status word it has to answer with and, for the commands that sign, whether the signature in the response has to
+//! verify over the values given. The app's CI replays the cases against the emulator and fails when either side has
+//! moved on without the other.
+//!
+//! Every case runs against a freshly started app with every setting enabled, and confirms every prompt. The encoders
+//! run against a [`DryRunTransport`], so a case's command is exactly what [`LedgerDevice`] sends.
+
+use ledger_transport::APDUCommand;
+use serde_json::{json, Value};
+use tari_crypto::{
+    commitment::HomomorphicCommitmentFactory,
+    ristretto::{
+        pedersen::{extended_commitment_factory::ExtendedPedersenCommitmentFactory, PedersenCommitment},
+        RistrettoSecretKey,
+    },
+    tari_utilities::{hex::to_hex, ByteArray},
+};
+use tari_ledger_protocol::{
+    CLA,
+    KERNEL_NONCE_HEADER_LENGTH,
+    MAX_NONCE_POOL_FETCH,
+    P1_KERNEL_NONCE,
+    P1_KERNEL_SIGN,
+    P1_NONCE_POOL_FETCH,
+    P1_NONCE_POOL_SIGN,
+    SW_CONVERSION_ERROR,
+    SW_NONCE_NOT_ISSUED,
+    SW_OK,
+    SW_TRANSACTION_NOT_APPROVED,
+};
+
+use crate::{
+    birthday::wallet_birthday,
+    commitment::{batch_commitments, CommitmentRequest},
+    device::{DerivationVersion, Instruction, KeyBranch, LedgerDevice},
+    domains::SCRIPT_MESSAGE_LABEL,
+    dry_run::{DryRunLog, DryRunTransport},
+    export::export_public_keys,
+    fee::FeeCalculator,
+    hashing::{DomainSeparatedConsensusHasher, TransactionHashDomain},
+    kernel::kernel_nonce,
+    nonce_pool::{fetch_nonces, invalidate_nonce_pool},
+    sender_offset::sign_sender_offset,
+    signer::{cancel_transaction, LedgerTransactionSigner, SignerMode},
+};
+
+/// The version of the corpus layout
+pub const CONFORMANCE_FORMAT_VERSION: u64 = 1;
+
+/// What the app's CI checks of the signature in a response
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SignatureCheck {
+    /// The response carries no signature
+    None,
+    /// `[public key][s][public nonce]` signs the script challenge over `message`, as `verify_script_signature` checks
+    ScriptChallenge { message: [u8; 32], verifies: bool },
+    /// `[sender offset public key][s][public nonce]` signs the metadata challenge over the output with `commitment`,
+    /// as `verify_sender_offset_signature` checks
+    MetadataSignature {
+        commitment: PedersenCommitment,
+        ephemeral_commitment: PedersenCommitment,
+        message: [u8; 32],
+        verifies: bool,
+    },
+}
+
+/// One command and what the app has to answer it with
+#[derive(Clone, Debug)]
+pub struct ConformanceCase {
+    pub name: &'static str,
+    /// The serialized APDU, header included
+    pub command: Vec<u8>,
+    pub response_schema: &'static str,
+    pub status: u16,
+    pub signature: SignatureCheck,
+}
+
+impl ConformanceCase {
+    fn to_json(&self) -> Value {
+        let signature = match &self.signature {
+            SignatureCheck::None => Value::Null,
+            SignatureCheck::ScriptChallenge { message, verifies } => json!({
+                "scheme": "script_challenge",
+                "message": to_hex(message),
+                "verifies": verifies,
+            }),
+            SignatureCheck::MetadataSignature {
+                commitment,
+                ephemeral_commitment,
+                message,
+                verifies,
+            } => json!({
+                "scheme": "metadata_signature",
+                "commitment": to_hex(commitment.as_bytes()),
+                "ephemeral_commitment": to_hex(ephemeral_commitment.as_bytes()),
+                "message": to_hex(message),
+                "verifies": verifies,
+            }),
+        };
+        json!({
+            "name": self.name,
+            "command": to_hex(&self.command),
+            "response_schema": self.response_schema,
+            "status": format!("0x{:04x}", self.status),
+            "signature": signature,
+        })
+    }
+}
+
+/// The corpus as a JSON document
+pub fn corpus_to_json(cases: &[ConformanceCase]) -> String {
+    let corpus = json!({
+        "version": CONFORMANCE_FORMAT_VERSION,
+        "cases": cases.iter().map(ConformanceCase::to_json).collect::<Vec<_>>(),
+    });
+    serde_json::to_string_pretty(&corpus).expect("a JSON value always serializes")
+}
+
+/// Every case: the commands of the host encoders, answered successfully, and malformed or out of order commands with
+/// the status the app refuses them with
+pub fn conformance_cases() -> Vec<ConformanceCase> {
+    let message = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(SCRIPT_MESSAGE_LABEL)
+        .chain(&42u64)
+        .finalize_challenge();
+    let mut other_message = *message.as_bytes();
+    other_message[0] ^= 0x01;
+    let factory = ExtendedPedersenCommitmentFactory::default();
+    let commitment = factory.commit_value(&RistrettoSecretKey::from(7u64), 1000);
+    let ephemeral_commitment = factory.commit_value(&RistrettoSecretKey::from(11u64), 0);
+    let output_message = [0x5a; 32];
+
+    let sign = encoded(
+        "sign_script_message",
+        SignatureCheck::ScriptChallenge {
+            message: *message.as_bytes(),
+            verifies: true,
+        },
+        |device| {
+            let signer = LedgerTransactionSigner::new(device, FeeCalculator::new(5)).with_mode(SignerMode::Offline);
+            signer.sign_script_message(&message)
+        },
+    );
+    // The signature over one message must not pass for another
+    let sign_other_message = ConformanceCase {
+        name: "sign_script_message_other_message",
+        signature: SignatureCheck::ScriptChallenge {
+            message: other_message,
+            verifies: false,
+        },
+        ..sign.clone()
+    };
+
+    let mut cases = vec![
+        encoded("get_capabilities", SignatureCheck::None, |device| device.capabilities()),
+        encoded("get_signing_counter", SignatureCheck::None, |device| {
+            device.signing_counter()
+        }),
+        encoded("get_output_counter", SignatureCheck::None, |device| {
+            device.output_counter()
+        }),
+        encoded("get_public_keys", SignatureCheck::None, |device| {
+            export_public_keys(device, 0, KeyBranch::ScriptKey, 0..4, |_, _| {})
+        }),
+        encoded("batch_commitment", SignatureCheck::None, |device| {
+            let requests = [CommitmentRequest { value: 1000, index: 0 }, CommitmentRequest {
+                value: 2500,
+                index: 1,
+            }];
+            batch_commitments(device, &requests)
+        }),
+        encoded("get_wallet_birthday", SignatureCheck::None, |device| {
+            wallet_birthday(device)
+        }),
+        encoded("kernel_nonce", SignatureCheck::None, |device| {
+            kernel_nonce(device, &[], &[0, 1], 0, 0, DerivationVersion::LATEST)
+        }),
+        encoded("nonce_pool_fetch", SignatureCheck::None, |device| {
+            fetch_nonces(device, 4)
+        }),
+        encoded("nonce_pool_invalidate", SignatureCheck::None, invalidate_nonce_pool),
+        encoded("transaction_cancel", SignatureCheck::None, cancel_transaction),
+        // A fresh app has no approved transaction to sign a sender offset under
+        ConformanceCase {
+            name: "sender_offset_sign_unapproved",
+            response_schema: "no data",
+            status: SW_TRANSACTION_NOT_APPROVED,
+            ..encoded("sender_offset_sign", SignatureCheck::None, |device| {
+                sign_sender_offset(
+                    device,
+                    0,
+                    0,
+                    &commitment,
+                    &ephemeral_commitment,
+                    &output_message,
+                    DerivationVersion::LATEST,
+                )
+            })
+        },
+        sign,
+        sign_other_message,
+    ];
+    let version = DerivationVersion::LATEST.as_byte();
+    cases.extend([
+        refused(
+            "kernel_nonce_without_keys",
+            Instruction::KernelSignature,
+            P1_KERNEL_NONCE,
+            version,
+            vec![0; KERNEL_NONCE_HEADER_LENGTH],
+            SW_CONVERSION_ERROR,
+        ),
+        refused(
+            "kernel_nonce_hardened_index",
+            Instruction::KernelSignature,
+            P1_KERNEL_NONCE,
+            version,
+            [kernel_nonce_header(0, 1), 0x8000_0000u32.to_le_bytes().to_vec()].concat(),
+            SW_CONVERSION_ERROR,
+        ),
+        refused(
+            "kernel_nonce_spending_unapproved",
+            Instruction::KernelSignature,
+            P1_KERNEL_NONCE,
+            version,
+            [kernel_nonce_header(1, 0), 0u32.to_le_bytes().to_vec()].concat(),
+            SW_TRANSACTION_NOT_APPROVED,
+        ),
+        refused(
+            "kernel_sign_without_nonce",
+            Instruction::KernelSignature,
+            P1_KERNEL_SIGN,
+            0x00,
+            vec![0; 3 * 32],
+            SW_TRANSACTION_NOT_APPROVED,
+        ),
+        refused(
+            "nonce_pool_fetch_too_many",
+            Instruction::NoncePool,
+            P1_NONCE_POOL_FETCH,
+            0x00,
+            vec![MAX_NONCE_POOL_FETCH as u8 + 1],
+            SW_CONVERSION_ERROR,
+        ),
+        refused(
+            "nonce_pool_sign_not_issued",
+            Instruction::NoncePool,
+            P1_NONCE_POOL_SIGN,
+            version,
+            [vec![0; 8], vec![0, 0, 1], 0u32.to_le_bytes().to_vec(), vec![0; 80]].concat(),
+            SW_NONCE_NOT_ISSUED,
+        ),
+    ]);
+    cases
+}
+
+/// The last command `encode` sends, which the app answers with `SW_OK`. The dry run's placeholder answers fail most
+/// checks of the response, so what `encode` returns is ignored.
+fn encoded<T, E, F>(name: &'static str, signature: SignatureCheck, encode: F) -> ConformanceCase
+where F: FnOnce(&LedgerDevice) -> Result<T, E> {
+    let log = DryRunLog::new();
+    let device = LedgerDevice::from_transport(DryRunTransport::new(log.clone()));
+    let _ = encode(&device);
+    let exchange = log
+        .exchanges()
+        .pop()
+        .expect("every conformance encoder sends a command");
+    ConformanceCase {
+        name,
+        command: exchange.command,
+        response_schema: exchange.response_schema,
+        status: SW_OK,
+        signature,
+    }
+}
+
+/// The header of a kernel nonce request over `inputs` and `outputs` masks, with no fee and lock height 0
+fn kernel_nonce_header(inputs: u8, outputs: u8) -> Vec<u8> {
+    let mut header = vec![0; KERNEL_NONCE_HEADER_LENGTH];
+    header[0] = inputs;
+    header[1] = outputs;
+    header
+}
+
+/// A command the app refuses with `status`
+fn refused(
+    name: &'static str,
+    instruction: Instruction,
+    p1: u8,
+    p2: u8,
+    data: Vec<u8>,
+    status: u16,
+) -> ConformanceCase {
+    let command = APDUCommand {
+        cla: CLA,
+        ins: instruction.as_byte(),
+        p1,
+        p2,
+        data,
+    };
+    ConformanceCase {
+        name,
+        command: command.serialize(),
+        response_schema: "no data",
+        status,
+        signature: SignatureCheck::None,
+    }
+}
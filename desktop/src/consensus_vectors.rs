@@ -0,0 +1,327 @@
+//! Golden consensus encoding vectors
+//! Every Borsh encoded value that ends up in a challenge is round-tripped against bytes produced by tari-core, so that
+//! a dependency bump that silently changes an encoding is caught before the device signs a challenge the base layer
+//! will reject. The domain separation tag of every label in the [`domains`](crate::domains) registry is pinned the
+//! same way.
+//!
+//! The vectors cover keys, commitments, integers, byte strings and scripts. Output features and covenants have none
+//! and are out of scope here: the app never encodes them, the host only encodes the [`sidechain`](crate::sidechain)
+//! features, and no output it builds carries a covenant.
+
+use std::fmt;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use digest::Digest;
+use serde::Deserialize;
+use tari_crypto::{
+    hash::blake2::Blake256,
+    hashing::DomainSeparation,
+    ristretto::{pedersen::PedersenCommitment, RistrettoPublicKey, RistrettoSecretKey},
+    tari_utilities::{
+        hex::{from_hex, to_hex},
+        ByteArray,
+    },
+};
+
+use crate::{
+    domains::{transaction_hash_labels, transaction_hash_tag},
+    hashing::{DomainSeparatedConsensusHasher, TransactionHashDomain},
+    script::{script_signature_message, ExecutionStack, TariScript},
+};
+
+const VECTORS: &str = include_str!("../fixtures/consensus_vectors.json");
+
+#[derive(Deserialize)]
+struct Vectors {
+    encodings: Vec<EncodingVector>,
+    challenges: Vec<ChallengeVector>,
+    scripts: Vec<ScriptVector>,
+    domains: Vec<DomainVector>,
+}
+
+/// A single value and its expected Borsh encoding. `value` is hex for byte-like types and decimal (or `none`) for
+/// integers.
+#[derive(Deserialize)]
+struct EncodingVector {
+    name: String,
+    #[serde(rename = "type")]
+    kind: String,
+    value: String,
+    encoded: String,
+}
+
+/// A domain separated challenge over `public_key || public_nonce || message`
+#[derive(Deserialize)]
+struct ChallengeVector {
+    name: String,
+    label: String,
+    public_key: String,
+    public_nonce: String,
+    message: String,
+    hash: String,
+}
+
+/// A script and input data, with the script encoding and the resulting script signature message
+#[derive(Deserialize)]
+struct ScriptVector {
+    name: String,
+    script: String,
+    input_data: String,
+    encoded_script: String,
+    message: String,
+}
+
+/// A label and the exact domain separation tag it is hashed under
+#[derive(Deserialize)]
+struct DomainVector {
+    label: String,
+    tag: String,
+}
+
+/// A vector that did not reproduce
+#[derive(Debug)]
+pub struct VectorFailure {
+    pub name: String,
+    pub reason: String,
+}
+
+impl fmt::Display for VectorFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.reason)
+    }
+}
+
+/// Check every bundled vector and return how many passed, or every vector that failed
+pub fn check_all() -> Result<usize, Vec<VectorFailure>> {
+    let vectors: Vectors = serde_json::from_str(VECTORS).expect("bundled consensus vectors are valid JSON");
+    let mut passed = 0;
+    let mut failures = Vec::new();
+
+    let encodings = vectors.encodings.iter().map(|v| (&v.name, check_encoding(v)));
+    let challenges = vectors.challenges.iter().map(|v| (&v.name, check_challenge(v)));
+    let scripts = vectors.scripts.iter().map(|v| (&v.name, check_script(v)));
+    let domains = vectors.domains.iter().map(|v| (&v.label, check_domain(v)));
+    for (name, result) in encodings.chain(challenges).chain(scripts).chain(domains) {
+        match result {
+            Ok(()) => passed += 1,
+            Err(reason) => failures.push(VectorFailure {
+                name: name.clone(),
+                reason,
+            }),
+        }
+    }
+
+    // A label without a pinned tag could drift unnoticed
+    for label in transaction_hash_labels() {
+        if !vectors.domains.iter().any(|v| v.label == label) {
+            failures.push(VectorFailure {
+                name: label.to_string(),
+                reason: "no domain vector pins this label".to_string(),
+            });
+        }
+    }
+
+    if failures.is_empty() {
+        Ok(passed)
+    } else {
+        Err(failures)
+    }
+}
+
+fn check_encoding(vector: &EncodingVector) -> Result<(), String> {
+    let encoded = decode_hex(&vector.encoded)?;
+    match vector.kind.as_str() {
+        "secret_key" => round_trip(&secret_key(&vector.value)?, &encoded),
+        "public_key" => round_trip(&public_key(&vector.value)?, &encoded),
+        "commitment" => {
+            let commitment = PedersenCommitment::from_bytes(&decode_hex(&vector.value)?)
+                .map_err(|_| "value is not a valid commitment".to_string())?;
+            round_trip(&commitment, &encoded)
+        },
+        "bytes32" => {
+            let bytes: [u8; 32] = decode_hex(&vector.value)?
+                .as_slice()
+                .try_into()
+                .map_err(|_| "value must be 32 bytes".to_string())?;
+            round_trip(&bytes, &encoded)
+        },
+        "bytes" => round_trip(&decode_hex(&vector.value)?, &encoded),
+        "u64" => round_trip(&parse_u64(&vector.value)?, &encoded),
+        "option_u64" => {
+            let value = match vector.value.as_str() {
+                "none" => None,
+                value => Some(parse_u64(value)?),
+            };
+            round_trip(&value, &encoded)
+        },
+        kind => Err(format!("unknown vector type '{}'", kind)),
+    }
+}
+
+fn check_challenge(vector: &ChallengeVector) -> Result<(), String> {
+    let label = registered_label(&vector.label)?;
+    let message: [u8; 32] = decode_hex(&vector.message)?
+        .as_slice()
+        .try_into()
+        .map_err(|_| "message must be 32 bytes".to_string())?;
+    let hash = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(label)
+        .chain(&public_key(&vector.public_key)?)
+        .chain(&public_key(&vector.public_nonce)?)
+        .chain(&message)
+        .finalize();
+
+    let expected = decode_hex(&vector.hash)?;
+    if hash.as_slice() != expected.as_slice() {
+        return Err(format!("hashed to {}, expected {}", to_hex(&hash), vector.hash));
+    }
+    Ok(())
+}
+
+fn check_domain(vector: &DomainVector) -> Result<(), String> {
+    let label = registered_label(&vector.label)?;
+    let tag = transaction_hash_tag(label);
+    if tag != vector.tag {
+        return Err(format!("tag is '{}', expected '{}'", tag, vector.tag));
+    }
+    // The hasher is fed the tag with its length as a little-endian `u64`
+    let mut tagged = Blake256::new();
+    TransactionHashDomain::add_domain_separation_tag(&mut tagged, label);
+    let mut expected = Blake256::new();
+    expected.update((vector.tag.len() as u64).to_le_bytes());
+    expected.update(vector.tag.as_bytes());
+    if tagged.finalize() != expected.finalize() {
+        return Err("the hasher is not fed the tag as expected".to_string());
+    }
+    Ok(())
+}
+
+/// The hasher only takes static labels, so a vector's label is looked up in the registry
+fn registered_label(label: &str) -> Result<&'static str, String> {
+    transaction_hash_labels()
+        .find(|registered| *registered == label)
+        .ok_or_else(|| format!("unknown label '{}'", label))
+}
+
+fn check_script(vector: &ScriptVector) -> Result<(), String> {
+    let script_bytes = decode_hex(&vector.script)?;
+    let script = TariScript::from_bytes(&script_bytes).map_err(|e| e.to_string())?;
+    if script.to_bytes() != script_bytes {
+        return Err(format!("script re-encoded to {}", to_hex(&script.to_bytes())));
+    }
+    round_trip(&script, &decode_hex(&vector.encoded_script)?)?;
+
+    let input_data = ExecutionStack::from_bytes(&decode_hex(&vector.input_data)?).map_err(|e| e.to_string())?;
+    let message = script_signature_message(&script, &input_data);
+    if to_hex(message.as_bytes()) != vector.message {
+        return Err(format!(
+            "script message is {}, expected {}",
+            to_hex(message.as_bytes()),
+            vector.message
+        ));
+    }
+    Ok(())
+}
+
+/// Encode `value`, compare it to `expected`, then decode `expected` and make sure it encodes back to the same bytes
+fn round_trip<T: BorshSerialize + BorshDeserialize>(value: &T, expected: &[u8]) -> Result<(), String> {
+    let encoded = value.try_to_vec().map_err(|e| e.to_string())?;
+    if encoded != expected {
+        return Err(format!(
+            "encoded to {}, expected {}",
+            to_hex(&encoded),
+            to_hex(expected)
+        ));
+    }
+    let decoded = T::try_from_slice(expected).map_err(|e| format!("failed to decode: {}", e))?;
+    let reencoded = decoded.try_to_vec().map_err(|e| e.to_string())?;
+    if reencoded != expected {
+        return Err(format!("decoded value re-encoded to {}", to_hex(&reencoded)));
+    }
+    Ok(())
+}
+
+fn secret_key(hex: &str) -> Result<RistrettoSecretKey, String> {
+    RistrettoSecretKey::from_bytes(&decode_hex(hex)?).map_err(|_| format!("'{}' is not a valid secret key", hex))
+}
+
+fn public_key(hex: &str) -> Result<RistrettoPublicKey, String> {
+    RistrettoPublicKey::from_bytes(&decode_hex(hex)?).map_err(|_| format!("'{}' is not a valid public key", hex))
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>, String> {
+    from_hex(hex).map_err(|_| format!("'{}' is not valid hex", hex))
+}
+
+fn parse_u64(value: &str) -> Result<u64, String> {
+    value.parse().map_err(|_| format!("'{}' is not a valid u64", value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn encoding(kind: &str, value: &str, encoded: &str) -> EncodingVector {
+        EncodingVector {
+            name: kind.to_string(),
+            kind: kind.to_string(),
+            value: value.to_string(),
+            encoded: encoded.to_string(),
+        }
+    }
+
+    #[test]
+    fn bundled_vectors_pass() {
+        if let Err(failures) = check_all() {
+            let failures = failures.iter().map(ToString::to_string).collect::<Vec<_>>();
+            panic!("consensus vectors failed:\n{}", failures.join("\n"));
+        }
+    }
+
+    #[test]
+    fn integers_encode_little_endian() {
+        assert!(check_encoding(&encoding("u64", "1", "0100000000000000")).is_ok());
+        assert!(check_encoding(&encoding("option_u64", "none", "00")).is_ok());
+        assert!(check_encoding(&encoding("option_u64", "2", "010200000000000000")).is_ok());
+        assert!(check_encoding(&encoding("u64", "1", "0000000000000001")).is_err());
+    }
+
+    #[test]
+    fn byte_strings_carry_their_length() {
+        assert!(check_encoding(&encoding("bytes", "abcd", "02000000abcd")).is_ok());
+        assert!(check_encoding(&encoding("bytes", "abcd", "abcd")).is_err());
+        let bytes32 = "11".repeat(32);
+        assert!(check_encoding(&encoding("bytes32", &bytes32, &bytes32)).is_ok());
+    }
+
+    #[test]
+    fn unknown_types_are_refused() {
+        assert!(check_encoding(&encoding("covenant", "", "")).is_err());
+    }
+
+    #[test]
+    fn a_changed_tag_is_caught() {
+        let vector = DomainVector {
+            label: "kernel_signature".to_string(),
+            tag: "com.tari.base_layer.core.transactions.v1.kernel_signature".to_string(),
+        };
+        assert!(check_domain(&vector).is_err());
+    }
+
+    #[test]
+    fn a_changed_challenge_is_caught() {
+        let vectors: Vectors = serde_json::from_str(VECTORS).unwrap();
+        let mut vector = vectors
+            .challenges
+            .into_iter()
+            .next()
+            .expect("at least one challenge vector");
+        assert!(check_challenge(&vector).is_ok());
+        vector.message = "00".repeat(32);
+        assert!(check_challenge(&vector).is_err());
+    }
+
+    #[test]
+    fn unregistered_labels_are_refused() {
+        assert!(registered_label("not_a_label").is_err());
+    }
+}
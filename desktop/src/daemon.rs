@@ -0,0 +1,672 @@
+//! Keeping the device open across invocations
+//! Every invocation of the CLI enumerates the HID devices and opens the Ledger before it sends its first APDU, which
+//! takes about a second. [`serve`] keeps a device open and passes it the APDUs that arrive on a Unix socket, and
+//! [`connect`] hands an invocation a [`LedgerDevice`] over that socket when a daemon listens on it, so consecutive
+//! commands reuse the open device.
+//!
+//! The daemon is a pipe: it sends every APDU as it arrives, so the handshake, an authenticated session and every
+//! prompt on the device remain the business of each invocation. A command that [`needs_confirmation`] is first put to
+//! `approve`, e.g. the [`ApprovalHooks`](crate::hooks::ApprovalHooks), and one refused there is answered with
+//! `SW_APPROVAL_REFUSED` without the device seeing it. Anyone who can connect to the socket can talk to the device, so
+//! [`bind`] creates it in a directory that only its owner can enter, see [`socket_path`]. Clients that identify with a
+//! token are held to the instruction classes of their [`ClientGrant`](crate::permissions::ClientGrant), and a command
+//! outside of them is answered with `SW_CLIENT_NOT_PERMITTED`. A client with a [`RequestQuota`] that sent as many
+//! commands within its window as the quota allows, or as many that need confirmation, is answered with
+//! `SW_CLIENT_QUOTA_EXCEEDED` until the oldest of them leaves the window.
+//!
+//! A connection starts with the daemon naming the device: the length of the Speculos name of its model and the name,
+//! empty if the model is not known, then the length of its USB serial number and the serial number. The invocation
+//! answers with its deadline for a command, in milliseconds as a big-endian `u32`, 0 for none, and the length of its
+//! token and the token, empty for the owner of the socket. The daemon answers with 1 if it knows the token and 0
+//! before it hangs up if it does not. APDUs follow, framed as on the APDU port of Speculos, see [`TransportSpeculos`].
+//! The daemon serves one connection at a time and drops the device when an exchange with it fails, e.g. because it was
+//! unplugged, to open it again for the next one. At most [`ServeLimits::queue_depth`] connections wait for their turn,
+//! so that a flood of them cannot line up prompts for the user, and one that arrives while the queue is full is closed
+//! before the daemon names the device, as if it were busy. A connection that sends nothing for
+//! [`ServeLimits::idle_timeout`] is closed, so that an invocation that stalls cannot hold on to the device.
+//!
+//! A prompt of the daemon holds up every invocation that waits for its turn, so `approve` has to answer within
+//! [`ServeLimits::prompt_timeout`], and a prompt that is not answered by then refuses the command. While a command
+//! that [`needs_confirmation`] waits for the user, the daemon watches the invocation. When it hangs up or the deadline
+//! passes, the daemon shuts the connection down, so the invocation stops waiting with
+//! [`DeviceError::DeadlineExceeded`]. When the user has not answered on the device within the prompt timeout, the
+//! daemon answers `SW_APPROVAL_REFUSED` for them before it shuts the connection down. The prompt on the device cannot
+//! be taken back over APDU, so the daemon waits for the user to answer it, discards the answer and, for a transaction
+//! summary or kernel nonce that the user approved, drops the approval with [`cancel_transaction`], so that nothing can
+//! be signed under it by whoever connects next.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::{self, DirBuilder, Permissions},
+    io::{self, Read, Write},
+    net::Shutdown,
+    os::unix::{
+        fs::{DirBuilderExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, TrySendError},
+        Arc,
+        Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use ledger_transport::{APDUAnswer, APDUCommand};
+use tari_ledger_protocol::{
+    APDU_HEADER_LENGTH,
+    P1_KERNEL_NONCE,
+    SW_APPROVAL_REFUSED,
+    SW_CLIENT_NOT_PERMITTED,
+    SW_CLIENT_QUOTA_EXCEEDED,
+    SW_OK,
+};
+
+use crate::{
+    device::{Instruction, LedgerDevice},
+    errors::DeviceError,
+    hooks::needs_confirmation,
+    limits::DeviceModel,
+    permissions::{ClientPermissions, RequestQuota},
+    signer::cancel_transaction,
+    speculos::TransportSpeculos,
+    transport::LedgerTransport,
+};
+
+/// The name of the directory of the socket in the data directory
+pub const DAEMON_DIRECTORY_NAME: &str = "daemon";
+/// The name of the socket in its directory
+pub const DAEMON_SOCKET_NAME: &str = "daemon.sock";
+/// How many connections wait for their turn unless the daemon is told otherwise
+pub const DEFAULT_QUEUE_DEPTH: usize = 4;
+/// How long a connection may send nothing unless the daemon is told otherwise
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// How long the user has to answer a prompt unless the daemon is told otherwise
+pub const DEFAULT_PROMPT_TIMEOUT: Duration = Duration::from_secs(120);
+/// How long a daemon has to name its device. One that is busy with another invocation does not answer until that one
+/// is done, and is better skipped.
+const NAMING_TIMEOUT: Duration = Duration::from_secs(1);
+/// How often the daemon looks whether the invocation is still there while the device waits for the user
+const WATCH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How the daemon shares the device between the connections it serves
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ServeLimits {
+    /// How many connections may wait for their turn, later ones are closed
+    pub queue_depth: usize,
+    /// How long a connection may leave the daemon waiting for its next command before it is closed
+    pub idle_timeout: Duration,
+    /// How long a prompt of the daemon, or the confirmation on the device, may wait for the user before the command
+    /// is refused
+    pub prompt_timeout: Duration,
+}
+
+impl Default for ServeLimits {
+    fn default() -> Self {
+        Self {
+            queue_depth: DEFAULT_QUEUE_DEPTH,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            prompt_timeout: DEFAULT_PROMPT_TIMEOUT,
+        }
+    }
+}
+
+/// A device held open by a daemon
+pub struct TransportDaemon {
+    stream: Mutex<UnixStream>,
+    deadline: Option<Duration>,
+}
+
+impl LedgerTransport for TransportDaemon {
+    fn exchange(&self, command: &APDUCommand<Vec<u8>>) -> Result<APDUAnswer<Vec<u8>>, DeviceError> {
+        let mut stream = self.stream.lock().expect("the daemon connection is never poisoned");
+        TransportSpeculos::write_command(&mut *stream, command)?;
+        match (TransportSpeculos::read_answer(&mut *stream), self.deadline) {
+            (Err(DeviceError::Io(e)), Some(deadline))
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::UnexpectedEof | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                Err(DeviceError::DeadlineExceeded(deadline))
+            },
+            (answer, _) => answer,
+        }
+    }
+}
+
+/// The device of the daemon listening on `path`, which gives up on a command the device has not answered within
+/// `deadline`, for the client with `token`, or the owner of the socket without one. Fails if none listens, it cannot
+/// open its device or it is busy, and with [`DeviceError::ClientNotAuthorized`] if it does not know the token.
+pub fn connect(path: &Path, deadline: Option<Duration>, token: Option<&str>) -> Result<LedgerDevice, DeviceError> {
+    let mut stream = UnixStream::connect(path)?;
+    stream.set_read_timeout(Some(NAMING_TIMEOUT))?;
+    let model = read_field(&mut stream)?;
+    let device_id = read_field(&mut stream)?;
+    write_deadline(&mut stream, deadline)?;
+    write_field(&mut stream, token.unwrap_or_default())?;
+    let mut known = [0u8; 1];
+    stream.read_exact(&mut known)?;
+    if known[0] != 1 {
+        return Err(DeviceError::ClientNotAuthorized);
+    }
+    // The user may take minutes to confirm on the device, the daemon hangs up once the deadline passed
+    stream.set_read_timeout(deadline)?;
+    let device = LedgerDevice::from_transport(TransportDaemon {
+        stream: Mutex::new(stream),
+        deadline,
+    });
+    let device = match DeviceModel::from_speculos_name(&model) {
+        Some(model) => device.with_model(model),
+        None => device,
+    };
+    Ok(if device_id.is_empty() {
+        device
+    } else {
+        device.with_device_id(device_id)
+    })
+}
+
+/// Where the daemon of the data directory `data_dir` listens
+pub fn socket_path(data_dir: &Path) -> PathBuf {
+    data_dir.join(DAEMON_DIRECTORY_NAME).join(DAEMON_SOCKET_NAME)
+}
+
+/// Listen on `path`, replacing a socket a daemon that is no longer running left behind. The directory of the socket is
+/// created if needed and made the owner's alone before the socket is bound in it, so nobody else can connect however
+/// briefly, and one that is not a directory or belongs to someone else is refused.
+pub fn bind(path: &Path) -> io::Result<UnixListener> {
+    let directory = path
+        .parent()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "the socket has no directory"))?;
+    match DirBuilder::new().mode(0o700).create(directory) {
+        Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+        _ => {},
+    }
+    // A directory left behind by an earlier daemon may have been opened up since, and only its owner can close it
+    if !fs::symlink_metadata(directory)?.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the directory of the socket is not a directory",
+        ));
+    }
+    fs::set_permissions(directory, Permissions::from_mode(0o700))?;
+    if UnixStream::connect(path).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            "another daemon is listening on the socket",
+        ));
+    }
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {},
+    }
+    UnixListener::bind(path)
+}
+
+/// Serve every connection to `listener` in turn with the device `open` returns, keeping it open from one connection
+/// to the next, to the clients `permissions` knows and with the instructions and quotas it grants them, within
+/// `limits`. `approve` is asked about every command that [`needs_confirmation`], and has to answer by the instant it is
+/// given. `on_error` is told why the device could not be opened or was dropped.
+pub fn serve(
+    listener: &UnixListener,
+    permissions: &ClientPermissions,
+    limits: &ServeLimits,
+    mut open: impl FnMut() -> Result<LedgerDevice, DeviceError>,
+    mut approve: impl FnMut(&APDUCommand<Vec<u8>>, Instant) -> bool,
+    mut on_error: impl FnMut(&DeviceError),
+) -> io::Result<()> {
+    let (queue, waiting) = mpsc::sync_channel(limits.queue_depth);
+    let acceptor = listener.try_clone()?;
+    thread::spawn(move || {
+        for stream in acceptor.incoming() {
+            match stream {
+                Err(e) => {
+                    let _ = queue.send(Err(e));
+                    break;
+                },
+                // A connection the queue has no room for is dropped, and with it closed
+                Ok(stream) => {
+                    if let Err(TrySendError::Disconnected(_)) = queue.try_send(Ok(stream)) {
+                        break;
+                    }
+                },
+            }
+        }
+    });
+    let mut usage = HashMap::new();
+    let mut device = None;
+    for stream in waiting {
+        let mut stream = stream?;
+        if device.is_none() {
+            // The connection is closed without naming a device, and the invocation opens the device itself
+            match open() {
+                Ok(opened) => device = Some(opened),
+                Err(e) => {
+                    on_error(&e);
+                    continue;
+                },
+            }
+        }
+        let opened = device.as_ref().expect("the device was opened above");
+        if let Err(e) = serve_connection(
+            opened,
+            &mut stream,
+            permissions,
+            limits,
+            &mut usage,
+            &mut approve,
+            &mut on_error,
+        ) {
+            on_error(&e);
+            device = None;
+        }
+    }
+    Ok(())
+}
+
+/// Pass the APDUs of one connection to `device` until the invocation hangs up, gives up on a command or sends nothing
+/// for the idle timeout of `limits`, counting them in the `usage` of its client. Only a failed exchange with the device
+/// is an error, a connection that breaks off just ends, and `on_error` is told about an approval that could not be
+/// dropped.
+fn serve_connection(
+    device: &LedgerDevice,
+    stream: &mut UnixStream,
+    permissions: &ClientPermissions,
+    limits: &ServeLimits,
+    usage: &mut HashMap<String, Usage>,
+    approve: &mut impl FnMut(&APDUCommand<Vec<u8>>, Instant) -> bool,
+    on_error: &mut impl FnMut(&DeviceError),
+) -> Result<(), DeviceError> {
+    let model = device.model().map_or("", DeviceModel::speculos_name);
+    let device_id = device.device_id().unwrap_or_default();
+    if write_field(stream, model)
+        .and_then(|_| write_field(stream, device_id))
+        .is_err()
+    {
+        return Ok(());
+    }
+    let Ok((deadline, token)) = stream
+        .set_read_timeout(Some(NAMING_TIMEOUT))
+        .and_then(|_| read_deadline(stream))
+        .and_then(|deadline| Ok((deadline, read_bytes(stream)?)))
+        .and_then(|client| stream.set_read_timeout(Some(limits.idle_timeout)).map(|_| client))
+    else {
+        return Ok(());
+    };
+    let Some(grant) = permissions.grant(&token) else {
+        let _ = stream.write_all(&[0]);
+        return Ok(());
+    };
+    if stream.write_all(&[1]).is_err() {
+        return Ok(());
+    }
+    let usage = usage.entry(grant.name.clone()).or_default();
+    loop {
+        let Ok(command) = read_command(stream) else {
+            return Ok(());
+        };
+        let confirmation = needs_confirmation(&command);
+        let answer = if !grant.allows(&command) {
+            status_answer(SW_CLIENT_NOT_PERMITTED)
+        } else if grant
+            .quota
+            .is_some_and(|quota| !usage.admit(&quota, confirmation, Instant::now()))
+        {
+            status_answer(SW_CLIENT_QUOTA_EXCEEDED)
+        } else if !confirmation {
+            device.transport().exchange(&command)?
+        } else if approve(&command, Instant::now() + limits.prompt_timeout) {
+            let now = Instant::now();
+            let watch = Watch::start(
+                stream,
+                deadline.map(|deadline| now + deadline),
+                now + limits.prompt_timeout,
+            )?;
+            let answer = device.transport().exchange(&command);
+            if watch.stop() {
+                if answer.as_ref().is_ok_and(|answer| answer.retcode() == SW_OK) && leaves_approval(&command) {
+                    match cancel_transaction(device) {
+                        // A command outside the session of the departed invocation closes it, and drops its approval
+                        Ok(()) | Err(DeviceError::SessionMacRejected) => {},
+                        Err(e) => on_error(&e),
+                    }
+                }
+                return answer.map(|_| ());
+            }
+            stream.set_read_timeout(Some(limits.idle_timeout))?;
+            answer?
+        } else {
+            // The daemon does not hold the key of the invocation's session, so it answers with a status word of its own
+            // that the invocation takes without a MAC
+            status_answer(SW_APPROVAL_REFUSED)
+        };
+        if write_answer(stream, &answer).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+/// An answer the daemon gives in place of the device
+fn status_answer(status: u16) -> APDUAnswer<Vec<u8>> {
+    APDUAnswer::from_answer(status.to_be_bytes().to_vec()).expect("a status word alone is an answer")
+}
+
+/// The commands a client sent within the window of its quota, and of them the ones that needed confirmation
+#[derive(Default)]
+struct Usage {
+    commands: VecDeque<Instant>,
+    confirmations: VecDeque<Instant>,
+}
+
+impl Usage {
+    /// Count a command sent at `now` against `quota`, unless the quota is used up. A refused command does not count.
+    fn admit(&mut self, quota: &RequestQuota, confirmation: bool, now: Instant) -> bool {
+        for sent in [&mut self.commands, &mut self.confirmations] {
+            while sent
+                .front()
+                .is_some_and(|sent| now.duration_since(*sent) >= RequestQuota::WINDOW)
+            {
+                sent.pop_front();
+            }
+        }
+        if self.commands.len() >= quota.commands as usize ||
+            (confirmation && self.confirmations.len() >= quota.confirmations as usize)
+        {
+            return false;
+        }
+        self.commands.push_back(now);
+        if confirmation {
+            self.confirmations.push_back(now);
+        }
+        true
+    }
+}
+
+/// Whether the app keeps something the user approved with `command` for later commands to sign under
+fn leaves_approval(command: &APDUCommand<Vec<u8>>) -> bool {
+    match Instruction::try_from(command.ins) {
+        Ok(Instruction::TransactionSummary) => needs_confirmation(command),
+        Ok(Instruction::KernelSignature) => command.p1 == P1_KERNEL_NONCE,
+        _ => false,
+    }
+}
+
+/// Watches the invocation while the device waits for the user to confirm its command, and shuts the connection down
+/// once the invocation hung up, its deadline passed or the user did not answer by the end of the prompt, which refuses
+/// the command
+struct Watch {
+    done: Arc<AtomicBool>,
+    handle: JoinHandle<bool>,
+}
+
+impl Watch {
+    fn start(stream: &UnixStream, deadline: Option<Instant>, prompt_deadline: Instant) -> io::Result<Self> {
+        // The clone shares the socket, and with it the read timeout the invocation is polled with
+        let mut stream = stream.try_clone()?;
+        stream.set_read_timeout(Some(WATCH_INTERVAL))?;
+        let done = Arc::new(AtomicBool::new(false));
+        let handle = thread::spawn({
+            let done = done.clone();
+            move || {
+                let abandoned = loop {
+                    if done.load(Ordering::Acquire) {
+                        break false;
+                    }
+                    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                        break true;
+                    }
+                    if Instant::now() >= prompt_deadline {
+                        let _ = write_answer(&mut stream, &status_answer(SW_APPROVAL_REFUSED));
+                        break true;
+                    }
+                    match stream.peek(&mut [0u8; 1]) {
+                        Ok(0) => break true,
+                        // The invocation wrote ahead of the answer, which it is not meant to, and was not hung up
+                        Ok(_) => thread::sleep(WATCH_INTERVAL),
+                        Err(e)
+                            if matches!(
+                                e.kind(),
+                                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted
+                            ) => {},
+                        Err(_) => break true,
+                    }
+                };
+                if abandoned {
+                    let _ = stream.shutdown(Shutdown::Both);
+                }
+                abandoned
+            }
+        });
+        Ok(Self { done, handle })
+    }
+
+    /// Stop watching once the device answered, and whether the invocation was gone by then
+    fn stop(self) -> bool {
+        self.done.store(true, Ordering::Release);
+        self.handle.join().unwrap_or(true)
+    }
+}
+
+fn write_field(stream: &mut impl Write, field: &str) -> io::Result<()> {
+    let length = u8::try_from(field.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "a device field is longer than 255 bytes"))?;
+    stream.write_all(&[length])?;
+    stream.write_all(field.as_bytes())
+}
+
+fn read_field(stream: &mut impl Read) -> Result<String, DeviceError> {
+    String::from_utf8(read_bytes(stream)?)
+        .map_err(|_| DeviceError::InvalidResponse("the daemon named the device in invalid UTF-8"))
+}
+
+fn read_bytes(stream: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut length = [0u8; 1];
+    stream.read_exact(&mut length)?;
+    let mut field = vec![0u8; usize::from(length[0])];
+    stream.read_exact(&mut field)?;
+    Ok(field)
+}
+
+fn write_deadline(stream: &mut impl Write, deadline: Option<Duration>) -> io::Result<()> {
+    // A deadline of 50 days is none
+    let millis = deadline.map_or(0, |deadline| u32::try_from(deadline.as_millis()).unwrap_or(0));
+    stream.write_all(&millis.to_be_bytes())
+}
+
+fn read_deadline(stream: &mut impl Read) -> io::Result<Option<Duration>> {
+    let mut millis = [0u8; 4];
+    stream.read_exact(&mut millis)?;
+    Ok(match u32::from_be_bytes(millis) {
+        0 => None,
+        millis => Some(Duration::from_millis(u64::from(millis))),
+    })
+}
+
+/// A command as [`TransportSpeculos`] writes it: its length as a big-endian `u32`, then the serialized APDU
+fn read_command(stream: &mut impl Read) -> io::Result<APDUCommand<Vec<u8>>> {
+    let mut length = [0u8; 4];
+    stream.read_exact(&mut length)?;
+    let length = u32::from_be_bytes(length) as usize;
+    // The header, with the length of the data, and at most 255 bytes of data
+    if length > APDU_HEADER_LENGTH + usize::from(u8::MAX) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the command is longer than an APDU",
+        ));
+    }
+    let mut apdu = vec![0u8; length];
+    stream.read_exact(&mut apdu)?;
+    match apdu.as_slice() {
+        [cla, ins, p1, p2, data_length, data @ ..] if usize::from(*data_length) == data.len() => Ok(APDUCommand {
+            cla: *cla,
+            ins: *ins,
+            p1: *p1,
+            p2: *p2,
+            data: data.to_vec(),
+        }),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "not a serialized APDU")),
+    }
+}
+
+/// An answer as [`TransportSpeculos`] reads it: the length of the data as a big-endian `u32`, the data and the status
+/// word
+fn write_answer(stream: &mut impl Write, answer: &APDUAnswer<Vec<u8>>) -> io::Result<()> {
+    let data = answer.data();
+    // An answer is a few hundred bytes at most
+    stream.write_all(&(data.len() as u32).to_be_bytes())?;
+    stream.write_all(data)?;
+    stream.write_all(&answer.retcode().to_be_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use tari_ledger_protocol::P1_TRANSACTION_CANCEL;
+
+    use super::*;
+    use crate::test_helpers::command;
+
+    #[test]
+    fn deadlines_round_trip() {
+        for deadline in [None, Some(Duration::from_millis(1)), Some(Duration::from_secs(300))] {
+            let mut written = Vec::new();
+            write_deadline(&mut written, deadline).unwrap();
+            assert_eq!(read_deadline(&mut written.as_slice()).unwrap(), deadline);
+        }
+        let mut written = Vec::new();
+        write_deadline(&mut written, Some(Duration::from_secs(u64::from(u32::MAX)))).unwrap();
+        assert_eq!(read_deadline(&mut written.as_slice()).unwrap(), None);
+    }
+
+    #[test]
+    fn commands_round_trip() {
+        let sent = APDUCommand {
+            data: vec![1, 2, 3],
+            ..command(Instruction::GetVersion, 0)
+        };
+        let mut written = Vec::new();
+        TransportSpeculos::write_command(&mut written, &sent).unwrap();
+        assert_eq!(
+            read_command(&mut written.as_slice()).unwrap().serialize(),
+            sent.serialize()
+        );
+        let oversized = (APDU_HEADER_LENGTH as u32 + 256).to_be_bytes();
+        assert!(read_command(&mut oversized.as_slice()).is_err());
+    }
+
+    #[test]
+    fn only_approvals_the_app_keeps_are_cancelled() {
+        assert!(leaves_approval(&command(Instruction::TransactionSummary, 0)));
+        assert!(!leaves_approval(&command(
+            Instruction::TransactionSummary,
+            P1_TRANSACTION_CANCEL
+        )));
+        assert!(leaves_approval(&command(Instruction::KernelSignature, P1_KERNEL_NONCE)));
+        assert!(!leaves_approval(&command(Instruction::Sign, 0)));
+    }
+
+    #[test]
+    fn quotas_free_up_as_commands_leave_the_window() {
+        let quota = RequestQuota {
+            commands: 3,
+            confirmations: 1,
+        };
+        let mut usage = Usage::default();
+        let start = Instant::now();
+        assert!(usage.admit(&quota, true, start));
+        assert!(!usage.admit(&quota, true, start));
+        assert!(usage.admit(&quota, false, start));
+        assert!(usage.admit(&quota, false, start + Duration::from_secs(1)));
+        assert!(!usage.admit(&quota, false, start + Duration::from_secs(1)));
+        let later = start + RequestQuota::WINDOW;
+        assert!(usage.admit(&quota, true, later));
+        assert!(usage.admit(&quota, false, later));
+        assert!(!usage.admit(&quota, false, later));
+    }
+
+    #[test]
+    fn the_socket_is_bound_in_a_private_directory() {
+        let data_dir = std::env::temp_dir().join(format!("tari-ledger-daemon-{}", std::process::id()));
+        fs::create_dir_all(&data_dir).unwrap();
+        let path = socket_path(&data_dir);
+        let bound = bind(&path).map(|_listener| {
+            let mode = fs::metadata(path.parent().unwrap()).unwrap().permissions().mode();
+            (mode & 0o777, bind(&path).is_err())
+        });
+        fs::remove_dir_all(&data_dir).unwrap();
+        assert_eq!(bound.unwrap(), (0o700, true));
+    }
+
+    /// Answers every command with success
+    struct Answering;
+
+    impl LedgerTransport for Answering {
+        fn exchange(&self, _command: &APDUCommand<Vec<u8>>) -> Result<APDUAnswer<Vec<u8>>, DeviceError> {
+            Ok(status_answer(SW_OK))
+        }
+    }
+
+    #[test]
+    fn a_silent_invocation_is_hung_up_on() {
+        let (mut daemon, mut invocation) = UnixStream::pair().unwrap();
+        let (served, hang_up) = mpsc::channel::<()>();
+        let client = thread::spawn(move || {
+            read_field(&mut invocation).unwrap();
+            read_field(&mut invocation).unwrap();
+            write_deadline(&mut invocation, None).unwrap();
+            write_field(&mut invocation, "").unwrap();
+            let mut known = [0u8; 1];
+            invocation.read_exact(&mut known).unwrap();
+            // The invocation stays connected without sending anything until the daemon is done with it
+            let _ = hang_up.recv();
+            known[0]
+        });
+        let device = LedgerDevice::from_transport(Answering);
+        let start = Instant::now();
+        serve_connection(
+            &device,
+            &mut daemon,
+            &ClientPermissions::default(),
+            &ServeLimits {
+                idle_timeout: Duration::from_millis(200),
+                ..ServeLimits::default()
+            },
+            &mut HashMap::new(),
+            &mut |_, _| true,
+            &mut |_| {},
+        )
+        .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        served.send(()).unwrap();
+        assert_eq!(client.join().unwrap(), 1);
+    }
+
+    #[test]
+    fn the_watch_sees_the_invocation_leave() {
+        let later = Instant::now() + Duration::from_secs(60);
+        let (daemon, invocation) = UnixStream::pair().unwrap();
+        let watch = Watch::start(&daemon, None, later).unwrap();
+        drop(invocation);
+        thread::sleep(WATCH_INTERVAL * 3);
+        assert!(watch.stop());
+
+        let (daemon, _invocation) = UnixStream::pair().unwrap();
+        assert!(!Watch::start(&daemon, None, later).unwrap().stop());
+        let watch = Watch::start(&daemon, Some(Instant::now()), later).unwrap();
+        thread::sleep(WATCH_INTERVAL * 3);
+        assert!(watch.stop());
+    }
+
+    #[test]
+    fn an_unanswered_prompt_is_refused() {
+        let (daemon, mut invocation) = UnixStream::pair().unwrap();
+        let watch = Watch::start(&daemon, None, Instant::now()).unwrap();
+        thread::sleep(WATCH_INTERVAL * 3);
+        assert!(watch.stop());
+        let answer = TransportSpeculos::read_answer(&mut invocation).unwrap();
+        assert_eq!(answer.retcode(), SW_APPROVAL_REFUSED);
+    }
+}
@@ -0,0 +1,181 @@
+//! Splitting and consolidating outputs
+//! Spending an output locks up all of its value until the transaction is mined, so a miner paid in a few large
+//! outputs is better off splitting them into the denominations they will spend, and a wallet full of small outputs
+//! pays less in fees once they are consolidated. Splitting turns one output the device can spend into `parts` outputs
+//! of equal value, consolidating turns several into one. Every new output belongs to this wallet, with its keys at the
+//! next unused indices of the state store and its commitment made on the device, so the device shows nothing but the
+//! fee.
+
+use serde_json::{json, Value};
+use tari_crypto::tari_utilities::{hex::to_hex, ByteArray};
+
+use crate::{
+    commitment::{batch_commitments_with_version, CommitmentRequest},
+    device::DerivationVersion,
+    domains::CHANGE_OUTPUT_LABEL,
+    errors::{DenominationError, SignerError},
+    hashing::Challenge,
+    signature::LedgerSignature,
+    signer::{change_output_size, ChangeOutput, LedgerTransactionSigner, OutputToSign},
+    state_store::LedgerStateStore,
+    sweep::{input_json, signature_json, SweepInput},
+};
+
+/// The device protocol limits a transaction to 255 outputs
+pub const MAX_SPLIT_PARTS: usize = 255;
+
+/// A signed transaction spending outputs of this wallet into new outputs of this wallet
+#[derive(Clone, Debug)]
+pub struct Reshaped {
+    pub fee: u64,
+    pub inputs: Vec<SweepInput>,
+    /// The script signature of every input, in order
+    pub input_signatures: Vec<LedgerSignature>,
+    pub outputs: Vec<ChangeOutput>,
+    /// The script signature of every output, in order
+    pub output_signatures: Vec<LedgerSignature>,
+    /// The scheme the keys of the new outputs are derived under
+    pub derivation_version: DerivationVersion,
+}
+
+impl Reshaped {
+    /// The signed transaction as JSON for the wallet to finish, including the key index of every new output and the
+    /// scheme its keys are derived under so that the wallet can recover it from the seed
+    pub fn to_json(&self) -> String {
+        let inputs = self
+            .inputs
+            .iter()
+            .zip(&self.input_signatures)
+            .map(|(input, signature)| input_json(input, signature))
+            .collect::<Vec<_>>();
+        let outputs = self
+            .outputs
+            .iter()
+            .zip(&self.output_signatures)
+            .map(|(output, signature)| output_json(output, signature))
+            .collect::<Vec<_>>();
+        let document = json!({
+            "fee": self.fee,
+            "derivation_version": self.derivation_version.as_byte(),
+            "inputs": inputs,
+            "outputs": outputs,
+        });
+        serde_json::to_string_pretty(&document).expect("a JSON value always serializes")
+    }
+}
+
+/// Split `input` into `parts` outputs of equal value. What the fee leaves over after an even split goes to the first
+/// output.
+pub fn split_output(
+    signer: &LedgerTransactionSigner,
+    store: &dyn LedgerStateStore,
+    input: SweepInput,
+    parts: usize,
+) -> Result<Reshaped, DenominationError> {
+    if !(2..=MAX_SPLIT_PARTS).contains(&parts) {
+        return Err(DenominationError::InvalidParts(parts));
+    }
+    let fee = reshape_fee(signer, 1, parts);
+    // Cannot truncate, there are at most 255 parts
+    let part = input
+        .value
+        .checked_sub(fee)
+        .map(|value| value / parts as u64)
+        .filter(|part| *part > 0)
+        .ok_or(SignerError::InsufficientFunds {
+            available: input.value,
+            required: fee.saturating_add(parts as u64),
+        })?;
+    let mut values = vec![part; parts];
+    values[0] += input.value - fee - part * parts as u64;
+    reshape(signer, store, vec![input], &values)
+}
+
+/// Consolidate `inputs` into a single output holding their total, less the fee
+pub fn consolidate_outputs(
+    signer: &LedgerTransactionSigner,
+    store: &dyn LedgerStateStore,
+    inputs: Vec<SweepInput>,
+) -> Result<Reshaped, DenominationError> {
+    if inputs.len() < 2 {
+        return Err(DenominationError::NothingToConsolidate);
+    }
+    let total = inputs
+        .iter()
+        .try_fold(0u64, |total, input| total.checked_add(input.value))
+        .ok_or(SignerError::ValueOverflow)?;
+    let fee = reshape_fee(signer, inputs.len(), 1);
+    let value = total
+        .checked_sub(fee)
+        .filter(|value| *value > 0)
+        .ok_or(SignerError::InsufficientFunds {
+            available: total,
+            required: fee.saturating_add(1),
+        })?;
+    reshape(signer, store, inputs, &[value])
+}
+
+/// The fee of spending `num_inputs` inputs into `num_outputs` outputs of this wallet, which only depends on the shape
+/// of the transaction
+pub(crate) fn reshape_fee(signer: &LedgerTransactionSigner, num_inputs: usize, num_outputs: usize) -> u64 {
+    // Only weighed, never signed
+    let output = OutputToSign {
+        value: 0,
+        is_change: true,
+        features_and_scripts_size: change_output_size(),
+        challenge: Challenge::from_hashed(CHANGE_OUTPUT_LABEL, [0u8; 32]),
+        recipient: None,
+        features_title: None,
+    };
+    signer.fee(num_inputs, &vec![output; num_outputs])
+}
+
+fn reshape(
+    signer: &LedgerTransactionSigner,
+    store: &dyn LedgerStateStore,
+    inputs: Vec<SweepInput>,
+    values: &[u64],
+) -> Result<Reshaped, DenominationError> {
+    // Refuse before reserving any key index if an input is not ours to spend
+    let requests = inputs
+        .iter()
+        .map(|input| CommitmentRequest {
+            value: input.value,
+            index: input.mask_index,
+        })
+        .collect::<Vec<_>>();
+    let commitments = batch_commitments_with_version(signer.device(), &requests, signer.derivation_version())
+        .map_err(SignerError::from)?;
+    for (input, commitment) in inputs.iter().zip(commitments) {
+        if input.commitment != commitment {
+            return Err(DenominationError::NotSpendable(to_hex(input.commitment.as_bytes())));
+        }
+    }
+
+    let outputs = signer.change_outputs(store, values)?;
+    let to_sign = outputs.iter().map(ChangeOutput::to_output).collect::<Vec<_>>();
+    let signed = signer.sign_outputs(inputs.len(), &to_sign)?;
+    let input_signatures = inputs
+        .iter()
+        .map(|input| signer.sign_script_message(&input.script_message))
+        .collect::<Result<_, _>>()?;
+    Ok(Reshaped {
+        fee: signed.fee,
+        inputs,
+        input_signatures,
+        outputs,
+        output_signatures: signed.signatures,
+        derivation_version: signer.derivation_version(),
+    })
+}
+
+fn output_json(output: &ChangeOutput, signature: &LedgerSignature) -> Value {
+    json!({
+        "value": output.value,
+        "key_index": output.key_index,
+        "commitment": to_hex(output.commitment.as_bytes()),
+        "script": to_hex(&output.script.to_bytes()),
+        "script_challenge": to_hex(output.challenge().as_bytes()),
+        "script_signature": signature_json(signature),
+    })
+}
@@ -0,0 +1,115 @@
+//! The derivation of account keys, spelled out for audits
+//! The app derives every account key at `m/44'/535348'/account'/branch/index` and never shows the steps in between.
+//! [`explain_derivation`] lists the steps it is expected to take for a [`KeyPath`], with the index of every level and
+//! the exact hash domain of every hash, so that an auditor can reproduce the key from the seed with independent tools
+//! and compare it with the one the device returns.
+
+use std::{fmt, str::FromStr};
+
+use crate::{
+    device::{DerivationVersion, KeyBranch},
+    domains::{transaction_hash_tag, DERIVED_KEY_LABEL},
+    export::key_path,
+};
+
+/// The BIP44 purpose of every account path
+pub const BIP44_PURPOSE: u32 = 44;
+/// The SLIP-44 coin type of Tari
+pub const TARI_COIN_TYPE: u32 = 535348;
+/// Added to the index of a hardened level
+pub const HARDENED: u32 = 0x8000_0000;
+
+/// The path of an account key, `m/44'/535348'/account'/branch/index`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyPath {
+    pub account: u32,
+    pub branch: KeyBranch,
+    pub index: u32,
+}
+
+impl FromStr for KeyPath {
+    type Err = String;
+
+    /// Accepts `'` or `h` for hardened levels, e.g. `m/44'/535348'/0'/1/7` or `m/44h/535348h/0h/1/7`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid key path '{}', expected m/44'/535348'/account'/branch/index", s);
+        let levels = s
+            .strip_prefix("m/")
+            .ok_or_else(invalid)?
+            .split('/')
+            .map(parse_level)
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(invalid)?;
+        match levels.as_slice() {
+            [(BIP44_PURPOSE, true), (TARI_COIN_TYPE, true), (account, true), (branch, false), (index, false)] => {
+                let branch = u8::try_from(*branch)
+                    .ok()
+                    .and_then(|branch| KeyBranch::try_from(branch).ok())
+                    .ok_or_else(|| format!("{} is not a key manager branch", branch))?;
+                Ok(Self {
+                    account: *account,
+                    branch,
+                    index: *index,
+                })
+            },
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for KeyPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", key_path(self.account, self.branch, self.index))
+    }
+}
+
+/// A level of a path, its index and whether it is hardened, if the index is below the hardened range
+fn parse_level(level: &str) -> Option<(u32, bool)> {
+    let (index, hardened) = match level.strip_suffix('\'').or_else(|| level.strip_suffix('h')) {
+        Some(index) => (index, true),
+        None => (level, false),
+    };
+    index
+        .parse::<u32>()
+        .ok()
+        .filter(|index| *index < HARDENED)
+        .map(|index| (index, hardened))
+}
+
+/// The steps the app takes to derive the key at `path` under `version`, one line each
+pub fn explain_derivation(path: &KeyPath, version: DerivationVersion) -> Vec<String> {
+    let hardened =
+        |name: &str, index: u32| format!("{}: index {}' = 0x{:08x}, hardened", name, index, index | HARDENED);
+    let mut steps = vec![
+        "seed: the BIP39 seed of the device, never leaves it".to_string(),
+        hardened("purpose (BIP44)", BIP44_PURPOSE),
+        hardened("coin type (Tari)", TARI_COIN_TYPE),
+        hardened("account", path.account),
+        format!(
+            "branch ({}): index {} = 0x{:08x}, not hardened",
+            path.branch,
+            path.branch.as_byte(),
+            path.branch.as_byte()
+        ),
+        format!("key index: index {} = 0x{:08x}, not hardened", path.index, path.index),
+        format!(
+            "node key: the 32 byte BIP32 private key at {} on the Ed25519 curve",
+            path
+        ),
+    ];
+    match version {
+        DerivationVersion::Legacy => {
+            steps.push("secret key (legacy): the node key read as a little-endian Ristretto scalar".to_string())
+        },
+        DerivationVersion::Wide => {
+            let tag = transaction_hash_tag(DERIVED_KEY_LABEL);
+            steps.push(format!(
+                "wide bytes: Blake256 under '{}' of node key || 0x00, then of node key || 0x01",
+                tag
+            ));
+            steps.push("secret key (wide): the 64 wide bytes as a little-endian integer, reduced mod l".to_string());
+        },
+    }
+    steps.push("public key: the secret key times the Ristretto base point".to_string());
+    steps
+}
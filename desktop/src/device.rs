@@ -0,0 +1,833 @@
+//! A thin wrapper around a [`LedgerTransport`] that speaks the Tari Ledger app protocol
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+        MutexGuard,
+        OnceLock,
+    },
+    thread,
+    time::Duration,
+};
+
+use ledger_transport::{APDUAnswer, APDUCommand};
+#[cfg(feature = "hid")]
+use ledger_transport_hid::{hidapi::HidApi, LedgerHIDError, TransportNativeHID};
+use rand::{rngs::OsRng, RngCore};
+use tari_crypto::{
+    keys::{PublicKey, SecretKey},
+    ristretto::{RistrettoPublicKey, RistrettoSecretKey},
+    tari_utilities::ByteArray,
+};
+use tari_ledger_protocol::{
+    crc32,
+    parse_response,
+    CAPABILITIES_RESPONSE_LENGTH,
+    CHUNK_SEQUENCE_LENGTH,
+    CLA,
+    CLIENT_VERSION_RESPONSE_LENGTH,
+    MAX_CHUNK_LENGTH,
+    OPEN_SESSION_RESPONSE_LENGTH,
+    P1_CHUNK_ADD,
+    P1_CHUNK_INIT,
+    P1_CHUNK_LAST,
+    P1_COUNTER_OUTPUTS,
+    P1_COUNTER_SIGNATURES,
+    SESSION_MAC_LENGTH,
+    SIGNING_COUNTER_RESPONSE_LENGTH,
+    SW_APPROVAL_REFUSED,
+    SW_CLIENT_NOT_PERMITTED,
+    SW_CLIENT_QUOTA_EXCEEDED,
+    SW_CLIENT_VERSION_REJECTED,
+    SW_DEVICE_LOCKED,
+    SW_DEVICE_LOCKED_LEGACY,
+    SW_INS_NOT_SUPPORTED,
+    SW_NONCE_NOT_ISSUED,
+    SW_OK,
+    SW_PAIRING_FAILED,
+    SW_SECURITY_STATUS_NOT_SATISFIED,
+    SW_SESSION_MAC_FAILED,
+    SW_SETTING_DISABLED,
+    SW_TRANSACTION_NOT_APPROVED,
+    SW_UPLOAD_CORRUPTED,
+    SW_USER_REJECTED,
+    UPLOAD_CRC_LENGTH,
+};
+pub use tari_ledger_protocol::{Capabilities, DerivationVersion, Instruction, KeyBranch, SemanticVersion, Strictness};
+
+#[cfg(all(feature = "hidraw-direct", target_os = "linux"))]
+use crate::hidraw::{self, TransportHidraw};
+#[cfg(any(feature = "hid", all(feature = "hidraw-direct", target_os = "linux")))]
+use crate::transport::HidFilter;
+use crate::{
+    channel::SecureChannel,
+    errors::DeviceError,
+    limits::{max_data_length, max_upload_length, DeviceModel},
+    rng::{self, HostRng},
+    transport::LedgerTransport,
+};
+
+/// The oldest app version this client talks to. Earlier apps sign every script challenge with the same nonce, so any
+/// two of their signatures reveal the key, and [`LedgerDevice::check_nonce`] would refuse the second output of every
+/// transaction.
+pub const MIN_APP_VERSION: SemanticVersion = SemanticVersion::new(0, 0, 2);
+/// The instructions [`LedgerDevice::send_pipelined`] pipelines, those that neither prompt the user nor change the
+/// state of the app
+const PIPELINED_INSTRUCTIONS: [Instruction; 2] = [Instruction::GetPublicKeys, Instruction::BatchCommitment];
+
+/// The version of this crate
+pub fn client_version() -> SemanticVersion {
+    env!("CARGO_PKG_VERSION")
+        .parse()
+        .expect("CARGO_PKG_VERSION is a valid semantic version")
+}
+
+/// The result of a successful `ClientVersion` handshake
+#[derive(Clone, Copy, Debug)]
+pub struct HandshakeInfo {
+    pub app_version: SemanticVersion,
+    pub min_client_version: SemanticVersion,
+}
+
+/// What a [`LedgerDevice::ping`] found the device doing
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceState {
+    /// The Tari app is open and answering
+    Ready,
+    /// The device went to sleep or was locked, and has to be unlocked with its PIN
+    Locked,
+    /// The device answers, but not the Tari app: it was closed or another app was opened. An app that is opened again
+    /// has lost its session.
+    AppClosed,
+    /// Nothing answers, e.g. the device was unplugged
+    Disconnected,
+}
+
+impl DeviceState {
+    /// What a failed exchange says about the device
+    pub fn after(error: &DeviceError) -> Self {
+        match error {
+            DeviceError::DeviceLocked => DeviceState::Locked,
+            DeviceError::Status(_) |
+            DeviceError::Protocol(_) |
+            DeviceError::InvalidResponse(_) |
+            DeviceError::AuthenticationFailed |
+            DeviceError::SessionMacRejected |
+            DeviceError::SessionClosed => DeviceState::AppClosed,
+            _ => DeviceState::Disconnected,
+        }
+    }
+}
+
+impl fmt::Display for DeviceState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeviceState::Ready => write!(f, "ready"),
+            DeviceState::Locked => write!(f, "locked"),
+            DeviceState::AppClosed => write!(f, "the Tari app is not open"),
+            DeviceState::Disconnected => write!(f, "disconnected"),
+        }
+    }
+}
+
+pub struct LedgerDevice {
+    transport: Box<dyn LedgerTransport>,
+    capabilities: OnceLock<Capabilities>,
+    chunk_size: usize,
+    device_id: Option<String>,
+    model: Option<DeviceModel>,
+    channel: Mutex<Option<SecureChannel>>,
+    /// Set when the authenticated session fails, and cleared only once a new one is open, so that nothing goes out
+    /// in the clear in the meantime
+    session_failed: AtomicBool,
+    strictness: Strictness,
+    /// The challenge each public nonce returned by the device was used for
+    nonces: Mutex<HashMap<[u8; 32], [u8; 32]>>,
+    /// Where the host draws its ephemeral keys and challenges from
+    rng: Mutex<Box<dyn HostRng>>,
+}
+
+impl LedgerDevice {
+    /// Connect to the first Ledger device found over HID
+    #[cfg(feature = "hid")]
+    pub fn open(api: &HidApi) -> Result<Self, DeviceError> {
+        Self::open_filtered(api, &HidFilter::default())
+    }
+
+    /// Connect to the first HID interface `filter` matches
+    #[cfg(feature = "hid")]
+    pub fn open_filtered(api: &HidApi, filter: &HidFilter) -> Result<Self, DeviceError> {
+        let info = api
+            .device_list()
+            .find(|info| filter.matches(info.vendor_id(), info.product_id(), info.usage_page()))
+            .ok_or(LedgerHIDError::DeviceNotFound)?;
+        let device = Self::from_transport(TransportNativeHID::open_device(api, info)?);
+        let device = match DeviceModel::from_product_id(info.product_id()) {
+            Some(model) => device.with_model(model),
+            None => device,
+        };
+        Ok(match info.serial_number() {
+            Some(serial) if !serial.is_empty() => device.with_device_id(serial.to_string()),
+            _ => device,
+        })
+    }
+
+    /// Connect to the first Ledger device found through `/dev/hidraw*`, without hidapi
+    #[cfg(all(feature = "hidraw-direct", target_os = "linux"))]
+    pub fn open_hidraw() -> Result<Self, DeviceError> {
+        Self::open_hidraw_filtered(&HidFilter::default())
+    }
+
+    /// Connect to the first hidraw node `filter` matches
+    #[cfg(all(feature = "hidraw-direct", target_os = "linux"))]
+    pub fn open_hidraw_filtered(filter: &HidFilter) -> Result<Self, DeviceError> {
+        let info = hidraw::list_devices(filter)?.into_iter().next().ok_or_else(|| {
+            DeviceError::Io(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "no Ledger device found among the hidraw nodes",
+            ))
+        })?;
+        let device = Self::from_transport(TransportHidraw::open(&info)?);
+        let device = match DeviceModel::from_product_id(info.product_id) {
+            Some(model) => device.with_model(model),
+            None => device,
+        };
+        Ok(match info.serial {
+            Some(serial) => device.with_device_id(serial),
+            None => device,
+        })
+    }
+
+    pub fn from_transport<T: LedgerTransport + 'static>(transport: T) -> Self {
+        let chunk_size = transport.max_chunk_size().clamp(1, MAX_CHUNK_LENGTH);
+        Self {
+            transport: Box::new(transport),
+            capabilities: OnceLock::new(),
+            chunk_size,
+            device_id: None,
+            model: None,
+            channel: Mutex::new(None),
+            session_failed: AtomicBool::new(false),
+            strictness: Strictness::default(),
+            nonces: Mutex::new(HashMap::new()),
+            rng: Mutex::new(Box::new(OsRng)),
+        }
+    }
+
+    /// Identify the physical device, e.g. by its USB serial number
+    pub fn with_device_id(mut self, device_id: String) -> Self {
+        self.device_id = Some(device_id);
+        self
+    }
+
+    /// Apply the limits of `model` rather than the smallest of any model, see [`limits`](crate::limits)
+    pub fn with_model(mut self, model: DeviceModel) -> Self {
+        self.model = Some(model);
+        self
+    }
+
+    /// Use chunks of at most `chunk_size` bytes instead of the size the transport asks for, e.g. for a BLE link whose
+    /// MTU the transport cannot discover. The size is still capped to what the app accepts.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.clamp(1, MAX_CHUNK_LENGTH);
+        self
+    }
+
+    /// Fail on responses with undocumented trailing bytes instead of ignoring them, see [`Strictness`]
+    pub fn with_strictness(mut self, strictness: Strictness) -> Self {
+        self.strictness = strictness;
+        self
+    }
+
+    /// Draw the host's ephemeral keys and challenges from `rng` instead of the operating system
+    pub fn with_rng(mut self, rng: impl HostRng + 'static) -> Self {
+        self.rng = Mutex::new(Box::new(rng));
+        self
+    }
+
+    /// Draw the host's ephemeral keys and challenges from a generator seeded with `seed`, so that every run sends the
+    /// same APDUs. For tests against the emulator only, see [`rng`].
+    pub fn with_seed(self, seed: u64) -> Self {
+        self.with_rng(rng::seeded(seed))
+    }
+
+    /// A fresh random key, e.g. the `host_secret` of [`LedgerDevice::open_session`]
+    pub fn random_secret(&self) -> RistrettoSecretKey {
+        let mut rng = self.rng.lock().unwrap_or_else(|e| e.into_inner());
+        RistrettoSecretKey::random(&mut &mut **rng)
+    }
+
+    /// Fill `bytes` with random bytes
+    pub fn fill_random(&self, bytes: &mut [u8]) {
+        self.rng.lock().unwrap_or_else(|e| e.into_inner()).fill_bytes(bytes);
+    }
+
+    pub fn transport(&self) -> &dyn LedgerTransport {
+        self.transport.as_ref()
+    }
+
+    pub fn device_id(&self) -> Option<&str> {
+        self.device_id.as_deref()
+    }
+
+    pub fn model(&self) -> Option<DeviceModel> {
+        self.model
+    }
+
+    /// The longest data of a single `instruction` APDU, which an authenticated session shortens by the MAC
+    pub fn max_data_length(&self, instruction: Instruction) -> usize {
+        if self.is_authenticated() {
+            max_data_length(instruction).min(MAX_CHUNK_LENGTH - SESSION_MAC_LENGTH)
+        } else {
+            max_data_length(instruction)
+        }
+    }
+
+    /// The payload size long uploads are sliced into. An authenticated session needs room for the MAC.
+    pub fn chunk_size(&self) -> usize {
+        if self.is_authenticated() {
+            self.chunk_size.min(MAX_CHUNK_LENGTH - SESSION_MAC_LENGTH)
+        } else {
+            self.chunk_size
+        }
+    }
+
+    /// Open an authenticated session, after which every command and response carries a MAC. `host_secret` has to be
+    /// a fresh random key. If `expected_key` is given the device has to prove it holds that app key, e.g. the one
+    /// returned by an earlier session. Returns the app key the device proved.
+    pub fn open_session(
+        &self,
+        host_secret: &RistrettoSecretKey,
+        expected_key: Option<&RistrettoPublicKey>,
+    ) -> Result<RistrettoPublicKey, DeviceError> {
+        self.require(Capabilities::AUTHENTICATED_SESSION)?;
+        // The app answers a new session outside of the old one
+        *self.lock_channel() = None;
+        let host_key = RistrettoPublicKey::from_secret_key(host_secret);
+        let response = self.send(Instruction::OpenSession, 0x00, 0x00, host_key.as_bytes().to_vec())?;
+        let channel = self
+            .response_payload(&response, OPEN_SESSION_RESPONSE_LENGTH)
+            .and_then(|payload| SecureChannel::from_response(host_secret, payload, expected_key))
+            // The app has opened a session this host cannot follow, and refuses anything sent in the clear
+            .map_err(|e| {
+                self.session_failed.store(true, Ordering::SeqCst);
+                e
+            })?;
+        let app_public_key = channel.app_public_key().clone();
+        *self.lock_channel() = Some(channel);
+        self.session_failed.store(false, Ordering::SeqCst);
+        Ok(app_public_key)
+    }
+
+    /// Whether commands are sent over an authenticated session
+    pub fn is_authenticated(&self) -> bool {
+        self.lock_channel().is_some()
+    }
+
+    /// The open authenticated session, if there is one
+    pub(crate) fn channel(&self) -> Option<SecureChannel> {
+        self.lock_channel().clone()
+    }
+
+    /// Remember that the device signed `challenge` with `public_nonce`, failing if it used the nonce for a different
+    /// challenge before. Two signatures under the same nonce reveal the private key.
+    pub fn check_nonce(&self, public_nonce: &RistrettoPublicKey, challenge: &[u8; 32]) -> Result<(), DeviceError> {
+        let mut nonce = [0u8; 32];
+        nonce.copy_from_slice(public_nonce.as_bytes());
+        let mut nonces = self.nonces.lock().unwrap_or_else(|e| e.into_inner());
+        match nonces.insert(nonce, *challenge) {
+            Some(earlier) if earlier != *challenge => Err(DeviceError::NonceReuse),
+            _ => Ok(()),
+        }
+    }
+
+    /// Send a single APDU to the app and return the response data if the device reports success
+    pub fn send(&self, instruction: Instruction, p1: u8, p2: u8, data: Vec<u8>) -> Result<Vec<u8>, DeviceError> {
+        let max = self.max_data_length(instruction);
+        if data.len() > max {
+            return Err(DeviceError::DataTooLong {
+                instruction,
+                length: data.len(),
+                max,
+            });
+        }
+        self.send_unchecked(instruction, p1, p2, data)
+    }
+
+    /// How many commands to hand [`LedgerDevice::send_pipelined`] at once, one unless the transport pipelines. An
+    /// authenticated session never pipelines, the MAC of every command counts the responses before it.
+    pub fn pipeline_depth(&self) -> usize {
+        if self.is_authenticated() {
+            1
+        } else {
+            self.transport.pipeline_depth().max(1)
+        }
+    }
+
+    /// Send `commands` of `instruction`, each `(p1, p2, data)`, and return their responses in order. Over a transport
+    /// that pipelines, such as Speculos, every command is sent before the first response is read. Commands that change
+    /// the state of the app, and every command of an authenticated session, are sent one at a time instead. Fails with
+    /// the first command that fails.
+    pub fn send_pipelined(
+        &self,
+        instruction: Instruction,
+        commands: Vec<(u8, u8, Vec<u8>)>,
+    ) -> Result<Vec<Vec<u8>>, DeviceError> {
+        if !PIPELINED_INSTRUCTIONS.contains(&instruction) || self.is_authenticated() {
+            return commands
+                .into_iter()
+                .map(|(p1, p2, data)| self.send(instruction, p1, p2, data))
+                .collect();
+        }
+        let max = self.max_data_length(instruction);
+        let commands = commands
+            .into_iter()
+            .map(|(p1, p2, data)| {
+                if data.len() > max {
+                    return Err(DeviceError::DataTooLong {
+                        instruction,
+                        length: data.len(),
+                        max,
+                    });
+                }
+                Ok(APDUCommand {
+                    cla: CLA,
+                    ins: instruction.as_byte(),
+                    p1,
+                    p2,
+                    data,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        self.transport
+            .exchange_pipelined(&commands)?
+            .into_iter()
+            .map(|answer| match answer.retcode() {
+                SW_OK => Ok(answer.data().to_vec()),
+                sw => Err(status_error(sw)),
+            })
+            .collect()
+    }
+
+    /// [`LedgerDevice::send`] without the length check, for the chunks of an upload, which the chunk size bounds
+    fn send_unchecked(&self, instruction: Instruction, p1: u8, p2: u8, data: Vec<u8>) -> Result<Vec<u8>, DeviceError> {
+        let command = APDUCommand {
+            cla: CLA,
+            ins: instruction.as_byte(),
+            p1,
+            p2,
+            data,
+        };
+        let answer = self.exchange(command)?;
+        match answer.retcode() {
+            SW_OK => Ok(answer.data().to_vec()),
+            sw => Err(status_error(sw)),
+        }
+    }
+
+    /// Check the length and format byte of `response`, returning the payload documented for an `expected_length` byte
+    /// response. Any bytes after it are ignored unless the device parses strictly.
+    pub fn response_payload<'r>(&self, response: &'r [u8], expected_length: usize) -> Result<&'r [u8], DeviceError> {
+        Ok(parse_response(response, expected_length, self.strictness)?)
+    }
+
+    /// Send `data` in an init APDU, followed by `payload` sliced into [`LedgerDevice::chunk_size`] chunks, and return
+    /// the response to the last chunk
+    pub fn send_chunks(
+        &self,
+        instruction: Instruction,
+        p2: u8,
+        data: Vec<u8>,
+        payload: &[u8],
+    ) -> Result<Vec<u8>, DeviceError> {
+        self.send_chunks_with_progress(instruction, p2, data, payload, |_, _| {})
+    }
+
+    /// [`LedgerDevice::send_chunks`], calling `progress` after every chunk with the number of payload bytes sent so
+    /// far and the total. The chunks are framed if the app supports it, so that a lost or reordered chunk fails the
+    /// upload rather than changing what the app works on.
+    pub fn send_chunks_with_progress<P: FnMut(usize, usize)>(
+        &self,
+        instruction: Instruction,
+        p2: u8,
+        data: Vec<u8>,
+        payload: &[u8],
+        progress: P,
+    ) -> Result<Vec<u8>, DeviceError> {
+        // Whatever cannot tell whether it frames uploads, e.g. the dashboard, gets them unframed
+        let framed = self
+            .capabilities()
+            .map(|capabilities| capabilities.contains(Capabilities::FRAMED_UPLOADS))
+            .unwrap_or(false);
+        if framed {
+            self.send_framed_chunks(instruction, p2, data, payload, progress)
+        } else {
+            self.send_unframed_chunks(instruction, p2, data, payload, progress)
+        }
+    }
+
+    fn send_unframed_chunks<P: FnMut(usize, usize)>(
+        &self,
+        instruction: Instruction,
+        p2: u8,
+        data: Vec<u8>,
+        payload: &[u8],
+        mut progress: P,
+    ) -> Result<Vec<u8>, DeviceError> {
+        let chunk_size = self.chunk_size();
+        let mut response = self.send(instruction, P1_CHUNK_INIT, p2, data)?;
+        let last = payload.len().saturating_sub(1) / chunk_size;
+        let mut sent = 0;
+        for (index, chunk) in payload.chunks(chunk_size).enumerate() {
+            let p1 = if index == last { P1_CHUNK_LAST } else { P1_CHUNK_ADD };
+            response = self.send_unchecked(instruction, p1, p2, chunk.to_vec())?;
+            sent += chunk.len();
+            progress(sent, payload.len());
+        }
+        Ok(response)
+    }
+
+    /// Send `payload` followed by its CRC, every chunk prefixed with its sequence number, and check that the app
+    /// echoes the CRC back
+    fn send_framed_chunks<P: FnMut(usize, usize)>(
+        &self,
+        instruction: Instruction,
+        p2: u8,
+        data: Vec<u8>,
+        payload: &[u8],
+        mut progress: P,
+    ) -> Result<Vec<u8>, DeviceError> {
+        let max = max_upload_length(self.model);
+        if payload.len() > max {
+            return Err(DeviceError::UploadTooLong {
+                length: payload.len(),
+                max,
+            });
+        }
+        let crc = crc32(payload).to_le_bytes();
+        let mut framed = payload.to_vec();
+        framed.extend_from_slice(&crc);
+        let chunk_size = self.chunk_size().saturating_sub(CHUNK_SEQUENCE_LENGTH).max(1);
+
+        let mut response = self.send(instruction, P1_CHUNK_INIT, p2, data)?;
+        let last = (framed.len() - 1) / chunk_size;
+        let mut sent = 0;
+        for (sequence, chunk) in (0u16..).zip(framed.chunks(chunk_size)) {
+            let p1 = if usize::from(sequence) == last {
+                P1_CHUNK_LAST
+            } else {
+                P1_CHUNK_ADD
+            };
+            let mut apdu = sequence.to_le_bytes().to_vec();
+            apdu.extend_from_slice(chunk);
+            response = self.send_unchecked(instruction, p1, p2, apdu)?;
+            sent = (sent + chunk.len()).min(payload.len());
+            progress(sent, payload.len());
+        }
+        match response.len().checked_sub(UPLOAD_CRC_LENGTH) {
+            Some(end) if response[end..] == crc => {
+                response.truncate(end);
+                Ok(response)
+            },
+            _ => Err(DeviceError::UploadCorrupted),
+        }
+    }
+
+    /// The optional features of the app. They are queried once and cached; apps that predate `GetCapabilities`
+    /// report none.
+    pub fn capabilities(&self) -> Result<Capabilities, DeviceError> {
+        if let Some(capabilities) = self.capabilities.get() {
+            return Ok(*capabilities);
+        }
+        let capabilities = match self.send(Instruction::GetCapabilities, 0x00, 0x00, vec![]) {
+            Ok(response) => {
+                Capabilities::from_le_bytes(self.response_payload(&response, CAPABILITIES_RESPONSE_LENGTH)?)?
+            },
+            Err(DeviceError::Status(SW_INS_NOT_SUPPORTED)) => Capabilities::empty(),
+            Err(e) => return Err(e),
+        };
+        Ok(*self.capabilities.get_or_init(|| capabilities))
+    }
+
+    /// Use capabilities read in an earlier session instead of querying the app
+    pub fn cache_capabilities(&self, capabilities: Capabilities) {
+        let _ = self.capabilities.set(capabilities);
+    }
+
+    /// Fail with [`DeviceError::Unsupported`] unless the app supports `capability`
+    pub fn require(&self, capability: Capabilities) -> Result<(), DeviceError> {
+        if self.capabilities()?.contains(capability) {
+            Ok(())
+        } else {
+            Err(DeviceError::Unsupported(capability.name()))
+        }
+    }
+
+    /// The P2 of an instruction deriving keys under `version`. Every app derives legacy keys when P2 is 0, any other
+    /// version needs [`Capabilities::DERIVATION_VERSIONS`].
+    pub fn derivation_p2(&self, version: DerivationVersion) -> Result<u8, DeviceError> {
+        if version != DerivationVersion::Legacy {
+            self.require(Capabilities::DERIVATION_VERSIONS)?;
+        }
+        Ok(version.as_byte())
+    }
+
+    /// The number of signatures the app has produced so far. It never goes down, so comparing it with the number of
+    /// signatures this host asked for reveals signing done behind its back.
+    pub fn signing_counter(&self) -> Result<u64, DeviceError> {
+        self.require(Capabilities::SIGNING_COUNTER)?;
+        self.counter(P1_COUNTER_SIGNATURES)
+    }
+
+    /// The number of outputs the app has signed in approved transactions so far. Like the signing counter it never
+    /// goes down, which is what `SigningHistory::reconcile_outputs` relies on.
+    pub fn output_counter(&self) -> Result<u64, DeviceError> {
+        self.require(Capabilities::OUTPUT_COUNTER)?;
+        self.counter(P1_COUNTER_OUTPUTS)
+    }
+
+    fn counter(&self, p1: u8) -> Result<u64, DeviceError> {
+        let response = self.send(Instruction::GetSigningCounter, p1, 0x00, vec![])?;
+        let payload = self.response_payload(&response, SIGNING_COUNTER_RESPONSE_LENGTH)?;
+        let mut counter = [0u8; 8];
+        counter.copy_from_slice(payload);
+        Ok(u64::from_le_bytes(counter))
+    }
+
+    /// Check that the device is still there with the app open. `GetVersion` asks nothing of the app but to name
+    /// itself, so this is cheap enough to call every few seconds to notice sleep, app exit or a disconnect promptly.
+    pub fn ping(&self) -> DeviceState {
+        match self.send(Instruction::GetVersion, 0x00, 0x00, vec![]) {
+            Ok(_) => DeviceState::Ready,
+            Err(e) => DeviceState::after(&e),
+        }
+    }
+
+    /// Exchange protocol versions with the app. This should be the first command of every session; both the app and
+    /// this client refuse to continue if the other side is too old.
+    pub fn handshake(&self) -> Result<HandshakeInfo, DeviceError> {
+        let client = client_version();
+        let command = APDUCommand {
+            cla: CLA,
+            ins: Instruction::ClientVersion.as_byte(),
+            p1: 0x00,
+            p2: 0x00,
+            data: client.to_le_bytes().to_vec(),
+        };
+        let answer = self.exchange(command)?;
+        if answer.retcode() != SW_OK && answer.retcode() != SW_CLIENT_VERSION_REJECTED {
+            return Err(status_error(answer.retcode()));
+        }
+
+        let payload = self.response_payload(answer.data(), CLIENT_VERSION_RESPONSE_LENGTH)?;
+        let (app_version, min_client_version) = payload.split_at(SemanticVersion::ENCODED_LENGTH);
+        let app_version = SemanticVersion::from_le_bytes(app_version)?;
+        let min_client_version = SemanticVersion::from_le_bytes(min_client_version)?;
+
+        if answer.retcode() == SW_CLIENT_VERSION_REJECTED || client < min_client_version {
+            return Err(DeviceError::ClientVersionRejected {
+                client,
+                min_client: min_client_version,
+            });
+        }
+        if app_version < MIN_APP_VERSION {
+            return Err(DeviceError::AppVersionUnsupported {
+                app: app_version,
+                min_app: MIN_APP_VERSION,
+            });
+        }
+
+        Ok(HandshakeInfo {
+            app_version,
+            min_client_version,
+        })
+    }
+
+    /// Exchange `command` with the transport, sealing it and checking the answer if a session is open. Any failure
+    /// of the session closes it, as the app does on its side, and nothing but a new session is sent until one is
+    /// open.
+    fn exchange(&self, mut command: APDUCommand<Vec<u8>>) -> Result<APDUAnswer<Vec<u8>>, DeviceError> {
+        let mut channel = self.lock_channel();
+        let session = match channel.as_mut() {
+            Some(session) => session,
+            None if self.session_failed.load(Ordering::SeqCst) && command.ins != Instruction::OpenSession as u8 => {
+                return Err(DeviceError::SessionClosed)
+            },
+            None => return self.transport.exchange(&command),
+        };
+        session.seal(&mut command);
+        let answer = self.transport.exchange(&command)?;
+        // The device OS answers for the app while it is locked, and the daemon for the app when it does not let this
+        // client send the command. Neither holds the session key, and the command never reached the app, so the
+        // session carries on and nothing but the status word is taken from the answer.
+        let retcode = answer.retcode();
+        if is_os_status(retcode) || is_daemon_status(retcode) {
+            let status = APDUAnswer::from_answer(retcode.to_be_bytes().to_vec());
+            return Ok(status.expect("a status word alone is an answer"));
+        }
+        // The app has closed the session and answers without a MAC. Anyone could have sent this, but all it can do is
+        // close the session.
+        let answer = match retcode {
+            SW_SESSION_MAC_FAILED => Err(DeviceError::SessionMacRejected),
+            _ => session.open(answer),
+        };
+        if answer.is_err() {
+            *channel = None;
+            self.session_failed.store(true, Ordering::SeqCst);
+        }
+        answer
+    }
+
+    fn lock_channel(&self) -> MutexGuard<'_, Option<SecureChannel>> {
+        self.channel.lock().expect("the session lock is never poisoned")
+    }
+}
+
+/// The status words the device OS answers with in place of the app, without a MAC of the session
+fn is_os_status(sw: u16) -> bool {
+    matches!(
+        sw,
+        SW_DEVICE_LOCKED | SW_DEVICE_LOCKED_LEGACY | SW_SECURITY_STATUS_NOT_SATISFIED
+    )
+}
+
+/// The status words the daemon answers with in place of the app, without a MAC of the session
+fn is_daemon_status(sw: u16) -> bool {
+    matches!(
+        sw,
+        SW_CLIENT_NOT_PERMITTED | SW_CLIENT_QUOTA_EXCEEDED | SW_APPROVAL_REFUSED
+    )
+}
+
+fn status_error(sw: u16) -> DeviceError {
+    match sw {
+        SW_USER_REJECTED => DeviceError::UserRejected,
+        SW_TRANSACTION_NOT_APPROVED => DeviceError::TransactionNotApproved,
+        SW_DEVICE_LOCKED | SW_DEVICE_LOCKED_LEGACY | SW_SECURITY_STATUS_NOT_SATISFIED => DeviceError::DeviceLocked,
+        SW_SETTING_DISABLED => DeviceError::SettingDisabled("a setting"),
+        SW_PAIRING_FAILED => DeviceError::PairingFailed,
+        SW_UPLOAD_CORRUPTED => DeviceError::UploadCorrupted,
+        SW_NONCE_NOT_ISSUED => DeviceError::NonceNotIssued,
+        SW_SESSION_MAC_FAILED => DeviceError::SessionMacRejected,
+        SW_CLIENT_NOT_PERMITTED => DeviceError::ClientNotPermitted,
+        SW_CLIENT_QUOTA_EXCEEDED => DeviceError::ClientQuotaExceeded,
+        SW_APPROVAL_REFUSED => DeviceError::ApprovalRefused,
+        sw => DeviceError::Status(sw),
+    }
+}
+
+/// How long to keep polling a locked device, backing off exponentially between attempts
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    /// Give up with [`DeviceError::DeviceLocked`] once the device has been locked for this long
+    pub timeout: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(5),
+            timeout: Duration::from_secs(120),
+        }
+    }
+}
+
+/// Run `operation`, retrying it for as long as it fails with [`DeviceError::DeviceLocked`] and `policy` allows.
+/// `on_locked` is called before every wait with the time left, e.g. to ask the user to unlock the device. Any other
+/// result, success or failure, is returned as is.
+pub fn retry_while_locked<T, F, L>(policy: &RetryPolicy, mut on_locked: L, mut operation: F) -> Result<T, DeviceError>
+where
+    F: FnMut() -> Result<T, DeviceError>,
+    L: FnMut(Duration),
+{
+    let mut waited = Duration::ZERO;
+    let mut delay = policy.initial_delay;
+    loop {
+        match operation() {
+            Err(DeviceError::DeviceLocked) if waited < policy.timeout => {
+                let wait = delay.min(policy.timeout - waited);
+                on_locked(policy.timeout - waited);
+                thread::sleep(wait);
+                waited += wait;
+                delay = delay.saturating_mul(2).min(policy.max_delay);
+            },
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{atomic::AtomicUsize, Arc};
+
+    use super::*;
+
+    /// Answers every command with success, and counts them
+    struct Answering(Arc<AtomicUsize>);
+
+    impl LedgerTransport for Answering {
+        fn exchange(&self, _command: &APDUCommand<Vec<u8>>) -> Result<APDUAnswer<Vec<u8>>, DeviceError> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(APDUAnswer::from_answer(SW_OK.to_be_bytes().to_vec()).unwrap())
+        }
+    }
+
+    #[test]
+    fn nothing_but_a_new_session_goes_out_after_the_session_failed() {
+        let sent = Arc::new(AtomicUsize::new(0));
+        let device = LedgerDevice::from_transport(Answering(sent.clone()));
+        device.session_failed.store(true, Ordering::SeqCst);
+        assert!(matches!(
+            device.send(Instruction::GetVersion, 0x00, 0x00, vec![]),
+            Err(DeviceError::SessionClosed)
+        ));
+        assert_eq!(sent.load(Ordering::SeqCst), 0);
+        // The app answers a new session in the clear, without a MAC
+        device
+            .send(Instruction::OpenSession, 0x00, 0x00, vec![0u8; 32])
+            .unwrap();
+        assert_eq!(sent.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn only_the_os_and_the_daemon_answer_without_a_mac() {
+        for sw in [
+            SW_DEVICE_LOCKED,
+            SW_DEVICE_LOCKED_LEGACY,
+            SW_SECURITY_STATUS_NOT_SATISFIED,
+        ] {
+            assert!(is_os_status(sw), "{:04x}", sw);
+        }
+        for sw in [SW_CLIENT_NOT_PERMITTED, SW_CLIENT_QUOTA_EXCEEDED, SW_APPROVAL_REFUSED] {
+            assert!(is_daemon_status(sw), "{:04x}", sw);
+        }
+        for sw in [SW_OK, SW_USER_REJECTED, SW_TRANSACTION_NOT_APPROVED, SW_PAIRING_FAILED] {
+            assert!(!is_os_status(sw) && !is_daemon_status(sw), "{:04x}", sw);
+        }
+    }
+
+    #[test]
+    fn locked_devices_and_refused_sessions_are_told_apart() {
+        for sw in [
+            SW_DEVICE_LOCKED,
+            SW_DEVICE_LOCKED_LEGACY,
+            SW_SECURITY_STATUS_NOT_SATISFIED,
+        ] {
+            assert!(matches!(status_error(sw), DeviceError::DeviceLocked), "{:04x}", sw);
+        }
+        assert!(matches!(
+            status_error(SW_SESSION_MAC_FAILED),
+            DeviceError::SessionMacRejected
+        ));
+        assert!(matches!(
+            DeviceState::after(&DeviceError::SessionMacRejected),
+            DeviceState::AppClosed
+        ));
+    }
+}
@@ -0,0 +1,102 @@
+//! Out-of-band confirmation of large transactions
+//! A Nano screen fits a line or two, so a transaction with many outputs cannot be shown output by output. Before the
+//! summary the host sends `Instruction::DisplayHints`: a few short pages to show after the totals, and a digest of the
+//! outputs it is about to have signed. The app shows a fingerprint of the digest for the user to compare with the one
+//! the host prints, and refuses to sign the last output unless the outputs it was sent hash to the same digest.
+
+use tari_ledger_protocol::{
+    digest_fingerprint,
+    display_title,
+    TransactionSummary,
+    DISPLAY_DIGEST_LABEL,
+    MAX_DISPLAY_PAGES,
+    OUTPUT_KIND_CHANGE,
+    OUTPUT_KIND_RECIPIENT,
+};
+
+use crate::{
+    hashing::{DomainSeparatedConsensusHasher, TransactionHashDomain},
+    signer::OutputToSign,
+};
+
+/// Transactions with more outputs than this are sent with display hints when the app supports them
+pub const MAX_OUTPUTS_WITHOUT_HINTS: usize = 2;
+/// How many recipients get a page of their own, the rest are counted on a single page
+const MAX_RECIPIENT_PAGES: usize = MAX_DISPLAY_PAGES as usize - 3;
+
+/// The display hints of one transaction
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisplaySummary {
+    pub digest: [u8; 32],
+    /// Shown in order after the totals, each cut to fit a line
+    pub titles: Vec<String>,
+}
+
+impl DisplaySummary {
+    /// The hints for signing `outputs`, in order, under `summary`
+    pub fn for_transaction(summary: &TransactionSummary, outputs: &[OutputToSign]) -> Self {
+        let mut digest = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(DISPLAY_DIGEST_LABEL)
+            .chain_fixed(&summary.to_le_bytes())
+            .finalize();
+        for output in outputs {
+            let kind = if output.is_change {
+                OUTPUT_KIND_CHANGE
+            } else {
+                OUTPUT_KIND_RECIPIENT
+            };
+            digest = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(DISPLAY_DIGEST_LABEL)
+                .chain(&digest)
+                .chain(&kind)
+                .chain(&output.value)
+                .chain(output.challenge.as_bytes())
+                .finalize();
+        }
+
+        // The features of an output cannot be told from its value, so their pages come before the plain payments
+        let mut titles = vec![format!("{} outputs", outputs.len())];
+        let mut recipients = outputs.iter().filter(|output| !output.is_change).collect::<Vec<_>>();
+        recipients.sort_by_key(|output| output.features_title.is_none());
+        titles.extend(
+            recipients
+                .iter()
+                .take(MAX_RECIPIENT_PAGES)
+                .map(|output| match &output.features_title {
+                    Some(title) => title.clone(),
+                    None => format!("Pay {} uT", output.value),
+                }),
+        );
+        if recipients.len() > MAX_RECIPIENT_PAGES {
+            titles.push(format!("+{} more", recipients.len() - MAX_RECIPIENT_PAGES));
+        }
+        let change = outputs
+            .iter()
+            .filter(|output| output.is_change)
+            .fold(0u64, |total, output| total.saturating_add(output.value));
+        if change > 0 {
+            titles.push(format!("Change {} uT", change));
+        }
+        Self { digest, titles }
+    }
+
+    /// Whether a transaction of `outputs` is too large to confirm from the totals alone, or has features to show
+    pub fn is_needed(outputs: &[OutputToSign]) -> bool {
+        outputs.len() > MAX_OUTPUTS_WITHOUT_HINTS || outputs.iter().any(|output| output.features_title.is_some())
+    }
+
+    /// The fingerprint the app shows, e.g. `abcd ef01 2345`
+    pub fn fingerprint(&self) -> String {
+        String::from_utf8_lossy(&digest_fingerprint(&self.digest)).into_owned()
+    }
+
+    /// The `[digest][page count][titles]` data of the `DisplayHints` request
+    pub fn to_request(&self) -> Vec<u8> {
+        let titles = &self.titles[..self.titles.len().min(usize::from(MAX_DISPLAY_PAGES))];
+        let mut data = self.digest.to_vec();
+        // Cannot truncate, there are no more titles than pages
+        data.push(titles.len() as u8);
+        for title in titles {
+            data.extend_from_slice(&display_title(title));
+        }
+        data
+    }
+}
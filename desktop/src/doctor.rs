@@ -0,0 +1,340 @@
+//! Host environment diagnostics
+//! Most first-run failures are caused by the operating system refusing access to the Ledger HID interface rather than
+//! by the app itself, so `tari-ledger doctor` checks the usual suspects per platform and suggests a fix for each.
+
+use std::fmt;
+
+use ledger_transport_hid::hidapi::{DeviceInfo, HidApi};
+
+use crate::transport::LEDGER_USAGE_PAGE;
+pub use crate::transport::LEDGER_VENDOR_ID;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheckStatus::Pass => write!(f, "PASS"),
+            CheckStatus::Warn => write!(f, "WARN"),
+            CheckStatus::Fail => write!(f, "FAIL"),
+        }
+    }
+}
+
+/// The outcome of a single diagnostic check
+#[derive(Clone, Debug)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+    /// An actionable suggestion, only set when the check did not pass
+    pub fix: Option<String>,
+}
+
+/// One line of the human readable report, with the fix on a second line when there is one
+impl fmt::Display for CheckResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] {}: {}", self.status, self.name, self.detail)?;
+        if let Some(fix) = &self.fix {
+            write!(f, "\n       fix: {}", fix)?;
+        }
+        Ok(())
+    }
+}
+
+impl CheckResult {
+    fn pass(name: &'static str, detail: String) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Pass,
+            detail,
+            fix: None,
+        }
+    }
+
+    fn warn(name: &'static str, detail: String, fix: &str) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Warn,
+            detail,
+            fix: Some(fix.to_string()),
+        }
+    }
+
+    fn fail(name: &'static str, detail: String, fix: &str) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Fail,
+            detail,
+            fix: Some(fix.to_string()),
+        }
+    }
+}
+
+/// Run all diagnostics applicable to the current platform
+pub fn run_diagnostics() -> Vec<CheckResult> {
+    let mut results = Vec::new();
+    results.extend(platform_checks());
+
+    let api = match HidApi::new() {
+        Ok(api) => {
+            results.push(CheckResult::pass("hidapi", "HID subsystem initialised".to_string()));
+            api
+        },
+        Err(e) => {
+            results.push(CheckResult::fail(
+                "hidapi",
+                format!("Could not initialise the HID subsystem: {}", e),
+                "Make sure the hidapi/libusb system libraries are installed",
+            ));
+            return results;
+        },
+    };
+    results.extend(device_checks(&api));
+
+    results
+}
+
+/// Whether no check failed
+pub fn all_passed(results: &[CheckResult]) -> bool {
+    results.iter().all(|r| r.status != CheckStatus::Fail)
+}
+
+fn is_ledger_interface(device: &DeviceInfo) -> bool {
+    device.vendor_id() == LEDGER_VENDOR_ID && device.usage_page() == LEDGER_USAGE_PAGE
+}
+
+fn device_checks(api: &HidApi) -> Vec<CheckResult> {
+    let mut results = Vec::new();
+    let ledger_devices: Vec<&DeviceInfo> = api
+        .device_list()
+        .filter(|d| d.vendor_id() == LEDGER_VENDOR_ID)
+        .collect();
+    if ledger_devices.is_empty() {
+        results.push(CheckResult::fail(
+            "device",
+            "No Ledger device was found".to_string(),
+            "Connect the device with a data capable USB cable, unlock it and open the Tari app",
+        ));
+        return results;
+    }
+
+    let interfaces: Vec<&DeviceInfo> = ledger_devices
+        .iter()
+        .copied()
+        .filter(|d| is_ledger_interface(d))
+        .collect();
+    if interfaces.is_empty() {
+        results.push(CheckResult::fail(
+            "device",
+            format!(
+                "Found {} Ledger USB interface(s), but none expose the APDU usage page",
+                ledger_devices.len()
+            ),
+            missing_interface_fix(),
+        ));
+        return results;
+    }
+    results.push(CheckResult::pass(
+        "device",
+        format!(
+            "Found Ledger device '{}' (product id {:#06x})",
+            interfaces[0].product_string().unwrap_or("unknown"),
+            interfaces[0].product_id()
+        ),
+    ));
+
+    match interfaces[0].open_device(api) {
+        Ok(_) => results.push(CheckResult::pass(
+            "access",
+            "The APDU interface can be opened".to_string(),
+        )),
+        Err(e) => results.push(CheckResult::fail(
+            "access",
+            format!("The APDU interface could not be opened: {}", e),
+            access_denied_fix(),
+        )),
+    }
+
+    results
+}
+
+#[cfg(target_os = "linux")]
+fn missing_interface_fix() -> &'static str {
+    "Open the Tari app on the device; the dashboard does not expose the APDU interface"
+}
+
+#[cfg(target_os = "windows")]
+fn missing_interface_fix() -> &'static str {
+    "Open the Tari app on the device. If it is open, remove the device in Device Manager and reconnect it so Windows \
+     reinstalls the HID-compliant device driver"
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn missing_interface_fix() -> &'static str {
+    "Open the Tari app on the device; the dashboard does not expose the APDU interface"
+}
+
+#[cfg(target_os = "linux")]
+fn access_denied_fix() -> &'static str {
+    "Install the Ledger udev rules (https://github.com/LedgerHQ/udev-rules), make sure your user is in the 'plugdev' \
+     group and reconnect the device"
+}
+
+#[cfg(target_os = "windows")]
+fn access_denied_fix() -> &'static str {
+    "Close Ledger Live or any other wallet that may hold the device open and try again"
+}
+
+#[cfg(target_os = "macos")]
+fn access_denied_fix() -> &'static str {
+    "Allow your terminal in System Settings > Privacy & Security > Input Monitoring, then restart the terminal"
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows", target_os = "macos")))]
+fn access_denied_fix() -> &'static str {
+    "Make sure the current user has read/write access to the HID device"
+}
+
+//---------------------------------------------      Linux     -------------------------------------------------------//
+
+#[cfg(target_os = "linux")]
+fn platform_checks() -> Vec<CheckResult> {
+    use std::{fs, path::Path};
+
+    const UDEV_RULE_DIRS: [&str; 3] = ["/etc/udev/rules.d", "/lib/udev/rules.d", "/usr/lib/udev/rules.d"];
+
+    let mut results = Vec::new();
+    let vendor_id = format!("{:04x}", LEDGER_VENDOR_ID);
+    let rule_file = UDEV_RULE_DIRS
+        .iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flat_map(|entries| entries.filter_map(|e| e.ok()))
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().map(|ext| ext == "rules").unwrap_or(false))
+        .find(|path| {
+            fs::read_to_string(path)
+                .map(|rules| rules.to_lowercase().contains(&vendor_id))
+                .unwrap_or(false)
+        });
+    match rule_file {
+        Some(path) => results.push(CheckResult::pass(
+            "udev",
+            format!("Ledger udev rules found in {}", path.display()),
+        )),
+        None => results.push(CheckResult::fail(
+            "udev",
+            "No udev rules for the Ledger vendor ID were found".to_string(),
+            "Install the Ledger udev rules from https://github.com/LedgerHQ/udev-rules and run 'sudo udevadm control \
+             --reload-rules && sudo udevadm trigger'",
+        )),
+    }
+
+    let in_plugdev = fs::read_to_string("/etc/group")
+        .map(|groups| {
+            let user = std::env::var("USER").unwrap_or_default();
+            groups.lines().any(|line| {
+                let mut fields = line.split(':');
+                fields.next() == Some("plugdev") &&
+                    fields
+                        .nth(2)
+                        .map(|members| members.split(',').any(|m| m == user))
+                        .unwrap_or(false)
+            })
+        })
+        .unwrap_or(false);
+    if in_plugdev {
+        results.push(CheckResult::pass(
+            "plugdev",
+            "Current user is in the 'plugdev' group".to_string(),
+        ));
+    } else {
+        results.push(CheckResult::warn(
+            "plugdev",
+            "Current user is not in the 'plugdev' group".to_string(),
+            "Run 'sudo usermod -aG plugdev $USER' and log in again (only needed if your udev rules use the group)",
+        ));
+    }
+
+    if !Path::new("/dev").join("hidraw0").exists() {
+        results.push(CheckResult::warn(
+            "hidraw",
+            "No /dev/hidraw* nodes exist".to_string(),
+            "Make sure the 'hidraw' kernel module is loaded and the device is connected",
+        ));
+    }
+
+    results
+}
+
+//---------------------------------------------      Windows     -----------------------------------------------------//
+
+#[cfg(target_os = "windows")]
+fn platform_checks() -> Vec<CheckResult> {
+    let api = match HidApi::new() {
+        Ok(api) => api,
+        Err(_) => return Vec::new(),
+    };
+    // Windows splits a composite device into one HID collection per interface; if the vendor ID shows up without the
+    // APDU usage page the driver for that collection did not bind
+    let collections = api.device_list().filter(|d| d.vendor_id() == LEDGER_VENDOR_ID).count();
+    let apdu_collections = api.device_list().filter(|d| is_ledger_interface(d)).count();
+    if collections > 0 && apdu_collections == 0 {
+        vec![CheckResult::fail(
+            "driver",
+            format!(
+                "{} Ledger HID collection(s) are present but the APDU collection is missing",
+                collections
+            ),
+            "Remove the device in Device Manager (View > Devices by connection), reconnect it and open the Tari app",
+        )]
+    } else {
+        vec![CheckResult::pass(
+            "driver",
+            "No HID driver problems detected".to_string(),
+        )]
+    }
+}
+
+//---------------------------------------------      macOS     -------------------------------------------------------//
+
+#[cfg(target_os = "macos")]
+fn platform_checks() -> Vec<CheckResult> {
+    // See IOKit/hid/IOHIDLib.h
+    const IOHID_REQUEST_TYPE_LISTEN_EVENT: u32 = 1;
+    const IOHID_ACCESS_TYPE_GRANTED: u32 = 0;
+    const IOHID_ACCESS_TYPE_DENIED: u32 = 1;
+
+    #[link(name = "IOKit", kind = "framework")]
+    extern "C" {
+        fn IOHIDCheckAccess(request_type: u32) -> u32;
+    }
+
+    match unsafe { IOHIDCheckAccess(IOHID_REQUEST_TYPE_LISTEN_EVENT) } {
+        IOHID_ACCESS_TYPE_GRANTED => vec![CheckResult::pass(
+            "input-monitoring",
+            "Input Monitoring permission granted".to_string(),
+        )],
+        IOHID_ACCESS_TYPE_DENIED => vec![CheckResult::fail(
+            "input-monitoring",
+            "Input Monitoring permission was denied for this terminal".to_string(),
+            access_denied_fix(),
+        )],
+        _ => vec![CheckResult::warn(
+            "input-monitoring",
+            "Input Monitoring permission has not been requested yet".to_string(),
+            "Run any device command once and accept the permission prompt, or add your terminal in System Settings > \
+             Privacy & Security > Input Monitoring",
+        )],
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows", target_os = "macos")))]
+fn platform_checks() -> Vec<CheckResult> {
+    Vec::new()
+}
@@ -0,0 +1,130 @@
+//! The registry of hash domains
+//! Every challenge, key and MAC is hashed under a domain separation tag `domain.vversion.label`. The base layer and the
+//! app compute the same tags, so a changed domain, version or label invalidates every signature made under it, without
+//! any error short of the node rejecting the transaction. All of them are declared here, and the bundled
+//! [`consensus_vectors`](crate::consensus_vectors) pin the exact tag of every label.
+
+use tari_crypto::{hash_domain, hashing::DomainSeparation};
+pub use tari_ledger_protocol::{
+    APP_HASH_LABELS,
+    BLINDED_KEY_LABEL,
+    BLINDED_PATH_LABEL,
+    DERIVED_KEY_LABEL,
+    DISPLAY_DIGEST_LABEL,
+    ENVELOPE_KEY_LABEL,
+    ENVELOPE_TAG_LABEL,
+    KERNEL_MESSAGE_LABEL,
+    KERNEL_NONCE_LABEL,
+    KERNEL_SIGNATURE_LABEL,
+    METADATA_SIGNATURE_LABEL,
+    SCRIPT_CHALLENGE_LABEL,
+    SENDER_OFFSET_NONCE_LABEL,
+    SESSION_AUTH_LABEL,
+    SESSION_KEY_LABEL,
+    SESSION_MAC_LABEL,
+    SWAP_PREIMAGE_LABEL,
+    TRANSACTION_HASH_DOMAIN,
+    TRANSACTION_HASH_DOMAIN_VERSION,
+};
+
+hash_domain!(
+    TransactionHashDomain,
+    TRANSACTION_HASH_DOMAIN,
+    TRANSACTION_HASH_DOMAIN_VERSION
+);
+
+/// The label of the script signature message, the part of the script challenge that does not depend on any keys
+pub const SCRIPT_MESSAGE_LABEL: &str = "script_message";
+/// The label of the challenge the device signs for a change output
+pub const CHANGE_OUTPUT_LABEL: &str = "change_output";
+/// The label of a payment reference
+pub const PAYMENT_REFERENCE_LABEL: &str = "payment_reference";
+/// The label of the challenge the device signs for a withdrawal output
+pub const WITHDRAWAL_OUTPUT_LABEL: &str = "withdrawal_output";
+/// The label of the challenge the device signs for the output of a sweep
+pub const SWEEP_OUTPUT_LABEL: &str = "sweep_output";
+/// The label of the tweak that binds a script key to the commitment of its output
+pub const BOUND_SCRIPT_KEY_LABEL: &str = "bound_script_key";
+/// The label of the challenge the device signs for the receiver's output of an interactive transaction
+pub const RECEIVER_OUTPUT_LABEL: &str = "receiver_output";
+/// The label of the message the metadata signature of an output covers
+pub const OUTPUT_METADATA_LABEL: &str = "output_metadata";
+/// The label of the challenge the device signs for an output with sidechain features
+pub const SIDECHAIN_OUTPUT_LABEL: &str = "sidechain_output";
+/// The labels of the proofs a remote frontend and the daemon exchange with their pairing secret, and of the key of the
+/// connection, see `remote`
+pub const REMOTE_PROOF_LABEL: &str = "remote_proof";
+pub const REMOTE_RESPONSE_LABEL: &str = "remote_response";
+pub const REMOTE_KEY_LABEL: &str = "remote_key";
+
+/// Labels only the host hashes under the transaction hash domain
+pub const HOST_HASH_LABELS: [&str; 12] = [
+    SCRIPT_MESSAGE_LABEL,
+    CHANGE_OUTPUT_LABEL,
+    PAYMENT_REFERENCE_LABEL,
+    WITHDRAWAL_OUTPUT_LABEL,
+    SWEEP_OUTPUT_LABEL,
+    BOUND_SCRIPT_KEY_LABEL,
+    RECEIVER_OUTPUT_LABEL,
+    OUTPUT_METADATA_LABEL,
+    SIDECHAIN_OUTPUT_LABEL,
+    REMOTE_PROOF_LABEL,
+    REMOTE_RESPONSE_LABEL,
+    REMOTE_KEY_LABEL,
+];
+
+/// The purposes of the challenges the device signs for an output of a transaction
+pub const OUTPUT_CHALLENGE_LABELS: [&str; 6] = [
+    SCRIPT_CHALLENGE_LABEL,
+    CHANGE_OUTPUT_LABEL,
+    WITHDRAWAL_OUTPUT_LABEL,
+    SWEEP_OUTPUT_LABEL,
+    RECEIVER_OUTPUT_LABEL,
+    SIDECHAIN_OUTPUT_LABEL,
+];
+/// The purposes of the challenges the device signs on their own, outside a transaction summary
+pub const MESSAGE_LABELS: [&str; 2] = [SCRIPT_MESSAGE_LABEL, PAYMENT_REFERENCE_LABEL];
+
+/// Every label hashed under the transaction hash domain, by the app or the host
+pub fn transaction_hash_labels() -> impl Iterator<Item = &'static str> {
+    APP_HASH_LABELS.iter().chain(HOST_HASH_LABELS.iter()).copied()
+}
+
+/// The domain separation tag of `label` in the transaction hash domain
+pub fn transaction_hash_tag(label: &str) -> String {
+    TransactionHashDomain::domain_separation_tag(label)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn labels_are_unique() {
+        let labels = transaction_hash_labels().collect::<Vec<_>>();
+        for (i, label) in labels.iter().enumerate() {
+            assert!(!labels[i + 1..].contains(label), "{} is registered twice", label);
+        }
+    }
+
+    #[test]
+    fn challenge_labels_are_registered() {
+        for label in OUTPUT_CHALLENGE_LABELS.iter().chain(MESSAGE_LABELS.iter()) {
+            assert!(
+                transaction_hash_labels().any(|registered| registered == *label),
+                "{}",
+                label
+            );
+        }
+    }
+
+    #[test]
+    fn tags_name_the_domain_version_and_label() {
+        for label in transaction_hash_labels() {
+            assert_eq!(
+                transaction_hash_tag(label),
+                format!("com.tari.base_layer.core.transactions.v0.{}", label)
+            );
+        }
+    }
+}
@@ -0,0 +1,313 @@
+//! A transport that records APDUs instead of sending them
+//! Firmware developers validating the app's parser need the exact bytes the host produces. In a dry run every command
+//! is logged as hex together with the response layout the app is expected to return, and answered with a well formed
+//! placeholder so that the host walks the whole pipeline without a device. The placeholder keys and signatures are
+//! all zero, so signers have to run in [`SignerMode::Offline`](crate::signer::SignerMode::Offline) to skip their
+//! verification.
+//!
+//! A dry run [`with_software_keys`](DryRunTransport::with_software_keys) answers the requests for public keys,
+//! commitments and script signatures with keys derived from a seed instead, so that they verify. The keys are not the
+//! ones a device derives from any seed phrase, they only stand in for them.
+
+use std::{
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use ledger_transport::{APDUAnswer, APDUCommand};
+use sha2::{Digest, Sha256};
+use tari_crypto::{
+    commitment::HomomorphicCommitmentFactory,
+    keys::PublicKey,
+    ristretto::{
+        pedersen::extended_commitment_factory::ExtendedPedersenCommitmentFactory,
+        RistrettoPublicKey,
+        RistrettoSecretKey,
+    },
+    tari_utilities::{hex::to_hex, ByteArray},
+};
+use tari_ledger_protocol::{
+    batch_commitment_response_length,
+    nonce_pool_fetch_response_length,
+    public_keys_response_length,
+    Capabilities,
+    Instruction,
+    SemanticVersion,
+    BP_RESPONSE_LENGTH,
+    COMMITMENT_RESPONSE_LENGTH,
+    DISPLAY_HINTS_RESPONSE_LENGTH,
+    EXPORT_PRIVATE_KEY_RESPONSE_LENGTH,
+    GET_BIRTHDAY_RESPONSE_LENGTH,
+    GET_BLINDED_PUBLIC_KEY_RESPONSE_LENGTH,
+    GET_SETTINGS_RESPONSE_LENGTH,
+    KERNEL_NONCE_RESPONSE_LENGTH,
+    MAX_NONCE_POOL_FETCH,
+    NONCE_POOL_SIGN_RESPONSE_LENGTH,
+    OPEN_SESSION_RESPONSE_LENGTH,
+    PAIRING_VERIFY_RESPONSE_LENGTH,
+    RESPONSE_FORMAT_VERSION,
+    SENDER_OFFSET_SIGN_RESPONSE_LENGTH,
+    SETTINGS_ALL,
+    SETTINGS_HOST_ENABLED,
+    SET_SETTINGS_RESPONSE_LENGTH,
+    SIGNING_COUNTER_RESPONSE_LENGTH,
+    SIGN_RESPONSE_LENGTH,
+    SWAP_LOCK_RESPONSE_LENGTH,
+    SWAP_PREIMAGE_RESPONSE_LENGTH,
+    SW_INS_NOT_SUPPORTED,
+    SW_OK,
+    TRANSACTION_SUMMARY_RESPONSE_LENGTH,
+};
+
+use crate::{
+    device::{client_version, KeyBranch},
+    errors::DeviceError,
+    transport::LedgerTransport,
+    verify::script_challenge,
+};
+
+/// One command of a dry run
+#[derive(Clone, Debug)]
+pub struct DryRunExchange {
+    /// The serialized APDU, header included
+    pub command: Vec<u8>,
+    /// The layout of the response the app should return
+    pub response_schema: &'static str,
+}
+
+/// The commands recorded by a [`DryRunTransport`]. Clones share the same log, so a handle can be kept while the
+/// transport itself is moved into a device.
+#[derive(Clone, Debug, Default)]
+pub struct DryRunLog {
+    exchanges: Arc<Mutex<Vec<DryRunExchange>>>,
+}
+
+impl DryRunLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn exchanges(&self) -> Vec<DryRunExchange> {
+        self.exchanges
+            .lock()
+            .expect("the dry run log is never poisoned")
+            .clone()
+    }
+
+    /// Every command as a `> <hex>` line followed by a `< <schema>` line for its expected response
+    pub fn to_text(&self) -> String {
+        self.exchanges()
+            .iter()
+            .map(|exchange| format!("> {}\n< {}\n", to_hex(&exchange.command), exchange.response_schema))
+            .collect()
+    }
+
+    pub fn write_to<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        fs::write(path, self.to_text())
+    }
+
+    fn push(&self, exchange: DryRunExchange) {
+        self.exchanges
+            .lock()
+            .expect("the dry run log is never poisoned")
+            .push(exchange);
+    }
+}
+
+pub struct DryRunTransport {
+    log: DryRunLog,
+    software_keys: Option<u64>,
+}
+
+impl DryRunTransport {
+    pub fn new(log: DryRunLog) -> Self {
+        Self {
+            log,
+            software_keys: None,
+        }
+    }
+
+    /// Answer requests for public keys, commitments and script signatures with keys derived from `seed`
+    pub fn with_software_keys(mut self, seed: u64) -> Self {
+        self.software_keys = Some(seed);
+        self
+    }
+}
+
+impl LedgerTransport for DryRunTransport {
+    fn exchange(&self, command: &APDUCommand<Vec<u8>>) -> Result<APDUAnswer<Vec<u8>>, DeviceError> {
+        let (response_schema, answer) = match Instruction::try_from(command.ins) {
+            Ok(instruction) => {
+                let mut answer = self
+                    .software_keys
+                    .and_then(|seed| software_response(seed, instruction, command))
+                    .unwrap_or_else(|| placeholder_response(instruction, &command.data));
+                answer.extend_from_slice(&SW_OK.to_be_bytes());
+                (response_schema(instruction, command.p1), answer)
+            },
+            Err(_) => (
+                "status 0x6d00, unknown instruction",
+                SW_INS_NOT_SUPPORTED.to_be_bytes().to_vec(),
+            ),
+        };
+        self.log.push(DryRunExchange {
+            command: command.serialize(),
+            response_schema,
+        });
+        APDUAnswer::from_answer(answer).map_err(|_| DeviceError::InvalidResponse("the dry run answer is malformed"))
+    }
+}
+
+/// The response layout of the protocol spec for the message `instruction` sends with `p1`
+fn response_schema(instruction: Instruction, p1: u8) -> &'static str {
+    instruction
+        .spec()
+        .message(p1)
+        .map_or("status 0x6a88, unknown P1", |message| message.response)
+}
+
+/// A well formed response without a status word. Keys, scalars and commitments are all zero, which decode as the
+/// identity and zero scalar.
+fn placeholder_response(instruction: Instruction, data: &[u8]) -> Vec<u8> {
+    let zeroed = |length: usize| {
+        let mut response = vec![0u8; length];
+        response[0] = RESPONSE_FORMAT_VERSION;
+        response
+    };
+    match instruction {
+        Instruction::GetVersion => {
+            let name = b"Tari";
+            let version = client_version().to_string();
+            let mut response = vec![RESPONSE_FORMAT_VERSION, name.len() as u8];
+            response.extend_from_slice(name);
+            response.push(version.len() as u8);
+            response.extend_from_slice(version.as_bytes());
+            // A dry run refuses nothing
+            response.push(SETTINGS_ALL);
+            response
+        },
+        Instruction::ClientVersion => {
+            let mut response = vec![RESPONSE_FORMAT_VERSION];
+            response.extend_from_slice(&client_version().to_le_bytes());
+            response.extend_from_slice(&SemanticVersion::new(0, 0, 0).to_le_bytes());
+            response
+        },
+        Instruction::GetCapabilities => {
+            // Every chunk is answered on its own, so there is no reassembled upload to echo the CRC of
+            let all = Capabilities::NAMED
+                .iter()
+                .filter(|(capability, _)| *capability != Capabilities::FRAMED_UPLOADS)
+                .fold(Capabilities::empty(), |all, (capability, _)| all.union(*capability));
+            let mut response = vec![RESPONSE_FORMAT_VERSION];
+            response.extend_from_slice(&all.to_le_bytes());
+            response
+        },
+        Instruction::Sign | Instruction::SignOutput | Instruction::SignConfirmedOutput => zeroed(SIGN_RESPONSE_LENGTH),
+        Instruction::Commitment => zeroed(COMMITMENT_RESPONSE_LENGTH),
+        Instruction::BPData => zeroed(BP_RESPONSE_LENGTH),
+        Instruction::TransactionSummary => zeroed(TRANSACTION_SUMMARY_RESPONSE_LENGTH),
+        Instruction::SwapLock => zeroed(SWAP_LOCK_RESPONSE_LENGTH),
+        Instruction::SwapPreimage => zeroed(SWAP_PREIMAGE_RESPONSE_LENGTH),
+        Instruction::GetPublicKeys => zeroed(public_keys_response_length(data.get(8).copied().unwrap_or(0))),
+        Instruction::BatchCommitment => zeroed(batch_commitment_response_length(data.first().copied().unwrap_or(0))),
+        Instruction::GetSigningCounter => zeroed(SIGNING_COUNTER_RESPONSE_LENGTH),
+        Instruction::OpenSession => zeroed(OPEN_SESSION_RESPONSE_LENGTH),
+        Instruction::ExportPrivateKey => zeroed(EXPORT_PRIVATE_KEY_RESPONSE_LENGTH),
+        Instruction::DisplayHints => zeroed(DISPLAY_HINTS_RESPONSE_LENGTH),
+        Instruction::GetBlindedPublicKey => zeroed(GET_BLINDED_PUBLIC_KEY_RESPONSE_LENGTH),
+        // Registering and verifying look alike to the transport, and the longer answer parses leniently as either
+        Instruction::Pairing => zeroed(PAIRING_VERIFY_RESPONSE_LENGTH),
+        Instruction::WalletBirthday => zeroed(GET_BIRTHDAY_RESPONSE_LENGTH),
+        // Like pairing, the longer answer parses leniently as the script offset too
+        Instruction::SenderOffset => zeroed(SENDER_OFFSET_SIGN_RESPONSE_LENGTH),
+        Instruction::KernelSignature => zeroed(KERNEL_NONCE_RESPONSE_LENGTH),
+        // The fetch is sized by its count, and answers long enough for a signature parse leniently as the other modes
+        Instruction::NoncePool => {
+            let count = data.first().copied().unwrap_or(0).min(MAX_NONCE_POOL_FETCH as u8);
+            zeroed(nonce_pool_fetch_response_length(count).max(NONCE_POOL_SIGN_RESPONSE_LENGTH))
+        },
+        // Only a change carries data
+        Instruction::AppSettings if data.is_empty() => {
+            let mut response = zeroed(GET_SETTINGS_RESPONSE_LENGTH);
+            response[1] = SETTINGS_ALL;
+            response[2] = SETTINGS_HOST_ENABLED;
+            response
+        },
+        Instruction::AppSettings => {
+            let mut response = zeroed(SET_SETTINGS_RESPONSE_LENGTH);
+            response[1] = SETTINGS_ALL;
+            response
+        },
+    }
+}
+
+/// A scalar from the hash of `parts`, so that every key of a dry run with software keys follows from its seed
+fn software_scalar(parts: &[&[u8]]) -> RistrettoSecretKey {
+    let mut hash: [u8; 32] = parts
+        .iter()
+        .fold(Sha256::new(), |hash, part| hash.chain_update(part))
+        .finalize()
+        .into();
+    // Below 2^252, so always a canonical scalar
+    hash[31] &= 0x0f;
+    RistrettoSecretKey::from_bytes(&hash).expect("a value below the group order is a scalar")
+}
+
+/// The key at `index` in `branch` of `account`, derived under the version in `p2`
+fn software_key(seed: u64, account: u32, branch: u8, index: u32, p2: u8) -> RistrettoSecretKey {
+    software_scalar(&[
+        b"dry run key",
+        &seed.to_le_bytes(),
+        &account.to_le_bytes(),
+        &[branch, p2],
+        &index.to_le_bytes(),
+    ])
+}
+
+/// The response of a dry run with software keys, without a status word, for the instructions it derives keys for.
+/// Requests it cannot parse get placeholders like any other.
+fn software_response(seed: u64, instruction: Instruction, command: &APDUCommand<Vec<u8>>) -> Option<Vec<u8>> {
+    let data = &command.data;
+    let u32_at = |offset: usize| Some(u32::from_le_bytes(data.get(offset..offset + 4)?.try_into().ok()?));
+    let mut response = vec![RESPONSE_FORMAT_VERSION];
+    match instruction {
+        Instruction::GetPublicKeys => {
+            let account = u32_at(0)?;
+            let index = u32_at(4)?;
+            let count = *data.get(8)?;
+            for index in (index..).take(usize::from(count)) {
+                let k = software_key(seed, account, command.p1, index, command.p2);
+                response.extend_from_slice(RistrettoPublicKey::from_secret_key(&k).as_bytes());
+            }
+        },
+        Instruction::BatchCommitment => {
+            let factory = ExtendedPedersenCommitmentFactory::default();
+            let count = usize::from(*data.first()?);
+            for offset in (1..).step_by(12).take(count) {
+                let value = u64::from_le_bytes(data.get(offset..offset + 8)?.try_into().ok()?);
+                let index = u32_at(offset + 8)?;
+                let mask = software_key(seed, 0, KeyBranch::CommitmentMask.as_byte(), index, command.p2);
+                response.extend_from_slice(factory.commit_value(&mask, value).as_bytes());
+            }
+        },
+        Instruction::Sign | Instruction::SignOutput | Instruction::SignConfirmedOutput => {
+            // `[kind][value u64][session nonce u64][challenge 32]..` for an output, the challenge alone otherwise
+            let offset = if instruction == Instruction::Sign { 0 } else { 17 };
+            let mut message = [0u8; 32];
+            message.copy_from_slice(data.get(offset..offset + 32)?);
+            // The app key is the first commitment mask key of account 0
+            let k = software_key(seed, 0, KeyBranch::CommitmentMask.as_byte(), 0, command.p2);
+            let r = software_scalar(&[b"dry run nonce", &seed.to_le_bytes(), &message]);
+            let public_key = RistrettoPublicKey::from_secret_key(&k);
+            let public_nonce = RistrettoPublicKey::from_secret_key(&r);
+            let e = RistrettoSecretKey::from_bytes(&script_challenge(&public_key, &public_nonce, &message)).ok()?;
+            let s = &r + &(&e * &k);
+            response.extend_from_slice(public_key.as_bytes());
+            response.extend_from_slice(s.as_bytes());
+            response.extend_from_slice(public_nonce.as_bytes());
+        },
+        _ => return None,
+    }
+    Some(response)
+}
@@ -0,0 +1,59 @@
+//! Decryption of the private keys the app exports
+//! The view key and the rewind key reveal every output of the wallet, so the app never returns them in the clear. The
+//! host sends a fresh ephemeral key with the request and the app encrypts the key to it, ECIES style: a one-time pad
+//! and a tag derived from the Diffie-Hellman secret of the host key and an ephemeral key of the app. Anything that
+//! watches the USB link only sees the ciphertext.
+
+use tari_crypto::{
+    keys::PublicKey,
+    ristretto::{RistrettoPublicKey, RistrettoSecretKey},
+    tari_utilities::ByteArray,
+};
+use tari_ledger_protocol::{
+    ENVELOPE_KEY_LABEL,
+    ENVELOPE_TAG_LABEL,
+    ENVELOPE_TAG_LENGTH,
+    EXPORT_PRIVATE_KEY_RESPONSE_LENGTH,
+};
+
+use crate::{
+    errors::DeviceError,
+    hashing::{DomainSeparatedConsensusHasher, TransactionHashDomain},
+};
+
+/// Decrypt the payload of an `ExportPrivateKey` response with the ephemeral key the request was made for
+pub fn open_envelope(host_secret: &RistrettoSecretKey, payload: &[u8]) -> Result<RistrettoSecretKey, DeviceError> {
+    if payload.len() != EXPORT_PRIVATE_KEY_RESPONSE_LENGTH - 1 {
+        return Err(DeviceError::InvalidResponse("wrong envelope length"));
+    }
+    let ephemeral_public_key = RistrettoPublicKey::from_bytes(&payload[0..32])
+        .map_err(|_| DeviceError::InvalidResponse("the envelope holds an invalid key"))?;
+    let ciphertext = &payload[32..64];
+    let tag = &payload[64..64 + ENVELOPE_TAG_LENGTH];
+
+    let host_key = RistrettoPublicKey::from_secret_key(host_secret);
+    let shared_secret = &ephemeral_public_key * host_secret;
+    let mut ciphertext_bytes = [0u8; 32];
+    ciphertext_bytes.copy_from_slice(ciphertext);
+    let expected_tag = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(ENVELOPE_TAG_LABEL)
+        .chain(&shared_secret)
+        .chain(&ephemeral_public_key)
+        .chain(&ciphertext_bytes)
+        .finalize();
+    if expected_tag[..ENVELOPE_TAG_LENGTH] != *tag {
+        return Err(DeviceError::AuthenticationFailed);
+    }
+
+    let pad = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(ENVELOPE_KEY_LABEL)
+        .chain(&shared_secret)
+        .chain(&host_key)
+        .chain(&ephemeral_public_key)
+        .finalize();
+    let plaintext = ciphertext_bytes
+        .iter()
+        .zip(pad.iter())
+        .map(|(c, p)| c ^ p)
+        .collect::<Vec<_>>();
+    RistrettoSecretKey::from_bytes(&plaintext)
+        .map_err(|_| DeviceError::InvalidResponse("the envelope holds an invalid key"))
+}
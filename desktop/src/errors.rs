@@ -0,0 +1,1011 @@
+use std::{fmt, time::Duration};
+
+#[cfg(feature = "hid")]
+use ledger_transport_hid::LedgerHIDError;
+use tari_ledger_protocol::{DerivationVersion, Instruction, ProtocolError, SemanticVersion};
+
+#[cfg(feature = "serde")]
+use crate::multisig::MultisigState;
+use crate::{address::Network, hashing::Preimage, units::Unit};
+
+#[derive(Debug)]
+pub enum DeviceError {
+    #[cfg(feature = "hid")]
+    Transport(LedgerHIDError),
+    /// The device answered with a status word other than `0x9000`
+    Status(u16),
+    /// The response did not match the wire format
+    Protocol(ProtocolError),
+    /// The response was well formed but holds an invalid value
+    InvalidResponse(&'static str),
+    /// The app refused this client because it is older than the app's minimum supported client version
+    ClientVersionRejected {
+        client: SemanticVersion,
+        min_client: SemanticVersion,
+    },
+    /// The app is older than the minimum app version supported by this client
+    AppVersionUnsupported {
+        app: SemanticVersion,
+        min_app: SemanticVersion,
+    },
+    /// The user declined the request on the device
+    UserRejected,
+    /// The device refused to sign an output that is not covered by the transaction the user approved
+    TransactionNotApproved,
+    /// The app on the device does not implement a feature this request needs
+    Unsupported(&'static str),
+    /// The device is locked and has to be unlocked with its PIN
+    DeviceLocked,
+    /// A transport that talks to the device node itself failed to read or write it
+    Io(std::io::Error),
+    /// A MAC of the authenticated session did not check out, or the device could not prove it holds the expected key
+    AuthenticationFailed,
+    /// The device signed two different challenges with the same public nonce, which leaks its private key
+    NonceReuse,
+    /// The device is unlocked with another seed than the one the profile is pinned to, by their fingerprints
+    WrongWallet { expected: String, actual: String },
+    /// The request needs a setting the user has not enabled in the Tari app, by its name
+    SettingDisabled(&'static str),
+    /// The app refused a command of the authenticated session whose MAC did not check out, and closed the session
+    SessionMacRejected,
+    /// The authenticated session failed, and nothing is sent to the app until a new one is opened
+    SessionClosed,
+    /// The device and this host could not prove to each other that they hold the same pairing secret
+    PairingFailed,
+    /// An approval hook of the daemon could not be run or failed, and why
+    ApprovalHookFailed(String),
+    /// An approval hook of the daemon, or its operator, refused the command before it reached the device
+    ApprovalRefused,
+    /// The daemon gave up on a command that the device did not answer within the deadline
+    DeadlineExceeded(Duration),
+    /// The daemon knows no client with the token this one presented
+    ClientNotAuthorized,
+    /// The daemon does not let this client send the instruction, by the class it belongs to
+    ClientNotPermitted,
+    /// The daemon refuses further commands of this client until its quota frees up again
+    ClientQuotaExceeded,
+    /// A remote frontend and the daemon could not prove to each other that they hold the same pairing code
+    RemoteNotPaired,
+    /// A chunked upload reached the app incomplete or out of order, or the app reassembled a different payload
+    UploadCorrupted,
+    /// The payload is longer than the app reassembles from a framed upload
+    UploadTooLong { length: usize, max: usize },
+    /// A request names more keys than fit into a single APDU
+    TooManyKeys { count: usize, max: usize },
+    /// The data of a command is longer than the app accepts with its instruction
+    DataTooLong {
+        instruction: Instruction,
+        length: usize,
+        max: usize,
+    },
+    /// A pooled nonce the app never issued, has already signed with, or dropped with its pool
+    NonceNotIssued,
+    /// A signature the device returned does not verify, with the preimage of the challenge the host checked it under
+    InvalidSignature {
+        signature: &'static str,
+        preimage: Box<Preimage>,
+    },
+}
+
+impl fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            #[cfg(feature = "hid")]
+            DeviceError::Transport(e) => write!(f, "Transport error: {}", e),
+            DeviceError::Status(sw) => write!(f, "Device returned status word {:#06x}", sw),
+            DeviceError::Protocol(e) => write!(f, "Invalid device response: {}", e),
+            DeviceError::InvalidResponse(e) => write!(f, "Invalid device response: {}", e),
+            DeviceError::ClientVersionRejected { client, min_client } => write!(
+                f,
+                "The Tari Ledger app requires client version {} or newer, but this client is {}. Please upgrade \
+                 tari-ledger",
+                min_client, client
+            ),
+            DeviceError::AppVersionUnsupported { app, min_app } => write!(
+                f,
+                "This client requires Tari Ledger app version {} or newer, but the device runs {}. Please update the \
+                 app on the device",
+                min_app, app
+            ),
+            DeviceError::UserRejected => write!(f, "The request was rejected on the device"),
+            DeviceError::TransactionNotApproved => {
+                write!(
+                    f,
+                    "The device refused to sign an output outside of the approved transaction"
+                )
+            },
+            DeviceError::Unsupported(feature) => write!(f, "The app on the device does not support {}", feature),
+            DeviceError::DeviceLocked => write!(f, "The device is locked, please unlock it"),
+            DeviceError::Io(e) => write!(f, "Transport I/O error: {}", e),
+            DeviceError::AuthenticationFailed => write!(
+                f,
+                "The authenticated session with the device failed, the connection may have been tampered with"
+            ),
+            DeviceError::NonceReuse => write!(
+                f,
+                "SECURITY ALERT: the device reused a signature nonce for a different challenge, which reveals its \
+                 key. Stop using this device and move the funds to a new seed"
+            ),
+            DeviceError::WrongWallet { expected, actual } => write!(
+                f,
+                "The device is unlocked with wallet {} but the profile belongs to wallet {}, unlock it with the PIN \
+                 of that wallet",
+                actual, expected
+            ),
+            DeviceError::SettingDisabled(setting) => write!(
+                f,
+                "The request needs {} to be enabled in the settings of the Tari app, press the left button on the \
+                 device to change them",
+                setting
+            ),
+            DeviceError::SessionMacRejected => write!(
+                f,
+                "The app refused a command of the authenticated session and closed it, the connection may have been \
+                 tampered with"
+            ),
+            DeviceError::SessionClosed => write!(
+                f,
+                "The authenticated session with the device failed, nothing more is sent to it until a new session is \
+                 opened"
+            ),
+            DeviceError::PairingFailed => write!(
+                f,
+                "The device does not recognise the pairing of this host. If it was not paired with another host on \
+                 purpose, something other than the genuine tari-ledger may have been driving it: pair it again with \
+                 `tari-ledger pair` and check the words it shows"
+            ),
+            DeviceError::ApprovalHookFailed(reason) => write!(f, "An approval hook failed: {}", reason),
+            DeviceError::ApprovalRefused => write!(f, "The daemon refused the command before it reached the device"),
+            DeviceError::DeadlineExceeded(deadline) => write!(
+                f,
+                "The device did not answer within {} seconds, the daemon gave up on the command. Raise \
+                 `timeouts.command_secs` in the profile to give the confirmation more time",
+                deadline.as_secs()
+            ),
+            DeviceError::ClientNotAuthorized => write!(f, "The daemon does not know the token this client presented"),
+            DeviceError::ClientNotPermitted => write!(
+                f,
+                "The daemon does not let this client send the command, its token is not granted that class of \
+                 instructions"
+            ),
+            DeviceError::ClientQuotaExceeded => write!(
+                f,
+                "The daemon refuses further commands of this client for now, it sent more than its quota allows"
+            ),
+            DeviceError::RemoteNotPaired => write!(
+                f,
+                "The other end does not hold the pairing code of this connection, pair the frontend with the code the \
+                 daemon shows"
+            ),
+            DeviceError::UploadCorrupted => write!(
+                f,
+                "The device did not receive the payload that was sent, the connection lost or reordered part of it"
+            ),
+            DeviceError::UploadTooLong { length, max } => {
+                write!(f, "The payload is {} bytes, the app accepts at most {}", length, max)
+            },
+            DeviceError::TooManyKeys { count, max } => {
+                write!(f, "The request names {} keys, the app accepts at most {}", count, max)
+            },
+            DeviceError::DataTooLong {
+                instruction,
+                length,
+                max,
+            } => write!(
+                f,
+                "The {:?} command carries {} bytes of data, the app accepts at most {}",
+                instruction, length, max
+            ),
+            DeviceError::NonceNotIssued => write!(
+                f,
+                "The device no longer holds the nonce, it has signed with it already or dropped its nonce pool, fetch \
+                 a new one and start the signature over"
+            ),
+            DeviceError::InvalidSignature { signature, preimage } => write!(
+                f,
+                "Invalid device response: the {} does not verify, the host hashed the {}",
+                signature, preimage
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DeviceError {}
+
+#[cfg(feature = "hid")]
+impl From<LedgerHIDError> for DeviceError {
+    fn from(e: LedgerHIDError) -> Self {
+        DeviceError::Transport(e)
+    }
+}
+
+impl From<ProtocolError> for DeviceError {
+    fn from(e: ProtocolError) -> Self {
+        DeviceError::Protocol(e)
+    }
+}
+
+impl From<std::io::Error> for DeviceError {
+    fn from(e: std::io::Error) -> Self {
+        DeviceError::Io(e)
+    }
+}
+
+#[derive(Debug)]
+pub enum SignerError {
+    Device(DeviceError),
+    /// The device protocol limits a transaction to 255 outputs
+    TooManyOutputs(usize),
+    /// The recipient output values do not fit in a `u64`
+    ValueOverflow,
+    /// The device returned a signature that does not verify against the challenge of output `index`, with the
+    /// preimage of the script challenge the host checked it under if the signature is well formed
+    InvalidSignature {
+        index: usize,
+        preimage: Option<Box<Preimage>>,
+    },
+    /// The transaction fee is above the configured maximum
+    FeeTooHigh {
+        fee: u64,
+        max_fee: u64,
+    },
+    /// The preimage does not unlock the hash lock
+    InvalidPreimage,
+    /// The device signed with a different key than the one the script expects
+    KeyMismatch,
+    /// The signing session was started too long ago and has to be started over
+    SessionExpired {
+        age: Duration,
+    },
+    /// The inputs do not cover the payments, the fee and a change output, in microTari
+    InsufficientFunds {
+        available: u64,
+        required: u64,
+    },
+    /// The next change key could not be reserved
+    Store(StoreError),
+    /// The script cannot be satisfied with the input data
+    Script(ScriptError),
+    /// Recipient output `index` has no address for the device to show
+    MissingRecipient {
+        index: usize,
+    },
+    /// The challenge was hashed under this label, for a purpose other than the one being signed
+    WrongPurpose(&'static str),
+    /// A field of the sidechain features of an output is longer than the base layer accepts
+    FeatureTooLong {
+        field: &'static str,
+        length: usize,
+        max: usize,
+    },
+    /// A transaction over the threshold of the two-man rule was not confirmed by a second operator, in microTari
+    NotConfirmed {
+        amount: u64,
+        threshold: u64,
+        reason: String,
+    },
+}
+
+impl fmt::Display for SignerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignerError::Device(e) => write!(f, "{}", e),
+            SignerError::TooManyOutputs(n) => write!(f, "A transaction can have at most 255 outputs, got {}", n),
+            SignerError::ValueOverflow => write!(f, "The total output value overflows"),
+            SignerError::InvalidSignature { index, preimage } => {
+                write!(f, "The device returned an invalid signature for output {}", index)?;
+                match preimage {
+                    Some(preimage) => write!(f, ", the host hashed the {}", preimage),
+                    None => Ok(()),
+                }
+            },
+            SignerError::FeeTooHigh { fee, max_fee } => write!(
+                f,
+                "The transaction fee of {} uT is above the maximum of {} uT, refusing to sign",
+                fee, max_fee
+            ),
+            SignerError::InvalidPreimage => write!(f, "The preimage does not match the hash lock"),
+            SignerError::KeyMismatch => write!(f, "The device key does not match the key required by the script"),
+            SignerError::SessionExpired { age } => write!(
+                f,
+                "The signing session expired after {} seconds, please start the transaction again",
+                age.as_secs()
+            ),
+            SignerError::InsufficientFunds { available, required } => write!(
+                f,
+                "The inputs hold {} uT but the transaction needs {} uT including the fee",
+                available, required
+            ),
+            SignerError::Store(e) => write!(f, "Could not reserve a change key: {}", e),
+            SignerError::Script(e) => write!(f, "{}", e),
+            SignerError::MissingRecipient { index } => write!(
+                f,
+                "Output {} has no recipient address for the device to show, refusing to sign it unconfirmed",
+                index
+            ),
+            SignerError::WrongPurpose(label) => {
+                write!(f, "A {} hash cannot be signed here, refusing to sign it", label)
+            },
+            SignerError::FeatureTooLong { field, length, max } => write!(
+                f,
+                "The {} of the output features is {} bytes long, the base layer accepts at most {}",
+                field, length, max
+            ),
+            SignerError::NotConfirmed {
+                amount,
+                threshold,
+                reason,
+            } => write!(
+                f,
+                "The transaction pays out {} uT, more than the {} uT a second operator has to confirm, and was not \
+                 confirmed: {}",
+                amount, threshold, reason
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SignerError {}
+
+impl From<DeviceError> for SignerError {
+    fn from(e: DeviceError) -> Self {
+        SignerError::Device(e)
+    }
+}
+
+impl From<ScriptError> for SignerError {
+    fn from(e: ScriptError) -> Self {
+        SignerError::Script(e)
+    }
+}
+
+impl From<StoreError> for SignerError {
+    fn from(e: StoreError) -> Self {
+        SignerError::Store(e)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptError {
+    InvalidOpcode(u8),
+    InvalidStackItem(u8),
+    /// The bytes end in the middle of an opcode or item, or hold an invalid key or varint
+    InvalidData,
+    TooLong,
+    /// An opcode needs more items than are on the stack
+    StackUnderflow,
+    StackOverflow,
+    /// An opcode was applied to items of the wrong type
+    IncompatibleTypes,
+    /// A number or the block height is out of range for the operation
+    ValueExceedsBounds,
+    /// A verify opcode failed, so the script cannot be satisfied with this input
+    VerifyFailed,
+    /// The script ran into `Return`
+    Returned,
+    /// An `IfThen`, `Else` or `EndIf` has no counterpart, or the condition is not 0 or 1
+    InvalidBranch,
+    /// The script did not leave exactly one item on the stack
+    NonUnitLengthStack,
+    /// The script did not evaluate to a public key
+    NotAPublicKey,
+    /// The opcode cannot be evaluated on the host
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScriptError::InvalidOpcode(code) => write!(f, "Invalid script opcode {:#04x}", code),
+            ScriptError::InvalidStackItem(kind) => write!(f, "Invalid stack item type {}", kind),
+            ScriptError::InvalidData => write!(f, "Malformed script data"),
+            ScriptError::TooLong => write!(f, "The script or stack exceeds the maximum size"),
+            ScriptError::StackUnderflow => write!(f, "The script needs more items than are on the stack"),
+            ScriptError::StackOverflow => write!(f, "The script exceeds the maximum stack size"),
+            ScriptError::IncompatibleTypes => write!(f, "A script operation was applied to the wrong item types"),
+            ScriptError::ValueExceedsBounds => write!(f, "A script value is out of range"),
+            ScriptError::VerifyFailed => write!(f, "A script verification failed, the input does not satisfy it"),
+            ScriptError::Returned => write!(f, "The script returned early and can never be spent"),
+            ScriptError::InvalidBranch => write!(f, "The script has an unbalanced or invalid conditional"),
+            ScriptError::NonUnitLengthStack => write!(f, "The script did not leave exactly one item on the stack"),
+            ScriptError::NotAPublicKey => write!(f, "The script did not evaluate to a public key"),
+            ScriptError::Unsupported(opcode) => write!(f, "{} cannot be evaluated on the host", opcode),
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+#[derive(Debug)]
+pub enum StoreError {
+    Io(std::io::Error),
+    Serialization(String),
+    /// An error reported by the underlying database
+    Backend(String),
+    /// Stored data could not be decoded
+    Corrupt(&'static str),
+    /// A signature was recorded with the public nonce of the earlier record `earlier`, over a different challenge
+    NonceReuse {
+        earlier: i64,
+        record: i64,
+    },
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StoreError::Io(e) => write!(f, "State store I/O error: {}", e),
+            StoreError::Serialization(e) => write!(f, "State store serialization error: {}", e),
+            StoreError::Backend(e) => write!(f, "State store backend error: {}", e),
+            StoreError::Corrupt(reason) => write!(f, "State store is corrupt: {}", reason),
+            StoreError::NonceReuse { earlier, record } => write!(
+                f,
+                "SECURITY ALERT: history record #{} reuses the signature nonce of #{} for a different challenge, \
+                 which reveals the device key. Stop using this device and move the funds to a new seed",
+                record, earlier
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<std::io::Error> for StoreError {
+    fn from(e: std::io::Error) -> Self {
+        StoreError::Io(e)
+    }
+}
+
+#[cfg(feature = "sled")]
+impl From<sled::Error> for StoreError {
+    fn from(e: sled::Error) -> Self {
+        StoreError::Backend(e.to_string())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for StoreError {
+    fn from(e: rusqlite::Error) -> Self {
+        StoreError::Backend(e.to_string())
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Parse(String),
+    /// `--profile` names a profile the configuration file does not define
+    UnknownProfile(String),
+    /// The configuration file is encrypted but no passphrase was given
+    PassphraseRequired,
+    /// Encryption failed, or decryption did because of a wrong passphrase or a corrupted file
+    Encryption,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "Configuration I/O error: {}", e),
+            ConfigError::Parse(e) => write!(f, "Invalid configuration file: {}", e),
+            ConfigError::UnknownProfile(name) => write!(f, "There is no profile called '{}'", name),
+            ConfigError::PassphraseRequired => write!(
+                f,
+                "The configuration file is encrypted, use --config-key or TARI_LEDGER_CONFIG_KEY"
+            ),
+            ConfigError::Encryption => write!(f, "Could not decrypt the configuration file (wrong passphrase?)"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(e: std::io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddressError {
+    InvalidSize,
+    InvalidEmoji,
+    /// The checksum does not match any known network
+    InvalidNetworkOrChecksum,
+    InvalidPublicKey,
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AddressError::InvalidSize => write!(f, "An address must be 33 bytes or emoji long"),
+            AddressError::InvalidEmoji => write!(f, "The address contains an emoji outside of the Emoji ID set"),
+            AddressError::InvalidNetworkOrChecksum => write!(f, "Invalid address network or checksum"),
+            AddressError::InvalidPublicKey => write!(f, "The address does not hold a valid public key"),
+        }
+    }
+}
+
+impl std::error::Error for AddressError {}
+
+#[derive(Debug)]
+pub enum AmountError {
+    /// Not a number in the separators of the format
+    Invalid(String),
+    UnknownUnit(String),
+    /// More decimal places than the unit has
+    TooPrecise {
+        unit: Unit,
+    },
+    /// More than fits in a `u64` of microTari
+    Overflow,
+}
+
+impl fmt::Display for AmountError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AmountError::Invalid(amount) => write!(f, "'{}' is not an amount", amount),
+            AmountError::UnknownUnit(e) => write!(f, "{}", e),
+            AmountError::TooPrecise { unit } => {
+                write!(
+                    f,
+                    "An amount in {} has at most {} decimal places",
+                    unit,
+                    unit.decimals()
+                )
+            },
+            AmountError::Overflow => write!(f, "The amount is too large"),
+        }
+    }
+}
+
+impl std::error::Error for AmountError {}
+
+#[derive(Debug)]
+pub enum WalletTxError {
+    /// The file is not JSON or lacks a field the signer needs
+    Parse(String),
+    UnsupportedVersion(u64),
+    /// The wallet built the kernel with a different fee than the one the device would show, in microTari
+    FeeMismatch {
+        wallet: u64,
+        computed: u64,
+    },
+    Signer(SignerError),
+}
+
+impl fmt::Display for WalletTxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WalletTxError::Parse(e) => write!(f, "Invalid unsigned transaction: {}", e),
+            WalletTxError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported unsigned transaction version {}", version)
+            },
+            WalletTxError::FeeMismatch { wallet, computed } => write!(
+                f,
+                "The wallet used a fee of {} uT but the transaction weighs in at {} uT, refusing to sign",
+                wallet, computed
+            ),
+            WalletTxError::Signer(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for WalletTxError {}
+
+impl From<SignerError> for WalletTxError {
+    fn from(e: SignerError) -> Self {
+        WalletTxError::Signer(e)
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(Debug)]
+pub enum MultisigError {
+    /// The document is not JSON or lacks a field
+    Parse(String),
+    UnsupportedVersion(u64),
+    /// The threshold has to be between 1 and the number of participants
+    InvalidThreshold {
+        threshold: u8,
+        participants: u8,
+    },
+    /// The document is not in the round the operation belongs to
+    WrongState(MultisigState),
+    /// The key is already in the document
+    DuplicateKey(String),
+    /// A signature by a key that is not one of the participants
+    UnknownKey(String),
+    /// A signature that does not verify against its key and the message
+    InvalidSignature(String),
+    Signer(SignerError),
+}
+
+#[cfg(feature = "serde")]
+impl fmt::Display for MultisigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MultisigError::Parse(e) => write!(f, "Invalid multisig document: {}", e),
+            MultisigError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported multisig document version {}", version)
+            },
+            MultisigError::InvalidThreshold {
+                threshold,
+                participants,
+            } => write!(f, "A {}-of-{} multisig is not possible", threshold, participants),
+            MultisigError::WrongState(state) => write!(f, "The multisig document is {}", state),
+            MultisigError::DuplicateKey(key) => write!(f, "Key {} is already in the multisig document", key),
+            MultisigError::UnknownKey(key) => write!(f, "Key {} is not a participant of the multisig", key),
+            MultisigError::InvalidSignature(key) => write!(f, "The signature by key {} is invalid", key),
+            MultisigError::Signer(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl std::error::Error for MultisigError {}
+
+#[cfg(feature = "serde")]
+impl From<SignerError> for MultisigError {
+    fn from(e: SignerError) -> Self {
+        MultisigError::Signer(e)
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(Debug)]
+pub enum TransactionProtocolError {
+    /// The message is not JSON or lacks a field
+    Parse(String),
+    UnsupportedVersion(u64),
+    /// The message belongs to another transaction, by its id
+    WrongTransaction {
+        expected: u64,
+        actual: u64,
+    },
+    /// A field of the reply does not match the message it answers, by its name
+    Mismatch(&'static str),
+    /// A signature that does not verify, by what it signs
+    InvalidSignature(&'static str),
+    /// The finished transaction does not hold together, by the check that failed
+    InvalidTransaction(&'static str),
+    Device(DeviceError),
+    Signer(SignerError),
+}
+
+#[cfg(feature = "serde")]
+impl fmt::Display for TransactionProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransactionProtocolError::Parse(e) => write!(f, "Invalid transaction message: {}", e),
+            TransactionProtocolError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported transaction message version {}", version)
+            },
+            TransactionProtocolError::WrongTransaction { expected, actual } => write!(
+                f,
+                "The message belongs to transaction {} but transaction {} was expected",
+                actual, expected
+            ),
+            TransactionProtocolError::Mismatch(field) => {
+                write!(f, "The {} of the reply does not match the message it answers", field)
+            },
+            TransactionProtocolError::InvalidSignature(what) => write!(f, "The {} is invalid", what),
+            TransactionProtocolError::InvalidTransaction(check) => write!(f, "The transaction is invalid: {}", check),
+            TransactionProtocolError::Device(e) => write!(f, "{}", e),
+            TransactionProtocolError::Signer(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl std::error::Error for TransactionProtocolError {}
+
+#[cfg(feature = "serde")]
+impl From<DeviceError> for TransactionProtocolError {
+    fn from(e: DeviceError) -> Self {
+        TransactionProtocolError::Device(e)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<SignerError> for TransactionProtocolError {
+    fn from(e: SignerError) -> Self {
+        TransactionProtocolError::Signer(e)
+    }
+}
+
+#[cfg(feature = "broadcast")]
+#[derive(Debug)]
+pub enum BroadcastError {
+    /// The base node could not be reached
+    Connect(String),
+    /// The base node failed the call, with its message
+    Rpc(String),
+    /// The base node answered with a value the client does not know
+    UnexpectedAnswer(String),
+    /// The base node dropped the transaction from its mempool
+    Dropped,
+    /// The transaction was not mined within the time it was given
+    NotMined(Duration),
+}
+
+#[cfg(feature = "broadcast")]
+impl fmt::Display for BroadcastError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BroadcastError::Connect(e) => write!(f, "Could not connect to the base node: {}", e),
+            BroadcastError::Rpc(e) => write!(f, "The base node failed the call: {}", e),
+            BroadcastError::UnexpectedAnswer(e) => write!(f, "The base node answered with an unknown {}", e),
+            BroadcastError::Dropped => write!(f, "The base node dropped the transaction from its mempool"),
+            BroadcastError::NotMined(timeout) => {
+                write!(f, "The transaction was not mined within {} seconds", timeout.as_secs())
+            },
+        }
+    }
+}
+
+#[cfg(feature = "broadcast")]
+impl std::error::Error for BroadcastError {}
+
+#[derive(Debug)]
+pub enum WithdrawalError {
+    /// A row of the withdrawal file, counting from 1, could not be used
+    Invalid {
+        line: usize,
+        reason: String,
+    },
+    /// Two rows pay the same address
+    DuplicateAddress {
+        line: usize,
+        first: usize,
+    },
+    /// The file holds no withdrawals
+    Empty,
+    /// A batch has to hold between 1 and 255 withdrawals
+    InvalidBatchSize(usize),
+    Signer(SignerError),
+}
+
+impl fmt::Display for WithdrawalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WithdrawalError::Invalid { line, reason } => write!(f, "Invalid withdrawal on line {}: {}", line, reason),
+            WithdrawalError::DuplicateAddress { line, first } => {
+                write!(
+                    f,
+                    "The withdrawal on line {} pays the same address as line {}",
+                    line, first
+                )
+            },
+            WithdrawalError::Empty => write!(f, "There are no withdrawals to process"),
+            WithdrawalError::InvalidBatchSize(size) => {
+                write!(
+                    f,
+                    "A batch of {} withdrawals is not possible, it has to hold 1 to 255",
+                    size
+                )
+            },
+            WithdrawalError::Signer(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for WithdrawalError {}
+
+impl From<SignerError> for WithdrawalError {
+    fn from(e: SignerError) -> Self {
+        WithdrawalError::Signer(e)
+    }
+}
+
+#[derive(Debug)]
+pub enum SweepError {
+    /// The export is not JSON or lacks a field
+    Parse(String),
+    UnsupportedVersion(u64),
+    /// The target address is for another network than the profile
+    WrongNetwork {
+        address: Network,
+        profile: Network,
+    },
+    /// No output the device can spend is worth more than the dust threshold
+    NothingToSweep,
+    Signer(SignerError),
+}
+
+impl fmt::Display for SweepError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SweepError::Parse(e) => write!(f, "Invalid output export: {}", e),
+            SweepError::UnsupportedVersion(version) => write!(f, "Unsupported output export version {}", version),
+            SweepError::WrongNetwork { address, profile } => {
+                write!(f, "The address is for {} but the profile is for {}", address, profile)
+            },
+            SweepError::NothingToSweep => write!(f, "There are no outputs above the dust threshold to sweep"),
+            SweepError::Signer(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for SweepError {}
+
+impl From<SignerError> for SweepError {
+    fn from(e: SignerError) -> Self {
+        SweepError::Signer(e)
+    }
+}
+
+#[derive(Debug)]
+pub enum DenominationError {
+    /// An output can be split into 2 to 255 parts
+    InvalidParts(usize),
+    /// The device does not reproduce the commitment of the output, it belongs to another seed
+    NotSpendable(String),
+    /// Consolidating needs at least two outputs
+    NothingToConsolidate,
+    Signer(SignerError),
+}
+
+impl fmt::Display for DenominationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DenominationError::InvalidParts(parts) => {
+                write!(f, "An output cannot be split into {} parts, only 2 to 255", parts)
+            },
+            DenominationError::NotSpendable(commitment) => {
+                write!(f, "The device cannot spend the output with commitment {}", commitment)
+            },
+            DenominationError::NothingToConsolidate => write!(f, "Consolidating needs at least two outputs"),
+            DenominationError::Signer(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for DenominationError {}
+
+impl From<SignerError> for DenominationError {
+    fn from(e: SignerError) -> Self {
+        DenominationError::Signer(e)
+    }
+}
+
+#[derive(Debug)]
+pub enum MigrationError {
+    /// Outputs cannot be migrated to the scheme they are already derived under
+    SameVersion(DerivationVersion),
+    /// A signer derives keys under another scheme than the one its part of the migration is under
+    WrongSignerVersion {
+        expected: DerivationVersion,
+        found: DerivationVersion,
+    },
+    /// No output under the old scheme is worth more than the dust threshold
+    NothingToMigrate,
+    Signer(SignerError),
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MigrationError::SameVersion(version) => {
+                write!(f, "The outputs are already derived under the {} scheme", version)
+            },
+            MigrationError::WrongSignerVersion { expected, found } => {
+                write!(f, "The signer derives {} keys, not {} keys", found, expected)
+            },
+            MigrationError::NothingToMigrate => write!(f, "There are no outputs above the dust threshold to migrate"),
+            MigrationError::Signer(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+impl From<SignerError> for MigrationError {
+    fn from(e: SignerError) -> Self {
+        MigrationError::Signer(e)
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(Debug)]
+pub enum RecoveryError {
+    /// The chain data is not JSON or lacks a field
+    Parse(String),
+    UnsupportedVersion(u64),
+    /// A scan has to look at least one index past the last one used
+    InvalidGapLimit,
+    Io(std::io::Error),
+    Device(DeviceError),
+}
+
+#[cfg(feature = "serde")]
+impl fmt::Display for RecoveryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecoveryError::Parse(e) => write!(f, "Invalid chain data: {}", e),
+            RecoveryError::UnsupportedVersion(version) => write!(f, "Unsupported chain data version {}", version),
+            RecoveryError::InvalidGapLimit => write!(f, "The gap limit has to be at least 1"),
+            RecoveryError::Io(e) => write!(f, "Could not read the chain data: {}", e),
+            RecoveryError::Device(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl std::error::Error for RecoveryError {}
+
+#[cfg(feature = "serde")]
+impl From<DeviceError> for RecoveryError {
+    fn from(e: DeviceError) -> Self {
+        RecoveryError::Device(e)
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(Debug)]
+pub enum SimulationError {
+    /// The description is not JSON or TOML, or lacks a field
+    Parse(String),
+    /// Output `index` of the description cannot be built
+    InvalidOutput {
+        index: usize,
+        reason: String,
+    },
+    Signer(SignerError),
+}
+
+#[cfg(feature = "serde")]
+impl fmt::Display for SimulationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SimulationError::Parse(e) => write!(f, "Invalid transaction description: {}", e),
+            SimulationError::InvalidOutput { index, reason } => write!(f, "Output {}: {}", index, reason),
+            SimulationError::Signer(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl std::error::Error for SimulationError {}
+
+#[cfg(feature = "serde")]
+impl From<SignerError> for SimulationError {
+    fn from(e: SignerError) -> Self {
+        SimulationError::Signer(e)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<DeviceError> for SimulationError {
+    fn from(e: DeviceError) -> Self {
+        SimulationError::Signer(SignerError::Device(e))
+    }
+}
+
+#[cfg(feature = "cbor")]
+#[derive(Debug)]
+pub enum CborError {
+    /// The bytes are not CBOR, or do not hold the fields of the document
+    Parse(String),
+    /// The document does not start with the self-describe tag and a document kind tag
+    MissingTag,
+    /// The document is of another kind than the one being read
+    WrongKind { expected: u64, found: u64 },
+}
+
+#[cfg(feature = "cbor")]
+impl fmt::Display for CborError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CborError::Parse(e) => write!(f, "Invalid CBOR document: {}", e),
+            CborError::MissingTag => write!(f, "The CBOR document does not say what kind of document it is"),
+            CborError::WrongKind { expected, found } => write!(
+                f,
+                "The CBOR document is of kind {:#x}, expected a document of kind {:#x}",
+                found, expected
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "cbor")]
+impl std::error::Error for CborError {}
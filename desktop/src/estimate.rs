@@ -0,0 +1,192 @@
+//! Estimates of a transaction before the device is involved
+//! A wallet knows what it is about to spend and create before it asks the user to connect and unlock the device.
+//! [`estimate_transaction`] gives it the weight and fee of that transaction and the number of prompts the user will
+//! have to approve on the device, so that it can warn about a high fee or a long confirmation before the hardware flow
+//! starts.
+
+use std::fmt;
+
+use crate::{
+    device::Capabilities,
+    display::MAX_OUTPUTS_WITHOUT_HINTS,
+    fee::FeeCalculator,
+    signer::{change_output_size, OutputToSign},
+    units::AmountFormat,
+};
+
+/// An output the wallet plans to create
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PlannedOutput {
+    pub is_change: bool,
+    /// Serialized size of the output features and script
+    pub features_and_scripts_size: usize,
+}
+
+impl PlannedOutput {
+    /// A change output as the signer builds it
+    pub fn change() -> Self {
+        Self {
+            is_change: true,
+            features_and_scripts_size: change_output_size(),
+        }
+    }
+}
+
+impl From<&OutputToSign> for PlannedOutput {
+    fn from(output: &OutputToSign) -> Self {
+        Self {
+            is_change: output.is_change,
+            features_and_scripts_size: output.features_and_scripts_size,
+        }
+    }
+}
+
+/// What a planned transaction weighs, costs and asks of the user
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TransactionEstimate {
+    /// Weight in grams
+    pub weight: u64,
+    /// Fee in microTari
+    pub fee: u64,
+    pub fee_per_gram: u64,
+    /// The prompts the user has to approve: the summary, then every recipient output if the app confirms outputs
+    pub confirmations: usize,
+    /// Whether the device shows a fingerprint of the outputs to compare with the one the host prints
+    pub display_hints: bool,
+}
+
+impl TransactionEstimate {
+    /// The estimate for the user, with the fee in `format`. The fee per gram stays in microTari, a fraction of any
+    /// larger unit.
+    pub fn to_text(&self, format: &AmountFormat) -> String {
+        let mut text = format!(
+            "weight {} g, fee {} at {} µT/g, {} confirmation(s) on the device",
+            self.weight,
+            format.format(self.fee),
+            self.fee_per_gram,
+            self.confirmations
+        );
+        if self.display_hints {
+            text.push_str(", with a fingerprint to compare");
+        }
+        text
+    }
+}
+
+impl fmt::Display for TransactionEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_text(&AmountFormat::default()))
+    }
+}
+
+/// Estimate a transaction spending `num_inputs` inputs into `outputs`, signed by an app with `capabilities`
+pub fn estimate_transaction(
+    fee_calculator: &FeeCalculator,
+    num_inputs: usize,
+    outputs: &[PlannedOutput],
+    capabilities: Capabilities,
+) -> TransactionEstimate {
+    let sizes = outputs
+        .iter()
+        .map(|output| output.features_and_scripts_size)
+        .collect::<Vec<_>>();
+    let recipients = outputs.iter().filter(|output| !output.is_change).count();
+    let confirmations = if capabilities.contains(Capabilities::OUTPUT_CONFIRMATION) {
+        1 + recipients
+    } else {
+        1
+    };
+    TransactionEstimate {
+        weight: fee_calculator.weight(1, num_inputs, &sizes),
+        fee: fee_calculator.calculate(1, num_inputs, &sizes),
+        fee_per_gram: fee_calculator.fee_per_gram(),
+        confirmations,
+        display_hints: outputs.len() > MAX_OUTPUTS_WITHOUT_HINTS && capabilities.contains(Capabilities::DISPLAY_HINTS),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        fee::DEFAULT_FEE_PER_GRAM,
+        units::{Separators, Unit},
+    };
+
+    fn payment(features_and_scripts_size: usize) -> PlannedOutput {
+        PlannedOutput {
+            is_change: false,
+            features_and_scripts_size,
+        }
+    }
+
+    #[test]
+    fn the_estimate_weighs_one_kernel() {
+        let calculator = FeeCalculator::new(DEFAULT_FEE_PER_GRAM);
+        let outputs = [payment(40), PlannedOutput::change()];
+        let estimate = estimate_transaction(&calculator, 2, &outputs, Capabilities::empty());
+        let sizes = [40, change_output_size()];
+        assert_eq!(estimate.weight, calculator.weight(1, 2, &sizes));
+        assert_eq!(estimate.fee, calculator.calculate(1, 2, &sizes));
+        assert_eq!(estimate.fee, estimate.weight * DEFAULT_FEE_PER_GRAM);
+        assert_eq!(estimate.fee_per_gram, DEFAULT_FEE_PER_GRAM);
+    }
+
+    #[test]
+    fn only_recipients_are_confirmed() {
+        let calculator = FeeCalculator::new(DEFAULT_FEE_PER_GRAM);
+        let outputs = [payment(0), payment(0), PlannedOutput::change()];
+        let estimate = estimate_transaction(&calculator, 1, &outputs, Capabilities::OUTPUT_CONFIRMATION);
+        assert_eq!(estimate.confirmations, 3);
+        // Without output confirmation the summary is the only prompt
+        let estimate = estimate_transaction(&calculator, 1, &outputs, Capabilities::empty());
+        assert_eq!(estimate.confirmations, 1);
+        let estimate = estimate_transaction(
+            &calculator,
+            1,
+            &[PlannedOutput::change()],
+            Capabilities::OUTPUT_CONFIRMATION,
+        );
+        assert_eq!(estimate.confirmations, 1);
+    }
+
+    #[test]
+    fn hints_need_the_capability_and_enough_outputs() {
+        let calculator = FeeCalculator::new(DEFAULT_FEE_PER_GRAM);
+        let few = [payment(0); MAX_OUTPUTS_WITHOUT_HINTS];
+        let many = [payment(0); MAX_OUTPUTS_WITHOUT_HINTS + 1];
+        assert!(!estimate_transaction(&calculator, 1, &few, Capabilities::DISPLAY_HINTS).display_hints);
+        assert!(estimate_transaction(&calculator, 1, &many, Capabilities::DISPLAY_HINTS).display_hints);
+        assert!(!estimate_transaction(&calculator, 1, &many, Capabilities::empty()).display_hints);
+    }
+
+    #[test]
+    fn the_text_gives_the_fee_in_the_format() {
+        let calculator = FeeCalculator::new(DEFAULT_FEE_PER_GRAM);
+        let estimate = estimate_transaction(&calculator, 1, &[payment(0)], Capabilities::OUTPUT_CONFIRMATION);
+        assert_eq!(
+            estimate.to_string(),
+            "weight 71 g, fee 355 µT at 5 µT/g, 2 confirmation(s) on the device"
+        );
+        assert_eq!(
+            estimate.to_text(&AmountFormat::new(Unit::Tari, Separators::PLAIN)),
+            "weight 71 g, fee 0.000355 T at 5 µT/g, 2 confirmation(s) on the device"
+        );
+
+        let many = [payment(0); MAX_OUTPUTS_WITHOUT_HINTS + 1];
+        let estimate = estimate_transaction(&calculator, 1, &many, Capabilities::DISPLAY_HINTS);
+        assert!(estimate.to_string().ends_with(", with a fingerprint to compare"));
+    }
+
+    #[test]
+    fn an_unpayable_fee_saturates() {
+        let estimate = estimate_transaction(
+            &FeeCalculator::new(u64::MAX),
+            usize::MAX,
+            &[payment(usize::MAX)],
+            Capabilities::empty(),
+        );
+        assert_eq!(estimate.weight, u64::MAX);
+        assert_eq!(estimate.fee, u64::MAX);
+    }
+}
@@ -0,0 +1,223 @@
+//! Bulk export of account public keys
+//! Exchanges pre-generate deposit addresses from a range of key indices. The keys are fetched from the device in
+//! batches of [`MAX_PUBLIC_KEYS_PER_REQUEST`], pipelined over transports that allow it, and written to a JSON or CSV
+//! manifest that records the path of every key.
+//! Every key belongs to one of the [`KeyBranch`]es of the Tari wallet key manager.
+//!
+//! A single key can also be fetched [`blinded`](crate::blinding), so that the link shows neither its path nor the key.
+//!
+//! The [`SensitiveKey`]s are the only private keys the app exports, and only inside an [`envelope`](crate::envelope).
+
+use std::{fmt, ops::Range};
+
+use tari_crypto::{
+    keys::PublicKey,
+    ristretto::{RistrettoPublicKey, RistrettoSecretKey},
+    tari_utilities::{hex::Hex, ByteArray},
+};
+pub use tari_ledger_protocol::SensitiveKey;
+use tari_ledger_protocol::{
+    public_keys_response_length,
+    EXPORT_PRIVATE_KEY_RESPONSE_LENGTH,
+    GET_BLINDED_PUBLIC_KEY_RESPONSE_LENGTH,
+    MAX_PUBLIC_KEYS_PER_REQUEST,
+};
+
+use crate::{
+    blinding::BlindedKeyRequest,
+    device::{Capabilities, DerivationVersion, Instruction, KeyBranch, LedgerDevice},
+    envelope::open_envelope,
+    errors::DeviceError,
+    redact::short_hex,
+};
+
+#[derive(Clone, Debug)]
+pub struct ExportedKey {
+    pub index: u32,
+    pub public_key: RistrettoPublicKey,
+}
+
+impl fmt::Display for ExportedKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.index, short_hex(self.public_key.as_bytes()))
+    }
+}
+
+/// The public keys of one branch of an account
+#[derive(Clone, Debug)]
+pub struct KeyExport {
+    pub account: u32,
+    pub branch: KeyBranch,
+    pub keys: Vec<ExportedKey>,
+}
+
+impl KeyExport {
+    /// The manifest as `index,path,public_key` rows under a header
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("index,path,public_key\n");
+        for key in &self.keys {
+            csv.push_str(&format!(
+                "{},{},{}\n",
+                key.index,
+                key_path(self.account, self.branch, key.index),
+                key.public_key.to_hex()
+            ));
+        }
+        csv
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> String {
+        let keys = self
+            .keys
+            .iter()
+            .map(|key| {
+                serde_json::json!({
+                    "index": key.index,
+                    "path": key_path(self.account, self.branch, key.index),
+                    "public_key": key.public_key.to_hex(),
+                })
+            })
+            .collect::<Vec<_>>();
+        let manifest = serde_json::json!({
+            "account": self.account,
+            "branch": self.branch.name(),
+            "keys": keys,
+        });
+        serde_json::to_string_pretty(&manifest).expect("a JSON value always serializes")
+    }
+}
+
+/// The derivation path of `branch` of `account`, the parent of all of its keys
+pub fn branch_path(account: u32, branch: KeyBranch) -> String {
+    format!("m/44'/535348'/{}'/{}", account, branch.as_byte())
+}
+
+/// The derivation path of key `index` in `branch` of `account`
+pub fn key_path(account: u32, branch: KeyBranch, index: u32) -> String {
+    format!("{}/{}", branch_path(account, branch), index)
+}
+
+/// The public key at `index` in `branch` of `account`
+pub fn public_key(
+    device: &LedgerDevice,
+    account: u32,
+    branch: KeyBranch,
+    index: u32,
+) -> Result<RistrettoPublicKey, DeviceError> {
+    public_key_with_version(device, account, branch, index, DerivationVersion::Legacy)
+}
+
+/// The public key at `index` in `branch` of `account`, derived under `version`
+pub fn public_key_with_version(
+    device: &LedgerDevice,
+    account: u32,
+    branch: KeyBranch,
+    index: u32,
+    version: DerivationVersion,
+) -> Result<RistrettoPublicKey, DeviceError> {
+    let indices = index..index.saturating_add(1);
+    let export = export_public_keys_with_version(device, account, branch, indices, version, |_, _| {})?;
+    export
+        .keys
+        .into_iter()
+        .next()
+        .map(|key| key.public_key)
+        .ok_or(DeviceError::InvalidResponse("the device returned no public key"))
+}
+
+/// The public key at `index` in `branch` of `account`, requested blinded so that only the host learns the path and the
+/// key. `app_public_key` is the app key of the device, `host_secret` has to be a fresh random key.
+pub fn blinded_public_key(
+    device: &LedgerDevice,
+    app_public_key: &RistrettoPublicKey,
+    account: u32,
+    branch: KeyBranch,
+    index: u32,
+    host_secret: &RistrettoSecretKey,
+) -> Result<RistrettoPublicKey, DeviceError> {
+    device.require(Capabilities::BLINDED_KEYS)?;
+    let request = BlindedKeyRequest::new(host_secret, app_public_key, account, branch, index);
+    let response = device.send(Instruction::GetBlindedPublicKey, 0x00, 0x00, request.to_request())?;
+    let payload = device.response_payload(&response, GET_BLINDED_PUBLIC_KEY_RESPONSE_LENGTH)?;
+    request.unblind(payload)
+}
+
+/// Fetch the public keys at `indices` in `branch` of `account`. `progress` is called after every batch with the
+/// number of keys fetched so far and the total.
+pub fn export_public_keys<P: FnMut(usize, usize)>(
+    device: &LedgerDevice,
+    account: u32,
+    branch: KeyBranch,
+    indices: Range<u32>,
+    progress: P,
+) -> Result<KeyExport, DeviceError> {
+    export_public_keys_with_version(device, account, branch, indices, DerivationVersion::Legacy, progress)
+}
+
+/// Fetch the public keys at `indices` in `branch` of `account`, derived under `version`
+pub fn export_public_keys_with_version<P: FnMut(usize, usize)>(
+    device: &LedgerDevice,
+    account: u32,
+    branch: KeyBranch,
+    indices: Range<u32>,
+    version: DerivationVersion,
+    mut progress: P,
+) -> Result<KeyExport, DeviceError> {
+    device.require(Capabilities::PUBLIC_KEY_EXPORT)?;
+    let p2 = device.derivation_p2(version)?;
+    let total = indices.len();
+    let mut keys = Vec::with_capacity(total);
+    let batches = indices
+        .clone()
+        .step_by(usize::from(MAX_PUBLIC_KEYS_PER_REQUEST))
+        // Cannot truncate, the batch is capped to MAX_PUBLIC_KEYS_PER_REQUEST
+        .map(|index| (index, (indices.end - index).min(u32::from(MAX_PUBLIC_KEYS_PER_REQUEST)) as u8))
+        .collect::<Vec<_>>();
+    for window in batches.chunks(device.pipeline_depth()) {
+        let commands = window
+            .iter()
+            .map(|(index, count)| {
+                let mut data = account.to_le_bytes().to_vec();
+                data.extend_from_slice(&index.to_le_bytes());
+                data.push(*count);
+                (branch.as_byte(), p2, data)
+            })
+            .collect();
+        let responses = device.send_pipelined(Instruction::GetPublicKeys, commands)?;
+        for ((index, count), response) in window.iter().zip(responses) {
+            let payload = device.response_payload(&response, public_keys_response_length(*count))?;
+            for (offset, bytes) in payload.chunks(32).enumerate() {
+                let public_key = RistrettoPublicKey::from_bytes(bytes)
+                    .map_err(|_| DeviceError::InvalidResponse("the device returned an invalid public key"))?;
+                keys.push(ExportedKey {
+                    index: index + offset as u32,
+                    public_key,
+                });
+            }
+        }
+        progress(keys.len(), total);
+    }
+    Ok(KeyExport { account, branch, keys })
+}
+
+/// The derivation path of `key` of `account`
+pub fn sensitive_key_path(account: u32, key: SensitiveKey) -> String {
+    format!("m/44'/535348'/{}'/{}/0", account, key.as_byte())
+}
+
+/// Export `key` of `account`, which the user has to confirm on the device. `host_secret` has to be a fresh random
+/// key, the app encrypts the exported key to its public key.
+pub fn export_private_key(
+    device: &LedgerDevice,
+    account: u32,
+    key: SensitiveKey,
+    host_secret: &RistrettoSecretKey,
+) -> Result<RistrettoSecretKey, DeviceError> {
+    device.require(Capabilities::ENCRYPTED_KEY_EXPORT)?;
+    let mut data = account.to_le_bytes().to_vec();
+    data.extend_from_slice(RistrettoPublicKey::from_secret_key(host_secret).as_bytes());
+    let response = device.send(Instruction::ExportPrivateKey, key.as_byte(), 0x00, data)?;
+    let payload = device.response_payload(&response, EXPORT_PRIVATE_KEY_RESPONSE_LENGTH)?;
+    open_envelope(host_secret, payload)
+}
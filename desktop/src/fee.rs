@@ -0,0 +1,158 @@
+//! Transaction fees, following the base layer weight rules
+//! The fee is `weight * fee_per_gram`, where the weight counts kernels, inputs and outputs, plus the size of each
+//! output's features and script rounded up to a whole number of grams.
+
+/// The base layer rejects transactions paying less than this, in microTari
+pub const MINIMUM_TRANSACTION_FEE: u64 = 101;
+/// The fee per gram the console wallet uses unless told otherwise, in microTari
+pub const DEFAULT_FEE_PER_GRAM: u64 = 5;
+
+/// Per-component weights in grams
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WeightParams {
+    pub kernel_weight: u64,
+    pub input_weight: u64,
+    /// Weight of an output, excluding its features and script
+    pub output_weight: u64,
+    pub features_and_scripts_bytes_per_gram: u64,
+}
+
+impl WeightParams {
+    /// The weights currently used by the base layer
+    pub const V1: Self = Self {
+        kernel_weight: 10,
+        input_weight: 8,
+        output_weight: 53,
+        features_and_scripts_bytes_per_gram: 16,
+    };
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct FeeCalculator {
+    params: WeightParams,
+    fee_per_gram: u64,
+}
+
+impl FeeCalculator {
+    pub fn new(fee_per_gram: u64) -> Self {
+        Self::with_params(WeightParams::V1, fee_per_gram)
+    }
+
+    pub fn with_params(params: WeightParams, fee_per_gram: u64) -> Self {
+        Self { params, fee_per_gram }
+    }
+
+    pub fn fee_per_gram(&self) -> u64 {
+        self.fee_per_gram
+    }
+
+    /// The weight in grams of a transaction with `num_kernels` kernels, `num_inputs` inputs and one output for each
+    /// entry of `features_and_scripts_sizes`, the serialized size of that output's features and script
+    pub fn weight(&self, num_kernels: usize, num_inputs: usize, features_and_scripts_sizes: &[usize]) -> u64 {
+        // Every output is rounded up on its own, rounding the total instead would not match the base layer
+        let features_and_scripts_size = features_and_scripts_sizes
+            .iter()
+            .map(|size| self.round_up_features_and_scripts_size(*size as u64))
+            .fold(0u64, u64::saturating_add);
+        // A weight that does not fit saturates, it is too heavy for any block either way
+        [
+            self.params.kernel_weight.saturating_mul(num_kernels as u64),
+            self.params.input_weight.saturating_mul(num_inputs as u64),
+            self.params
+                .output_weight
+                .saturating_mul(features_and_scripts_sizes.len() as u64),
+            features_and_scripts_size / self.params.features_and_scripts_bytes_per_gram,
+        ]
+        .into_iter()
+        .fold(0, u64::saturating_add)
+    }
+
+    /// The fee in microTari for the transaction described in [`FeeCalculator::weight`], never less than
+    /// [`MINIMUM_TRANSACTION_FEE`]
+    pub fn calculate(&self, num_kernels: usize, num_inputs: usize, features_and_scripts_sizes: &[usize]) -> u64 {
+        let weight = self.weight(num_kernels, num_inputs, features_and_scripts_sizes);
+        weight.saturating_mul(self.fee_per_gram).max(MINIMUM_TRANSACTION_FEE)
+    }
+
+    fn round_up_features_and_scripts_size(&self, size: u64) -> u64 {
+        let per_gram = self.params.features_and_scripts_bytes_per_gram;
+        match size % per_gram {
+            0 => size,
+            rem => size.saturating_add(per_gram - rem),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn the_weight_counts_every_component() {
+        let calculator = FeeCalculator::new(DEFAULT_FEE_PER_GRAM);
+        assert_eq!(calculator.weight(0, 0, &[]), 0);
+        assert_eq!(calculator.weight(1, 0, &[]), 10);
+        assert_eq!(calculator.weight(0, 3, &[]), 24);
+        // 53 per output, exact multiples of 16 bytes are not rounded
+        assert_eq!(calculator.weight(0, 0, &[0, 16, 32]), 3 * 53 + 3);
+        assert_eq!(calculator.weight(1, 2, &[16]), 10 + 16 + 53 + 1);
+    }
+
+    #[test]
+    fn every_output_is_rounded_up_on_its_own() {
+        let calculator = FeeCalculator::new(DEFAULT_FEE_PER_GRAM);
+        assert_eq!(calculator.weight(0, 0, &[1]), 53 + 1);
+        assert_eq!(calculator.weight(0, 0, &[17]), 53 + 2);
+        // Rounding the total of 2 bytes would only add a single gram
+        assert_eq!(calculator.weight(0, 0, &[1, 1]), 2 * 53 + 2);
+    }
+
+    #[test]
+    fn the_fee_is_the_weight_at_the_fee_per_gram() {
+        let calculator = FeeCalculator::new(DEFAULT_FEE_PER_GRAM);
+        let weight = calculator.weight(1, 2, &[40, 40]);
+        assert_eq!(weight, 10 + 16 + 106 + 6);
+        assert_eq!(calculator.calculate(1, 2, &[40, 40]), weight * DEFAULT_FEE_PER_GRAM);
+        assert_eq!(calculator.fee_per_gram(), DEFAULT_FEE_PER_GRAM);
+
+        let params = WeightParams {
+            kernel_weight: 1,
+            input_weight: 2,
+            output_weight: 3,
+            features_and_scripts_bytes_per_gram: 4,
+        };
+        assert_eq!(
+            FeeCalculator::with_params(params, 100).calculate(1, 1, &[5]),
+            (1 + 2 + 3 + 2) * 100
+        );
+    }
+
+    #[test]
+    fn the_fee_is_never_below_the_minimum() {
+        assert_eq!(FeeCalculator::new(0).calculate(1, 1, &[0]), MINIMUM_TRANSACTION_FEE);
+        assert_eq!(FeeCalculator::new(1).calculate(1, 0, &[]), MINIMUM_TRANSACTION_FEE);
+        // 71 grams at 1 µT/g is below the minimum, at 2 µT/g it is not
+        assert_eq!(FeeCalculator::new(1).weight(1, 1, &[0]), 71);
+        assert_eq!(FeeCalculator::new(2).calculate(1, 1, &[0]), 142);
+    }
+
+    #[test]
+    fn large_transactions_saturate() {
+        assert_eq!(FeeCalculator::new(u64::MAX).calculate(1, 1, &[0]), u64::MAX);
+
+        let calculator = FeeCalculator::new(1);
+        assert_eq!(calculator.weight(0, 0, &[usize::MAX]), 53 + u64::MAX / 16);
+        assert_eq!(
+            calculator.weight(0, 0, &[usize::MAX, usize::MAX]),
+            2 * 53 + u64::MAX / 16
+        );
+
+        let heavy = WeightParams {
+            kernel_weight: u64::MAX,
+            ..WeightParams::V1
+        };
+        let calculator = FeeCalculator::with_params(heavy, 1);
+        assert_eq!(calculator.weight(2, 1, &[0]), u64::MAX);
+        assert_eq!(calculator.calculate(2, 1, &[0]), u64::MAX);
+    }
+}
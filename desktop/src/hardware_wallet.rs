@@ -0,0 +1,190 @@
+//! What a signing device does for a wallet, apart from the device
+//! [`LedgerDevice`] speaks the APDUs of the Tari Ledger app, and the functions of this crate that build on it take a
+//! `&LedgerDevice`. Wallet code that only needs keys, commitments and signatures can be written against
+//! [`HardwareWallet`] instead, which captures those operations without the transport, status words or capability
+//! bits of any one device. A backend for another device implements the trait in its own module, behind its own cargo
+//! feature, and the wallet code keeps working unchanged.
+//!
+//! Keys are named as this crate names them, by account, [`KeyBranch`] and index, derived under a
+//! [`DerivationVersion`]. A backend that derives some branch or version differently has to refuse it rather than
+//! return another key.
+
+use std::{error::Error, ops::Range};
+
+use tari_crypto::ristretto::{pedersen::PedersenCommitment, RistrettoPublicKey, RistrettoSecretKey};
+
+use crate::{
+    birthday::wallet_birthday,
+    commitment::{batch_commitments_with_version, CommitmentRequest},
+    device::{DerivationVersion, KeyBranch, LedgerDevice},
+    errors::SignerError,
+    export::export_public_keys_with_version,
+    fee::FeeCalculator,
+    hashing::Challenge,
+    kernel::{kernel_nonce, sign_kernel, KernelShare},
+    sender_offset::{sign_sender_offset, SenderOffsetSignature},
+    signature::LedgerSignature,
+    signer::LedgerTransactionSigner,
+    wallet::wallet_fingerprint,
+};
+
+/// The operations of a signing device a wallet builds on
+pub trait HardwareWallet {
+    type Error: Error + 'static;
+
+    /// The device and model, for messages to the user
+    fn name(&self) -> String;
+
+    /// An identifier of the seed the device is unlocked with that reveals no key, see
+    /// [`wallet_fingerprint`](crate::wallet::wallet_fingerprint)
+    fn fingerprint(&self) -> Result<String, Self::Error>;
+
+    /// The public keys at `indices` in `branch` of `account`, in order
+    fn public_keys(
+        &self,
+        account: u32,
+        branch: KeyBranch,
+        indices: Range<u32>,
+        version: DerivationVersion,
+    ) -> Result<Vec<RistrettoPublicKey>, Self::Error>;
+
+    /// Commit to every request, returning the commitments in the same order
+    fn commitments(
+        &self,
+        requests: &[CommitmentRequest],
+        version: DerivationVersion,
+    ) -> Result<Vec<PedersenCommitment>, Self::Error>;
+
+    /// The public excess of the masks at `created` less those at `spent`, and a nonce to sign the kernel with `fee`
+    /// and `lock_height` with, see [`kernel_nonce`](crate::kernel::kernel_nonce)
+    fn kernel_share(
+        &self,
+        spent: &[u32],
+        created: &[u32],
+        fee: u64,
+        lock_height: u64,
+        version: DerivationVersion,
+    ) -> Result<KernelShare, Self::Error>;
+
+    /// The partial kernel signature with the last `share`, under the sums of the nonces and excesses of every party
+    fn sign_kernel(
+        &self,
+        share: &KernelShare,
+        total_nonce: &RistrettoPublicKey,
+        total_excess: &RistrettoPublicKey,
+        message: &[u8; 32],
+    ) -> Result<RistrettoSecretKey, Self::Error>;
+
+    /// The sender half of the metadata signature of the output with `commitment`, with the sender offset key at
+    /// `index` of `account`
+    fn sign_sender_offset(
+        &self,
+        account: u32,
+        index: u32,
+        commitment: &PedersenCommitment,
+        ephemeral_commitment: &PedersenCommitment,
+        message: &[u8; 32],
+        version: DerivationVersion,
+    ) -> Result<SenderOffsetSignature, Self::Error>;
+
+    /// A script signature over a standalone message, see
+    /// [`LedgerTransactionSigner::sign_script_message`](crate::signer::LedgerTransactionSigner::sign_script_message)
+    fn sign_script_message(
+        &self,
+        message: &Challenge,
+        version: DerivationVersion,
+    ) -> Result<LedgerSignature, Self::Error>;
+
+    /// The block height the wallet was created at, if the device recorded one
+    fn birthday(&self) -> Result<Option<u64>, Self::Error>;
+}
+
+impl HardwareWallet for LedgerDevice {
+    type Error = SignerError;
+
+    fn name(&self) -> String {
+        match self.model() {
+            Some(model) => format!("Ledger {}", model),
+            None => "Ledger".to_string(),
+        }
+    }
+
+    fn fingerprint(&self) -> Result<String, Self::Error> {
+        Ok(wallet_fingerprint(self)?)
+    }
+
+    fn public_keys(
+        &self,
+        account: u32,
+        branch: KeyBranch,
+        indices: Range<u32>,
+        version: DerivationVersion,
+    ) -> Result<Vec<RistrettoPublicKey>, Self::Error> {
+        let export = export_public_keys_with_version(self, account, branch, indices, version, |_, _| {})?;
+        Ok(export.keys.into_iter().map(|key| key.public_key).collect())
+    }
+
+    fn commitments(
+        &self,
+        requests: &[CommitmentRequest],
+        version: DerivationVersion,
+    ) -> Result<Vec<PedersenCommitment>, Self::Error> {
+        Ok(batch_commitments_with_version(self, requests, version)?)
+    }
+
+    fn kernel_share(
+        &self,
+        spent: &[u32],
+        created: &[u32],
+        fee: u64,
+        lock_height: u64,
+        version: DerivationVersion,
+    ) -> Result<KernelShare, Self::Error> {
+        Ok(kernel_nonce(self, spent, created, fee, lock_height, version)?)
+    }
+
+    fn sign_kernel(
+        &self,
+        share: &KernelShare,
+        total_nonce: &RistrettoPublicKey,
+        total_excess: &RistrettoPublicKey,
+        message: &[u8; 32],
+    ) -> Result<RistrettoSecretKey, Self::Error> {
+        Ok(sign_kernel(self, share, total_nonce, total_excess, message)?)
+    }
+
+    fn sign_sender_offset(
+        &self,
+        account: u32,
+        index: u32,
+        commitment: &PedersenCommitment,
+        ephemeral_commitment: &PedersenCommitment,
+        message: &[u8; 32],
+        version: DerivationVersion,
+    ) -> Result<SenderOffsetSignature, Self::Error> {
+        Ok(sign_sender_offset(
+            self,
+            account,
+            index,
+            commitment,
+            ephemeral_commitment,
+            message,
+            version,
+        )?)
+    }
+
+    fn sign_script_message(
+        &self,
+        message: &Challenge,
+        version: DerivationVersion,
+    ) -> Result<LedgerSignature, Self::Error> {
+        // A standalone message pays no fee
+        LedgerTransactionSigner::new(self, FeeCalculator::new(0))
+            .with_derivation_version(version)
+            .sign_script_message(message)
+    }
+
+    fn birthday(&self) -> Result<Option<u64>, Self::Error> {
+        Ok(wallet_birthday(self)?)
+    }
+}
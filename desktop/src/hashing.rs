@@ -0,0 +1,704 @@
+//! Consensus encoding and domain separated hashing, matching the way the base layer builds challenges
+//! Every field is Borsh encoded, as in tari-core. The `chain_*` helpers spell out the encoding of the fields whose
+//! shape a plain [`ConsensusHasher::chain`] leaves implicit, so that a challenge reads like its definition in the node.
+//! What the device signs is a [`Challenge`], which only a hasher can produce and which carries the label it was hashed
+//! under, so that bytes hashed for one purpose are never signed for another.
+//!
+//! Challenges, keys and MACs are all 32 bytes. A hasher over a digest of another length says how it is cut down to
+//! them with a [`Truncation`] policy, so that moving a label to a wider hash changes where its hasher is made and
+//! nothing that calls [`ConsensusHasher::finalize`].
+//!
+//! A hasher made with [`DomainSeparatedConsensusHasher::traced`] also keeps every item chained into it with its
+//! encoding, its [`Preimage`]. When a signature fails to verify, the preimage of the challenge the host hashed is what
+//! tells an encoding mismatch apart from a wrong key, and [`Preimage::diff`] lines it up with the preimage of the same
+//! challenge from another encoder. Preimages can hold secrets, so only the hashers of public challenges are traced.
+//!
+//! Variable length bytes too large to hold in memory, e.g. a coinbase extra or a sidechain blob, are fed to a
+//! [`ByteStream`] in chunks instead, or read with [`ConsensusHasher::chain_reader`] one upload chunk at a time. They
+//! hash exactly as [`ConsensusHasher::chain_bytes`] over the whole, which needs the length up front. A traced hasher
+//! still keeps every byte it streams.
+
+use core::{any::type_name, fmt, marker::PhantomData};
+use std::io::{self, Read};
+
+use borsh::{
+    maybestd::io::{Result as BorshResult, Write},
+    BorshSerialize,
+};
+use digest::{
+    consts::{U32, U64},
+    generic_array::{ArrayLength, GenericArray},
+    Digest,
+    Output,
+};
+use tari_crypto::{hash::blake2::Blake256, hashing::DomainSeparation, tari_utilities::hex::to_hex};
+use tari_ledger_protocol::MAX_CHUNK_LENGTH;
+
+pub use crate::domains::TransactionHashDomain;
+use crate::{domains::transaction_hash_labels, redact::short_hex};
+
+pub struct DomainSeparatedConsensusHasher<M>(PhantomData<M>);
+
+impl<M: DomainSeparation> DomainSeparatedConsensusHasher<M> {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(label: &'static str) -> ConsensusHasher<Blake256> {
+        Self::with_digest(label)
+    }
+
+    /// A hasher over the digest `D`, whose output the truncation policy `P` cuts down to 32 bytes
+    pub fn with_digest<D, P>(label: &'static str) -> ConsensusHasher<D, P>
+    where
+        D: Digest,
+        P: Truncation<D::OutputSize>,
+    {
+        let mut digest = D::new();
+        M::add_domain_separation_tag(&mut digest, label);
+        ConsensusHasher::from_digest(digest, label)
+    }
+
+    /// A hasher like [`DomainSeparatedConsensusHasher::new`] that keeps the [`Preimage`] of what it hashes
+    pub fn traced(label: &'static str) -> ConsensusHasher<Blake256> {
+        let mut hasher = Self::new(label);
+        hasher.trace = Some(Vec::new());
+        hasher
+    }
+}
+
+/// How the output of a digest is cut down to the 32 bytes of a hash
+pub trait Truncation<N: ArrayLength<u8>> {
+    fn truncate(digest: GenericArray<u8, N>) -> [u8; 32];
+}
+
+/// A 32-byte digest, taken as is
+#[derive(Clone, Copy, Debug)]
+pub struct Exact;
+
+impl Truncation<U32> for Exact {
+    fn truncate(digest: GenericArray<u8, U32>) -> [u8; 32] {
+        digest.into()
+    }
+}
+
+/// The first 32 bytes of a 64-byte digest. They differ from the digest of the same hash set up for 32 bytes of output,
+/// Blake2b for one mixes its output length into every byte.
+#[derive(Clone, Copy, Debug)]
+pub struct LeadingBytes;
+
+impl Truncation<U64> for LeadingBytes {
+    fn truncate(digest: GenericArray<u8, U64>) -> [u8; 32] {
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&digest[..32]);
+        hash
+    }
+}
+
+#[derive(Clone)]
+pub struct ConsensusHasher<D, P = Exact> {
+    writer: WriteHashWrapper<D>,
+    label: &'static str,
+    /// The items chained so far, if the hasher is traced
+    trace: Option<Vec<PreimageField>>,
+    truncation: PhantomData<P>,
+}
+
+impl<D: Digest, P> ConsensusHasher<D, P> {
+    fn from_digest(digest: D, label: &'static str) -> Self {
+        Self {
+            writer: WriteHashWrapper { digest, item: None },
+            label,
+            trace: None,
+            truncation: PhantomData,
+        }
+    }
+
+    /// What the hasher hashed so far after its domain separation tag, without any items unless it is
+    /// [`traced`](DomainSeparatedConsensusHasher::traced)
+    pub fn preimage(&self) -> Preimage {
+        Preimage {
+            label: self.label,
+            fields: self.trace.clone().unwrap_or_default(),
+        }
+    }
+
+    /// Write one item of type `item` with `encode`, keeping its bytes if the hasher is traced
+    fn record<F: FnOnce(&mut WriteHashWrapper<D>)>(&mut self, item: &'static str, encode: F) {
+        if self.trace.is_some() {
+            self.writer.item = Some(Vec::new());
+        }
+        encode(&mut self.writer);
+        if let (Some(trace), Some(bytes)) = (&mut self.trace, self.writer.item.take()) {
+            trace.push(PreimageField { item, bytes });
+        }
+    }
+}
+
+impl<D, P> ConsensusHasher<D, P>
+where
+    D: Digest,
+    P: Truncation<D::OutputSize>,
+{
+    pub fn finalize(self) -> [u8; 32] {
+        P::truncate(self.finalize_full())
+    }
+
+    /// The whole digest, for the hashes that are not challenges, keys or MACs and use all of it
+    pub fn finalize_full(self) -> Output<D> {
+        self.writer.digest.finalize()
+    }
+
+    /// The hash as a challenge to sign, for the purpose named by the label it was hashed under
+    pub fn finalize_challenge(self) -> Challenge {
+        Challenge {
+            purpose: self.label,
+            hash: self.finalize(),
+        }
+    }
+
+    pub fn update_consensus_encode<T: BorshSerialize>(&mut self, data: &T) {
+        self.record(type_name::<T>(), |writer| consensus_encode(data, writer));
+    }
+
+    pub fn chain<T: BorshSerialize>(mut self, data: &T) -> Self {
+        self.update_consensus_encode(data);
+        self
+    }
+
+    /// An optional field: `0x00` if absent, `0x01` followed by the value if present
+    pub fn chain_optional<T: BorshSerialize>(self, data: &Option<T>) -> Self {
+        self.chain(data)
+    }
+
+    /// A variable length field: the number of items as a little-endian `u32`, then every item
+    pub fn chain_vec<T: BorshSerialize>(mut self, items: &[T]) -> Self {
+        let length = u32::try_from(items.len()).expect("a hashed field never holds 2^32 items");
+        self.record(type_name::<Vec<T>>(), |writer| {
+            consensus_encode(&length, writer);
+            for item in items {
+                consensus_encode(item, writer);
+            }
+        });
+        self
+    }
+
+    /// Variable length bytes, e.g. a payment id: the length as a little-endian `u32`, then the bytes
+    pub fn chain_bytes(self, data: &[u8]) -> Self {
+        self.chain_vec(data)
+    }
+
+    /// A fixed-size field, as is and without a length, whatever its size
+    pub fn chain_fixed<const N: usize>(mut self, data: &[u8; N]) -> Self {
+        self.record(type_name::<[u8; N]>(), |writer| writer.update(data));
+        self
+    }
+
+    /// Start a variable length field of `length` bytes, like [`ConsensusHasher::chain_bytes`], to feed in chunks
+    pub fn stream_bytes(mut self, length: u32) -> ByteStream<D, P> {
+        if self.trace.is_some() {
+            self.writer.item = Some(Vec::new());
+        }
+        self.writer.update(&length.to_le_bytes());
+        ByteStream {
+            hasher: self,
+            remaining: length,
+        }
+    }
+
+    /// The next `length` bytes of `reader` as a variable length field, like [`ConsensusHasher::chain_bytes`], read
+    /// [`MAX_CHUNK_LENGTH`] bytes at a time
+    pub fn chain_reader<R: Read>(self, length: u32, mut reader: R) -> io::Result<Self> {
+        let mut stream = self.stream_bytes(length);
+        let mut chunk = [0u8; MAX_CHUNK_LENGTH];
+        while stream.remaining() > 0 {
+            // Cannot truncate, the chunk is at most MAX_CHUNK_LENGTH long
+            let chunk = &mut chunk[..MAX_CHUNK_LENGTH.min(stream.remaining() as usize)];
+            reader.read_exact(chunk)?;
+            stream.update(chunk)?;
+        }
+        stream.finish()
+    }
+}
+
+/// A variable length field of a hasher being fed in chunks, see [`ConsensusHasher::stream_bytes`]. It also takes the
+/// bytes written to it, e.g. with [`io::copy`].
+pub struct ByteStream<D, P = Exact> {
+    hasher: ConsensusHasher<D, P>,
+    remaining: u32,
+}
+
+impl<D: Digest, P> ByteStream<D, P> {
+    /// How many bytes are left to feed
+    pub fn remaining(&self) -> u32 {
+        self.remaining
+    }
+
+    /// Feed the next chunk, failing if it runs past the length the field was started with
+    pub fn update(&mut self, chunk: &[u8]) -> io::Result<()> {
+        let length = u32::try_from(chunk.len())
+            .ok()
+            .filter(|length| *length <= self.remaining)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "the chunk runs past the end of the field"))?;
+        self.hasher.writer.update(chunk);
+        self.remaining -= length;
+        Ok(())
+    }
+
+    /// The hasher with the field closed, failing if fewer bytes were fed than it was started with
+    pub fn finish(mut self) -> io::Result<ConsensusHasher<D, P>> {
+        if self.remaining > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("{} bytes of the field were not fed", self.remaining),
+            ));
+        }
+        if let (Some(trace), Some(bytes)) = (&mut self.hasher.trace, self.hasher.writer.item.take()) {
+            trace.push(PreimageField {
+                item: type_name::<Vec<u8>>(),
+                bytes,
+            });
+        }
+        Ok(self.hasher)
+    }
+}
+
+impl<D: Digest, P> io::Write for ByteStream<D, P> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn consensus_encode<T: BorshSerialize, D: Digest>(data: &T, writer: &mut WriteHashWrapper<D>) {
+    BorshSerialize::serialize(data, writer)
+        .expect("Incorrect implementation of BorshSerialize encountered. Implementations MUST be infallible.");
+}
+
+/// One item chained into a traced hasher, as it was encoded
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PreimageField {
+    /// The type of the item, e.g. `u64` or `Vec<u8>` for [`ConsensusHasher::chain_bytes`]
+    pub item: &'static str,
+    pub bytes: Vec<u8>,
+}
+
+impl fmt::Display for PreimageField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} ({} bytes) {}",
+            short_type_name(self.item),
+            self.bytes.len(),
+            to_hex(&self.bytes)
+        )
+    }
+}
+
+/// Every item a hasher hashed after the domain separation tag of its label, in order
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Preimage {
+    pub label: &'static str,
+    pub fields: Vec<PreimageField>,
+}
+
+impl Preimage {
+    /// Line this preimage up with `other`, e.g. the preimage of the same challenge from the encoder of the base node
+    pub fn diff<'a>(&'a self, other: &'a Preimage) -> PreimageDiff<'a> {
+        PreimageDiff {
+            left: self,
+            right: other,
+        }
+    }
+}
+
+impl fmt::Display for Preimage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "preimage under {}:", self.label)?;
+        for (position, field) in self.fields.iter().enumerate() {
+            write!(f, "\n  {:>2} {}", position, field)?;
+        }
+        Ok(())
+    }
+}
+
+/// Two preimages side by side, field by field, see [`Preimage::diff`]
+pub struct PreimageDiff<'a> {
+    pub left: &'a Preimage,
+    pub right: &'a Preimage,
+}
+
+impl PreimageDiff<'_> {
+    /// Whether both hash the same items under the same label
+    pub fn is_empty(&self) -> bool {
+        self.left == self.right
+    }
+}
+
+/// Every field is marked `=` if both preimages encode it the same and `!` otherwise, with the offset of the first byte
+/// that differs, so that a type encoded with the wrong width or without its length stands out
+impl fmt::Display for PreimageDiff<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mark = |same: bool| if same { '=' } else { '!' };
+        write!(
+            f,
+            "{} label {} / {}",
+            mark(self.left.label == self.right.label),
+            self.left.label,
+            self.right.label
+        )?;
+        for position in 0..self.left.fields.len().max(self.right.fields.len()) {
+            let (left, right) = (self.left.fields.get(position), self.right.fields.get(position));
+            write!(f, "\n{} {:>2}", mark(left == right), position)?;
+            match left {
+                Some(left) => write!(f, " {}", left)?,
+                None => write!(f, " (missing)")?,
+            }
+            if left == right {
+                continue;
+            }
+            match right {
+                Some(right) => write!(f, "\n     {}", right)?,
+                None => write!(f, "\n     (missing)")?,
+            }
+            if let (Some(left), Some(right)) = (left, right) {
+                let offset = left
+                    .bytes
+                    .iter()
+                    .zip(&right.bytes)
+                    .position(|(left, right)| left != right)
+                    .unwrap_or_else(|| left.bytes.len().min(right.bytes.len()));
+                write!(f, "\n     first difference at byte {}", offset)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A type name without its module paths, e.g. `Option<u64>` for `core::option::Option<u64>`
+fn short_type_name(name: &str) -> String {
+    let mut short = String::with_capacity(name.len());
+    let mut ident = String::new();
+    let mut chars = name.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_alphanumeric() || c == '_' {
+            ident.push(c);
+        } else if c == ':' && chars.peek() == Some(&':') {
+            chars.next();
+            ident.clear();
+        } else {
+            short.push_str(&ident);
+            ident.clear();
+            short.push(c);
+        }
+    }
+    short.push_str(&ident);
+    short
+}
+
+/// A hash the device may sign, together with the label it was hashed under
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Challenge {
+    purpose: &'static str,
+    hash: [u8; 32],
+}
+
+impl Challenge {
+    /// A challenge the wallet hashed under the label `purpose`, e.g. the script message of an output it exports. Only
+    /// for hashes made elsewhere, everything the host hashes itself comes from
+    /// [`ConsensusHasher::finalize_challenge`].
+    pub fn from_hashed(purpose: &'static str, hash: [u8; 32]) -> Self {
+        debug_assert!(
+            transaction_hash_labels().any(|label| label == purpose),
+            "'{}' is not a transaction hash label",
+            purpose
+        );
+        Self { purpose, hash }
+    }
+
+    /// The label the challenge was hashed under
+    pub fn purpose(&self) -> &'static str {
+        self.purpose
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.hash
+    }
+}
+
+impl fmt::Display for Challenge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.purpose, short_hex(&self.hash))
+    }
+}
+
+#[derive(Clone)]
+struct WriteHashWrapper<D> {
+    digest: D,
+    /// The bytes of the item being written, when the hasher is traced
+    item: Option<Vec<u8>>,
+}
+
+impl<D: Digest> WriteHashWrapper<D> {
+    fn update(&mut self, data: &[u8]) {
+        self.digest.update(data);
+        if let Some(item) = &mut self.item {
+            item.extend_from_slice(data);
+        }
+    }
+}
+
+impl<D: Digest> Write for WriteHashWrapper<D> {
+    fn write(&mut self, buf: &[u8]) -> BorshResult<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> BorshResult<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tari_crypto::tari_utilities::hex::from_hex;
+    use tari_ledger_protocol::{
+        KERNEL_MESSAGE_LABEL,
+        SCRIPT_CHALLENGE_LABEL,
+        TRANSACTION_HASH_DOMAIN,
+        TRANSACTION_HASH_DOMAIN_VERSION,
+    };
+
+    use super::*;
+
+    type Hasher = DomainSeparatedConsensusHasher<TransactionHashDomain>;
+
+    /// The hash the base node makes of `data` under `label`, the tag written out by hand
+    fn node_hash(label: &str, data: &[u8]) -> [u8; 32] {
+        let tag = format!(
+            "{}.v{}.{}",
+            TRANSACTION_HASH_DOMAIN, TRANSACTION_HASH_DOMAIN_VERSION, label
+        );
+        let mut digest = Blake256::new();
+        digest.update((tag.len() as u64).to_le_bytes());
+        digest.update(tag.as_bytes());
+        digest.update(data);
+        digest.finalize().into()
+    }
+
+    fn hex(hash: &str) -> [u8; 32] {
+        from_hex(hash).unwrap().try_into().unwrap()
+    }
+
+    #[test]
+    fn hashes_match_the_base_node() {
+        let hash = Hasher::new(SCRIPT_CHALLENGE_LABEL).chain(&255u64).finalize();
+        assert_eq!(hash, node_hash(SCRIPT_CHALLENGE_LABEL, &255u64.to_le_bytes()));
+        assert_eq!(
+            hash,
+            hex("d463b1c1e54878e326174240dcc7d8e753558ca4352bf32415882030f5b1db89")
+        );
+
+        let hash = Hasher::new(KERNEL_MESSAGE_LABEL)
+            .chain_optional(&Some(5u64))
+            .chain_bytes(b"tari")
+            .chain_fixed(&[7u8; 32])
+            .finalize();
+        assert_eq!(
+            hash,
+            hex("792de736f508434767d9dc624c9177505682a978f711a327a2ff181b6ffc09a7")
+        );
+        let hash = Hasher::new(KERNEL_MESSAGE_LABEL)
+            .chain_optional(&None::<u64>)
+            .chain_bytes(&[])
+            .chain_fixed(&[7u8; 32])
+            .finalize();
+        assert_eq!(
+            hash,
+            hex("e4fa6bc024fc3880b0deada0611f5f600c12d5f1c7cb40de196e9874c0b49e9b")
+        );
+    }
+
+    #[test]
+    fn the_label_separates_hashes() {
+        let script = Hasher::new(SCRIPT_CHALLENGE_LABEL).chain(&1u64).finalize_challenge();
+        let kernel = Hasher::new(KERNEL_MESSAGE_LABEL).chain(&1u64).finalize_challenge();
+        assert_ne!(script.as_bytes(), kernel.as_bytes());
+        assert_eq!(script.purpose(), SCRIPT_CHALLENGE_LABEL);
+        assert_eq!(kernel.purpose(), KERNEL_MESSAGE_LABEL);
+    }
+
+    #[test]
+    fn fields_are_encoded_as_the_node_encodes_them() {
+        let traced = Hasher::traced(KERNEL_MESSAGE_LABEL)
+            .chain_optional(&None::<u64>)
+            .chain_optional(&Some(2u64))
+            .chain_vec(&[1u16, 2])
+            .chain_bytes(b"ab")
+            .chain_fixed(&[9u8; 3]);
+        let fields = traced.preimage().fields;
+        let bytes = fields.iter().map(|field| field.bytes.clone()).collect::<Vec<_>>();
+        assert_eq!(bytes, vec![
+            vec![0],
+            vec![1, 2, 0, 0, 0, 0, 0, 0, 0],
+            vec![2, 0, 0, 0, 1, 0, 2, 0],
+            vec![2, 0, 0, 0, b'a', b'b'],
+            vec![9, 9, 9],
+        ]);
+        assert_eq!(traced.finalize(), node_hash(KERNEL_MESSAGE_LABEL, &bytes.concat()));
+    }
+
+    #[test]
+    fn bytes_hash_as_a_vector_of_bytes() {
+        let bytes = Hasher::new(SCRIPT_CHALLENGE_LABEL)
+            .chain_bytes(b"payment id")
+            .finalize();
+        let vec = Hasher::new(SCRIPT_CHALLENGE_LABEL)
+            .chain_vec(b"payment id".as_slice())
+            .finalize();
+        let chained = Hasher::new(SCRIPT_CHALLENGE_LABEL)
+            .chain(&b"payment id".to_vec())
+            .finalize();
+        assert_eq!(bytes, vec);
+        assert_eq!(bytes, chained);
+
+        // A fixed-size field carries no length
+        let fixed = Hasher::new(SCRIPT_CHALLENGE_LABEL)
+            .chain_fixed(b"payment id")
+            .finalize();
+        assert_ne!(fixed, bytes);
+        assert_eq!(fixed, node_hash(SCRIPT_CHALLENGE_LABEL, b"payment id"));
+    }
+
+    #[test]
+    fn a_wider_digest_keeps_its_leading_bytes() {
+        let hasher = Hasher::with_digest::<sha2::Sha512, LeadingBytes>(SCRIPT_CHALLENGE_LABEL).chain(&3u64);
+        let full = hasher.clone().finalize_full();
+        assert_eq!(hasher.finalize(), full[..32]);
+
+        let mut digest = sha2::Sha512::new();
+        TransactionHashDomain::add_domain_separation_tag(&mut digest, SCRIPT_CHALLENGE_LABEL);
+        digest.update(3u64.to_le_bytes());
+        assert_eq!(full, digest.finalize());
+    }
+
+    #[test]
+    fn only_a_traced_hasher_keeps_its_preimage() {
+        let plain = Hasher::new(SCRIPT_CHALLENGE_LABEL).chain(&1u64);
+        assert!(plain.preimage().fields.is_empty());
+
+        let traced = Hasher::traced(SCRIPT_CHALLENGE_LABEL).chain(&1u64);
+        let other = Hasher::traced(SCRIPT_CHALLENGE_LABEL).chain(&1u32);
+        let (traced, other) = (traced.preimage(), other.preimage());
+        assert_eq!(traced.fields, vec![PreimageField {
+            item: "u64",
+            bytes: vec![1, 0, 0, 0, 0, 0, 0, 0],
+        }]);
+        assert!(traced.diff(&traced).is_empty());
+        let diff = traced.diff(&other);
+        assert!(!diff.is_empty());
+        assert!(diff.to_string().contains("first difference at byte 4"), "{}", diff);
+    }
+
+    /// Bytes longer than an upload chunk, and not a whole number of chunks
+    fn blob() -> Vec<u8> {
+        (0..3 * MAX_CHUNK_LENGTH + 17).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn streamed_bytes_hash_as_chained_bytes() {
+        let blob = blob();
+        let expected = Hasher::new(SCRIPT_CHALLENGE_LABEL)
+            .chain(&1u64)
+            .chain_bytes(&blob)
+            .finalize();
+
+        let mut stream = Hasher::new(SCRIPT_CHALLENGE_LABEL)
+            .chain(&1u64)
+            .stream_bytes(blob.len() as u32);
+        for chunk in blob.chunks(100) {
+            stream.update(chunk).unwrap();
+        }
+        stream.update(&[]).unwrap();
+        assert_eq!(stream.remaining(), 0);
+        assert_eq!(stream.finish().unwrap().finalize(), expected);
+
+        let mut stream = Hasher::new(SCRIPT_CHALLENGE_LABEL)
+            .chain(&1u64)
+            .stream_bytes(blob.len() as u32);
+        io::copy(&mut blob.as_slice(), &mut stream).unwrap();
+        assert_eq!(stream.finish().unwrap().finalize(), expected);
+
+        let hasher = Hasher::new(SCRIPT_CHALLENGE_LABEL)
+            .chain(&1u64)
+            .chain_reader(blob.len() as u32, blob.as_slice())
+            .unwrap();
+        assert_eq!(hasher.finalize(), expected);
+    }
+
+    #[test]
+    fn a_stream_keeps_to_its_length() {
+        let mut stream = Hasher::new(SCRIPT_CHALLENGE_LABEL).stream_bytes(4);
+        stream.update(b"abc").unwrap();
+        let error = stream.update(b"de").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        // The refused chunk was not hashed
+        assert_eq!(stream.remaining(), 1);
+        stream.update(b"d").unwrap();
+        assert_eq!(
+            stream.finish().unwrap().finalize(),
+            Hasher::new(SCRIPT_CHALLENGE_LABEL).chain_bytes(b"abcd").finalize()
+        );
+
+        let mut stream = Hasher::new(SCRIPT_CHALLENGE_LABEL).stream_bytes(4);
+        stream.update(b"abc").unwrap();
+        let error = stream.finish().err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(error.to_string().contains("1 bytes"), "{}", error);
+    }
+
+    #[test]
+    fn a_short_reader_fails() {
+        let blob = blob();
+        let error = Hasher::new(SCRIPT_CHALLENGE_LABEL)
+            .chain_reader(blob.len() as u32 + 1, blob.as_slice())
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+
+        // A longer reader is only read as far as the field
+        let mut reader = blob.as_slice();
+        let hasher = Hasher::new(SCRIPT_CHALLENGE_LABEL)
+            .chain_reader(10, &mut reader)
+            .unwrap();
+        assert_eq!(reader.len(), blob.len() - 10);
+        assert_eq!(
+            hasher.finalize(),
+            Hasher::new(SCRIPT_CHALLENGE_LABEL).chain_bytes(&blob[..10]).finalize()
+        );
+    }
+
+    #[test]
+    fn a_traced_stream_keeps_its_bytes() {
+        let blob = blob();
+        let chained = Hasher::traced(SCRIPT_CHALLENGE_LABEL).chain_bytes(&blob).chain(&2u8);
+        let streamed = Hasher::traced(SCRIPT_CHALLENGE_LABEL)
+            .chain_reader(blob.len() as u32, blob.as_slice())
+            .unwrap()
+            .chain(&2u8);
+        assert!(streamed.preimage().diff(&chained.preimage()).is_empty());
+        assert_eq!(streamed.preimage().fields[0].item, "alloc::vec::Vec<u8>");
+        assert_eq!(streamed.finalize(), chained.finalize());
+    }
+
+    #[test]
+    fn type_names_drop_their_paths() {
+        assert_eq!(short_type_name("core::option::Option<u64>"), "Option<u64>");
+        assert_eq!(
+            short_type_name("alloc::vec::Vec<tari_crypto::ristretto::RistrettoPublicKey>"),
+            "Vec<RistrettoPublicKey>"
+        );
+        assert_eq!(short_type_name("[u8; 32]"), "[u8; 32]");
+    }
+}
@@ -0,0 +1,203 @@
+//! A Linux transport that opens `/dev/hidraw*` directly
+//! hidapi relies on libudev or libusb, which are often missing or sandboxed away when a wallet is packaged as a
+//! Flatpak or Snap. The kernel hidraw nodes only need sysfs and permission on the node, so this backend finds the
+//! Ledger APDU interface through sysfs and speaks the Ledger HID framing over the node itself.
+
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use ledger_transport::{APDUAnswer, APDUCommand};
+
+use crate::{
+    errors::DeviceError,
+    transport::{HidFilter, LedgerTransport},
+};
+
+const SYSFS_HIDRAW: &str = "/sys/class/hidraw";
+/// The tag of a report descriptor Usage Page item with a 2-byte page
+const USAGE_PAGE_ITEM_TAG: u8 = 0x06;
+const HID_PACKET_SIZE: usize = 64;
+/// Every frame starts with the channel, the APDU tag and a sequence number
+const CHANNEL: u16 = 0x0101;
+const TAG_APDU: u8 = 0x05;
+const FRAME_HEADER_LENGTH: usize = 5;
+
+/// A Ledger APDU interface found in sysfs
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HidrawDeviceInfo {
+    /// The device node, e.g. `/dev/hidraw3`
+    pub node: PathBuf,
+    pub name: Option<String>,
+    pub serial: Option<String>,
+    pub product_id: u16,
+}
+
+/// Whether this backend can reach a device in the current environment
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HidrawSupport {
+    Available(HidrawDeviceInfo),
+    /// sysfs does not list any hidraw nodes, e.g. inside a sandbox without `/sys` access
+    NoHidraw,
+    /// There are hidraw nodes but none of them is a Ledger APDU interface
+    NoDevice,
+    /// A Ledger node exists but cannot be opened for reading and writing
+    PermissionDenied(PathBuf),
+}
+
+impl fmt::Display for HidrawSupport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HidrawSupport::Available(info) => write!(f, "Ledger device at {}", info.node.display()),
+            HidrawSupport::NoHidraw => write!(f, "{} is not readable or lists no hidraw nodes", SYSFS_HIDRAW),
+            HidrawSupport::NoDevice => write!(f, "No Ledger device found, is it connected and unlocked?"),
+            HidrawSupport::PermissionDenied(node) => write!(
+                f,
+                "{} exists but cannot be opened, install the Ledger udev rules or grant the sandbox access to it",
+                node.display()
+            ),
+        }
+    }
+}
+
+/// Every Ledger APDU interface the kernel exposes, ordered by node
+pub fn list_ledgers() -> io::Result<Vec<HidrawDeviceInfo>> {
+    list_devices(&HidFilter::default())
+}
+
+/// Every APDU interface `filter` matches, ordered by node
+pub fn list_devices(filter: &HidFilter) -> io::Result<Vec<HidrawDeviceInfo>> {
+    let [page_low, page_high] = filter.usage_page.to_le_bytes();
+    let usage_page_item = [USAGE_PAGE_ITEM_TAG, page_low, page_high];
+    let mut devices = Vec::new();
+    for entry in fs::read_dir(SYSFS_HIDRAW)? {
+        let entry = entry?;
+        let device_dir = entry.path().join("device");
+        let uevent = match fs::read_to_string(device_dir.join("uevent")) {
+            Ok(uevent) => uevent,
+            Err(_) => continue,
+        };
+        let field = |key: &str| {
+            uevent
+                .lines()
+                .find_map(|line| line.strip_prefix(key))
+                .map(|value| value.trim().to_string())
+        };
+        let product_id = match field("HID_ID=").and_then(|id| parse_id(&id)) {
+            Some((vendor_id, product_id)) if filter.matches_device(vendor_id, product_id) => product_id,
+            _ => continue,
+        };
+        // Ledger devices expose several interfaces, only one of them carries APDUs
+        let descriptor = fs::read(device_dir.join("report_descriptor")).unwrap_or_default();
+        if !descriptor.windows(3).any(|item| item == usage_page_item) {
+            continue;
+        }
+        devices.push(HidrawDeviceInfo {
+            node: Path::new("/dev").join(entry.file_name()),
+            name: field("HID_NAME="),
+            serial: field("HID_UNIQ=").filter(|serial| !serial.is_empty()),
+            product_id,
+        });
+    }
+    devices.sort_by(|a, b| a.node.cmp(&b.node));
+    Ok(devices)
+}
+
+/// Check whether a device can be reached over hidraw, and what is in the way if not
+pub fn detect() -> HidrawSupport {
+    let devices = match list_ledgers() {
+        Ok(devices) => devices,
+        Err(_) => return HidrawSupport::NoHidraw,
+    };
+    let info = match devices.into_iter().next() {
+        Some(info) => info,
+        None => return HidrawSupport::NoDevice,
+    };
+    match OpenOptions::new().read(true).write(true).open(&info.node) {
+        Ok(_) => HidrawSupport::Available(info),
+        Err(_) => HidrawSupport::PermissionDenied(info.node),
+    }
+}
+
+/// The vendor and product id of a `HID_ID`, which is `bus:vendor:product` in hex, eight digits each
+fn parse_id(id: &str) -> Option<(u16, u16)> {
+    let mut fields = id.split(':').skip(1).map(|field| u32::from_str_radix(field, 16).ok());
+    match (fields.next().flatten(), fields.next().flatten()) {
+        (Some(vendor), Some(product)) => Some((u16::try_from(vendor).ok()?, u16::try_from(product).ok()?)),
+        _ => None,
+    }
+}
+
+pub struct TransportHidraw {
+    node: Mutex<File>,
+}
+
+impl TransportHidraw {
+    pub fn open(info: &HidrawDeviceInfo) -> Result<Self, DeviceError> {
+        let node = OpenOptions::new().read(true).write(true).open(&info.node)?;
+        Ok(Self { node: Mutex::new(node) })
+    }
+}
+
+impl LedgerTransport for TransportHidraw {
+    fn exchange(&self, command: &APDUCommand<Vec<u8>>) -> Result<APDUAnswer<Vec<u8>>, DeviceError> {
+        let mut node = self.node.lock().expect("the hidraw node is never poisoned");
+        write_apdu(&mut node, &command.serialize())?;
+        let answer = read_answer(&mut node)?;
+        APDUAnswer::from_answer(answer).map_err(|_| DeviceError::InvalidResponse("the answer has no status word"))
+    }
+}
+
+/// Split the length prefixed APDU into frames, each written as an unnumbered HID report
+fn write_apdu(node: &mut File, apdu: &[u8]) -> io::Result<()> {
+    let length = u16::try_from(apdu.len()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "APDU too long"))?;
+    let mut data = length.to_be_bytes().to_vec();
+    data.extend_from_slice(apdu);
+    for (sequence, chunk) in data.chunks(HID_PACKET_SIZE - FRAME_HEADER_LENGTH).enumerate() {
+        // The first byte is the report number, Ledger devices do not number their reports
+        let mut report = [0u8; HID_PACKET_SIZE + 1];
+        report[1..3].copy_from_slice(&CHANNEL.to_be_bytes());
+        report[3] = TAG_APDU;
+        report[4..6].copy_from_slice(&(sequence as u16).to_be_bytes());
+        report[6..6 + chunk.len()].copy_from_slice(chunk);
+        node.write_all(&report)?;
+    }
+    Ok(())
+}
+
+/// Read frames until the length announced in the first one has arrived
+fn read_answer(node: &mut File) -> io::Result<Vec<u8>> {
+    let invalid = |message| io::Error::new(io::ErrorKind::InvalidData, message);
+    let mut answer = Vec::new();
+    let mut length = 0;
+    let mut sequence = 0u16;
+    loop {
+        let mut report = [0u8; HID_PACKET_SIZE];
+        let read = node.read(&mut report)?;
+        if read < FRAME_HEADER_LENGTH || report[0..2] != CHANNEL.to_be_bytes() || report[2] != TAG_APDU {
+            return Err(invalid("unexpected HID frame"));
+        }
+        if u16::from_be_bytes([report[3], report[4]]) != sequence {
+            return Err(invalid("HID frame out of sequence"));
+        }
+        let mut payload = &report[FRAME_HEADER_LENGTH..read];
+        if sequence == 0 {
+            if payload.len() < 2 {
+                return Err(invalid("HID frame too short"));
+            }
+            length = usize::from(u16::from_be_bytes([payload[0], payload[1]]));
+            payload = &payload[2..];
+        }
+        answer.extend_from_slice(payload);
+        if answer.len() >= length {
+            // The last frame is padded
+            answer.truncate(length);
+            return Ok(answer);
+        }
+        sequence = sequence.wrapping_add(1);
+    }
+}
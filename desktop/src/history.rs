@@ -0,0 +1,1044 @@
+//! A local, encrypted log of every operation the device was asked to sign
+//! The log is a SQLCipher database so that users and auditors can reconstruct what the device approved without the
+//! file leaking signing activity to anyone without the passphrase.
+//!
+//! Where the app supports it, each record also holds the device's signing counter after the operation. A counter that
+//! has moved on since the last record means the device signed something this host never asked for.
+//!
+//! Every signature is also checked against the public nonces of the earlier records: the same nonce over a different
+//! challenge reveals the private key, so recording one fails with [`StoreError::NonceReuse`].
+//!
+//! For audits the log can be exported as CSV or JSON, and a JSON export of another host's log imported. Its
+//! signatures can be re-verified in bulk with [`verify_records`], which flags records a corrupted database or a
+//! compromised host changed after the fact.
+//!
+//! The database is also the [`IdempotencyLog`] of the signer, keeping the result of every completed signing request so
+//! that a retried request does not prompt the user again, and its [`SignedOutputLog`]. The outputs it records are
+//! reconciled with the app's output counter by [`SigningHistory::reconcile_outputs`]: the counter moving on by more
+//! outputs than were recorded since the last reconciliation means the device co-signed outputs this host never saw.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    path::Path,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use rusqlite::{params, Connection, Params};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tari_crypto::{
+    ristretto::RistrettoPublicKey,
+    tari_utilities::{
+        hex::{from_hex, to_hex},
+        ByteArray,
+    },
+};
+
+use crate::{
+    errors::StoreError,
+    hashing::Challenge,
+    signer::{IdempotencyLog, SignedOutputLog},
+    verify::{signature_from_bytes, verify_challenge_signature},
+};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS signing_history (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        timestamp INTEGER NOT NULL,
+        instruction TEXT NOT NULL,
+        challenge_hash BLOB NOT NULL,
+        public_key BLOB,
+        signature BLOB,
+        status TEXT NOT NULL,
+        device_counter INTEGER
+    );
+    CREATE TABLE IF NOT EXISTS completed_requests (
+        idempotency_key BLOB PRIMARY KEY,
+        timestamp INTEGER NOT NULL,
+        result BLOB NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS signed_outputs (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        timestamp INTEGER NOT NULL,
+        challenge BLOB NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS output_reconciliations (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        timestamp INTEGER NOT NULL,
+        device_counter INTEGER NOT NULL,
+        last_output INTEGER NOT NULL
+    );
+";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OperationStatus {
+    /// The device produced a signature
+    Signed,
+    /// The user declined the operation on the device
+    Rejected,
+    /// The operation failed for any other reason
+    Failed,
+}
+
+impl OperationStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            OperationStatus::Signed => "signed",
+            OperationStatus::Rejected => "rejected",
+            OperationStatus::Failed => "failed",
+        }
+    }
+
+    fn parse(s: &str) -> Result<Self, StoreError> {
+        match s {
+            "signed" => Ok(OperationStatus::Signed),
+            "rejected" => Ok(OperationStatus::Rejected),
+            "failed" => Ok(OperationStatus::Failed),
+            _ => Err(StoreError::Corrupt("unknown operation status")),
+        }
+    }
+}
+
+impl fmt::Display for OperationStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// A single device-signed operation
+#[derive(Clone, Debug)]
+pub struct HistoryRecord {
+    pub id: i64,
+    /// Seconds since the unix epoch
+    pub timestamp: i64,
+    pub instruction: String,
+    /// The challenge the device signed
+    pub challenge_hash: Vec<u8>,
+    pub public_key: Option<Vec<u8>>,
+    /// The public nonce followed by the signature scalar
+    pub signature: Option<Vec<u8>>,
+    pub status: OperationStatus,
+    /// The device signing counter once the operation completed
+    pub device_counter: Option<u64>,
+}
+
+impl HistoryRecord {
+    /// The first 8 bytes of the SHA-256 hash of the signature, to tell signatures apart without printing them
+    pub fn signature_fingerprint(&self) -> Option<String> {
+        self.signature
+            .as_ref()
+            .map(|signature| to_hex(&Sha256::digest(signature)[..8]))
+    }
+
+    /// Check the recorded signature against the recorded public key and challenge. Operations that did not complete
+    /// have no signature to check and return `None`.
+    pub fn verify(&self) -> Option<Result<(), RecordProblem>> {
+        if self.status != OperationStatus::Signed {
+            return None;
+        }
+        let (Some(public_key), Some(signature)) = (&self.public_key, &self.signature) else {
+            return Some(Err(RecordProblem::Incomplete));
+        };
+        let public_key = RistrettoPublicKey::from_bytes(public_key).ok();
+        let signature = signature_from_bytes(signature);
+        let challenge = <[u8; 32]>::try_from(self.challenge_hash.as_slice()).ok();
+        let (Some(public_key), Some(signature), Some(challenge)) = (public_key, signature, challenge) else {
+            return Some(Err(RecordProblem::Malformed));
+        };
+        if verify_challenge_signature(&public_key, &signature, &challenge) {
+            Some(Ok(()))
+        } else {
+            Some(Err(RecordProblem::InvalidSignature))
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "id": self.id,
+            "time": format_time(self.timestamp),
+            "timestamp": self.timestamp,
+            "instruction": self.instruction,
+            "status": self.status.as_str(),
+            "challenge_hash": to_hex(&self.challenge_hash),
+            "public_key": self.public_key.as_deref().map(to_hex),
+            "signature": self.signature.as_deref().map(to_hex),
+            "signature_fingerprint": self.signature_fingerprint(),
+            "device_counter": self.device_counter,
+        })
+    }
+}
+
+impl fmt::Display for HistoryRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let time = format_time(self.timestamp);
+        write!(
+            f,
+            "#{} {} {} {} challenge {}",
+            self.id,
+            time,
+            self.instruction,
+            self.status,
+            to_hex(&self.challenge_hash)
+        )?;
+        if let Some(signature) = &self.signature {
+            write!(f, " signature {}", to_hex(signature))?;
+        }
+        if let Some(counter) = self.device_counter {
+            write!(f, " counter {}", counter)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct SigningHistory {
+    connection: Connection,
+}
+
+impl SigningHistory {
+    /// Open (or create) the history database at `path`, encrypted with `passphrase`
+    pub fn open<P: AsRef<Path>>(path: P, passphrase: &str) -> Result<Self, StoreError> {
+        if let Some(parent) = path.as_ref().parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let connection = Connection::open(path)?;
+        // The key must be set before the first access to the database
+        connection.pragma_update(None, "key", passphrase)?;
+        connection
+            .execute_batch(SCHEMA)
+            .map_err(|e| StoreError::Backend(format!("{} (wrong history passphrase?)", e)))?;
+        add_device_counter_column(&connection)?;
+        Ok(Self { connection })
+    }
+
+    /// Append a record for an operation the device was asked to perform, returning the record id. `signature` is the
+    /// public nonce followed by the signature scalar. A nonce that signed a different challenge before is still
+    /// recorded, as evidence, but fails with [`StoreError::NonceReuse`].
+    pub fn record(
+        &self,
+        instruction: &str,
+        challenge_hash: &[u8],
+        public_key: Option<&[u8]>,
+        signature: Option<&[u8]>,
+        status: OperationStatus,
+        device_counter: Option<u64>,
+    ) -> Result<i64, StoreError> {
+        self.insert(
+            now(),
+            instruction,
+            challenge_hash,
+            public_key,
+            signature,
+            status,
+            device_counter,
+        )
+    }
+
+    /// Append the records of a JSON export, e.g. from another host, keeping their timestamps but not their ids.
+    /// Returns the number of records imported.
+    pub fn import_json(&self, json: &str) -> Result<usize, StoreError> {
+        let records: Vec<ImportedRecord> =
+            serde_json::from_str(json).map_err(|e| StoreError::Serialization(e.to_string()))?;
+        let invalid_hex = |_| StoreError::Corrupt("the export holds invalid hex");
+        let hex = |value: &Option<String>| value.as_deref().map(from_hex).transpose().map_err(invalid_hex);
+        for record in &records {
+            self.insert(
+                record.timestamp,
+                &record.instruction,
+                &from_hex(&record.challenge_hash).map_err(invalid_hex)?,
+                hex(&record.public_key)?.as_deref(),
+                hex(&record.signature)?.as_deref(),
+                OperationStatus::parse(&record.status)?,
+                record.device_counter,
+            )?;
+        }
+        Ok(records.len())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn insert(
+        &self,
+        timestamp: i64,
+        instruction: &str,
+        challenge_hash: &[u8],
+        public_key: Option<&[u8]>,
+        signature: Option<&[u8]>,
+        status: OperationStatus,
+        device_counter: Option<u64>,
+    ) -> Result<i64, StoreError> {
+        let earlier = match signature {
+            Some(signature) if signature.len() >= 32 => self.nonce_reused_by(&signature[..32], challenge_hash)?,
+            _ => None,
+        };
+        self.connection.execute(
+            "INSERT INTO signing_history (timestamp, instruction, challenge_hash, public_key, signature, status, \
+             device_counter) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                timestamp,
+                instruction,
+                challenge_hash,
+                public_key,
+                signature,
+                status.as_str(),
+                device_counter.map(|c| c as i64)
+            ],
+        )?;
+        let record = self.connection.last_insert_rowid();
+        match earlier {
+            Some(earlier) => Err(StoreError::NonceReuse { earlier, record }),
+            None => Ok(record),
+        }
+    }
+
+    /// The first record whose signature has `public_nonce` but was made over another challenge than `challenge_hash`
+    fn nonce_reused_by(&self, public_nonce: &[u8], challenge_hash: &[u8]) -> Result<Option<i64>, StoreError> {
+        let id = self.connection.query_row(
+            "SELECT id FROM signing_history WHERE substr(signature, 1, 32) = ?1 AND challenge_hash != ?2 ORDER BY id \
+             LIMIT 1",
+            params![public_nonce, challenge_hash],
+            |row| row.get::<_, i64>(0),
+        );
+        match id {
+            Ok(id) => Ok(Some(id)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Return the most recent records, newest first
+    pub fn records(&self, limit: Option<usize>) -> Result<Vec<HistoryRecord>, StoreError> {
+        let limit = limit.map(|l| l as i64).unwrap_or(-1);
+        self.query(
+            "SELECT id, timestamp, instruction, challenge_hash, public_key, signature, status, device_counter FROM \
+             signing_history ORDER BY id DESC LIMIT ?1",
+            params![limit],
+        )
+    }
+
+    /// Return the records made at or after `since`, in seconds since the unix epoch, oldest first
+    pub fn records_since(&self, since: i64) -> Result<Vec<HistoryRecord>, StoreError> {
+        self.query(
+            "SELECT id, timestamp, instruction, challenge_hash, public_key, signature, status, device_counter FROM \
+             signing_history WHERE timestamp >= ?1 ORDER BY id",
+            params![since],
+        )
+    }
+
+    fn query<P: Params>(&self, sql: &str, params: P) -> Result<Vec<HistoryRecord>, StoreError> {
+        let mut statement = self.connection.prepare(sql)?;
+        let rows = statement.query_map(params, |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, Vec<u8>>(3)?,
+                row.get::<_, Option<Vec<u8>>>(4)?,
+                row.get::<_, Option<Vec<u8>>>(5)?,
+                row.get::<_, String>(6)?,
+                row.get::<_, Option<i64>>(7)?,
+            ))
+        })?;
+        rows.map(|row| {
+            let (id, timestamp, instruction, challenge_hash, public_key, signature, status, device_counter) = row?;
+            Ok(HistoryRecord {
+                id,
+                timestamp,
+                instruction,
+                challenge_hash,
+                public_key,
+                signature,
+                status: OperationStatus::parse(&status)?,
+                device_counter: device_counter.map(|c| c as u64),
+            })
+        })
+        .collect()
+    }
+
+    /// The device signing counter of the most recent record that has one
+    pub fn last_device_counter(&self) -> Result<Option<u64>, StoreError> {
+        let counter = self.connection.query_row(
+            "SELECT device_counter FROM signing_history WHERE device_counter IS NOT NULL ORDER BY id DESC LIMIT 1",
+            [],
+            |row| row.get::<_, i64>(0),
+        );
+        match counter {
+            Ok(counter) => Ok(Some(counter as u64)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// How many signatures the device produced since the last record, given its `current_counter`. Anything but zero
+    /// means the device was used to sign without this host, e.g. by another wallet or by malware driving it.
+    pub fn unrecorded_signatures(&self, current_counter: u64) -> Result<u64, StoreError> {
+        Ok(self
+            .last_device_counter()?
+            .map(|last| current_counter.saturating_sub(last))
+            .unwrap_or(0))
+    }
+
+    /// Reconcile the app's output counter, `device_counter`, with the outputs recorded since the last reconciliation.
+    /// The first reconciliation only records the counter as the baseline. A reconciliation that finds the counts
+    /// agree is recorded as the next baseline, one that finds unrecorded outputs or a counter that went down only if
+    /// `accept`, so that the alert is raised again until the user acknowledges it.
+    pub fn reconcile_outputs(&self, device_counter: u64, accept: bool) -> Result<OutputReconciliation, StoreError> {
+        let last = self.connection.query_row(
+            "SELECT device_counter, last_output FROM output_reconciliations ORDER BY id DESC LIMIT 1",
+            [],
+            |row| Ok((row.get::<_, i64>(0)? as u64, row.get::<_, i64>(1)?)),
+        );
+        let last = match last {
+            Ok(last) => Some(last),
+            Err(rusqlite::Error::QueryReturnedNoRows) => None,
+            Err(e) => return Err(e.into()),
+        };
+        let last_output = self
+            .connection
+            .query_row("SELECT COALESCE(MAX(id), 0) FROM signed_outputs", [], |row| {
+                row.get::<_, i64>(0)
+            })?;
+        let reconciliation = match last {
+            None => OutputReconciliation::Baseline { device_counter },
+            Some((last_counter, _)) if device_counter < last_counter => OutputReconciliation::CounterReset {
+                device_counter,
+                last_counter,
+            },
+            Some((last_counter, since)) => {
+                let recorded = self.connection.query_row(
+                    "SELECT COUNT(*) FROM signed_outputs WHERE id > ?1 AND id <= ?2",
+                    params![since, last_output],
+                    |row| row.get::<_, i64>(0),
+                )? as u64;
+                match (device_counter - last_counter).saturating_sub(recorded) {
+                    0 => OutputReconciliation::Reconciled {
+                        device_counter,
+                        outputs: recorded,
+                    },
+                    unrecorded => OutputReconciliation::Unrecorded {
+                        device_counter,
+                        unrecorded,
+                    },
+                }
+            },
+        };
+        if accept || reconciliation.is_clean() {
+            self.connection.execute(
+                "INSERT INTO output_reconciliations (timestamp, device_counter, last_output) VALUES (?1, ?2, ?3)",
+                params![now(), device_counter as i64, last_output],
+            )?;
+        }
+        Ok(reconciliation)
+    }
+}
+
+impl SignedOutputLog for SigningHistory {
+    fn record_signed_output(&self, challenge: &Challenge) -> Result<(), StoreError> {
+        self.connection.execute(
+            "INSERT INTO signed_outputs (timestamp, challenge) VALUES (?1, ?2)",
+            params![now(), challenge.as_bytes().as_slice()],
+        )?;
+        Ok(())
+    }
+}
+
+/// What [`SigningHistory::reconcile_outputs`] found
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputReconciliation {
+    /// There was nothing to reconcile with yet, the counter is the baseline of the next reconciliation
+    Baseline { device_counter: u64 },
+    /// The device signed as many outputs as this host recorded
+    Reconciled { device_counter: u64, outputs: u64 },
+    /// The device signed `unrecorded` outputs more than this host recorded, for another host or behind its back
+    Unrecorded { device_counter: u64, unrecorded: u64 },
+    /// The counter is below the last one reconciled, from another device or an app that was reinstalled
+    CounterReset { device_counter: u64, last_counter: u64 },
+}
+
+impl OutputReconciliation {
+    /// Whether nothing calls for the user's attention
+    pub fn is_clean(&self) -> bool {
+        matches!(
+            self,
+            OutputReconciliation::Baseline { .. } | OutputReconciliation::Reconciled { .. }
+        )
+    }
+}
+
+impl fmt::Display for OutputReconciliation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OutputReconciliation::Baseline { device_counter } => write!(
+                f,
+                "Recorded the device's count of {} signed outputs as the baseline",
+                device_counter
+            ),
+            OutputReconciliation::Reconciled {
+                device_counter,
+                outputs,
+            } => write!(
+                f,
+                "The device signed the {} output(s) this host recorded since the last check, {} in total",
+                outputs, device_counter
+            ),
+            OutputReconciliation::Unrecorded {
+                device_counter,
+                unrecorded,
+            } => write!(
+                f,
+                "warning: the device signed {} output(s) that are not in this host's history, {} in total",
+                unrecorded, device_counter
+            ),
+            OutputReconciliation::CounterReset {
+                device_counter,
+                last_counter,
+            } => write!(
+                f,
+                "warning: the device's output counter is {} but was {} at the last check, this is another device or \
+                 the app was reinstalled",
+                device_counter, last_counter
+            ),
+        }
+    }
+}
+
+impl IdempotencyLog for SigningHistory {
+    fn completed_request(&self, key: &[u8; 32]) -> Result<Option<Vec<u8>>, StoreError> {
+        let result = self.connection.query_row(
+            "SELECT result FROM completed_requests WHERE idempotency_key = ?1",
+            params![key.as_slice()],
+            |row| row.get::<_, Vec<u8>>(0),
+        );
+        match result {
+            Ok(result) => Ok(Some(result)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn complete_request(&self, key: &[u8; 32], result: &[u8]) -> Result<(), StoreError> {
+        self.connection.execute(
+            "INSERT OR REPLACE INTO completed_requests (idempotency_key, timestamp, result) VALUES (?1, ?2, ?3)",
+            params![key.as_slice(), now(), result],
+        )?;
+        Ok(())
+    }
+}
+
+/// What is wrong with a record [`verify_records`] flags
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordProblem {
+    /// A signed operation without its public key or signature
+    Incomplete,
+    /// The public key, signature or challenge is not a valid encoding
+    Malformed,
+    /// The signature does not verify against the recorded public key and challenge
+    InvalidSignature,
+    /// The signature reuses the public nonce of the earlier record `earlier` over a different challenge
+    NonceReuse { earlier: i64 },
+}
+
+impl fmt::Display for RecordProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordProblem::Incomplete => write!(f, "signed, but the public key or signature is missing"),
+            RecordProblem::Malformed => write!(f, "the public key, signature or challenge is malformed"),
+            RecordProblem::InvalidSignature => write!(f, "the signature does not verify"),
+            RecordProblem::NonceReuse { earlier } => {
+                write!(
+                    f,
+                    "reuses the signature nonce of #{} for a different challenge",
+                    earlier
+                )
+            },
+        }
+    }
+}
+
+/// The outcome of [`verify_records`]
+#[derive(Clone, Debug, Default)]
+pub struct HistoryVerification {
+    /// Records whose signature verified
+    pub verified: usize,
+    /// Records of operations that did not complete, with nothing to verify
+    pub skipped: usize,
+    /// Records that failed verification, oldest first
+    pub flagged: Vec<(HistoryRecord, RecordProblem)>,
+}
+
+impl HistoryVerification {
+    pub fn is_clean(&self) -> bool {
+        self.flagged.is_empty()
+    }
+}
+
+impl fmt::Display for HistoryVerification {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} verified, {} without a signature, {} flagged",
+            self.verified,
+            self.skipped,
+            self.flagged.len()
+        )
+    }
+}
+
+/// Re-verify the signature of every record in `records`, which have to be oldest first, and check that no two of them
+/// share a public nonce over different challenges. The challenge is checked as recorded, the message it was derived
+/// from is not kept.
+pub fn verify_records(records: &[HistoryRecord]) -> HistoryVerification {
+    let mut verification = HistoryVerification::default();
+    let mut nonces = HashMap::<&[u8], (i64, &[u8])>::new();
+    for record in records {
+        let reused = record
+            .signature
+            .as_deref()
+            .filter(|signature| signature.len() >= 32)
+            .and_then(|signature| match nonces.get(&signature[..32]) {
+                Some((earlier, challenge)) if *challenge != record.challenge_hash.as_slice() => Some(*earlier),
+                Some(_) => None,
+                None => {
+                    nonces.insert(&signature[..32], (record.id, record.challenge_hash.as_slice()));
+                    None
+                },
+            });
+        let outcome = match (reused, record.verify()) {
+            (Some(earlier), _) => Err(RecordProblem::NonceReuse { earlier }),
+            (None, Some(outcome)) => outcome,
+            (None, None) => {
+                verification.skipped += 1;
+                continue;
+            },
+        };
+        match outcome {
+            Ok(()) => verification.verified += 1,
+            Err(problem) => verification.flagged.push((record.clone(), problem)),
+        }
+    }
+    verification
+}
+
+/// The formats [`export`] writes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    /// Holds the full keys and signatures, so it can be imported with [`SigningHistory::import_json`]
+    Json,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            _ => Err(format!("unknown export format '{}', use csv or json", s)),
+        }
+    }
+}
+
+/// Write `records` for an auditor
+pub fn export(records: &[HistoryRecord], format: ExportFormat) -> String {
+    match format {
+        ExportFormat::Csv => {
+            let mut csv = String::from(
+                "id,time,instruction,status,challenge_hash,public_key,signature_fingerprint,device_counter\n",
+            );
+            for record in records {
+                csv.push_str(&format!(
+                    "{},{},{},{},{},{},{},{}\n",
+                    record.id,
+                    format_time(record.timestamp),
+                    record.instruction,
+                    record.status,
+                    to_hex(&record.challenge_hash),
+                    record.public_key.as_deref().map(to_hex).unwrap_or_default(),
+                    record.signature_fingerprint().unwrap_or_default(),
+                    record.device_counter.map(|c| c.to_string()).unwrap_or_default()
+                ));
+            }
+            csv
+        },
+        ExportFormat::Json => {
+            let records = records.iter().map(HistoryRecord::to_json).collect::<Vec<_>>();
+            serde_json::to_string_pretty(&records).expect("a JSON value always serializes")
+        },
+    }
+}
+
+/// Parse a `YYYY-MM-DD` date, as midnight UTC, or an RFC 3339 time into seconds since the unix epoch
+pub fn parse_time(s: &str) -> Option<i64> {
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return date
+            .and_hms_opt(0, 0, 0)
+            .map(|time| Utc.from_utc_datetime(&time).timestamp());
+    }
+    DateTime::parse_from_rfc3339(s).ok().map(|time| time.timestamp())
+}
+
+/// A record as written by a JSON [`export`]
+#[derive(Deserialize)]
+struct ImportedRecord {
+    timestamp: i64,
+    instruction: String,
+    status: String,
+    challenge_hash: String,
+    public_key: Option<String>,
+    signature: Option<String>,
+    device_counter: Option<u64>,
+}
+
+/// Seconds since the unix epoch
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+fn format_time(timestamp: i64) -> String {
+    Utc.timestamp_opt(timestamp, 0)
+        .single()
+        .map(|t| t.to_rfc3339())
+        .unwrap_or_else(|| timestamp.to_string())
+}
+
+/// Databases created before the signing counter was recorded lack its column
+fn add_device_counter_column(connection: &Connection) -> Result<(), StoreError> {
+    let mut statement = connection.prepare("SELECT name FROM pragma_table_info('signing_history')")?;
+    let columns = statement
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    if !columns.iter().any(|column| column == "device_counter") {
+        connection.execute_batch("ALTER TABLE signing_history ADD COLUMN device_counter INTEGER")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use tari_crypto::{keys::PublicKey, ristretto::RistrettoSecretKey};
+
+    use super::*;
+    use crate::domains::CHANGE_OUTPUT_LABEL;
+
+    /// A fresh history in the temporary directory, named after the test
+    fn history(name: &str) -> SigningHistory {
+        let path = std::env::temp_dir().join(format!("tari-ledger-history-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        SigningHistory::open(path, "passphrase").unwrap()
+    }
+
+    fn challenge(n: u8) -> [u8; 32] {
+        let mut challenge = [0u8; 32];
+        challenge[0] = n;
+        challenge
+    }
+
+    /// The public key of the key `key` and its `public nonce || s` signature over `challenge` with the nonce `nonce`
+    fn signed(key: u64, nonce: u64, challenge: &[u8; 32]) -> (Vec<u8>, Vec<u8>) {
+        let k = RistrettoSecretKey::from(key);
+        let r = RistrettoSecretKey::from(nonce);
+        let e = RistrettoSecretKey::from_bytes(challenge).unwrap();
+        let s = &r + &(&e * &k);
+        let signature = [RistrettoPublicKey::from_secret_key(&r).as_bytes(), s.as_bytes()].concat();
+        (RistrettoPublicKey::from_secret_key(&k).as_bytes().to_vec(), signature)
+    }
+
+    fn record(id: i64, challenge: [u8; 32], signed: Option<(Vec<u8>, Vec<u8>)>) -> HistoryRecord {
+        let (public_key, signature) = signed.unzip();
+        HistoryRecord {
+            id,
+            timestamp: 0,
+            instruction: "sign".to_string(),
+            challenge_hash: challenge.to_vec(),
+            status: match signature {
+                Some(_) => OperationStatus::Signed,
+                None => OperationStatus::Rejected,
+            },
+            public_key,
+            signature,
+            device_counter: None,
+        }
+    }
+
+    /// Record a signed, a rejected and a failed operation
+    fn fill(history: &SigningHistory) {
+        let (public_key, signature) = signed(1, 11, &challenge(1));
+        let status = OperationStatus::Signed;
+        history
+            .record(
+                "sign",
+                &challenge(1),
+                Some(&public_key),
+                Some(&signature),
+                status,
+                Some(3),
+            )
+            .unwrap();
+        history
+            .record("sign", &challenge(2), None, None, OperationStatus::Rejected, None)
+            .unwrap();
+        history
+            .record(
+                "sign_output",
+                &challenge(3),
+                None,
+                None,
+                OperationStatus::Failed,
+                Some(3),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn a_json_export_imports_as_it_was() {
+        let original = history("export");
+        fill(&original);
+        let records = original.records_since(0).unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records.iter().map(|record| record.status).collect::<Vec<_>>(), [
+            OperationStatus::Signed,
+            OperationStatus::Rejected,
+            OperationStatus::Failed
+        ]);
+        let json = export(&records, ExportFormat::Json);
+
+        let imported = history("import");
+        assert_eq!(imported.import_json(&json).unwrap(), 3);
+        let reimported = imported.records_since(0).unwrap();
+        assert_eq!(export(&reimported, ExportFormat::Json), json);
+        assert_eq!(
+            export(&reimported, ExportFormat::Csv),
+            export(&records, ExportFormat::Csv)
+        );
+        assert!(verify_records(&reimported).is_clean());
+        // The newest comes first
+        assert_eq!(imported.records(Some(1)).unwrap()[0].instruction, "sign_output");
+    }
+
+    #[test]
+    fn the_csv_export_fingerprints_signatures() {
+        let history = history("csv");
+        fill(&history);
+        let records = history.records_since(0).unwrap();
+        let csv = export(&records, ExportFormat::Csv);
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[0],
+            "id,time,instruction,status,challenge_hash,public_key,signature_fingerprint,device_counter"
+        );
+        assert_eq!(lines.len(), 4);
+        let fingerprint = records[0].signature_fingerprint().unwrap();
+        assert_eq!(fingerprint.len(), 16);
+        assert!(lines[1].ends_with(&format!(",{},3", fingerprint)), "{}", lines[1]);
+        // The signature itself is not exported
+        assert!(!lines[1].contains(&to_hex(records[0].signature.as_ref().unwrap())));
+        assert!(
+            lines[2].ends_with(&format!("rejected,{},,,", to_hex(&challenge(2)))),
+            "{}",
+            lines[2]
+        );
+    }
+
+    #[test]
+    fn a_malformed_export_is_refused() {
+        let history = history("malformed");
+        let record = |challenge: &str, status: &str| {
+            format!(
+                r#"[{{"timestamp": 0, "instruction": "sign", "status": "{}", "challenge_hash": "{}"}}]"#,
+                status, challenge
+            )
+        };
+        assert!(matches!(
+            history.import_json("not json"),
+            Err(StoreError::Serialization(_))
+        ));
+        assert!(matches!(
+            history.import_json(&record("zz", "signed")),
+            Err(StoreError::Corrupt(_))
+        ));
+        assert!(matches!(
+            history.import_json(&record("00", "approved")),
+            Err(StoreError::Corrupt(_))
+        ));
+        assert_eq!(history.import_json(&record("00", "rejected")).unwrap(), 1);
+    }
+
+    #[test]
+    fn records_are_selected_by_time() {
+        let history = history("since");
+        let json = r#"[
+            {"timestamp": 100, "instruction": "early", "status": "rejected", "challenge_hash": "01"},
+            {"timestamp": 200, "instruction": "late", "status": "rejected", "challenge_hash": "02"}
+        ]"#;
+        assert_eq!(history.import_json(json).unwrap(), 2);
+        let since = history.records_since(150).unwrap();
+        assert_eq!(since.len(), 1);
+        assert_eq!(since[0].instruction, "late");
+        assert_eq!(history.records_since(100).unwrap().len(), 2);
+        assert_eq!(history.records(None).unwrap()[0].instruction, "late");
+    }
+
+    #[test]
+    fn a_reused_nonce_is_recorded_and_refused() {
+        let history = history("nonce");
+        let (public_key, first) = signed(1, 11, &challenge(1));
+        let (_, second) = signed(1, 11, &challenge(2));
+        let status = OperationStatus::Signed;
+        let earlier = history
+            .record("sign", &challenge(1), Some(&public_key), Some(&first), status, None)
+            .unwrap();
+        // The same signature again is a retry, not a reuse
+        history
+            .record("sign", &challenge(1), Some(&public_key), Some(&first), status, None)
+            .unwrap();
+        let reuse = history.record("sign", &challenge(2), Some(&public_key), Some(&second), status, None);
+        let Err(StoreError::NonceReuse {
+            earlier: reused,
+            record,
+        }) = reuse
+        else {
+            panic!("the reused nonce was accepted");
+        };
+        assert_eq!(reused, earlier);
+
+        // The record is kept as evidence, and flagged when verified
+        let verification = verify_records(&history.records_since(0).unwrap());
+        assert_eq!(verification.verified, 2);
+        assert_eq!(verification.flagged.len(), 1);
+        assert_eq!(verification.flagged[0].0.id, record);
+        assert_eq!(verification.flagged[0].1, RecordProblem::NonceReuse { earlier });
+    }
+
+    #[test]
+    fn verification_flags_what_does_not_verify() {
+        let good = record(1, challenge(1), Some(signed(1, 11, &challenge(1))));
+        let rejected = record(2, challenge(2), None);
+        let mut tampered = record(3, challenge(3), Some(signed(1, 13, &challenge(3))));
+        tampered.challenge_hash = challenge(4).to_vec();
+        let mut incomplete = record(4, challenge(5), None);
+        incomplete.status = OperationStatus::Signed;
+        let mut malformed = record(5, challenge(6), Some(signed(1, 16, &challenge(6))));
+        malformed.signature.as_mut().unwrap().pop();
+
+        assert_eq!(good.verify(), Some(Ok(())));
+        assert_eq!(rejected.verify(), None);
+        let verification = verify_records(&[good, rejected, tampered, incomplete, malformed]);
+        assert_eq!((verification.verified, verification.skipped), (1, 1));
+        let problems = verification
+            .flagged
+            .iter()
+            .map(|(record, problem)| (record.id, *problem))
+            .collect::<Vec<_>>();
+        assert_eq!(problems, [
+            (3, RecordProblem::InvalidSignature),
+            (4, RecordProblem::Incomplete),
+            (5, RecordProblem::Malformed),
+        ]);
+        assert_eq!(verification.to_string(), "1 verified, 1 without a signature, 3 flagged");
+    }
+
+    #[test]
+    fn the_device_counter_reveals_unrecorded_signatures() {
+        let history = history("counter");
+        assert_eq!(history.last_device_counter().unwrap(), None);
+        assert_eq!(history.unrecorded_signatures(5).unwrap(), 0);
+        fill(&history);
+        assert_eq!(history.last_device_counter().unwrap(), Some(3));
+        assert_eq!(history.unrecorded_signatures(3).unwrap(), 0);
+        assert_eq!(history.unrecorded_signatures(5).unwrap(), 2);
+        // A counter behind the history is not a signature
+        assert_eq!(history.unrecorded_signatures(1).unwrap(), 0);
+    }
+
+    #[test]
+    fn outputs_are_reconciled_with_the_output_counter() {
+        let history = history("outputs");
+        let output = |n| Challenge::from_hashed(CHANGE_OUTPUT_LABEL, challenge(n));
+        assert_eq!(
+            history.reconcile_outputs(5, false).unwrap(),
+            OutputReconciliation::Baseline { device_counter: 5 }
+        );
+        history.record_signed_output(&output(1)).unwrap();
+        history.record_signed_output(&output(2)).unwrap();
+        assert_eq!(
+            history.reconcile_outputs(7, false).unwrap(),
+            OutputReconciliation::Reconciled {
+                device_counter: 7,
+                outputs: 2
+            }
+        );
+
+        // The alert repeats until it is accepted
+        let unrecorded = OutputReconciliation::Unrecorded {
+            device_counter: 9,
+            unrecorded: 2,
+        };
+        assert_eq!(history.reconcile_outputs(9, false).unwrap(), unrecorded);
+        assert!(!unrecorded.is_clean());
+        assert_eq!(history.reconcile_outputs(9, false).unwrap(), unrecorded);
+        assert_eq!(history.reconcile_outputs(9, true).unwrap(), unrecorded);
+        assert_eq!(
+            history.reconcile_outputs(9, false).unwrap(),
+            OutputReconciliation::Reconciled {
+                device_counter: 9,
+                outputs: 0
+            }
+        );
+
+        assert_eq!(
+            history.reconcile_outputs(3, false).unwrap(),
+            OutputReconciliation::CounterReset {
+                device_counter: 3,
+                last_counter: 9
+            }
+        );
+    }
+
+    #[test]
+    fn completed_requests_are_kept() {
+        let history = history("idempotency");
+        assert_eq!(history.completed_request(&[1; 32]).unwrap(), None);
+        history.complete_request(&[1; 32], b"first").unwrap();
+        history.complete_request(&[2; 32], b"other").unwrap();
+        assert_eq!(history.completed_request(&[1; 32]).unwrap(), Some(b"first".to_vec()));
+        history.complete_request(&[1; 32], b"second").unwrap();
+        assert_eq!(history.completed_request(&[1; 32]).unwrap(), Some(b"second".to_vec()));
+    }
+
+    #[test]
+    fn a_wrong_passphrase_is_refused() {
+        let path = std::env::temp_dir().join(format!("tari-ledger-history-passphrase-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        fill(&SigningHistory::open(&path, "right").unwrap());
+        assert!(matches!(
+            SigningHistory::open(&path, "wrong"),
+            Err(StoreError::Backend(_))
+        ));
+        assert_eq!(
+            SigningHistory::open(&path, "right")
+                .unwrap()
+                .records(None)
+                .unwrap()
+                .len(),
+            3
+        );
+    }
+
+    #[test]
+    fn times_and_formats_parse() {
+        assert_eq!(parse_time("1970-01-02"), Some(86_400));
+        assert_eq!(parse_time("1970-01-01T00:01:00Z"), Some(60));
+        assert_eq!(parse_time("1970-01-01T01:00:00+01:00"), Some(0));
+        assert_eq!(parse_time("yesterday"), None);
+        assert_eq!(format_time(60), "1970-01-01T00:01:00+00:00");
+
+        assert_eq!("csv".parse::<ExportFormat>(), Ok(ExportFormat::Csv));
+        assert_eq!("json".parse::<ExportFormat>(), Ok(ExportFormat::Json));
+        assert!("xml".parse::<ExportFormat>().is_err());
+    }
+}
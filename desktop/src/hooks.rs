@@ -0,0 +1,365 @@
+//! Approval hooks of the daemon
+//! The [`daemon`](crate::daemon) passes on the APDUs of every invocation that connects to it, so a signing prompt can
+//! appear on the device without the user having just run a command. [`ApprovalHooks`] run before every command that
+//! [`needs_confirmation`] reaches the device: a [`ApprovalHook::Notify`] shows a desktop notification, a
+//! [`ApprovalHook::Webhook`] posts the request to a URL, e.g. a Slack incoming webhook, and a
+//! [`ApprovalHook::Command`] runs a program that has to approve the request on top of the confirmation on the device.
+//!
+//! Notifications and webhooks only tell someone about a request, one that fails is reported and the request goes
+//! ahead. A command that cannot be run, exits with anything but 0 or is still running when the prompt times out refuses
+//! it. The request is written to the standard input of webhooks and commands as [`SigningRequest::to_json`] writes it.
+
+use std::{
+    fmt,
+    io::{self, Write},
+    path::PathBuf,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use ledger_transport::APDUCommand;
+use tari_crypto::tari_utilities::hex::to_hex;
+use tari_ledger_protocol::{
+    TransactionSummary,
+    P1_KERNEL_NONCE,
+    P1_NONCE_POOL_SIGN,
+    P1_SENDER_OFFSET_SIGN,
+    P1_SETTINGS_SET,
+    P1_TRANSACTION_CANCEL,
+};
+
+use crate::{device::Instruction, errors::DeviceError};
+
+/// How long a webhook has to answer before it counts as failed
+const WEBHOOK_TIMEOUT_SECS: &str = "10";
+/// How often a hook that is still running is looked at
+const HOOK_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Whether the daemon has `command` approved on the host before the device sees it: every command that approves or
+/// signs something, reveals a secret or changes the app, and every command it does not know. `BPData` multiplies any
+/// scalar with the app key, so the host learns products of the key it could not compute itself.
+pub fn needs_confirmation(command: &APDUCommand<Vec<u8>>) -> bool {
+    match Instruction::try_from(command.ins) {
+        Ok(
+            Instruction::Sign |
+            Instruction::BPData |
+            Instruction::SwapPreimage |
+            Instruction::ExportPrivateKey |
+            Instruction::Pairing,
+        ) => true,
+        Ok(Instruction::SenderOffset) => command.p1 == P1_SENDER_OFFSET_SIGN,
+        Ok(Instruction::TransactionSummary) => command.p1 != P1_TRANSACTION_CANCEL,
+        Ok(Instruction::KernelSignature) => command.p1 == P1_KERNEL_NONCE,
+        Ok(Instruction::NoncePool) => command.p1 == P1_NONCE_POOL_SIGN,
+        Ok(Instruction::AppSettings) => command.p1 == P1_SETTINGS_SET,
+        Ok(_) => false,
+        Err(()) => true,
+    }
+}
+
+/// What a command asks the device for, as far as the host can tell from its data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SigningRequest {
+    /// The name of the instruction in the protocol spec
+    pub instruction: &'static str,
+    pub p1: u8,
+    /// The transaction summary or the challenge, for the commands that carry one
+    pub detail: Option<String>,
+}
+
+impl SigningRequest {
+    pub fn from_command(command: &APDUCommand<Vec<u8>>) -> Self {
+        let instruction = Instruction::try_from(command.ins).ok();
+        let detail = match instruction {
+            Some(Instruction::TransactionSummary) => command
+                .data
+                .get(..TransactionSummary::ENCODED_LENGTH)
+                .and_then(|summary| TransactionSummary::from_le_bytes(summary).ok())
+                .map(|summary| summary.to_string()),
+            Some(Instruction::Sign) => command
+                .data
+                .get(..32)
+                .map(|challenge| format!("challenge {}", to_hex(challenge))),
+            _ => None,
+        };
+        Self {
+            instruction: instruction.map_or("an unknown instruction", |instruction| instruction.spec().name),
+            p1: command.p1,
+            detail,
+        }
+    }
+
+    /// The request as a JSON object, with a `text` field that chat webhooks show as the message
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"text\":{},\"instruction\":{},\"p1\":{},\"detail\":{}}}",
+            json_string(&format!("tari-ledger: {}", self)),
+            json_string(self.instruction),
+            self.p1,
+            self.detail.as_deref().map_or("null".to_string(), json_string)
+        )
+    }
+}
+
+impl fmt::Display for SigningRequest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.detail {
+            Some(detail) => write!(f, "{} of {}", self.instruction, detail),
+            None => write!(f, "{}", self.instruction),
+        }
+    }
+}
+
+/// `value` as a JSON string
+fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", u32::from(c))),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// One thing done with a request before it reaches the device
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ApprovalHook {
+    /// A desktop notification, with `notify-send` or on macOS `osascript`
+    Notify,
+    /// A POST of the request to the URL, with `curl`
+    Webhook(String),
+    /// A program that has to exit with 0 to approve the request
+    Command(PathBuf),
+}
+
+impl ApprovalHook {
+    /// Whether the hook lets `request` go ahead, by `deadline`
+    fn run(&self, request: &SigningRequest, deadline: Instant) -> Result<bool, DeviceError> {
+        let failed = |e: io::Error| DeviceError::ApprovalHookFailed(format!("{}: {}", self, e));
+        match self {
+            ApprovalHook::Notify => {
+                let mut command = if cfg!(target_os = "macos") {
+                    let mut command = Command::new("osascript");
+                    command.arg("-e").arg(format!(
+                        "display notification {} with title \"tari-ledger\"",
+                        json_string(&request.to_string())
+                    ));
+                    command
+                } else {
+                    let mut command = Command::new("notify-send");
+                    command.arg("tari-ledger").arg(request.to_string());
+                    command
+                };
+                if !run(&mut command, None, deadline).map_err(failed)? {
+                    return Err(DeviceError::ApprovalHookFailed(format!("{} could not be shown", self)));
+                }
+                Ok(true)
+            },
+            ApprovalHook::Webhook(url) => {
+                let mut command = Command::new("curl");
+                command
+                    .args(["--silent", "--show-error", "--fail", "--max-time", WEBHOOK_TIMEOUT_SECS])
+                    .args(["--header", "Content-Type: application/json", "--data-binary", "@-"])
+                    .arg(url);
+                if !run(&mut command, Some(&request.to_json()), deadline).map_err(failed)? {
+                    return Err(DeviceError::ApprovalHookFailed(format!(
+                        "{} did not accept the request",
+                        self
+                    )));
+                }
+                Ok(true)
+            },
+            ApprovalHook::Command(program) => {
+                run(&mut Command::new(program), Some(&request.to_json()), deadline).map_err(failed)
+            },
+        }
+    }
+}
+
+impl fmt::Display for ApprovalHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ApprovalHook::Notify => write!(f, "the desktop notification"),
+            ApprovalHook::Webhook(url) => write!(f, "the webhook {}", url),
+            ApprovalHook::Command(program) => write!(f, "the approval command {}", program.display()),
+        }
+    }
+}
+
+/// Run `command` with `input` on its standard input until it exits, and whether it exited with 0. One that is still
+/// running at `deadline` is killed and fails.
+fn run(command: &mut Command, input: Option<&str>, deadline: Instant) -> io::Result<bool> {
+    let mut child = command
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::null())
+        .spawn()?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        // A program that does not read its input closes the pipe early, which is its business
+        let _ = stdin.write_all(input.as_bytes());
+    }
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status.success());
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "it was still running when the prompt timed out",
+            ));
+        }
+        thread::sleep(HOOK_POLL_INTERVAL);
+    }
+}
+
+/// The hooks that every command that [`needs_confirmation`] goes through, in order
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ApprovalHooks {
+    hooks: Vec<ApprovalHook>,
+}
+
+impl ApprovalHooks {
+    pub fn new(hooks: Vec<ApprovalHook>) -> Self {
+        Self { hooks }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    /// Whether `command` may go to the device, decided by `deadline`. Every hook runs until one refuses, and
+    /// `on_error` is told about those that failed.
+    pub fn approve(
+        &self,
+        command: &APDUCommand<Vec<u8>>,
+        deadline: Instant,
+        mut on_error: impl FnMut(&DeviceError),
+    ) -> bool {
+        let request = SigningRequest::from_command(command);
+        for hook in &self.hooks {
+            match hook.run(&request, deadline) {
+                Ok(true) => {},
+                Ok(false) => return false,
+                Err(e) => {
+                    on_error(&e);
+                    if matches!(hook, ApprovalHook::Command(_)) {
+                        return false;
+                    }
+                },
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_helpers::command;
+
+    /// A command of an instruction the app does not know
+    fn unknown() -> APDUCommand<Vec<u8>> {
+        APDUCommand {
+            ins: 0xff,
+            ..command(Instruction::GetVersion, 0)
+        }
+    }
+
+    #[test]
+    fn signing_commands_need_confirmation() {
+        assert!(needs_confirmation(&command(Instruction::Sign, 0)));
+        assert!(needs_confirmation(&command(
+            Instruction::KernelSignature,
+            P1_KERNEL_NONCE
+        )));
+        assert!(!needs_confirmation(&command(Instruction::GetVersion, 0)));
+        assert!(needs_confirmation(&command(Instruction::BPData, 0)));
+        assert!(needs_confirmation(&command(
+            Instruction::SenderOffset,
+            P1_SENDER_OFFSET_SIGN
+        )));
+        assert!(needs_confirmation(&unknown()));
+        assert!(!needs_confirmation(&command(
+            Instruction::TransactionSummary,
+            P1_TRANSACTION_CANCEL
+        )));
+    }
+
+    #[test]
+    fn requests_describe_the_summary() {
+        let summary = TransactionSummary {
+            total_out: 1_000,
+            fee: 5,
+            recipient_count: 1,
+            output_count: 2,
+            session_nonce: 9,
+        };
+        let request = SigningRequest::from_command(&APDUCommand {
+            data: summary.to_le_bytes().to_vec(),
+            ..command(Instruction::TransactionSummary, 0)
+        });
+        assert_eq!(request.detail, Some(summary.to_string()));
+        assert!(request
+            .to_json()
+            .starts_with("{\"text\":\"tari-ledger: TransactionSummary of 1000 uT"));
+        assert_eq!(SigningRequest::from_command(&unknown()).detail, None);
+    }
+
+    #[test]
+    fn json_strings_are_escaped() {
+        assert_eq!(json_string("a\"b\\c\n"), "\"a\\\"b\\\\c\\u000a\"");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn commands_approve_with_their_exit_status() {
+        let request = APDUCommand {
+            data: vec![0; 32],
+            ..command(Instruction::Sign, 0)
+        };
+        let deadline = Instant::now() + Duration::from_secs(60);
+        let mut errors = 0;
+        assert!(
+            ApprovalHooks::new(vec![ApprovalHook::Command("true".into())]).approve(&request, deadline, |_| errors += 1)
+        );
+        assert!(!ApprovalHooks::new(vec![ApprovalHook::Command("false".into())])
+            .approve(&request, deadline, |_| errors += 1));
+        assert_eq!(errors, 0);
+        assert!(
+            !ApprovalHooks::new(vec![ApprovalHook::Command("/nonexistent/approve".into())]).approve(
+                &request,
+                deadline,
+                |_| errors += 1
+            )
+        );
+        assert_eq!(errors, 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_command_that_outlasts_the_prompt_refuses() {
+        use std::{fs, os::unix::fs::PermissionsExt};
+
+        let program = std::env::temp_dir().join(format!("tari-ledger-slow-approval-{}", std::process::id()));
+        fs::write(&program, "#!/bin/sh\nsleep 10\n").unwrap();
+        fs::set_permissions(&program, fs::Permissions::from_mode(0o755)).unwrap();
+        let start = Instant::now();
+        let mut errors = 0;
+        let approved = ApprovalHooks::new(vec![ApprovalHook::Command(program.clone())]).approve(
+            &command(Instruction::Sign, 0),
+            start + Duration::from_millis(200),
+            |_| errors += 1,
+        );
+        fs::remove_file(&program).unwrap();
+        assert!(!approved);
+        assert_eq!(errors, 1);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,145 @@
+//! Time locked and hash time locked (HTLC) output scripts
+//! The HTLC script is the one from the Tari atomic swap RFC: the output can be claimed with the preimage of `hash`, or
+//! refunded once the chain reaches `timeout`.
+
+use sha2::{Digest, Sha256};
+use tari_crypto::ristretto::RistrettoPublicKey;
+
+use crate::{
+    errors::{ScriptError, SignerError},
+    hashing::Challenge,
+    interpreter::{expected_key, ScriptContext},
+    script::{script_signature_message, ExecutionStack, Opcode, StackItem, TariScript},
+    signature::LedgerSignature,
+    signer::LedgerTransactionSigner,
+};
+
+/// An output that can only be spent by `key`, and not before block `height`
+#[derive(Clone, Debug)]
+pub struct TimeLock {
+    pub height: u64,
+    pub key: RistrettoPublicKey,
+}
+
+impl TimeLock {
+    pub fn script(&self) -> TariScript {
+        TariScript::new(vec![
+            Opcode::CheckHeightVerify(self.height),
+            Opcode::PushPubKey(self.key.clone()),
+        ])
+    }
+
+    /// The message to sign when spending the output, the script takes no input data
+    pub fn spend_message(&self) -> Challenge {
+        script_signature_message(&self.script(), &ExecutionStack::default())
+    }
+}
+
+/// An output that `claim_key` can spend by revealing the SHA-256 preimage of `hash`, or that `refund_key` can spend
+/// from block `timeout` onwards
+#[derive(Clone, Debug)]
+pub struct HashTimeLock {
+    pub hash: [u8; 32],
+    pub timeout: u64,
+    pub claim_key: RistrettoPublicKey,
+    pub refund_key: RistrettoPublicKey,
+}
+
+impl HashTimeLock {
+    /// Lock to the hash of `preimage`
+    pub fn from_preimage(
+        preimage: &[u8; 32],
+        timeout: u64,
+        claim_key: RistrettoPublicKey,
+        refund_key: RistrettoPublicKey,
+    ) -> Self {
+        Self {
+            hash: sha256(preimage),
+            timeout,
+            claim_key,
+            refund_key,
+        }
+    }
+
+    pub fn script(&self) -> TariScript {
+        TariScript::new(vec![
+            Opcode::HashSha256,
+            Opcode::PushHash(self.hash),
+            Opcode::Equal,
+            Opcode::IfThen,
+            Opcode::PushPubKey(self.claim_key.clone()),
+            Opcode::Else,
+            Opcode::CheckHeightVerify(self.timeout),
+            Opcode::PushPubKey(self.refund_key.clone()),
+            Opcode::EndIf,
+        ])
+    }
+
+    /// Whether `preimage` unlocks the claim path
+    pub fn is_preimage(&self, preimage: &[u8; 32]) -> bool {
+        sha256(preimage) == self.hash
+    }
+
+    pub fn claim_input_data(preimage: &[u8; 32]) -> ExecutionStack {
+        ExecutionStack::new(vec![StackItem::Hash(*preimage)])
+    }
+
+    /// Any value that does not hash to `hash` selects the refund path
+    pub fn refund_input_data() -> ExecutionStack {
+        ExecutionStack::new(vec![StackItem::Hash([0u8; 32])])
+    }
+
+    pub fn claim_message(&self, preimage: &[u8; 32]) -> Challenge {
+        script_signature_message(&self.script(), &Self::claim_input_data(preimage))
+    }
+
+    pub fn refund_message(&self) -> Challenge {
+        script_signature_message(&self.script(), &Self::refund_input_data())
+    }
+
+    /// Run the refund path as if mined at `block_height`, failing unless the lock has timed out by then
+    pub fn preview_refund(&self, block_height: u64) -> Result<RistrettoPublicKey, ScriptError> {
+        expected_key(
+            &self.script(),
+            &Self::refund_input_data(),
+            &ScriptContext::at_height(block_height),
+        )
+    }
+
+    /// Sign the claim path with the device. Fails without prompting the user if `preimage` does not match.
+    pub fn sign_claim(
+        &self,
+        signer: &LedgerTransactionSigner,
+        preimage: &[u8; 32],
+    ) -> Result<LedgerSignature, SignerError> {
+        if !self.is_preimage(preimage) {
+            return Err(SignerError::InvalidPreimage);
+        }
+        // The claim path does not check the height
+        let key = expected_key(
+            &self.script(),
+            &Self::claim_input_data(preimage),
+            &ScriptContext::default(),
+        )?;
+        let signature = signer.sign_script_message(&self.claim_message(preimage))?;
+        check_key(signature, &key)
+    }
+
+    /// Sign the refund path with the device. The signature is only accepted by the base layer from block `timeout`.
+    pub fn sign_refund(&self, signer: &LedgerTransactionSigner) -> Result<LedgerSignature, SignerError> {
+        let signature = signer.sign_script_message(&self.refund_message())?;
+        check_key(signature, &self.refund_key)
+    }
+}
+
+/// The script only accepts a signature from the key it pushes, anything else means the device holds a different key
+fn check_key(signature: LedgerSignature, expected: &RistrettoPublicKey) -> Result<LedgerSignature, SignerError> {
+    if &signature.public_key != expected {
+        return Err(SignerError::KeyMismatch);
+    }
+    Ok(signature)
+}
+
+fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
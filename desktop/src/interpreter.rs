@@ -0,0 +1,609 @@
+//! Host side execution of TariScript
+//! The base layer only accepts a spend if the script, run against the input data, leaves a single public key on the
+//! stack, and the script signature is made with that key. Running the script here first shows which key the device
+//! will have to sign with, and catches input data that can never satisfy the script before the user is asked to
+//! confirm anything. Only the base layer can tell the real block height, so a script that checks it is run against the
+//! height the caller expects the spend to be mined at.
+
+use sha2::{Digest, Sha256};
+use tari_crypto::{
+    hash::blake2::Blake256,
+    keys::PublicKey,
+    ristretto::{RistrettoPublicKey, RistrettoSecretKey},
+    tari_utilities::ByteArray,
+};
+
+use crate::{
+    errors::ScriptError,
+    script::{ExecutionStack, Opcode, StackItem, TariScript, MAX_STACK_SIZE},
+    verify::verify_script_signature,
+};
+
+/// What a script can observe of the chain when it is executed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ScriptContext {
+    pub block_height: u64,
+}
+
+impl ScriptContext {
+    pub fn at_height(block_height: u64) -> Self {
+        Self { block_height }
+    }
+}
+
+/// Run `script` against `input` and return the single item it leaves on the stack
+pub fn execute(script: &TariScript, input: &ExecutionStack, context: &ScriptContext) -> Result<StackItem, ScriptError> {
+    let mut stack = Stack(input.items.clone());
+    // One entry per enclosing `IfThen`: whether its branch is being taken, and whether its `Else` has been seen
+    let mut branches: Vec<(bool, bool)> = Vec::new();
+    for opcode in &script.opcodes {
+        let executing = branches.iter().all(|(taken, _)| *taken);
+        match opcode {
+            Opcode::IfThen => {
+                let taken = if executing {
+                    match stack.pop_number()? {
+                        1 => true,
+                        0 => false,
+                        _ => return Err(ScriptError::InvalidBranch),
+                    }
+                } else {
+                    false
+                };
+                branches.push((taken, false));
+            },
+            Opcode::Else => match branches.last_mut() {
+                Some((taken, seen_else)) if !*seen_else => {
+                    *taken = !*taken;
+                    *seen_else = true;
+                },
+                _ => return Err(ScriptError::InvalidBranch),
+            },
+            Opcode::EndIf => {
+                branches.pop().ok_or(ScriptError::InvalidBranch)?;
+            },
+            opcode if executing => execute_opcode(opcode, &mut stack, context)?,
+            _ => {},
+        }
+    }
+    if !branches.is_empty() {
+        return Err(ScriptError::InvalidBranch);
+    }
+    match stack.0.len() {
+        1 => stack.pop(),
+        _ => Err(ScriptError::NonUnitLengthStack),
+    }
+}
+
+/// Run `script` against `input` and return the public key the script signature has to be made with
+pub fn expected_key(
+    script: &TariScript,
+    input: &ExecutionStack,
+    context: &ScriptContext,
+) -> Result<RistrettoPublicKey, ScriptError> {
+    match execute(script, input, context)? {
+        StackItem::PublicKey(key) => Ok(key),
+        _ => Err(ScriptError::NotAPublicKey),
+    }
+}
+
+fn execute_opcode(opcode: &Opcode, stack: &mut Stack, context: &ScriptContext) -> Result<(), ScriptError> {
+    use Opcode::*;
+    let height = || i64::try_from(context.block_height).map_err(|_| ScriptError::ValueExceedsBounds);
+    match opcode {
+        CheckHeightVerify(min_height) => {
+            if context.block_height < *min_height {
+                return Err(ScriptError::VerifyFailed);
+            }
+        },
+        CheckHeight(min_height) => {
+            let min_height = i64::try_from(*min_height).map_err(|_| ScriptError::ValueExceedsBounds)?;
+            stack.push(StackItem::Number(checked(height()?.checked_sub(min_height))?))?;
+        },
+        CompareHeightVerify => {
+            if height()? < stack.pop_number()? {
+                return Err(ScriptError::VerifyFailed);
+            }
+        },
+        CompareHeight => {
+            let target = stack.pop_number()?;
+            stack.push(StackItem::Number(checked(height()?.checked_sub(target))?))?;
+        },
+        Nop => {},
+        PushZero => stack.push(StackItem::Number(0))?,
+        PushOne => stack.push(StackItem::Number(1))?,
+        PushHash(hash) => stack.push(StackItem::Hash(*hash))?,
+        PushInt(n) => stack.push(StackItem::Number(*n))?,
+        PushPubKey(key) => stack.push(StackItem::PublicKey(key.clone()))?,
+        Drop => {
+            stack.pop()?;
+        },
+        Dup => {
+            let top = stack.0.last().cloned().ok_or(ScriptError::StackUnderflow)?;
+            stack.push(top)?;
+        },
+        RevRot => {
+            // The top item moves to third place
+            if stack.0.len() < 3 {
+                return Err(ScriptError::StackUnderflow);
+            }
+            let top = stack.pop()?;
+            let at = stack.0.len() - 2;
+            stack.0.insert(at, top);
+        },
+        GeZero => compare_zero(stack, |n| n >= 0)?,
+        GtZero => compare_zero(stack, |n| n > 0)?,
+        LeZero => compare_zero(stack, |n| n <= 0)?,
+        LtZero => compare_zero(stack, |n| n < 0)?,
+        Add => {
+            let (a, b) = stack.pop_pair()?;
+            let sum = match (a, b) {
+                (StackItem::Number(a), StackItem::Number(b)) => StackItem::Number(checked(a.checked_add(b))?),
+                (StackItem::Commitment(a), StackItem::Commitment(b)) => StackItem::Commitment(&a + &b),
+                (StackItem::PublicKey(a), StackItem::PublicKey(b)) => StackItem::PublicKey(&a + &b),
+                _ => return Err(ScriptError::IncompatibleTypes),
+            };
+            stack.push(sum)?;
+        },
+        Sub => {
+            let (a, b) = stack.pop_pair()?;
+            let difference = match (a, b) {
+                (StackItem::Number(a), StackItem::Number(b)) => StackItem::Number(checked(a.checked_sub(b))?),
+                (StackItem::Commitment(a), StackItem::Commitment(b)) => StackItem::Commitment(&a - &b),
+                _ => return Err(ScriptError::IncompatibleTypes),
+            };
+            stack.push(difference)?;
+        },
+        Equal => {
+            let (a, b) = stack.pop_pair()?;
+            stack.push(StackItem::Number(i64::from(a == b)))?;
+        },
+        EqualVerify => {
+            let (a, b) = stack.pop_pair()?;
+            if a != b {
+                return Err(ScriptError::VerifyFailed);
+            }
+        },
+        Or(n) | OrVerify(n) => {
+            let candidates = (0..*n).map(|_| stack.pop()).collect::<Result<Vec<_>, _>>()?;
+            let item = stack.pop()?;
+            let found = candidates.contains(&item);
+            match opcode {
+                Or(_) => stack.push(StackItem::Number(i64::from(found)))?,
+                _ if !found => return Err(ScriptError::VerifyFailed),
+                _ => {},
+            }
+        },
+        HashBlake256 => {
+            let data = item_data(&stack.pop()?);
+            stack.push(StackItem::Hash(Blake256::digest(data).into()))?;
+        },
+        HashSha256 => {
+            let data = item_data(&stack.pop()?);
+            stack.push(StackItem::Hash(Sha256::digest(data).into()))?;
+        },
+        HashSha3 => return Err(ScriptError::Unsupported("HashSha3")),
+        CheckSig(message) | CheckSigVerify(message) => {
+            let key = match stack.pop()? {
+                StackItem::PublicKey(key) => key,
+                _ => return Err(ScriptError::IncompatibleTypes),
+            };
+            let signature = match stack.pop()? {
+                StackItem::Signature(signature) => signature,
+                _ => return Err(ScriptError::IncompatibleTypes),
+            };
+            let valid = verify_script_signature(&key, &signature, message);
+            match opcode {
+                CheckSig(_) => stack.push(StackItem::Number(i64::from(valid)))?,
+                _ if !valid => return Err(ScriptError::VerifyFailed),
+                _ => {},
+            }
+        },
+        CheckMultiSig(m, n, keys, message) |
+        CheckMultiSigVerify(m, n, keys, message) |
+        CheckMultiSigVerifyAggregatePubKey(m, n, keys, message) => {
+            if m > n || usize::from(*n) != keys.len() {
+                return Err(ScriptError::ValueExceedsBounds);
+            }
+            let signatures = (0..*m)
+                .map(|_| match stack.pop()? {
+                    StackItem::Signature(signature) => Ok(signature),
+                    _ => Err(ScriptError::IncompatibleTypes),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            // Every key can vouch for at most one signature
+            let mut unused = keys.iter().collect::<Vec<_>>();
+            let mut signers = Vec::with_capacity(signatures.len());
+            for signature in &signatures {
+                if let Some(i) = unused
+                    .iter()
+                    .position(|key| verify_script_signature(key, signature, message))
+                {
+                    signers.push(unused.remove(i));
+                }
+            }
+            let valid = signers.len() == signatures.len();
+            match opcode {
+                CheckMultiSig(..) => stack.push(StackItem::Number(i64::from(valid)))?,
+                _ if !valid => return Err(ScriptError::VerifyFailed),
+                CheckMultiSigVerifyAggregatePubKey(..) => {
+                    let aggregate = signers
+                        .into_iter()
+                        .fold(RistrettoPublicKey::default(), |total, key| &total + key);
+                    stack.push(StackItem::PublicKey(aggregate))?;
+                },
+                _ => {},
+            }
+        },
+        ToRistrettoPoint => {
+            let scalar = match stack.pop()? {
+                StackItem::Scalar(bytes) | StackItem::Hash(bytes) => bytes,
+                _ => return Err(ScriptError::IncompatibleTypes),
+            };
+            let secret = RistrettoSecretKey::from_bytes(&scalar).map_err(|_| ScriptError::InvalidData)?;
+            stack.push(StackItem::PublicKey(RistrettoPublicKey::from_secret_key(&secret)))?;
+        },
+        Return => return Err(ScriptError::Returned),
+        // Branches are tracked by `execute`
+        IfThen | Else | EndIf => {},
+    }
+    Ok(())
+}
+
+fn compare_zero(stack: &mut Stack, predicate: impl Fn(i64) -> bool) -> Result<(), ScriptError> {
+    let n = stack.pop_number()?;
+    stack.push(StackItem::Number(i64::from(predicate(n))))
+}
+
+fn checked(n: Option<i64>) -> Result<i64, ScriptError> {
+    n.ok_or(ScriptError::ValueExceedsBounds)
+}
+
+/// The bytes a hash opcode hashes, the item without its type
+fn item_data(item: &StackItem) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    item.write_bytes(&mut bytes);
+    bytes.split_off(1)
+}
+
+/// The execution stack, its last item being the top
+struct Stack(Vec<StackItem>);
+
+impl Stack {
+    fn push(&mut self, item: StackItem) -> Result<(), ScriptError> {
+        if self.0.len() >= MAX_STACK_SIZE {
+            return Err(ScriptError::StackOverflow);
+        }
+        self.0.push(item);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<StackItem, ScriptError> {
+        self.0.pop().ok_or(ScriptError::StackUnderflow)
+    }
+
+    fn pop_number(&mut self) -> Result<i64, ScriptError> {
+        match self.pop()? {
+            StackItem::Number(n) => Ok(n),
+            _ => Err(ScriptError::IncompatibleTypes),
+        }
+    }
+
+    /// Pop the top two items, the top one last
+    fn pop_pair(&mut self) -> Result<(StackItem, StackItem), ScriptError> {
+        let b = self.pop()?;
+        let a = self.pop()?;
+        Ok((a, b))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tari_crypto::ristretto::RistrettoSchnorr;
+
+    use super::*;
+    use crate::verify::script_challenge;
+
+    fn run(opcodes: Vec<Opcode>, input: Vec<StackItem>) -> Result<StackItem, ScriptError> {
+        execute(
+            &TariScript::new(opcodes),
+            &ExecutionStack::new(input),
+            &ScriptContext::at_height(100),
+        )
+    }
+
+    fn key(n: u64) -> RistrettoPublicKey {
+        RistrettoPublicKey::from_secret_key(&RistrettoSecretKey::from(n))
+    }
+
+    /// The script signature of the key `n` over `message`
+    fn signature(n: u64, message: &[u8; 32]) -> RistrettoSchnorr {
+        let k = RistrettoSecretKey::from(n);
+        let r = RistrettoSecretKey::from(n + 1000);
+        let public_nonce = RistrettoPublicKey::from_secret_key(&r);
+        let e = RistrettoSecretKey::from_bytes(&script_challenge(&key(n), &public_nonce, message)).unwrap();
+        RistrettoSchnorr::new(public_nonce, &r + &(&e * &k))
+    }
+
+    #[test]
+    fn pushes_and_stack_manipulation() {
+        use Opcode::*;
+        assert_eq!(run(vec![PushZero], vec![]), Ok(StackItem::Number(0)));
+        assert_eq!(run(vec![PushOne, Nop], vec![]), Ok(StackItem::Number(1)));
+        assert_eq!(run(vec![PushInt(-7)], vec![]), Ok(StackItem::Number(-7)));
+        assert_eq!(run(vec![PushHash([3; 32])], vec![]), Ok(StackItem::Hash([3; 32])));
+        assert_eq!(run(vec![PushPubKey(key(1))], vec![]), Ok(StackItem::PublicKey(key(1))));
+        assert_eq!(
+            run(vec![PushInt(2), PushInt(3), Drop], vec![]),
+            Ok(StackItem::Number(2))
+        );
+        assert_eq!(run(vec![PushInt(2), Dup, Add], vec![]), Ok(StackItem::Number(4)));
+        // 1 2 3 becomes 3 1 2, so what is left is 3 - (1 - 2)
+        assert_eq!(
+            run(vec![RevRot, Sub, Sub], vec![
+                StackItem::Number(1),
+                StackItem::Number(2),
+                StackItem::Number(3)
+            ]),
+            Ok(StackItem::Number(4))
+        );
+    }
+
+    #[test]
+    fn arithmetic_and_comparisons() {
+        use Opcode::*;
+        assert_eq!(run(vec![PushInt(5), PushInt(3), Sub], vec![]), Ok(StackItem::Number(2)));
+        assert_eq!(run(vec![PushInt(5), PushInt(3), Add], vec![]), Ok(StackItem::Number(8)));
+        assert_eq!(
+            run(vec![PushPubKey(key(1)), PushPubKey(key(2)), Add], vec![]),
+            Ok(StackItem::PublicKey(key(3)))
+        );
+        assert_eq!(
+            run(vec![PushInt(i64::MAX), PushOne, Add], vec![]),
+            Err(ScriptError::ValueExceedsBounds)
+        );
+        assert_eq!(
+            run(vec![PushPubKey(key(1)), PushPubKey(key(2)), Sub], vec![]),
+            Err(ScriptError::IncompatibleTypes)
+        );
+        for (opcode, results) in [
+            (GeZero, [1, 1, 0]),
+            (GtZero, [1, 0, 0]),
+            (LeZero, [0, 1, 1]),
+            (LtZero, [0, 0, 1]),
+        ] {
+            for (n, result) in [1, 0, -1].into_iter().zip(results) {
+                assert_eq!(
+                    run(vec![PushInt(n), opcode.clone()], vec![]),
+                    Ok(StackItem::Number(result)),
+                    "{:?} of {}",
+                    opcode,
+                    n
+                );
+            }
+        }
+        assert_eq!(
+            run(vec![PushInt(4), PushInt(4), Equal], vec![]),
+            Ok(StackItem::Number(1))
+        );
+        assert_eq!(
+            run(vec![PushInt(4), PushInt(5), Equal], vec![]),
+            Ok(StackItem::Number(0))
+        );
+        assert_eq!(
+            run(vec![PushInt(4), PushInt(5), EqualVerify, PushOne], vec![]),
+            Err(ScriptError::VerifyFailed)
+        );
+        assert_eq!(
+            run(vec![PushInt(2), PushInt(1), PushInt(2), Or(2)], vec![]),
+            Ok(StackItem::Number(1))
+        );
+        assert_eq!(
+            run(vec![PushInt(3), PushInt(1), PushInt(2), OrVerify(2), PushOne], vec![]),
+            Err(ScriptError::VerifyFailed)
+        );
+    }
+
+    #[test]
+    fn heights() {
+        use Opcode::*;
+        assert_eq!(run(vec![CheckHeight(40)], vec![]), Ok(StackItem::Number(60)));
+        assert_eq!(run(vec![CheckHeight(140)], vec![]), Ok(StackItem::Number(-40)));
+        assert_eq!(
+            run(vec![CheckHeightVerify(100), PushOne], vec![]),
+            Ok(StackItem::Number(1))
+        );
+        assert_eq!(
+            run(vec![CheckHeightVerify(101), PushOne], vec![]),
+            Err(ScriptError::VerifyFailed)
+        );
+        assert_eq!(run(vec![PushInt(30), CompareHeight], vec![]), Ok(StackItem::Number(70)));
+        assert_eq!(
+            run(vec![PushInt(101), CompareHeightVerify, PushOne], vec![]),
+            Err(ScriptError::VerifyFailed)
+        );
+    }
+
+    #[test]
+    fn hashes() {
+        use Opcode::*;
+        let data = [7u8; 32];
+        assert_eq!(
+            run(vec![PushHash(data), HashSha256], vec![]),
+            Ok(StackItem::Hash(Sha256::digest(data).into()))
+        );
+        assert_eq!(
+            run(vec![PushHash(data), HashBlake256], vec![]),
+            Ok(StackItem::Hash(Blake256::digest(data).into()))
+        );
+        assert_eq!(
+            run(vec![PushHash(data), HashSha3], vec![]),
+            Err(ScriptError::Unsupported("HashSha3"))
+        );
+        // A hash is a scalar the point of which can be taken
+        let mut scalar = [0u8; 32];
+        scalar[0] = 5;
+        assert_eq!(
+            run(vec![PushHash(scalar), ToRistrettoPoint], vec![]),
+            Ok(StackItem::PublicKey(key(5)))
+        );
+        assert_eq!(
+            run(vec![PushHash([0xff; 32]), ToRistrettoPoint], vec![]),
+            Err(ScriptError::InvalidData)
+        );
+    }
+
+    #[test]
+    fn signatures() {
+        use Opcode::*;
+        let message = [9u8; 32];
+        let signed = vec![StackItem::Signature(signature(1, &message))];
+        assert_eq!(
+            run(vec![PushPubKey(key(1)), CheckSig(message)], signed.clone()),
+            Ok(StackItem::Number(1))
+        );
+        assert_eq!(
+            run(vec![PushPubKey(key(2)), CheckSig(message)], signed.clone()),
+            Ok(StackItem::Number(0))
+        );
+        assert_eq!(
+            run(
+                vec![PushPubKey(key(2)), CheckSigVerify(message), PushOne],
+                signed.clone()
+            ),
+            Err(ScriptError::VerifyFailed)
+        );
+        assert_eq!(
+            run(vec![PushPubKey(key(1)), CheckSig([8; 32])], signed),
+            Ok(StackItem::Number(0))
+        );
+
+        // Two of three, the signatures in any order, and the aggregate of the keys that signed is left
+        let keys = vec![key(1), key(2), key(3)];
+        let input = vec![
+            StackItem::Signature(signature(3, &message)),
+            StackItem::Signature(signature(1, &message)),
+        ];
+        assert_eq!(
+            run(vec![CheckMultiSig(2, 3, keys.clone(), message)], input.clone()),
+            Ok(StackItem::Number(1))
+        );
+        assert_eq!(
+            run(
+                vec![CheckMultiSigVerifyAggregatePubKey(2, 3, keys.clone(), message)],
+                input
+            ),
+            Ok(StackItem::PublicKey(key(4)))
+        );
+        // The same signature twice counts once
+        let twice = vec![
+            StackItem::Signature(signature(1, &message)),
+            StackItem::Signature(signature(1, &message)),
+        ];
+        assert_eq!(
+            run(vec![CheckMultiSig(2, 3, keys.clone(), message)], twice),
+            Ok(StackItem::Number(0))
+        );
+        assert_eq!(
+            run(vec![CheckMultiSig(3, 2, keys, message)], vec![]),
+            Err(ScriptError::ValueExceedsBounds)
+        );
+    }
+
+    #[test]
+    fn branches() {
+        use Opcode::*;
+        let script = |condition| {
+            vec![
+                PushInt(condition),
+                IfThen,
+                PushPubKey(key(1)),
+                Else,
+                PushPubKey(key(2)),
+                EndIf,
+            ]
+        };
+        assert_eq!(run(script(1), vec![]), Ok(StackItem::PublicKey(key(1))));
+        assert_eq!(run(script(0), vec![]), Ok(StackItem::PublicKey(key(2))));
+        assert_eq!(run(script(2), vec![]), Err(ScriptError::InvalidBranch));
+        // An untaken branch is not run, however it would fail
+        assert_eq!(
+            run(vec![PushZero, IfThen, Return, Else, PushOne, EndIf], vec![]),
+            Ok(StackItem::Number(1))
+        );
+        assert_eq!(
+            run(vec![PushOne, IfThen, PushOne], vec![]),
+            Err(ScriptError::InvalidBranch)
+        );
+        assert_eq!(run(vec![PushOne, EndIf], vec![]), Err(ScriptError::InvalidBranch));
+        assert_eq!(
+            run(vec![PushOne, IfThen, PushOne, Else, Else, EndIf], vec![]),
+            Err(ScriptError::InvalidBranch)
+        );
+        assert_eq!(run(vec![Return], vec![]), Err(ScriptError::Returned));
+    }
+
+    #[test]
+    fn stack_underflow_and_overflow() {
+        use Opcode::*;
+        for opcode in [
+            Drop,
+            Dup,
+            Add,
+            Sub,
+            Equal,
+            GeZero,
+            CompareHeight,
+            HashSha256,
+            ToRistrettoPoint,
+        ] {
+            assert_eq!(
+                run(vec![opcode.clone()], vec![]),
+                Err(ScriptError::StackUnderflow),
+                "{:?}",
+                opcode
+            );
+        }
+        assert_eq!(
+            run(vec![RevRot], vec![StackItem::Number(1), StackItem::Number(2)]),
+            Err(ScriptError::StackUnderflow)
+        );
+        assert_eq!(run(vec![CheckSig([0; 32])], vec![]), Err(ScriptError::StackUnderflow));
+        assert_eq!(
+            run(vec![PushOne], vec![StackItem::Number(0); MAX_STACK_SIZE]),
+            Err(ScriptError::StackOverflow)
+        );
+    }
+
+    #[test]
+    fn the_result_has_to_be_a_single_key() {
+        use Opcode::*;
+        assert_eq!(run(vec![], vec![]), Err(ScriptError::NonUnitLengthStack));
+        assert_eq!(
+            run(vec![PushOne, PushOne], vec![]),
+            Err(ScriptError::NonUnitLengthStack)
+        );
+        let context = ScriptContext::at_height(0);
+        assert_eq!(
+            expected_key(
+                &TariScript::new(vec![PushPubKey(key(1))]),
+                &ExecutionStack::default(),
+                &context
+            ),
+            Ok(key(1))
+        );
+        assert_eq!(
+            expected_key(&TariScript::new(vec![PushOne]), &ExecutionStack::default(), &context),
+            Err(ScriptError::NotAPublicKey)
+        );
+    }
+
+    #[test]
+    fn a_bad_opcode_is_refused() {
+        assert_eq!(TariScript::from_bytes(&[0xff]), Err(ScriptError::InvalidOpcode(0xff)));
+        assert_eq!(TariScript::from_bytes(&[0x00]), Err(ScriptError::InvalidOpcode(0x00)));
+        // A push cut short is no script either
+        let mut bytes = TariScript::new(vec![Opcode::PushHash([1; 32])]).to_bytes();
+        bytes.pop();
+        assert_eq!(TariScript::from_bytes(&bytes), Err(ScriptError::InvalidData));
+    }
+}
@@ -0,0 +1,155 @@
+//! Partial kernel signatures with excesses that stay on the device
+//! Every party to a transaction signs its kernel with its excess, the masks of the outputs it creates minus the masks
+//! of the inputs it spends, under a challenge over the sums of all their public nonces and excesses. The public nonce
+//! therefore goes out before the challenge is known: [`kernel_nonce`] has the app take the excess and a nonce and keep
+//! them, and [`sign_kernel`] has it sign once with them. The host only learns the public excess, the public nonce and
+//! the partial signature, which it checks before passing it on.
+
+use tari_crypto::{
+    hash::blake2::Blake256,
+    ristretto::{RistrettoPublicKey, RistrettoSchnorr, RistrettoSecretKey},
+    tari_utilities::ByteArray,
+};
+use tari_ledger_protocol::{
+    KERNEL_NONCE_HEADER_LENGTH,
+    KERNEL_NONCE_RESPONSE_LENGTH,
+    KERNEL_SIGN_RESPONSE_LENGTH,
+    MAX_KERNEL_EXCESS_KEYS,
+    P1_KERNEL_NONCE,
+    P1_KERNEL_SIGN,
+};
+
+use crate::{
+    device::{Capabilities, DerivationVersion, Instruction, LedgerDevice},
+    domains::{KERNEL_MESSAGE_LABEL, KERNEL_SIGNATURE_LABEL},
+    errors::DeviceError,
+    hashing::{ConsensusHasher, DomainSeparatedConsensusHasher, Preimage, TransactionHashDomain},
+    verify::verify_challenge_signature,
+};
+
+/// The public excess and nonce of one party to a kernel
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KernelShare {
+    pub public_excess: RistrettoPublicKey,
+    pub public_nonce: RistrettoPublicKey,
+}
+
+/// The message of a kernel with `fee` in microTari that can be mined from `lock_height` on
+pub fn kernel_message(fee: u64, lock_height: u64) -> [u8; 32] {
+    DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(KERNEL_MESSAGE_LABEL)
+        .chain(&fee)
+        .chain(&lock_height)
+        .finalize()
+}
+
+/// The challenge every party signs the kernel under
+pub fn kernel_challenge(
+    total_nonce: &RistrettoPublicKey,
+    total_excess: &RistrettoPublicKey,
+    message: &[u8; 32],
+) -> [u8; 32] {
+    let hasher = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(KERNEL_SIGNATURE_LABEL);
+    chain_kernel_challenge(hasher, total_nonce, total_excess, message).finalize()
+}
+
+/// The preimage of [`kernel_challenge`], to show when a partial signature over it does not verify
+pub fn kernel_challenge_preimage(
+    total_nonce: &RistrettoPublicKey,
+    total_excess: &RistrettoPublicKey,
+    message: &[u8; 32],
+) -> Preimage {
+    let hasher = DomainSeparatedConsensusHasher::<TransactionHashDomain>::traced(KERNEL_SIGNATURE_LABEL);
+    chain_kernel_challenge(hasher, total_nonce, total_excess, message).preimage()
+}
+
+fn chain_kernel_challenge(
+    hasher: ConsensusHasher<Blake256>,
+    total_nonce: &RistrettoPublicKey,
+    total_excess: &RistrettoPublicKey,
+    message: &[u8; 32],
+) -> ConsensusHasher<Blake256> {
+    hasher.chain(total_nonce).chain(total_excess).chain(message)
+}
+
+/// Check the partial signature `s` of the party with `share`
+pub fn verify_partial_signature(
+    share: &KernelShare,
+    s: &RistrettoSecretKey,
+    total_nonce: &RistrettoPublicKey,
+    total_excess: &RistrettoPublicKey,
+    message: &[u8; 32],
+) -> bool {
+    let signature = RistrettoSchnorr::new(share.public_nonce.clone(), s.clone());
+    let challenge = kernel_challenge(total_nonce, total_excess, message);
+    verify_challenge_signature(&share.public_excess, &signature, &challenge)
+}
+
+/// Have the app take the excess of the outputs masked with the keys at `created` less the inputs masked with the keys
+/// at `spent`, and a nonce for it to sign the [`kernel_message`] of `fee` and `lock_height` with. Spending inputs needs
+/// an approved transaction with the same fee, which the first such share binds to its lock height and excess, and
+/// receiving into outputs alone is confirmed by the user on the device. The app keeps a single excess, so this
+/// replaces any share it was asked for before.
+pub fn kernel_nonce(
+    device: &LedgerDevice,
+    spent: &[u32],
+    created: &[u32],
+    fee: u64,
+    lock_height: u64,
+    version: DerivationVersion,
+) -> Result<KernelShare, DeviceError> {
+    device.require(Capabilities::KERNEL_SIGNATURES)?;
+    let count = spent.len() + created.len();
+    let (input_count, output_count) = match (u8::try_from(spent.len()), u8::try_from(created.len())) {
+        (Ok(input_count), Ok(output_count)) if count <= MAX_KERNEL_EXCESS_KEYS => (input_count, output_count),
+        _ => {
+            return Err(DeviceError::TooManyKeys {
+                count,
+                max: MAX_KERNEL_EXCESS_KEYS,
+            })
+        },
+    };
+    let p2 = device.derivation_p2(version)?;
+    let mut data = Vec::with_capacity(KERNEL_NONCE_HEADER_LENGTH + 4 * count);
+    data.push(input_count);
+    data.push(output_count);
+    data.extend_from_slice(&fee.to_le_bytes());
+    data.extend_from_slice(&lock_height.to_le_bytes());
+    for index in spent.iter().chain(created) {
+        data.extend_from_slice(&index.to_le_bytes());
+    }
+    let response = device.send(Instruction::KernelSignature, P1_KERNEL_NONCE, p2, data)?;
+    let payload = device.response_payload(&response, KERNEL_NONCE_RESPONSE_LENGTH)?;
+    let invalid = |_| DeviceError::InvalidResponse("the kernel share is not a pair of public keys");
+    Ok(KernelShare {
+        public_excess: RistrettoPublicKey::from_bytes(&payload[0..32]).map_err(invalid)?,
+        public_nonce: RistrettoPublicKey::from_bytes(&payload[32..64]).map_err(invalid)?,
+    })
+}
+
+/// Have the app sign the kernel with the share it took last, `share`, under the sums of the nonces and excesses of
+/// every party. `message` has to be the one the share was taken for, and a kernel that spends uses up the approval it
+/// was bound to.
+pub fn sign_kernel(
+    device: &LedgerDevice,
+    share: &KernelShare,
+    total_nonce: &RistrettoPublicKey,
+    total_excess: &RistrettoPublicKey,
+    message: &[u8; 32],
+) -> Result<RistrettoSecretKey, DeviceError> {
+    device.require(Capabilities::KERNEL_SIGNATURES)?;
+    let mut data = Vec::with_capacity(3 * 32);
+    data.extend_from_slice(total_nonce.as_bytes());
+    data.extend_from_slice(total_excess.as_bytes());
+    data.extend_from_slice(message);
+    let response = device.send(Instruction::KernelSignature, P1_KERNEL_SIGN, 0x00, data)?;
+    let payload = device.response_payload(&response, KERNEL_SIGN_RESPONSE_LENGTH)?;
+    let s = RistrettoSecretKey::from_bytes(payload)
+        .map_err(|_| DeviceError::InvalidResponse("the partial kernel signature is not a scalar"))?;
+    if !verify_partial_signature(share, &s, total_nonce, total_excess, message) {
+        return Err(DeviceError::InvalidSignature {
+            signature: "partial kernel signature",
+            preimage: Box::new(kernel_challenge_preimage(total_nonce, total_excess, message)),
+        });
+    }
+    Ok(s)
+}
@@ -0,0 +1,127 @@
+//! Host side library for the Tari Ledger app
+//!
+//! The protocol and encoding layer, [`device::LedgerDevice`] and [`signer::LedgerTransactionSigner`] are always
+//! available and work over any [`transport::LedgerTransport`]. Concrete transports and everything that pulls in
+//! heavier dependencies are behind a cargo feature:
+//! * `hid` - the HID transport and the `doctor` diagnostics
+//! * `hidraw-direct` - a Linux transport over `/dev/hidraw*` that needs neither hidapi nor libudev
+//! * `serde` - the JSON file backed state store, the golden [`consensus_vectors`], the APDU [`conformance`] corpus, the
+//!   console wallet's [`wallet_tx`] files, the messages of the interactive [`transaction_protocol`], the [`recovery`]
+//!   of the host state from chain data, the signing transcripts of a [`simulation`] and the generated [`protocol_spec`]
+//! * `cbor` - compact [`cbor`] encodings of the JSON documents, for QR codes and air-gapped hosts
+//! * `config` - the profile [`config`] file and encrypted [`watch_only`] bundles
+//! * `remote` - [`remote`] wallet frontends paired with the device over an encrypted connection
+//! * `sled`, `sqlite` - the respective state store backends
+//! * `history` - the encrypted signing history
+//! * `rest` - a [`rest`] facade over the daemon for HTTP clients, with its OpenAPI description
+//! * `broadcast` - a gRPC client of the base node that [`broadcast`]s finished transactions
+//! * `hidapi-vendored` - the HID transport over a hidapi built from bundled sources, without libusb
+//! * `cli` - everything the `tari-ledger` binary needs (enabled by default), `cli-base` the same without a transport
+//! * `static` - a self-contained `tari-ledger`, e.g. `cargo build --release --no-default-features --features static
+//!   --target x86_64-unknown-linux-musl`, adding `hidapi-vendored` on Windows
+//!
+//! Wallet code that should not depend on the device it signs with can be written against
+//! [`hardware_wallet::HardwareWallet`], which `LedgerDevice` implements and backends for other devices will too.
+//!
+//! The programs in `examples/` walk through the common flows against a [`dry_run::DryRunTransport`], so they run
+//! without a device, e.g. `cargo run --example get_address`. `cargo build --examples` keeps them building.
+//!
+//! `tests/speculos.rs` runs the app in the [`speculos`] emulator with a fixed seed, e.g. `TARI_LEDGER_APP=<elf> cargo
+//! test --test speculos`, and is skipped without an app to run.
+
+pub mod address;
+pub mod app_info;
+#[cfg(feature = "serde")]
+pub mod balance;
+pub mod birthday;
+pub mod blinding;
+#[cfg(feature = "broadcast")]
+pub mod broadcast;
+#[cfg(feature = "cbor")]
+pub mod cbor;
+pub mod channel;
+pub mod commitment;
+#[cfg(feature = "config")]
+pub mod config;
+#[cfg(feature = "serde")]
+pub mod conformance;
+#[cfg(feature = "serde")]
+pub mod consensus_vectors;
+#[cfg(unix)]
+pub mod daemon;
+#[cfg(feature = "serde")]
+pub mod denominations;
+pub mod derivation;
+pub mod device;
+pub mod display;
+#[cfg(feature = "hid")]
+pub mod doctor;
+pub mod domains;
+pub mod dry_run;
+pub mod envelope;
+pub mod errors;
+pub mod estimate;
+pub mod export;
+pub mod fee;
+pub mod hardware_wallet;
+pub mod hashing;
+#[cfg(all(feature = "hidraw-direct", target_os = "linux"))]
+pub mod hidraw;
+#[cfg(feature = "history")]
+pub mod history;
+pub mod hooks;
+pub mod htlc;
+pub mod interpreter;
+pub mod kernel;
+pub mod limits;
+#[cfg(feature = "serde")]
+pub mod migration;
+#[cfg(feature = "serde")]
+pub mod multisig;
+pub mod nonce_pool;
+pub mod pairing;
+pub mod payref;
+pub mod permissions;
+pub mod policy;
+#[cfg(feature = "serde")]
+pub mod protocol_spec;
+#[cfg(feature = "serde")]
+pub mod recovery;
+pub mod redact;
+#[cfg(feature = "remote")]
+pub mod remote;
+#[cfg(all(unix, feature = "rest"))]
+pub mod rest;
+pub mod rng;
+pub mod script;
+pub mod script_keys;
+pub mod sender_offset;
+#[cfg(feature = "serde")]
+pub mod session;
+pub mod sidechain;
+pub mod signature;
+pub mod signer;
+#[cfg(feature = "serde")]
+pub mod simulation;
+pub mod soak;
+pub mod speculos;
+pub mod state_store;
+pub mod swap;
+#[cfg(feature = "serde")]
+pub mod sweep;
+#[cfg(test)]
+mod test_helpers;
+#[cfg(feature = "serde")]
+pub mod transaction_protocol;
+pub mod transport;
+pub mod units;
+pub mod verify;
+pub mod wallet;
+#[cfg(feature = "serde")]
+pub mod wallet_tx;
+#[cfg(feature = "config")]
+pub mod watch_only;
+#[cfg(feature = "serde")]
+pub mod withdrawals;
+
+pub use tari_ledger_protocol as protocol;
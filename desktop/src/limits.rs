@@ -0,0 +1,124 @@
+//! The most data the app accepts with each instruction
+//! Every instruction has a request the app parses out of a single APDU, and a request longer than the app expects is
+//! refused with a bare status word that does not say what the limit was. [`LedgerDevice`](crate::device::LedgerDevice)
+//! checks every command against [`max_data_length`] before it is sent, and every chunked upload against
+//! [`max_upload_length`] of the model it talks to, whose RAM bounds what the app can reassemble. Requests that list
+//! keys or outputs are split by the high level API into as many commands as it takes, see e.g.
+//! [`export_public_keys`](crate::export::export_public_keys).
+
+use std::fmt;
+
+use tari_ledger_protocol::{
+    batch_commitment_request_length,
+    display_hints_request_length,
+    kernel_nonce_request_length,
+    nonce_pool_sign_request_length,
+    script_offset_request_length,
+    EXPORT_PRIVATE_KEY_REQUEST_LENGTH,
+    GET_BLINDED_PUBLIC_KEY_REQUEST_LENGTH,
+    GET_PUBLIC_KEYS_REQUEST_LENGTH,
+    KERNEL_SIGN_REQUEST_LENGTH,
+    MAX_CHUNK_LENGTH,
+    MAX_COMMITMENTS_PER_REQUEST,
+    MAX_DISPLAY_PAGES,
+    MAX_KERNEL_EXCESS_KEYS,
+    MAX_POOLED_KERNEL_KEYS,
+    MAX_SCRIPT_OFFSET_KEYS,
+    MAX_UPLOAD_LENGTH,
+    MAX_UPLOAD_LENGTH_NANO_S,
+    SENDER_OFFSET_SIGN_REQUEST_LENGTH,
+    SET_SETTINGS_REQUEST_LENGTH,
+};
+
+use crate::device::Instruction;
+
+/// The Ledger models, as far as their limits differ
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceModel {
+    NanoS,
+    NanoSPlus,
+    NanoX,
+    Stax,
+    Flex,
+}
+
+impl DeviceModel {
+    /// The model of a Ledger USB product id, which carries it in the high byte
+    pub fn from_product_id(product_id: u16) -> Option<Self> {
+        match product_id >> 8 {
+            0x10 => Some(Self::NanoS),
+            0x40 => Some(Self::NanoX),
+            0x50 => Some(Self::NanoSPlus),
+            0x60 => Some(Self::Stax),
+            0x70 => Some(Self::Flex),
+            _ => None,
+        }
+    }
+
+    /// The model Speculos emulates for its `--model` argument
+    pub fn from_speculos_name(name: &str) -> Option<Self> {
+        match name {
+            "nanos" => Some(Self::NanoS),
+            "nanosp" => Some(Self::NanoSPlus),
+            "nanox" => Some(Self::NanoX),
+            "stax" => Some(Self::Stax),
+            "flex" => Some(Self::Flex),
+            _ => None,
+        }
+    }
+
+    /// The name Speculos gives the model, see [`DeviceModel::from_speculos_name`]
+    pub fn speculos_name(self) -> &'static str {
+        match self {
+            Self::NanoS => "nanos",
+            Self::NanoSPlus => "nanosp",
+            Self::NanoX => "nanox",
+            Self::Stax => "stax",
+            Self::Flex => "flex",
+        }
+    }
+}
+
+impl fmt::Display for DeviceModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeviceModel::NanoS => write!(f, "Nano S"),
+            DeviceModel::NanoSPlus => write!(f, "Nano S Plus"),
+            DeviceModel::NanoX => write!(f, "Nano X"),
+            DeviceModel::Stax => write!(f, "Stax"),
+            DeviceModel::Flex => write!(f, "Flex"),
+        }
+    }
+}
+
+/// The longest data of a single `instruction` APDU the app accepts, in whichever of its modes takes the most. Chunks
+/// of an upload are bounded by [`MAX_CHUNK_LENGTH`] instead.
+pub fn max_data_length(instruction: Instruction) -> usize {
+    match instruction {
+        Instruction::GetPublicKeys => GET_PUBLIC_KEYS_REQUEST_LENGTH,
+        Instruction::BatchCommitment => batch_commitment_request_length(MAX_COMMITMENTS_PER_REQUEST),
+        Instruction::OpenSession => 32,
+        Instruction::ExportPrivateKey => EXPORT_PRIVATE_KEY_REQUEST_LENGTH,
+        Instruction::DisplayHints => display_hints_request_length(MAX_DISPLAY_PAGES),
+        Instruction::GetBlindedPublicKey => GET_BLINDED_PUBLIC_KEY_REQUEST_LENGTH,
+        // Cannot truncate, every key count is below 256
+        Instruction::SenderOffset => {
+            SENDER_OFFSET_SIGN_REQUEST_LENGTH.max(script_offset_request_length(MAX_SCRIPT_OFFSET_KEYS as u8, 0))
+        },
+        Instruction::KernelSignature => {
+            KERNEL_SIGN_REQUEST_LENGTH.max(kernel_nonce_request_length(MAX_KERNEL_EXCESS_KEYS as u8, 0))
+        },
+        Instruction::NoncePool => nonce_pool_sign_request_length(MAX_POOLED_KERNEL_KEYS as u8, 0),
+        Instruction::AppSettings => SET_SETTINGS_REQUEST_LENGTH,
+        _ => MAX_CHUNK_LENGTH,
+    }
+}
+
+/// The longest payload the app on `model` reassembles from a framed upload. An unknown model gets the smallest limit
+/// of any.
+pub fn max_upload_length(model: Option<DeviceModel>) -> usize {
+    match model {
+        Some(DeviceModel::NanoS) | None => MAX_UPLOAD_LENGTH_NANO_S,
+        Some(_) => MAX_UPLOAD_LENGTH,
+    }
+}
@@ -1,25 +1,31 @@
-use core::marker::PhantomData;
-
-use borsh::{
-    maybestd::io::{Result as BorshResult, Write},
-    BorshSerialize,
+use std::{
+    io::IsTerminal,
+    net::{SocketAddr, TcpListener},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Mutex,
+    },
+    time::{Duration, Instant},
 };
+
 use bulletproofs_plus::{range_proof::MemLimitedRangeProof, range_statement::RangeStatement};
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
 use curve25519_dalek::{ristretto::RistrettoPoint, Scalar};
-use digest::{Digest, Update};
+use indicatif::{ProgressBar, ProgressStyle};
 use ledger_transport::APDUCommand;
-use ledger_transport_hid::{hidapi::HidApi, TransportNativeHID};
-use ledger_zondax_generic::{App, AppExt};
+#[cfg(feature = "hid")]
+use ledger_transport_hid::hidapi::HidApi;
+#[cfg(feature = "hid")]
 use once_cell::sync::Lazy;
+use qrcode::{render::unicode, QrCode};
 use rand::rngs::OsRng;
 use tari_crypto::extended_range_proof::ExtendedRangeProofService;
 
 use tari_crypto::{
     extended_range_proof::{AggregatedPublicStatement, Statement},
-    hash::blake2::Blake256,
-    hash_domain,
-    hashing::DomainSeparation,
-    keys::SecretKey,
     ristretto::{
         bulletproofs_plus::BulletproofsPlusService,
         pedersen::{extended_commitment_factory::ExtendedPedersenCommitmentFactory, PedersenCommitment},
@@ -27,97 +33,2925 @@ use tari_crypto::{
         RistrettoSchnorr,
         RistrettoSecretKey,
     },
-    tari_utilities::{hex::Hex, ByteArray},
+    tari_utilities::{
+        hex::{from_hex, Hex},
+        ByteArray,
+    },
+};
+#[cfg(feature = "broadcast")]
+use tari_ledger::broadcast::{BaseNodeClient, SubmitResult, TransactionLocation};
+#[cfg(unix)]
+use tari_ledger::daemon::{self, ServeLimits};
+#[cfg(feature = "hid")]
+use tari_ledger::doctor;
+#[cfg(feature = "history")]
+use tari_ledger::history;
+#[cfg(all(unix, feature = "rest"))]
+use tari_ledger::rest;
+use tari_ledger::{
+    address::TariAddress,
+    app_info,
+    balance,
+    birthday,
+    cbor,
+    config::{self, Config, Profile, TransportKind},
+    conformance::{conformance_cases, corpus_to_json},
+    denominations,
+    derivation::{explain_derivation, KeyPath},
+    device::{
+        retry_while_locked,
+        Capabilities,
+        DerivationVersion,
+        DeviceState,
+        HandshakeInfo,
+        KeyBranch,
+        LedgerDevice,
+        RetryPolicy,
+        Strictness,
+    },
+    dry_run::{DryRunLog, DryRunTransport},
+    errors::DeviceError,
+    estimate::{estimate_transaction, PlannedOutput},
+    export::{
+        blinded_public_key,
+        export_private_key,
+        export_public_keys,
+        key_path,
+        public_key,
+        public_key_with_version,
+        sensitive_key_path,
+        SensitiveKey,
+    },
+    fee::FeeCalculator,
+    hashing::{DomainSeparatedConsensusHasher, TransactionHashDomain},
+    hooks::{ApprovalHook, ApprovalHooks},
+    interpreter::{self, ScriptContext},
+    migration,
+    multisig::MultisigDocument,
+    pairing::{self, PairingSecret},
+    payref::PaymentProof,
+    permissions::{ClientGrant, ClientPermissions},
+    policy::{ConfirmationRequest, OperatorToken, SecondaryConfirmation, TwoManRule},
+    protocol::{
+        Instruction,
+        BP_RESPONSE_LENGTH,
+        COMMITMENT_RESPONSE_LENGTH,
+        SCRIPT_CHALLENGE_LABEL,
+        SIGN_RESPONSE_LENGTH,
+    },
+    protocol_spec,
+    recovery::{self, BlockchainBackend, ChainDataFile},
+    remote::{self, PairingCode},
+    script::{ExecutionStack, TariScript},
+    sender_offset::ScriptOffsetInput,
+    session,
+    signer::{change_output_size, LedgerTransactionSigner, SignerMode},
+    simulation,
+    soak::{self, SoakOptions},
+    speculos::{self, SpeculosOptions},
+    state_store::{default_data_dir, FileStateStore, LedgerStateStore},
+    sweep,
+    transaction_protocol::{self, ReceiverReply, SendRequest, SenderInput, SenderMessage},
+    transport::HidFilter,
+    units::{AmountFormat, Unit},
+    verify,
+    wallet::{check_wallet, wallet_fingerprint, ScopedStateStore},
+    wallet_tx::UnsignedTransaction,
+    watch_only,
+    withdrawals,
 };
 
+#[cfg(feature = "hid")]
 fn hidapi() -> &'static HidApi {
     static HIDAPI: Lazy<HidApi> = Lazy::new(|| HidApi::new().expect("unable to get HIDAPI"));
 
     &HIDAPI
 }
-struct Tari;
-impl App for Tari {
-    const CLA: u8 = 0x0;
+
+/// The key branch and index the app currently signs with, `m/44'/535348'/0'/0/0`
+const DEMO_KEY_BRANCH: &str = "m/44'/535348'/0'/0";
+const DEMO_KEY_INDEX: u64 = 0;
+
+#[derive(Parser)]
+#[command(name = "tari-ledger", version, about = "Interact with the Tari Ledger app")]
+struct Cli {
+    /// Passphrase of the encrypted signing history database
+    #[cfg(feature = "history")]
+    #[arg(long, global = true, env = "TARI_LEDGER_HISTORY_KEY", hide_env_values = true)]
+    history_key: Option<String>,
+    /// The configuration file, `~/.tari-ledger/config.toml` by default
+    #[arg(long, global = true, env = "TARI_LEDGER_CONFIG")]
+    config: Option<PathBuf>,
+    /// Passphrase of an encrypted configuration file
+    #[arg(long, global = true, env = "TARI_LEDGER_CONFIG_KEY", hide_env_values = true)]
+    config_key: Option<String>,
+    /// The configuration profile to use
+    #[arg(long, global = true, env = "TARI_LEDGER_PROFILE")]
+    profile: Option<String>,
+    /// Do not use a device, write every APDU that would be sent and the expected response layout to this file
+    #[arg(long, global = true, value_name = "FILE")]
+    dry_run: Option<PathBuf>,
+    /// Open an authenticated session with the app, so that every APDU carries a MAC. Ignored in a dry run.
+    #[arg(long, global = true)]
+    authenticated: bool,
+    /// Fail on device responses with undocumented trailing bytes, to catch protocol regressions in the app
+    #[arg(long, global = true)]
+    strict: bool,
+    /// Sign outputs the device cannot show with their amount and recipient, e.g. with apps that predate output
+    /// confirmation
+    #[arg(long, global = true)]
+    allow_silent_outputs: bool,
+    /// Take HID devices with this USB vendor id (hex) for a Ledger, instead of the one of the profile
+    #[arg(long, global = true, value_parser = parse_usb_id)]
+    vendor_id: Option<u16>,
+    /// Only take HID devices with this USB product id (hex)
+    #[arg(long, global = true, value_parser = parse_usb_id)]
+    product_id: Option<u16>,
+    /// The unit amounts are shown in, `T`, `mT` or `uT`, instead of the one of the profile
+    #[arg(long, global = true)]
+    units: Option<Unit>,
+    /// The token of the second operator, for transactions over the threshold of the profile's two-man rule
+    #[arg(long, global = true, env = "TARI_LEDGER_OPERATOR_TOKEN", hide_env_values = true)]
+    operator_token: Option<String>,
+    /// The token this invocation identifies with to a running daemon, as one of the daemon clients of the profile
+    #[arg(long, global = true, env = "TARI_LEDGER_DAEMON_TOKEN", hide_env_values = true)]
+    daemon_token: Option<String>,
+    /// Seed the host's ephemeral keys and challenges, so that every run sends the same APDUs. Only for tests against
+    /// the emulator: anyone who knows the seed knows every session key.
+    #[arg(long, global = true, env = "TARI_LEDGER_RNG_SEED", hide = true)]
+    rng_seed: Option<u64>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the signing, commitment and range proof demo against a connected device (default)
+    Demo,
+    /// Check the host environment for common HID permission and driver problems
+    #[cfg(feature = "hid")]
+    Doctor,
+    /// Show the app that is open on the device
+    AppInfo {
+        /// Also report the firmware and the Tari app's version and capabilities, as far as the device tells
+        #[arg(long)]
+        all: bool,
+    },
+    /// Show the settings of the Tari app, or change them with every change confirmed on the device
+    Settings {
+        /// A setting to enable: blind-signing or microtari-amounts, expert mode is only enabled on the device
+        #[arg(long, value_parser = parse_setting)]
+        enable: Vec<u8>,
+        /// A setting to disable: blind-signing, expert-mode or microtari-amounts
+        #[arg(long, value_parser = parse_setting)]
+        disable: Vec<u8>,
+    },
+    /// Report whenever the device is locked, unplugged or the app is closed, until interrupted
+    Watch {
+        /// Seconds between heartbeats
+        #[arg(long, default_value_t = 5)]
+        interval: u64,
+    },
+    /// Exercise the device with commands that need no confirmation for hours, logging every transport failure and
+    /// summarising when they clustered, e.g. to find out whether a USB hub drops the device
+    Soak {
+        #[arg(long, default_value_t = 1.0)]
+        hours: f64,
+        /// Seconds between rounds of commands
+        #[arg(long, default_value_t = 1)]
+        interval: u64,
+    },
+    /// Keep the device open for later invocations, which then skip opening it, until interrupted. The device is taken
+    /// with the transport and HID filter of this invocation. The hooks run for every command of an invocation that
+    /// signs or reveals something, before the device sees it.
+    #[cfg(unix)]
+    Daemon {
+        /// Show a desktop notification about every such command
+        #[arg(long)]
+        notify: bool,
+        /// POST every such command as JSON to this URL, e.g. a Slack incoming webhook
+        #[arg(long)]
+        webhook: Vec<String>,
+        /// Run this program with every such command as JSON on its standard input, and refuse the command unless it
+        /// exits with 0
+        #[arg(long)]
+        approve_with: Option<PathBuf>,
+        /// Have every such command confirmed at this terminal as well
+        #[arg(long)]
+        confirm: bool,
+        /// How many invocations may wait for the device while the daemon serves another one, later ones are turned
+        /// away
+        #[arg(long, default_value_t = daemon::DEFAULT_QUEUE_DEPTH)]
+        queue_depth: usize,
+        /// How long an invocation may leave the daemon waiting for its next command, in seconds, before the daemon
+        /// hangs up on it and serves the next one
+        #[arg(long, default_value_t = daemon::DEFAULT_IDLE_TIMEOUT.as_secs())]
+        idle_timeout_secs: u64,
+        /// How long the user has to answer a prompt of the daemon, or to confirm on the device, in seconds, before the
+        /// command is refused
+        #[arg(long, default_value_t = daemon::DEFAULT_PROMPT_TIMEOUT.as_secs())]
+        prompt_timeout_secs: u64,
+    },
+    /// Pair a remote wallet frontend, e.g. one in a browser, and serve it the device over an encrypted connection
+    /// until interrupted. The pairing code is printed as text and as a QR code for the frontend, and every command of
+    /// the frontend that signs or reveals something is confirmed here before the device sees it.
+    Remote {
+        /// The address and port to listen on
+        #[arg(long, default_value = "127.0.0.1:7070")]
+        listen: SocketAddr,
+    },
+    /// Serve the device of a running `tari-ledger daemon` to HTTP clients as JSON until interrupted, with the OpenAPI
+    /// description of the routes at `/openapi.json`. Requests have to present the token of one of the daemon clients
+    /// of the profile as `Authorization: Bearer <token>`, and may send what that client may.
+    #[cfg(all(unix, feature = "rest"))]
+    Rest {
+        /// The address and port to listen on
+        #[arg(long, default_value = "127.0.0.1:8990")]
+        listen: SocketAddr,
+    },
+    /// Pair this host with the device, so that later authenticated sessions prove to each other that neither is an
+    /// impostor and the device shows the pairing words printed here
+    Pair,
+    /// Write the APDU conformance corpus the app's CI replays, every command the host encoders produce with the
+    /// response and signature check it expects, no device required
+    GenConformance {
+        #[arg(long, default_value = "conformance.json")]
+        out: PathBuf,
+    },
+    /// Write the protocol documentation, every instruction with its P1, P2, request and response layouts and status
+    /// words, generated from the registry the app and the host are built against
+    GenProtocolSpec {
+        #[arg(long, default_value = "protocol.json")]
+        out: PathBuf,
+        /// `json` or `markdown`
+        #[arg(long, default_value = "json")]
+        format: protocol_spec::SpecFormat,
+    },
+    /// Sign a transaction description, JSON or a `.toml` file, against software keys and print every key,
+    /// challenge, signature and APDU of it, no device required
+    SimulateTx {
+        description: PathBuf,
+        /// The seed the software keys are derived from
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+    },
+    /// Start the app in the Speculos emulator with a fixed seed and record or check its keys and signatures
+    Speculos {
+        /// The app's ELF file
+        app: PathBuf,
+        /// The seed the emulator derives every key from
+        #[arg(long, default_value = speculos::TEST_SEED)]
+        seed: String,
+        /// The `speculos` executable
+        #[arg(long, default_value = "speculos")]
+        binary: PathBuf,
+        /// The device model to emulate
+        #[arg(long, default_value = "nanos")]
+        model: String,
+        #[arg(long, default_value_t = speculos::DEFAULT_APDU_PORT)]
+        apdu_port: u16,
+        /// Write the answers to this file
+        #[arg(long, conflicts_with = "check")]
+        record: Option<PathBuf>,
+        /// Compare the answers with a file written by `--record`
+        #[arg(long)]
+        check: Option<PathBuf>,
+    },
+    /// Sign a payment reference proving that an output was paid from this device
+    Payref {
+        /// Hex encoded hash of the paying output
+        #[arg(long)]
+        output_hash: String,
+        /// The payment id agreed with the payee, e.g. an invoice number
+        #[arg(long)]
+        payment_id: String,
+    },
+    /// Sign an unsigned transaction exported by the console wallet
+    SignTx {
+        /// The JSON file written by the wallet
+        #[arg(long = "in")]
+        input: PathBuf,
+        /// Where to write the file with the signatures added
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Pay another wallet interactively: write the message for the receiver, wait for its reply and write the finished
+    /// transaction
+    Send {
+        /// The Tari address to pay, as an Emoji ID or in hex
+        #[arg(long, value_parser = parse_address)]
+        to: TariAddress,
+        /// The amount to pay, e.g. `1.5T` or `250mT`, in microTari without a unit
+        #[arg(long)]
+        amount: String,
+        /// The console wallet's export of its unspent outputs, every one of them is spent
+        #[arg(long)]
+        outputs: PathBuf,
+        /// Where to write the JSON message for the receiver
+        #[arg(long)]
+        message: PathBuf,
+        /// The file the receiver's JSON reply is expected in, waited for until the signing session expires
+        #[arg(long)]
+        reply: PathBuf,
+        /// Where to write the finished transaction
+        #[arg(long)]
+        out: PathBuf,
+        #[arg(long, default_value_t = 0)]
+        lock_height: u64,
+        /// The script keys of the outputs to spend are bound to their commitments, as those of received outputs are
+        #[arg(long)]
+        bound_script_keys: bool,
+        #[arg(long, value_parser = parse_derivation_version, default_value = "legacy")]
+        version: DerivationVersion,
+        /// The gRPC address of a base node, e.g. `http://127.0.0.1:18142`, to broadcast the finished transaction to
+        /// and follow until it is mined
+        #[cfg(feature = "broadcast")]
+        #[arg(long)]
+        base_node: Option<String>,
+        /// How long to wait for the broadcast transaction to be mined
+        #[cfg(feature = "broadcast")]
+        #[arg(long, default_value_t = 3600)]
+        mined_within_secs: u64,
+    },
+    /// Answer the message of a sender with a new output and the receiver's partial kernel signature, both from the
+    /// device
+    Receive {
+        /// The JSON message of the sender
+        #[arg(long = "in")]
+        input: PathBuf,
+        /// Where to write the JSON reply for the sender
+        #[arg(long)]
+        out: PathBuf,
+        /// The index of the commitment mask of the new output
+        #[arg(long)]
+        mask_index: u32,
+        /// The index of the script key of the new output, defaults to the mask index
+        #[arg(long)]
+        script_key_index: Option<u32>,
+        /// Defaults to the account of the profile
+        #[arg(long)]
+        account: Option<u32>,
+        #[arg(long, value_parser = parse_derivation_version, default_value = "legacy")]
+        version: DerivationVersion,
+    },
+    /// Coordinate an m-of-n multi-signature output through a document passed between the participants
+    Multisig {
+        #[command(subcommand)]
+        action: MultisigAction,
+    },
+    /// Sign a CSV of `address,amount` withdrawals in batches, confirming each batch once on the device
+    ProcessWithdrawals {
+        /// One withdrawal per line, the amount in microTari
+        file: PathBuf,
+        #[arg(long, default_value_t = withdrawals::DEFAULT_BATCH_SIZE)]
+        batch_size: usize,
+        /// The number of inputs funding each batch, for the fee
+        #[arg(long, default_value_t = 1)]
+        inputs: usize,
+        /// Where to write the JSON report of the signed withdrawals
+        #[arg(long)]
+        report: PathBuf,
+    },
+    /// Estimate the weight, fee and device confirmations of a transaction before connecting the device, assuming an
+    /// app that confirms every recipient output
+    Estimate {
+        #[arg(long)]
+        inputs: usize,
+        #[arg(long, default_value_t = 1)]
+        recipients: usize,
+        /// Serialized size of the features and script of each recipient output, a one key script by default
+        #[arg(long)]
+        features_size: Option<usize>,
+        /// Leave out the change output
+        #[arg(long)]
+        no_change: bool,
+        /// In microTari per gram, the fee per gram of the profile by default
+        #[arg(long)]
+        fee_per_gram: Option<u64>,
+    },
+    /// Report the balance of the outputs the device can spend and of the interactive transactions under way
+    Balance {
+        /// The console wallet's export of its unspent outputs
+        #[arg(long)]
+        outputs: PathBuf,
+        /// The height of the chain tip, outputs that mature above it are time locked
+        #[arg(long, default_value_t = 0)]
+        height: u64,
+        /// The JSON message of a transaction being received, once per transaction
+        #[arg(long)]
+        receiving: Vec<PathBuf>,
+        /// The JSON message of a transaction being sent, once per transaction
+        #[arg(long)]
+        sending: Vec<PathBuf>,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Find the key indices the wallet used by scanning each branch against the outputs on chain, and restore them
+    /// in the state store, e.g. after it was lost
+    Recover {
+        /// The unspent outputs on chain, with their values where known
+        #[arg(long)]
+        chain_data: PathBuf,
+        /// Defaults to the account of the profile
+        #[arg(long)]
+        account: Option<u32>,
+        /// The number of unused indices in a row after the last used one that ends the scan of a branch
+        #[arg(long, default_value_t = recovery::DEFAULT_GAP_LIMIT)]
+        gap_limit: u32,
+        #[arg(long, value_parser = parse_derivation_version, default_value = "legacy")]
+        version: DerivationVersion,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Sweep every output the device can spend above the dust threshold into one output paying `--to`
+    Sweep {
+        /// The Tari address to pay, as an Emoji ID or in hex
+        #[arg(long, value_parser = parse_address)]
+        to: TariAddress,
+        /// The console wallet's export of its unspent outputs
+        #[arg(long)]
+        outputs: PathBuf,
+        /// Outputs worth no more than this are left behind, e.g. `0.01T`, in microTari without a unit. Defaults to the
+        /// fee of spending an output.
+        #[arg(long)]
+        dust_threshold: Option<String>,
+        /// Where to write the signed sweep for the wallet
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Split one output the device can spend into outputs of equal value, e.g. the denominations a miner spends
+    Split {
+        /// The console wallet's export of its unspent outputs
+        #[arg(long)]
+        outputs: PathBuf,
+        /// Hex encoded commitment of the output to split
+        #[arg(long)]
+        commitment: String,
+        #[arg(long)]
+        parts: usize,
+        /// Where to write the signed transaction for the wallet
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Consolidate every output in the export into one
+    Consolidate {
+        /// The console wallet's export of the unspent outputs to consolidate
+        #[arg(long)]
+        outputs: PathBuf,
+        /// Where to write the signed transaction for the wallet
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Sweep every output under an older key derivation scheme into one output with keys of the latest scheme
+    Migrate {
+        /// The console wallet's export of its unspent outputs
+        #[arg(long)]
+        outputs: PathBuf,
+        /// The scheme the outputs to migrate are derived under, e.g. `legacy`
+        #[arg(long, value_parser = parse_derivation_version, default_value = "legacy")]
+        from: DerivationVersion,
+        /// Outputs worth no more than this are left behind, e.g. `0.01T`, in microTari without a unit. Defaults to the
+        /// fee of spending an output.
+        #[arg(long)]
+        dust_threshold: Option<String>,
+        /// Where to write the signed transaction for the wallet
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Show the Tari address of an account key, as an Emoji ID and in hex
+    Address {
+        /// Defaults to the account of the profile
+        #[arg(long)]
+        account: Option<u32>,
+        /// The key manager branch, e.g. `script_key`
+        #[arg(long, value_parser = parse_key_branch, default_value = "commitment_mask")]
+        branch: KeyBranch,
+        #[arg(long, default_value_t = 0)]
+        index: u32,
+        /// Request the key blinded, so that the USB link shows neither its path nor the key
+        #[arg(long)]
+        blind: bool,
+    },
+    /// Show the public key at a derivation path, e.g. `m/44'/535348'/0'/1/7`
+    Derive {
+        #[arg(value_parser = parse_key_path)]
+        path: KeyPath,
+        /// Also list every step the device is expected to take to derive the key
+        #[arg(long)]
+        explain: bool,
+        #[arg(long, value_parser = parse_derivation_version, default_value = "legacy")]
+        version: DerivationVersion,
+    },
+    /// Export a range of account public keys to a JSON manifest, or CSV if `--out` ends in `.csv`
+    ExportPubkeys {
+        /// Defaults to the account of the profile
+        #[arg(long)]
+        account: Option<u32>,
+        /// The key manager branch, e.g. `script_key`
+        #[arg(long, value_parser = parse_key_branch, default_value = "commitment_mask")]
+        branch: KeyBranch,
+        /// Key indices to export, e.g. `0..1000`
+        #[arg(long, value_parser = parse_index_range)]
+        range: Range<u32>,
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Export the view key or the rewind key of an account, encrypted on its way off the device
+    ExportKey {
+        /// Defaults to the account of the profile
+        #[arg(long)]
+        account: Option<u32>,
+        /// `view_key` or `rewind_key`
+        #[arg(long, value_parser = parse_sensitive_key)]
+        key: SensitiveKey,
+    },
+    /// Write an encrypted bundle with the view key, the public keys, the birthday and the network of an account, for a
+    /// wallet to follow it without the device
+    ExportWatchOnly {
+        /// Defaults to the account of the profile
+        #[arg(long)]
+        account: Option<u32>,
+        /// The number of public keys exported from each branch
+        #[arg(long, default_value_t = 1000)]
+        count: u32,
+        /// The passphrase the bundle is encrypted with
+        #[arg(long, env = "TARI_LEDGER_BUNDLE_KEY", hide_env_values = true)]
+        passphrase: String,
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Show the wallet birthday recorded on the device and keep a copy on the host for recovery scans
+    Birthday {
+        /// Record this block height as the birthday first, once confirmed on the device. 0 clears it.
+        #[arg(long)]
+        set: Option<u64>,
+        /// Defaults to the account of the profile
+        #[arg(long)]
+        account: Option<u32>,
+        /// Write the birthday, the wallet fingerprint and the derivation paths of the account to this JSON file
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Check a payment proof created with `payref`, no device required
+    VerifyPayref {
+        proof: String,
+        #[arg(long)]
+        output_hash: String,
+        #[arg(long)]
+        payment_id: String,
+    },
+    /// Check a signature made by the device, no device required
+    Verify {
+        /// Hex encoded public key, or the Tari address of the key
+        #[arg(long)]
+        public_key: String,
+        /// Hex encoded `public nonce || s`
+        #[arg(long)]
+        signature: String,
+        /// The hex encoded 32-byte message that was signed
+        #[arg(long)]
+        message: String,
+        /// Treat the message as the final challenge rather than hashing it into the script challenge
+        #[arg(long)]
+        raw_challenge: bool,
+    },
+    /// Run a script against its input data and show what it evaluates to, no device required
+    RunScript {
+        /// The hex encoded script
+        script: String,
+        /// The hex encoded execution stack, empty by default
+        #[arg(long, default_value = "")]
+        input: String,
+        /// The block height the spend is expected to be mined at
+        #[arg(long, default_value_t = 0)]
+        height: u64,
+    },
+    /// Convert a document between JSON and compact CBOR, whichever it is not, no device required
+    Convert {
+        #[arg(long = "in")]
+        input: PathBuf,
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Show the fingerprint of the seed the device is unlocked with, e.g. to tell a hidden wallet from the main one
+    Wallet {
+        /// Save a label for the seed in the configuration file
+        #[arg(long)]
+        label: Option<String>,
+    },
+    /// Encrypt the configuration file with the passphrase given by `--config-key`
+    EncryptConfig,
+    /// Print the completions of this command for a shell, or its manpage, e.g. for packaging. No device required.
+    Completions {
+        /// bash, zsh, fish, elvish or powershell
+        #[arg(value_enum, required_unless_present = "man")]
+        shell: Option<Shell>,
+        /// Print the manpage in roff instead
+        #[arg(long, conflicts_with = "shell")]
+        man: bool,
+    },
+    /// List the operations the device has signed, newest first
+    #[cfg(feature = "history")]
+    History {
+        /// Only show the most recent N records
+        #[arg(long)]
+        limit: Option<usize>,
+        #[command(subcommand)]
+        action: Option<HistoryAction>,
+    },
+}
+
+#[derive(Subcommand)]
+enum MultisigAction {
+    /// Start a document for `threshold` of `participants` signatures over `message`, no device required
+    New {
+        #[arg(long)]
+        threshold: u8,
+        #[arg(long)]
+        participants: u8,
+        /// Hex encoded message the script commits to
+        #[arg(long)]
+        message: String,
+        file: PathBuf,
+    },
+    /// Add the device's key or signature, whichever the document is collecting, and write the document back
+    Contribute { file: PathBuf },
+    /// Show the round of the document, and the script or input data once known, no device required
+    Show {
+        file: PathBuf,
+        /// Also show the document as a QR code, for the next participant to scan
+        #[arg(long)]
+        qr: bool,
+        /// Encode the QR code as CBOR, about half the size of JSON
+        #[arg(long, requires = "qr")]
+        cbor: bool,
+    },
+}
+
+#[cfg(feature = "history")]
+#[derive(Subcommand)]
+enum HistoryAction {
+    /// Write the records for an auditor to stdout, oldest first
+    Export {
+        /// `csv` or `json`, only JSON exports can be imported again
+        #[arg(long, default_value = "json")]
+        format: history::ExportFormat,
+        /// Only export records from this date (`YYYY-MM-DD`, UTC) or RFC 3339 time on
+        #[arg(long, value_parser = parse_time)]
+        since: Option<i64>,
+    },
+    /// Append the records of a JSON export, e.g. from another host
+    Import { file: PathBuf },
+    /// Re-verify every recorded signature against its public key and challenge, and report the records that fail
+    Verify,
+    /// Compare the device's count of the outputs it signed with the outputs recorded here since the last check
+    Reconcile {
+        /// Take the device's count as the baseline of the next check even if it does not match
+        #[arg(long)]
+        accept: bool,
+    },
 }
-hash_domain!(TransactionHashDomain, "com.tari.base_layer.core.transactions", 0);
 
 fn main() {
-    let command = APDUCommand {
-        cla: 0x80,
-        ins: 0x01,
-        p1: 0x00,
-        p2: 0x00,
-        data: vec![0],
+    let cli = Cli::parse();
+    // Packaging runs this at build time, without a configuration file
+    if let Some(Command::Completions { shell, man }) = &cli.command {
+        print_completions(*shell, *man);
+        return;
+    }
+    #[cfg(feature = "history")]
+    let history = cli.history_key.as_deref().map(|key| {
+        history::SigningHistory::open(default_data_dir().join("history.db"), key).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        })
+    });
+    #[cfg(not(feature = "history"))]
+    let history = ();
+
+    let config_path = cli
+        .config
+        .clone()
+        .unwrap_or_else(|| default_data_dir().join("config.toml"));
+    let config = Config::load(&config_path, cli.config_key.as_deref()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let profile = config.profile(cli.profile.as_deref()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let amounts = profile.amount_format(cli.units);
+    let two_man_rule = profile.two_man_rule.clone().map(|rule| {
+        let confirmation: Box<dyn SecondaryConfirmation> = match &rule.token_sha256 {
+            Some(token_sha256) => Box::new(OperatorToken::new(token_sha256, cli.operator_token.clone())),
+            None => Box::new(PromptConfirmation { amounts }),
+        };
+        (rule, confirmation)
+    });
+    let connect = ConnectOptions {
+        retry_policy: RetryPolicy {
+            timeout: profile.timeouts.unlock(),
+            ..RetryPolicy::default()
+        },
+        session_ttl: profile.timeouts.session_token(),
+        command_deadline: profile.timeouts.command(),
+        daemon_token: cli.daemon_token.clone(),
+        transport: profile.transport,
+        dry_run: cli.dry_run.as_ref().map(|_| DryRunLog::new()),
+        authenticated: cli.authenticated,
+        strictness: if cli.strict {
+            Strictness::Strict
+        } else {
+            Strictness::Lenient
+        },
+        wallet: profile.wallet.clone(),
+        allow_silent_outputs: cli.allow_silent_outputs,
+        hid_filter: HidFilter {
+            vendor_id: cli.vendor_id.unwrap_or(profile.hid_filter.vendor_id),
+            product_id: cli.product_id.or(profile.hid_filter.product_id),
+            ..profile.hid_filter
+        },
+        rng_seed: cli.rng_seed,
+        two_man_rule,
+    };
+
+    match cli.command.unwrap_or(Command::Demo) {
+        Command::Demo => run_demo(&connect, &history).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }),
+        #[cfg(feature = "hid")]
+        Command::Doctor => run_doctor(),
+        Command::AppInfo { all } => run_app_info(&connect, all),
+        Command::Settings { enable, disable } => run_settings(&connect, enable, disable),
+        Command::Watch { interval } => run_watch(&connect, interval),
+        #[cfg(unix)]
+        Command::Daemon {
+            notify,
+            webhook,
+            approve_with,
+            confirm,
+            queue_depth,
+            idle_timeout_secs,
+            prompt_timeout_secs,
+        } => run_daemon(
+            &connect,
+            &profile,
+            notify,
+            webhook,
+            approve_with,
+            confirm,
+            ServeLimits {
+                queue_depth,
+                idle_timeout: Duration::from_secs(idle_timeout_secs),
+                prompt_timeout: Duration::from_secs(prompt_timeout_secs),
+            },
+        ),
+        Command::Remote { listen } => run_remote(&connect, listen),
+        #[cfg(all(unix, feature = "rest"))]
+        Command::Rest { listen } => run_rest(&connect, listen),
+        Command::Soak { hours, interval } => run_soak(&connect, hours, interval),
+        Command::Pair => run_pair(&connect),
+        Command::GenConformance { out } => run_gen_conformance(out),
+        Command::GenProtocolSpec { out, format } => run_gen_protocol_spec(out, format),
+        Command::SimulateTx { description, seed } => run_simulate_tx(description, seed),
+        Command::Speculos {
+            app,
+            seed,
+            binary,
+            model,
+            apdu_port,
+            record,
+            check,
+        } => run_speculos(app, seed, binary, model, apdu_port, record, check),
+        Command::Payref {
+            output_hash,
+            payment_id,
+        } => run_payref(&connect, &profile, &history, output_hash, payment_id),
+        Command::SignTx { input, out } => run_sign_tx(&connect, &profile, amounts, &history, input, out),
+        Command::Send {
+            to,
+            amount,
+            outputs,
+            message,
+            reply,
+            out,
+            lock_height,
+            bound_script_keys,
+            version,
+            #[cfg(feature = "broadcast")]
+            base_node,
+            #[cfg(feature = "broadcast")]
+            mined_within_secs,
+        } => run_send(
+            &connect,
+            &profile,
+            amounts,
+            &history,
+            to,
+            amount,
+            outputs,
+            message,
+            reply,
+            out,
+            lock_height,
+            bound_script_keys,
+            version,
+            #[cfg(feature = "broadcast")]
+            base_node,
+            #[cfg(feature = "broadcast")]
+            mined_within_secs,
+        ),
+        Command::Receive {
+            input,
+            out,
+            mask_index,
+            script_key_index,
+            account,
+            version,
+        } => run_receive(
+            &connect,
+            &profile,
+            amounts,
+            input,
+            out,
+            mask_index,
+            script_key_index,
+            account,
+            version,
+        ),
+        Command::Multisig { action } => run_multisig(&connect, &profile, &history, action),
+        Command::ProcessWithdrawals {
+            file,
+            batch_size,
+            inputs,
+            report,
+        } => run_process_withdrawals(&connect, &profile, amounts, &history, file, batch_size, inputs, report),
+        Command::Estimate {
+            inputs,
+            recipients,
+            features_size,
+            no_change,
+            fee_per_gram,
+        } => run_estimate(
+            &profile,
+            amounts,
+            inputs,
+            recipients,
+            features_size,
+            no_change,
+            fee_per_gram,
+        ),
+        Command::Balance {
+            outputs,
+            height,
+            receiving,
+            sending,
+            json,
+        } => run_balance(
+            &connect, &profile, amounts, &history, outputs, height, receiving, sending, json,
+        ),
+        Command::Recover {
+            chain_data,
+            account,
+            gap_limit,
+            version,
+            json,
+        } => run_recover(&connect, &profile, chain_data, account, gap_limit, version, json),
+        Command::Sweep {
+            to,
+            outputs,
+            dust_threshold,
+            out,
+        } => run_sweep(&connect, &profile, amounts, &history, to, outputs, dust_threshold, out),
+        Command::Split {
+            outputs,
+            commitment,
+            parts,
+            out,
+        } => run_split(&connect, &profile, amounts, &history, outputs, commitment, parts, out),
+        Command::Consolidate { outputs, out } => run_consolidate(&connect, &profile, amounts, &history, outputs, out),
+        Command::Migrate {
+            outputs,
+            from,
+            dust_threshold,
+            out,
+        } => run_migrate(
+            &connect,
+            &profile,
+            amounts,
+            &history,
+            outputs,
+            from,
+            dust_threshold,
+            out,
+        ),
+        Command::Address {
+            account,
+            branch,
+            index,
+            blind,
+        } => run_address(&connect, &profile, account, branch, index, blind),
+        Command::Derive { path, explain, version } => run_derive(&connect, path, explain, version),
+        Command::ExportPubkeys {
+            account,
+            branch,
+            range,
+            out,
+        } => run_export_pubkeys(&connect, &profile, account, branch, range, out),
+        Command::ExportKey { account, key } => run_export_key(&connect, &profile, account, key),
+        Command::ExportWatchOnly {
+            account,
+            count,
+            passphrase,
+            out,
+        } => run_export_watch_only(&connect, &profile, account, count, passphrase, out),
+        Command::Birthday { set, account, out } => run_birthday(&connect, &profile, set, account, out),
+        Command::VerifyPayref {
+            proof,
+            output_hash,
+            payment_id,
+        } => run_verify_payref(proof, output_hash, payment_id),
+        Command::Verify {
+            public_key,
+            signature,
+            message,
+            raw_challenge,
+        } => run_verify(public_key, signature, message, raw_challenge),
+        Command::RunScript { script, input, height } => run_script(script, input, height),
+        Command::Convert { input, out } => run_convert(input, out),
+        Command::Wallet { label } => run_wallet(
+            &connect,
+            &profile,
+            config,
+            &config_path,
+            cli.config_key.as_deref(),
+            label,
+        ),
+        Command::EncryptConfig => run_encrypt_config(&config, &config_path, cli.config_key.as_deref()),
+        Command::Completions { shell, man } => print_completions(shell, man),
+        #[cfg(feature = "history")]
+        Command::History { limit, action } => run_history(&connect, history, limit, action),
+    }
+
+    if let (Some(path), Some(log)) = (&cli.dry_run, &connect.dry_run) {
+        if let Err(e) = log.write_to(path) {
+            eprintln!("Could not write {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+        println!("Wrote {} APDUs to {}", log.exchanges().len(), path.display());
+    }
+}
+
+#[cfg(feature = "hid")]
+fn run_doctor() {
+    let results = doctor::run_diagnostics();
+    for result in &results {
+        println!("{}", result);
+    }
+    if !doctor::all_passed(&results) {
+        std::process::exit(1);
+    }
+}
+
+fn run_app_info(connect: &ConnectOptions, all: bool) {
+    let device = connect_device(connect);
+    if all {
+        match app_info::full_device_report(&device) {
+            Ok(report) => println!("{}", report),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            },
+        }
+    } else {
+        match app_info::app_info(&device) {
+            Ok(info) => println!("{} {}", info.name, info.version),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            },
+        }
+    }
+}
+
+fn run_settings(connect: &ConnectOptions, enable: Vec<u8>, disable: Vec<u8>) {
+    let device = open_device(connect);
+    let enable = enable.iter().fold(0, |flags, setting| flags | setting);
+    let disable = disable.iter().fold(0, |flags, setting| flags | setting);
+    if enable & disable != 0 {
+        eprintln!("A setting cannot be enabled and disabled at once");
+        std::process::exit(1);
+    }
+    if enable | disable == 0 {
+        match app_info::settings_report(&device) {
+            Ok(report) => {
+                println!("{}", report.settings);
+                let host_enabled = app_info::SETTINGS
+                    .iter()
+                    .filter(|setting| report.host_enabled & *setting != 0)
+                    .map(|setting| app_info::setting_name(*setting))
+                    .collect::<Vec<_>>();
+                println!("can be enabled from the host: {}", host_enabled.join(", "));
+            },
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            },
+        }
+        return;
+    }
+    println!("Confirm the changes on the device");
+    match app_info::change_app_settings(&device, enable, disable) {
+        Ok(settings) => println!("{}", settings),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    }
+}
+
+fn run_watch(connect: &ConnectOptions, interval: u64) {
+    // The app may not be open yet, so there is no handshake
+    let mut device = connect_device(connect);
+    let mut last = None;
+    loop {
+        let state = device.ping();
+        if last != Some(state) {
+            println!("device: {}", state);
+            last = Some(state);
+        }
+        // A device that is plugged back in has to be opened again
+        if state == DeviceState::Disconnected {
+            if let Ok(reconnected) = connect_transport(connect.transport, &connect.hid_filter) {
+                device = reconnected.with_strictness(connect.strictness);
+            }
+        }
+        std::thread::sleep(Duration::from_secs(interval));
+    }
+}
+
+#[cfg(unix)]
+fn run_daemon(
+    connect: &ConnectOptions,
+    profile: &Profile,
+    notify: bool,
+    webhook: Vec<String>,
+    approve_with: Option<PathBuf>,
+    confirm: bool,
+    limits: ServeLimits,
+) {
+    if connect.dry_run.is_some() {
+        eprintln!("A dry run has no device to keep open");
+        std::process::exit(1);
+    }
+    let socket = daemon::socket_path(&default_data_dir());
+    let listener = std::fs::create_dir_all(default_data_dir())
+        .and_then(|_| daemon::bind(&socket))
+        .unwrap_or_else(|e| {
+            eprintln!("Could not listen on {}: {}", socket.display(), e);
+            std::process::exit(1);
+        });
+    let permissions = if profile.daemon_clients.is_empty() {
+        ClientPermissions::default()
+    } else {
+        ClientPermissions::new(profile.daemon_owner_allow.clone())
+    };
+    let permissions = profile
+        .daemon_clients
+        .iter()
+        .try_fold(permissions, |permissions, (name, client)| {
+            let grant = ClientGrant::new(name, client.allow.clone());
+            let grant = match client.quota {
+                Some(quota) => grant.with_quota(quota),
+                None => grant,
+            };
+            permissions.with_client(&client.token_sha256, grant)
+        })
+        .unwrap_or_else(|e| {
+            eprintln!("Invalid daemon client: {}", e);
+            std::process::exit(1);
+        });
+    let hooks = ApprovalHooks::new(
+        notify
+            .then_some(ApprovalHook::Notify)
+            .into_iter()
+            .chain(webhook.into_iter().map(ApprovalHook::Webhook))
+            .chain(approve_with.map(ApprovalHook::Command))
+            .collect(),
+    );
+    println!("Keeping the device open for other invocations on {}", socket.display());
+    let served = daemon::serve(
+        &listener,
+        &permissions,
+        &limits,
+        || connect_transport(connect.transport, &connect.hid_filter),
+        |command, deadline| {
+            hooks.approve(command, deadline, |e| eprintln!("hook: {}", e)) &&
+                (!confirm || confirm_command("An invocation", command, Some(deadline)))
+        },
+        |e| eprintln!("device: {}", e),
+    );
+    if let Err(e) = served {
+        eprintln!("Could not accept connections on {}: {}", socket.display(), e);
+        std::process::exit(1);
+    }
+}
+
+fn run_remote(connect: &ConnectOptions, listen: SocketAddr) {
+    if connect.dry_run.is_some() {
+        eprintln!("A dry run has no device to serve");
+        std::process::exit(1);
+    }
+    let listener = TcpListener::bind(listen).unwrap_or_else(|e| {
+        eprintln!("Could not listen on {}: {}", listen, e);
+        std::process::exit(1);
+    });
+    let code = PairingCode::generate(listener.local_addr().unwrap_or(listen));
+    println!("Pair the frontend with {}", code);
+    if let Ok(qr) = QrCode::new(code.to_string()) {
+        println!("{}", qr.render::<unicode::Dense1x2>().build());
+    }
+    let served = remote::serve_remote(
+        &listener,
+        &code,
+        || connect_transport(connect.transport, &connect.hid_filter),
+        |command| confirm_command("The remote frontend", command, None),
+        |e| eprintln!("remote: {}", e),
+    );
+    if let Err(e) = served {
+        eprintln!("Could not accept connections on {}: {}", listen, e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(all(unix, feature = "rest"))]
+fn run_rest(connect: &ConnectOptions, listen: SocketAddr) {
+    println!("Serving the daemon's device on http://{}", listen);
+    let served = tokio::runtime::Runtime::new()
+        .map_err(|e| e.to_string())
+        .and_then(|runtime| {
+            runtime.block_on(rest::serve_rest(
+                listen,
+                daemon::socket_path(&default_data_dir()),
+                connect.command_deadline,
+            ))
+        });
+    if let Err(e) = served {
+        eprintln!("Could not serve on {}: {}", listen, e);
+        std::process::exit(1);
+    }
+}
+
+fn run_soak(connect: &ConnectOptions, hours: f64, interval: u64) {
+    if connect.dry_run.is_some() {
+        eprintln!("A dry run cannot soak a device");
+        std::process::exit(1);
+    }
+    if !(hours.is_finite() && hours > 0.0) {
+        eprintln!("--hours has to be a positive number");
+        std::process::exit(1);
+    }
+    let options = SoakOptions {
+        duration: Duration::from_secs_f64(hours * 3600.0),
+        interval: Duration::from_secs(interval),
+    };
+    let open = || {
+        let device = connect_transport(connect.transport, &connect.hid_filter)?.with_strictness(connect.strictness);
+        device.handshake().map(|_| device)
+    };
+    println!("Soaking the device for {} hour(s), interrupt to stop early", hours);
+    let report = soak::soak(options, open, |event| println!("{}", event));
+    println!("{}", report);
+    for cluster in report.clusters(soak::CLUSTER_GAP) {
+        println!("{}", cluster);
+    }
+}
+
+fn run_pair(connect: &ConnectOptions) {
+    if connect.dry_run.is_some() {
+        eprintln!("A dry run cannot pair with a device");
+        std::process::exit(1);
+    }
+    let device = connect_device(connect);
+    handshake(&device, connect);
+    let app_key = device.open_session(&device.random_secret(), None).unwrap_or_else(|e| {
+        eprintln!("Could not open an authenticated session: {}", e);
+        std::process::exit(1);
+    });
+    let mut bytes = [0u8; 32];
+    device.fill_random(&mut bytes);
+    let secret = PairingSecret::from_bytes(bytes);
+    println!(
+        "Check that the device shows the pairing words {} and confirm",
+        secret.words().join(" ")
+    );
+    if let Err(e) = pairing::pair(&device, &secret) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    let path = pairing::pairing_path(&default_data_dir().join("pairing"), &app_key);
+    if let Err(e) = pairing::save_pairing_secret(&path, &secret) {
+        eprintln!("Could not write {}: {}", path.display(), e);
+        std::process::exit(1);
+    }
+    println!("Paired, sessions opened with --authenticated now check the pairing");
+}
+
+fn run_gen_conformance(out: PathBuf) {
+    let cases = conformance_cases();
+    if let Err(e) = std::fs::write(&out, corpus_to_json(&cases)) {
+        eprintln!("Could not write {}: {}", out.display(), e);
+        std::process::exit(1);
+    }
+    println!("Wrote {} conformance cases to {}", cases.len(), out.display());
+}
+
+fn run_gen_protocol_spec(out: PathBuf, format: protocol_spec::SpecFormat) {
+    if let Err(e) = std::fs::write(&out, protocol_spec::generate(format)) {
+        eprintln!("Could not write {}: {}", out.display(), e);
+        std::process::exit(1);
+    }
+    println!("Wrote the protocol spec to {}", out.display());
+}
+
+fn run_simulate_tx(description: PathBuf, seed: u64) {
+    let is_toml = description.extension().map_or(false, |extension| extension == "toml");
+    let transcript = std::fs::read_to_string(&description)
+        .map_err(|e| format!("Could not read {}: {}", description.display(), e))
+        .and_then(|text| {
+            let description = if is_toml {
+                simulation::parse_description_toml(&text)
+            } else {
+                simulation::parse_description_json(&text)
+            };
+            description
+                .and_then(|description| simulation::simulate(&description, seed))
+                .map_err(|e| e.to_string())
+        })
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    print!("{}", transcript);
+}
+
+fn run_speculos(
+    app: PathBuf,
+    seed: String,
+    binary: PathBuf,
+    model: String,
+    apdu_port: u16,
+    record: Option<PathBuf>,
+    check: Option<PathBuf>,
+) {
+    let emulator = SpeculosOptions::new(app)
+        .with_seed(seed)
+        .with_binary(binary)
+        .with_model(model)
+        .with_apdu_port(apdu_port)
+        .launch()
+        .and_then(|emulator| emulator.device().map(|device| (emulator, device)));
+    let (_emulator, device) = emulator.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    if let Some(check) = check {
+        let recording = std::fs::read_to_string(&check).unwrap_or_else(|e| {
+            eprintln!("Could not read {}: {}", check.display(), e);
+            std::process::exit(1);
+        });
+        match speculos::check_vectors(&device, &recording) {
+            Ok(passed) => println!("All {} answers match {}", passed, check.display()),
+            Err(failures) => {
+                failures.iter().for_each(|failure| eprintln!("FAIL {}", failure));
+                std::process::exit(1);
+            },
+        }
+    } else {
+        let recording = speculos::record_vectors(&device).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+        match record {
+            Some(out) => {
+                if let Err(e) = std::fs::write(&out, recording) {
+                    eprintln!("Could not write {}: {}", out.display(), e);
+                    std::process::exit(1);
+                }
+                println!("Wrote the answers to {}", out.display());
+            },
+            None => println!("{}", recording),
+        }
+    }
+}
+
+fn run_payref(connect: &ConnectOptions, profile: &Profile, history: &History, output_hash: String, payment_id: String) {
+    let output_hash = parse_hash(&output_hash);
+    let device = open_device(connect);
+    let signer = transaction_signer(&device, profile, connect, history);
+    let proof = with_spinner("Signing the payment reference on the device", || {
+        PaymentProof::create(&signer, &output_hash, payment_id.as_bytes())
+    });
+    match proof {
+        Ok(proof) => {
+            println!("payment reference: {}", proof.reference);
+            println!("proof: {}", proof);
+        },
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    }
+}
+
+fn run_sign_tx(
+    connect: &ConnectOptions,
+    profile: &Profile,
+    amounts: AmountFormat,
+    history: &History,
+    input: PathBuf,
+    out: PathBuf,
+) {
+    let transaction = read_json_file(&input)
+        .and_then(|json| UnsignedTransaction::from_json(&json).map_err(|e| e.to_string()))
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    println!(
+        "{} inputs, {} outputs, fee {}",
+        transaction.input_messages.len(),
+        transaction.outputs.len(),
+        amounts.format(transaction.fee)
+    );
+    // The device shows the same amount and address for each output before signing it
+    for (i, output) in transaction.outputs.iter().enumerate() {
+        if let Some(recipient) = output.recipient.as_ref().filter(|_| !output.is_change) {
+            println!(
+                "output {}: {} to {}",
+                i,
+                amounts.format(output.value),
+                recipient.to_hex()
+            );
+        }
+    }
+    let device = open_device(connect);
+    let signer = transaction_signer(&device, profile, connect, history);
+    let signed = transaction.sign(&signer).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    if let Err(e) = std::fs::write(&out, signed) {
+        eprintln!("Could not write {}: {}", out.display(), e);
+        std::process::exit(1);
+    }
+    println!("Wrote the signed transaction to {}", out.display());
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_send(
+    connect: &ConnectOptions,
+    profile: &Profile,
+    amounts: AmountFormat,
+    history: &History,
+    to: TariAddress,
+    amount: String,
+    outputs: PathBuf,
+    message: PathBuf,
+    reply: PathBuf,
+    out: PathBuf,
+    lock_height: u64,
+    bound_script_keys: bool,
+    version: DerivationVersion,
+    #[cfg(feature = "broadcast")] base_node: Option<String>,
+    #[cfg(feature = "broadcast")] mined_within_secs: u64,
+) {
+    let amount = parse_amount(&amounts, &amount);
+    let unspent = read_unspent(&outputs);
+    let device = open_device(connect);
+    // The script key of a wallet output is the key at the index of its mask
+    let inputs = unspent
+        .into_iter()
+        .map(|output| {
+            let script_public_key =
+                public_key_with_version(&device, 0, KeyBranch::ScriptKey, output.mask_index, version)?;
+            Ok(SenderInput {
+                script_key: ScriptOffsetInput {
+                    index: output.mask_index,
+                    script_public_key,
+                    bound_to: bound_script_keys.then(|| output.commitment.clone()),
+                },
+                commitment: output.commitment,
+                value: output.value,
+                mask_index: output.mask_index,
+            })
+        })
+        .collect::<Result<Vec<_>, DeviceError>>()
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    let mut tx_id = [0u8; 8];
+    device.fill_random(&mut tx_id);
+    let request = SendRequest {
+        tx_id: u64::from_le_bytes(tx_id),
+        amount,
+        lock_height,
+        recipient: Some(to),
+        features_title: None,
+        inputs,
+    };
+    let signer = transaction_signer(&device, profile, connect, history)
+        .with_derivation_version(version)
+        .with_bound_script_keys(bound_script_keys);
+    let pending = with_state_store(&device, |store| transaction_protocol::send(&signer, store, request))
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    if let Err(e) = std::fs::write(&message, pending.message().to_json()) {
+        eprintln!("Could not write {}: {}", message.display(), e);
+        std::process::exit(1);
+    }
+    println!(
+        "transaction {}: sending {}, fee {}",
+        pending.message().tx_id,
+        amounts.format(amount),
+        amounts.format(pending.message().fee)
+    );
+    println!(
+        "Wrote the message for the receiver to {}, waiting for the reply in {}",
+        message.display(),
+        reply.display()
+    );
+
+    // The receiver may still be writing the reply, so one that does not parse is read again
+    let started = Instant::now();
+    let expiry = profile.timeouts.session_expiry();
+    let answer = loop {
+        let answer = read_json_file(&reply).and_then(|json| ReceiverReply::from_json(&json).map_err(|e| e.to_string()));
+        match answer {
+            Ok(answer) => break answer,
+            Err(e) if started.elapsed() > expiry => {
+                eprintln!("No reply before the signing session expired: {}", e);
+                std::process::exit(1);
+            },
+            Err(_) => std::thread::sleep(Duration::from_secs(1)),
+        }
+    };
+    let transaction = pending.finish(&answer).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    if let Err(e) = std::fs::write(&out, transaction.to_json()) {
+        eprintln!("Could not write {}: {}", out.display(), e);
+        std::process::exit(1);
+    }
+    if let Some(change) = &transaction.change {
+        println!("change: {}", change);
+    }
+    println!("Wrote the finished transaction to {}", out.display());
+    #[cfg(feature = "broadcast")]
+    if let Some(base_node) = base_node {
+        broadcast_transaction(&base_node, &transaction, Duration::from_secs(mined_within_secs));
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_receive(
+    connect: &ConnectOptions,
+    profile: &Profile,
+    amounts: AmountFormat,
+    input: PathBuf,
+    out: PathBuf,
+    mask_index: u32,
+    script_key_index: Option<u32>,
+    account: Option<u32>,
+    version: DerivationVersion,
+) {
+    let message = read_json_file(&input)
+        .and_then(|json| SenderMessage::from_json(&json).map_err(|e| e.to_string()))
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    println!(
+        "transaction {}: receiving {}, fee {}, lock height {}",
+        message.tx_id,
+        amounts.format(message.amount),
+        amounts.format(message.fee),
+        message.lock_height
+    );
+    let device = open_device(connect);
+    let account = account.unwrap_or(profile.account);
+    let script_key_index = script_key_index.unwrap_or(mask_index);
+    let reply = transaction_protocol::receive(&device, &message, account, mask_index, script_key_index, version)
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    if let Err(e) = std::fs::write(&out, reply.to_json()) {
+        eprintln!("Could not write {}: {}", out.display(), e);
+        std::process::exit(1);
+    }
+    println!("output commitment: {}", reply.output.commitment.to_hex());
+    println!("Wrote the reply for the sender to {}", out.display());
+}
+
+fn run_multisig(connect: &ConnectOptions, profile: &Profile, history: &History, action: MultisigAction) {
+    match action {
+        MultisigAction::New {
+            threshold,
+            participants,
+            message,
+            file,
+        } => {
+            let document = MultisigDocument::new(threshold, participants, parse_hash(&message)).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            });
+            write_multisig(&file, &document);
+        },
+        MultisigAction::Contribute { file } => {
+            let mut document = read_multisig(&file);
+            let device = open_device(connect);
+            let signer = transaction_signer(&device, profile, connect, history);
+            match document.contribute(&signer) {
+                Ok(state) => {
+                    write_multisig(&file, &document);
+                    println!("The document is now {}", state);
+                },
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                },
+            }
+        },
+        MultisigAction::Show { file, qr, cbor } => {
+            let document = read_multisig(&file);
+            println!(
+                "{}-of-{} multisig, {}: {} keys, {} signatures",
+                document.threshold(),
+                document.participants(),
+                document.state(),
+                document.keys().len(),
+                document.signatures().len()
+            );
+            if let Ok(script) = document.script() {
+                println!("script: {}", script.to_bytes().to_hex());
+            }
+            if let Ok(input_data) = document.input_data() {
+                println!("input data: {}", input_data.to_bytes().to_hex());
+            }
+            if qr {
+                let payload = if cbor {
+                    document.to_cbor()
+                } else {
+                    document.to_json().into_bytes()
+                };
+                match QrCode::new(payload) {
+                    Ok(code) => println!("{}", code.render::<unicode::Dense1x2>().build()),
+                    Err(e) => {
+                        eprintln!("The document does not fit in a QR code: {}", e);
+                        std::process::exit(1);
+                    },
+                }
+            }
+        },
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_process_withdrawals(
+    connect: &ConnectOptions,
+    profile: &Profile,
+    amounts: AmountFormat,
+    history: &History,
+    file: PathBuf,
+    batch_size: usize,
+    inputs: usize,
+    report: PathBuf,
+) {
+    let pending = std::fs::read_to_string(&file)
+        .map_err(|e| format!("Could not read {}: {}", file.display(), e))
+        .and_then(|csv| withdrawals::parse_withdrawals(&csv, profile.network).map_err(|e| e.to_string()))
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    let total = pending.iter().map(|withdrawal| withdrawal.amount).sum::<u64>();
+    println!("{} withdrawals, {} in total", pending.len(), amounts.format(total));
+    let device = open_device(connect);
+    // The outputs carry no addresses for the device to show one at a time, the digest covers them instead
+    let signer = transaction_signer(&device, profile, connect, history)
+        .with_silent_outputs(true)
+        .with_display_hints(true);
+    let signed = withdrawals::process_withdrawals(&signer, &pending, batch_size, inputs, |i, batch| {
+        let amount = batch.iter().map(|withdrawal| withdrawal.amount).sum::<u64>();
+        println!(
+            "batch {}: lines {} to {}, {}",
+            i,
+            batch[0].line,
+            batch[batch.len() - 1].line,
+            amounts.format(amount)
+        );
+    })
+    .unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    if let Err(e) = std::fs::write(&report, signed.to_json()) {
+        eprintln!("Could not write {}: {}", report.display(), e);
+        std::process::exit(1);
+    }
+    println!(
+        "Signed {} of {} withdrawals, wrote the report to {}",
+        signed.signed_count(),
+        pending.len(),
+        report.display()
+    );
+    if let Some(e) = signed.error {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run_estimate(
+    profile: &Profile,
+    amounts: AmountFormat,
+    inputs: usize,
+    recipients: usize,
+    features_size: Option<usize>,
+    no_change: bool,
+    fee_per_gram: Option<u64>,
+) {
+    let recipient = PlannedOutput {
+        is_change: false,
+        features_and_scripts_size: features_size.unwrap_or_else(change_output_size),
+    };
+    let mut outputs = vec![recipient; recipients];
+    if !no_change {
+        outputs.push(PlannedOutput::change());
+    }
+    let estimate = estimate_transaction(
+        &FeeCalculator::new(fee_per_gram.unwrap_or(profile.fee_per_gram)),
+        inputs,
+        &outputs,
+        Capabilities::OUTPUT_CONFIRMATION.union(Capabilities::DISPLAY_HINTS),
+    );
+    println!("{}", estimate.to_text(&amounts));
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_balance(
+    connect: &ConnectOptions,
+    profile: &Profile,
+    amounts: AmountFormat,
+    history: &History,
+    outputs: PathBuf,
+    height: u64,
+    receiving: Vec<PathBuf>,
+    sending: Vec<PathBuf>,
+    json: bool,
+) {
+    let unspent = read_unspent(&outputs);
+    let read_messages = |files: &[PathBuf]| {
+        files
+            .iter()
+            .map(|file| {
+                read_json_file(file)
+                    .and_then(|json| SenderMessage::from_json(&json).map_err(|e| e.to_string()))
+                    .unwrap_or_else(|e| {
+                        eprintln!("{}", e);
+                        std::process::exit(1);
+                    })
+            })
+            .collect::<Vec<_>>()
+    };
+    let incoming = read_messages(&receiving);
+    let outgoing = read_messages(&sending);
+    let device = open_device(connect);
+    let signer = transaction_signer(&device, profile, connect, history);
+    let report = with_spinner("Checking the outputs on the device", || {
+        balance::balance(&signer, unspent, height, &incoming, &outgoing)
+    })
+    .unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    if json {
+        println!("{}", report.to_json());
+    } else {
+        println!("{}", report.to_text(&amounts));
+    }
+}
+
+fn run_recover(
+    connect: &ConnectOptions,
+    profile: &Profile,
+    chain_data: PathBuf,
+    account: Option<u32>,
+    gap_limit: u32,
+    version: DerivationVersion,
+    json: bool,
+) {
+    let chain = ChainDataFile::new(chain_data).chain_data().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let device = open_device(connect);
+    let account = account.unwrap_or(profile.account);
+    let report = with_spinner("Scanning the key branches", || {
+        recovery::recover(&device, account, &chain, gap_limit, version, |_, _| {})
+    })
+    .unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    if let Err(e) = with_state_store(&device, |store| recovery::restore_state(store, &report)) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    if json {
+        println!("{}", report.to_json());
+    } else {
+        println!("{}", report);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_sweep(
+    connect: &ConnectOptions,
+    profile: &Profile,
+    amounts: AmountFormat,
+    history: &History,
+    to: TariAddress,
+    outputs: PathBuf,
+    dust_threshold: Option<String>,
+    out: PathBuf,
+) {
+    let unspent = read_unspent(&outputs);
+    let dust_threshold = dust_threshold
+        .map(|threshold| parse_amount(&amounts, &threshold))
+        .unwrap_or_else(|| sweep::dust_threshold(&FeeCalculator::new(profile.fee_per_gram)));
+    let device = open_device(connect);
+    let signer = transaction_signer(&device, profile, connect, history);
+    let signed = with_spinner("Checking the outputs on the device", || {
+        sweep::scan_outputs(&signer, unspent, dust_threshold)
+    })
+    .and_then(|scan| {
+        println!(
+            "{} outputs worth {} to sweep, leaving behind {} dust outputs and {} outputs of another seed",
+            scan.inputs.len(),
+            amounts.format(scan.total()),
+            scan.dust.len(),
+            scan.foreign.len()
+        );
+        let output = sweep::sweep_output(&signer, &scan, to, profile.network)?;
+        println!(
+            "Sending {} to {}, a fee of {}",
+            amounts.format(output.value),
+            output.address,
+            amounts.format(scan.total() - output.value)
+        );
+        sweep::sign_sweep(&signer, &scan, &output)
+    })
+    .unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    if let Err(e) = std::fs::write(&out, signed) {
+        eprintln!("Could not write {}: {}", out.display(), e);
+        std::process::exit(1);
+    }
+    println!("Wrote the signed sweep to {}", out.display());
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_split(
+    connect: &ConnectOptions,
+    profile: &Profile,
+    amounts: AmountFormat,
+    history: &History,
+    outputs: PathBuf,
+    commitment: String,
+    parts: usize,
+    out: PathBuf,
+) {
+    let input = read_unspent(&outputs)
+        .into_iter()
+        .find(|output| output.commitment.to_hex() == commitment.to_lowercase())
+        .unwrap_or_else(|| {
+            eprintln!(
+                "There is no output with commitment {} in {}",
+                commitment,
+                outputs.display()
+            );
+            std::process::exit(1);
+        });
+    let device = open_device(connect);
+    let signer = transaction_signer(&device, profile, connect, history);
+    let split = with_state_store(&device, |store| {
+        denominations::split_output(&signer, store, input, parts)
+    });
+    write_reshaped(&out, split, &amounts);
+}
+
+fn run_consolidate(
+    connect: &ConnectOptions,
+    profile: &Profile,
+    amounts: AmountFormat,
+    history: &History,
+    outputs: PathBuf,
+    out: PathBuf,
+) {
+    let inputs = read_unspent(&outputs);
+    let device = open_device(connect);
+    let signer = transaction_signer(&device, profile, connect, history);
+    let consolidated = with_state_store(&device, |store| {
+        denominations::consolidate_outputs(&signer, store, inputs)
+    });
+    write_reshaped(&out, consolidated, &amounts);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_migrate(
+    connect: &ConnectOptions,
+    profile: &Profile,
+    amounts: AmountFormat,
+    history: &History,
+    outputs: PathBuf,
+    from: DerivationVersion,
+    dust_threshold: Option<String>,
+    out: PathBuf,
+) {
+    let unspent = read_unspent(&outputs);
+    let dust_threshold = dust_threshold
+        .map(|threshold| parse_amount(&amounts, &threshold))
+        .unwrap_or_else(|| sweep::dust_threshold(&FeeCalculator::new(profile.fee_per_gram)));
+    let device = open_device(connect);
+    let to = DerivationVersion::LATEST;
+    let supported = migration::supported_versions(&device).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    if !supported.contains(&to) {
+        eprintln!("The app cannot derive keys under the {} scheme, update it first", to);
+        std::process::exit(1);
+    }
+    let old = transaction_signer(&device, profile, connect, history).with_derivation_version(from);
+    let new = transaction_signer(&device, profile, connect, history).with_derivation_version(to);
+    let scan = with_spinner("Checking the outputs on the device", || {
+        migration::scan_schemes(&device, unspent, from, to, dust_threshold)
+    })
+    .unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    println!(
+        "{} outputs worth {} to migrate from the {} to the {} scheme, {} already migrated, leaving behind {} dust \
+         outputs and {} outputs of another seed",
+        scan.inputs.len(),
+        amounts.format(scan.total()),
+        from,
+        to,
+        scan.migrated.len(),
+        scan.dust.len(),
+        scan.foreign.len()
+    );
+    let migrated = with_state_store(&device, |store| migration::migrate_outputs(&old, &new, store, &scan));
+    write_reshaped(&out, migrated, &amounts);
+}
+
+fn run_address(
+    connect: &ConnectOptions,
+    profile: &Profile,
+    account: Option<u32>,
+    branch: KeyBranch,
+    index: u32,
+    blind: bool,
+) {
+    let device = open_device(connect);
+    let account = account.unwrap_or(profile.account);
+    let public_key = if blind {
+        // The app key is the first key of the first account, which every wallet uses anyway
+        public_key(&device, 0, KeyBranch::CommitmentMask, 0).and_then(|app_public_key| {
+            let host_secret = device.random_secret();
+            blinded_public_key(&device, &app_public_key, account, branch, index, &host_secret)
+        })
+    } else {
+        public_key(&device, account, branch, index)
+    };
+    let public_key = public_key.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let address = TariAddress::new(public_key, profile.network);
+    println!("path: {}", key_path(account, branch, index));
+    println!("network: {}", address.network());
+    println!("emoji id: {}", address);
+    println!("hex: {}", address.to_hex());
+}
+
+fn run_derive(connect: &ConnectOptions, path: KeyPath, explain: bool, version: DerivationVersion) {
+    let device = open_device(connect);
+    let public_key =
+        public_key_with_version(&device, path.account, path.branch, path.index, version).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    println!("path: {}", path);
+    println!("version: {}", version);
+    if explain {
+        for (step, line) in explain_derivation(&path, version).iter().enumerate() {
+            println!("{}. {}", step + 1, line);
+        }
+    }
+    println!("public key: {}", public_key.to_hex());
+}
+
+fn run_export_pubkeys(
+    connect: &ConnectOptions,
+    profile: &Profile,
+    account: Option<u32>,
+    branch: KeyBranch,
+    range: Range<u32>,
+    out: PathBuf,
+) {
+    let device = open_device(connect);
+    let account = account.unwrap_or(profile.account);
+    let bar = progress_bar(range.len(), "keys");
+    let export = export_public_keys(&device, account, branch, range, |done, _| bar.set_position(done as u64));
+    bar.finish_and_clear();
+    let export = export.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let manifest = if out.extension().map(|ext| ext == "csv").unwrap_or(false) {
+        export.to_csv()
+    } else {
+        export.to_json()
     };
+    if let Err(e) = std::fs::write(&out, manifest) {
+        eprintln!("Could not write {}: {}", out.display(), e);
+        std::process::exit(1);
+    }
+    println!("Exported {} public keys to {}", export.keys.len(), out.display());
+}
+
+fn run_export_key(connect: &ConnectOptions, profile: &Profile, account: Option<u32>, key: SensitiveKey) {
+    let device = open_device(connect);
+    let account = account.unwrap_or(profile.account);
+    let host_secret = device.random_secret();
+    let export = with_spinner("Confirm the export on the device", || {
+        export_private_key(&device, account, key, &host_secret)
+    });
+    match export {
+        Ok(secret) => {
+            println!("path: {}", sensitive_key_path(account, key));
+            println!("{}: {}", key, secret.to_hex());
+        },
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    }
+}
+
+fn run_export_watch_only(
+    connect: &ConnectOptions,
+    profile: &Profile,
+    account: Option<u32>,
+    count: u32,
+    passphrase: String,
+    out: PathBuf,
+) {
+    let device = open_device(connect);
+    let account = account.unwrap_or(profile.account);
+    println!("Confirm the export of the view key on the device");
+    let bar = progress_bar(count as usize * watch_only::WATCH_ONLY_BRANCHES.len(), "keys");
+    let bundle = watch_only::watch_only_bundle(&device, account, profile.network, count, |done, _| {
+        bar.set_position(done as u64)
+    });
+    bar.finish_and_clear();
+    let mut bundle = bundle.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    // A device without a birthday may still have one kept on the host
+    if bundle.metadata.birthday.is_none() {
+        bundle.metadata.birthday = with_state_store(&device, birthday::stored_birthday).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    }
+    let encrypted = bundle.encrypt(&passphrase).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    if let Err(e) = std::fs::write(&out, encrypted) {
+        eprintln!("Could not write {}: {}", out.display(), e);
+        std::process::exit(1);
+    }
+    println!(
+        "Wrote the watch-only bundle of account {} on {} to {}",
+        account,
+        profile.network,
+        out.display()
+    );
+}
+
+fn run_birthday(
+    connect: &ConnectOptions,
+    profile: &Profile,
+    set: Option<u64>,
+    account: Option<u32>,
+    out: Option<PathBuf>,
+) {
+    let device = open_device(connect);
+    if let Some(height) = set {
+        if let Err(e) = birthday::set_wallet_birthday(&device, height) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    let mut metadata = birthday::wallet_metadata(&device, account.unwrap_or(profile.account)).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let stored = with_state_store(&device, |store| match metadata.birthday {
+        Some(height) => birthday::persist_birthday(store, height).map(|_| None),
+        None => birthday::stored_birthday(store),
+    })
+    .unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    match (metadata.birthday, stored) {
+        (Some(height), _) => println!("Wallet birthday: block {}", height),
+        // An app reinstalled or updated since loses what it recorded, the host still knows
+        (None, Some(height)) => {
+            println!("Wallet birthday: block {}, as recorded by this host", height);
+            metadata.birthday = Some(height);
+        },
+        (None, None) => println!("No wallet birthday is recorded, recovery scans start at genesis"),
+    }
+    if let Some(out) = out {
+        if let Err(e) = std::fs::write(&out, metadata.to_json()) {
+            eprintln!("Could not write {}: {}", out.display(), e);
+            std::process::exit(1);
+        }
+        println!("Wrote the wallet metadata to {}", out.display());
+    }
+}
+
+fn run_verify_payref(proof: String, output_hash: String, payment_id: String) {
+    let output_hash = parse_hash(&output_hash);
+    let proof = proof.parse::<PaymentProof>().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    if proof.verify(&output_hash, payment_id.as_bytes()) {
+        println!("Valid payment proof signed by {}", proof.public_key.to_hex());
+    } else {
+        eprintln!("The payment proof is not valid for this output and payment id");
+        std::process::exit(1);
+    }
+}
+
+fn run_verify(public_key: String, signature: String, message: String, raw_challenge: bool) {
+    let public_key = parse_public_key(&public_key);
+    let signature = from_hex(&signature)
+        .ok()
+        .and_then(|bytes| verify::signature_from_bytes(&bytes))
+        .unwrap_or_else(|| {
+            eprintln!("'{}' is not a hex encoded 64-byte signature", signature);
+            std::process::exit(1);
+        });
+    let message = parse_hash(&message);
+    let valid = if raw_challenge {
+        verify::verify_challenge_signature(&public_key, &signature, &message)
+    } else {
+        verify::verify_script_signature(&public_key, &signature, &message)
+    };
+    if valid {
+        println!("Valid signature by {}", public_key.to_hex());
+    } else {
+        eprintln!("The signature is not valid for this public key and message");
+        std::process::exit(1);
+    }
+}
+
+fn run_script(script: String, input: String, height: u64) {
+    let script = from_hex(&script)
+        .ok()
+        .and_then(|bytes| TariScript::from_bytes(&bytes).ok())
+        .unwrap_or_else(|| {
+            eprintln!("'{}' is not a hex encoded script", script);
+            std::process::exit(1);
+        });
+    let input = from_hex(&input)
+        .ok()
+        .and_then(|bytes| ExecutionStack::from_bytes(&bytes).ok())
+        .unwrap_or_else(|| {
+            eprintln!("'{}' is not a hex encoded execution stack", input);
+            std::process::exit(1);
+        });
+    println!("Script: {}", script);
+    match interpreter::execute(&script, &input, &ScriptContext::at_height(height)) {
+        Ok(result) => println!("Result: {}", result),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    }
+}
+
+fn run_convert(input: PathBuf, out: PathBuf) {
+    let bytes = std::fs::read(&input).unwrap_or_else(|e| {
+        eprintln!("Could not read {}: {}", input.display(), e);
+        std::process::exit(1);
+    });
+    let converted = if !cbor::is_cbor(&bytes) {
+        String::from_utf8(bytes)
+            .map_err(|_| format!("{} is neither JSON nor CBOR", input.display()))
+            .and_then(|json| cbor::json_to_cbor(&json).map_err(|e| e.to_string()))
+    } else if cbor::document_kind(&bytes).ok() == Some(cbor::MULTISIG_DOCUMENT_TAG) {
+        MultisigDocument::from_cbor(&bytes)
+            .map(|document| document.to_json().into_bytes())
+            .map_err(|e| e.to_string())
+    } else {
+        cbor::cbor_to_json(&bytes)
+            .map(String::into_bytes)
+            .map_err(|e| e.to_string())
+    };
+    let converted = converted.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    if let Err(e) = std::fs::write(&out, &converted) {
+        eprintln!("Could not write {}: {}", out.display(), e);
+        std::process::exit(1);
+    }
+    println!("Wrote {} bytes to {}", converted.len(), out.display());
+}
+
+fn run_wallet(
+    connect: &ConnectOptions,
+    profile: &Profile,
+    mut config: Config,
+    config_path: &Path,
+    config_key: Option<&str>,
+    label: Option<String>,
+) {
+    let device = open_device(connect);
+    let fingerprint = wallet_fingerprint(&device).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    if let Some(label) = label {
+        config.wallets.insert(fingerprint.clone(), label);
+        // Keep the file encrypted if it was
+        let encrypted = std::fs::read(config_path)
+            .map(|bytes| config::is_encrypted(&bytes))
+            .unwrap_or(false);
+        if let Err(e) = config.save(config_path, config_key.filter(|_| encrypted)) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    let label = config.wallet_label(&fingerprint).unwrap_or("unlabeled");
+    println!("wallet: {} ({})", fingerprint, label);
+    match &profile.wallet {
+        Some(pinned) if *pinned == fingerprint => println!("the profile is pinned to this wallet"),
+        Some(pinned) => println!("the profile is pinned to wallet {}", pinned),
+        None => {},
+    }
+}
+
+fn run_encrypt_config(config: &Config, config_path: &Path, config_key: Option<&str>) {
+    let passphrase = config_key.unwrap_or_else(|| {
+        eprintln!("The passphrase is required, use --config-key or TARI_LEDGER_CONFIG_KEY");
+        std::process::exit(1);
+    });
+    if let Err(e) = config.save(config_path, Some(passphrase)) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    println!("Encrypted {}", config_path.display());
+}
+
+#[cfg(feature = "history")]
+fn run_history(connect: &ConnectOptions, history: History, limit: Option<usize>, action: Option<HistoryAction>) {
+    let history = history.unwrap_or_else(|| {
+        eprintln!("The history passphrase is required, use --history-key or TARI_LEDGER_HISTORY_KEY");
+        std::process::exit(1);
+    });
+    let result = match action {
+        None => history
+            .records(limit)
+            .map(|records| records.iter().for_each(|record| println!("{}", record))),
+        Some(HistoryAction::Export { format, since }) => history
+            .records_since(since.unwrap_or(0))
+            .map(|records| print!("{}", history::export(&records, format))),
+        Some(HistoryAction::Import { file }) => std::fs::read_to_string(&file)
+            .map_err(Into::into)
+            .and_then(|json| history.import_json(&json))
+            .map(|count| println!("Imported {} records from {}", count, file.display())),
+        Some(HistoryAction::Verify) => history.records_since(0).map(|records| {
+            let verification = history::verify_records(&records);
+            for (record, problem) in &verification.flagged {
+                println!("#{} {}: {}", record.id, record.instruction, problem);
+            }
+            println!("{}", verification);
+            if !verification.is_clean() {
+                std::process::exit(1);
+            }
+        }),
+        Some(HistoryAction::Reconcile { accept }) => {
+            let device = open_device(connect);
+            let counter = device.output_counter().unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            });
+            history.reconcile_outputs(counter, accept).map(|reconciliation| {
+                println!("{}", reconciliation);
+                if !reconciliation.is_clean() && !accept {
+                    std::process::exit(1);
+                }
+            })
+        },
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+/// How to connect to the device, taken from the selected profile
+struct ConnectOptions {
+    retry_policy: RetryPolicy,
+    /// How long a cached handshake may be reused
+    session_ttl: Duration,
+    /// How long the daemon waits for the device to answer a command
+    command_deadline: Option<Duration>,
+    /// The token to identify with to the daemon
+    daemon_token: Option<String>,
+    transport: TransportKind,
+    /// Record the APDUs instead of connecting to a device
+    dry_run: Option<DryRunLog>,
+    /// Open an authenticated session after the handshake
+    authenticated: bool,
+    strictness: Strictness,
+    /// The fingerprint of the seed the profile is pinned to
+    wallet: Option<String>,
+    allow_silent_outputs: bool,
+    /// Which USB devices are taken for a Ledger
+    hid_filter: HidFilter,
+    /// Seed the host's randomness with, for reproducible tests
+    rng_seed: Option<u64>,
+    /// The rule for transactions that need a second operator, and how the operator confirms them
+    two_man_rule: Option<(TwoManRule, Box<dyn SecondaryConfirmation>)>,
+}
+
+/// A second operator confirming at the terminal
+struct PromptConfirmation {
+    amounts: AmountFormat,
+}
+
+impl SecondaryConfirmation for PromptConfirmation {
+    fn confirm(&self, request: &ConfirmationRequest) -> Result<(), String> {
+        let stdin = std::io::stdin();
+        if !stdin.is_terminal() {
+            return Err("there is no terminal for the second operator to confirm at".to_string());
+        }
+        eprint!(
+            "The transaction pays out {} in {} payment(s), the fee included, more than the {} a second operator has \
+             to confirm. Second operator, type 'yes' to send it to the device: ",
+            self.amounts.format(request.amount),
+            request.payments,
+            self.amounts.format(request.threshold)
+        );
+        let mut answer = String::new();
+        stdin.read_line(&mut answer).map_err(|e| e.to_string())?;
+        if answer.trim() == "yes" {
+            Ok(())
+        } else {
+            Err("the second operator declined".to_string())
+        }
+    }
+}
+
+/// Ask at the terminal whether `client`, e.g. the remote frontend, may send `command` to the device. The command is
+/// refused unless the answer comes by `deadline`.
+fn confirm_command(client: &str, command: &APDUCommand<Vec<u8>>, deadline: Option<Instant>) -> bool {
+    // The terminal is read on a thread of its own, so that a prompt can stop waiting for it
+    static TYPED: Lazy<Mutex<mpsc::Receiver<String>>> = Lazy::new(|| {
+        let (lines, typed) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in std::io::stdin().lines() {
+                if line.map_or(true, |line| lines.send(line).is_err()) {
+                    break;
+                }
+            }
+        });
+        Mutex::new(typed)
+    });
+    if !std::io::stdin().is_terminal() {
+        return false;
+    }
+    let name = Instruction::try_from(command.ins).map_or("an unknown command", |instruction| instruction.spec().name);
+    let typed = TYPED.lock().unwrap_or_else(|e| e.into_inner());
+    // What was typed while no prompt was up does not answer this one
+    while typed.try_recv().is_ok() {}
+    eprint!("{} asks for {}. Type 'yes' to send it to the device: ", client, name);
+    let answer = match deadline {
+        Some(deadline) => typed.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+        None => typed.recv().map_err(RecvTimeoutError::from),
+    };
+    match answer {
+        Ok(answer) => answer.trim() == "yes",
+        Err(RecvTimeoutError::Timeout) => {
+            eprintln!();
+            eprintln!("No answer in time, the command is refused");
+            false
+        },
+        Err(RecvTimeoutError::Disconnected) => false,
+    }
+}
+
+/// Connect to the device and check that the app and this client support each other
+fn open_device(connect: &ConnectOptions) -> LedgerDevice {
+    let device = connect_device(connect);
+    handshake(&device, connect);
+    // The placeholder answers of a dry run cannot complete the key agreement
+    if connect.authenticated && connect.dry_run.is_none() {
+        match device.open_session(&device.random_secret(), None) {
+            Ok(app_key) => check_pairing(&device, &app_key),
+            Err(e) => {
+                eprintln!("Could not open an authenticated session: {}", e);
+                std::process::exit(1);
+            },
+        }
+    }
+    // State and signatures must never mix between the seeds of a device, and the placeholder keys of a dry run match
+    // no seed
+    if let (Some(wallet), None) = (&connect.wallet, &connect.dry_run) {
+        if let Err(e) = check_wallet(&device, wallet) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    device
+}
+
+/// If this host was paired with the device, prove it to the device and have the device prove it back, so that the
+/// device shows the pairing words only to the genuine host
+fn check_pairing(device: &LedgerDevice, app_key: &RistrettoPublicKey) {
+    let path = pairing::pairing_path(&default_data_dir().join("pairing"), app_key);
+    let secret = match pairing::load_pairing_secret(&path) {
+        Ok(Some(secret)) => secret,
+        Ok(None) => return,
+        Err(e) => {
+            eprintln!("Could not read {}: {}", path.display(), e);
+            std::process::exit(1);
+        },
+    };
+    match pairing::verify_pairing(device, &secret) {
+        Ok(words) => println!("Check that the device shows the pairing words {}", words.join(" ")),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    }
+}
+
+/// Connect to the device without talking to the app, which may not even be open
+fn connect_device(connect: &ConnectOptions) -> LedgerDevice {
+    let device = match &connect.dry_run {
+        Some(log) => LedgerDevice::from_transport(DryRunTransport::new(log.clone())),
+        None => connect_daemon(connect.command_deadline, connect.daemon_token.as_deref()).unwrap_or_else(|| {
+            connect_transport(connect.transport, &connect.hid_filter).unwrap_or_else(|e| {
+                eprintln!("Could not connect to the device: {}", e);
+                std::process::exit(1);
+            })
+        }),
+    }
+    .with_strictness(connect.strictness);
+    match connect.rng_seed {
+        Some(seed) => device.with_seed(seed),
+        None => device,
+    }
+}
+
+/// The device a running `tari-ledger daemon` keeps open, unless none runs or it is busy with another invocation
+#[cfg(unix)]
+fn connect_daemon(deadline: Option<Duration>, token: Option<&str>) -> Option<LedgerDevice> {
+    daemon::connect(&daemon::socket_path(&default_data_dir()), deadline, token).ok()
+}
+
+#[cfg(not(unix))]
+fn connect_daemon(_deadline: Option<Duration>, _token: Option<&str>) -> Option<LedgerDevice> {
+    None
+}
+
+fn connect_transport(transport: TransportKind, filter: &HidFilter) -> Result<LedgerDevice, DeviceError> {
+    match transport {
+        #[cfg(feature = "hid")]
+        TransportKind::Hid => LedgerDevice::open_filtered(hidapi(), filter),
+        #[cfg(not(feature = "hid"))]
+        TransportKind::Hid => {
+            eprintln!(
+                "The hid transport needs a build with the `hid` feature, set `transport = \"hidraw\"` in the profile"
+            );
+            std::process::exit(1);
+        },
+        #[cfg(all(feature = "hidraw-direct", target_os = "linux"))]
+        TransportKind::Hidraw => LedgerDevice::open_hidraw_filtered(filter),
+        #[cfg(not(all(feature = "hidraw-direct", target_os = "linux")))]
+        TransportKind::Hidraw => {
+            eprintln!("The hidraw transport needs a Linux build with the `hidraw-direct` feature");
+            std::process::exit(1);
+        },
+    }
+}
+
+/// Run the version handshake, or resume a recent session with the same device, waiting for the user to unlock the
+/// device if it is locked
+fn handshake(device: &LedgerDevice, connect: &ConnectOptions) -> HandshakeInfo {
+    let mut prompted = false;
+    let on_locked = |remaining: Duration| {
+        if !prompted {
+            eprintln!(
+                "Please unlock your device, waiting up to {} seconds...",
+                remaining.as_secs()
+            );
+            prompted = true;
+        }
+    };
+    let session_path = default_data_dir().join("session.json");
+    retry_while_locked(&connect.retry_policy, on_locked, || {
+        session::resume_or_handshake(device, &session_path, connect.session_ttl, || device.handshake())
+    })
+    .unwrap_or_else(|e| {
+        session::clear(&session_path);
+        eprintln!("{}", e);
+        std::process::exit(1);
+    })
+}
+
+/// With the signing history open, requests that completed before are answered from it and every signed output is
+/// recorded in it
+fn transaction_signer<'a>(
+    device: &'a LedgerDevice,
+    profile: &Profile,
+    connect: &'a ConnectOptions,
+    #[cfg_attr(not(feature = "history"), allow(unused_variables))] history: &'a History,
+) -> LedgerTransactionSigner<'a> {
+    let mode = match connect.dry_run {
+        Some(_) => SignerMode::Offline,
+        None => SignerMode::Device,
+    };
+    let signer = LedgerTransactionSigner::new(device, FeeCalculator::new(profile.fee_per_gram))
+        .with_session_expiry(profile.timeouts.session_expiry())
+        .with_mode(mode)
+        .with_silent_outputs(connect.allow_silent_outputs)
+        .with_lock_recovery(connect.retry_policy, |remaining| {
+            eprintln!(
+                "The device locked itself, unlock it to continue signing, waiting up to {} seconds...",
+                remaining.as_secs()
+            );
+        })
+        .with_display_listener(|display| {
+            println!("Check that the device shows the digest {}", display.fingerprint());
+        });
+    #[cfg(feature = "history")]
+    let signer = match history {
+        Some(history) => signer.with_idempotency_log(history).with_output_log(history),
+        None => signer,
+    };
+    let signer = match &connect.two_man_rule {
+        Some((rule, confirmation)) => signer.with_two_man_rule(rule.clone(), confirmation.as_ref()),
+        None => signer,
+    };
+    match profile.max_fee {
+        Some(max_fee) => signer.with_max_fee(max_fee),
+        None => signer,
+    }
+}
+
+/// Parse a hex encoded public key or a Tari address in either of its encodings
+fn parse_public_key(key: &str) -> RistrettoPublicKey {
+    if let Ok(public_key) = RistrettoPublicKey::from_hex(key) {
+        return public_key;
+    }
+    match key.parse::<TariAddress>() {
+        Ok(address) => address.public_key().clone(),
+        Err(_) => {
+            eprintln!("'{}' is neither a hex encoded public key nor a Tari address", key);
+            std::process::exit(1);
+        },
+    }
+}
+
+fn parse_address(address: &str) -> Result<TariAddress, String> {
+    address
+        .parse()
+        .map_err(|_| format!("'{}' is not a Tari address", address))
+}
+
+fn parse_hash(hex: &str) -> [u8; 32] {
+    match from_hex(hex) {
+        Ok(bytes) if bytes.len() == 32 => {
+            let mut hash = [0u8; 32];
+            hash.copy_from_slice(&bytes);
+            hash
+        },
+        _ => {
+            eprintln!("'{}' is not a 32-byte hex encoded hash", hex);
+            std::process::exit(1);
+        },
+    }
+}
+
+/// The JSON document in `file`, converted first if the file holds CBOR
+fn read_json_file(file: &Path) -> Result<String, String> {
+    std::fs::read(file)
+        .map_err(|e| format!("Could not read {}: {}", file.display(), e))
+        .and_then(|bytes| cbor::read_json(&bytes).map_err(|e| e.to_string()))
+}
+
+fn is_cbor_file(file: &Path) -> bool {
+    file.extension().map_or(false, |extension| extension == "cbor")
+}
+
+fn read_multisig(file: &Path) -> MultisigDocument {
+    std::fs::read(file)
+        .map_err(|e| format!("Could not read {}: {}", file.display(), e))
+        .and_then(|bytes| {
+            if cbor::is_cbor(&bytes) {
+                MultisigDocument::from_cbor(&bytes).map_err(|e| e.to_string())
+            } else {
+                let json = String::from_utf8(bytes).map_err(|_| format!("{} is not a JSON file", file.display()))?;
+                MultisigDocument::from_json(&json).map_err(|e| e.to_string())
+            }
+        })
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        })
+}
+
+/// Written as CBOR if the file name ends in `.cbor`
+fn write_multisig(file: &Path, document: &MultisigDocument) {
+    let contents = if is_cbor_file(file) {
+        document.to_cbor()
+    } else {
+        document.to_json().into_bytes()
+    };
+    if let Err(e) = std::fs::write(file, contents) {
+        eprintln!("Could not write {}: {}", file.display(), e);
+        std::process::exit(1);
+    }
+}
+
+/// Write the completions of `tari-ledger` for `shell`, or its manpage, to stdout
+fn print_completions(shell: Option<Shell>, man: bool) {
+    let mut command = Cli::command();
+    let mut stdout = std::io::stdout();
+    if man {
+        if let Err(e) = clap_mangen::Man::new(command).render(&mut stdout) {
+            eprintln!("Could not write the manpage: {}", e);
+            std::process::exit(1);
+        }
+    } else if let Some(shell) = shell {
+        clap_complete::generate(shell, &mut command, "tari-ledger", &mut stdout);
+    }
+}
+
+/// An amount the user gave, in microTari
+fn parse_amount(amounts: &AmountFormat, amount: &str) -> u64 {
+    amounts.parse(amount).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    })
+}
+
+fn read_unspent(file: &Path) -> Vec<sweep::SweepInput> {
+    read_json_file(file)
+        .and_then(|json| sweep::parse_outputs(&json).map_err(|e| e.to_string()))
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        })
+}
+
+/// Submit `transaction` to the base node at `url` and follow it until it is mined
+#[cfg(feature = "broadcast")]
+fn broadcast_transaction(url: &str, transaction: &transaction_protocol::FinalizedTransaction, mined_within: Duration) {
+    let broadcast = BaseNodeClient::connect(url).and_then(|client| {
+        match client.submit(transaction)? {
+            SubmitResult::Accepted | SubmitResult::AlreadyMined => {},
+            SubmitResult::NotProcessableAtThisTime => {
+                eprintln!("The base node cannot take the transaction yet, broadcast it again later");
+                std::process::exit(1);
+            },
+            SubmitResult::Rejected => {
+                eprintln!("The base node rejected the transaction");
+                std::process::exit(1);
+            },
+        }
+        client.wait_until_mined(&transaction.kernel.signature, mined_within, |location| match location {
+            TransactionLocation::Unknown => println!("The base node does not know the transaction yet"),
+            TransactionLocation::Mempool => println!("The transaction is in the mempool"),
+            TransactionLocation::Mined => println!("The transaction is mined"),
+            TransactionLocation::NotStored => println!("The transaction left the mempool unmined"),
+        })
+    });
+    if let Err(e) = broadcast {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn write_reshaped<E: std::fmt::Display>(
+    file: &Path,
+    reshaped: Result<denominations::Reshaped, E>,
+    amounts: &AmountFormat,
+) {
+    let reshaped = reshaped.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    if let Err(e) = std::fs::write(file, reshaped.to_json()) {
+        eprintln!("Could not write {}: {}", file.display(), e);
+        std::process::exit(1);
+    }
+    let values = reshaped
+        .outputs
+        .iter()
+        .map(|output| amounts.format(output.value))
+        .collect::<Vec<_>>();
+    println!(
+        "Spent {} outputs into outputs of {}, a fee of {}",
+        reshaped.inputs.len(),
+        values.join(", "),
+        amounts.format(reshaped.fee)
+    );
+    println!("Wrote the signed transaction to {}", file.display());
+}
+
+/// Run `operation` with the state store of the seed the device is unlocked with
+fn with_state_store<T>(device: &LedgerDevice, operation: impl FnOnce(&dyn LedgerStateStore) -> T) -> T {
+    let file_store = FileStateStore::open(default_data_dir().join("state.json")).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    // The state of a hidden wallet is kept apart from that of the main one
+    match wallet_fingerprint(device) {
+        Ok(fingerprint) => operation(&ScopedStateStore::new(&file_store, &fingerprint)),
+        Err(_) => operation(&file_store),
+    }
+}
+
+fn parse_usb_id(id: &str) -> Result<u16, String> {
+    u16::from_str_radix(id.trim_start_matches("0x"), 16).map_err(|_| format!("'{}' is not a 4 digit hex USB id", id))
+}
+
+#[cfg(feature = "history")]
+fn parse_time(time: &str) -> Result<i64, String> {
+    history::parse_time(time).ok_or_else(|| format!("'{}' is not a YYYY-MM-DD date or an RFC 3339 time", time))
+}
+
+fn parse_setting(name: &str) -> Result<u8, String> {
+    let name = name.to_lowercase().replace(['-', '_'], " ");
+    app_info::SETTINGS
+        .iter()
+        .copied()
+        .find(|setting| app_info::setting_name(*setting).to_lowercase() == name)
+        .ok_or_else(|| {
+            let names = app_info::SETTINGS
+                .iter()
+                .map(|setting| app_info::setting_name(*setting).to_lowercase().replace(' ', "-"))
+                .collect::<Vec<_>>();
+            format!("expected one of {}", names.join(", "))
+        })
+}
+
+fn parse_sensitive_key(key: &str) -> Result<SensitiveKey, String> {
+    key.parse().map_err(|_| {
+        let names = SensitiveKey::ALL
+            .iter()
+            .map(|k| k.name().replace(' ', "_"))
+            .collect::<Vec<_>>();
+        format!("expected one of {}", names.join(", "))
+    })
+}
+
+fn parse_derivation_version(version: &str) -> Result<DerivationVersion, String> {
+    version.parse().map_err(|_| {
+        let names = DerivationVersion::ALL.iter().map(|v| v.name()).collect::<Vec<_>>();
+        format!("expected one of {}", names.join(", "))
+    })
+}
+
+fn parse_key_path(path: &str) -> Result<KeyPath, String> {
+    path.parse()
+}
+
+fn parse_key_branch(branch: &str) -> Result<KeyBranch, String> {
+    branch.parse().map_err(|_| {
+        let names = KeyBranch::ALL
+            .iter()
+            .map(|b| b.name().replace(' ', "_"))
+            .collect::<Vec<_>>();
+        format!("expected one of {}", names.join(", "))
+    })
+}
+
+/// Parse a `start..end` range of key indices
+fn parse_index_range(range: &str) -> Result<Range<u32>, String> {
+    let (start, end) = range
+        .split_once("..")
+        .ok_or_else(|| format!("'{}' is not a range, expected e.g. 0..1000", range))?;
+    let start = start
+        .parse::<u32>()
+        .map_err(|e| format!("invalid range start: {}", e))?;
+    let end = end.parse::<u32>().map_err(|e| format!("invalid range end: {}", e))?;
+    if start >= end {
+        return Err(format!("the range {} is empty", range));
+    }
+    Ok(start..end)
+}
+
+/// A progress bar on stderr counting `total` items of `unit`, e.g. keys or bytes. It stays hidden when stderr is not
+/// a terminal.
+fn progress_bar(total: usize, unit: &str) -> ProgressBar {
+    let template = format!("{{spinner}} [{{bar:40}}] {{pos}}/{{len}} {} ({{eta}} left)", unit);
+    let style = ProgressStyle::with_template(&template)
+        .expect("the progress template is valid")
+        .progress_chars("#> ");
+    ProgressBar::new(total as u64).with_style(style)
+}
+
+/// Run `operation` behind a spinner showing `message`, for device operations that take a while or wait for the user
+fn with_spinner<T, F: FnOnce() -> T>(message: &'static str, operation: F) -> T {
+    let spinner = ProgressBar::new_spinner().with_message(message);
+    spinner.enable_steady_tick(Duration::from_millis(100));
+    let result = operation();
+    spinner.finish_and_clear();
+    result
+}
+
+#[cfg(feature = "history")]
+type History = Option<history::SigningHistory>;
+#[cfg(not(feature = "history"))]
+type History = ();
+
+fn run_demo(
+    connect: &ConnectOptions,
+    #[cfg_attr(not(feature = "history"), allow(unused_variables))] history: &History,
+) -> Result<(), String> {
     let message = vec![0];
-    let ledger = TransportNativeHID::new(hidapi()).expect("Could not get a device");
+    let device = connect_transport(connect.transport, &connect.hid_filter)
+        .map_err(|e| format!("Could not get a device: {}", e))?;
+    let handshake = handshake(&device, connect);
+    println!(
+        "app version: {} (requires client {} or newer)",
+        handshake.app_version, handshake.min_client_version
+    );
+    match device.capabilities() {
+        Ok(capabilities) => println!("capabilities: {}", capabilities),
+        Err(e) => println!("warning: could not read the app capabilities: {}", e),
+    }
+    match app_info::app_settings(&device) {
+        Ok(settings) if !(settings.blind_signing && settings.expert_mode) => println!(
+            "warning: the demo signs raw challenges and needs blind signing and expert mode enabled in the app \
+             settings ({})",
+            settings
+        ),
+        Ok(_) => {},
+        Err(e) => println!("warning: could not read the app settings: {}", e),
+    }
 
     // use device info command that works in the dashboard
-    let result = futures::executor::block_on(Tari::send_chunks(&ledger, command, &message)).unwrap();
-    let data_len = result.data()[1] as usize;
-    let name = &result.data()[2..data_len + 2];
-    let name = std::str::from_utf8(name).unwrap();
+    let bar = progress_bar(message.len(), "bytes");
+    let result = device
+        .send_chunks_with_progress(Instruction::GetVersion, 0x00, vec![0], &message, |sent, _| {
+            bar.set_position(sent as u64)
+        })
+        .map_err(|e| format!("Could not read the app version: {}", e))?;
+    bar.finish_and_clear();
+    let (name, rest) = length_prefixed(result.get(1..).unwrap_or_default())?;
     println!("name: {}", name);
-    let package_len = result.data()[data_len + 2] as usize;
-    let package = &result.data()[data_len + 3..data_len + package_len + 3];
-    let package = std::str::from_utf8(package).unwrap();
+    let (package, _) = length_prefixed(rest)?;
     println!("package version: {}", package);
     println!(" ");
 
-    let challenge = RistrettoSecretKey::random(&mut OsRng);
-    let command2 = APDUCommand {
-        cla: 0x80,
-        ins: 0x02,
-        p1: 0x00,
-        p2: 0x00,
-        data: challenge.as_bytes().clone(),
-    };
-    let result = ledger.exchange(&command2).unwrap();
+    #[cfg(feature = "history")]
+    if let (Some(history), Ok(counter)) = (history, device.signing_counter()) {
+        match history.unrecorded_signatures(counter) {
+            Ok(0) => {},
+            Ok(unrecorded) => println!(
+                "warning: the device produced {} signature(s) that are not in this host's history",
+                unrecorded
+            ),
+            Err(e) => println!("warning: could not check the signing history: {}", e),
+        }
+    }
 
-    let public_key = &result.data()[1..33];
-    let public_key = RistrettoPublicKey::from_bytes(public_key).unwrap();
+    let challenge = device.random_secret();
+    let response = device
+        .send(Instruction::Sign, 0x00, 0x00, challenge.as_bytes().to_vec())
+        .map_err(|e| format!("Could not sign the challenge: {}", e))?;
+    let payload = device
+        .response_payload(&response, SIGN_RESPONSE_LENGTH)
+        .map_err(|e| format!("Could not sign the challenge: {}", e))?;
+    let invalid = |_| "The device answered the challenge with an invalid signature".to_string();
 
-    let sig = &result.data()[33..65];
-    let sig = RistrettoSecretKey::from_bytes(sig).unwrap();
+    let public_key = RistrettoPublicKey::from_bytes(&payload[0..32]).map_err(invalid)?;
+    let file_store = FileStateStore::open(default_data_dir().join("state.json"))
+        .map_err(|e| format!("Could not open the state store: {}", e))?;
+    // The state of a hidden wallet is kept apart from that of the main one
+    let scoped_store = wallet_fingerprint(&device)
+        .ok()
+        .map(|fingerprint| ScopedStateStore::new(&file_store, &fingerprint));
+    let state_store: &dyn LedgerStateStore = match &scoped_store {
+        Some(store) => store,
+        None => &file_store,
+    };
+    let mut public_key_bytes = [0u8; 32];
+    public_key_bytes.copy_from_slice(public_key.as_bytes());
+    match state_store.cached_public_key(DEMO_KEY_BRANCH, DEMO_KEY_INDEX) {
+        Ok(Some(cached)) if cached != public_key_bytes => {
+            println!("warning: the device public key differs from the key cached for this wallet")
+        },
+        Ok(_) => {},
+        Err(e) => println!("warning: {}", e),
+    }
+    state_store
+        .cache_public_key(DEMO_KEY_BRANCH, DEMO_KEY_INDEX, &public_key_bytes)
+        .map_err(|e| format!("Could not cache the public key: {}", e))?;
 
-    let nonce = &result.data()[65..97];
-    let nonce = RistrettoPublicKey::from_bytes(nonce).unwrap();
+    let sig = RistrettoSecretKey::from_bytes(&payload[32..64]).map_err(invalid)?;
+    let nonce = RistrettoPublicKey::from_bytes(&payload[64..96]).map_err(invalid)?;
 
     let signature = RistrettoSchnorr::new(nonce.clone(), sig);
-    // let e = Blake256::new()
-    //     .chain(&public_key.as_bytes())
-    //     .chain(&nonce.as_bytes())
-    //     .chain(&challenge.as_bytes())
-    //     .finalize().to_vec();
     let mut challenge_bytes = [0u8; 32];
     challenge_bytes.clone_from_slice(challenge.as_bytes());
-    let hash = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new("script_challenge")
+    device
+        .check_nonce(&nonce, &challenge_bytes)
+        .map_err(|e| e.to_string())?;
+    let hash = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(SCRIPT_CHALLENGE_LABEL)
         .chain(&public_key)
         .chain(&nonce)
         .chain(&challenge_bytes)
         .finalize();
-    let e = RistrettoSecretKey::from_bytes(&hash).unwrap();
+    let e = RistrettoSecretKey::from_bytes(&hash).map_err(|e| e.to_string())?;
     println!("challange as secretkey: {}", e.to_hex());
     println!("signature: {}", signature.get_signature().to_hex());
     println!("public key: {}", public_key.to_hex());
 
     let result = signature.verify(&public_key, &e);
     println!("sign: {}", result);
+    #[cfg(feature = "history")]
+    if let Some(history) = history {
+        let mut signature_bytes = nonce.as_bytes().to_vec();
+        signature_bytes.extend_from_slice(signature.get_signature().as_bytes());
+        let status = if result {
+            history::OperationStatus::Signed
+        } else {
+            history::OperationStatus::Failed
+        };
+        if let Err(e) = history.record(
+            "Sign",
+            &hash,
+            Some(public_key.as_bytes()),
+            Some(&signature_bytes),
+            status,
+            device.signing_counter().ok(),
+        ) {
+            if let tari_ledger::errors::StoreError::NonceReuse { .. } = e {
+                return Err(e.to_string());
+            }
+            println!("warning: could not record the signature in the history: {}", e);
+        }
+    }
     println!(" ");
 
     let value: u64 = 60;
-    let value_bytes = value.to_le_bytes();
-    let command3 = APDUCommand {
-        cla: 0x80,
-        ins: 0x03,
-        p1: 0x00,
-        p2: 0x00,
-        data: value_bytes.as_bytes().clone(),
-    };
-    let result = ledger.exchange(&command3).unwrap();
-
-    let commitment = &result.data()[1..33];
-    let commitment = PedersenCommitment::from_bytes(commitment).unwrap();
+    let response = device
+        .send(Instruction::Commitment, 0x00, 0x00, value.to_le_bytes().to_vec())
+        .map_err(|e| format!("Could not get the commitment: {}", e))?;
+    let payload = device
+        .response_payload(&response, COMMITMENT_RESPONSE_LENGTH)
+        .map_err(|e| format!("Could not get the commitment: {}", e))?;
+    let commitment = PedersenCommitment::from_bytes(payload)
+        .map_err(|_| "The device answered with an invalid commitment".to_string())?;
     println!("commitment: {}", commitment.to_hex());
 
     let statement = Statement {
@@ -127,100 +2961,51 @@ fn main() {
     let agg_statement = AggregatedPublicStatement {
         statements: vec![statement],
     };
-    let (lim_rp, range_statement) = create_lim_rp(&agg_statement, value);
+    let (lim_rp, range_statement) = create_lim_rp(&agg_statement, value)?;
 
     let y_scalar = lim_rp.y_pow_const.clone();
-    let bytes = y_scalar.as_bytes().to_vec();
-    let command4 = APDUCommand {
-        cla: 0x80,
-        ins: 0x04,
-        p1: 0x00,
-        p2: 0x00,
-        data: bytes,
-    };
-    let result = ledger.exchange(&command4).unwrap();
+    let response = device
+        .send(Instruction::BPData, 0x00, 0x00, y_scalar.as_bytes().to_vec())
+        .map_err(|e| format!("Could not get the range proof scalar: {}", e))?;
+    let payload = device
+        .response_payload(&response, BP_RESPONSE_LENGTH)
+        .map_err(|e| format!("Could not get the range proof scalar: {}", e))?;
 
     let mut scalar_bytes = [0u8; 32];
-    scalar_bytes.clone_from_slice(&result.data()[1..33]);
+    scalar_bytes.clone_from_slice(payload);
     let combined_scalar = Scalar::from_bits(scalar_bytes);
 
     let rp = lim_rp
         .prove(vec![vec![combined_scalar]], &range_statement, &mut OsRng)
-        .unwrap()
+        .map_err(|e| format!("Could not prove the range: {}", e))?
         .to_bytes();
-    let rp_plus_service = BulletproofsPlusService::init(64, 1, ExtendedPedersenCommitmentFactory::default()).unwrap();
+    let rp_plus_service = BulletproofsPlusService::init(64, 1, ExtendedPedersenCommitmentFactory::default())
+        .map_err(|e| e.to_string())?;
     let bp_result = rp_plus_service.verify_batch(vec![&rp], vec![&agg_statement], &mut OsRng);
     println!("BP result: {:?}", bp_result);
+    Ok(())
+}
+
+/// The string `bytes` start with, after its length byte, and the bytes after it
+fn length_prefixed(bytes: &[u8]) -> Result<(&str, &[u8]), String> {
+    let invalid = || "The device answered with an invalid app version".to_string();
+    let (length, rest) = bytes.split_first().ok_or_else(invalid)?;
+    let string = rest.get(..usize::from(*length)).ok_or_else(invalid)?;
+    let string = std::str::from_utf8(string).map_err(|_| invalid())?;
+    Ok((string, &rest[string.len()..]))
 }
 
 fn create_lim_rp(
     agg_statement: &AggregatedPublicStatement<RistrettoPublicKey>,
     value: u64,
-) -> (MemLimitedRangeProof<RistrettoPoint>, RangeStatement<RistrettoPoint>) {
-    let rp_plus_service = BulletproofsPlusService::init(64, 1, ExtendedPedersenCommitmentFactory::default()).unwrap();
+) -> Result<(MemLimitedRangeProof<RistrettoPoint>, RangeStatement<RistrettoPoint>), String> {
+    let rp_plus_service = BulletproofsPlusService::init(64, 1, ExtendedPedersenCommitmentFactory::default())
+        .map_err(|e| e.to_string())?;
 
     let public_range_statements = rp_plus_service.prepare_public_range_statements(vec![agg_statement]);
     let range_statment = public_range_statements[0].clone();
-    (
+    let range_proof =
         MemLimitedRangeProof::<RistrettoPoint>::init("Tari Bulletproofs+", &range_statment, &vec![value], &mut OsRng)
-            .unwrap(),
-        range_statment,
-    )
-}
-
-pub struct DomainSeparatedConsensusHasher<M>(PhantomData<M>);
-
-impl<M: DomainSeparation> DomainSeparatedConsensusHasher<M> {
-    #[allow(clippy::new_ret_no_self)]
-    pub fn new(label: &'static str) -> ConsensusHasher<Blake256> {
-        let mut digest = Blake256::new();
-        M::add_domain_separation_tag(&mut digest, label);
-        ConsensusHasher::from_digest(digest)
-    }
-}
-
-use digest::consts::U32;
-#[derive(Clone)]
-pub struct ConsensusHasher<D> {
-    writer: WriteHashWrapper<D>,
-}
-
-impl<D: Digest> ConsensusHasher<D> {
-    fn from_digest(digest: D) -> Self {
-        Self {
-            writer: WriteHashWrapper(digest),
-        }
-    }
-}
-
-impl<D> ConsensusHasher<D>
-where D: Digest<OutputSize = U32>
-{
-    pub fn finalize(self) -> [u8; 32] {
-        self.writer.0.finalize().into()
-    }
-
-    pub fn update_consensus_encode<T: BorshSerialize>(&mut self, data: &T) {
-        BorshSerialize::serialize(data, &mut self.writer)
-            .expect("Incorrect implementation of BorshSerialize encountered. Implementations MUST be infallible.");
-    }
-
-    pub fn chain<T: BorshSerialize>(mut self, data: &T) -> Self {
-        self.update_consensus_encode(data);
-        self
-    }
-}
-
-#[derive(Clone)]
-struct WriteHashWrapper<D>(D);
-
-impl<D: Digest> Write for WriteHashWrapper<D> {
-    fn write(&mut self, buf: &[u8]) -> BorshResult<usize> {
-        self.0.update(buf);
-        Ok(buf.len())
-    }
-
-    fn flush(&mut self) -> BorshResult<()> {
-        Ok(())
-    }
+            .map_err(|e| format!("Could not start the range proof: {}", e))?;
+    Ok((range_proof, range_statment))
 }
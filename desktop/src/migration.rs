@@ -0,0 +1,143 @@
+//! Migrating outputs to a new derivation scheme
+//! When the app moves to a new [`DerivationVersion`], outputs created under an older one can only be spent with keys
+//! of the scheme they were created with. A migration finds them and sweeps them into keys of the new scheme: every
+//! output is checked by having the device commit to its value under both schemes, and the outputs whose commitment
+//! matches the old one become the inputs of one transaction, signed with the keys they were created with. Their total,
+//! less the fee, goes to a single output of this wallet with its keys under the new scheme at the next unused index of
+//! the state store, so the device shows nothing but the fee. Outputs already under the new scheme are left alone, as
+//! is dust, which would cost more in fees than it holds.
+
+use crate::{
+    commitment::{batch_commitments_with_version, CommitmentRequest},
+    denominations::{reshape_fee, Reshaped},
+    device::{Capabilities, DerivationVersion, LedgerDevice},
+    errors::{DeviceError, MigrationError, SignerError},
+    signer::{ChangeOutput, LedgerTransactionSigner},
+    state_store::LedgerStateStore,
+    sweep::SweepInput,
+};
+
+/// The outputs of a wallet sorted by the scheme their keys are derived under
+#[derive(Clone, Debug)]
+pub struct MigrationScan {
+    pub from: DerivationVersion,
+    pub to: DerivationVersion,
+    /// Outputs under the old scheme worth more than the dust threshold
+    pub inputs: Vec<SweepInput>,
+    /// Outputs under the new scheme, which need no migration
+    pub migrated: Vec<SweepInput>,
+    /// Outputs under the old scheme worth no more than the dust threshold
+    pub dust: Vec<SweepInput>,
+    /// Outputs the device reproduces under neither scheme, from another seed or a different index
+    pub foreign: Vec<SweepInput>,
+}
+
+impl MigrationScan {
+    pub fn total(&self) -> u64 {
+        self.inputs.iter().map(|input| input.value).sum()
+    }
+}
+
+/// The schemes the app can derive keys under, oldest first. Apps that do not know about versions only derive legacy
+/// keys.
+pub fn supported_versions(device: &LedgerDevice) -> Result<Vec<DerivationVersion>, DeviceError> {
+    if device.capabilities()?.contains(Capabilities::DERIVATION_VERSIONS) {
+        Ok(DerivationVersion::ALL.to_vec())
+    } else {
+        Ok(vec![DerivationVersion::Legacy])
+    }
+}
+
+/// Sort `outputs` by whether the device reproduces their commitment under `from` or `to`
+pub fn scan_schemes(
+    device: &LedgerDevice,
+    outputs: Vec<SweepInput>,
+    from: DerivationVersion,
+    to: DerivationVersion,
+    dust_threshold: u64,
+) -> Result<MigrationScan, MigrationError> {
+    if from == to {
+        return Err(MigrationError::SameVersion(to));
+    }
+    let requests = outputs
+        .iter()
+        .map(|output| CommitmentRequest {
+            value: output.value,
+            index: output.mask_index,
+        })
+        .collect::<Vec<_>>();
+    let old = batch_commitments_with_version(device, &requests, from).map_err(SignerError::from)?;
+    let new = batch_commitments_with_version(device, &requests, to).map_err(SignerError::from)?;
+    let mut scan = MigrationScan {
+        from,
+        to,
+        inputs: Vec::new(),
+        migrated: Vec::new(),
+        dust: Vec::new(),
+        foreign: Vec::new(),
+    };
+    for ((output, old), new) in outputs.into_iter().zip(old).zip(new) {
+        if output.commitment == new {
+            scan.migrated.push(output);
+        } else if output.commitment != old {
+            scan.foreign.push(output);
+        } else if output.value <= dust_threshold {
+            scan.dust.push(output);
+        } else {
+            scan.inputs.push(output);
+        }
+    }
+    Ok(scan)
+}
+
+/// Sweep the inputs of `scan` into one output with keys under the new scheme. `old` signs the inputs and has to derive
+/// keys under the scheme of the scan's inputs, `new` derives the output and has to derive keys under the scheme they
+/// migrate to.
+pub fn migrate_outputs(
+    old: &LedgerTransactionSigner,
+    new: &LedgerTransactionSigner,
+    store: &dyn LedgerStateStore,
+    scan: &MigrationScan,
+) -> Result<Reshaped, MigrationError> {
+    for (signer, expected) in [(old, scan.from), (new, scan.to)] {
+        if signer.derivation_version() != expected {
+            return Err(MigrationError::WrongSignerVersion {
+                expected,
+                found: signer.derivation_version(),
+            });
+        }
+    }
+    if scan.inputs.is_empty() {
+        return Err(MigrationError::NothingToMigrate);
+    }
+    let total = scan
+        .inputs
+        .iter()
+        .try_fold(0u64, |total, input| total.checked_add(input.value))
+        .ok_or(SignerError::ValueOverflow)?;
+    let fee = reshape_fee(new, scan.inputs.len(), 1);
+    let value = total
+        .checked_sub(fee)
+        .filter(|value| *value > 0)
+        .ok_or(SignerError::InsufficientFunds {
+            available: total,
+            required: fee.saturating_add(1),
+        })?;
+
+    let outputs = new.change_outputs(store, &[value])?;
+    let to_sign = outputs.iter().map(ChangeOutput::to_output).collect::<Vec<_>>();
+    let signed = new.sign_outputs(scan.inputs.len(), &to_sign)?;
+    let input_signatures = scan
+        .inputs
+        .iter()
+        .map(|input| old.sign_script_message(&input.script_message))
+        .collect::<Result<_, _>>()?;
+    Ok(Reshaped {
+        fee: signed.fee,
+        inputs: scan.inputs.clone(),
+        input_signatures,
+        outputs,
+        output_signatures: signed.signatures,
+        derivation_version: scan.to,
+    })
+}
@@ -0,0 +1,648 @@
+//! Coordinating an m-of-n multi-signature output
+//! A multi-signature output is locked with `CheckMultiSigVerifyAggregatePubKey`: any `threshold` of the `n`
+//! participant keys have to sign the message fixed in the script. The participants pass a [`MultisigDocument`] around,
+//! as a file or a QR code, and each of them adds what their device contributes to the current round: first every
+//! participant adds their key, then `threshold` of them sign. Every participant signs on their own, so the public
+//! nonces travel with the signatures and there is no separate nonce round.
+//!
+//! Nothing in the document is secret, but every key and signature in it is checked when it is read, so a tampered
+//! document is rejected before anyone signs.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "cbor")]
+use serde_bytes::ByteBuf;
+use tari_crypto::{
+    ristretto::{RistrettoPublicKey, RistrettoSchnorr},
+    tari_utilities::{
+        hex::{from_hex, to_hex, Hex},
+        ByteArray,
+    },
+};
+
+#[cfg(feature = "cbor")]
+use crate::{
+    cbor::{self, JSON_DOCUMENT_TAG, MULTISIG_DOCUMENT_TAG},
+    errors::CborError,
+};
+use crate::{
+    device::KeyBranch,
+    domains::SCRIPT_MESSAGE_LABEL,
+    errors::{MultisigError, SignerError},
+    export::public_key,
+    hashing::Challenge,
+    redact::short_hex,
+    script::{ExecutionStack, Opcode, StackItem, TariScript},
+    signer::LedgerTransactionSigner,
+    verify::{signature_from_bytes, verify_script_signature},
+};
+
+/// The version of the document format this module understands
+pub const MULTISIG_FORMAT_VERSION: u64 = 1;
+/// `CheckMultiSig` encodes `n` in a byte, and the script has to fit in an output
+pub const MAX_MULTISIG_PARTICIPANTS: u8 = 32;
+
+/// The round a multi-signature document is in
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MultisigState {
+    /// Not every participant has added their key yet
+    CollectingKeys,
+    /// The script is fixed, fewer than `threshold` participants have signed
+    CollectingSignatures,
+    /// Enough participants have signed to spend the output
+    Complete,
+}
+
+impl fmt::Display for MultisigState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MultisigState::CollectingKeys => write!(f, "collecting keys"),
+            MultisigState::CollectingSignatures => write!(f, "collecting signatures"),
+            MultisigState::Complete => write!(f, "complete"),
+        }
+    }
+}
+
+/// One participant's signature of the script message
+#[derive(Clone, Debug)]
+pub struct MultisigSignature {
+    pub public_key: RistrettoPublicKey,
+    pub signature: RistrettoSchnorr,
+}
+
+impl fmt::Display for MultisigSignature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "key {}, nonce {}, s {}",
+            short_hex(self.public_key.as_bytes()),
+            short_hex(self.signature.get_public_nonce().as_bytes()),
+            short_hex(self.signature.get_signature().as_bytes())
+        )
+    }
+}
+
+/// The state of one m-of-n multi-signature, as passed between the participants
+#[derive(Clone, Debug)]
+pub struct MultisigDocument {
+    threshold: u8,
+    participants: u8,
+    message: [u8; 32],
+    keys: Vec<RistrettoPublicKey>,
+    signatures: Vec<MultisigSignature>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DocumentJson {
+    version: u64,
+    state: MultisigState,
+    threshold: u8,
+    participants: u8,
+    message: String,
+    keys: Vec<String>,
+    signatures: Vec<SignatureJson>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SignatureJson {
+    public_key: String,
+    public_nonce: String,
+    signature: String,
+}
+
+/// [`DocumentJson`] with raw bytes in place of hex
+#[cfg(feature = "cbor")]
+#[derive(Serialize, Deserialize)]
+struct DocumentCbor {
+    version: u64,
+    state: MultisigState,
+    threshold: u8,
+    participants: u8,
+    #[serde(with = "serde_bytes")]
+    message: Vec<u8>,
+    keys: Vec<ByteBuf>,
+    signatures: Vec<SignatureCbor>,
+}
+
+#[cfg(feature = "cbor")]
+#[derive(Serialize, Deserialize)]
+struct SignatureCbor {
+    public_key: ByteBuf,
+    public_nonce: ByteBuf,
+    signature: ByteBuf,
+}
+
+impl MultisigDocument {
+    /// A new document for `threshold` of `participants` signatures over `message`, the message the script commits to
+    pub fn new(threshold: u8, participants: u8, message: [u8; 32]) -> Result<Self, MultisigError> {
+        if threshold == 0 || threshold > participants || participants > MAX_MULTISIG_PARTICIPANTS {
+            return Err(MultisigError::InvalidThreshold {
+                threshold,
+                participants,
+            });
+        }
+        Ok(Self {
+            threshold,
+            participants,
+            message,
+            keys: Vec::new(),
+            signatures: Vec::new(),
+        })
+    }
+
+    pub fn threshold(&self) -> u8 {
+        self.threshold
+    }
+
+    pub fn participants(&self) -> u8 {
+        self.participants
+    }
+
+    pub fn message(&self) -> &[u8; 32] {
+        &self.message
+    }
+
+    pub fn keys(&self) -> &[RistrettoPublicKey] {
+        &self.keys
+    }
+
+    pub fn signatures(&self) -> &[MultisigSignature] {
+        &self.signatures
+    }
+
+    pub fn state(&self) -> MultisigState {
+        if self.keys.len() < usize::from(self.participants) {
+            MultisigState::CollectingKeys
+        } else if self.signatures.len() < usize::from(self.threshold) {
+            MultisigState::CollectingSignatures
+        } else {
+            MultisigState::Complete
+        }
+    }
+
+    /// Add a participant's key. Keys can only be added until every participant has one.
+    pub fn add_key(&mut self, key: RistrettoPublicKey) -> Result<(), MultisigError> {
+        if self.state() != MultisigState::CollectingKeys {
+            return Err(MultisigError::WrongState(self.state()));
+        }
+        if self.keys.contains(&key) {
+            return Err(MultisigError::DuplicateKey(key.to_hex()));
+        }
+        self.keys.push(key);
+        Ok(())
+    }
+
+    /// Add a participant's signature of the message, checked against their key
+    pub fn add_signature(&mut self, signature: MultisigSignature) -> Result<(), MultisigError> {
+        if self.state() != MultisigState::CollectingSignatures {
+            return Err(MultisigError::WrongState(self.state()));
+        }
+        if !self.keys.contains(&signature.public_key) {
+            return Err(MultisigError::UnknownKey(signature.public_key.to_hex()));
+        }
+        if self.signatures.iter().any(|s| s.public_key == signature.public_key) {
+            return Err(MultisigError::DuplicateKey(signature.public_key.to_hex()));
+        }
+        if !verify_script_signature(&signature.public_key, &signature.signature, &self.message) {
+            return Err(MultisigError::InvalidSignature(signature.public_key.to_hex()));
+        }
+        self.signatures.push(signature);
+        Ok(())
+    }
+
+    /// Add what the device contributes to the current round: its key while keys are collected, its signature once the
+    /// script is fixed
+    pub fn contribute(&mut self, signer: &LedgerTransactionSigner) -> Result<MultisigState, MultisigError> {
+        match self.state() {
+            // The signing key is the app key, the first commitment mask key of the first account
+            MultisigState::CollectingKeys => {
+                let key = public_key(signer.device(), 0, KeyBranch::CommitmentMask, 0).map_err(SignerError::from)?;
+                self.add_key(key)?;
+            },
+            MultisigState::CollectingSignatures => {
+                let signed = signer.sign_script_message(&Challenge::from_hashed(SCRIPT_MESSAGE_LABEL, self.message))?;
+                self.add_signature(MultisigSignature {
+                    signature: signed.schnorr(),
+                    public_key: signed.public_key,
+                })?;
+            },
+            MultisigState::Complete => return Err(MultisigError::WrongState(MultisigState::Complete)),
+        }
+        Ok(self.state())
+    }
+
+    /// The script that locks the output, once every key is known. It leaves the sum of the signing keys on the stack
+    /// as the script key.
+    pub fn script(&self) -> Result<TariScript, MultisigError> {
+        if self.state() == MultisigState::CollectingKeys {
+            return Err(MultisigError::WrongState(self.state()));
+        }
+        Ok(TariScript::new(vec![Opcode::CheckMultiSigVerifyAggregatePubKey(
+            self.threshold,
+            self.participants,
+            self.keys.clone(),
+            self.message,
+        )]))
+    }
+
+    /// The input data that spends the output, once enough participants have signed
+    pub fn input_data(&self) -> Result<ExecutionStack, MultisigError> {
+        if self.state() != MultisigState::Complete {
+            return Err(MultisigError::WrongState(self.state()));
+        }
+        Ok(ExecutionStack::new(
+            self.signatures
+                .iter()
+                .map(|s| StackItem::Signature(s.signature.clone()))
+                .collect(),
+        ))
+    }
+
+    /// The document as compact JSON, small enough for a QR code with a handful of participants
+    pub fn to_json(&self) -> String {
+        let document = DocumentJson {
+            version: MULTISIG_FORMAT_VERSION,
+            state: self.state(),
+            threshold: self.threshold,
+            participants: self.participants,
+            message: to_hex(&self.message),
+            keys: self.keys.iter().map(|key| key.to_hex()).collect(),
+            signatures: self
+                .signatures
+                .iter()
+                .map(|s| SignatureJson {
+                    public_key: s.public_key.to_hex(),
+                    public_nonce: s.signature.get_public_nonce().to_hex(),
+                    signature: to_hex(s.signature.get_signature().as_bytes()),
+                })
+                .collect(),
+        };
+        serde_json::to_string(&document).expect("a multisig document always serializes")
+    }
+
+    /// The document as CBOR, about half the size of [`MultisigDocument::to_json`]
+    #[cfg(feature = "cbor")]
+    pub fn to_cbor(&self) -> Vec<u8> {
+        let document = DocumentCbor {
+            version: MULTISIG_FORMAT_VERSION,
+            state: self.state(),
+            threshold: self.threshold,
+            participants: self.participants,
+            message: self.message.to_vec(),
+            keys: self.keys.iter().map(|key| ByteBuf::from(key.as_bytes())).collect(),
+            signatures: self
+                .signatures
+                .iter()
+                .map(|s| SignatureCbor {
+                    public_key: ByteBuf::from(s.public_key.as_bytes()),
+                    public_nonce: ByteBuf::from(s.signature.get_public_nonce().as_bytes()),
+                    signature: ByteBuf::from(s.signature.get_signature().as_bytes()),
+                })
+                .collect(),
+        };
+        cbor::encode(MULTISIG_DOCUMENT_TAG, &document)
+    }
+
+    /// Read a document from CBOR, written by [`MultisigDocument::to_cbor`] or converted from JSON
+    #[cfg(feature = "cbor")]
+    pub fn from_cbor(bytes: &[u8]) -> Result<Self, MultisigError> {
+        let parse = |e: CborError| MultisigError::Parse(e.to_string());
+        if cbor::document_kind(bytes).map_err(parse)? == JSON_DOCUMENT_TAG {
+            return Self::from_json(&cbor::cbor_to_json(bytes).map_err(parse)?);
+        }
+        let document: DocumentCbor = cbor::decode(MULTISIG_DOCUMENT_TAG, bytes).map_err(parse)?;
+        Self::from_document(DocumentJson {
+            version: document.version,
+            state: document.state,
+            threshold: document.threshold,
+            participants: document.participants,
+            message: to_hex(&document.message),
+            keys: document.keys.iter().map(|key| to_hex(key)).collect(),
+            signatures: document
+                .signatures
+                .iter()
+                .map(|s| SignatureJson {
+                    public_key: to_hex(&s.public_key),
+                    public_nonce: to_hex(&s.public_nonce),
+                    signature: to_hex(&s.signature),
+                })
+                .collect(),
+        })
+    }
+
+    /// Read a document, replaying every key and signature in it so that each one is checked again
+    pub fn from_json(json: &str) -> Result<Self, MultisigError> {
+        let document: DocumentJson = serde_json::from_str(json).map_err(|e| MultisigError::Parse(e.to_string()))?;
+        Self::from_document(document)
+    }
+
+    fn from_document(document: DocumentJson) -> Result<Self, MultisigError> {
+        if document.version != MULTISIG_FORMAT_VERSION {
+            return Err(MultisigError::UnsupportedVersion(document.version));
+        }
+        let message = from_hex(&document.message)
+            .ok()
+            .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+            .ok_or_else(|| MultisigError::Parse("the message is not a hex encoded 32-byte hash".to_string()))?;
+        let mut result = Self::new(document.threshold, document.participants, message)?;
+        for key in &document.keys {
+            result.add_key(parse_key(key)?)?;
+        }
+        for signature in &document.signatures {
+            let bytes = from_hex(&format!("{}{}", signature.public_nonce, signature.signature))
+                .map_err(|_| MultisigError::Parse("a signature is not hex encoded".to_string()))?;
+            result.add_signature(MultisigSignature {
+                public_key: parse_key(&signature.public_key)?,
+                signature: signature_from_bytes(&bytes)
+                    .ok_or_else(|| MultisigError::Parse("a signature is not a Schnorr signature".to_string()))?,
+            })?;
+        }
+        if result.state() != document.state {
+            return Err(MultisigError::Parse(format!(
+                "the document claims to be {} but is {}",
+                document.state,
+                result.state()
+            )));
+        }
+        Ok(result)
+    }
+}
+
+fn parse_key(hex: &str) -> Result<RistrettoPublicKey, MultisigError> {
+    from_hex(hex)
+        .ok()
+        .and_then(|bytes| RistrettoPublicKey::from_bytes(&bytes).ok())
+        .ok_or_else(|| MultisigError::Parse(format!("'{}' is not a hex encoded public key", hex)))
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use tari_crypto::{keys::PublicKey, ristretto::RistrettoSecretKey};
+
+    use super::*;
+    use crate::{
+        device::LedgerDevice,
+        dry_run::{DryRunLog, DryRunTransport},
+        fee::FeeCalculator,
+        verify::script_challenge,
+    };
+
+    const MESSAGE: [u8; 32] = [9; 32];
+
+    fn key(n: u64) -> RistrettoPublicKey {
+        RistrettoPublicKey::from_secret_key(&RistrettoSecretKey::from(n))
+    }
+
+    /// The signature of `message` by the key `n`
+    fn signature(n: u64, message: &[u8; 32]) -> MultisigSignature {
+        let (k, r) = (RistrettoSecretKey::from(n), RistrettoSecretKey::from(n + 100));
+        let public_nonce = RistrettoPublicKey::from_secret_key(&r);
+        let e = RistrettoSecretKey::from_bytes(&script_challenge(&key(n), &public_nonce, message)).unwrap();
+        MultisigSignature {
+            public_key: key(n),
+            signature: RistrettoSchnorr::new(public_nonce, &r + &(&e * &k)),
+        }
+    }
+
+    /// A 2-of-3 document with the keys 1, 2 and 3
+    fn keyed() -> MultisigDocument {
+        let mut document = MultisigDocument::new(2, 3, MESSAGE).unwrap();
+        for n in 1..=3 {
+            document.add_key(key(n)).unwrap();
+        }
+        document
+    }
+
+    fn json_with(document: &MultisigDocument, change: impl FnOnce(&mut serde_json::Value)) -> String {
+        let mut json: serde_json::Value = serde_json::from_str(&document.to_json()).unwrap();
+        change(&mut json);
+        json.to_string()
+    }
+
+    #[test]
+    fn a_document_goes_through_its_rounds_in_order() {
+        let mut document = MultisigDocument::new(2, 3, MESSAGE).unwrap();
+        assert_eq!(document.state(), MultisigState::CollectingKeys);
+        assert!(matches!(
+            document.add_signature(signature(1, &MESSAGE)),
+            Err(MultisigError::WrongState(MultisigState::CollectingKeys))
+        ));
+        assert!(matches!(
+            document.script(),
+            Err(MultisigError::WrongState(MultisigState::CollectingKeys))
+        ));
+        for n in 1..=3 {
+            document.add_key(key(n)).unwrap();
+        }
+        assert_eq!(document.state(), MultisigState::CollectingSignatures);
+        assert!(matches!(
+            document.add_key(key(4)),
+            Err(MultisigError::WrongState(MultisigState::CollectingSignatures))
+        ));
+        assert!(matches!(
+            document.input_data(),
+            Err(MultisigError::WrongState(MultisigState::CollectingSignatures))
+        ));
+        assert_eq!(
+            document.script().unwrap(),
+            TariScript::new(vec![Opcode::CheckMultiSigVerifyAggregatePubKey(
+                2,
+                3,
+                vec![key(1), key(2), key(3)],
+                MESSAGE
+            )])
+        );
+
+        document.add_signature(signature(3, &MESSAGE)).unwrap();
+        assert_eq!(document.state(), MultisigState::CollectingSignatures);
+        document.add_signature(signature(1, &MESSAGE)).unwrap();
+        assert_eq!(document.state(), MultisigState::Complete);
+        assert!(matches!(
+            document.add_signature(signature(2, &MESSAGE)),
+            Err(MultisigError::WrongState(MultisigState::Complete))
+        ));
+        // The signatures go on the stack in the order they were added
+        assert_eq!(
+            document.input_data().unwrap(),
+            ExecutionStack::new(vec![
+                StackItem::Signature(signature(3, &MESSAGE).signature),
+                StackItem::Signature(signature(1, &MESSAGE).signature),
+            ])
+        );
+        assert!(document.script().is_ok());
+    }
+
+    #[test]
+    fn impossible_thresholds_are_refused() {
+        for (threshold, participants) in [(0, 1), (0, 0), (3, 2), (1, MAX_MULTISIG_PARTICIPANTS + 1)] {
+            assert!(
+                matches!(
+                    MultisigDocument::new(threshold, participants, MESSAGE),
+                    Err(MultisigError::InvalidThreshold { .. })
+                ),
+                "{}-of-{}",
+                threshold,
+                participants
+            );
+        }
+        assert!(MultisigDocument::new(1, 1, MESSAGE).is_ok());
+        assert!(MultisigDocument::new(MAX_MULTISIG_PARTICIPANTS, MAX_MULTISIG_PARTICIPANTS, MESSAGE).is_ok());
+    }
+
+    #[test]
+    fn keys_and_signatures_are_checked_as_they_are_added() {
+        let mut document = MultisigDocument::new(2, 3, MESSAGE).unwrap();
+        document.add_key(key(1)).unwrap();
+        assert!(matches!(document.add_key(key(1)), Err(MultisigError::DuplicateKey(_))));
+        assert_eq!(document.keys().len(), 1);
+
+        let mut document = keyed();
+        assert!(matches!(
+            document.add_signature(signature(4, &MESSAGE)),
+            Err(MultisigError::UnknownKey(_))
+        ));
+        // A signature of another message, or by another key under a participant's name
+        assert!(matches!(
+            document.add_signature(signature(1, &[8; 32])),
+            Err(MultisigError::InvalidSignature(_))
+        ));
+        let mut borrowed = signature(2, &MESSAGE);
+        borrowed.public_key = key(1);
+        assert!(matches!(
+            document.add_signature(borrowed),
+            Err(MultisigError::InvalidSignature(_))
+        ));
+        assert!(document.signatures().is_empty());
+        document.add_signature(signature(1, &MESSAGE)).unwrap();
+        assert!(matches!(
+            document.add_signature(signature(1, &MESSAGE)),
+            Err(MultisigError::DuplicateKey(_))
+        ));
+        assert_eq!(document.state(), MultisigState::CollectingSignatures);
+    }
+
+    #[test]
+    fn documents_round_trip_through_json_in_every_round() {
+        let mut document = MultisigDocument::new(2, 3, MESSAGE).unwrap();
+        let round_trip = |document: &MultisigDocument| {
+            let read = MultisigDocument::from_json(&document.to_json()).unwrap();
+            assert_eq!(read.state(), document.state());
+            assert_eq!(read.keys(), document.keys());
+            assert_eq!(read.to_json(), document.to_json());
+        };
+        round_trip(&document);
+        document.add_key(key(1)).unwrap();
+        round_trip(&document);
+        document = keyed();
+        round_trip(&document);
+        document.add_signature(signature(2, &MESSAGE)).unwrap();
+        round_trip(&document);
+        document.add_signature(signature(3, &MESSAGE)).unwrap();
+        round_trip(&document);
+
+        let json: serde_json::Value = serde_json::from_str(&document.to_json()).unwrap();
+        assert_eq!(json["state"], "complete");
+        assert_eq!(json["message"], to_hex(&MESSAGE));
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn documents_round_trip_through_cbor() {
+        let mut document = keyed();
+        document.add_signature(signature(1, &MESSAGE)).unwrap();
+        let read = MultisigDocument::from_cbor(&document.to_cbor()).unwrap();
+        assert_eq!(read.to_json(), document.to_json());
+        assert!(document.to_cbor().len() < document.to_json().len());
+        // JSON carried as CBOR is read the same
+        let read = MultisigDocument::from_cbor(&cbor::json_to_cbor(&document.to_json()).unwrap()).unwrap();
+        assert_eq!(read.to_json(), document.to_json());
+    }
+
+    #[test]
+    fn a_tampered_document_is_refused() {
+        let mut document = keyed();
+        document.add_signature(signature(1, &MESSAGE)).unwrap();
+
+        let read = |change: fn(&mut serde_json::Value)| MultisigDocument::from_json(&json_with(&document, change));
+        assert!(matches!(
+            read(|json| json["version"] = json!(2)),
+            Err(MultisigError::UnsupportedVersion(2))
+        ));
+        assert!(matches!(
+            read(|json| json["state"] = json!("complete")),
+            Err(MultisigError::Parse(_))
+        ));
+        assert!(matches!(
+            read(|json| json["message"] = json!("0909")),
+            Err(MultisigError::Parse(_))
+        ));
+        // The signature was over the original message
+        assert!(matches!(
+            read(|json| json["message"] = json!(to_hex(&[8u8; 32]))),
+            Err(MultisigError::InvalidSignature(_))
+        ));
+        assert!(matches!(
+            read(|json| json["keys"][1] = json!("ff".repeat(32))),
+            Err(MultisigError::Parse(_))
+        ));
+        assert!(matches!(
+            read(|json| json["keys"][1] = json["keys"][0].clone()),
+            Err(MultisigError::DuplicateKey(_))
+        ));
+        assert!(matches!(
+            read(|json| json["keys"].as_array_mut().unwrap().push(json!(key(4).to_hex()))),
+            Err(MultisigError::WrongState(MultisigState::CollectingSignatures))
+        ));
+        assert!(matches!(
+            read(|json| json["threshold"] = json!(4)),
+            Err(MultisigError::InvalidThreshold { .. })
+        ));
+        assert!(matches!(
+            read(|json| json["signatures"][0]["signature"] = json!(to_hex(&[1u8; 32]))),
+            Err(MultisigError::InvalidSignature(_))
+        ));
+        assert!(matches!(
+            read(|json| json["signatures"][0]["public_nonce"] = json!("zz")),
+            Err(MultisigError::Parse(_))
+        ));
+        assert!(matches!(
+            read(|json| json["signatures"][0]["public_key"] = json!(key(4).to_hex())),
+            Err(MultisigError::UnknownKey(_))
+        ));
+        assert!(matches!(
+            MultisigDocument::from_json("{\"version\": 1}"),
+            Err(MultisigError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn the_device_contributes_its_key_and_then_its_signature() {
+        let device = LedgerDevice::from_transport(DryRunTransport::new(DryRunLog::new()).with_software_keys(7));
+        let signer = LedgerTransactionSigner::new(&device, FeeCalculator::new(5));
+        let mut document = MultisigDocument::new(1, 2, MESSAGE).unwrap();
+
+        assert_eq!(document.contribute(&signer).unwrap(), MultisigState::CollectingKeys);
+        let device_key = document.keys()[0].clone();
+        assert_eq!(
+            device_key,
+            public_key(&device, 0, KeyBranch::CommitmentMask, 0).unwrap()
+        );
+        // The device's key is in the document once
+        assert!(matches!(
+            document.contribute(&signer),
+            Err(MultisigError::DuplicateKey(_))
+        ));
+        document.add_key(key(1)).unwrap();
+
+        assert_eq!(document.contribute(&signer).unwrap(), MultisigState::Complete);
+        assert_eq!(document.signatures()[0].public_key, device_key);
+        assert!(matches!(
+            document.contribute(&signer),
+            Err(MultisigError::WrongState(MultisigState::Complete))
+        ));
+        assert!(MultisigDocument::from_json(&document.to_json()).is_ok());
+    }
+}
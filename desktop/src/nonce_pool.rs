@@ -0,0 +1,490 @@
+//! Public nonces fetched ahead of the kernels they sign
+//! An interactive transaction sends the public nonce of the device's partial kernel signature to the other party
+//! before the challenge is known. With [`kernel_nonce`](crate::kernel::kernel_nonce) that takes a round trip to the
+//! device at that point, with a [`NoncePool`] the host fetches a batch of nonces beforehand, hands one out from memory
+//! and only goes back to the device to sign with it.
+//!
+//! The app names every nonce by the id of its pool and its own id, and signs with each once. It keeps one pool at a
+//! time: it starts a new one when the current one cannot issue a fetch in full, drops it on
+//! [`invalidate_nonce_pool`], with the session, or when it is closed, and answers a nonce of a dropped pool with
+//! [`DeviceError::NonceNotIssued`]. The device has no clock, so nonces held longer than the pool's time to live are
+//! dropped by the host.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use tari_crypto::{
+    commitment::HomomorphicCommitmentFactory,
+    ristretto::{
+        pedersen::{extended_commitment_factory::ExtendedPedersenCommitmentFactory, PedersenCommitment},
+        RistrettoPublicKey,
+        RistrettoSecretKey,
+    },
+    tari_utilities::ByteArray,
+};
+use tari_ledger_protocol::{
+    nonce_pool_fetch_response_length,
+    nonce_pool_sign_request_length,
+    MAX_NONCE_POOL_FETCH,
+    MAX_POOLED_KERNEL_KEYS,
+    MAX_POOLED_NONCES,
+    NONCE_POOL_INVALIDATE_RESPONSE_LENGTH,
+    NONCE_POOL_SIGN_RESPONSE_LENGTH,
+    P1_NONCE_POOL_FETCH,
+    P1_NONCE_POOL_INVALIDATE,
+    P1_NONCE_POOL_SIGN,
+};
+
+use crate::{
+    device::{Capabilities, DerivationVersion, Instruction, LedgerDevice},
+    errors::DeviceError,
+    kernel::{kernel_challenge_preimage, kernel_message, verify_partial_signature, KernelShare},
+};
+
+/// How long fetched nonces are handed out for by default
+pub const DEFAULT_NONCE_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// A public nonce the app issued, by the ids it signs with
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PooledNonce {
+    pub pool_id: u64,
+    pub nonce_id: u8,
+    pub public_nonce: RistrettoPublicKey,
+}
+
+/// The nonces fetched from the device and not handed out yet
+pub struct NoncePool {
+    ttl: Duration,
+    fetched_at: Instant,
+    nonces: VecDeque<PooledNonce>,
+}
+
+impl NoncePool {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            fetched_at: Instant::now(),
+            nonces: VecDeque::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nonces.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nonces.is_empty()
+    }
+
+    /// Whether the nonces on hand were fetched longer than the time to live ago
+    pub fn is_expired(&self) -> bool {
+        self.fetched_at.elapsed() > self.ttl
+    }
+
+    /// Fetch nonces until `count` are on hand, dropping those that expired first
+    pub fn prefetch(&mut self, device: &LedgerDevice, count: usize) -> Result<(), DeviceError> {
+        if self.is_expired() && !self.nonces.is_empty() {
+            self.invalidate(device)?;
+        }
+        while self.nonces.len() < count {
+            let batch = (count - self.nonces.len()).min(MAX_NONCE_POOL_FETCH);
+            let nonces = fetch_nonces(device, batch as u8)?;
+            // The app started a new pool, which leaves the nonces of the old one unusable
+            if self.nonces.front().map(|nonce| nonce.pool_id) != Some(nonces[0].pool_id) {
+                self.nonces.clear();
+                self.fetched_at = Instant::now();
+            }
+            self.nonces.extend(nonces);
+        }
+        Ok(())
+    }
+
+    /// Hand out the next nonce, fetching another batch when none is on hand
+    pub fn take(&mut self, device: &LedgerDevice) -> Result<PooledNonce, DeviceError> {
+        if self.is_expired() || self.nonces.is_empty() {
+            self.prefetch(device, MAX_NONCE_POOL_FETCH)?;
+        }
+        Ok(self
+            .nonces
+            .pop_front()
+            .expect("a prefetch leaves at least one nonce on hand"))
+    }
+
+    /// Drop the nonces on hand and have the app drop its pool, which also voids the nonces handed out and not signed
+    /// with yet
+    pub fn invalidate(&mut self, device: &LedgerDevice) -> Result<(), DeviceError> {
+        self.nonces.clear();
+        invalidate_nonce_pool(device)
+    }
+}
+
+impl Default for NoncePool {
+    fn default() -> Self {
+        Self::new(DEFAULT_NONCE_TTL)
+    }
+}
+
+/// One party's share of a kernel that signs with a pooled nonce
+#[derive(Clone, Debug)]
+pub struct PooledKernel {
+    pub nonce: PooledNonce,
+    /// The public excess of the masks at `created` less those at `spent`, see [`public_excess`]
+    pub public_excess: RistrettoPublicKey,
+    pub spent: Vec<u32>,
+    pub created: Vec<u32>,
+    pub version: DerivationVersion,
+}
+
+impl PooledKernel {
+    /// The public excess and nonce to send to the other parties
+    pub fn share(&self) -> KernelShare {
+        KernelShare {
+            public_excess: self.public_excess.clone(),
+            public_nonce: self.nonce.public_nonce.clone(),
+        }
+    }
+
+    /// Have the app sign the [`kernel_message`] of `fee` and `lock_height` with the pooled nonce under the sums of the
+    /// nonces and excesses of every party. Spending inputs needs an approved transaction with the same fee, whose
+    /// kernel this is then, and receiving into outputs alone is confirmed by the user on the device.
+    pub fn sign(
+        &self,
+        device: &LedgerDevice,
+        total_nonce: &RistrettoPublicKey,
+        total_excess: &RistrettoPublicKey,
+        fee: u64,
+        lock_height: u64,
+    ) -> Result<RistrettoSecretKey, DeviceError> {
+        device.require(Capabilities::NONCE_POOL)?;
+        let count = self.spent.len() + self.created.len();
+        let (input_count, output_count) = match (u8::try_from(self.spent.len()), u8::try_from(self.created.len())) {
+            (Ok(input_count), Ok(output_count)) if count <= MAX_POOLED_KERNEL_KEYS => (input_count, output_count),
+            _ => {
+                return Err(DeviceError::TooManyKeys {
+                    count,
+                    max: MAX_POOLED_KERNEL_KEYS,
+                })
+            },
+        };
+        let p2 = device.derivation_p2(self.version)?;
+        let mut data = Vec::with_capacity(nonce_pool_sign_request_length(input_count, output_count));
+        data.extend_from_slice(&self.nonce.pool_id.to_le_bytes());
+        data.push(self.nonce.nonce_id);
+        data.push(input_count);
+        data.push(output_count);
+        for index in self.spent.iter().chain(&self.created) {
+            data.extend_from_slice(&index.to_le_bytes());
+        }
+        data.extend_from_slice(total_nonce.as_bytes());
+        data.extend_from_slice(total_excess.as_bytes());
+        data.extend_from_slice(&fee.to_le_bytes());
+        data.extend_from_slice(&lock_height.to_le_bytes());
+        let response = device.send(Instruction::NoncePool, P1_NONCE_POOL_SIGN, p2, data)?;
+        let payload = device.response_payload(&response, NONCE_POOL_SIGN_RESPONSE_LENGTH)?;
+        let s = RistrettoSecretKey::from_bytes(payload)
+            .map_err(|_| DeviceError::InvalidResponse("the partial kernel signature is not a scalar"))?;
+        let message = kernel_message(fee, lock_height);
+        if !verify_partial_signature(&self.share(), &s, total_nonce, total_excess, &message) {
+            return Err(DeviceError::InvalidSignature {
+                signature: "partial kernel signature",
+                preimage: Box::new(kernel_challenge_preimage(total_nonce, total_excess, &message)),
+            });
+        }
+        Ok(s)
+    }
+}
+
+/// The public excess of outputs `created` less inputs `spent`, each a commitment with its value, from the commitments
+/// alone, so that a pooled share needs no round trip to the device
+pub fn public_excess(created: &[(PedersenCommitment, u64)], spent: &[(PedersenCommitment, u64)]) -> RistrettoPublicKey {
+    let factory = ExtendedPedersenCommitmentFactory::default();
+    let mask_public_key = |(commitment, value): &(PedersenCommitment, u64)| {
+        let value = factory.commit_value(&RistrettoSecretKey::default(), *value);
+        commitment.as_public_key() - value.as_public_key()
+    };
+    let created = created.iter().fold(RistrettoPublicKey::default(), |sum, output| {
+        &sum + &mask_public_key(output)
+    });
+    spent.iter().fold(created, |sum, input| &sum - &mask_public_key(input))
+}
+
+/// Have the app issue the next `count` nonces of its pool
+pub fn fetch_nonces(device: &LedgerDevice, count: u8) -> Result<Vec<PooledNonce>, DeviceError> {
+    device.require(Capabilities::NONCE_POOL)?;
+    if count == 0 {
+        return Ok(Vec::new());
+    }
+    if usize::from(count) > MAX_NONCE_POOL_FETCH {
+        return Err(DeviceError::TooManyKeys {
+            count: usize::from(count),
+            max: MAX_NONCE_POOL_FETCH,
+        });
+    }
+    let response = device.send(Instruction::NoncePool, P1_NONCE_POOL_FETCH, 0x00, vec![count])?;
+    let payload = device.response_payload(&response, nonce_pool_fetch_response_length(count))?;
+    let mut pool_id = [0u8; 8];
+    pool_id.copy_from_slice(&payload[0..8]);
+    let pool_id = u64::from_le_bytes(pool_id);
+    let first = payload[8];
+    if usize::from(first) + usize::from(count) > MAX_POOLED_NONCES {
+        return Err(DeviceError::InvalidResponse("the nonce ids are out of range"));
+    }
+    payload[9..]
+        .chunks(32)
+        .zip(first..)
+        .map(|(bytes, nonce_id)| {
+            let public_nonce = RistrettoPublicKey::from_bytes(bytes)
+                .map_err(|_| DeviceError::InvalidResponse("a pooled nonce is not a public key"))?;
+            Ok(PooledNonce {
+                pool_id,
+                nonce_id,
+                public_nonce,
+            })
+        })
+        .collect()
+}
+
+/// Have the app drop its pool and every nonce in it that has not signed yet
+pub fn invalidate_nonce_pool(device: &LedgerDevice) -> Result<(), DeviceError> {
+    device.require(Capabilities::NONCE_POOL)?;
+    let response = device.send(Instruction::NoncePool, P1_NONCE_POOL_INVALIDATE, 0x00, vec![])?;
+    device.response_payload(&response, NONCE_POOL_INVALIDATE_RESPONSE_LENGTH)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use ledger_transport::{APDUAnswer, APDUCommand};
+    use tari_crypto::keys::PublicKey;
+    use tari_ledger_protocol::{RESPONSE_FORMAT_VERSION, SW_OK};
+
+    use super::*;
+    use crate::transport::LedgerTransport;
+
+    /// What the app keeps of its pool, and the `(p1, data)` of every command it was sent
+    #[derive(Default)]
+    struct App {
+        /// The id of the current pool and the nonces issued from it
+        pool: Option<(u64, u8)>,
+        pools_started: u64,
+        /// Keep issuing from a pool that cannot issue a fetch in full, as a broken app would
+        never_restarts: bool,
+        commands: Vec<(u8, Vec<u8>)>,
+    }
+
+    /// Plays the nonce pool of the app
+    struct PoolApp(Arc<Mutex<App>>);
+
+    impl LedgerTransport for PoolApp {
+        fn exchange(&self, command: &APDUCommand<Vec<u8>>) -> Result<APDUAnswer<Vec<u8>>, DeviceError> {
+            assert_eq!(command.ins, Instruction::NoncePool.as_byte());
+            let mut app = self.0.lock().unwrap();
+            let app = &mut *app;
+            app.commands.push((command.p1, command.data.clone()));
+            let mut response = vec![RESPONSE_FORMAT_VERSION];
+            match command.p1 {
+                P1_NONCE_POOL_FETCH => {
+                    let count = command.data[0];
+                    let (pool_id, first) = match app.pool {
+                        Some((pool_id, issued))
+                            if app.never_restarts || usize::from(issued) + usize::from(count) <= MAX_POOLED_NONCES =>
+                        {
+                            (pool_id, issued)
+                        },
+                        _ => {
+                            app.pools_started += 1;
+                            (app.pools_started, 0)
+                        },
+                    };
+                    app.pool = Some((pool_id, first + count));
+                    response.extend_from_slice(&pool_id.to_le_bytes());
+                    response.push(first);
+                    for nonce_id in first..first + count {
+                        response.extend_from_slice(public_nonce(pool_id, nonce_id).as_bytes());
+                    }
+                },
+                P1_NONCE_POOL_INVALIDATE => app.pool = None,
+                p1 => panic!("unexpected P1 {}", p1),
+            }
+            response.extend_from_slice(&SW_OK.to_be_bytes());
+            Ok(APDUAnswer::from_answer(response).unwrap())
+        }
+    }
+
+    fn public_nonce(pool_id: u64, nonce_id: u8) -> RistrettoPublicKey {
+        RistrettoPublicKey::from_secret_key(&RistrettoSecretKey::from(pool_id * 1000 + u64::from(nonce_id) + 1))
+    }
+
+    fn pool_app(app: App) -> (LedgerDevice, Arc<Mutex<App>>) {
+        let app = Arc::new(Mutex::new(app));
+        let device = LedgerDevice::from_transport(PoolApp(app.clone()));
+        device.cache_capabilities(Capabilities::NONCE_POOL);
+        (device, app)
+    }
+
+    /// The commands sent since the last call
+    fn sent(app: &Arc<Mutex<App>>) -> Vec<(u8, Vec<u8>)> {
+        std::mem::take(&mut app.lock().unwrap().commands)
+    }
+
+    fn ids(nonces: &[PooledNonce]) -> Vec<(u64, u8)> {
+        nonces.iter().map(|nonce| (nonce.pool_id, nonce.nonce_id)).collect()
+    }
+
+    #[test]
+    fn an_empty_pool_fetches_a_full_batch_and_hands_it_out_in_order() {
+        let (device, app) = pool_app(App::default());
+        let mut pool = NoncePool::default();
+        assert!(pool.is_empty());
+
+        let first = pool.take(&device).unwrap();
+        assert_eq!(first, PooledNonce {
+            pool_id: 1,
+            nonce_id: 0,
+            public_nonce: public_nonce(1, 0),
+        });
+        assert_eq!(sent(&app), vec![(P1_NONCE_POOL_FETCH, vec![
+            MAX_NONCE_POOL_FETCH as u8
+        ])]);
+        assert_eq!(pool.len(), MAX_NONCE_POOL_FETCH - 1);
+
+        let rest = (1..MAX_NONCE_POOL_FETCH)
+            .map(|_| pool.take(&device).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ids(&rest),
+            (1..MAX_NONCE_POOL_FETCH as u8).map(|id| (1, id)).collect::<Vec<_>>()
+        );
+        assert!(sent(&app).is_empty());
+        assert!(pool.is_empty());
+
+        // Once the batch is handed out, the next take fetches the following nonces of the same pool
+        let next = pool.take(&device).unwrap();
+        assert_eq!((next.pool_id, next.nonce_id), (1, MAX_NONCE_POOL_FETCH as u8));
+        assert_eq!(sent(&app).len(), 1);
+    }
+
+    #[test]
+    fn a_prefetch_tops_the_pool_up_in_batches() {
+        let (device, app) = pool_app(App::default());
+        let mut pool = NoncePool::default();
+        pool.prefetch(&device, 10).unwrap();
+        assert_eq!(sent(&app), vec![
+            (P1_NONCE_POOL_FETCH, vec![MAX_NONCE_POOL_FETCH as u8]),
+            (P1_NONCE_POOL_FETCH, vec![10 - MAX_NONCE_POOL_FETCH as u8]),
+        ]);
+        assert_eq!(pool.len(), 10);
+        pool.prefetch(&device, 4).unwrap();
+        assert!(sent(&app).is_empty());
+        pool.take(&device).unwrap();
+        pool.prefetch(&device, 10).unwrap();
+        assert_eq!(sent(&app), vec![(P1_NONCE_POOL_FETCH, vec![1])]);
+        pool.prefetch(&device, 0).unwrap();
+        assert_eq!(pool.len(), 10);
+    }
+
+    #[test]
+    fn expired_nonces_are_dropped_before_one_is_handed_out() {
+        let (device, app) = pool_app(App::default());
+        let mut pool = NoncePool::new(Duration::ZERO);
+        pool.prefetch(&device, 2).unwrap();
+        sent(&app);
+        std::thread::sleep(Duration::from_millis(1));
+        assert!(pool.is_expired());
+
+        // The app's pool goes with them, so the nonces already handed out cannot sign either
+        let nonce = pool.take(&device).unwrap();
+        assert_eq!(sent(&app), vec![
+            (P1_NONCE_POOL_INVALIDATE, vec![]),
+            (P1_NONCE_POOL_FETCH, vec![MAX_NONCE_POOL_FETCH as u8]),
+        ]);
+        assert_eq!((nonce.pool_id, nonce.nonce_id), (2, 0));
+        assert_eq!(pool.len(), MAX_NONCE_POOL_FETCH - 1);
+
+        // An expired pool with nothing on hand has nothing to drop
+        let mut pool = NoncePool::new(Duration::ZERO);
+        std::thread::sleep(Duration::from_millis(1));
+        assert!(pool.is_expired());
+        pool.take(&device).unwrap();
+        assert_eq!(sent(&app), vec![(P1_NONCE_POOL_FETCH, vec![
+            MAX_NONCE_POOL_FETCH as u8
+        ])]);
+
+        assert!(!NoncePool::default().is_expired());
+    }
+
+    #[test]
+    fn invalidating_drops_the_nonces_on_hand_and_the_apps_pool() {
+        let (device, app) = pool_app(App::default());
+        let mut pool = NoncePool::default();
+        pool.prefetch(&device, 3).unwrap();
+        sent(&app);
+        pool.invalidate(&device).unwrap();
+        assert!(pool.is_empty());
+        assert_eq!(sent(&app), vec![(P1_NONCE_POOL_INVALIDATE, vec![])]);
+        assert_eq!(app.lock().unwrap().pool, None);
+
+        let nonce = pool.take(&device).unwrap();
+        assert_eq!((nonce.pool_id, nonce.nonce_id), (2, 0));
+    }
+
+    #[test]
+    fn a_new_pool_on_the_device_drops_the_nonces_of_the_old_one() {
+        let (device, app) = pool_app(App {
+            pool: Some((1, 55)),
+            pools_started: 1,
+            ..App::default()
+        });
+        let mut pool = NoncePool::default();
+        pool.prefetch(&device, MAX_NONCE_POOL_FETCH).unwrap();
+        assert_eq!(pool.take(&device).unwrap().nonce_id, 55);
+        sent(&app);
+
+        // The first batch no longer fits into the old pool, so the app starts a new one
+        pool.prefetch(&device, 10).unwrap();
+        assert_eq!(sent(&app), vec![
+            (P1_NONCE_POOL_FETCH, vec![4]),
+            (P1_NONCE_POOL_FETCH, vec![6])
+        ]);
+        let nonces = (0..10).map(|_| pool.take(&device).unwrap()).collect::<Vec<_>>();
+        assert_eq!(ids(&nonces), (0..10).map(|id| (2, id)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn fetches_are_checked() {
+        let (device, app) = pool_app(App::default());
+        assert_eq!(fetch_nonces(&device, 0).unwrap(), vec![]);
+        assert!(sent(&app).is_empty());
+        assert!(matches!(
+            fetch_nonces(&device, MAX_NONCE_POOL_FETCH as u8 + 1),
+            Err(DeviceError::TooManyKeys { count, max: MAX_NONCE_POOL_FETCH }) if count == MAX_NONCE_POOL_FETCH + 1
+        ));
+        assert!(sent(&app).is_empty());
+
+        let (device, _) = pool_app(App {
+            pool: Some((1, 60)),
+            pools_started: 1,
+            never_restarts: true,
+            ..App::default()
+        });
+        assert!(matches!(
+            fetch_nonces(&device, MAX_NONCE_POOL_FETCH as u8),
+            Err(DeviceError::InvalidResponse(_))
+        ));
+    }
+
+    #[test]
+    fn an_app_without_a_nonce_pool_is_not_asked() {
+        let app = Arc::new(Mutex::new(App::default()));
+        let device = LedgerDevice::from_transport(PoolApp(app.clone()));
+        device.cache_capabilities(Capabilities::empty());
+        let mut pool = NoncePool::default();
+        assert!(matches!(pool.take(&device), Err(DeviceError::Unsupported(_))));
+        assert!(matches!(pool.invalidate(&device), Err(DeviceError::Unsupported(_))));
+        assert!(sent(&app).is_empty());
+    }
+}
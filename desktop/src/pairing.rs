@@ -0,0 +1,137 @@
+//! Pairing a host with the device
+//! Software that can reach the device can ask it for anything, and a rogue host binary looks just like the genuine
+//! one to the user. Pairing stores a random secret on both sides the first time the host is used. At the start of every
+//! later session the host proves it holds the secret and the device proves it back, both bound to the fresh session
+//! challenge, and both show the same pairing words derived from the secret. A host without the secret cannot pass the
+//! check, the device warns that it does not know the host, and the words the user expects never show up.
+//!
+//! The secret only crosses the link inside an authenticated session, masked under the session key, and is kept in the
+//! data directory of the host, one file per app key, readable only by the user.
+
+use std::{
+    fmt,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use tari_crypto::{
+    ristretto::RistrettoPublicKey,
+    tari_utilities::hex::{from_hex, to_hex, Hex},
+};
+use tari_ledger_protocol::{
+    pairing_words,
+    P1_PAIRING_REGISTER,
+    P1_PAIRING_VERIFY,
+    PAIRING_PROOF_LABEL,
+    PAIRING_REGISTER_RESPONSE_LENGTH,
+    PAIRING_RESPONSE_LABEL,
+    PAIRING_SECRET_LENGTH,
+    PAIRING_VERIFY_RESPONSE_LENGTH,
+    PAIRING_WORD_COUNT,
+    PAIRING_WORD_LABEL,
+};
+
+use crate::{
+    device::{Capabilities, Instruction, LedgerDevice},
+    errors::{DeviceError, StoreError},
+    hashing::{DomainSeparatedConsensusHasher, TransactionHashDomain},
+    redact::Redacted,
+};
+
+/// The secret a host shares with the device it was paired with
+#[derive(Clone, PartialEq, Eq)]
+pub struct PairingSecret([u8; PAIRING_SECRET_LENGTH]);
+
+impl PairingSecret {
+    /// `bytes` have to be fresh random bytes
+    pub fn from_bytes(bytes: [u8; PAIRING_SECRET_LENGTH]) -> Self {
+        Self(bytes)
+    }
+
+    /// The words the device shows for this secret
+    pub fn words(&self) -> [&'static str; PAIRING_WORD_COUNT] {
+        let digest = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(PAIRING_WORD_LABEL)
+            .chain_fixed(&self.0)
+            .finalize();
+        pairing_words(&digest)
+    }
+
+    fn proof(&self, label: &'static str, challenge: &[u8; 32]) -> [u8; 32] {
+        DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(label)
+            .chain_fixed(&self.0)
+            .chain_fixed(challenge)
+            .finalize()
+    }
+}
+
+impl fmt::Debug for PairingSecret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("PairingSecret").field(&Redacted).finish()
+    }
+}
+
+/// Pair the device with `secret` in place of any host it was paired with before, once the user confirms the pairing
+/// words on the device. Needs an authenticated session.
+pub fn pair(device: &LedgerDevice, secret: &PairingSecret) -> Result<(), DeviceError> {
+    device.require(Capabilities::PAIRING)?;
+    let channel = device.channel().ok_or(DeviceError::PairingFailed)?;
+    let masked = secret
+        .0
+        .iter()
+        .zip(channel.pairing_mask())
+        .map(|(s, m)| s ^ m)
+        .collect::<Vec<_>>();
+    let response = device.send(Instruction::Pairing, P1_PAIRING_REGISTER, 0x00, masked)?;
+    device.response_payload(&response, PAIRING_REGISTER_RESPONSE_LENGTH)?;
+    Ok(())
+}
+
+/// Prove to the device that this host holds `secret` and check that the device holds it too. Returns the pairing words
+/// the device shows, for the user to compare. Needs an authenticated session.
+pub fn verify_pairing(
+    device: &LedgerDevice,
+    secret: &PairingSecret,
+) -> Result<[&'static str; PAIRING_WORD_COUNT], DeviceError> {
+    device.require(Capabilities::PAIRING)?;
+    let channel = device.channel().ok_or(DeviceError::PairingFailed)?;
+    let proof = secret.proof(PAIRING_PROOF_LABEL, channel.challenge());
+    let response = device.send(Instruction::Pairing, P1_PAIRING_VERIFY, 0x00, proof.to_vec())?;
+    let payload = device.response_payload(&response, PAIRING_VERIFY_RESPONSE_LENGTH)?;
+    if payload != secret.proof(PAIRING_RESPONSE_LABEL, channel.challenge()) {
+        return Err(DeviceError::PairingFailed);
+    }
+    Ok(secret.words())
+}
+
+/// Where the secret of the pairing with the app key `app_public_key` is kept under `dir`
+pub fn pairing_path(dir: &Path, app_public_key: &RistrettoPublicKey) -> PathBuf {
+    dir.join(format!("{}.pairing", app_public_key.to_hex()))
+}
+
+/// The pairing secret stored at `path`, if this host was paired
+pub fn load_pairing_secret(path: &Path) -> Result<Option<PairingSecret>, StoreError> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let bytes = from_hex(contents.trim())
+        .ok()
+        .and_then(|bytes| <[u8; PAIRING_SECRET_LENGTH]>::try_from(bytes).ok())
+        .ok_or(StoreError::Corrupt("the pairing secret is not 32 hex encoded bytes"))?;
+    Ok(Some(PairingSecret(bytes)))
+}
+
+/// Store `secret` at `path`, readable only by the user where the platform allows it
+pub fn save_pairing_secret(path: &Path, secret: &PairingSecret) -> Result<(), StoreError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, to_hex(&secret.0))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,122 @@
+//! Payment references bound to an output and signed by the device
+//! A payment reference commits to the hash of the paying output and a free-form payment id, e.g. an invoice number.
+//! Signing it with the device key lets a merchant, or the payer, prove later that the payment came from the hardware
+//! wallet without revealing anything else about it.
+
+use std::{fmt, str::FromStr};
+
+use tari_crypto::{
+    ristretto::{RistrettoPublicKey, RistrettoSchnorr, RistrettoSecretKey},
+    tari_utilities::{
+        hex::{from_hex, to_hex},
+        ByteArray,
+    },
+};
+
+use crate::{
+    domains::PAYMENT_REFERENCE_LABEL,
+    errors::SignerError,
+    hashing::{Challenge, DomainSeparatedConsensusHasher, TransactionHashDomain},
+    signer::LedgerTransactionSigner,
+    verify::verify_script_signature,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PaymentReference(pub [u8; 32]);
+
+impl PaymentReference {
+    pub fn new(output_hash: &[u8; 32], payment_id: &[u8]) -> Self {
+        Self(
+            DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(PAYMENT_REFERENCE_LABEL)
+                .chain(output_hash)
+                .chain_bytes(payment_id)
+                .finalize(),
+        )
+    }
+
+    /// The reference as the challenge the device signs
+    pub fn challenge(&self) -> Challenge {
+        Challenge::from_hashed(PAYMENT_REFERENCE_LABEL, self.0)
+    }
+}
+
+impl fmt::Display for PaymentReference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", to_hex(&self.0))
+    }
+}
+
+/// A payment reference with the device signature over it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PaymentProof {
+    pub reference: PaymentReference,
+    pub public_key: RistrettoPublicKey,
+    pub signature: RistrettoSchnorr,
+}
+
+impl PaymentProof {
+    pub const ENCODED_LENGTH: usize = 4 * 32;
+
+    /// Ask the device to sign the payment reference of `output_hash` and `payment_id`
+    pub fn create(
+        signer: &LedgerTransactionSigner,
+        output_hash: &[u8; 32],
+        payment_id: &[u8],
+    ) -> Result<Self, SignerError> {
+        let reference = PaymentReference::new(output_hash, payment_id);
+        let signed = signer.sign_script_message(&reference.challenge())?;
+        Ok(Self {
+            reference,
+            signature: signed.schnorr(),
+            public_key: signed.public_key,
+        })
+    }
+
+    /// Check that the proof is for `output_hash` and `payment_id` and carries a valid signature. The caller must still
+    /// check `public_key` belongs to the wallet being audited.
+    pub fn verify(&self, output_hash: &[u8; 32], payment_id: &[u8]) -> bool {
+        self.reference == PaymentReference::new(output_hash, payment_id) &&
+            verify_script_signature(&self.public_key, &self.signature, &self.reference.0)
+    }
+
+    /// `reference || public key || public nonce || s`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::ENCODED_LENGTH);
+        bytes.extend_from_slice(&self.reference.0);
+        bytes.extend_from_slice(self.public_key.as_bytes());
+        bytes.extend_from_slice(self.signature.get_public_nonce().as_bytes());
+        bytes.extend_from_slice(self.signature.get_signature().as_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != Self::ENCODED_LENGTH {
+            return None;
+        }
+        let mut reference = [0u8; 32];
+        reference.copy_from_slice(&bytes[0..32]);
+        let public_key = RistrettoPublicKey::from_bytes(&bytes[32..64]).ok()?;
+        let nonce = RistrettoPublicKey::from_bytes(&bytes[64..96]).ok()?;
+        let s = RistrettoSecretKey::from_bytes(&bytes[96..128]).ok()?;
+        Some(Self {
+            reference: PaymentReference(reference),
+            public_key,
+            signature: RistrettoSchnorr::new(nonce, s),
+        })
+    }
+}
+
+impl fmt::Display for PaymentProof {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", to_hex(&self.to_bytes()))
+    }
+}
+
+impl FromStr for PaymentProof {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = from_hex(s).map_err(|_| "the payment proof is not valid hex")?;
+        Self::from_bytes(&bytes).ok_or("the payment proof is malformed")
+    }
+}
@@ -0,0 +1,226 @@
+//! Who may send what through the daemon
+//! The [`daemon`](crate::daemon) passes on the APDUs of every client that connects, so each [`ClientGrant`] limits a
+//! client to some [`InstructionClass`]es: a monitoring service that reads public keys and counters can be given
+//! [`InstructionClass::ReadOnly`] only, and cannot start a spend however it crafts its APDUs. A client identifies with
+//! a token when it connects, of which the daemon only keeps the SHA-256 hash. One that presents no token is the owner
+//! of the socket, which nobody else can open, and may send anything while no client is configured. Once one is, a
+//! client that leaves out its token would otherwise get more than its grant, so the owner may only send the classes
+//! configured for it, none by default. A grant can also
+//! carry a [`RequestQuota`], so that a client that misbehaves cannot flood the device with commands or the user with
+//! prompts.
+
+use std::time::Duration;
+
+use ledger_transport::APDUCommand;
+use sha2::{Digest, Sha256};
+use tari_crypto::tari_utilities::hex::from_hex;
+use tari_ledger_protocol::{CLA, P1_BIRTHDAY_GET, P1_SETTINGS_GET};
+
+use crate::device::Instruction;
+
+/// What a command can do with the device
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum InstructionClass {
+    /// Instructions of the Tari app that only read, e.g. public keys, commitments, counters and the version, and set
+    /// up the session others are sent in
+    ReadOnly,
+    /// Every other instruction of the Tari app: the ones that sign or take part in signing, reveal a secret or change
+    /// the app
+    Signing,
+    /// Everything that is not an instruction of the Tari app, e.g. the commands of the Ledger OS
+    Raw,
+}
+
+impl InstructionClass {
+    pub const ALL: [Self; 3] = [Self::ReadOnly, Self::Signing, Self::Raw];
+
+    pub fn of(command: &APDUCommand<Vec<u8>>) -> Self {
+        if command.cla != CLA {
+            return InstructionClass::Raw;
+        }
+        match Instruction::try_from(command.ins) {
+            Ok(
+                Instruction::GetVersion |
+                Instruction::ClientVersion |
+                Instruction::GetCapabilities |
+                Instruction::GetPublicKeys |
+                Instruction::GetBlindedPublicKey |
+                Instruction::GetSigningCounter |
+                Instruction::Commitment |
+                Instruction::BatchCommitment |
+                Instruction::OpenSession,
+            ) => InstructionClass::ReadOnly,
+            Ok(Instruction::WalletBirthday) if command.p1 == P1_BIRTHDAY_GET => InstructionClass::ReadOnly,
+            Ok(Instruction::AppSettings) if command.p1 == P1_SETTINGS_GET => InstructionClass::ReadOnly,
+            Ok(_) => InstructionClass::Signing,
+            Err(()) => InstructionClass::Raw,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            InstructionClass::ReadOnly => "read-only",
+            InstructionClass::Signing => "signing",
+            InstructionClass::Raw => "raw",
+        }
+    }
+}
+
+/// How many commands a client may send within [`RequestQuota::WINDOW`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RequestQuota {
+    pub commands: u32,
+    /// Of the commands, how many may need the user's confirmation
+    pub confirmations: u32,
+}
+
+impl RequestQuota {
+    /// The quota is of the commands sent within the last minute
+    pub const WINDOW: Duration = Duration::from_secs(60);
+}
+
+/// A client and the classes of instructions it may send
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientGrant {
+    pub name: String,
+    pub classes: Vec<InstructionClass>,
+    /// No quota lets the client send as many commands as it likes
+    pub quota: Option<RequestQuota>,
+}
+
+impl ClientGrant {
+    pub fn new(name: impl Into<String>, classes: Vec<InstructionClass>) -> Self {
+        Self {
+            name: name.into(),
+            classes,
+            quota: None,
+        }
+    }
+
+    pub fn with_quota(mut self, quota: RequestQuota) -> Self {
+        self.quota = Some(quota);
+        self
+    }
+
+    pub fn allows(&self, command: &APDUCommand<Vec<u8>>) -> bool {
+        self.classes.contains(&InstructionClass::of(command))
+    }
+}
+
+/// The grants of the clients of a daemon, by the SHA-256 hash of their tokens
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientPermissions {
+    owner: ClientGrant,
+    clients: Vec<([u8; 32], ClientGrant)>,
+}
+
+impl Default for ClientPermissions {
+    /// Only the owner of the socket, who may send anything
+    fn default() -> Self {
+        Self::new(InstructionClass::ALL.to_vec())
+    }
+}
+
+impl ClientPermissions {
+    /// The owner of the socket may send `classes`, and no client with a token is known
+    pub fn new(classes: Vec<InstructionClass>) -> Self {
+        Self {
+            owner: ClientGrant::new("the owner of the socket", classes),
+            clients: Vec::new(),
+        }
+    }
+
+    /// Grant the client whose token hashes to `token_sha256`, in hex. A hash that is not 32 bytes in hex is refused.
+    pub fn with_client(mut self, token_sha256: &str, grant: ClientGrant) -> Result<Self, String> {
+        let hash = from_hex(token_sha256)
+            .ok()
+            .and_then(|hash| <[u8; 32]>::try_from(hash).ok())
+            .ok_or_else(|| format!("the token hash of {} is not 32 bytes in hex", grant.name))?;
+        self.clients.push((hash, grant));
+        Ok(self)
+    }
+
+    /// The grant of the client that presented `token`, the owner's if it is empty, or `None` if no client has it
+    pub fn grant(&self, token: &[u8]) -> Option<&ClientGrant> {
+        if token.is_empty() {
+            return Some(&self.owner);
+        }
+        // Only the hash of the token is compared, so the comparison does not leak the token
+        let hash = Sha256::digest(token);
+        self.clients
+            .iter()
+            .find(|(expected, _)| hash.as_slice() == expected)
+            .map(|(_, grant)| grant)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tari_crypto::tari_utilities::hex::to_hex;
+    use tari_ledger_protocol::{P1_BIRTHDAY_SET, P1_KERNEL_NONCE};
+
+    use super::*;
+    use crate::test_helpers::command;
+
+    #[test]
+    fn commands_are_classified() {
+        assert_eq!(
+            InstructionClass::of(&command(Instruction::GetPublicKeys, 0)),
+            InstructionClass::ReadOnly
+        );
+        assert_eq!(
+            InstructionClass::of(&command(Instruction::KernelSignature, P1_KERNEL_NONCE)),
+            InstructionClass::Signing
+        );
+        assert_eq!(
+            InstructionClass::of(&command(Instruction::SignOutput, 0)),
+            InstructionClass::Signing
+        );
+        // Reading the birthday only reads, setting it changes the app
+        assert_eq!(
+            InstructionClass::of(&command(Instruction::WalletBirthday, P1_BIRTHDAY_GET)),
+            InstructionClass::ReadOnly
+        );
+        assert_eq!(
+            InstructionClass::of(&command(Instruction::WalletBirthday, P1_BIRTHDAY_SET)),
+            InstructionClass::Signing
+        );
+        assert_eq!(
+            InstructionClass::of(&APDUCommand {
+                cla: 0xb0,
+                ..command(Instruction::GetVersion, 0)
+            }),
+            InstructionClass::Raw
+        );
+    }
+
+    #[test]
+    fn clients_get_their_grant() {
+        let token = "monitoring token";
+        // With a client configured the owner gets only what is configured for it
+        let permissions = ClientPermissions::new(vec![InstructionClass::ReadOnly])
+            .with_client(
+                &to_hex(&Sha256::digest(token.as_bytes())),
+                ClientGrant::new("monitoring", vec![InstructionClass::ReadOnly]),
+            )
+            .unwrap();
+        let grant = permissions.grant(token.as_bytes()).unwrap();
+        assert_eq!(grant.name, "monitoring");
+        assert!(grant.allows(&command(Instruction::GetPublicKeys, 0)));
+        assert!(!grant.allows(&command(Instruction::Sign, 0)));
+        let owner = permissions.grant(b"").unwrap();
+        assert!(owner.allows(&command(Instruction::GetPublicKeys, 0)));
+        assert!(!owner.allows(&command(Instruction::Sign, 0)));
+        assert!(ClientPermissions::default()
+            .grant(b"")
+            .unwrap()
+            .allows(&command(Instruction::Sign, 0)));
+        assert!(permissions.grant(b"another token").is_none());
+        assert!(ClientPermissions::default()
+            .with_client("00", ClientGrant::new("short", Vec::new()))
+            .is_err());
+    }
+}
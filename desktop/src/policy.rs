@@ -0,0 +1,98 @@
+//! Host side signing policy
+//! The device asks whoever holds it to confirm every transaction, which is one person. A wallet run by a team can
+//! require a second one for large amounts: under a [`TwoManRule`] the signer asks a [`SecondaryConfirmation`] before it
+//! sends the first APDU of any transaction paying out more than the threshold, and refuses to sign unless it is
+//! confirmed. The second operator confirms at a prompt on the host or, with an [`OperatorToken`], by giving a token
+//! only they know, of which the host only keeps the SHA-256 hash.
+//!
+//! The rule is enforced by the host alone. It keeps an operator from signing a large transaction by mistake or on
+//! their own with this host, not someone who talks to the device with another one.
+
+use sha2::{Digest, Sha256};
+use tari_crypto::tari_utilities::hex::from_hex;
+
+use crate::signer::OutputToSign;
+
+/// Transactions that need a second confirmation before they are signed
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TwoManRule {
+    /// Transactions paying out more than this, the fee included, need a second confirmation, in microTari
+    pub threshold: u64,
+    /// The SHA-256 hash in hex of the token of the second operator, who confirms at a prompt without one
+    pub token_sha256: Option<String>,
+}
+
+impl TwoManRule {
+    /// Whether a transaction paying out `amount` needs a second confirmation
+    pub fn applies_to(&self, amount: u64) -> bool {
+        amount > self.threshold
+    }
+}
+
+/// A transaction waiting for a second confirmation
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfirmationRequest {
+    /// What the transaction pays to others, the fee included, in microTari
+    pub amount: u64,
+    /// In microTari
+    pub fee: u64,
+    /// The outputs that are not change
+    pub payments: usize,
+    pub threshold: u64,
+}
+
+impl ConfirmationRequest {
+    /// The request for `outputs`, which pay `fee`
+    pub fn for_transaction(outputs: &[OutputToSign], fee: u64, threshold: u64) -> Self {
+        let payments = outputs.iter().filter(|output| !output.is_change);
+        Self {
+            amount: payments
+                .clone()
+                .fold(fee, |amount, output| amount.saturating_add(output.value)),
+            fee,
+            payments: payments.count(),
+            threshold,
+        }
+    }
+}
+
+/// A second operator confirming a transaction on the host
+pub trait SecondaryConfirmation {
+    /// Nothing if the transaction of `request` is confirmed, otherwise why it is not
+    fn confirm(&self, request: &ConfirmationRequest) -> Result<(), String>;
+}
+
+/// Confirms a transaction if the second operator gave the token whose hash the rule holds
+pub struct OperatorToken {
+    expected: Option<[u8; 32]>,
+    token: Option<String>,
+}
+
+impl OperatorToken {
+    /// Check `token`, if the operator gave one, against `token_sha256`, the SHA-256 hash in hex of their token. A hash
+    /// that is not 32 bytes in hex confirms nothing.
+    pub fn new(token_sha256: &str, token: Option<String>) -> Self {
+        let expected = from_hex(token_sha256)
+            .ok()
+            .and_then(|hash| <[u8; 32]>::try_from(hash).ok());
+        Self { expected, token }
+    }
+}
+
+impl SecondaryConfirmation for OperatorToken {
+    fn confirm(&self, _request: &ConfirmationRequest) -> Result<(), String> {
+        let Some(expected) = self.expected else {
+            return Err("the operator token hash of the rule is not 32 bytes in hex".to_string());
+        };
+        let Some(token) = &self.token else {
+            return Err("no operator token was given".to_string());
+        };
+        // Only the hash of the token is compared, so the comparison does not leak the token
+        if Sha256::digest(token.as_bytes()).as_slice() == expected {
+            Ok(())
+        } else {
+            Err("the operator token does not match".to_string())
+        }
+    }
+}
@@ -0,0 +1,176 @@
+//! The protocol documentation
+//! Every instruction of the protocol crate carries its [`InstructionSpec`]: what P1 and P2 carry, the layout of each
+//! request and response and the status words the app answers with. The app and the host are both built against it and
+//! the dry run logs its response layouts, so the documentation generated here from the same registry cannot describe a
+//! protocol other than the one they speak. [`spec_to_json`] writes it for tools, [`spec_to_markdown`] as tables for
+//! people.
+
+use std::str::FromStr;
+
+use serde_json::{json, Value};
+use tari_ledger_protocol::{
+    Capabilities,
+    Instruction,
+    InstructionSpec,
+    MessageSpec,
+    APDU_HEADER_LENGTH,
+    CLA,
+    COMMON_STATUS_WORDS,
+    MAX_CHUNK_LENGTH,
+    RESPONSE_FORMAT_VERSION,
+    STATUS_WORDS,
+};
+
+/// The version of the JSON layout
+pub const PROTOCOL_SPEC_FORMAT_VERSION: u64 = 1;
+
+/// How the spec is written
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpecFormat {
+    Json,
+    Markdown,
+}
+
+impl FromStr for SpecFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(SpecFormat::Json),
+            "markdown" | "md" => Ok(SpecFormat::Markdown),
+            _ => Err(format!("unknown spec format '{}', use json or markdown", s)),
+        }
+    }
+}
+
+/// The spec in `format`
+pub fn generate(format: SpecFormat) -> String {
+    match format {
+        SpecFormat::Json => spec_to_json(),
+        SpecFormat::Markdown => spec_to_markdown(),
+    }
+}
+
+/// The spec of every instruction, the status words and the capabilities as a JSON document
+pub fn spec_to_json() -> String {
+    let spec = json!({
+        "version": PROTOCOL_SPEC_FORMAT_VERSION,
+        "cla": format!("0x{:02x}", CLA),
+        "header_length": APDU_HEADER_LENGTH,
+        "max_chunk_length": MAX_CHUNK_LENGTH,
+        "response_format": RESPONSE_FORMAT_VERSION,
+        "common_status_words": COMMON_STATUS_WORDS.iter().map(|code| status_word(*code)).collect::<Vec<_>>(),
+        "status_words": STATUS_WORDS
+            .iter()
+            .map(|spec| json!({
+                "code": status_word(spec.code),
+                "name": spec.name,
+                "meaning": spec.meaning,
+            }))
+            .collect::<Vec<_>>(),
+        "capabilities": Capabilities::NAMED
+            .iter()
+            .map(|(capability, name)| json!({
+                "bit": capability.bits().trailing_zeros(),
+                "name": name,
+            }))
+            .collect::<Vec<_>>(),
+        "instructions": Instruction::ALL
+            .iter()
+            .map(|instruction| instruction_to_json(&instruction.spec()))
+            .collect::<Vec<_>>(),
+    });
+    serde_json::to_string_pretty(&spec).expect("a JSON value always serializes")
+}
+
+fn instruction_to_json(spec: &InstructionSpec) -> Value {
+    json!({
+        "ins": format!("0x{:02x}", spec.instruction.as_byte()),
+        "name": spec.name,
+        "summary": spec.summary,
+        "capability": spec.capability.map(Capabilities::name),
+        "p1": spec.p1,
+        "p2": spec.p2,
+        "messages": spec.messages.iter().map(message_to_json).collect::<Vec<_>>(),
+        "status_words": spec.status_words.iter().map(|code| status_word(*code)).collect::<Vec<_>>(),
+    })
+}
+
+fn message_to_json(message: &MessageSpec) -> Value {
+    json!({
+        "p1": message.p1.map(|p1| format!("0x{:02x}", p1)),
+        "name": message.name,
+        "request": message.request,
+        "request_length": message.request_length,
+        "response": message.response,
+        "response_length": message.response_length,
+    })
+}
+
+fn status_word(code: u16) -> String {
+    format!("0x{:04x}", code)
+}
+
+/// The spec as Markdown: a table of every message of every instruction, followed by the status words
+pub fn spec_to_markdown() -> String {
+    let mut markdown = format!(
+        "# Tari Ledger APDU protocol\n\nCLA `0x{:02x}`. Every response starts with the format byte {}. Payloads \
+         longer than {} bytes are sent in chunks. Every instruction can also be answered with {}.\n\n",
+        CLA,
+        RESPONSE_FORMAT_VERSION,
+        MAX_CHUNK_LENGTH,
+        COMMON_STATUS_WORDS
+            .iter()
+            .map(|code| format!("`{}`", status_word(*code)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    markdown.push_str("| INS | Instruction | Capability | P1 | P2 | Request | Response | Status words |\n");
+    markdown.push_str("|---|---|---|---|---|---|---|---|\n");
+    for instruction in Instruction::ALL {
+        let spec = instruction.spec();
+        let status_words = spec
+            .status_words
+            .iter()
+            .map(|code| format!("`{}`", status_word(*code)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        for message in spec.messages {
+            let p1 = match (message.p1, spec.p1) {
+                (Some(p1), _) if message.name.is_empty() => format!("`0x{:02x}`", p1),
+                (Some(p1), _) => format!("`0x{:02x}` {}", p1, message.name),
+                (None, Some(p1)) => p1.to_string(),
+                (None, None) => "0".to_string(),
+            };
+            markdown.push_str(&format!(
+                "| `0x{:02x}` | {} | {} | {} | {} | {} | {} | {} |\n",
+                instruction.as_byte(),
+                spec.name,
+                spec.capability.map_or("", Capabilities::name),
+                p1,
+                spec.p2.unwrap_or("0"),
+                layout(message.request, message.request_length),
+                layout(message.response, message.response_length),
+                status_words,
+            ));
+        }
+    }
+    markdown.push_str("\n| Status word | Name | Meaning |\n|---|---|---|\n");
+    for spec in STATUS_WORDS {
+        markdown.push_str(&format!(
+            "| `{}` | `{}` | {} |\n",
+            status_word(spec.code),
+            spec.name,
+            spec.meaning
+        ));
+    }
+    markdown
+}
+
+/// A layout in code, so that `*` is not read as emphasis, with its length when it is fixed
+fn layout(layout: &str, length: Option<usize>) -> String {
+    match length {
+        Some(length) => format!("`{}`, {} bytes", layout, length),
+        None => format!("`{}`", layout),
+    }
+}
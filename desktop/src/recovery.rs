@@ -0,0 +1,432 @@
+//! Recovering the host state from the device and the chain
+//! The host keeps the next unused index of every key branch in its [`LedgerStateStore`]. When that is lost, the indices
+//! the wallet used are found again from the device and the chain alone: the public keys of each branch are fetched in
+//! windows and compared with the outputs the chain holds, the mask key of an output against its commitment less its
+//! value, its script key as is or bound to the commitment, its sender offset key as is. A branch is scanned until
+//! `gap_limit` indices in a row after the last used one match nothing, the gap a wallet leaves at most between outputs
+//! that made it on chain. The store is then moved past every index found, and the outputs whose mask key was found are
+//! the funds the device can spend.
+//!
+//! The host cannot see the chain, so the outputs come from a [`BlockchainBackend`]. [`ChainDataFile`] reads a base node
+//! or block explorer's export, in the JSON layout [`parse_chain_data`] reads.
+
+use std::{fmt, path::PathBuf};
+
+use serde::Deserialize;
+use serde_json::json;
+use tari_crypto::{
+    ristretto::{pedersen::PedersenCommitment, RistrettoPublicKey},
+    tari_utilities::{
+        hex::{from_hex, to_hex},
+        ByteArray,
+    },
+};
+
+use crate::{
+    device::{DerivationVersion, KeyBranch, LedgerDevice},
+    errors::{RecoveryError, StoreError},
+    export::{branch_path, export_public_keys_with_version},
+    nonce_pool::public_excess,
+    redact::short_hex,
+    script_keys::bound_script_public_key,
+    state_store::LedgerStateStore,
+};
+
+/// The version of the chain data format this module understands
+pub const RECOVERY_FORMAT_VERSION: u64 = 1;
+/// How many unused indices in a row end the scan of a branch by default
+pub const DEFAULT_GAP_LIMIT: u32 = 20;
+/// The branches whose keys end up on chain, and so can be recovered
+pub const RECOVERY_BRANCHES: [KeyBranch; 3] =
+    [KeyBranch::CommitmentMask, KeyBranch::ScriptKey, KeyBranch::SenderOffset];
+
+#[derive(Deserialize)]
+struct ChainDataJson {
+    version: u64,
+    #[serde(default)]
+    tip_height: u64,
+    outputs: Vec<ChainOutputJson>,
+}
+
+#[derive(Deserialize)]
+struct ChainOutputJson {
+    commitment: String,
+    /// Known for outputs the wallet rewound or was told the value of
+    #[serde(default)]
+    value: Option<u64>,
+    #[serde(default)]
+    script_public_key: Option<String>,
+    #[serde(default)]
+    sender_offset_public_key: Option<String>,
+    #[serde(default)]
+    maturity: u64,
+}
+
+/// An unspent output on chain
+#[derive(Clone, Debug)]
+pub struct ChainOutput {
+    pub commitment: PedersenCommitment,
+    pub value: Option<u64>,
+    pub script_public_key: Option<RistrettoPublicKey>,
+    pub sender_offset_public_key: Option<RistrettoPublicKey>,
+    /// The block height from which the output can be spent, 0 unless it is time locked
+    pub maturity: u64,
+}
+
+/// The unspent outputs on chain at `tip_height`
+#[derive(Clone, Debug, Default)]
+pub struct ChainData {
+    pub tip_height: u64,
+    pub outputs: Vec<ChainOutput>,
+}
+
+/// Read an export of the unspent outputs on chain
+pub fn parse_chain_data(json: &str) -> Result<ChainData, RecoveryError> {
+    let data: ChainDataJson = serde_json::from_str(json).map_err(|e| RecoveryError::Parse(e.to_string()))?;
+    if data.version != RECOVERY_FORMAT_VERSION {
+        return Err(RecoveryError::UnsupportedVersion(data.version));
+    }
+    let public_key = |hex: &Option<String>| {
+        hex.as_ref()
+            .map(|hex| {
+                from_hex(hex)
+                    .ok()
+                    .and_then(|bytes| RistrettoPublicKey::from_bytes(&bytes).ok())
+                    .ok_or_else(|| RecoveryError::Parse(format!("'{}' is not a hex encoded public key", hex)))
+            })
+            .transpose()
+    };
+    let outputs = data
+        .outputs
+        .iter()
+        .map(|output| {
+            let commitment = from_hex(&output.commitment)
+                .ok()
+                .and_then(|bytes| PedersenCommitment::from_bytes(&bytes).ok())
+                .ok_or_else(|| {
+                    RecoveryError::Parse(format!("'{}' is not a hex encoded commitment", output.commitment))
+                })?;
+            Ok(ChainOutput {
+                commitment,
+                value: output.value,
+                script_public_key: public_key(&output.script_public_key)?,
+                sender_offset_public_key: public_key(&output.sender_offset_public_key)?,
+                maturity: output.maturity,
+            })
+        })
+        .collect::<Result<Vec<_>, RecoveryError>>()?;
+    Ok(ChainData {
+        tip_height: data.tip_height,
+        outputs,
+    })
+}
+
+/// A source of the unspent outputs on chain
+pub trait BlockchainBackend {
+    /// The unspent outputs on chain at the tip the source knows
+    fn chain_data(&self) -> Result<ChainData, RecoveryError>;
+}
+
+impl BlockchainBackend for ChainData {
+    fn chain_data(&self) -> Result<ChainData, RecoveryError> {
+        Ok(self.clone())
+    }
+}
+
+/// An export of the unspent outputs on chain, in the layout [`parse_chain_data`] reads, read anew on every call
+#[derive(Clone, Debug)]
+pub struct ChainDataFile {
+    path: PathBuf,
+}
+
+impl ChainDataFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl BlockchainBackend for ChainDataFile {
+    fn chain_data(&self) -> Result<ChainData, RecoveryError> {
+        parse_chain_data(&std::fs::read_to_string(&self.path).map_err(RecoveryError::Io)?)
+    }
+}
+
+/// The indices of a branch found on chain
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BranchRecovery {
+    pub branch: KeyBranch,
+    /// In ascending order, with the public key of each
+    pub used: Vec<(u32, RistrettoPublicKey)>,
+    /// The number of indices scanned, from 0
+    pub scanned: u32,
+}
+
+impl BranchRecovery {
+    /// The index the branch continues at, past the last one used
+    pub fn next_index(&self) -> u32 {
+        self.used.last().map_or(0, |(index, _)| index + 1)
+    }
+}
+
+/// An output on chain with a key of the wallet
+#[derive(Clone, Debug)]
+pub struct RecoveredOutput {
+    pub commitment: PedersenCommitment,
+    pub value: Option<u64>,
+    /// Only set when the value is known, the mask key cannot be found without it
+    pub mask_index: Option<u32>,
+    pub script_key_index: Option<u32>,
+    pub sender_offset_index: Option<u32>,
+    pub maturity: u64,
+}
+
+impl RecoveredOutput {
+    /// Whether the device can spend the output, which takes its mask key
+    pub fn is_spendable(&self) -> bool {
+        self.mask_index.is_some()
+    }
+}
+
+impl fmt::Display for RecoveredOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let index = |index: Option<u32>| index.map_or_else(|| "-".to_string(), |index| index.to_string());
+        match self.value {
+            Some(value) => write!(f, "{} uT", value)?,
+            None => write!(f, "unknown value")?,
+        }
+        write!(
+            f,
+            ", commitment {}, mask {}, script key {}, sender offset {}",
+            short_hex(self.commitment.as_bytes()),
+            index(self.mask_index),
+            index(self.script_key_index),
+            index(self.sender_offset_index)
+        )
+    }
+}
+
+/// What a scan found of the wallet on chain
+#[derive(Clone, Debug)]
+pub struct Recovery {
+    pub account: u32,
+    pub branches: Vec<BranchRecovery>,
+    pub outputs: Vec<RecoveredOutput>,
+    /// The value of the spendable outputs, in microTari, spendable now
+    pub available: u64,
+    /// The value of the spendable outputs that mature above the tip height
+    pub time_locked: u64,
+}
+
+impl Recovery {
+    pub fn to_json(&self) -> String {
+        let branches = self
+            .branches
+            .iter()
+            .map(|branch| {
+                json!({
+                    "branch": branch.branch.name(),
+                    "path": branch_path(self.account, branch.branch),
+                    "scanned": branch.scanned,
+                    "used": branch.used.iter().map(|(index, _)| index).collect::<Vec<_>>(),
+                    "next_index": branch.next_index(),
+                })
+            })
+            .collect::<Vec<_>>();
+        let outputs = self
+            .outputs
+            .iter()
+            .map(|output| {
+                json!({
+                    "commitment": to_hex(output.commitment.as_bytes()),
+                    "value": output.value,
+                    "mask_index": output.mask_index,
+                    "script_key_index": output.script_key_index,
+                    "sender_offset_index": output.sender_offset_index,
+                    "maturity": output.maturity,
+                })
+            })
+            .collect::<Vec<_>>();
+        let recovery = json!({
+            "account": self.account,
+            "branches": branches,
+            "outputs": outputs,
+            "available": self.available,
+            "time_locked": self.time_locked,
+        });
+        serde_json::to_string_pretty(&recovery).expect("a JSON value always serializes")
+    }
+}
+
+impl fmt::Display for Recovery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for branch in &self.branches {
+            writeln!(
+                f,
+                "{}: {} used of {} scanned, next index {}",
+                branch.branch.name(),
+                branch.used.len(),
+                branch.scanned,
+                branch.next_index()
+            )?;
+        }
+        for output in &self.outputs {
+            writeln!(f, "{}", output)?;
+        }
+        writeln!(f, "available:   {} uT", self.available)?;
+        write!(f, "time locked: {} uT", self.time_locked)
+    }
+}
+
+/// Scan the branches of [`RECOVERY_BRANCHES`] of `account`, derived under `version`, against the outputs of `chain`,
+/// until `gap_limit` indices in a row after the last used one match none of them. `progress` is called with the branch
+/// being scanned and the number of its indices scanned so far.
+pub fn recover<P: FnMut(KeyBranch, u32)>(
+    device: &LedgerDevice,
+    account: u32,
+    chain: &ChainData,
+    gap_limit: u32,
+    version: DerivationVersion,
+    mut progress: P,
+) -> Result<Recovery, RecoveryError> {
+    if gap_limit == 0 {
+        return Err(RecoveryError::InvalidGapLimit);
+    }
+    // The mask key of an output is its commitment less its value, known only for outputs with a value
+    let mask_keys = chain
+        .outputs
+        .iter()
+        .map(|output| {
+            output
+                .value
+                .map(|value| public_excess(&[(output.commitment.clone(), value)], &[]))
+        })
+        .collect::<Vec<_>>();
+    let mut outputs = chain
+        .outputs
+        .iter()
+        .map(|output| RecoveredOutput {
+            commitment: output.commitment.clone(),
+            value: output.value,
+            mask_index: None,
+            script_key_index: None,
+            sender_offset_index: None,
+            maturity: output.maturity,
+        })
+        .collect::<Vec<_>>();
+    let mut branches = Vec::with_capacity(RECOVERY_BRANCHES.len());
+    for branch in RECOVERY_BRANCHES {
+        let mut recovered = BranchRecovery {
+            branch,
+            used: Vec::new(),
+            scanned: 0,
+        };
+        // Key indices stay below the hardened range
+        let mut end = gap_limit.min(0x8000_0000);
+        while recovered.scanned < end {
+            let export =
+                export_public_keys_with_version(device, account, branch, recovered.scanned..end, version, |_, _| {})?;
+            for key in export.keys {
+                let mut used = false;
+                for (position, (output, recovered_output)) in chain.outputs.iter().zip(&mut outputs).enumerate() {
+                    let found = match branch {
+                        KeyBranch::CommitmentMask => mask_keys[position].as_ref() == Some(&key.public_key),
+                        KeyBranch::ScriptKey => output.script_public_key.as_ref().map_or(false, |script_key| {
+                            *script_key == key.public_key ||
+                                *script_key == bound_script_public_key(&key.public_key, &output.commitment)
+                        }),
+                        _ => output.sender_offset_public_key.as_ref() == Some(&key.public_key),
+                    };
+                    if found {
+                        let index = match branch {
+                            KeyBranch::CommitmentMask => &mut recovered_output.mask_index,
+                            KeyBranch::ScriptKey => &mut recovered_output.script_key_index,
+                            _ => &mut recovered_output.sender_offset_index,
+                        };
+                        *index = Some(key.index);
+                        used = true;
+                    }
+                }
+                if used {
+                    recovered.used.push((key.index, key.public_key));
+                }
+            }
+            recovered.scanned = end;
+            progress(branch, recovered.scanned);
+            end = recovered.next_index().saturating_add(gap_limit).min(0x8000_0000);
+        }
+        branches.push(recovered);
+    }
+    let outputs = outputs
+        .into_iter()
+        .filter(|output| {
+            output.mask_index.is_some() || output.script_key_index.is_some() || output.sender_offset_index.is_some()
+        })
+        .collect::<Vec<_>>();
+    let (mut available, mut time_locked) = (0u64, 0u64);
+    for output in outputs.iter().filter(|output| output.is_spendable()) {
+        let value = output.value.unwrap_or_default();
+        if output.maturity > chain.tip_height {
+            time_locked = time_locked.saturating_add(value);
+        } else {
+            available = available.saturating_add(value);
+        }
+    }
+    Ok(Recovery {
+        account,
+        branches,
+        outputs,
+        available,
+        time_locked,
+    })
+}
+
+/// Move the key index of every branch of `recovery` in `store` past the last index used, and cache the public keys of
+/// the indices used. An index the store is already past is left as it is.
+pub fn restore_state(store: &dyn LedgerStateStore, recovery: &Recovery) -> Result<(), StoreError> {
+    for branch in &recovery.branches {
+        let path = branch_path(recovery.account, branch.branch);
+        for (index, public_key) in &branch.used {
+            let mut bytes = [0u8; 32];
+            bytes.copy_from_slice(public_key.as_bytes());
+            store.cache_public_key(&path, u64::from(*index), &bytes)?;
+        }
+        let next_index = u64::from(branch.next_index());
+        if store.key_index(&path)? < next_index {
+            store.set_key_index(&path, next_index)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn the_file_backend_reads_the_export() {
+        let commitment = PedersenCommitment::default();
+        let path = std::env::temp_dir().join(format!("tari-ledger-chain-data-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            json!({
+                "version": RECOVERY_FORMAT_VERSION,
+                "tip_height": 12,
+                "outputs": [{ "commitment": to_hex(commitment.as_bytes()), "value": 5, "maturity": 3 }],
+            })
+            .to_string(),
+        )
+        .unwrap();
+        let chain = ChainDataFile::new(&path).chain_data();
+        std::fs::remove_file(&path).unwrap();
+        let chain = chain.unwrap();
+        assert_eq!(chain.tip_height, 12);
+        assert_eq!(chain.outputs.len(), 1);
+        assert_eq!(chain.outputs[0].commitment, commitment);
+        assert_eq!(chain.outputs[0].value, Some(5));
+        assert_eq!(chain.outputs[0].maturity, 3);
+        assert!(matches!(
+            ChainDataFile::new(&path).chain_data(),
+            Err(RecoveryError::Io(_))
+        ));
+    }
+}
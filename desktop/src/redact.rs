@@ -0,0 +1,30 @@
+//! Log safe renderings of keys, hashes and signatures
+//! The `Display` of a type that holds keys, commitments or signatures shows each of them as [`short_hex`], enough to
+//! tell them apart in a log line without filling it. `Debug` shows [`Redacted`] in place of anything that would help
+//! to spend funds or to impersonate the host, e.g. a session key, a pairing secret or a swap preimage, so that
+//! wallets can log these types as they are.
+
+use std::fmt;
+
+use tari_crypto::tari_utilities::hex::to_hex;
+
+/// Values up to this many bytes are shown in full by [`short_hex`]
+const SHORT_HEX_BYTES: usize = 8;
+
+/// `bytes` as hex, with everything but the first and last four bytes left out of longer values
+pub fn short_hex(bytes: &[u8]) -> String {
+    if bytes.len() <= SHORT_HEX_BYTES {
+        return to_hex(bytes);
+    }
+    let half = SHORT_HEX_BYTES / 2;
+    format!("{}..{}", to_hex(&bytes[..half]), to_hex(&bytes[bytes.len() - half..]))
+}
+
+/// Stands in for a secret field in a `Debug` implementation
+pub struct Redacted;
+
+impl fmt::Debug for Redacted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<redacted>")
+    }
+}
@@ -0,0 +1,397 @@
+//! Remote wallet frontends
+//! A wallet frontend in a browser or on another machine cannot reach the USB stack of the host the device is plugged
+//! into. [`serve_remote`] keeps the device open as the [`daemon`](crate::daemon) does and passes it the APDUs of a
+//! frontend that connects over TCP, but only of one that holds the secret of the [`PairingCode`] the user handed it
+//! out of band, as a QR code or a string to paste. Both sides prove they hold the secret and agree a key for the
+//! connection under it, and every APDU and answer then travels encrypted and authenticated with ChaCha20-Poly1305, so
+//! nothing on the network between them can read, change, replay or reorder it.
+//!
+//! Whoever runs the daemon confirms every command that [`needs_confirmation`] before it reaches the device, and the
+//! device then asks for its own confirmation as it always does. A command refused on the host is answered with
+//! `SW_USER_REJECTED` without the device seeing it.
+//!
+//! A connection starts with the frontend sending `[hello 32][REMOTE_PROOF_LABEL(secret, hello)]` and the daemon
+//! answering `[hello 32][REMOTE_RESPONSE_LABEL(secret, frontend hello, daemon hello)]`, each hello fresh random bytes,
+//! and the key of the connection is `REMOTE_KEY_LABEL(secret, frontend hello, daemon hello)`. Frames follow, the length
+//! of the ciphertext as a big-endian `u32` and the ciphertext, under the nonce `[direction][0; 3][counter u64 LE]`
+//! with direction 0 from the frontend, 1 from the daemon, and the counter of the frames sent that way before. A
+//! frontend frame holds a serialized APDU, a daemon frame the data of its answer followed by the status word.
+
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    str::FromStr,
+    sync::Mutex,
+    time::Duration,
+};
+
+use chacha20poly1305::{aead::Aead, ChaCha20Poly1305, Key, KeyInit, Nonce};
+use ledger_transport::{APDUAnswer, APDUCommand};
+use rand::{rngs::OsRng, RngCore};
+use tari_crypto::tari_utilities::hex::{from_hex, to_hex};
+use tari_ledger_protocol::SW_USER_REJECTED;
+
+pub use crate::hooks::needs_confirmation;
+use crate::{
+    device::LedgerDevice,
+    domains::{REMOTE_KEY_LABEL, REMOTE_PROOF_LABEL, REMOTE_RESPONSE_LABEL},
+    errors::DeviceError,
+    hashing::{DomainSeparatedConsensusHasher, TransactionHashDomain},
+    redact::Redacted,
+    transport::LedgerTransport,
+};
+
+/// The scheme a pairing code starts with
+pub const PAIRING_CODE_SCHEME: &str = "tari-ledger-pair";
+/// How long a frontend has to prove it was paired
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+/// The longest frame, an APDU or an answer with the tag of the cipher
+const MAX_FRAME_LENGTH: usize = 512;
+const DIRECTION_FRONTEND: u8 = 0x00;
+const DIRECTION_DAEMON: u8 = 0x01;
+
+/// Where a frontend finds the daemon, and the secret it proves it was paired with
+#[derive(Clone, PartialEq, Eq)]
+pub struct PairingCode {
+    pub address: SocketAddr,
+    secret: [u8; 32],
+}
+
+impl PairingCode {
+    /// A code with a fresh secret for the daemon listening on `address`
+    pub fn generate(address: SocketAddr) -> Self {
+        let mut secret = [0u8; 32];
+        OsRng.fill_bytes(&mut secret);
+        Self { address, secret }
+    }
+
+    fn proof(&self, frontend_hello: &[u8; 32]) -> [u8; 32] {
+        DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(REMOTE_PROOF_LABEL)
+            .chain_fixed(&self.secret)
+            .chain_fixed(frontend_hello)
+            .finalize()
+    }
+
+    fn response(&self, frontend_hello: &[u8; 32], daemon_hello: &[u8; 32]) -> [u8; 32] {
+        DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(REMOTE_RESPONSE_LABEL)
+            .chain_fixed(&self.secret)
+            .chain_fixed(frontend_hello)
+            .chain_fixed(daemon_hello)
+            .finalize()
+    }
+
+    fn key(&self, frontend_hello: &[u8; 32], daemon_hello: &[u8; 32]) -> [u8; 32] {
+        DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(REMOTE_KEY_LABEL)
+            .chain_fixed(&self.secret)
+            .chain_fixed(frontend_hello)
+            .chain_fixed(daemon_hello)
+            .finalize()
+    }
+}
+
+impl fmt::Debug for PairingCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PairingCode")
+            .field("address", &self.address)
+            .field("secret", &Redacted)
+            .finish()
+    }
+}
+
+/// `tari-ledger-pair:<address>?secret=<hex>`, to show as a QR code or paste into the frontend
+impl fmt::Display for PairingCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}?secret={}",
+            PAIRING_CODE_SCHEME,
+            self.address,
+            to_hex(&self.secret)
+        )
+    }
+}
+
+impl FromStr for PairingCode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let code = s
+            .strip_prefix(PAIRING_CODE_SCHEME)
+            .and_then(|code| code.strip_prefix(':'))
+            .ok_or_else(|| format!("a pairing code starts with '{}:'", PAIRING_CODE_SCHEME))?;
+        let (address, secret) = code
+            .split_once("?secret=")
+            .ok_or_else(|| "the pairing code has no secret".to_string())?;
+        let address = address
+            .parse()
+            .map_err(|_| format!("'{}' is not an address and port", address))?;
+        let secret = from_hex(secret)
+            .ok()
+            .and_then(|secret| <[u8; 32]>::try_from(secret).ok())
+            .ok_or_else(|| "the secret of a pairing code is 32 bytes in hex".to_string())?;
+        Ok(Self { address, secret })
+    }
+}
+
+/// One side of an encrypted connection
+struct RemoteChannel {
+    cipher: ChaCha20Poly1305,
+    direction: u8,
+    sent: u64,
+    received: u64,
+}
+
+impl RemoteChannel {
+    /// The side that sends in `direction`
+    fn new(key: &[u8; 32], direction: u8) -> Self {
+        Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(key)),
+            direction,
+            sent: 0,
+            received: 0,
+        }
+    }
+
+    fn send(&mut self, stream: &mut impl Write, plaintext: &[u8]) -> io::Result<()> {
+        let ciphertext = self
+            .cipher
+            .encrypt(&frame_nonce(self.direction, self.sent), plaintext)
+            .map_err(|_| invalid_data("the frame could not be encrypted"))?;
+        self.sent += 1;
+        stream.write_all(&(ciphertext.len() as u32).to_be_bytes())?;
+        stream.write_all(&ciphertext)
+    }
+
+    /// The next frame of the other side, failing if it was changed or is not the one that comes next
+    fn receive(&mut self, stream: &mut impl Read) -> io::Result<Vec<u8>> {
+        let mut length = [0u8; 4];
+        stream.read_exact(&mut length)?;
+        let length = u32::from_be_bytes(length) as usize;
+        if length > MAX_FRAME_LENGTH {
+            return Err(invalid_data("the frame is longer than an APDU"));
+        }
+        let mut ciphertext = vec![0u8; length];
+        stream.read_exact(&mut ciphertext)?;
+        let plaintext = self
+            .cipher
+            .decrypt(&frame_nonce(self.direction ^ 1, self.received), ciphertext.as_slice())
+            .map_err(|_| invalid_data("the frame does not authenticate"))?;
+        self.received += 1;
+        Ok(plaintext)
+    }
+}
+
+fn frame_nonce(direction: u8, counter: u64) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[0] = direction;
+    nonce[4..].copy_from_slice(&counter.to_le_bytes());
+    *Nonce::from_slice(&nonce)
+}
+
+fn invalid_data(reason: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+/// A device a daemon serves to this frontend
+pub struct TransportRemote {
+    connection: Mutex<(TcpStream, RemoteChannel)>,
+}
+
+impl TransportRemote {
+    /// The device of the daemon of `code`, once both have proven they hold its secret
+    pub fn connect(code: &PairingCode) -> Result<LedgerDevice, DeviceError> {
+        let mut stream = TcpStream::connect(code.address)?;
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        let mut hello = [0u8; 32];
+        OsRng.fill_bytes(&mut hello);
+        stream.write_all(&hello)?;
+        stream.write_all(&code.proof(&hello))?;
+        let mut daemon_hello = [0u8; 32];
+        stream.read_exact(&mut daemon_hello)?;
+        let mut response = [0u8; 32];
+        stream.read_exact(&mut response)?;
+        if response != code.response(&hello, &daemon_hello) {
+            return Err(DeviceError::RemoteNotPaired);
+        }
+        // The user may take minutes to confirm on the host and on the device
+        stream.set_read_timeout(None)?;
+        let channel = RemoteChannel::new(&code.key(&hello, &daemon_hello), DIRECTION_FRONTEND);
+        Ok(LedgerDevice::from_transport(Self {
+            connection: Mutex::new((stream, channel)),
+        }))
+    }
+}
+
+impl LedgerTransport for TransportRemote {
+    fn exchange(&self, command: &APDUCommand<Vec<u8>>) -> Result<APDUAnswer<Vec<u8>>, DeviceError> {
+        let mut connection = self.connection.lock().expect("the remote connection is never poisoned");
+        let (stream, channel) = &mut *connection;
+        channel.send(stream, &command.serialize())?;
+        let answer = channel.receive(stream)?;
+        APDUAnswer::from_answer(answer).map_err(|_| DeviceError::InvalidResponse("the answer has no status word"))
+    }
+}
+
+/// Serve every frontend that connects to `listener` with the secret of `code` in turn, with the device `open`
+/// returns, keeping it open from one connection to the next. `confirm` is asked about every command that
+/// [`needs_confirmation`], and `on_error` is told why a frontend was turned away or the device was dropped.
+pub fn serve_remote(
+    listener: &TcpListener,
+    code: &PairingCode,
+    mut open: impl FnMut() -> Result<LedgerDevice, DeviceError>,
+    mut confirm: impl FnMut(&APDUCommand<Vec<u8>>) -> bool,
+    mut on_error: impl FnMut(&DeviceError),
+) -> io::Result<()> {
+    let mut device = None;
+    for stream in listener.incoming() {
+        let mut stream = stream?;
+        let mut channel = match accept(&mut stream, code) {
+            Ok(channel) => channel,
+            Err(e) => {
+                on_error(&e);
+                continue;
+            },
+        };
+        if device.is_none() {
+            match open() {
+                Ok(opened) => device = Some(opened),
+                Err(e) => {
+                    on_error(&e);
+                    continue;
+                },
+            }
+        }
+        let opened = device.as_ref().expect("the device was opened above");
+        if let Err(e) = serve_frontend(opened, &mut stream, &mut channel, &mut confirm) {
+            on_error(&e);
+            device = None;
+        }
+    }
+    Ok(())
+}
+
+/// Check that the frontend on `stream` holds the secret of `code`, and prove back that the daemon does
+fn accept(stream: &mut TcpStream, code: &PairingCode) -> Result<RemoteChannel, DeviceError> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let mut hello = [0u8; 32];
+    stream.read_exact(&mut hello)?;
+    let mut proof = [0u8; 32];
+    stream.read_exact(&mut proof)?;
+    if proof != code.proof(&hello) {
+        return Err(DeviceError::RemoteNotPaired);
+    }
+    let mut daemon_hello = [0u8; 32];
+    OsRng.fill_bytes(&mut daemon_hello);
+    stream.write_all(&daemon_hello)?;
+    stream.write_all(&code.response(&hello, &daemon_hello))?;
+    stream.set_read_timeout(None)?;
+    Ok(RemoteChannel::new(&code.key(&hello, &daemon_hello), DIRECTION_DAEMON))
+}
+
+/// Pass the commands of one frontend to `device` until it hangs up. Only a failed exchange with the device is an
+/// error, a connection that breaks off or sends a frame that does not authenticate just ends.
+fn serve_frontend(
+    device: &LedgerDevice,
+    stream: &mut TcpStream,
+    channel: &mut RemoteChannel,
+    confirm: &mut impl FnMut(&APDUCommand<Vec<u8>>) -> bool,
+) -> Result<(), DeviceError> {
+    loop {
+        let Some(command) = channel.receive(stream).ok().and_then(|frame| parse_command(&frame)) else {
+            return Ok(());
+        };
+        let answer = if !needs_confirmation(&command) || confirm(&command) {
+            let answer = device.transport().exchange(&command)?;
+            [answer.data(), &answer.retcode().to_be_bytes()].concat()
+        } else {
+            SW_USER_REJECTED.to_be_bytes().to_vec()
+        };
+        if channel.send(stream, &answer).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+/// A command as [`APDUCommand::serialize`] writes it
+fn parse_command(apdu: &[u8]) -> Option<APDUCommand<Vec<u8>>> {
+    match apdu {
+        [cla, ins, p1, p2, data_length, data @ ..] if usize::from(*data_length) == data.len() => Some(APDUCommand {
+            cla: *cla,
+            ins: *ins,
+            p1: *p1,
+            p2: *p2,
+            data: data.to_vec(),
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn code() -> PairingCode {
+        PairingCode {
+            address: "127.0.0.1:9999".parse().unwrap(),
+            secret: [7; 32],
+        }
+    }
+
+    #[test]
+    fn pairing_codes_round_trip() {
+        let code = code();
+        assert_eq!(code.to_string().parse::<PairingCode>(), Ok(code));
+        assert!("tari-ledger-pair:127.0.0.1:9999".parse::<PairingCode>().is_err());
+        assert!("tari-ledger-pair:127.0.0.1:9999?secret=0707"
+            .parse::<PairingCode>()
+            .is_err());
+        assert!(!format!("{:?}", code()).contains("0707"));
+    }
+
+    #[test]
+    fn frames_reach_the_other_side_once_and_in_order() {
+        let key = code().key(&[1; 32], &[2; 32]);
+        let mut frontend = RemoteChannel::new(&key, DIRECTION_FRONTEND);
+        let mut daemon = RemoteChannel::new(&key, DIRECTION_DAEMON);
+        let mut wire = Vec::new();
+        frontend.send(&mut wire, b"first").unwrap();
+        frontend.send(&mut wire, b"second").unwrap();
+        let mut reader = wire.as_slice();
+        assert_eq!(daemon.receive(&mut reader).unwrap(), b"first");
+        assert_eq!(daemon.receive(&mut reader).unwrap(), b"second");
+        // A replayed frame is not the one that comes next
+        assert!(daemon.receive(&mut wire.as_slice()).is_err());
+    }
+
+    #[test]
+    fn changed_frames_do_not_authenticate() {
+        let key = code().key(&[1; 32], &[2; 32]);
+        let mut frontend = RemoteChannel::new(&key, DIRECTION_FRONTEND);
+        let mut daemon = RemoteChannel::new(&key, DIRECTION_DAEMON);
+        let mut wire = Vec::new();
+        frontend.send(&mut wire, b"frame").unwrap();
+        *wire.last_mut().unwrap() ^= 1;
+        assert!(daemon.receive(&mut wire.as_slice()).is_err());
+        // Nor does a frame sent back in the direction it came from
+        let mut wire = Vec::new();
+        frontend.send(&mut wire, b"frame").unwrap();
+        assert!(RemoteChannel::new(&key, DIRECTION_FRONTEND)
+            .receive(&mut wire.as_slice())
+            .is_err());
+    }
+
+    #[test]
+    fn commands_parse_as_they_serialize() {
+        let command = APDUCommand {
+            cla: 0x80,
+            ins: 0x02,
+            p1: 0x01,
+            p2: 0x00,
+            data: vec![1, 2, 3],
+        };
+        let parsed = parse_command(&command.serialize()).unwrap();
+        assert_eq!((parsed.ins, parsed.p1, parsed.data), (0x02, 0x01, vec![1, 2, 3]));
+        assert!(parse_command(&[0x80, 0x02, 0x01, 0x00, 0x04, 1]).is_none());
+    }
+}
@@ -0,0 +1,381 @@
+//! A REST facade over the daemon
+//! Dashboards and scripts that speak HTTP and JSON rather than the [`daemon`]'s socket protocol reach the device
+//! through [`serve_rest`]: every request connects to the daemon, so it goes through the same approval hooks and
+//! deadline as an invocation, and is refused with `503` while the daemon is busy with another one. Every route is
+//! listed in [`ROUTES`], from which both the router and the OpenAPI description at `/openapi.json` are built, so the
+//! description cannot document a route that is not served or miss one that is. The schemas in the description are
+//! derived from the types the handlers answer with, so they cannot drift from what is served either.
+//!
+//! Anyone who can reach the port could otherwise talk to the device as the owner of the daemon's socket, so every route
+//! under `/v1` needs the token of a client of the daemon as `Authorization: Bearer <token>`. The facade passes it on,
+//! and the daemon holds the request to the instruction classes of that client, see [`permissions`]. Only the typed
+//! routes below are served, raw APDUs stay on the socket.
+
+use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, State},
+    http::{header::AUTHORIZATION, request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, MethodRouter},
+    Json,
+    Router,
+};
+use schemars::{
+    gen::{SchemaGenerator, SchemaSettings},
+    schema::Schema,
+    JsonSchema,
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::{
+    daemon,
+    device::{Capabilities, LedgerDevice},
+    errors::DeviceError,
+    permissions,
+};
+
+/// The version of the API, the first segment of every route but the description
+pub const REST_API_VERSION: &str = "v1";
+
+/// What a route does
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endpoint {
+    Device,
+    App,
+    Counters,
+}
+
+/// How a route is called and what it answers, as the OpenAPI description lists it
+#[derive(Clone, Copy, Debug)]
+pub struct RouteSpec {
+    pub endpoint: Endpoint,
+    pub path: &'static str,
+    pub summary: &'static str,
+    /// The schema of the type the handler answers with, added to the generator's definitions
+    pub response: fn(&mut SchemaGenerator) -> Schema,
+}
+
+/// Every route under `/v1`, each a `get`
+pub const ROUTES: [RouteSpec; 3] = [
+    RouteSpec {
+        endpoint: Endpoint::Device,
+        path: "/v1/device",
+        summary: "The model and serial number of the device the daemon holds open, and whether the Tari app answers",
+        response: SchemaGenerator::subschema_for::<DeviceInfo>,
+    },
+    RouteSpec {
+        endpoint: Endpoint::App,
+        path: "/v1/app",
+        summary: "The version of the Tari app, the oldest client it accepts and its capabilities",
+        response: SchemaGenerator::subschema_for::<AppInfo>,
+    },
+    RouteSpec {
+        endpoint: Endpoint::Counters,
+        path: "/v1/counters",
+        summary: "How many signatures and output signatures the app has made",
+        response: SchemaGenerator::subschema_for::<Counters>,
+    },
+];
+
+/// The device the daemon holds open
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct DeviceInfo {
+    /// The model, as Speculos names it, if the transport knows it
+    pub model: Option<String>,
+    /// The serial number, if the transport knows it
+    pub device_id: Option<String>,
+    /// Whether the Tari app answers, see `DeviceState`
+    pub state: String,
+}
+
+/// The Tari app on the device
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct AppInfo {
+    pub version: String,
+    /// The oldest client version the app accepts
+    pub min_client_version: String,
+    /// The names of the capabilities the app has
+    pub capabilities: Vec<String>,
+}
+
+/// The app's signature counters
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Counters {
+    /// Every signature the app has made
+    pub signing: u64,
+    /// The output signatures among them
+    pub output: u64,
+}
+
+/// Why a request failed
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Failure {
+    pub error: String,
+}
+
+/// What every request is served with
+struct RestState {
+    socket: PathBuf,
+    deadline: Option<Duration>,
+}
+
+/// The token of the client of the daemon a request is sent for, from `Authorization: Bearer <token>`
+struct ClientToken(String);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientToken {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .filter(|token| !token.is_empty())
+            .map(|token| ClientToken(token.to_string()))
+            .ok_or_else(|| {
+                failure(
+                    StatusCode::UNAUTHORIZED,
+                    "the request needs the token of a client of the daemon",
+                )
+            })
+    }
+}
+
+/// Serve the REST facade on `listen` until the process ends, passing every request to the daemon on `socket` with
+/// `deadline`
+pub async fn serve_rest(listen: SocketAddr, socket: PathBuf, deadline: Option<Duration>) -> Result<(), String> {
+    let state = Arc::new(RestState { socket, deadline });
+    axum::Server::try_bind(&listen)
+        .map_err(|e| e.to_string())?
+        .serve(router(state).into_make_service())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+fn router(state: Arc<RestState>) -> Router {
+    ROUTES
+        .iter()
+        .fold(Router::new(), |router, route| {
+            router.route(route.path, method_router(route.endpoint))
+        })
+        // The description is public, it is generated from the code anyone can read
+        .route("/openapi.json", get(|| async { Json(openapi()) }))
+        .with_state(state)
+}
+
+fn method_router(endpoint: Endpoint) -> MethodRouter<Arc<RestState>> {
+    match endpoint {
+        Endpoint::Device => get(device_info),
+        Endpoint::App => get(app_info),
+        Endpoint::Counters => get(counters),
+    }
+}
+
+async fn device_info(State(state): State<Arc<RestState>>, ClientToken(token): ClientToken) -> Response {
+    with_device(&state, token, |device| {
+        Ok(DeviceInfo {
+            model: device.model().map(|model| model.speculos_name().to_string()),
+            device_id: device.device_id().map(str::to_string),
+            state: device.ping().to_string(),
+        })
+    })
+    .await
+}
+
+async fn app_info(State(state): State<Arc<RestState>>, ClientToken(token): ClientToken) -> Response {
+    with_device(&state, token, |device| {
+        let handshake = device.handshake()?;
+        let capabilities = device.capabilities()?;
+        Ok(AppInfo {
+            version: handshake.app_version.to_string(),
+            min_client_version: handshake.min_client_version.to_string(),
+            capabilities: Capabilities::NAMED
+                .iter()
+                .filter(|(capability, _)| capabilities.contains(*capability))
+                .map(|(_, name)| name.to_string())
+                .collect(),
+        })
+    })
+    .await
+}
+
+async fn counters(State(state): State<Arc<RestState>>, ClientToken(token): ClientToken) -> Response {
+    with_device(&state, token, |device| {
+        Ok(Counters {
+            signing: device.signing_counter()?,
+            output: device.output_counter()?,
+        })
+    })
+    .await
+}
+
+/// Connect to the daemon as the client with `token` and answer with what `request` makes of its device
+async fn with_device<T: Serialize + Send + 'static>(
+    state: &RestState,
+    token: String,
+    request: impl FnOnce(&LedgerDevice) -> Result<T, DeviceError> + Send + 'static,
+) -> Response {
+    let socket = state.socket.clone();
+    let deadline = state.deadline;
+    let served = tokio::task::spawn_blocking(move || {
+        let device = daemon::connect(&socket, deadline, Some(&token)).map_err(|e| match e {
+            DeviceError::ClientNotAuthorized => (StatusCode::UNAUTHORIZED, e.to_string()),
+            e => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!("the daemon is not running or busy: {}", e),
+            ),
+        })?;
+        request(&device).map_err(|e| (status_of(&e), e.to_string()))
+    })
+    .await;
+    match served {
+        Ok(Ok(value)) => Json(value).into_response(),
+        Ok(Err((status, reason))) => failure(status, &reason),
+        Err(_) => failure(StatusCode::INTERNAL_SERVER_ERROR, "the request failed"),
+    }
+}
+
+/// The HTTP status a request that failed with `error` is answered with
+fn status_of(error: &DeviceError) -> StatusCode {
+    match error {
+        DeviceError::ClientNotAuthorized => StatusCode::UNAUTHORIZED,
+        DeviceError::UserRejected | DeviceError::ClientNotPermitted | DeviceError::ApprovalRefused => {
+            StatusCode::FORBIDDEN
+        },
+        DeviceError::DeviceLocked => StatusCode::LOCKED,
+        DeviceError::Unsupported(_) | DeviceError::SettingDisabled(_) => StatusCode::CONFLICT,
+        DeviceError::DeadlineExceeded(_) => StatusCode::GATEWAY_TIMEOUT,
+        DeviceError::ClientQuotaExceeded => StatusCode::TOO_MANY_REQUESTS,
+        _ => StatusCode::BAD_GATEWAY,
+    }
+}
+
+fn failure(status: StatusCode, reason: &str) -> Response {
+    let failure = Failure {
+        error: reason.to_string(),
+    };
+    (status, Json(failure)).into_response()
+}
+
+/// The OpenAPI 3 description of every route in [`ROUTES`]
+pub fn openapi() -> Value {
+    let mut generator = SchemaSettings::openapi3().into_generator();
+    let error = to_value(generator.subschema_for::<Failure>());
+    let error_response = |description: &str| {
+        json!({
+            "description": description,
+            "content": { "application/json": { "schema": error } },
+        })
+    };
+    let mut paths = serde_json::Map::new();
+    for route in ROUTES {
+        let operation = json!({
+            "summary": route.summary,
+            "security": [{ "token": [] }],
+            "responses": {
+                "200": {
+                    "description": "OK",
+                    "content": { "application/json": { "schema": to_value((route.response)(&mut generator)) } },
+                },
+                "401": error_response("The token is missing or no client of the daemon has it"),
+                "403": error_response(
+                    "The command was refused by an approval hook or the user, or the client may not send it"
+                ),
+                "409": error_response("The app lacks the capability or setting the request needs"),
+                "423": error_response("The device is locked"),
+                "429": error_response("The client used up its quota of commands for now"),
+                "502": error_response("The device failed the request"),
+                "503": error_response("The daemon is not running or busy with another invocation"),
+                "504": error_response("The device did not answer within the deadline of the daemon"),
+            },
+        });
+        paths.insert(route.path.to_string(), json!({ "get": operation }));
+    }
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "tari-ledger REST facade",
+            "description": "The device held open by `tari-ledger daemon`, over HTTP",
+            "version": REST_API_VERSION,
+        },
+        "paths": paths,
+        "components": {
+            "securitySchemes": { "token": { "type": "http", "scheme": "bearer" } },
+            "schemas": to_value(generator.take_definitions()),
+        },
+    })
+}
+
+fn to_value<T: Serialize>(schema: T) -> Value {
+    serde_json::to_value(schema).expect("a schema always serializes")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// The component a `$ref` of the description points at
+    fn resolve<'a>(openapi: &'a Value, schema: &Value) -> &'a Value {
+        let name = schema["$ref"]
+            .as_str()
+            .and_then(|reference| reference.strip_prefix("#/components/schemas/"))
+            .expect("every schema is a reference to a component");
+        &openapi["components"]["schemas"][name]
+    }
+
+    #[test]
+    fn the_description_lists_every_route() {
+        let openapi = openapi();
+        for route in ROUTES {
+            let operation = &openapi["paths"][route.path]["get"];
+            assert_eq!(operation["summary"], route.summary);
+            let schema = &operation["responses"]["200"]["content"]["application/json"]["schema"];
+            assert!(resolve(&openapi, schema).is_object());
+            let error = &operation["responses"]["403"]["content"]["application/json"]["schema"];
+            assert!(resolve(&openapi, error)["properties"]["error"].is_object());
+            assert!(route.path.starts_with(&format!("/{}/", REST_API_VERSION)));
+        }
+        assert_eq!(openapi["paths"].as_object().unwrap().len(), ROUTES.len());
+    }
+
+    #[test]
+    fn the_schemas_follow_the_handler_types() {
+        let openapi = openapi();
+        let counters = to_value(Counters { signing: 3, output: 1 });
+        let schema = &openapi["components"]["schemas"]["Counters"];
+        for field in counters.as_object().unwrap().keys() {
+            assert!(schema["properties"][field].is_object(), "{} is not described", field);
+        }
+        let device = to_value(DeviceInfo {
+            model: None,
+            device_id: None,
+            state: "ready".to_string(),
+        });
+        let schema = &openapi["components"]["schemas"]["DeviceInfo"];
+        assert_eq!(
+            schema["properties"].as_object().unwrap().len(),
+            device.as_object().unwrap().len()
+        );
+        assert_eq!(schema["properties"]["model"]["nullable"], true);
+    }
+
+    #[test]
+    fn refusals_map_to_their_status() {
+        assert_eq!(status_of(&DeviceError::UserRejected), StatusCode::FORBIDDEN);
+        assert_eq!(status_of(&DeviceError::ClientNotPermitted), StatusCode::FORBIDDEN);
+        assert_eq!(
+            status_of(&DeviceError::ClientQuotaExceeded),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(
+            status_of(&DeviceError::DeadlineExceeded(Duration::from_secs(1))),
+            StatusCode::GATEWAY_TIMEOUT
+        );
+        assert_eq!(status_of(&DeviceError::Status(0x6d00)), StatusCode::BAD_GATEWAY);
+    }
+}
@@ -0,0 +1,19 @@
+//! The randomness of the host side
+//! The host draws the ephemeral keys of authenticated sessions, blinded key requests and exports, and the challenges it
+//! has the device sign. [`LedgerDevice`](crate::device::LedgerDevice) draws them from the operating system unless it
+//! is given another generator, e.g. a [`seeded`] one, so that a test against the emulator sends the same APDUs on
+//! every run and its transcript can be compared byte for byte. A seeded generator must never be used with a real
+//! wallet: anyone who knows the seed knows every ephemeral key.
+
+use rand::{CryptoRng, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+
+/// A cryptographically secure generator the host can draw its secrets from
+pub trait HostRng: RngCore + CryptoRng + Send {}
+
+impl<R: RngCore + CryptoRng + Send> HostRng for R {}
+
+/// A generator that yields the same stream for the same `seed`, for reproducible tests only
+pub fn seeded(seed: u64) -> ChaCha20Rng {
+    ChaCha20Rng::seed_from_u64(seed)
+}
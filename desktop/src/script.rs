@@ -0,0 +1,559 @@
+//! TariScript and execution stack encoding
+//! The byte encoding, enough to build scripts, parse them back and hash them into challenges the same way the base
+//! layer does. Scripts are executed by the [`interpreter`](crate::interpreter).
+
+use std::fmt;
+
+use borsh::{
+    maybestd::io::{self, Read, Write},
+    BorshDeserialize,
+    BorshSerialize,
+};
+use tari_crypto::{
+    ristretto::{pedersen::PedersenCommitment, RistrettoPublicKey, RistrettoSchnorr, RistrettoSecretKey},
+    tari_utilities::{hex::to_hex, ByteArray},
+};
+
+use crate::{
+    domains::SCRIPT_MESSAGE_LABEL,
+    errors::ScriptError,
+    hashing::{Challenge, DomainSeparatedConsensusHasher, TransactionHashDomain},
+};
+
+/// The scripts and stacks accepted by the base layer are limited in length
+pub const MAX_SCRIPT_BYTES: usize = 4096;
+pub const MAX_STACK_SIZE: usize = 255;
+/// The current `TransactionInputVersion`
+pub const TRANSACTION_INPUT_VERSION: u8 = 0;
+
+const OP_CHECK_HEIGHT_VERIFY: u8 = 0x66;
+const OP_CHECK_HEIGHT: u8 = 0x67;
+const OP_COMPARE_HEIGHT_VERIFY: u8 = 0x68;
+const OP_COMPARE_HEIGHT: u8 = 0x69;
+const OP_DROP: u8 = 0x70;
+const OP_DUP: u8 = 0x71;
+const OP_REV_ROT: u8 = 0x72;
+const OP_NOP: u8 = 0x73;
+const OP_PUSH_HASH: u8 = 0x7a;
+const OP_PUSH_ZERO: u8 = 0x7b;
+const OP_PUSH_ONE: u8 = 0x7c;
+const OP_PUSH_INT: u8 = 0x7d;
+const OP_PUSH_PUBKEY: u8 = 0x7e;
+const OP_EQUAL: u8 = 0x80;
+const OP_EQUAL_VERIFY: u8 = 0x81;
+const OP_GE_ZERO: u8 = 0x82;
+const OP_GT_ZERO: u8 = 0x83;
+const OP_LE_ZERO: u8 = 0x84;
+const OP_LT_ZERO: u8 = 0x85;
+const OP_ADD: u8 = 0x93;
+const OP_SUB: u8 = 0x94;
+const OP_OR_VERIFY: u8 = 0x64;
+const OP_OR: u8 = 0x65;
+const OP_CHECK_SIG: u8 = 0xac;
+const OP_CHECK_SIG_VERIFY: u8 = 0xad;
+const OP_CHECK_MULTI_SIG: u8 = 0xae;
+const OP_CHECK_MULTI_SIG_VERIFY: u8 = 0xaf;
+const OP_HASH_BLAKE256: u8 = 0xb0;
+const OP_HASH_SHA256: u8 = 0xb1;
+const OP_HASH_SHA3: u8 = 0xb2;
+const OP_TO_RISTRETTO_POINT: u8 = 0xb3;
+const OP_CHECK_MULTI_SIG_VERIFY_AGGREGATE_PUB_KEY: u8 = 0xb4;
+const OP_RETURN: u8 = 0x60;
+const OP_IF_THEN: u8 = 0x61;
+const OP_ELSE: u8 = 0x62;
+const OP_END_IF: u8 = 0x63;
+
+const TYPE_NUMBER: u8 = 1;
+const TYPE_HASH: u8 = 2;
+const TYPE_COMMITMENT: u8 = 3;
+const TYPE_PUBKEY: u8 = 4;
+const TYPE_SIG: u8 = 5;
+const TYPE_SCALAR: u8 = 6;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Opcode {
+    CheckHeightVerify(u64),
+    CheckHeight(u64),
+    CompareHeightVerify,
+    CompareHeight,
+    Nop,
+    PushZero,
+    PushOne,
+    PushHash([u8; 32]),
+    PushInt(i64),
+    PushPubKey(RistrettoPublicKey),
+    Drop,
+    Dup,
+    RevRot,
+    GeZero,
+    GtZero,
+    LeZero,
+    LtZero,
+    Add,
+    Sub,
+    Equal,
+    EqualVerify,
+    Or(u8),
+    OrVerify(u8),
+    HashBlake256,
+    HashSha256,
+    HashSha3,
+    CheckSig([u8; 32]),
+    CheckSigVerify([u8; 32]),
+    CheckMultiSig(u8, u8, Vec<RistrettoPublicKey>, [u8; 32]),
+    CheckMultiSigVerify(u8, u8, Vec<RistrettoPublicKey>, [u8; 32]),
+    CheckMultiSigVerifyAggregatePubKey(u8, u8, Vec<RistrettoPublicKey>, [u8; 32]),
+    ToRistrettoPoint,
+    Return,
+    IfThen,
+    Else,
+    EndIf,
+}
+
+impl Opcode {
+    /// Append the encoding of this opcode, including its arguments, to `bytes`
+    pub fn write_bytes(&self, bytes: &mut Vec<u8>) {
+        use Opcode::*;
+        match self {
+            CheckHeightVerify(height) => {
+                bytes.push(OP_CHECK_HEIGHT_VERIFY);
+                write_varint(bytes, *height);
+            },
+            CheckHeight(height) => {
+                bytes.push(OP_CHECK_HEIGHT);
+                write_varint(bytes, *height);
+            },
+            CompareHeightVerify => bytes.push(OP_COMPARE_HEIGHT_VERIFY),
+            CompareHeight => bytes.push(OP_COMPARE_HEIGHT),
+            Nop => bytes.push(OP_NOP),
+            PushZero => bytes.push(OP_PUSH_ZERO),
+            PushOne => bytes.push(OP_PUSH_ONE),
+            PushHash(hash) => {
+                bytes.push(OP_PUSH_HASH);
+                bytes.extend_from_slice(hash);
+            },
+            PushInt(n) => {
+                bytes.push(OP_PUSH_INT);
+                // Signed integers are zigzag encoded before the varint encoding
+                write_varint(bytes, ((n << 1) ^ (n >> 63)) as u64);
+            },
+            PushPubKey(key) => {
+                bytes.push(OP_PUSH_PUBKEY);
+                bytes.extend_from_slice(key.as_bytes());
+            },
+            Drop => bytes.push(OP_DROP),
+            Dup => bytes.push(OP_DUP),
+            RevRot => bytes.push(OP_REV_ROT),
+            GeZero => bytes.push(OP_GE_ZERO),
+            GtZero => bytes.push(OP_GT_ZERO),
+            LeZero => bytes.push(OP_LE_ZERO),
+            LtZero => bytes.push(OP_LT_ZERO),
+            Add => bytes.push(OP_ADD),
+            Sub => bytes.push(OP_SUB),
+            Equal => bytes.push(OP_EQUAL),
+            EqualVerify => bytes.push(OP_EQUAL_VERIFY),
+            Or(n) => bytes.extend_from_slice(&[OP_OR, *n]),
+            OrVerify(n) => bytes.extend_from_slice(&[OP_OR_VERIFY, *n]),
+            HashBlake256 => bytes.push(OP_HASH_BLAKE256),
+            HashSha256 => bytes.push(OP_HASH_SHA256),
+            HashSha3 => bytes.push(OP_HASH_SHA3),
+            CheckSig(message) => {
+                bytes.push(OP_CHECK_SIG);
+                bytes.extend_from_slice(message);
+            },
+            CheckSigVerify(message) => {
+                bytes.push(OP_CHECK_SIG_VERIFY);
+                bytes.extend_from_slice(message);
+            },
+            CheckMultiSig(m, n, keys, message) => write_multisig(bytes, OP_CHECK_MULTI_SIG, *m, *n, keys, message),
+            CheckMultiSigVerify(m, n, keys, message) => {
+                write_multisig(bytes, OP_CHECK_MULTI_SIG_VERIFY, *m, *n, keys, message)
+            },
+            CheckMultiSigVerifyAggregatePubKey(m, n, keys, message) => write_multisig(
+                bytes,
+                OP_CHECK_MULTI_SIG_VERIFY_AGGREGATE_PUB_KEY,
+                *m,
+                *n,
+                keys,
+                message,
+            ),
+            ToRistrettoPoint => bytes.push(OP_TO_RISTRETTO_POINT),
+            Return => bytes.push(OP_RETURN),
+            IfThen => bytes.push(OP_IF_THEN),
+            Else => bytes.push(OP_ELSE),
+            EndIf => bytes.push(OP_END_IF),
+        }
+    }
+
+    /// Read the next opcode from `bytes`, returning it with the remaining bytes
+    fn read(bytes: &[u8]) -> Result<(Self, &[u8]), ScriptError> {
+        use Opcode::*;
+        let (code, rest) = bytes.split_first().ok_or(ScriptError::InvalidData)?;
+        let opcode = match *code {
+            OP_CHECK_HEIGHT_VERIFY => {
+                let (height, rest) = read_varint(rest)?;
+                return Ok((CheckHeightVerify(height), rest));
+            },
+            OP_CHECK_HEIGHT => {
+                let (height, rest) = read_varint(rest)?;
+                return Ok((CheckHeight(height), rest));
+            },
+            OP_COMPARE_HEIGHT_VERIFY => CompareHeightVerify,
+            OP_COMPARE_HEIGHT => CompareHeight,
+            OP_NOP => Nop,
+            OP_PUSH_ZERO => PushZero,
+            OP_PUSH_ONE => PushOne,
+            OP_PUSH_HASH => {
+                let (hash, rest) = read_32(rest)?;
+                return Ok((PushHash(hash), rest));
+            },
+            OP_PUSH_INT => {
+                let (n, rest) = read_varint(rest)?;
+                return Ok((PushInt((n >> 1) as i64 ^ -((n & 1) as i64)), rest));
+            },
+            OP_PUSH_PUBKEY => {
+                let (key, rest) = read_public_key(rest)?;
+                return Ok((PushPubKey(key), rest));
+            },
+            OP_DROP => Drop,
+            OP_DUP => Dup,
+            OP_REV_ROT => RevRot,
+            OP_GE_ZERO => GeZero,
+            OP_GT_ZERO => GtZero,
+            OP_LE_ZERO => LeZero,
+            OP_LT_ZERO => LtZero,
+            OP_ADD => Add,
+            OP_SUB => Sub,
+            OP_EQUAL => Equal,
+            OP_EQUAL_VERIFY => EqualVerify,
+            OP_OR | OP_OR_VERIFY => {
+                let (n, rest) = rest.split_first().ok_or(ScriptError::InvalidData)?;
+                let opcode = if *code == OP_OR { Or(*n) } else { OrVerify(*n) };
+                return Ok((opcode, rest));
+            },
+            OP_HASH_BLAKE256 => HashBlake256,
+            OP_HASH_SHA256 => HashSha256,
+            OP_HASH_SHA3 => HashSha3,
+            OP_CHECK_SIG | OP_CHECK_SIG_VERIFY => {
+                let (message, rest) = read_32(rest)?;
+                let opcode = if *code == OP_CHECK_SIG {
+                    CheckSig(message)
+                } else {
+                    CheckSigVerify(message)
+                };
+                return Ok((opcode, rest));
+            },
+            OP_CHECK_MULTI_SIG | OP_CHECK_MULTI_SIG_VERIFY | OP_CHECK_MULTI_SIG_VERIFY_AGGREGATE_PUB_KEY => {
+                let (&[m, n], rest) = split_array::<2>(rest)?;
+                let mut keys = Vec::with_capacity(n as usize);
+                let mut rest = rest;
+                for _ in 0..n {
+                    let (key, remaining) = read_public_key(rest)?;
+                    keys.push(key);
+                    rest = remaining;
+                }
+                let (message, rest) = read_32(rest)?;
+                let opcode = match *code {
+                    OP_CHECK_MULTI_SIG => CheckMultiSig(m, n, keys, message),
+                    OP_CHECK_MULTI_SIG_VERIFY => CheckMultiSigVerify(m, n, keys, message),
+                    _ => CheckMultiSigVerifyAggregatePubKey(m, n, keys, message),
+                };
+                return Ok((opcode, rest));
+            },
+            OP_TO_RISTRETTO_POINT => ToRistrettoPoint,
+            OP_RETURN => Return,
+            OP_IF_THEN => IfThen,
+            OP_ELSE => Else,
+            OP_END_IF => EndIf,
+            code => return Err(ScriptError::InvalidOpcode(code)),
+        };
+        Ok((opcode, rest))
+    }
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Opcode::*;
+        match self {
+            CheckHeightVerify(height) => write!(f, "CheckHeightVerify({})", height),
+            CheckHeight(height) => write!(f, "CheckHeight({})", height),
+            PushHash(hash) => write!(f, "PushHash({})", to_hex(hash)),
+            PushInt(n) => write!(f, "PushInt({})", n),
+            PushPubKey(key) => write!(f, "PushPubKey({})", to_hex(key.as_bytes())),
+            Or(n) => write!(f, "Or({})", n),
+            OrVerify(n) => write!(f, "OrVerify({})", n),
+            CheckSig(message) => write!(f, "CheckSig({})", to_hex(message)),
+            CheckSigVerify(message) => write!(f, "CheckSigVerify({})", to_hex(message)),
+            CheckMultiSig(m, n, keys, _) |
+            CheckMultiSigVerify(m, n, keys, _) |
+            CheckMultiSigVerifyAggregatePubKey(m, n, keys, _) => {
+                let name = match self {
+                    CheckMultiSig(..) => "CheckMultiSig",
+                    CheckMultiSigVerify(..) => "CheckMultiSigVerify",
+                    _ => "CheckMultiSigVerifyAggregatePubKey",
+                };
+                write!(f, "{}({}, {}, {} keys)", name, m, n, keys.len())
+            },
+            opcode => write!(f, "{:?}", opcode),
+        }
+    }
+}
+
+/// A script as a list of opcodes, encoded on the wire as the concatenation of the opcode encodings
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TariScript {
+    pub opcodes: Vec<Opcode>,
+}
+
+impl TariScript {
+    pub fn new(opcodes: Vec<Opcode>) -> Self {
+        Self { opcodes }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.opcodes.iter().for_each(|opcode| opcode.write_bytes(&mut bytes));
+        bytes
+    }
+
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self, ScriptError> {
+        if bytes.len() > MAX_SCRIPT_BYTES {
+            return Err(ScriptError::TooLong);
+        }
+        let mut opcodes = Vec::new();
+        while !bytes.is_empty() {
+            let (opcode, rest) = Opcode::read(bytes)?;
+            opcodes.push(opcode);
+            bytes = rest;
+        }
+        Ok(Self { opcodes })
+    }
+
+    /// The length of the consensus encoding, as counted towards the output weight
+    pub fn encoded_len(&self) -> usize {
+        let bytes = self.to_bytes();
+        let mut len = Vec::new();
+        write_varint(&mut len, bytes.len() as u64);
+        len.len() + bytes.len()
+    }
+}
+
+impl fmt::Display for TariScript {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let opcodes = self.opcodes.iter().map(|op| op.to_string()).collect::<Vec<_>>();
+        write!(f, "{}", opcodes.join(" "))
+    }
+}
+
+/// An item of the execution stack the script is evaluated against
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StackItem {
+    Number(i64),
+    Hash([u8; 32]),
+    Commitment(PedersenCommitment),
+    PublicKey(RistrettoPublicKey),
+    Signature(RistrettoSchnorr),
+    Scalar([u8; 32]),
+}
+
+impl fmt::Display for StackItem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StackItem::Number(n) => write!(f, "Number({})", n),
+            StackItem::Hash(hash) => write!(f, "Hash({})", to_hex(hash)),
+            StackItem::Commitment(commitment) => write!(f, "Commitment({})", to_hex(commitment.as_bytes())),
+            StackItem::PublicKey(key) => write!(f, "PublicKey({})", to_hex(key.as_bytes())),
+            StackItem::Signature(signature) => write!(
+                f,
+                "Signature({}{})",
+                to_hex(signature.get_public_nonce().as_bytes()),
+                to_hex(signature.get_signature().as_bytes())
+            ),
+            StackItem::Scalar(scalar) => write!(f, "Scalar({})", to_hex(scalar)),
+        }
+    }
+}
+
+impl StackItem {
+    pub fn write_bytes(&self, bytes: &mut Vec<u8>) {
+        match self {
+            StackItem::Number(n) => {
+                bytes.push(TYPE_NUMBER);
+                bytes.extend_from_slice(&n.to_le_bytes());
+            },
+            StackItem::Hash(hash) => {
+                bytes.push(TYPE_HASH);
+                bytes.extend_from_slice(hash);
+            },
+            StackItem::Commitment(commitment) => {
+                bytes.push(TYPE_COMMITMENT);
+                bytes.extend_from_slice(commitment.as_bytes());
+            },
+            StackItem::PublicKey(key) => {
+                bytes.push(TYPE_PUBKEY);
+                bytes.extend_from_slice(key.as_bytes());
+            },
+            StackItem::Signature(signature) => {
+                bytes.push(TYPE_SIG);
+                bytes.extend_from_slice(signature.get_public_nonce().as_bytes());
+                bytes.extend_from_slice(signature.get_signature().as_bytes());
+            },
+            StackItem::Scalar(scalar) => {
+                bytes.push(TYPE_SCALAR);
+                bytes.extend_from_slice(scalar);
+            },
+        }
+    }
+
+    fn read(bytes: &[u8]) -> Result<(Self, &[u8]), ScriptError> {
+        let (kind, rest) = bytes.split_first().ok_or(ScriptError::InvalidData)?;
+        match *kind {
+            TYPE_NUMBER => {
+                let (n, rest) = split_array::<8>(rest)?;
+                Ok((StackItem::Number(i64::from_le_bytes(*n)), rest))
+            },
+            TYPE_HASH => {
+                let (hash, rest) = read_32(rest)?;
+                Ok((StackItem::Hash(hash), rest))
+            },
+            TYPE_COMMITMENT => {
+                let (bytes, rest) = read_32(rest)?;
+                let commitment = PedersenCommitment::from_bytes(&bytes).map_err(|_| ScriptError::InvalidData)?;
+                Ok((StackItem::Commitment(commitment), rest))
+            },
+            TYPE_PUBKEY => {
+                let (key, rest) = read_public_key(rest)?;
+                Ok((StackItem::PublicKey(key), rest))
+            },
+            TYPE_SIG => {
+                let (nonce, rest) = read_public_key(rest)?;
+                let (s, rest) = read_32(rest)?;
+                let s = RistrettoSecretKey::from_bytes(&s).map_err(|_| ScriptError::InvalidData)?;
+                Ok((StackItem::Signature(RistrettoSchnorr::new(nonce, s)), rest))
+            },
+            TYPE_SCALAR => {
+                let (scalar, rest) = read_32(rest)?;
+                Ok((StackItem::Scalar(scalar), rest))
+            },
+            kind => Err(ScriptError::InvalidStackItem(kind)),
+        }
+    }
+}
+
+/// The input data provided when spending an output, encoded as the concatenation of the item encodings
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExecutionStack {
+    pub items: Vec<StackItem>,
+}
+
+impl ExecutionStack {
+    pub fn new(items: Vec<StackItem>) -> Self {
+        Self { items }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.items.iter().for_each(|item| item.write_bytes(&mut bytes));
+        bytes
+    }
+
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self, ScriptError> {
+        let mut items = Vec::new();
+        while !bytes.is_empty() {
+            if items.len() == MAX_STACK_SIZE {
+                return Err(ScriptError::TooLong);
+            }
+            let (item, rest) = StackItem::read(bytes)?;
+            items.push(item);
+            bytes = rest;
+        }
+        Ok(Self { items })
+    }
+}
+
+/// The script signature message of an input spending an output locked with `script`, using `input_data`. The device
+/// signs this together with its keys, so everything that locks the output is committed to here.
+pub fn script_signature_message(script: &TariScript, input_data: &ExecutionStack) -> Challenge {
+    DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(SCRIPT_MESSAGE_LABEL)
+        .chain(&TRANSACTION_INPUT_VERSION)
+        .chain(script)
+        .chain(input_data)
+        .finalize_challenge()
+}
+
+// Scripts and stacks are encoded as a varint length followed by the raw bytes
+macro_rules! impl_borsh_bytes {
+    ($type:ty, $max:expr) => {
+        impl BorshSerialize for $type {
+            fn serialize<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+                let bytes = self.to_bytes();
+                let mut len = Vec::new();
+                write_varint(&mut len, bytes.len() as u64);
+                writer.write_all(&len)?;
+                writer.write_all(&bytes)
+            }
+        }
+
+        impl BorshDeserialize for $type {
+            fn deserialize(buf: &mut &[u8]) -> io::Result<Self> {
+                let invalid = |e: ScriptError| io::Error::new(io::ErrorKind::InvalidData, e.to_string());
+                let (len, rest) = read_varint(buf).map_err(invalid)?;
+                if len > $max as u64 || len > rest.len() as u64 {
+                    return Err(invalid(ScriptError::TooLong));
+                }
+                let mut bytes = vec![0u8; len as usize];
+                let mut rest = rest;
+                rest.read_exact(&mut bytes)?;
+                *buf = rest;
+                Self::from_bytes(&bytes).map_err(invalid)
+            }
+        }
+    };
+}
+
+impl_borsh_bytes!(TariScript, MAX_SCRIPT_BYTES);
+impl_borsh_bytes!(ExecutionStack, MAX_SCRIPT_BYTES);
+
+fn write_multisig(bytes: &mut Vec<u8>, code: u8, m: u8, n: u8, keys: &[RistrettoPublicKey], message: &[u8; 32]) {
+    bytes.extend_from_slice(&[code, m, n]);
+    keys.iter().for_each(|key| bytes.extend_from_slice(key.as_bytes()));
+    bytes.extend_from_slice(message);
+}
+
+/// Unsigned LEB128, as used by the `integer-encoding` crate
+fn write_varint(bytes: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        bytes.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    bytes.push(n as u8);
+}
+
+fn read_varint(bytes: &[u8]) -> Result<(u64, &[u8]), ScriptError> {
+    let mut n = 0u64;
+    for (i, byte) in bytes.iter().enumerate().take(10) {
+        n |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok((n, &bytes[i + 1..]));
+        }
+    }
+    Err(ScriptError::InvalidData)
+}
+
+fn split_array<const N: usize>(bytes: &[u8]) -> Result<(&[u8; N], &[u8]), ScriptError> {
+    if bytes.len() < N {
+        return Err(ScriptError::InvalidData);
+    }
+    let (array, rest) = bytes.split_at(N);
+    Ok((array.try_into().expect("length checked above"), rest))
+}
+
+fn read_32(bytes: &[u8]) -> Result<([u8; 32], &[u8]), ScriptError> {
+    split_array::<32>(bytes).map(|(array, rest)| (*array, rest))
+}
+
+fn read_public_key(bytes: &[u8]) -> Result<(RistrettoPublicKey, &[u8]), ScriptError> {
+    let (key, rest) = read_32(bytes)?;
+    let key = RistrettoPublicKey::from_bytes(&key).map_err(|_| ScriptError::InvalidData)?;
+    Ok((key, rest))
+}
@@ -0,0 +1,69 @@
+//! Script keys bound to the commitment of their output
+//! The script key of an output is the key at its index in the script key branch, so two outputs that end up at the
+//! same index, e.g. after a state store was restored from an old backup, share a script key. A bound script key tweaks
+//! the key at the index by a hash of that key and the output's commitment, `K' = K + H(K || C)·G` with the secret key
+//! `k' = k + H(K || C)`, so that every output has a script key of its own. Anyone who knows the seed can still recover
+//! it from the commitment alone, and the tweak only needs public values, so the host derives the bound key from the
+//! public key the device exports without any new instruction.
+
+use std::ops::Range;
+
+use tari_crypto::{
+    keys::PublicKey,
+    ristretto::{pedersen::PedersenCommitment, RistrettoPublicKey, RistrettoSecretKey},
+    tari_utilities::ByteArray,
+};
+
+use crate::{
+    device::{DerivationVersion, KeyBranch, LedgerDevice},
+    domains::BOUND_SCRIPT_KEY_LABEL,
+    errors::DeviceError,
+    export::export_public_keys_with_version,
+    hashing::{DomainSeparatedConsensusHasher, TransactionHashDomain},
+};
+
+/// The scalar that binds `script_public_key` to `commitment`, `H(K || C)`
+pub fn script_key_tweak(script_public_key: &RistrettoPublicKey, commitment: &PedersenCommitment) -> RistrettoSecretKey {
+    let hash = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(BOUND_SCRIPT_KEY_LABEL)
+        .chain(script_public_key)
+        .chain(commitment.as_public_key())
+        .finalize();
+    RistrettoSecretKey::from_bytes(&hash).expect("a 32 byte hash always reduces to a scalar")
+}
+
+/// The script public key of the output with `commitment`, bound from the key at its index
+pub fn bound_script_public_key(
+    script_public_key: &RistrettoPublicKey,
+    commitment: &PedersenCommitment,
+) -> RistrettoPublicKey {
+    let tweak = script_key_tweak(script_public_key, commitment);
+    script_public_key + &RistrettoPublicKey::from_secret_key(&tweak)
+}
+
+/// The secret key of [`bound_script_public_key`], for whoever holds the key at the index, e.g. a key manager
+/// recovering the wallet from its seed
+pub fn bound_script_secret_key(
+    script_secret_key: &RistrettoSecretKey,
+    commitment: &PedersenCommitment,
+) -> RistrettoSecretKey {
+    let tweak = script_key_tweak(&RistrettoPublicKey::from_secret_key(script_secret_key), commitment);
+    script_secret_key + &tweak
+}
+
+/// The index in the script key branch of account `account` whose key, bound to `commitment`, is `bound_key`, among
+/// `indices`. Finds the script key of an output after the state store that held its index was lost.
+pub fn recover_script_key_index(
+    device: &LedgerDevice,
+    account: u32,
+    commitment: &PedersenCommitment,
+    bound_key: &RistrettoPublicKey,
+    indices: Range<u32>,
+    version: DerivationVersion,
+) -> Result<Option<u32>, DeviceError> {
+    let export = export_public_keys_with_version(device, account, KeyBranch::ScriptKey, indices, version, |_, _| {})?;
+    Ok(export
+        .keys
+        .iter()
+        .find(|key| bound_script_public_key(&key.public_key, commitment) == *bound_key)
+        .map(|key| key.index))
+}
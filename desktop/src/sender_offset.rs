@@ -0,0 +1,246 @@
+//! Sender offset keys that never leave the device
+//! Every output carries a sender offset public key `K_O` and a metadata signature that the sender makes with its
+//! secret key `k_O`, and the transaction carries the script offset `Σ k_script - Σ k_O` over its inputs and outputs, so
+//! that nobody can swap the script of an input or an output after the fact. The app derives the sender offset keys in
+//! the sender offset branch itself, signs the sender half of the metadata signature with them and subtracts them into
+//! the script offset, so the host only ever sees the public keys, the signatures and the offset. Everything the device
+//! returns is checked against the public keys before it is used.
+
+use tari_crypto::{
+    hash::blake2::Blake256,
+    keys::PublicKey,
+    ristretto::{pedersen::PedersenCommitment, RistrettoPublicKey, RistrettoSchnorr, RistrettoSecretKey},
+    tari_utilities::ByteArray,
+};
+use tari_ledger_protocol::{
+    MAX_SCRIPT_OFFSET_KEYS,
+    P1_SCRIPT_OFFSET,
+    P1_SENDER_OFFSET_SIGN,
+    SCRIPT_OFFSET_RESPONSE_LENGTH,
+    SENDER_OFFSET_SIGN_RESPONSE_LENGTH,
+};
+
+use crate::{
+    device::{Capabilities, DerivationVersion, Instruction, LedgerDevice},
+    domains::METADATA_SIGNATURE_LABEL,
+    errors::DeviceError,
+    hashing::{ConsensusHasher, DomainSeparatedConsensusHasher, Preimage, TransactionHashDomain},
+    script_keys::{bound_script_public_key, script_key_tweak},
+    verify::verify_challenge_signature,
+};
+
+/// The sender half of the metadata signature of an output
+#[derive(Clone, Debug)]
+pub struct SenderOffsetSignature {
+    /// The index of the sender offset key in its branch
+    pub index: u32,
+    pub sender_offset_public_key: RistrettoPublicKey,
+    pub signature: RistrettoSchnorr,
+}
+
+/// The script key of an input the script offset is taken over
+#[derive(Clone, Debug)]
+pub struct ScriptOffsetInput {
+    /// The index of the script key in its branch
+    pub index: u32,
+    /// The public key at the index, as recorded when the output being spent was created
+    pub script_public_key: RistrettoPublicKey,
+    /// The commitment the script key is bound to, if the output was created with a bound script key
+    pub bound_to: Option<PedersenCommitment>,
+}
+
+impl ScriptOffsetInput {
+    /// The script public key the output being spent carries
+    pub fn output_script_public_key(&self) -> RistrettoPublicKey {
+        match &self.bound_to {
+            Some(commitment) => bound_script_public_key(&self.script_public_key, commitment),
+            None => self.script_public_key.clone(),
+        }
+    }
+}
+
+/// The challenge of the sender half of the metadata signature, `e` in `s = r + e·k_O`
+pub fn metadata_challenge(
+    ephemeral_commitment: &PedersenCommitment,
+    public_nonce: &RistrettoPublicKey,
+    sender_offset_public_key: &RistrettoPublicKey,
+    commitment: &PedersenCommitment,
+    message: &[u8; 32],
+) -> [u8; 32] {
+    let hasher = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(METADATA_SIGNATURE_LABEL);
+    chain_metadata_challenge(
+        hasher,
+        ephemeral_commitment,
+        public_nonce,
+        sender_offset_public_key,
+        commitment,
+        message,
+    )
+    .finalize()
+}
+
+/// The preimage of [`metadata_challenge`], to show when a sender offset signature over it does not verify
+pub fn metadata_challenge_preimage(
+    ephemeral_commitment: &PedersenCommitment,
+    public_nonce: &RistrettoPublicKey,
+    sender_offset_public_key: &RistrettoPublicKey,
+    commitment: &PedersenCommitment,
+    message: &[u8; 32],
+) -> Preimage {
+    let hasher = DomainSeparatedConsensusHasher::<TransactionHashDomain>::traced(METADATA_SIGNATURE_LABEL);
+    chain_metadata_challenge(
+        hasher,
+        ephemeral_commitment,
+        public_nonce,
+        sender_offset_public_key,
+        commitment,
+        message,
+    )
+    .preimage()
+}
+
+fn chain_metadata_challenge(
+    hasher: ConsensusHasher<Blake256>,
+    ephemeral_commitment: &PedersenCommitment,
+    public_nonce: &RistrettoPublicKey,
+    sender_offset_public_key: &RistrettoPublicKey,
+    commitment: &PedersenCommitment,
+    message: &[u8; 32],
+) -> ConsensusHasher<Blake256> {
+    hasher
+        .chain(ephemeral_commitment.as_public_key())
+        .chain(public_nonce)
+        .chain(sender_offset_public_key)
+        .chain(commitment.as_public_key())
+        .chain(message)
+}
+
+/// Check the sender half of the metadata signature of the output with `commitment`
+pub fn verify_sender_offset_signature(
+    signature: &SenderOffsetSignature,
+    commitment: &PedersenCommitment,
+    ephemeral_commitment: &PedersenCommitment,
+    message: &[u8; 32],
+) -> bool {
+    let challenge = metadata_challenge(
+        ephemeral_commitment,
+        signature.signature.get_public_nonce(),
+        &signature.sender_offset_public_key,
+        commitment,
+        message,
+    );
+    verify_challenge_signature(&signature.sender_offset_public_key, &signature.signature, &challenge)
+}
+
+/// Have the device sign the sender half of the metadata signature of the output with `commitment` with the sender
+/// offset key at `index` of `account`. `ephemeral_commitment` is the commitment half of the metadata signature's nonce
+/// and `message` the hash of the output fields it covers. The device only signs the outputs of the approved
+/// transaction, one sender offset each, and otherwise refuses with `TransactionNotApproved`.
+pub fn sign_sender_offset(
+    device: &LedgerDevice,
+    account: u32,
+    index: u32,
+    commitment: &PedersenCommitment,
+    ephemeral_commitment: &PedersenCommitment,
+    message: &[u8; 32],
+    version: DerivationVersion,
+) -> Result<SenderOffsetSignature, DeviceError> {
+    device.require(Capabilities::SENDER_OFFSETS)?;
+    let p2 = device.derivation_p2(version)?;
+    let mut data = Vec::with_capacity(4 + 4 + 3 * 32);
+    data.extend_from_slice(&account.to_le_bytes());
+    data.extend_from_slice(&index.to_le_bytes());
+    data.extend_from_slice(commitment.as_bytes());
+    data.extend_from_slice(ephemeral_commitment.as_bytes());
+    data.extend_from_slice(message);
+    let response = device.send(Instruction::SenderOffset, P1_SENDER_OFFSET_SIGN, p2, data)?;
+    let payload = device.response_payload(&response, SENDER_OFFSET_SIGN_RESPONSE_LENGTH)?;
+    let invalid = |_| DeviceError::InvalidResponse("the sender offset signature is malformed");
+    let sender_offset_public_key = RistrettoPublicKey::from_bytes(&payload[0..32]).map_err(invalid)?;
+    let s = RistrettoSecretKey::from_bytes(&payload[32..64]).map_err(invalid)?;
+    let public_nonce = RistrettoPublicKey::from_bytes(&payload[64..96]).map_err(invalid)?;
+    let signature = SenderOffsetSignature {
+        index,
+        sender_offset_public_key,
+        signature: RistrettoSchnorr::new(public_nonce, s),
+    };
+    if !verify_sender_offset_signature(&signature, commitment, ephemeral_commitment, message) {
+        return Err(DeviceError::InvalidSignature {
+            signature: "sender offset signature",
+            preimage: Box::new(metadata_challenge_preimage(
+                ephemeral_commitment,
+                signature.signature.get_public_nonce(),
+                &signature.sender_offset_public_key,
+                commitment,
+                message,
+            )),
+        });
+    }
+    Ok(signature)
+}
+
+/// Have the device compute the script offset of the approved transaction with `session_nonce`, spending `inputs` into
+/// outputs with the sender offset keys of `sender_offsets`. The tweaks of bound script keys only need public values,
+/// so the host adds them to the offset the device returns. The app answers this once per approved transaction and only
+/// before its last output is signed.
+pub fn script_offset(
+    device: &LedgerDevice,
+    session_nonce: u64,
+    account: u32,
+    inputs: &[ScriptOffsetInput],
+    sender_offsets: &[SenderOffsetSignature],
+    version: DerivationVersion,
+) -> Result<RistrettoSecretKey, DeviceError> {
+    device.require(Capabilities::SENDER_OFFSETS)?;
+    let count = inputs.len() + sender_offsets.len();
+    let (input_count, output_count) = match (u8::try_from(inputs.len()), u8::try_from(sender_offsets.len())) {
+        (Ok(input_count), Ok(output_count)) if count <= MAX_SCRIPT_OFFSET_KEYS => (input_count, output_count),
+        _ => {
+            return Err(DeviceError::TooManyKeys {
+                count,
+                max: MAX_SCRIPT_OFFSET_KEYS,
+            })
+        },
+    };
+    let p2 = device.derivation_p2(version)?;
+    let mut data = Vec::with_capacity(8 + 4 + 2 + 4 * count);
+    data.extend_from_slice(&session_nonce.to_le_bytes());
+    data.extend_from_slice(&account.to_le_bytes());
+    data.push(input_count);
+    data.push(output_count);
+    for input in inputs {
+        data.extend_from_slice(&input.index.to_le_bytes());
+    }
+    for output in sender_offsets {
+        data.extend_from_slice(&output.index.to_le_bytes());
+    }
+    let response = device.send(Instruction::SenderOffset, P1_SCRIPT_OFFSET, p2, data)?;
+    let payload = device.response_payload(&response, SCRIPT_OFFSET_RESPONSE_LENGTH)?;
+    let offset = RistrettoSecretKey::from_bytes(payload)
+        .map_err(|_| DeviceError::InvalidResponse("the script offset is not a scalar"))?;
+
+    let offset = inputs
+        .iter()
+        .filter_map(|input| {
+            input
+                .bound_to
+                .as_ref()
+                .map(|commitment| script_key_tweak(&input.script_public_key, commitment))
+        })
+        .fold(offset, |offset, tweak| &offset + &tweak);
+    let expected = inputs
+        .iter()
+        .map(ScriptOffsetInput::output_script_public_key)
+        .fold(RistrettoPublicKey::default(), |sum, key| &sum + &key);
+    let sender_offset_sum = sender_offsets
+        .iter()
+        .fold(RistrettoPublicKey::default(), |sum, output| {
+            &sum + &output.sender_offset_public_key
+        });
+    if RistrettoPublicKey::from_secret_key(&offset) + sender_offset_sum != expected {
+        return Err(DeviceError::InvalidResponse(
+            "the script offset does not match the script and sender offset keys",
+        ));
+    }
+    Ok(offset)
+}
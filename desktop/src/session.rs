@@ -0,0 +1,110 @@
+//! Session resumption across quick consecutive invocations
+//! The result of the handshake is cached in a short lived token, so that running several commands in a row does not
+//! repeat the version and capability exchange every time. A token only applies to the device it was created for and
+//! expires after a configurable time, after which the next command performs a full handshake again.
+
+use std::{
+    fs,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    device::{Capabilities, HandshakeInfo, LedgerDevice},
+    errors::{DeviceError, StoreError},
+    wallet::wallet_fingerprint,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionToken {
+    pub device_id: String,
+    pub app_version: String,
+    pub min_client_version: String,
+    pub capabilities: u32,
+    /// Identifies the seed without revealing any key, see [`wallet_fingerprint`]
+    pub wallet_fingerprint: Option<String>,
+    /// Seconds since the Unix epoch
+    pub created_at: u64,
+}
+
+impl SessionToken {
+    /// The token stored at `path`, if there is a readable one
+    pub fn load<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let contents = fs::read_to_string(path).ok()?;
+        serde_json::from_str(&contents).ok()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), StoreError> {
+        if let Some(parent) = path.as_ref().parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let contents = serde_json::to_string(self).map_err(|e| StoreError::Serialization(e.to_string()))?;
+        fs::write(path, contents)?;
+        Ok(())
+    }
+
+    /// Whether the token was created for `device_id` less than `ttl` ago
+    pub fn is_valid_for(&self, device_id: &str, ttl: Duration) -> bool {
+        let age = now().saturating_sub(self.created_at);
+        self.device_id == device_id && age < ttl.as_secs()
+    }
+
+    pub fn handshake_info(&self) -> Option<HandshakeInfo> {
+        Some(HandshakeInfo {
+            app_version: self.app_version.parse().ok()?,
+            min_client_version: self.min_client_version.parse().ok()?,
+        })
+    }
+}
+
+/// Resume the session cached at `path` if it belongs to this device and is younger than `ttl`, otherwise run
+/// `handshake` and cache its result. Devices that cannot be identified always get a full handshake.
+pub fn resume_or_handshake<P, H>(
+    device: &LedgerDevice,
+    path: P,
+    ttl: Duration,
+    handshake: H,
+) -> Result<HandshakeInfo, DeviceError>
+where
+    P: AsRef<Path>,
+    H: FnOnce() -> Result<HandshakeInfo, DeviceError>,
+{
+    let device_id = match device.device_id() {
+        Some(device_id) if !ttl.is_zero() => device_id,
+        _ => return handshake(),
+    };
+    if let Some(token) = SessionToken::load(&path).filter(|token| token.is_valid_for(device_id, ttl)) {
+        if let Some(info) = token.handshake_info() {
+            device.cache_capabilities(Capabilities::from_bits(token.capabilities));
+            return Ok(info);
+        }
+    }
+
+    let info = handshake()?;
+    let capabilities = device.capabilities()?;
+    let token = SessionToken {
+        device_id: device_id.to_string(),
+        app_version: info.app_version.to_string(),
+        min_client_version: info.min_client_version.to_string(),
+        capabilities: capabilities.bits(),
+        wallet_fingerprint: wallet_fingerprint(device).ok(),
+        created_at: now(),
+    };
+    // The token only saves a few round trips, failing to write it is not worth failing the command for
+    let _ = token.save(&path);
+    Ok(info)
+}
+
+/// Forget the cached session, e.g. after the device reported an error that may mean it was swapped
+pub fn clear<P: AsRef<Path>>(path: P) {
+    let _ = fs::remove_file(path);
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
@@ -0,0 +1,292 @@
+//! Outputs carrying sidechain features
+//! The Digital Assets Network (DAN) registers its templates and pays out the fees its validators earned with base
+//! layer outputs whose features carry the data, a code template registration or a validator fee claim. The device
+//! cannot parse the features, so the challenge it signs binds their consensus encoding, and the host sends a page
+//! describing them with the display hints of the transaction. The signer refuses to sign such an output on an app
+//! that does not show display hints, so the user never confirms an output without seeing what it registers or
+//! claims.
+//!
+//! The features are encoded as the base layer encodes `OutputFeatures`: the version, the output type, the maturity,
+//! the coinbase extra, the sidechain feature if any and the range proof type, with every string and byte vector
+//! length prefixed. [`OutputFeatures::default`] encodes to [`DEFAULT_OUTPUT_FEATURES`].
+
+use std::{fmt, io, io::Write};
+
+use borsh::BorshSerialize;
+use tari_crypto::{
+    ristretto::{RistrettoPublicKey, RistrettoSchnorr},
+    tari_utilities::ByteArray,
+};
+
+use crate::{
+    address::TariAddress,
+    domains::SIDECHAIN_OUTPUT_LABEL,
+    errors::SignerError,
+    hashing::{Challenge, DomainSeparatedConsensusHasher, TransactionHashDomain},
+    redact::short_hex,
+    script::{Opcode, TariScript},
+    signer::{OutputToSign, DEFAULT_OUTPUT_FEATURES},
+};
+
+/// The longest template name the base layer accepts, in bytes
+pub const MAX_TEMPLATE_NAME_LENGTH: usize = 32;
+/// The longest repository or binary URL the base layer accepts, in bytes
+pub const MAX_URL_LENGTH: usize = 255;
+/// The longest commit hash the base layer accepts, in bytes
+pub const MAX_COMMIT_HASH_LENGTH: usize = 32;
+
+/// The output types as the base layer numbers them
+const OUTPUT_TYPE_STANDARD: u8 = 0;
+const OUTPUT_TYPE_CODE_TEMPLATE_REGISTRATION: u8 = 4;
+const OUTPUT_TYPE_VALIDATOR_FEE_CLAIM: u8 = 5;
+/// The sidechain features as the base layer tags them
+const SIDECHAIN_FEATURE_CODE_TEMPLATE_REGISTRATION: u8 = 1;
+const SIDECHAIN_FEATURE_VALIDATOR_FEE_CLAIM: u8 = 3;
+
+/// How a template is executed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TemplateType {
+    Wasm { abi_version: u16 },
+    Flow,
+    Manifest,
+}
+
+impl fmt::Display for TemplateType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TemplateType::Wasm { abi_version } => write!(f, "WASM (ABI {})", abi_version),
+            TemplateType::Flow => write!(f, "flow"),
+            TemplateType::Manifest => write!(f, "manifest"),
+        }
+    }
+}
+
+impl BorshSerialize for TemplateType {
+    fn serialize<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        match self {
+            TemplateType::Wasm { abi_version } => {
+                0u8.serialize(writer)?;
+                abi_version.serialize(writer)
+            },
+            TemplateType::Flow => 1u8.serialize(writer),
+            TemplateType::Manifest => 2u8.serialize(writer),
+        }
+    }
+}
+
+/// The registration of a template on the DAN, signed by its author
+#[derive(Clone, Debug)]
+pub struct CodeTemplateRegistration {
+    pub author_public_key: RistrettoPublicKey,
+    pub author_signature: RistrettoSchnorr,
+    pub template_name: String,
+    pub template_version: u16,
+    pub template_type: TemplateType,
+    pub repo_url: String,
+    pub commit_hash: Vec<u8>,
+    /// The SHA-256 hash of the compiled template
+    pub binary_sha: [u8; 32],
+    pub binary_url: String,
+    /// The sidechain the template is registered on, the DAN itself if none
+    pub sidechain_id: Option<RistrettoPublicKey>,
+}
+
+impl BorshSerialize for CodeTemplateRegistration {
+    fn serialize<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.author_public_key.serialize(writer)?;
+        self.author_signature.get_public_nonce().serialize(writer)?;
+        self.author_signature.get_signature().serialize(writer)?;
+        self.template_name.serialize(writer)?;
+        self.template_version.serialize(writer)?;
+        self.template_type.serialize(writer)?;
+        self.repo_url.serialize(writer)?;
+        self.commit_hash.serialize(writer)?;
+        writer.write_all(&self.binary_sha)?;
+        self.binary_url.serialize(writer)?;
+        self.sidechain_id.serialize(writer)
+    }
+}
+
+/// A claim of the fees a validator earned on the DAN in an epoch
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValidatorFeeClaim {
+    pub validator_public_key: RistrettoPublicKey,
+    pub epoch: u64,
+    /// The sidechain the validator served, the DAN itself if none
+    pub sidechain_id: Option<RistrettoPublicKey>,
+}
+
+impl BorshSerialize for ValidatorFeeClaim {
+    fn serialize<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.validator_public_key.serialize(writer)?;
+        self.epoch.serialize(writer)?;
+        self.sidechain_id.serialize(writer)
+    }
+}
+
+/// The sidechain data an output carries
+#[derive(Clone, Debug)]
+pub enum SideChainFeature {
+    CodeTemplateRegistration(CodeTemplateRegistration),
+    ValidatorFeeClaim(ValidatorFeeClaim),
+}
+
+impl SideChainFeature {
+    fn output_type(&self) -> u8 {
+        match self {
+            SideChainFeature::CodeTemplateRegistration(_) => OUTPUT_TYPE_CODE_TEMPLATE_REGISTRATION,
+            SideChainFeature::ValidatorFeeClaim(_) => OUTPUT_TYPE_VALIDATOR_FEE_CLAIM,
+        }
+    }
+
+    /// Refuse what the base layer would reject, before anything is signed
+    fn validate(&self) -> Result<(), SignerError> {
+        let check = |field: &'static str, length: usize, max: usize| {
+            if length > max {
+                Err(SignerError::FeatureTooLong { field, length, max })
+            } else {
+                Ok(())
+            }
+        };
+        match self {
+            SideChainFeature::CodeTemplateRegistration(registration) => {
+                check(
+                    "template name",
+                    registration.template_name.len(),
+                    MAX_TEMPLATE_NAME_LENGTH,
+                )?;
+                check("repository URL", registration.repo_url.len(), MAX_URL_LENGTH)?;
+                check("commit hash", registration.commit_hash.len(), MAX_COMMIT_HASH_LENGTH)?;
+                check("binary URL", registration.binary_url.len(), MAX_URL_LENGTH)
+            },
+            SideChainFeature::ValidatorFeeClaim(_) => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for SideChainFeature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SideChainFeature::CodeTemplateRegistration(registration) => write!(
+                f,
+                "Template {} v{}",
+                registration.template_name, registration.template_version
+            ),
+            SideChainFeature::ValidatorFeeClaim(claim) => write!(
+                f,
+                "Fees {} epoch {}",
+                short_hex(claim.validator_public_key.as_bytes()),
+                claim.epoch
+            ),
+        }
+    }
+}
+
+impl BorshSerialize for SideChainFeature {
+    fn serialize<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        match self {
+            SideChainFeature::CodeTemplateRegistration(registration) => {
+                SIDECHAIN_FEATURE_CODE_TEMPLATE_REGISTRATION.serialize(writer)?;
+                registration.serialize(writer)
+            },
+            SideChainFeature::ValidatorFeeClaim(claim) => {
+                SIDECHAIN_FEATURE_VALIDATOR_FEE_CLAIM.serialize(writer)?;
+                claim.serialize(writer)
+            },
+        }
+    }
+}
+
+/// The features of an output, as far as the host sets them
+#[derive(Clone, Debug, Default)]
+pub struct OutputFeatures {
+    /// The block height from which the output can be spent
+    pub maturity: u64,
+    pub sidechain_feature: Option<SideChainFeature>,
+}
+
+impl OutputFeatures {
+    pub fn with_sidechain_feature(feature: SideChainFeature) -> Self {
+        Self {
+            maturity: 0,
+            sidechain_feature: Some(feature),
+        }
+    }
+
+    /// The consensus encoding of the features
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(DEFAULT_OUTPUT_FEATURES.len());
+        self.serialize(&mut bytes).expect("writing to a vector is infallible");
+        bytes
+    }
+}
+
+impl BorshSerialize for OutputFeatures {
+    fn serialize<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let output_type = self
+            .sidechain_feature
+            .as_ref()
+            .map_or(OUTPUT_TYPE_STANDARD, SideChainFeature::output_type);
+        // Version 0
+        0u8.serialize(writer)?;
+        output_type.serialize(writer)?;
+        self.maturity.serialize(writer)?;
+        // No coinbase extra
+        Vec::<u8>::new().serialize(writer)?;
+        self.sidechain_feature.serialize(writer)?;
+        // A bulletproof+ range proof
+        0u8.serialize(writer)
+    }
+}
+
+/// An output paying `value` to `address` with sidechain features
+#[derive(Clone, Debug)]
+pub struct SidechainOutput {
+    pub address: TariAddress,
+    /// In microTari
+    pub value: u64,
+    pub features: OutputFeatures,
+}
+
+impl SidechainOutput {
+    /// Spendable by the key of the address
+    pub fn script(&self) -> TariScript {
+        TariScript::new(vec![Opcode::PushPubKey(self.address.public_key().clone())])
+    }
+
+    /// The challenge signed for the output, binding the address, the value, the script and the features
+    pub fn challenge(&self) -> Challenge {
+        DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(SIDECHAIN_OUTPUT_LABEL)
+            .chain_fixed(&self.address.to_bytes())
+            .chain(&self.value)
+            .chain(&self.script())
+            .chain(&self.features)
+            .finalize_challenge()
+    }
+
+    /// The output for the signer, with a page describing its sidechain feature. Fields longer than the base layer
+    /// accepts are refused.
+    pub fn to_output(&self) -> Result<OutputToSign, SignerError> {
+        if let Some(feature) = &self.features.sidechain_feature {
+            feature.validate()?;
+        }
+        Ok(OutputToSign {
+            value: self.value,
+            is_change: false,
+            features_and_scripts_size: self.features.to_bytes().len() + self.script().encoded_len(),
+            challenge: self.challenge(),
+            recipient: Some(self.address.clone()),
+            features_title: self.features.sidechain_feature.as_ref().map(ToString::to_string),
+        })
+    }
+}
+
+impl fmt::Display for SidechainOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} uT to {}", self.value, self.address)?;
+        if let Some(feature) = &self.features.sidechain_feature {
+            write!(f, ", {}", feature)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+//! The device's signatures over script challenges
+//! A [`LedgerSignature`] keeps everything it takes to check a signature the device returned: the key it signed with,
+//! the public nonce and scalar of the Schnorr signature and the label the signed message was hashed under, so that a
+//! signature over a change output cannot be mistaken for one over a payment reference. It travels as 96 bytes, `public
+//! key || public nonce || s`, in hex, or with the `serde` feature as a JSON object that also names the label.
+
+use std::fmt;
+
+use tari_crypto::{
+    ristretto::{RistrettoPublicKey, RistrettoSchnorr, RistrettoSecretKey},
+    tari_utilities::{
+        hex::{from_hex, to_hex},
+        ByteArray,
+    },
+};
+
+use crate::{hashing::Challenge, redact::short_hex, verify::verify_script_signature};
+
+/// A signature of the device over the script challenge of a message
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "LedgerSignatureJson", into = "LedgerSignatureJson")
+)]
+pub struct LedgerSignature {
+    pub public_key: RistrettoPublicKey,
+    pub public_nonce: RistrettoPublicKey,
+    pub s: RistrettoSecretKey,
+    /// The label the signed message was hashed under, see [`Challenge::purpose`]
+    pub purpose: &'static str,
+}
+
+impl LedgerSignature {
+    /// The length of [`LedgerSignature::to_bytes`]
+    pub const LENGTH: usize = 3 * 32;
+
+    pub fn new(public_key: RistrettoPublicKey, signature: RistrettoSchnorr, purpose: &'static str) -> Self {
+        Self {
+            public_key,
+            public_nonce: signature.get_public_nonce().clone(),
+            s: signature.get_signature().clone(),
+            purpose,
+        }
+    }
+
+    pub fn schnorr(&self) -> RistrettoSchnorr {
+        RistrettoSchnorr::new(self.public_nonce.clone(), self.s.clone())
+    }
+
+    /// Whether this is a signature over `message`, hashed under the same label
+    pub fn verify(&self, message: &Challenge) -> bool {
+        message.purpose() == self.purpose &&
+            verify_script_signature(&self.public_key, &self.schnorr(), message.as_bytes())
+    }
+
+    /// `public key || public nonce || s`
+    pub fn to_bytes(&self) -> [u8; Self::LENGTH] {
+        let mut bytes = [0u8; Self::LENGTH];
+        bytes[0..32].copy_from_slice(self.public_key.as_bytes());
+        bytes[32..64].copy_from_slice(self.public_nonce.as_bytes());
+        bytes[64..96].copy_from_slice(self.s.as_bytes());
+        bytes
+    }
+
+    /// Decode [`LedgerSignature::to_bytes`] of a signature over a message hashed under `purpose`
+    pub fn from_bytes(purpose: &'static str, bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != Self::LENGTH {
+            return None;
+        }
+        Some(Self {
+            public_key: RistrettoPublicKey::from_bytes(&bytes[0..32]).ok()?,
+            public_nonce: RistrettoPublicKey::from_bytes(&bytes[32..64]).ok()?,
+            s: RistrettoSecretKey::from_bytes(&bytes[64..96]).ok()?,
+            purpose,
+        })
+    }
+
+    pub fn to_hex(&self) -> String {
+        to_hex(&self.to_bytes())
+    }
+
+    pub fn from_hex(purpose: &'static str, hex: &str) -> Option<Self> {
+        Self::from_bytes(purpose, &from_hex(hex).ok()?)
+    }
+}
+
+impl fmt::Display for LedgerSignature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "key {}, nonce {}, s {}",
+            short_hex(self.public_key.as_bytes()),
+            short_hex(self.public_nonce.as_bytes()),
+            short_hex(self.s.as_bytes())
+        )
+    }
+}
+
+/// The JSON form of a [`LedgerSignature`], every key in hex
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct LedgerSignatureJson {
+    purpose: String,
+    public_key: String,
+    public_nonce: String,
+    signature: String,
+}
+
+#[cfg(feature = "serde")]
+impl From<LedgerSignature> for LedgerSignatureJson {
+    fn from(signature: LedgerSignature) -> Self {
+        Self {
+            purpose: signature.purpose.to_string(),
+            public_key: to_hex(signature.public_key.as_bytes()),
+            public_nonce: to_hex(signature.public_nonce.as_bytes()),
+            signature: to_hex(signature.s.as_bytes()),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<LedgerSignatureJson> for LedgerSignature {
+    type Error = String;
+
+    fn try_from(json: LedgerSignatureJson) -> Result<Self, Self::Error> {
+        let purpose = crate::domains::transaction_hash_labels()
+            .find(|label| *label == json.purpose)
+            .ok_or_else(|| format!("'{}' is not a transaction hash label", json.purpose))?;
+        let hex = format!("{}{}{}", json.public_key, json.public_nonce, json.signature);
+        Self::from_hex(purpose, &hex).ok_or_else(|| "the keys of the signature are not valid".to_string())
+    }
+}
@@ -0,0 +1,1253 @@
+//! Transaction level signing on top of [`LedgerDevice`]
+//! A transaction is first summarised on the device for a single user confirmation, after which the script challenge
+//! of every output is signed without further prompts. The fee shown on the device is computed here from the shape of
+//! the transaction, so the host never has to be trusted to report it.
+//! Each summary starts a [`SigningSession`] with a fresh nonce that every output request carries, and the host aborts
+//! sessions that have been open for longer than the configured expiry, so a half signed transaction cannot be finished
+//! long after the fact with outdated context.
+//! Callers that only know the payments can leave the change output to [`LedgerTransactionSigner::sign_with_change`],
+//! which takes its commitment mask and script key from the device.
+//! Transactions with more outputs than fit the totals are announced with a [`DisplaySummary`] first, when the app
+//! supports it, so the user can compare its fingerprint on both screens.
+//! With an [`IdempotencyLog`] every request is keyed by what it signs and its result kept, so a request that is retried
+//! after it failed on the host, e.g. the transport dropped after the device answered, is answered from the log instead
+//! of asking the user to confirm it again.
+//! With a [`SignedOutputLog`] every output signature the device returns is recorded, so that the app's output counter
+//! can be reconciled with what this host asked it to sign.
+//! Under a [`TwoManRule`] a transaction paying out more than its threshold is only sent to the device once a
+//! [`SecondaryConfirmation`] confirmed it.
+
+use std::{
+    fmt,
+    process,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        OnceLock,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use sha2::{Digest, Sha256};
+use tari_crypto::{
+    ristretto::{pedersen::PedersenCommitment, RistrettoPublicKey, RistrettoSchnorr, RistrettoSecretKey},
+    tari_utilities::ByteArray,
+};
+use tari_ledger_protocol::{
+    TransactionSummary,
+    DISPLAY_HINTS_RESPONSE_LENGTH,
+    OUTPUT_ADDRESS_LENGTH,
+    OUTPUT_KIND_CHANGE,
+    OUTPUT_KIND_RECIPIENT,
+    P1_TRANSACTION_APPROVE,
+    P1_TRANSACTION_CANCEL,
+    SETTING_BLIND_SIGNING,
+    SIGN_RESPONSE_LENGTH,
+    TRANSACTION_CANCEL_RESPONSE_LENGTH,
+    TRANSACTION_SUMMARY_RESPONSE_LENGTH,
+};
+
+use crate::{
+    address::TariAddress,
+    app_info::require_setting,
+    commitment::{batch_commitments_with_version, CommitmentRequest},
+    device::{retry_while_locked, Capabilities, DerivationVersion, Instruction, KeyBranch, LedgerDevice, RetryPolicy},
+    display::DisplaySummary,
+    domains::{CHANGE_OUTPUT_LABEL, MESSAGE_LABELS, OUTPUT_CHALLENGE_LABELS},
+    errors::{DeviceError, SignerError, StoreError},
+    export::{branch_path, public_key_with_version},
+    fee::FeeCalculator,
+    hashing::{Challenge, DomainSeparatedConsensusHasher, TransactionHashDomain},
+    policy::{ConfirmationRequest, SecondaryConfirmation, TwoManRule},
+    redact::short_hex,
+    script::{Opcode, TariScript},
+    script_keys::bound_script_public_key,
+    signature::LedgerSignature,
+    state_store::LedgerStateStore,
+    verify::{script_challenge_preimage, verify_script_signature},
+    wallet::wallet_fingerprint,
+};
+
+/// An output whose script challenge the device should sign
+#[derive(Clone, Debug)]
+pub struct OutputToSign {
+    /// Value in microTari
+    pub value: u64,
+    /// Change outputs return funds to this wallet and are not counted as recipients
+    pub is_change: bool,
+    /// Serialized size of the output features and script, used to weigh the output for the fee
+    pub features_and_scripts_size: usize,
+    /// Hashed under one of the [`OUTPUT_CHALLENGE_LABELS`]
+    pub challenge: Challenge,
+    /// The address the device shows for a recipient output before signing it, unused for change
+    pub recipient: Option<TariAddress>,
+    /// What the features of the output register or claim, shown on a page of its own with the display hints. An
+    /// output with a title is only signed by an app that shows display hints.
+    pub features_title: Option<String>,
+}
+
+/// The signatures of a transaction together with the fee the user approved
+#[derive(Clone, Debug)]
+pub struct SignedOutputs {
+    pub fee: u64,
+    /// In the same order as the outputs passed to [`LedgerTransactionSigner::sign_outputs`]
+    pub signatures: Vec<LedgerSignature>,
+}
+
+/// How long a signing session stays valid unless configured otherwise, including the time the user takes to confirm
+pub const DEFAULT_SESSION_EXPIRY: Duration = Duration::from_secs(5 * 60);
+/// A logged signature: the public key, the public nonce and the signature scalar
+const SIGNATURE_RECORD_LENGTH: usize = LedgerSignature::LENGTH;
+/// The consensus encoding of the default output features: version 0, a standard output, no maturity, no coinbase
+/// extra, no sidechain features and a bulletproof+ range proof
+pub const DEFAULT_OUTPUT_FEATURES: [u8; 16] = [0; 16];
+
+/// The change output added by [`LedgerTransactionSigner::sign_with_change`]. Its mask and script key are the keys at
+/// `key_index` of the commitment mask and script key branches of account 0, the script key bound to the commitment if
+/// the signer binds script keys, so the wallet can always recover it from the seed.
+#[derive(Clone, Debug)]
+pub struct ChangeOutput {
+    /// In microTari
+    pub value: u64,
+    pub key_index: u32,
+    pub commitment: PedersenCommitment,
+    pub script_public_key: RistrettoPublicKey,
+    pub script: TariScript,
+    pub features: [u8; 16],
+}
+
+impl ChangeOutput {
+    /// The challenge signed for the output, binding its commitment, script and features
+    pub fn challenge(&self) -> Challenge {
+        DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(CHANGE_OUTPUT_LABEL)
+            .chain(self.commitment.as_public_key())
+            .chain(&self.script)
+            .chain(&self.features)
+            .finalize_challenge()
+    }
+
+    /// The output for the signer to sign, counted as change
+    pub fn to_output(&self) -> OutputToSign {
+        OutputToSign {
+            value: self.value,
+            is_change: true,
+            features_and_scripts_size: self.features.len() + self.script.encoded_len(),
+            challenge: self.challenge(),
+            recipient: None,
+            features_title: None,
+        }
+    }
+}
+
+impl fmt::Display for ChangeOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} uT change at key index {}, commitment {}",
+            self.value,
+            self.key_index,
+            short_hex(self.commitment.as_bytes())
+        )
+    }
+}
+
+/// The size of the features and script of a [`ChangeOutput`], which does not depend on its keys
+pub fn change_output_size() -> usize {
+    DEFAULT_OUTPUT_FEATURES.len() + change_script(RistrettoPublicKey::default()).encoded_len()
+}
+
+/// A signed transaction with the change output the signer built for it
+#[derive(Clone, Debug)]
+pub struct SignedTransaction {
+    /// The signature of the change output, if there is one, comes after those of the payments
+    pub outputs: SignedOutputs,
+    pub change: Option<ChangeOutput>,
+}
+
+/// Whether the signer talks to a real device
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SignerMode {
+    /// Every signature returned by the device is verified
+    #[default]
+    Device,
+    /// The device is a [`DryRunTransport`](crate::dry_run::DryRunTransport) that answers with placeholders, so
+    /// signatures are passed through unverified
+    Offline,
+}
+
+/// Keeps the results of completed signing requests by their idempotency key
+pub trait IdempotencyLog {
+    /// The result stored for the request with `key`, if it completed before
+    fn completed_request(&self, key: &[u8; 32]) -> Result<Option<Vec<u8>>, StoreError>;
+    /// Store `result` as the result of the request with `key`
+    fn complete_request(&self, key: &[u8; 32], result: &[u8]) -> Result<(), StoreError>;
+}
+
+/// Records the outputs the device signed for this host
+pub trait SignedOutputLog {
+    /// Record that the device signed the output with script challenge `challenge`
+    fn record_signed_output(&self, challenge: &Challenge) -> Result<(), StoreError>;
+}
+
+pub struct LedgerTransactionSigner<'a> {
+    device: &'a LedgerDevice,
+    fee_calculator: FeeCalculator,
+    max_fee: Option<u64>,
+    session_expiry: Duration,
+    mode: SignerMode,
+    display_listener: Option<Box<dyn Fn(&DisplaySummary) + 'a>>,
+    allow_silent_outputs: bool,
+    always_display_hints: bool,
+    lock_recovery: Option<LockRecovery<'a>>,
+    idempotency_log: Option<&'a dyn IdempotencyLog>,
+    output_log: Option<&'a dyn SignedOutputLog>,
+    derivation_version: DerivationVersion,
+    bind_script_keys: bool,
+    two_man_rule: Option<(TwoManRule, &'a dyn SecondaryConfirmation)>,
+}
+
+/// How to wait out a device that locks itself halfway through signing
+struct LockRecovery<'a> {
+    policy: RetryPolicy,
+    on_locked: Box<dyn Fn(Duration) + 'a>,
+    /// The wallet the device was unlocked with before it locked, if the app can tell
+    fingerprint: OnceLock<Option<String>>,
+}
+
+impl LockRecovery<'_> {
+    /// Remember the wallet the device is unlocked with, once
+    fn remember_wallet(&self, device: &LedgerDevice) {
+        self.fingerprint.get_or_init(|| wallet_fingerprint(device).ok());
+    }
+
+    /// Wait until the device is unlocked and check that it is unlocked with the same wallet as before
+    fn wait_for_unlock(&self, device: &LedgerDevice) -> Result<(), DeviceError> {
+        let mut prompted = false;
+        let on_locked = |remaining: Duration| {
+            if !prompted {
+                (self.on_locked)(remaining);
+                prompted = true;
+            }
+        };
+        match self.fingerprint.get().cloned().flatten() {
+            Some(expected) => {
+                let actual = retry_while_locked(&self.policy, on_locked, || wallet_fingerprint(device))?;
+                if actual != expected {
+                    return Err(DeviceError::WrongWallet { expected, actual });
+                }
+                Ok(())
+            },
+            None => retry_while_locked(&self.policy, on_locked, || {
+                device.send(Instruction::GetVersion, 0x00, 0x00, vec![])
+            })
+            .map(|_| ()),
+        }
+    }
+}
+
+impl<'a> LedgerTransactionSigner<'a> {
+    pub fn new(device: &'a LedgerDevice, fee_calculator: FeeCalculator) -> Self {
+        Self {
+            device,
+            fee_calculator,
+            max_fee: None,
+            session_expiry: DEFAULT_SESSION_EXPIRY,
+            mode: SignerMode::Device,
+            display_listener: None,
+            allow_silent_outputs: false,
+            always_display_hints: false,
+            lock_recovery: None,
+            idempotency_log: None,
+            output_log: None,
+            derivation_version: DerivationVersion::Legacy,
+            bind_script_keys: false,
+            two_man_rule: None,
+        }
+    }
+
+    pub fn device(&self) -> &LedgerDevice {
+        self.device
+    }
+
+    /// Refuse to sign any transaction whose fee is above `max_fee` microTari
+    pub fn with_max_fee(mut self, max_fee: u64) -> Self {
+        self.max_fee = Some(max_fee);
+        self
+    }
+
+    /// Abort signing sessions once they are older than `expiry`
+    pub fn with_session_expiry(mut self, expiry: Duration) -> Self {
+        self.session_expiry = expiry;
+        self
+    }
+
+    pub fn with_mode(mut self, mode: SignerMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Call `listener` with the display hints of every transaction sent with them, before the device asks the user to
+    /// confirm, so the host can show the fingerprint to compare
+    pub fn with_display_listener(mut self, listener: impl Fn(&DisplaySummary) + 'a) -> Self {
+        self.display_listener = Some(Box::new(listener));
+        self
+    }
+
+    /// Sign outputs the device cannot show one by one, because the app predates output confirmation or the output has
+    /// no recipient address. By default such transactions are refused before the summary is sent.
+    pub fn with_silent_outputs(mut self, allowed: bool) -> Self {
+        self.allow_silent_outputs = allowed;
+        self
+    }
+
+    /// Send display hints with every transaction, not only with those too large to confirm from the totals, so that
+    /// the digest the user compares always covers every output
+    pub fn with_display_hints(mut self, always: bool) -> Self {
+        self.always_display_hints = always;
+        self
+    }
+
+    /// Pause instead of failing when the device locks itself halfway through a transaction, e.g. because the user
+    /// took longer to confirm than its auto-lock allows. `on_locked` is called with the time left under `policy`
+    /// whenever the device locks, e.g. to ask the user to unlock it. Once it is unlocked with the same wallet,
+    /// signing resumes with the step that was interrupted: the app keeps the approved transaction while the device is
+    /// locked. The time spent locked still counts towards the session expiry.
+    pub fn with_lock_recovery(mut self, policy: RetryPolicy, on_locked: impl Fn(Duration) + 'a) -> Self {
+        self.lock_recovery = Some(LockRecovery {
+            policy,
+            on_locked: Box::new(on_locked),
+            fingerprint: OnceLock::new(),
+        });
+        self
+    }
+
+    /// Answer a request that completed before from `log` instead of sending it to the device again, and keep the
+    /// result of every new request in it. A request is the same if it signs the same challenges with the same wallet
+    /// for the same fee.
+    pub fn with_idempotency_log(mut self, log: &'a dyn IdempotencyLog) -> Self {
+        self.idempotency_log = Some(log);
+        self
+    }
+
+    /// Record every output the device signs in `log`
+    pub fn with_output_log(mut self, log: &'a dyn SignedOutputLog) -> Self {
+        self.output_log = Some(log);
+        self
+    }
+
+    /// Derive the keys of change outputs and sign script messages under `version` rather than the legacy scheme
+    pub fn with_derivation_version(mut self, version: DerivationVersion) -> Self {
+        self.derivation_version = version;
+        self
+    }
+
+    /// Bind the script key of every change output to its commitment, see [`script_keys`](crate::script_keys)
+    pub fn with_bound_script_keys(mut self, bind: bool) -> Self {
+        self.bind_script_keys = bind;
+        self
+    }
+
+    /// Have `confirmation` confirm every transaction that `rule` applies to before anything is sent to the device,
+    /// and refuse to sign those it does not confirm
+    pub fn with_two_man_rule(mut self, rule: TwoManRule, confirmation: &'a dyn SecondaryConfirmation) -> Self {
+        self.two_man_rule = Some((rule, confirmation));
+        self
+    }
+
+    pub fn mode(&self) -> SignerMode {
+        self.mode
+    }
+
+    pub fn derivation_version(&self) -> DerivationVersion {
+        self.derivation_version
+    }
+
+    /// The fee of a single kernel transaction spending `num_inputs` inputs into `outputs`
+    pub fn fee(&self, num_inputs: usize, outputs: &[OutputToSign]) -> u64 {
+        let sizes = outputs
+            .iter()
+            .map(|output| output.features_and_scripts_size)
+            .collect::<Vec<_>>();
+        self.fee_calculator.calculate(1, num_inputs, &sizes)
+    }
+
+    /// Summarise the transaction for the user to confirm once, then sign all of its `outputs`. All returned
+    /// signatures have been verified.
+    pub fn sign_outputs(&self, num_inputs: usize, outputs: &[OutputToSign]) -> Result<SignedOutputs, SignerError> {
+        let key = self.idempotency_key(b"sign_outputs", |hash| {
+            hash.update((num_inputs as u64).to_le_bytes());
+            hash.update(self.fee(num_inputs, outputs).to_le_bytes());
+            for output in outputs {
+                hash.update([u8::from(output.is_change)]);
+                hash.update(output.value.to_le_bytes());
+                hash.update(output.challenge.as_bytes());
+                hash.update(
+                    output
+                        .recipient
+                        .as_ref()
+                        .map(|recipient| recipient.to_bytes().to_vec())
+                        .unwrap_or_default(),
+                );
+            }
+        });
+        let challenges = outputs.iter().map(|output| &output.challenge).collect::<Vec<_>>();
+        if let Some(signed) = self.completed_request(key.as_ref(), &challenges)? {
+            return Ok(signed);
+        }
+
+        let signed = self
+            .transaction_session(num_inputs, outputs.to_vec())
+            .approve()?
+            .sign_all()?
+            .into_signed_outputs();
+        self.complete_request(key.as_ref(), &signed);
+        Ok(signed)
+    }
+
+    /// Sign `payments` funded by `num_inputs` inputs worth `input_value` microTari, adding a change output for
+    /// whatever the payments and the fee leave over. The change keys are taken from the next unused index in
+    /// `store`. No change output is added when the inputs exactly cover the payments and the fee.
+    pub fn sign_with_change(
+        &self,
+        store: &dyn LedgerStateStore,
+        num_inputs: usize,
+        input_value: u64,
+        payments: &[OutputToSign],
+    ) -> Result<SignedTransaction, SignerError> {
+        let payment_total = payments
+            .iter()
+            .try_fold(0u64, |total, output| total.checked_add(output.value))
+            .ok_or(SignerError::ValueOverflow)?;
+        if Some(input_value) == payment_total.checked_add(self.fee(num_inputs, payments)) {
+            let outputs = self.sign_outputs(num_inputs, payments)?;
+            return Ok(SignedTransaction { outputs, change: None });
+        }
+
+        // The size of the change script does not depend on the key, so the funds are checked before reserving one
+        let mut sizes = payments
+            .iter()
+            .map(|output| output.features_and_scripts_size)
+            .collect::<Vec<_>>();
+        sizes.push(change_output_size());
+        let fee = self.fee_calculator.calculate(1, num_inputs, &sizes);
+        let required = payment_total.checked_add(fee).ok_or(SignerError::ValueOverflow)?;
+        let value = match input_value.checked_sub(required) {
+            Some(value) if value > 0 => value,
+            // A change output has to hold something
+            _ => {
+                return Err(SignerError::InsufficientFunds {
+                    available: input_value,
+                    required: required.saturating_add(1),
+                })
+            },
+        };
+
+        let change = self
+            .change_outputs(store, &[value])?
+            .pop()
+            .ok_or(DeviceError::InvalidResponse("the device returned no change commitment"))?;
+        let mut outputs = payments.to_vec();
+        outputs.push(change.to_output());
+        Ok(SignedTransaction {
+            outputs: self.sign_outputs(num_inputs, &outputs)?,
+            change: Some(change),
+        })
+    }
+
+    /// Build an output back to this wallet for each of `values`, each with the keys at the next unused index in
+    /// `store`. The commitments are requested in one batch.
+    pub fn change_outputs(
+        &self,
+        store: &dyn LedgerStateStore,
+        values: &[u64],
+    ) -> Result<Vec<ChangeOutput>, SignerError> {
+        let mut requests = Vec::with_capacity(values.len());
+        for value in values {
+            requests.push(CommitmentRequest {
+                value: *value,
+                index: self.reserve_change_index(store)?,
+            });
+        }
+        let commitments = batch_commitments_with_version(self.device, &requests, self.derivation_version)?;
+        requests
+            .iter()
+            .zip(commitments)
+            .map(|(request, commitment)| {
+                let script_public_key = public_key_with_version(
+                    self.device,
+                    0,
+                    KeyBranch::ScriptKey,
+                    request.index,
+                    self.derivation_version,
+                )?;
+                let script_public_key = if self.bind_script_keys {
+                    bound_script_public_key(&script_public_key, &commitment)
+                } else {
+                    script_public_key
+                };
+                Ok(ChangeOutput {
+                    value: request.value,
+                    key_index: request.index,
+                    commitment,
+                    script: change_script(script_public_key.clone()),
+                    script_public_key,
+                    features: DEFAULT_OUTPUT_FEATURES,
+                })
+            })
+            .collect()
+    }
+
+    fn reserve_change_index(&self, store: &dyn LedgerStateStore) -> Result<u32, SignerError> {
+        let index = store.next_key_index(&branch_path(0, KeyBranch::CommitmentMask))?;
+        let out_of_range = StoreError::Corrupt("the change key index is out of range");
+        // Key indices must stay below the hardened range
+        u32::try_from(index)
+            .ok()
+            .filter(|index| *index < 0x8000_0000)
+            .ok_or(SignerError::Store(out_of_range))
+    }
+
+    /// A [`TransactionSession`] to sign `outputs` with, which has yet to be approved
+    pub fn transaction_session(
+        &self,
+        num_inputs: usize,
+        outputs: Vec<OutputToSign>,
+    ) -> TransactionSession<AwaitingApproval<'_>> {
+        TransactionSession {
+            state: AwaitingApproval {
+                signer: self,
+                num_inputs,
+            },
+            outputs,
+            signatures: Vec::new(),
+        }
+    }
+
+    /// Summarise the transaction for the user to confirm, returning a session to sign `outputs` with, in order
+    pub fn begin_transaction(
+        &self,
+        num_inputs: usize,
+        outputs: &[OutputToSign],
+    ) -> Result<SigningSession<'_>, SignerError> {
+        // Before the first APDU, so that a transaction nobody confirmed never reaches the device
+        let fee = self.fee(num_inputs, outputs);
+        if let Some((rule, confirmation)) = &self.two_man_rule {
+            let request = ConfirmationRequest::for_transaction(outputs, fee, rule.threshold);
+            if rule.applies_to(request.amount) {
+                confirmation
+                    .confirm(&request)
+                    .map_err(|reason| SignerError::NotConfirmed {
+                        amount: request.amount,
+                        threshold: rule.threshold,
+                        reason,
+                    })?;
+            }
+        }
+        self.device.require(Capabilities::BATCH_SIGNING)?;
+        for output in outputs {
+            check_purpose(&output.challenge, &OUTPUT_CHALLENGE_LABELS)?;
+        }
+        if let Some(recovery) = &self.lock_recovery {
+            recovery.remember_wallet(self.device);
+        }
+        if let Some(max_fee) = self.max_fee {
+            if fee > max_fee {
+                return Err(SignerError::FeeTooHigh { fee, max_fee });
+            }
+        }
+        let confirm_outputs = if self.allow_silent_outputs {
+            self.device.capabilities()?.contains(Capabilities::OUTPUT_CONFIRMATION)
+        } else {
+            self.device.require(Capabilities::OUTPUT_CONFIRMATION)?;
+            if let Some(index) = outputs
+                .iter()
+                .position(|output| !output.is_change && output.recipient.is_none())
+            {
+                return Err(SignerError::MissingRecipient { index });
+            }
+            true
+        };
+        let started = Instant::now();
+        let started_at = SystemTime::now();
+        let nonce = session_nonce(started_at);
+        let summary = summarise(outputs, fee, nonce)?;
+        if outputs.iter().any(|output| output.features_title.is_some()) {
+            self.device.require(Capabilities::DISPLAY_HINTS)?;
+        }
+        let display = if (self.always_display_hints || DisplaySummary::is_needed(outputs)) &&
+            self.device.capabilities()?.contains(Capabilities::DISPLAY_HINTS)
+        {
+            let display = DisplaySummary::for_transaction(&summary, outputs);
+            let response = self
+                .device
+                .send(Instruction::DisplayHints, 0x00, 0x00, display.to_request())?;
+            self.device.response_payload(&response, DISPLAY_HINTS_RESPONSE_LENGTH)?;
+            if let Some(listener) = &self.display_listener {
+                listener(&display);
+            }
+            Some(display)
+        } else {
+            None
+        };
+        let response = self.device.send(
+            Instruction::TransactionSummary,
+            P1_TRANSACTION_APPROVE,
+            0x00,
+            summary.to_le_bytes().to_vec(),
+        )?;
+        self.device
+            .response_payload(&response, TRANSACTION_SUMMARY_RESPONSE_LENGTH)?;
+
+        Ok(SigningSession {
+            device: self.device,
+            mode: self.mode,
+            expiry: self.session_expiry,
+            confirm_outputs,
+            nonce,
+            started,
+            started_at,
+            fee,
+            display,
+            lock_recovery: self.lock_recovery.as_ref(),
+            output_log: self.output_log,
+            signed: 0,
+        })
+    }
+
+    /// Sign a standalone script signature message, e.g. to spend a script locked output. The device cannot show what
+    /// the message commits to, so the user has to enable blind signing first. `message` has to be hashed under one of
+    /// the [`MESSAGE_LABELS`].
+    pub fn sign_script_message(&self, message: &Challenge) -> Result<LedgerSignature, SignerError> {
+        check_purpose(message, &MESSAGE_LABELS)?;
+        let key = self.idempotency_key(b"sign_script_message", |hash| {
+            hash.update([self.derivation_version.as_byte()]);
+            hash.update(message.as_bytes());
+        });
+        if let Some(mut signed) = self.completed_request(key.as_ref(), &[message])? {
+            return Ok(signed.signatures.remove(0));
+        }
+
+        require_setting(self.device, SETTING_BLIND_SIGNING)?;
+        let p2 = self.device.derivation_p2(self.derivation_version)?;
+        let recovery = self.lock_recovery.as_ref();
+        if let Some(recovery) = recovery {
+            recovery.remember_wallet(self.device);
+        }
+        let response = send_resuming(
+            self.device,
+            recovery,
+            Instruction::Sign,
+            p2,
+            message.as_bytes().to_vec(),
+        )?;
+        let signature = verify_signature_response(self.device, &response, message, 0, self.mode)?;
+        self.complete_request(key.as_ref(), &SignedOutputs {
+            fee: 0,
+            signatures: vec![signature.clone()],
+        });
+        Ok(signature)
+    }
+
+    /// The idempotency key of a request of `kind` whose contents `hash_request` hashes, if there is a log to look it up
+    /// in. The key covers the wallet the device is unlocked with, another wallet signs with other keys.
+    fn idempotency_key(&self, kind: &[u8], hash_request: impl FnOnce(&mut Sha256)) -> Option<[u8; 32]> {
+        if self.idempotency_log.is_none() {
+            return None;
+        }
+        let mut hash = Sha256::new();
+        hash.update(kind);
+        hash.update(wallet_fingerprint(self.device).unwrap_or_default());
+        hash_request(&mut hash);
+        Some(hash.finalize().into())
+    }
+
+    /// The stored result of the request with `key`, which signed `challenges`. The signatures are verified again before
+    /// they are returned.
+    fn completed_request(
+        &self,
+        key: Option<&[u8; 32]>,
+        challenges: &[&Challenge],
+    ) -> Result<Option<SignedOutputs>, SignerError> {
+        let result = match (self.idempotency_log, key) {
+            (Some(log), Some(key)) => log.completed_request(key)?,
+            _ => None,
+        };
+        let result = match result {
+            Some(result) => result,
+            None => return Ok(None),
+        };
+        let corrupt = || SignerError::Store(StoreError::Corrupt("a logged result does not match the request"));
+        if result.len() != 8 + challenges.len() * SIGNATURE_RECORD_LENGTH {
+            return Err(corrupt());
+        }
+        let mut fee = [0u8; 8];
+        fee.copy_from_slice(&result[..8]);
+        let signatures = result[8..]
+            .chunks(SIGNATURE_RECORD_LENGTH)
+            .zip(challenges)
+            .map(|(record, challenge)| {
+                let signature = LedgerSignature::from_bytes(challenge.purpose(), record).ok_or_else(corrupt)?;
+                if self.mode == SignerMode::Device && !signature.verify(challenge) {
+                    return Err(corrupt());
+                }
+                Ok(signature)
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Some(SignedOutputs {
+            fee: u64::from_le_bytes(fee),
+            signatures,
+        }))
+    }
+
+    /// Keep `signed` as the result of the request with `key`. Failing to keep it only costs a prompt on a retry, so it
+    /// does not fail the request.
+    fn complete_request(&self, key: Option<&[u8; 32]>, signed: &SignedOutputs) {
+        let (log, key) = match (self.idempotency_log, key) {
+            (Some(log), Some(key)) => (log, key),
+            _ => return,
+        };
+        let mut result = signed.fee.to_le_bytes().to_vec();
+        for signature in &signed.signatures {
+            result.extend_from_slice(&signature.to_bytes());
+        }
+        let _ = log.complete_request(key, &result);
+    }
+}
+
+/// The outputs of one approved transaction summary, signed one at a time
+pub struct SigningSession<'a> {
+    device: &'a LedgerDevice,
+    mode: SignerMode,
+    expiry: Duration,
+    /// Whether outputs are sent with `Instruction::SignConfirmedOutput`
+    confirm_outputs: bool,
+    nonce: u64,
+    started: Instant,
+    started_at: SystemTime,
+    fee: u64,
+    display: Option<DisplaySummary>,
+    lock_recovery: Option<&'a LockRecovery<'a>>,
+    output_log: Option<&'a dyn SignedOutputLog>,
+    signed: usize,
+}
+
+impl SigningSession<'_> {
+    pub fn nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    /// When the session was started, by the host clock
+    pub fn started_at(&self) -> SystemTime {
+        self.started_at
+    }
+
+    /// The fee the user approved
+    pub fn fee(&self) -> u64 {
+        self.fee
+    }
+
+    /// The display hints the transaction was announced with, if it was
+    pub fn display_summary(&self) -> Option<&DisplaySummary> {
+        self.display.as_ref()
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.started.elapsed() > self.expiry
+    }
+
+    /// Sign the next output of the approved transaction, failing with [`SignerError::SessionExpired`] once the
+    /// session is too old. The device shows the amount and recipient of the output first, if it can. It voids the
+    /// approval on any error or rejection, so a failed session cannot be resumed, but a device that locks itself is
+    /// waited out if the signer was built [`with_lock_recovery`](LedgerTransactionSigner::with_lock_recovery).
+    pub fn sign_output(&mut self, output: &OutputToSign) -> Result<LedgerSignature, SignerError> {
+        let age = self.started.elapsed();
+        if age > self.expiry {
+            return Err(SignerError::SessionExpired { age });
+        }
+        check_purpose(&output.challenge, &OUTPUT_CHALLENGE_LABELS)?;
+        let kind = if output.is_change {
+            OUTPUT_KIND_CHANGE
+        } else {
+            OUTPUT_KIND_RECIPIENT
+        };
+        let mut data = vec![kind];
+        data.extend_from_slice(&output.value.to_le_bytes());
+        data.extend_from_slice(&self.nonce.to_le_bytes());
+        data.extend_from_slice(output.challenge.as_bytes());
+        let instruction = match &output.recipient {
+            Some(recipient) if self.confirm_outputs && !output.is_change => {
+                data.extend_from_slice(&recipient.to_bytes());
+                Instruction::SignConfirmedOutput
+            },
+            _ if self.confirm_outputs && output.is_change => {
+                data.extend_from_slice(&[0u8; OUTPUT_ADDRESS_LENGTH]);
+                Instruction::SignConfirmedOutput
+            },
+            _ => Instruction::SignOutput,
+        };
+        let response = send_resuming(self.device, self.lock_recovery, instruction, 0x00, data)?;
+        // The app counted the output once it answered. One that fails to be recorded shows up as unrecorded, which errs
+        // on the safe side rather than failing a signature the device already produced.
+        if let Some(log) = self.output_log {
+            let _ = log.record_signed_output(&output.challenge);
+        }
+        let signature = verify_signature_response(self.device, &response, &output.challenge, self.signed, self.mode)?;
+        self.signed += 1;
+        Ok(signature)
+    }
+}
+
+/// A transaction signing session whose state is part of its type: its outputs can only be signed once the user
+/// approved the summary, in order, and the signatures only be taken once every output is signed. The kernel is not
+/// part of it: the kernel nonce is taken while outputs are still left to sign, and a kernel that only receives has no
+/// session at all. The app holds a kernel that spends to the fee of the approved transaction instead, see
+/// [`kernel_nonce`](crate::kernel::kernel_nonce).
+pub struct TransactionSession<S> {
+    state: S,
+    outputs: Vec<OutputToSign>,
+    signatures: Vec<LedgerSignature>,
+}
+
+/// The outputs are known but the user has not seen the summary yet
+pub struct AwaitingApproval<'a> {
+    signer: &'a LedgerTransactionSigner<'a>,
+    num_inputs: usize,
+}
+
+/// The user approved the summary and outputs are left to sign
+pub struct AwaitingSignatures<'a> {
+    session: SigningSession<'a>,
+}
+
+/// Every output is signed
+pub struct Finalized {
+    fee: u64,
+    display: Option<DisplaySummary>,
+}
+
+/// What signing one more output of a [`TransactionSession`] leaves it at
+pub enum SessionProgress<'a> {
+    Pending(TransactionSession<AwaitingSignatures<'a>>),
+    Complete(TransactionSession<Finalized>),
+}
+
+impl<S> TransactionSession<S> {
+    pub fn outputs(&self) -> &[OutputToSign] {
+        &self.outputs
+    }
+
+    /// The signatures of the outputs signed so far, in order
+    pub fn signatures(&self) -> &[LedgerSignature] {
+        &self.signatures
+    }
+}
+
+impl<'a> TransactionSession<AwaitingApproval<'a>> {
+    /// Summarise the transaction on the device for the user to confirm, see
+    /// [`LedgerTransactionSigner::begin_transaction`]
+    pub fn approve(self) -> Result<TransactionSession<AwaitingSignatures<'a>>, SignerError> {
+        let session = self
+            .state
+            .signer
+            .begin_transaction(self.state.num_inputs, &self.outputs)?;
+        Ok(TransactionSession {
+            state: AwaitingSignatures { session },
+            outputs: self.outputs,
+            signatures: self.signatures,
+        })
+    }
+}
+
+impl<'a> TransactionSession<AwaitingSignatures<'a>> {
+    /// The fee the user approved
+    pub fn fee(&self) -> u64 {
+        self.state.session.fee()
+    }
+
+    /// The nonce the approved summary was sent with
+    pub fn nonce(&self) -> u64 {
+        self.state.session.nonce()
+    }
+
+    pub fn display_summary(&self) -> Option<&DisplaySummary> {
+        self.state.session.display_summary()
+    }
+
+    /// Sign the next output, see [`SigningSession::sign_output`]
+    pub fn sign_next(mut self) -> Result<SessionProgress<'a>, SignerError> {
+        if let Some(output) = self.outputs.get(self.signatures.len()) {
+            let signature = self.state.session.sign_output(output)?;
+            self.signatures.push(signature);
+        }
+        if self.signatures.len() < self.outputs.len() {
+            return Ok(SessionProgress::Pending(self));
+        }
+        Ok(SessionProgress::Complete(TransactionSession {
+            state: Finalized {
+                fee: self.state.session.fee(),
+                display: self.state.session.display.take(),
+            },
+            outputs: self.outputs,
+            signatures: self.signatures,
+        }))
+    }
+
+    /// Sign every output that is left
+    pub fn sign_all(self) -> Result<TransactionSession<Finalized>, SignerError> {
+        let mut session = self;
+        loop {
+            match session.sign_next()? {
+                SessionProgress::Pending(next) => session = next,
+                SessionProgress::Complete(finalized) => return Ok(finalized),
+            }
+        }
+    }
+}
+
+impl TransactionSession<Finalized> {
+    /// The fee the user approved
+    pub fn fee(&self) -> u64 {
+        self.state.fee
+    }
+
+    pub fn display_summary(&self) -> Option<&DisplaySummary> {
+        self.state.display.as_ref()
+    }
+
+    pub fn into_signed_outputs(self) -> SignedOutputs {
+        SignedOutputs {
+            fee: self.state.fee,
+            signatures: self.signatures,
+        }
+    }
+}
+
+/// Drop the transaction the user approved on the device, with its display hints and kept kernel, so that nothing can be
+/// signed under it anymore, e.g. once the host that asked for it has gone away
+pub fn cancel_transaction(device: &LedgerDevice) -> Result<(), DeviceError> {
+    device.require(Capabilities::TRANSACTION_CANCEL)?;
+    let response = device.send(Instruction::TransactionSummary, P1_TRANSACTION_CANCEL, 0x00, vec![])?;
+    device.response_payload(&response, TRANSACTION_CANCEL_RESPONSE_LENGTH)?;
+    Ok(())
+}
+
+/// Send a signing request. If the device locked itself before the request reached the app and `recovery` is set,
+/// wait for it to be unlocked with the same wallet and send the request again.
+fn send_resuming(
+    device: &LedgerDevice,
+    recovery: Option<&LockRecovery>,
+    instruction: Instruction,
+    p2: u8,
+    data: Vec<u8>,
+) -> Result<Vec<u8>, DeviceError> {
+    match (device.send(instruction, 0x00, p2, data.clone()), recovery) {
+        (Err(DeviceError::DeviceLocked), Some(recovery)) => {
+            recovery.wait_for_unlock(device)?;
+            device.send(instruction, 0x00, p2, data)
+        },
+        (result, _) => result,
+    }
+}
+
+/// Refuse to sign `challenge` unless it was hashed under one of `labels`
+fn check_purpose(challenge: &Challenge, labels: &[&str]) -> Result<(), SignerError> {
+    if labels.contains(&challenge.purpose()) {
+        Ok(())
+    } else {
+        Err(SignerError::WrongPurpose(challenge.purpose()))
+    }
+}
+
+/// Change is locked to the script key, the default script of a one-sided wallet output
+fn change_script(script_public_key: RistrettoPublicKey) -> TariScript {
+    TariScript::new(vec![Opcode::PushPubKey(script_public_key)])
+}
+
+/// A nonce that is unique per session. It does not have to be secret, only never reused, so it mixes the host time
+/// with the process id and a per process counter.
+fn session_nonce(started_at: SystemTime) -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = started_at.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+    let hash = Sha256::new()
+        .chain_update(nanos.to_le_bytes())
+        .chain_update(process::id().to_le_bytes())
+        .chain_update(COUNTER.fetch_add(1, Ordering::Relaxed).to_le_bytes())
+        .finalize();
+    let mut nonce = [0u8; 8];
+    nonce.copy_from_slice(&hash[..8]);
+    u64::from_le_bytes(nonce)
+}
+
+/// Parse a `[format][public key][s][public nonce]` response and, unless offline, check the signature over `challenge`
+fn verify_signature_response(
+    device: &LedgerDevice,
+    response: &[u8],
+    challenge: &Challenge,
+    index: usize,
+    mode: SignerMode,
+) -> Result<LedgerSignature, SignerError> {
+    let purpose = challenge.purpose();
+    let challenge = challenge.as_bytes();
+    let payload = device.response_payload(response, SIGN_RESPONSE_LENGTH)?;
+
+    let malformed = || SignerError::InvalidSignature { index, preimage: None };
+    let public_key = RistrettoPublicKey::from_bytes(&payload[0..32]).map_err(|_| malformed())?;
+    let s = RistrettoSecretKey::from_bytes(&payload[32..64]).map_err(|_| malformed())?;
+    let nonce = RistrettoPublicKey::from_bytes(&payload[64..96]).map_err(|_| malformed())?;
+
+    if mode == SignerMode::Device {
+        // Placeholder signatures of a dry run all share the same nonce
+        device.check_nonce(&nonce, challenge)?;
+    }
+    let signature = RistrettoSchnorr::new(nonce, s);
+    if mode == SignerMode::Device && !verify_script_signature(&public_key, &signature, challenge) {
+        return Err(SignerError::InvalidSignature {
+            index,
+            preimage: Some(Box::new(script_challenge_preimage(
+                &public_key,
+                signature.get_public_nonce(),
+                challenge,
+            ))),
+        });
+    }
+    Ok(LedgerSignature::new(public_key, signature, purpose))
+}
+
+/// Build the summary the user confirms on the device
+pub fn summarise(outputs: &[OutputToSign], fee: u64, session_nonce: u64) -> Result<TransactionSummary, SignerError> {
+    let output_count = u8::try_from(outputs.len()).map_err(|_| SignerError::TooManyOutputs(outputs.len()))?;
+    let recipients = outputs.iter().filter(|output| !output.is_change);
+    let total_out = recipients
+        .clone()
+        .try_fold(0u64, |total, output| total.checked_add(output.value))
+        .ok_or(SignerError::ValueOverflow)?;
+    Ok(TransactionSummary {
+        total_out,
+        fee,
+        // Cannot overflow, there are no more recipients than outputs
+        recipient_count: recipients.count() as u8,
+        output_count,
+        session_nonce,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+
+    use tari_crypto::keys::PublicKey;
+
+    use super::*;
+    use crate::{
+        address::Network,
+        domains::{SCRIPT_CHALLENGE_LABEL, SCRIPT_MESSAGE_LABEL},
+        dry_run::{DryRunLog, DryRunTransport},
+    };
+
+    fn software_device(log: &DryRunLog) -> LedgerDevice {
+        LedgerDevice::from_transport(DryRunTransport::new(log.clone()).with_software_keys(7))
+    }
+
+    fn payment(value: u64, n: u64) -> OutputToSign {
+        let public_key = RistrettoPublicKey::from_secret_key(&RistrettoSecretKey::from(n));
+        OutputToSign {
+            value,
+            is_change: false,
+            features_and_scripts_size: 40,
+            challenge: DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(SCRIPT_CHALLENGE_LABEL)
+                .chain(&n)
+                .finalize_challenge(),
+            recipient: Some(TariAddress::new(public_key, Network::Esmeralda)),
+            features_title: None,
+        }
+    }
+
+    /// Whether the log holds a transaction summary, the first command that puts anything in front of the user
+    fn summarised(log: &DryRunLog) -> bool {
+        log.exchanges()
+            .iter()
+            .any(|exchange| exchange.command[1] == Instruction::TransactionSummary.as_byte())
+    }
+
+    /// A second operator who answers every request the same way and counts them
+    struct Operator {
+        confirms: bool,
+        asked: Cell<usize>,
+    }
+
+    impl SecondaryConfirmation for Operator {
+        fn confirm(&self, _request: &ConfirmationRequest) -> Result<(), String> {
+            self.asked.set(self.asked.get() + 1);
+            if self.confirms {
+                Ok(())
+            } else {
+                Err("refused".to_string())
+            }
+        }
+    }
+
+    #[test]
+    fn a_transaction_is_signed_and_verified() {
+        let log = DryRunLog::new();
+        let device = software_device(&log);
+        let signer = LedgerTransactionSigner::new(&device, FeeCalculator::new(5));
+        let outputs = [payment(1_000, 1), payment(2_000, 2)];
+        let signed = signer.sign_outputs(1, &outputs).unwrap();
+        assert_eq!(signed.fee, signer.fee(1, &outputs));
+        assert_eq!(signed.signatures.len(), 2);
+        for (output, signature) in outputs.iter().zip(&signed.signatures) {
+            assert!(signature.verify(&output.challenge));
+        }
+    }
+
+    #[test]
+    fn the_two_man_rule_holds_a_large_transaction_back() {
+        let rule = TwoManRule {
+            threshold: 5_000,
+            token_sha256: None,
+        };
+        let refusing = Operator {
+            confirms: false,
+            asked: Cell::new(0),
+        };
+        let log = DryRunLog::new();
+        let device = software_device(&log);
+        let signer =
+            LedgerTransactionSigner::new(&device, FeeCalculator::new(5)).with_two_man_rule(rule.clone(), &refusing);
+        match signer.sign_outputs(1, &[payment(10_000, 1)]) {
+            Err(SignerError::NotConfirmed { amount, threshold, .. }) => {
+                assert!(amount > 10_000);
+                assert_eq!(threshold, 5_000);
+            },
+            other => panic!("a refused transaction was not held back: {:?}", other.map(|_| ())),
+        }
+        assert_eq!(refusing.asked.get(), 1);
+        assert!(
+            log.exchanges().is_empty(),
+            "the device heard of a transaction nobody confirmed"
+        );
+
+        // Below the threshold the second operator is not asked
+        let signed = signer.sign_outputs(1, &[payment(1_000, 1)]);
+        assert!(signed.is_ok());
+        assert_eq!(refusing.asked.get(), 1);
+
+        let confirming = Operator {
+            confirms: true,
+            asked: Cell::new(0),
+        };
+        let signer = LedgerTransactionSigner::new(&device, FeeCalculator::new(5)).with_two_man_rule(rule, &confirming);
+        assert!(signer.sign_outputs(1, &[payment(10_000, 1)]).is_ok());
+        assert_eq!(confirming.asked.get(), 1);
+    }
+
+    #[test]
+    fn a_fee_above_the_maximum_is_refused() {
+        let log = DryRunLog::new();
+        let device = software_device(&log);
+        let outputs = [payment(1_000, 1)];
+        let signer = LedgerTransactionSigner::new(&device, FeeCalculator::new(5));
+        let fee = signer.fee(1, &outputs);
+        let signer = signer.with_max_fee(fee - 1);
+        match signer.sign_outputs(1, &outputs) {
+            Err(SignerError::FeeTooHigh { fee: refused, max_fee }) => {
+                assert_eq!(refused, fee);
+                assert_eq!(max_fee, fee - 1);
+            },
+            other => panic!("the fee was not refused: {:?}", other.map(|_| ())),
+        }
+        assert!(!summarised(&log));
+
+        let signer = LedgerTransactionSigner::new(&device, FeeCalculator::new(5)).with_max_fee(fee);
+        assert!(signer.sign_outputs(1, &outputs).is_ok());
+    }
+
+    #[test]
+    fn a_payment_without_an_address_is_refused_unless_silent_outputs_are_allowed() {
+        let log = DryRunLog::new();
+        let device = software_device(&log);
+        let silent = OutputToSign {
+            recipient: None,
+            ..payment(1_000, 2)
+        };
+        let outputs = [payment(1_000, 1), silent];
+        let signer = LedgerTransactionSigner::new(&device, FeeCalculator::new(5));
+        assert!(matches!(
+            signer.sign_outputs(1, &outputs),
+            Err(SignerError::MissingRecipient { index: 1 })
+        ));
+        assert!(!summarised(&log));
+
+        let signer = signer.with_silent_outputs(true);
+        assert_eq!(signer.sign_outputs(1, &outputs).unwrap().signatures.len(), 2);
+    }
+
+    #[test]
+    fn cancelling_sends_the_cancel_summary() {
+        let log = DryRunLog::new();
+        let device = software_device(&log);
+        cancel_transaction(&device).unwrap();
+        let cancel = log.exchanges().pop().unwrap().command;
+        assert_eq!(cancel[1], Instruction::TransactionSummary.as_byte());
+        assert_eq!(cancel[2], P1_TRANSACTION_CANCEL);
+        // No data but the length byte
+        assert_eq!(cancel.len(), 5);
+    }
+
+    #[test]
+    fn signature_responses_are_verified() {
+        let log = DryRunLog::new();
+        let device = software_device(&log);
+        let message = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(SCRIPT_MESSAGE_LABEL)
+            .chain(&42u64)
+            .finalize_challenge();
+        let response = device
+            .send(Instruction::Sign, 0x00, 0x00, message.as_bytes().to_vec())
+            .unwrap();
+        let signature = verify_signature_response(&device, &response, &message, 0, SignerMode::Device).unwrap();
+        assert!(signature.verify(&message));
+
+        // A signature over another challenge fails with the preimage it was checked under
+        let other = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(SCRIPT_MESSAGE_LABEL)
+            .chain(&43u64)
+            .finalize_challenge();
+        let fresh = software_device(&log);
+        assert!(matches!(
+            verify_signature_response(&fresh, &response, &other, 3, SignerMode::Device),
+            Err(SignerError::InvalidSignature {
+                index: 3,
+                preimage: Some(_)
+            })
+        ));
+
+        // A tampered scalar does not verify either
+        let mut tampered = response.clone();
+        tampered[33] ^= 0x01;
+        assert!(matches!(
+            verify_signature_response(&software_device(&log), &tampered, &message, 0, SignerMode::Device),
+            Err(SignerError::InvalidSignature { preimage: Some(_), .. })
+        ));
+
+        // A scalar that is not canonical is malformed, there is nothing to check it under
+        let mut malformed = response.clone();
+        malformed[33..65].copy_from_slice(&[0xff; 32]);
+        assert!(matches!(
+            verify_signature_response(&software_device(&log), &malformed, &message, 0, SignerMode::Device),
+            Err(SignerError::InvalidSignature { preimage: None, .. })
+        ));
+
+        // A response cut short is refused by its length
+        assert!(matches!(
+            verify_signature_response(
+                &device,
+                &response[..SIGN_RESPONSE_LENGTH - 1],
+                &message,
+                0,
+                SignerMode::Device
+            ),
+            Err(SignerError::Device(_))
+        ));
+
+        // Offline, the signature is passed through unverified
+        assert!(verify_signature_response(&device, &tampered, &message, 0, SignerMode::Offline).is_ok());
+    }
+}
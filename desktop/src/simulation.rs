@@ -0,0 +1,271 @@
+//! Signing transcripts of described transactions
+//! Designing a new kind of output means getting every key, challenge and signature of it right before any device signs
+//! one. [`simulate`] takes a [`TransactionDescription`], from JSON or TOML, and signs it end to end against a
+//! [`DryRunTransport`] with software keys: it derives the keys of every output, commits to its value, hashes its
+//! challenge with a traced hasher and has the signer sign and verify every output as it would with a device. The
+//! [`Transcript`] lists all of it, field by field, followed by the APDUs that were exchanged.
+//!
+//! The challenge of an output binds its commitment, script and features the way a [`ChangeOutput`] does, hashed under
+//! the change output label for change and the script challenge label otherwise. The software keys stand in for those
+//! of a device, see [`DryRunTransport::with_software_keys`].
+//!
+//! [`ChangeOutput`]: crate::signer::ChangeOutput
+
+use std::fmt;
+
+use serde::Deserialize;
+use tari_crypto::{
+    ristretto::{pedersen::PedersenCommitment, RistrettoPublicKey},
+    tari_utilities::{
+        hex::{from_hex, to_hex},
+        ByteArray,
+    },
+};
+
+use crate::{
+    address::TariAddress,
+    commitment::{batch_commitments, CommitmentRequest},
+    device::{KeyBranch, LedgerDevice},
+    domains::{CHANGE_OUTPUT_LABEL, SCRIPT_CHALLENGE_LABEL},
+    dry_run::{DryRunLog, DryRunTransport},
+    errors::SimulationError,
+    export::public_key,
+    fee::FeeCalculator,
+    hashing::{Challenge, DomainSeparatedConsensusHasher, Preimage, TransactionHashDomain},
+    script::{Opcode, TariScript},
+    signature::LedgerSignature,
+    signer::{LedgerTransactionSigner, OutputToSign, DEFAULT_OUTPUT_FEATURES},
+    verify::script_challenge_preimage,
+};
+
+/// The fee per gram of a description that does not set one, in microTari
+pub const DEFAULT_SIMULATION_FEE_PER_GRAM: u64 = 5;
+
+/// A transaction to simulate
+#[derive(Clone, Debug, Deserialize)]
+pub struct TransactionDescription {
+    #[serde(default = "default_fee_per_gram")]
+    pub fee_per_gram: u64,
+    /// The number of inputs the transaction spends, which only weighs in on the fee
+    #[serde(default)]
+    pub inputs: usize,
+    pub outputs: Vec<OutputDescription>,
+}
+
+/// One output of a [`TransactionDescription`]
+#[derive(Clone, Debug, Deserialize)]
+pub struct OutputDescription {
+    /// In microTari
+    pub value: u64,
+    /// The index of the commitment mask and script keys of the output in their branches of account 0
+    pub key_index: u32,
+    #[serde(default)]
+    pub change: bool,
+    /// The address the device shows for a payment, as an Emoji ID or in hex
+    pub recipient: Option<String>,
+    /// The serialized script in hex, a script locked to the script key of the output if absent
+    pub script: Option<String>,
+    /// The 16 bytes of consensus encoded output features in hex, the default features if absent
+    pub features: Option<String>,
+}
+
+fn default_fee_per_gram() -> u64 {
+    DEFAULT_SIMULATION_FEE_PER_GRAM
+}
+
+/// Read a description from JSON
+pub fn parse_description_json(json: &str) -> Result<TransactionDescription, SimulationError> {
+    serde_json::from_str(json).map_err(|e| SimulationError::Parse(e.to_string()))
+}
+
+/// Read a description from TOML, with every output in an `[[outputs]]` table
+#[cfg(feature = "config")]
+pub fn parse_description_toml(toml: &str) -> Result<TransactionDescription, SimulationError> {
+    toml::from_str(toml).map_err(|e| SimulationError::Parse(e.to_string()))
+}
+
+/// Everything derived and signed for one output
+#[derive(Clone, Debug)]
+pub struct SimulatedOutput {
+    pub value: u64,
+    pub is_change: bool,
+    pub key_index: u32,
+    pub mask_public_key: RistrettoPublicKey,
+    pub script_public_key: RistrettoPublicKey,
+    pub commitment: PedersenCommitment,
+    pub script: TariScript,
+    pub features: [u8; 16],
+    pub challenge: Challenge,
+    /// The preimage of the challenge
+    pub preimage: Preimage,
+    pub signature: LedgerSignature,
+    /// The preimage of the script challenge the signature is over
+    pub signature_preimage: Preimage,
+}
+
+impl fmt::Display for SimulatedOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = if self.is_change { "change" } else { "payment" };
+        writeln!(f, "{} uT {} at key index {}", self.value, kind, self.key_index)?;
+        writeln!(
+            f,
+            "  commitment mask public key {}",
+            to_hex(self.mask_public_key.as_bytes())
+        )?;
+        writeln!(f, "  script public key {}", to_hex(self.script_public_key.as_bytes()))?;
+        writeln!(f, "  commitment {}", to_hex(self.commitment.as_bytes()))?;
+        writeln!(f, "  script {}", self.script)?;
+        writeln!(f, "  features {}", to_hex(&self.features))?;
+        writeln!(f, "  challenge {}", self.challenge)?;
+        writeln!(f, "  {}", indented(&self.preimage))?;
+        writeln!(
+            f,
+            "  signature public key {}, public nonce {}, s {}",
+            to_hex(self.signature.public_key.as_bytes()),
+            to_hex(self.signature.public_nonce.as_bytes()),
+            to_hex(self.signature.s.as_bytes())
+        )?;
+        write!(f, "  signed {}", indented(&self.signature_preimage))
+    }
+}
+
+/// A simulated transaction, everything derived for and signed with it
+#[derive(Clone, Debug)]
+pub struct Transcript {
+    pub fee: u64,
+    pub outputs: Vec<SimulatedOutput>,
+    /// The commands sent, with the layouts of their responses, see [`DryRunLog::to_text`]
+    pub apdus: String,
+}
+
+impl fmt::Display for Transcript {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, output) in self.outputs.iter().enumerate() {
+            writeln!(f, "Output {}: {}", index, output)?;
+        }
+        writeln!(f, "Fee {} uT", self.fee)?;
+        write!(f, "APDUs:\n{}", self.apdus)
+    }
+}
+
+/// Sign the transaction of `description` with the software keys of `seed`
+pub fn simulate(description: &TransactionDescription, seed: u64) -> Result<Transcript, SimulationError> {
+    let log = DryRunLog::new();
+    let device = LedgerDevice::from_transport(DryRunTransport::new(log.clone()).with_software_keys(seed));
+
+    let requests = description
+        .outputs
+        .iter()
+        .map(|output| CommitmentRequest {
+            value: output.value,
+            index: output.key_index,
+        })
+        .collect::<Vec<_>>();
+    let commitments = batch_commitments(&device, &requests)?;
+    let mut prepared = Vec::with_capacity(description.outputs.len());
+    let mut to_sign = Vec::with_capacity(description.outputs.len());
+    for (index, (output, commitment)) in description.outputs.iter().zip(commitments).enumerate() {
+        let invalid = |reason: String| SimulationError::InvalidOutput { index, reason };
+        let mask_public_key = public_key(&device, 0, KeyBranch::CommitmentMask, output.key_index)?;
+        let script_public_key = public_key(&device, 0, KeyBranch::ScriptKey, output.key_index)?;
+        let script = match &output.script {
+            Some(hex) => from_hex(hex)
+                .map_err(|e| invalid(format!("the script is not hex: {}", e)))
+                .and_then(|bytes| TariScript::from_bytes(&bytes).map_err(|e| invalid(e.to_string())))?,
+            None => TariScript::new(vec![Opcode::PushPubKey(script_public_key.clone())]),
+        };
+        let features = match &output.features {
+            Some(hex) => from_hex(hex)
+                .ok()
+                .and_then(|bytes| <[u8; 16]>::try_from(bytes).ok())
+                .ok_or_else(|| invalid("the features are not 16 bytes in hex".to_string()))?,
+            None => DEFAULT_OUTPUT_FEATURES,
+        };
+        let recipient = output
+            .recipient
+            .as_deref()
+            .map(|address| address.parse::<TariAddress>().map_err(|e| invalid(e.to_string())))
+            .transpose()?;
+
+        let label = if output.change {
+            CHANGE_OUTPUT_LABEL
+        } else {
+            SCRIPT_CHALLENGE_LABEL
+        };
+        let hasher = DomainSeparatedConsensusHasher::<TransactionHashDomain>::traced(label)
+            .chain(commitment.as_public_key())
+            .chain(&script)
+            .chain(&features);
+        let preimage = hasher.preimage();
+        let challenge = hasher.finalize_challenge();
+        to_sign.push(OutputToSign {
+            value: output.value,
+            is_change: output.change,
+            features_and_scripts_size: features.len() + script.encoded_len(),
+            challenge,
+            recipient,
+            features_title: None,
+        });
+        prepared.push(PreparedOutput {
+            mask_public_key,
+            script_public_key,
+            commitment,
+            script,
+            features,
+            preimage,
+        });
+    }
+
+    // Payments without a recipient are signed unconfirmed, as an app with output confirmation turned off would
+    let signer =
+        LedgerTransactionSigner::new(&device, FeeCalculator::new(description.fee_per_gram)).with_silent_outputs(true);
+    let signed = signer.sign_outputs(description.inputs, &to_sign)?;
+    let outputs = description
+        .outputs
+        .iter()
+        .zip(prepared)
+        .zip(to_sign)
+        .zip(signed.signatures)
+        .map(|(((output, prepared), to_sign), signature)| {
+            let signature_preimage = script_challenge_preimage(
+                &signature.public_key,
+                &signature.public_nonce,
+                to_sign.challenge.as_bytes(),
+            );
+            SimulatedOutput {
+                value: output.value,
+                is_change: output.change,
+                key_index: output.key_index,
+                mask_public_key: prepared.mask_public_key,
+                script_public_key: prepared.script_public_key,
+                commitment: prepared.commitment,
+                script: prepared.script,
+                features: prepared.features,
+                challenge: to_sign.challenge,
+                preimage: prepared.preimage,
+                signature,
+                signature_preimage,
+            }
+        })
+        .collect();
+    Ok(Transcript {
+        fee: signed.fee,
+        outputs,
+        apdus: log.to_text(),
+    })
+}
+
+/// What is derived for an output before it is signed
+struct PreparedOutput {
+    mask_public_key: RistrettoPublicKey,
+    script_public_key: RistrettoPublicKey,
+    commitment: PedersenCommitment,
+    script: TariScript,
+    features: [u8; 16],
+    preimage: Preimage,
+}
+
+/// A multi-line value indented to sit under a field of an output
+fn indented(value: &impl fmt::Display) -> String {
+    value.to_string().replace('\n', "\n  ")
+}
@@ -0,0 +1,267 @@
+//! Long running checks of the link to the device
+//! Some users see the device drop off now and then, often only behind particular USB hubs or after hours of use. A
+//! soak runs commands that need no confirmation on the device over and over for hours, logs every failure with when it
+//! happened, how long the exchange took and how long the link had been healthy before, and reconnects whenever the
+//! device stops answering. The failures are then grouped into [`ErrorCluster`]s, so that a few bad minutes stand out
+//! from a steady trickle.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    app_info::app_info,
+    device::{Capabilities, DeviceState, Instruction, KeyBranch, LedgerDevice},
+    errors::DeviceError,
+    export::public_key,
+};
+
+/// Failures less than this far apart belong to the same cluster
+pub const CLUSTER_GAP: Duration = Duration::from_secs(60);
+
+type Operation = (&'static str, fn(&LedgerDevice) -> Result<(), DeviceError>);
+
+/// How long to soak the device for and how often to exercise it
+#[derive(Clone, Copy, Debug)]
+pub struct SoakOptions {
+    pub duration: Duration,
+    /// The pause between rounds of commands
+    pub interval: Duration,
+}
+
+/// A failed exchange, with when and how it failed
+#[derive(Clone, Debug)]
+pub struct SoakEvent {
+    /// Since the start of the soak
+    pub elapsed: Duration,
+    pub at: SystemTime,
+    pub operation: &'static str,
+    /// How long the failing exchange took
+    pub latency: Duration,
+    /// How long the link had been healthy, since the last exchange that succeeded or the start of the soak
+    pub since_success: Duration,
+    /// What the failure says about the device
+    pub state: DeviceState,
+    pub error: String,
+}
+
+impl fmt::Display for SoakEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[+{:.1}s] {} failed after {} ms, {:.1}s after the last success, device {}: {}",
+            self.elapsed.as_secs_f64(),
+            self.operation,
+            self.latency.as_millis(),
+            self.since_success.as_secs_f64(),
+            self.state,
+            self.error
+        )
+    }
+}
+
+/// Failures that happened within [`CLUSTER_GAP`] of each other
+#[derive(Clone, Debug)]
+pub struct ErrorCluster {
+    /// When the first failure happened, since the start of the soak
+    pub start: Duration,
+    /// When the last failure happened, since the start of the soak
+    pub end: Duration,
+    /// How often each error occurred
+    pub errors: BTreeMap<String, usize>,
+}
+
+impl ErrorCluster {
+    pub fn failures(&self) -> usize {
+        self.errors.values().sum()
+    }
+}
+
+impl fmt::Display for ErrorCluster {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "+{:.0}s to +{:.0}s: {} failure(s)",
+            self.start.as_secs_f64(),
+            self.end.as_secs_f64(),
+            self.failures()
+        )?;
+        for (error, count) in &self.errors {
+            write!(f, "\n  {} x {}", count, error)?;
+        }
+        Ok(())
+    }
+}
+
+/// The outcome of [`soak`]
+#[derive(Clone, Debug, Default)]
+pub struct SoakReport {
+    pub elapsed: Duration,
+    /// Exchanges attempted, including those that failed
+    pub operations: u64,
+    /// Times the device was connected again after it stopped answering
+    pub reconnects: u64,
+    pub failures: Vec<SoakEvent>,
+    /// The slowest exchange that succeeded
+    pub max_latency: Duration,
+    total_latency: Duration,
+}
+
+impl SoakReport {
+    /// The mean latency of the exchanges that succeeded
+    pub fn mean_latency(&self) -> Duration {
+        let succeeded = self.operations.saturating_sub(self.failures.len() as u64);
+        match u32::try_from(succeeded) {
+            Ok(0) => Duration::ZERO,
+            Ok(succeeded) => self.total_latency / succeeded,
+            Err(_) => Duration::ZERO,
+        }
+    }
+
+    /// The failures grouped into clusters of failures less than `gap` apart, oldest first
+    pub fn clusters(&self, gap: Duration) -> Vec<ErrorCluster> {
+        let mut clusters: Vec<ErrorCluster> = Vec::new();
+        for event in &self.failures {
+            match clusters.last_mut() {
+                Some(cluster) if event.elapsed.saturating_sub(cluster.end) < gap => {
+                    cluster.end = event.elapsed;
+                    *cluster.errors.entry(event.error.clone()).or_default() += 1;
+                },
+                _ => clusters.push(ErrorCluster {
+                    start: event.elapsed,
+                    end: event.elapsed,
+                    errors: BTreeMap::from([(event.error.clone(), 1)]),
+                }),
+            }
+        }
+        clusters
+    }
+}
+
+impl fmt::Display for SoakReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} operations in {:.0}s, {} failed, {} reconnect(s), latency mean {} ms, max {} ms",
+            self.operations,
+            self.elapsed.as_secs_f64(),
+            self.failures.len(),
+            self.reconnects,
+            self.mean_latency().as_millis(),
+            self.max_latency.as_millis()
+        )
+    }
+}
+
+/// Exercise the device with commands that need no confirmation until `options.duration` is up. `connect` opens the
+/// device, at the start and whenever it stopped answering, and `on_failure` is told about every failure as it happens.
+pub fn soak(
+    options: SoakOptions,
+    mut connect: impl FnMut() -> Result<LedgerDevice, DeviceError>,
+    mut on_failure: impl FnMut(&SoakEvent),
+) -> SoakReport {
+    let start = Instant::now();
+    let mut report = SoakReport::default();
+    let mut last_success = start;
+    let mut device: Option<LedgerDevice> = None;
+    let mut connected = false;
+    while start.elapsed() < options.duration {
+        let current = match device.take() {
+            Some(current) => current,
+            None => {
+                let began = Instant::now();
+                report.operations += 1;
+                match connect() {
+                    Ok(current) => {
+                        let latency = began.elapsed();
+                        report.total_latency += latency;
+                        report.max_latency = report.max_latency.max(latency);
+                        last_success = Instant::now();
+                        if connected {
+                            report.reconnects += 1;
+                        }
+                        connected = true;
+                        current
+                    },
+                    Err(e) => {
+                        let event = failure(start, last_success, "connect", began, &e);
+                        on_failure(&event);
+                        report.failures.push(event);
+                        thread::sleep(options.interval);
+                        continue;
+                    },
+                }
+            },
+        };
+        let mut healthy = true;
+        for (operation, run) in operations(&current) {
+            let began = Instant::now();
+            let result = run(&current);
+            report.operations += 1;
+            match result {
+                Ok(()) => {
+                    let latency = began.elapsed();
+                    report.total_latency += latency;
+                    report.max_latency = report.max_latency.max(latency);
+                    last_success = Instant::now();
+                },
+                Err(e) => {
+                    let event = failure(start, last_success, operation, began, &e);
+                    on_failure(&event);
+                    healthy = event.state != DeviceState::Disconnected;
+                    report.failures.push(event);
+                    if !healthy {
+                        break;
+                    }
+                },
+            }
+        }
+        if healthy {
+            device = Some(current);
+        }
+        thread::sleep(options.interval);
+    }
+    report.elapsed = start.elapsed();
+    report
+}
+
+/// The commands to exercise `device` with, as far as its app supports them
+fn operations(device: &LedgerDevice) -> Vec<Operation> {
+    let mut operations: Vec<Operation> = vec![
+        ("version", |device| {
+            device.send(Instruction::GetVersion, 0x00, 0x00, vec![]).map(drop)
+        }),
+        ("app info", |device| app_info(device).map(drop)),
+    ];
+    let capabilities = device.capabilities().unwrap_or_else(|_| Capabilities::empty());
+    if capabilities.contains(Capabilities::PUBLIC_KEY_EXPORT) {
+        operations.push(("public key", |device| {
+            public_key(device, 0, KeyBranch::CommitmentMask, 0).map(drop)
+        }));
+    }
+    if capabilities.contains(Capabilities::SIGNING_COUNTER) {
+        operations.push(("signing counter", |device| device.signing_counter().map(drop)));
+    }
+    operations
+}
+
+fn failure(
+    start: Instant,
+    last_success: Instant,
+    operation: &'static str,
+    began: Instant,
+    e: &DeviceError,
+) -> SoakEvent {
+    SoakEvent {
+        elapsed: began.duration_since(start),
+        at: SystemTime::now(),
+        operation,
+        latency: began.elapsed(),
+        since_success: began.duration_since(last_success),
+        state: DeviceState::after(e),
+        error: e.to_string(),
+    }
+}
@@ -0,0 +1,435 @@
+//! Running the app in the Speculos emulator
+//! Speculos derives every key from the seed it is started with, so an emulator started with a fixed seed answers
+//! exactly the same way on every run. [`SpeculosOptions::launch`] starts one with [`TEST_SEED`] unless told
+//! otherwise, and [`TransportSpeculos`] talks to its APDU port, where every APDU is sent with its length as a
+//! big-endian `u32` and answered with the length of the data, the data and the status word. Speculos queues what
+//! arrives on the port, so the transport pipelines commands that do not depend on each other, see
+//! [`LedgerDevice::send_pipelined`].
+//!
+//! With the `serde` feature [`record_vectors`] captures the keys, commitments and signatures of a run, and
+//! [`check_vectors`] turns a recording into a regression test that fails on any byte that changed.
+
+use std::{
+    fmt,
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+use ledger_transport::{APDUAnswer, APDUCommand};
+
+use crate::{
+    device::LedgerDevice,
+    errors::DeviceError,
+    limits::DeviceModel,
+    redact::Redacted,
+    transport::LedgerTransport,
+};
+
+/// The port Speculos serves APDUs on unless told otherwise
+pub const DEFAULT_APDU_PORT: u16 = 9999;
+/// The seed Speculos uses by default. It is public, never send funds to its keys.
+pub const TEST_SEED: &str = "glory promote mansion idle axis finger extra february uncover one trip resource lawn \
+                             turtle enact monster seven myth punch hobby comfort wild raise skin";
+/// How many commands are sent ahead of the answers read, few enough to never fill the socket buffers
+const PIPELINE_DEPTH: usize = 8;
+/// How long to wait for a freshly started emulator to open its APDU port
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The APDU port of a running Speculos
+pub struct TransportSpeculos {
+    stream: Mutex<TcpStream>,
+}
+
+impl TransportSpeculos {
+    pub fn connect(address: SocketAddr) -> Result<Self, DeviceError> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream: Mutex::new(stream),
+        })
+    }
+}
+
+impl TransportSpeculos {
+    /// Also how a connection to the [`daemon`](crate::daemon) is framed
+    pub(crate) fn write_command(stream: &mut impl Write, command: &APDUCommand<Vec<u8>>) -> Result<(), DeviceError> {
+        let apdu = command.serialize();
+        // An APDU is a few hundred bytes at most
+        stream.write_all(&(apdu.len() as u32).to_be_bytes())?;
+        stream.write_all(&apdu)?;
+        Ok(())
+    }
+
+    pub(crate) fn read_answer(stream: &mut impl Read) -> Result<APDUAnswer<Vec<u8>>, DeviceError> {
+        let mut length = [0u8; 4];
+        stream.read_exact(&mut length)?;
+        // The length does not count the status word
+        let mut answer = vec![0u8; u32::from_be_bytes(length) as usize + 2];
+        stream.read_exact(&mut answer)?;
+        APDUAnswer::from_answer(answer).map_err(|_| DeviceError::InvalidResponse("the answer has no status word"))
+    }
+}
+
+impl LedgerTransport for TransportSpeculos {
+    fn exchange(&self, command: &APDUCommand<Vec<u8>>) -> Result<APDUAnswer<Vec<u8>>, DeviceError> {
+        let mut stream = self.stream.lock().expect("the Speculos connection is never poisoned");
+        Self::write_command(&mut *stream, command)?;
+        Self::read_answer(&mut *stream)
+    }
+
+    fn pipeline_depth(&self) -> usize {
+        PIPELINE_DEPTH
+    }
+
+    fn exchange_pipelined(&self, commands: &[APDUCommand<Vec<u8>>]) -> Result<Vec<APDUAnswer<Vec<u8>>>, DeviceError> {
+        let mut stream = self.stream.lock().expect("the Speculos connection is never poisoned");
+        for command in commands {
+            Self::write_command(&mut *stream, command)?;
+        }
+        commands.iter().map(|_| Self::read_answer(&mut *stream)).collect()
+    }
+}
+
+/// How to start Speculos
+#[derive(Clone)]
+pub struct SpeculosOptions {
+    /// The app to load, the ELF file of the ledger crate
+    pub app: PathBuf,
+    /// The `speculos` executable, e.g. `speculos.py` from a checkout
+    pub binary: PathBuf,
+    pub seed: String,
+    /// The device model to emulate, e.g. `nanos` or `nanosp`
+    pub model: String,
+    pub apdu_port: u16,
+}
+
+impl fmt::Debug for SpeculosOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SpeculosOptions")
+            .field("app", &self.app)
+            .field("binary", &self.binary)
+            .field("seed", &Redacted)
+            .field("model", &self.model)
+            .field("apdu_port", &self.apdu_port)
+            .finish()
+    }
+}
+
+impl SpeculosOptions {
+    pub fn new(app: PathBuf) -> Self {
+        Self {
+            app,
+            binary: PathBuf::from("speculos"),
+            seed: TEST_SEED.to_string(),
+            model: "nanos".to_string(),
+            apdu_port: DEFAULT_APDU_PORT,
+        }
+    }
+
+    pub fn with_binary(mut self, binary: PathBuf) -> Self {
+        self.binary = binary;
+        self
+    }
+
+    pub fn with_seed(mut self, seed: String) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn with_model(mut self, model: String) -> Self {
+        self.model = model;
+        self
+    }
+
+    pub fn with_apdu_port(mut self, apdu_port: u16) -> Self {
+        self.apdu_port = apdu_port;
+        self
+    }
+
+    /// Start a headless emulator and wait until it accepts APDUs
+    pub fn launch(&self) -> Result<Speculos, DeviceError> {
+        let child = Command::new(&self.binary)
+            .arg("--model")
+            .arg(&self.model)
+            .arg("--seed")
+            .arg(&self.seed)
+            .arg("--apdu-port")
+            .arg(self.apdu_port.to_string())
+            .arg("--display")
+            .arg("headless")
+            .arg(&self.app)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let mut speculos = Speculos {
+            child,
+            address: SocketAddr::from(([127, 0, 0, 1], self.apdu_port)),
+            model: DeviceModel::from_speculos_name(&self.model),
+        };
+
+        let started = Instant::now();
+        while TcpStream::connect(speculos.address).is_err() {
+            if let Some(status) = speculos.child.try_wait()? {
+                return Err(DeviceError::Io(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    format!("Speculos exited with {} before it opened its APDU port", status),
+                )));
+            }
+            if started.elapsed() > STARTUP_TIMEOUT {
+                return Err(DeviceError::Io(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "Speculos did not open its APDU port",
+                )));
+            }
+            thread::sleep(Duration::from_millis(200));
+        }
+        Ok(speculos)
+    }
+}
+
+/// A running emulator, stopped when dropped
+pub struct Speculos {
+    child: Child,
+    address: SocketAddr,
+    model: Option<DeviceModel>,
+}
+
+impl Speculos {
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// A new connection to the emulator
+    pub fn device(&self) -> Result<LedgerDevice, DeviceError> {
+        let device = LedgerDevice::from_transport(TransportSpeculos::connect(self.address)?);
+        Ok(match self.model {
+            Some(model) => device.with_model(model),
+            None => device,
+        })
+    }
+}
+
+impl Drop for Speculos {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(feature = "serde")]
+pub use self::vectors::{check_vectors, record_vectors};
+
+#[cfg(feature = "serde")]
+mod vectors {
+    use serde::{Deserialize, Serialize};
+    use tari_crypto::tari_utilities::{hex::Hex, ByteArray};
+    use tari_ledger_protocol::{COMMITMENT_RESPONSE_LENGTH, SETTING_BLIND_SIGNING, SIGN_RESPONSE_LENGTH};
+
+    use crate::{
+        app_info::app_settings,
+        consensus_vectors::VectorFailure,
+        device::{Instruction, KeyBranch, LedgerDevice},
+        errors::DeviceError,
+        export::{key_path, public_key},
+    };
+
+    /// The key indices recorded in every branch
+    const RECORDED_INDICES: u32 = 3;
+    const RECORDED_VALUES: [u64; 3] = [0, 1, 1_000_000];
+    const RECORDED_CHALLENGES: [[u8; 32]; 2] = [[0x00; 32], [0x01; 32]];
+
+    /// What a run against an emulator with a fixed seed produced
+    #[derive(Default, Serialize, Deserialize)]
+    struct Recording {
+        keys: Vec<Recorded>,
+        commitments: Vec<Recorded>,
+        /// Empty unless blind signing was enabled when the run was recorded
+        signatures: Vec<Recorded>,
+    }
+
+    /// One request and the hex encoded answer
+    #[derive(Serialize, Deserialize)]
+    struct Recorded {
+        name: String,
+        answer: String,
+    }
+
+    /// Run every recorded request against `device` and return the answers as JSON
+    pub fn record_vectors(device: &LedgerDevice) -> Result<String, DeviceError> {
+        let recording = Recording {
+            keys: record_keys(device)?,
+            commitments: record_commitments(device)?,
+            signatures: if app_settings(device)?.allows(SETTING_BLIND_SIGNING) {
+                record_signatures(device)?
+            } else {
+                Vec::new()
+            },
+        };
+        Ok(serde_json::to_string_pretty(&recording).expect("a recording always serializes"))
+    }
+
+    /// Run the requests of a [`record_vectors`] recording against `device` and return how many answers matched, or
+    /// every answer that did not
+    pub fn check_vectors(device: &LedgerDevice, recording: &str) -> Result<usize, Vec<VectorFailure>> {
+        let failure = |name: &str, reason: String| {
+            vec![VectorFailure {
+                name: name.to_string(),
+                reason,
+            }]
+        };
+        let expected: Recording =
+            serde_json::from_str(recording).map_err(|e| failure("recording", format!("invalid JSON: {}", e)))?;
+        let actual = Recording {
+            keys: record_keys(device).map_err(|e| failure("keys", e.to_string()))?,
+            commitments: record_commitments(device).map_err(|e| failure("commitments", e.to_string()))?,
+            signatures: if expected.signatures.is_empty() {
+                Vec::new()
+            } else {
+                record_signatures(device).map_err(|e| failure("signatures", e.to_string()))?
+            },
+        };
+
+        let mut passed = 0;
+        let mut failures = Vec::new();
+        let pairs = [
+            (&expected.keys, &actual.keys),
+            (&expected.commitments, &actual.commitments),
+            (&expected.signatures, &actual.signatures),
+        ];
+        for (expected, actual) in pairs {
+            for expected in expected {
+                match actual.iter().find(|actual| actual.name == expected.name) {
+                    Some(actual) if actual.answer == expected.answer => passed += 1,
+                    Some(actual) => failures.push(VectorFailure {
+                        name: expected.name.clone(),
+                        reason: format!("answered {}, expected {}", actual.answer, expected.answer),
+                    }),
+                    None => failures.push(VectorFailure {
+                        name: expected.name.clone(),
+                        reason: "no longer requested".to_string(),
+                    }),
+                }
+            }
+        }
+        if failures.is_empty() {
+            Ok(passed)
+        } else {
+            Err(failures)
+        }
+    }
+
+    fn record_keys(device: &LedgerDevice) -> Result<Vec<Recorded>, DeviceError> {
+        let mut keys = Vec::new();
+        for branch in KeyBranch::ALL {
+            for index in 0..RECORDED_INDICES {
+                keys.push(Recorded {
+                    name: key_path(0, branch, index),
+                    answer: public_key(device, 0, branch, index)?.to_hex(),
+                });
+            }
+        }
+        Ok(keys)
+    }
+
+    fn record_commitments(device: &LedgerDevice) -> Result<Vec<Recorded>, DeviceError> {
+        RECORDED_VALUES
+            .iter()
+            .map(|value| {
+                let response = device.send(Instruction::Commitment, 0x00, 0x00, value.to_le_bytes().to_vec())?;
+                let payload = device.response_payload(&response, COMMITMENT_RESPONSE_LENGTH)?;
+                Ok(Recorded {
+                    name: format!("commitment to {}", value),
+                    answer: payload.to_hex(),
+                })
+            })
+            .collect()
+    }
+
+    /// The app derives its nonces from its key, so the signatures of a fixed seed are fixed too
+    fn record_signatures(device: &LedgerDevice) -> Result<Vec<Recorded>, DeviceError> {
+        RECORDED_CHALLENGES
+            .iter()
+            .map(|challenge| {
+                let response = device.send(Instruction::Sign, 0x00, 0x00, challenge.to_vec())?;
+                let payload = device.response_payload(&response, SIGN_RESPONSE_LENGTH)?;
+                Ok(Recorded {
+                    name: format!("signature of {}", challenge.to_hex()),
+                    answer: payload.to_hex(),
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    #[cfg(feature = "serde")]
+    use crate::dry_run::{DryRunLog, DryRunTransport};
+
+    #[cfg(feature = "serde")]
+    fn software_device(seed: u64) -> LedgerDevice {
+        LedgerDevice::from_transport(DryRunTransport::new(DryRunLog::new()).with_software_keys(seed))
+    }
+
+    #[test]
+    fn commands_are_framed_with_their_length() {
+        let command = APDUCommand {
+            cla: 0x80,
+            ins: 0x01,
+            p1: 0x02,
+            p2: 0x03,
+            data: vec![0xaa, 0xbb],
+        };
+        let mut framed = Vec::new();
+        TransportSpeculos::write_command(&mut framed, &command).unwrap();
+        assert_eq!(framed, [0, 0, 0, 7, 0x80, 0x01, 0x02, 0x03, 0x02, 0xaa, 0xbb]);
+    }
+
+    #[test]
+    fn answers_are_read_with_their_status_word() {
+        let answer = TransportSpeculos::read_answer(&mut Cursor::new([0, 0, 0, 2, 0xaa, 0xbb, 0x90, 0x00])).unwrap();
+        assert_eq!(answer.data(), [0xaa, 0xbb]);
+        assert_eq!(answer.retcode(), 0x9000);
+
+        // The status word is missing
+        assert!(TransportSpeculos::read_answer(&mut Cursor::new([0, 0, 0, 2, 0xaa, 0xbb])).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn a_recording_checks_against_the_same_keys() {
+        let recording = record_vectors(&software_device(1)).unwrap();
+        let recorded: serde_json::Value = serde_json::from_str(&recording).unwrap();
+        let answers = ["keys", "commitments", "signatures"]
+            .iter()
+            .map(|field| recorded[field].as_array().map_or(0, Vec::len))
+            .sum::<usize>();
+        match check_vectors(&software_device(1), &recording) {
+            Ok(passed) => assert_eq!(passed, answers),
+            Err(failures) => panic!("{}", failures[0]),
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn a_recording_fails_against_other_keys() {
+        let recording = record_vectors(&software_device(1)).unwrap();
+        let failures = check_vectors(&software_device(2), &recording).unwrap_err();
+        assert!(failures.iter().all(|failure| failure.reason.starts_with("answered")));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn an_invalid_recording_is_refused() {
+        let failures = check_vectors(&software_device(1), "not json").unwrap_err();
+        assert_eq!(failures[0].name, "recording");
+    }
+}
@@ -0,0 +1,105 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+use tari_crypto::tari_utilities::hex::{from_hex, to_hex};
+
+use super::{public_key_id, LedgerStateStore, ScanCheckpoint};
+use crate::errors::StoreError;
+
+#[derive(Default, Serialize, Deserialize)]
+struct FileState {
+    key_indices: HashMap<String, u64>,
+    /// Hex encoded public keys keyed by `branch/index`
+    public_keys: HashMap<String, String>,
+    checkpoints: HashMap<String, ScanCheckpoint>,
+}
+
+/// A [`LedgerStateStore`] that keeps all state in a single JSON file. The whole file is rewritten on every change,
+/// which is fine for the small amount of state a single wallet holds.
+pub struct FileStateStore {
+    path: PathBuf,
+    state: Mutex<FileState>,
+}
+
+impl FileStateStore {
+    /// Open the store at `path`, creating an empty one if the file does not exist yet
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, StoreError> {
+        let path = path.as_ref().to_path_buf();
+        let state = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).map_err(|e| StoreError::Serialization(e.to_string()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => FileState::default(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path,
+            state: Mutex::new(state),
+        })
+    }
+
+    fn update<F: FnOnce(&mut FileState)>(&self, f: F) -> Result<(), StoreError> {
+        let mut state = self.state.lock().expect("state store lock poisoned");
+        f(&mut state);
+        let contents = serde_json::to_string_pretty(&*state).map_err(|e| StoreError::Serialization(e.to_string()))?;
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Write to a temporary file first so that a crash never leaves a truncated state file behind
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, contents)?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+impl LedgerStateStore for FileStateStore {
+    fn key_index(&self, branch: &str) -> Result<u64, StoreError> {
+        let state = self.state.lock().expect("state store lock poisoned");
+        Ok(state.key_indices.get(branch).copied().unwrap_or(0))
+    }
+
+    fn set_key_index(&self, branch: &str, index: u64) -> Result<(), StoreError> {
+        self.update(|state| {
+            state.key_indices.insert(branch.to_string(), index);
+        })
+    }
+
+    fn cached_public_key(&self, branch: &str, index: u64) -> Result<Option<[u8; 32]>, StoreError> {
+        let state = self.state.lock().expect("state store lock poisoned");
+        match state.public_keys.get(&public_key_id(branch, index)) {
+            Some(hex) => {
+                let bytes = from_hex(hex).map_err(|_| StoreError::Corrupt("cached public key is not valid hex"))?;
+                if bytes.len() != 32 {
+                    return Err(StoreError::Corrupt("cached public key must be 32 bytes"));
+                }
+                let mut key = [0u8; 32];
+                key.copy_from_slice(&bytes);
+                Ok(Some(key))
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn cache_public_key(&self, branch: &str, index: u64, public_key: &[u8; 32]) -> Result<(), StoreError> {
+        self.update(|state| {
+            state
+                .public_keys
+                .insert(public_key_id(branch, index), to_hex(public_key.as_slice()));
+        })
+    }
+
+    fn checkpoint(&self, name: &str) -> Result<Option<ScanCheckpoint>, StoreError> {
+        let state = self.state.lock().expect("state store lock poisoned");
+        Ok(state.checkpoints.get(name).copied())
+    }
+
+    fn set_checkpoint(&self, name: &str, checkpoint: &ScanCheckpoint) -> Result<(), StoreError> {
+        self.update(|state| {
+            state.checkpoints.insert(name.to_string(), *checkpoint);
+        })
+    }
+}
@@ -0,0 +1,91 @@
+//! Persistence for host-side wallet state
+//! Key index counters, cached public keys and scan checkpoints are kept behind the [`LedgerStateStore`] trait so that
+//! they survive restarts, regardless of which storage backend the embedding application prefers.
+
+#[cfg(feature = "serde")]
+mod file_store;
+#[cfg(feature = "sled")]
+mod sled_store;
+#[cfg(feature = "sqlite")]
+mod sqlite_store;
+
+use std::path::PathBuf;
+
+#[cfg(feature = "serde")]
+pub use file_store::FileStateStore;
+#[cfg(feature = "sled")]
+pub use sled_store::SledStateStore;
+#[cfg(feature = "sqlite")]
+pub use sqlite_store::SqliteStateStore;
+
+use crate::errors::StoreError;
+
+/// The last block a scanner has fully processed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScanCheckpoint {
+    pub height: u64,
+    pub block_hash: [u8; 32],
+}
+
+impl ScanCheckpoint {
+    pub const ENCODED_LENGTH: usize = 40;
+
+    pub fn to_bytes(&self) -> [u8; Self::ENCODED_LENGTH] {
+        let mut bytes = [0u8; Self::ENCODED_LENGTH];
+        bytes[0..8].copy_from_slice(&self.height.to_le_bytes());
+        bytes[8..40].copy_from_slice(&self.block_hash);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, StoreError> {
+        if bytes.len() != Self::ENCODED_LENGTH {
+            return Err(StoreError::Corrupt("scan checkpoint must be 40 bytes"));
+        }
+        let mut height = [0u8; 8];
+        height.copy_from_slice(&bytes[0..8]);
+        let mut block_hash = [0u8; 32];
+        block_hash.copy_from_slice(&bytes[8..40]);
+        Ok(Self {
+            height: u64::from_le_bytes(height),
+            block_hash,
+        })
+    }
+}
+
+/// Storage for host state that must outlive a single session. The `branch` is a free-form label identifying the key
+/// tree, e.g. `"m/44'/535348'/0'"`.
+pub trait LedgerStateStore {
+    /// The next unused key index for `branch`, or 0 if the branch has never been used
+    fn key_index(&self, branch: &str) -> Result<u64, StoreError>;
+
+    fn set_key_index(&self, branch: &str, index: u64) -> Result<(), StoreError>;
+
+    /// Return the current key index for `branch` and persist the incremented value
+    fn next_key_index(&self, branch: &str) -> Result<u64, StoreError> {
+        let index = self.key_index(branch)?;
+        self.set_key_index(branch, index + 1)?;
+        Ok(index)
+    }
+
+    fn cached_public_key(&self, branch: &str, index: u64) -> Result<Option<[u8; 32]>, StoreError>;
+
+    fn cache_public_key(&self, branch: &str, index: u64, public_key: &[u8; 32]) -> Result<(), StoreError>;
+
+    fn checkpoint(&self, name: &str) -> Result<Option<ScanCheckpoint>, StoreError>;
+
+    fn set_checkpoint(&self, name: &str, checkpoint: &ScanCheckpoint) -> Result<(), StoreError>;
+}
+
+/// The directory host state is kept in by default, `~/.tari-ledger`
+pub fn default_data_dir() -> PathBuf {
+    let home = std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("."));
+    home.join(".tari-ledger")
+}
+
+fn public_key_id(branch: &str, index: u64) -> String {
+    format!("{}/{}", branch, index)
+}
@@ -0,0 +1,80 @@
+use std::path::Path;
+
+use super::{public_key_id, LedgerStateStore, ScanCheckpoint};
+use crate::errors::StoreError;
+
+const KEY_INDICES: &str = "key_indices";
+const PUBLIC_KEYS: &str = "public_keys";
+const CHECKPOINTS: &str = "checkpoints";
+
+/// A [`LedgerStateStore`] backed by an embedded sled database
+pub struct SledStateStore {
+    key_indices: sled::Tree,
+    public_keys: sled::Tree,
+    checkpoints: sled::Tree,
+}
+
+impl SledStateStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, StoreError> {
+        let db = sled::open(path)?;
+        Ok(Self {
+            key_indices: db.open_tree(KEY_INDICES)?,
+            public_keys: db.open_tree(PUBLIC_KEYS)?,
+            checkpoints: db.open_tree(CHECKPOINTS)?,
+        })
+    }
+}
+
+impl LedgerStateStore for SledStateStore {
+    fn key_index(&self, branch: &str) -> Result<u64, StoreError> {
+        match self.key_indices.get(branch)? {
+            Some(value) => {
+                let bytes: [u8; 8] = value
+                    .as_ref()
+                    .try_into()
+                    .map_err(|_| StoreError::Corrupt("key index must be 8 bytes"))?;
+                Ok(u64::from_le_bytes(bytes))
+            },
+            None => Ok(0),
+        }
+    }
+
+    fn set_key_index(&self, branch: &str, index: u64) -> Result<(), StoreError> {
+        self.key_indices.insert(branch, index.to_le_bytes().as_slice())?;
+        self.key_indices.flush()?;
+        Ok(())
+    }
+
+    fn cached_public_key(&self, branch: &str, index: u64) -> Result<Option<[u8; 32]>, StoreError> {
+        match self.public_keys.get(public_key_id(branch, index))? {
+            Some(value) => {
+                let key: [u8; 32] = value
+                    .as_ref()
+                    .try_into()
+                    .map_err(|_| StoreError::Corrupt("cached public key must be 32 bytes"))?;
+                Ok(Some(key))
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn cache_public_key(&self, branch: &str, index: u64, public_key: &[u8; 32]) -> Result<(), StoreError> {
+        self.public_keys
+            .insert(public_key_id(branch, index), public_key.as_slice())?;
+        self.public_keys.flush()?;
+        Ok(())
+    }
+
+    fn checkpoint(&self, name: &str) -> Result<Option<ScanCheckpoint>, StoreError> {
+        match self.checkpoints.get(name)? {
+            Some(value) => Ok(Some(ScanCheckpoint::from_bytes(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn set_checkpoint(&self, name: &str, checkpoint: &ScanCheckpoint) -> Result<(), StoreError> {
+        self.checkpoints.insert(name, checkpoint.to_bytes().as_slice())?;
+        self.checkpoints.flush()?;
+        Ok(())
+    }
+}
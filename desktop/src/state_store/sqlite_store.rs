@@ -0,0 +1,151 @@
+use std::{path::Path, sync::Mutex};
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use super::{LedgerStateStore, ScanCheckpoint};
+use crate::errors::StoreError;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS key_indices (
+        branch TEXT PRIMARY KEY NOT NULL,
+        next_index INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS public_keys (
+        branch TEXT NOT NULL,
+        key_index INTEGER NOT NULL,
+        public_key BLOB NOT NULL,
+        PRIMARY KEY (branch, key_index)
+    );
+    CREATE TABLE IF NOT EXISTS checkpoints (
+        name TEXT PRIMARY KEY NOT NULL,
+        height INTEGER NOT NULL,
+        block_hash BLOB NOT NULL
+    );
+";
+
+/// A [`LedgerStateStore`] backed by a SQLite database
+pub struct SqliteStateStore {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStateStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, StoreError> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    pub fn from_connection(connection: Connection) -> Result<Self, StoreError> {
+        connection.execute_batch(SCHEMA)?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn connection(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.connection.lock().expect("state store lock poisoned")
+    }
+}
+
+impl LedgerStateStore for SqliteStateStore {
+    fn key_index(&self, branch: &str) -> Result<u64, StoreError> {
+        let index: Option<i64> = self
+            .connection()
+            .query_row(
+                "SELECT next_index FROM key_indices WHERE branch = ?1",
+                params![branch],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(index.map(|i| i as u64).unwrap_or(0))
+    }
+
+    fn set_key_index(&self, branch: &str, index: u64) -> Result<(), StoreError> {
+        self.connection().execute(
+            "INSERT INTO key_indices (branch, next_index) VALUES (?1, ?2)
+             ON CONFLICT(branch) DO UPDATE SET next_index = excluded.next_index",
+            params![branch, index as i64],
+        )?;
+        Ok(())
+    }
+
+    fn next_key_index(&self, branch: &str) -> Result<u64, StoreError> {
+        // Read and increment in one transaction so that concurrent processes never hand out the same index
+        let mut connection = self.connection();
+        let tx = connection.transaction()?;
+        let index: Option<i64> = tx
+            .query_row(
+                "SELECT next_index FROM key_indices WHERE branch = ?1",
+                params![branch],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let index = index.map(|i| i as u64).unwrap_or(0);
+        tx.execute(
+            "INSERT INTO key_indices (branch, next_index) VALUES (?1, ?2)
+             ON CONFLICT(branch) DO UPDATE SET next_index = excluded.next_index",
+            params![branch, (index + 1) as i64],
+        )?;
+        tx.commit()?;
+        Ok(index)
+    }
+
+    fn cached_public_key(&self, branch: &str, index: u64) -> Result<Option<[u8; 32]>, StoreError> {
+        let bytes: Option<Vec<u8>> = self
+            .connection()
+            .query_row(
+                "SELECT public_key FROM public_keys WHERE branch = ?1 AND key_index = ?2",
+                params![branch, index as i64],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match bytes {
+            Some(bytes) => {
+                let key: [u8; 32] = bytes
+                    .as_slice()
+                    .try_into()
+                    .map_err(|_| StoreError::Corrupt("cached public key must be 32 bytes"))?;
+                Ok(Some(key))
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn cache_public_key(&self, branch: &str, index: u64, public_key: &[u8; 32]) -> Result<(), StoreError> {
+        self.connection().execute(
+            "INSERT OR REPLACE INTO public_keys (branch, key_index, public_key) VALUES (?1, ?2, ?3)",
+            params![branch, index as i64, public_key.as_slice()],
+        )?;
+        Ok(())
+    }
+
+    fn checkpoint(&self, name: &str) -> Result<Option<ScanCheckpoint>, StoreError> {
+        let row: Option<(i64, Vec<u8>)> = self
+            .connection()
+            .query_row(
+                "SELECT height, block_hash FROM checkpoints WHERE name = ?1",
+                params![name],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        match row {
+            Some((height, block_hash)) => {
+                let block_hash: [u8; 32] = block_hash
+                    .as_slice()
+                    .try_into()
+                    .map_err(|_| StoreError::Corrupt("checkpoint block hash must be 32 bytes"))?;
+                Ok(Some(ScanCheckpoint {
+                    height: height as u64,
+                    block_hash,
+                }))
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn set_checkpoint(&self, name: &str, checkpoint: &ScanCheckpoint) -> Result<(), StoreError> {
+        self.connection().execute(
+            "INSERT OR REPLACE INTO checkpoints (name, height, block_hash) VALUES (?1, ?2, ?3)",
+            params![name, checkpoint.height as i64, checkpoint.block_hash.as_slice()],
+        )?;
+        Ok(())
+    }
+}
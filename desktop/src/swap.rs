@@ -0,0 +1,118 @@
+//! The Tari side of a hash time locked atomic swap
+//! The swap preimage is derived on the device from the swap id and only leaves it when the user confirms the claim.
+//! Both parties lock their funds to the SHA-256 hash of the preimage, so the other chain only needs to support SHA-256
+//! hash locks, as Bitcoin does.
+
+use std::fmt;
+
+use tari_crypto::{ristretto::RistrettoPublicKey, tari_utilities::ByteArray};
+use tari_ledger_protocol::{SWAP_LOCK_RESPONSE_LENGTH, SWAP_PREIMAGE_RESPONSE_LENGTH};
+
+use crate::{
+    device::{Capabilities, Instruction},
+    errors::{DeviceError, SignerError},
+    htlc::HashTimeLock,
+    redact::Redacted,
+    script::ExecutionStack,
+    signature::LedgerSignature,
+    signer::LedgerTransactionSigner,
+};
+
+/// Everything needed to spend the claim path of a swap output
+#[derive(Clone)]
+pub struct SwapClaim {
+    pub preimage: [u8; 32],
+    pub input_data: ExecutionStack,
+    pub signature: LedgerSignature,
+}
+
+// The input data holds the preimage as well
+impl fmt::Debug for SwapClaim {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SwapClaim")
+            .field("preimage", &Redacted)
+            .field("input_data", &Redacted)
+            .field("signature", &self.signature)
+            .finish()
+    }
+}
+
+pub struct AtomicSwap<'a> {
+    signer: &'a LedgerTransactionSigner<'a>,
+    swap_id: [u8; 32],
+}
+
+impl<'a> AtomicSwap<'a> {
+    /// `swap_id` identifies the swap on the device, the same id always yields the same preimage
+    pub fn new(signer: &'a LedgerTransactionSigner<'a>, swap_id: [u8; 32]) -> Self {
+        Self { signer, swap_id }
+    }
+
+    /// The hash lock derived on the device, to be shared with the counterparty when initiating the swap, together with
+    /// the device key that can refund or claim outputs
+    pub fn lock(&self) -> Result<([u8; 32], RistrettoPublicKey), SignerError> {
+        self.signer.device().require(Capabilities::ATOMIC_SWAP)?;
+        let response = self
+            .signer
+            .device()
+            .send(Instruction::SwapLock, 0x00, 0x00, self.swap_id.to_vec())?;
+        let payload = self
+            .signer
+            .device()
+            .response_payload(&response, SWAP_LOCK_RESPONSE_LENGTH)?;
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&payload[0..32]);
+        let public_key = RistrettoPublicKey::from_bytes(&payload[32..64])
+            .map_err(|_| DeviceError::InvalidResponse("the swap public key is not a valid point"))?;
+        Ok((hash, public_key))
+    }
+
+    /// Build the output locking our funds for `counterparty`. The counterparty claims it with the preimage of `hash`,
+    /// the device key can refund it from block `timeout`.
+    pub fn lock_output(
+        &self,
+        counterparty: RistrettoPublicKey,
+        hash: [u8; 32],
+        timeout: u64,
+    ) -> Result<HashTimeLock, SignerError> {
+        let (_, public_key) = self.lock()?;
+        Ok(HashTimeLock {
+            hash,
+            timeout,
+            claim_key: counterparty,
+            refund_key: public_key,
+        })
+    }
+
+    /// Sign the refund of an output created with [`AtomicSwap::lock_output`] once the swap has timed out
+    pub fn refund(&self, lock: &HashTimeLock) -> Result<LedgerSignature, SignerError> {
+        lock.sign_refund(self.signer)
+    }
+
+    /// Claim an output the counterparty locked for us, using the preimage they revealed on the other chain
+    pub fn claim(&self, lock: &HashTimeLock, preimage: [u8; 32]) -> Result<SwapClaim, SignerError> {
+        let signature = lock.sign_claim(self.signer, &preimage)?;
+        Ok(SwapClaim {
+            preimage,
+            input_data: HashTimeLock::claim_input_data(&preimage),
+            signature,
+        })
+    }
+
+    /// As the initiator, claim an output locked to our own hash. The device asks the user before revealing the
+    /// preimage, which becomes public once the claim is broadcast.
+    pub fn claim_as_initiator(&self, lock: &HashTimeLock) -> Result<SwapClaim, SignerError> {
+        self.signer.device().require(Capabilities::ATOMIC_SWAP)?;
+        let response = self
+            .signer
+            .device()
+            .send(Instruction::SwapPreimage, 0x00, 0x00, self.swap_id.to_vec())?;
+        let payload = self
+            .signer
+            .device()
+            .response_payload(&response, SWAP_PREIMAGE_RESPONSE_LENGTH)?;
+        let mut preimage = [0u8; 32];
+        preimage.copy_from_slice(payload);
+        self.claim(lock, preimage)
+    }
+}
@@ -0,0 +1,273 @@
+//! Sweeping a wallet into cold storage
+//! The host cannot see the chain, so the outputs to sweep come from the console wallet's export of its unspent
+//! outputs. Only outputs the device can spend are swept: every output is checked by having the device commit to its
+//! value with the mask key at its index, and outputs whose commitment does not match are skipped. Outputs worth no
+//! more than the dust threshold are left behind, spending them would cost more in fees than they hold. Everything
+//! else becomes the inputs of one transaction with a single output paying the total, less the fee, to the target
+//! address, which the device shows before signing it.
+
+use std::fmt;
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tari_crypto::{
+    ristretto::pedersen::PedersenCommitment,
+    tari_utilities::{
+        hex::{from_hex, to_hex},
+        ByteArray,
+    },
+};
+
+use crate::{
+    address::{Network, TariAddress},
+    commitment::{batch_commitments_with_version, CommitmentRequest},
+    domains::{SCRIPT_MESSAGE_LABEL, SWEEP_OUTPUT_LABEL},
+    errors::{SignerError, SweepError},
+    fee::FeeCalculator,
+    hashing::{Challenge, DomainSeparatedConsensusHasher, TransactionHashDomain},
+    redact::short_hex,
+    script::{Opcode, TariScript},
+    signature::LedgerSignature,
+    signer::{LedgerTransactionSigner, OutputToSign, DEFAULT_OUTPUT_FEATURES},
+};
+
+/// The version of the export format this module understands
+pub const SWEEP_FORMAT_VERSION: u64 = 1;
+
+#[derive(Deserialize)]
+struct WalletOutputs {
+    version: u64,
+    outputs: Vec<WalletOutput>,
+}
+
+#[derive(Deserialize)]
+struct WalletOutput {
+    value: u64,
+    /// The index of the commitment mask key the wallet derived the output with
+    mask_index: u32,
+    commitment: String,
+    /// The script signature message of the output when spent as an input
+    script_message: String,
+    #[serde(default)]
+    maturity: u64,
+}
+
+/// An unspent output of the wallet
+#[derive(Clone, Debug)]
+pub struct SweepInput {
+    pub value: u64,
+    pub mask_index: u32,
+    pub commitment: PedersenCommitment,
+    pub script_message: Challenge,
+    /// The block height from which the output can be spent, 0 unless it is time locked
+    pub maturity: u64,
+}
+
+impl fmt::Display for SweepInput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} uT at mask index {}, commitment {}",
+            self.value,
+            self.mask_index,
+            short_hex(self.commitment.as_bytes())
+        )
+    }
+}
+
+/// The outputs of a sweep, split into those that will be spent and those that are left behind
+#[derive(Clone, Debug, Default)]
+pub struct SweepScan {
+    pub inputs: Vec<SweepInput>,
+    /// Outputs worth no more than the dust threshold
+    pub dust: Vec<SweepInput>,
+    /// Outputs whose commitment the device does not reproduce, from another seed or a different index
+    pub foreign: Vec<SweepInput>,
+}
+
+impl SweepScan {
+    pub fn total(&self) -> u64 {
+        self.inputs.iter().map(|input| input.value).sum()
+    }
+}
+
+/// The smallest value worth sweeping: an output worth no more than the fee of spending it is dust
+pub fn dust_threshold(fee_calculator: &FeeCalculator) -> u64 {
+    fee_calculator.weight(0, 1, &[]) * fee_calculator.fee_per_gram()
+}
+
+/// Read the wallet's export of its unspent outputs
+pub fn parse_outputs(json: &str) -> Result<Vec<SweepInput>, SweepError> {
+    let export: WalletOutputs = serde_json::from_str(json).map_err(|e| SweepError::Parse(e.to_string()))?;
+    if export.version != SWEEP_FORMAT_VERSION {
+        return Err(SweepError::UnsupportedVersion(export.version));
+    }
+    export
+        .outputs
+        .iter()
+        .map(|output| {
+            let commitment = from_hex(&output.commitment)
+                .ok()
+                .and_then(|bytes| PedersenCommitment::from_bytes(&bytes).ok())
+                .ok_or_else(|| SweepError::Parse(format!("'{}' is not a hex encoded commitment", output.commitment)))?;
+            let script_message = from_hex(&output.script_message)
+                .ok()
+                .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+                .ok_or_else(|| {
+                    SweepError::Parse(format!("'{}' is not a hex encoded 32-byte hash", output.script_message))
+                })?;
+            Ok(SweepInput {
+                value: output.value,
+                mask_index: output.mask_index,
+                commitment,
+                script_message: Challenge::from_hashed(SCRIPT_MESSAGE_LABEL, script_message),
+                maturity: output.maturity,
+            })
+        })
+        .collect()
+}
+
+/// Sort `outputs` into inputs, dust and outputs the device cannot spend
+pub fn scan_outputs(
+    signer: &LedgerTransactionSigner,
+    outputs: Vec<SweepInput>,
+    dust_threshold: u64,
+) -> Result<SweepScan, SweepError> {
+    let requests = outputs
+        .iter()
+        .map(|output| CommitmentRequest {
+            value: output.value,
+            index: output.mask_index,
+        })
+        .collect::<Vec<_>>();
+    let commitments = batch_commitments_with_version(signer.device(), &requests, signer.derivation_version())
+        .map_err(SignerError::from)?;
+    let mut scan = SweepScan::default();
+    for (output, commitment) in outputs.into_iter().zip(commitments) {
+        if output.commitment != commitment {
+            scan.foreign.push(output);
+        } else if output.value <= dust_threshold {
+            scan.dust.push(output);
+        } else {
+            scan.inputs.push(output);
+        }
+    }
+    Ok(scan)
+}
+
+/// The output a sweep pays into
+#[derive(Clone, Debug)]
+pub struct SweepOutput {
+    pub address: TariAddress,
+    pub value: u64,
+}
+
+impl SweepOutput {
+    /// Spendable by the key of the address
+    pub fn script(&self) -> TariScript {
+        TariScript::new(vec![Opcode::PushPubKey(self.address.public_key().clone())])
+    }
+
+    /// The challenge signed for the output, binding the address, the value, the script and the features
+    pub fn challenge(&self) -> Challenge {
+        DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(SWEEP_OUTPUT_LABEL)
+            .chain_fixed(&self.address.to_bytes())
+            .chain(&self.value)
+            .chain(&self.script())
+            .chain(&DEFAULT_OUTPUT_FEATURES)
+            .finalize_challenge()
+    }
+
+    fn to_output(&self) -> OutputToSign {
+        OutputToSign {
+            value: self.value,
+            is_change: false,
+            features_and_scripts_size: DEFAULT_OUTPUT_FEATURES.len() + self.script().encoded_len(),
+            challenge: self.challenge(),
+            recipient: Some(self.address.clone()),
+            features_title: None,
+        }
+    }
+}
+
+/// The output paying the inputs of `scan`, less the fee, to `address`
+pub fn sweep_output(
+    signer: &LedgerTransactionSigner,
+    scan: &SweepScan,
+    address: TariAddress,
+    network: Network,
+) -> Result<SweepOutput, SweepError> {
+    if address.network() != network {
+        return Err(SweepError::WrongNetwork {
+            address: address.network(),
+            profile: network,
+        });
+    }
+    if scan.inputs.is_empty() {
+        return Err(SweepError::NothingToSweep);
+    }
+    let total = scan.total();
+    let mut output = SweepOutput { address, value: total };
+    // The size of the output does not depend on its value
+    let fee = signer.fee(scan.inputs.len(), &[output.to_output()]);
+    match total.checked_sub(fee) {
+        Some(value) if value > 0 => {
+            output.value = value;
+            Ok(output)
+        },
+        _ => Err(SignerError::InsufficientFunds {
+            available: total,
+            required: fee.saturating_add(1),
+        }
+        .into()),
+    }
+}
+
+/// Sign the sweep: first the output, which the device shows together with the fee, then the script message of every
+/// input. Returns the signatures as JSON for the wallet to finish the transaction with.
+pub fn sign_sweep(
+    signer: &LedgerTransactionSigner,
+    scan: &SweepScan,
+    output: &SweepOutput,
+) -> Result<String, SweepError> {
+    let to_sign = output.to_output();
+    let signed = signer.sign_outputs(scan.inputs.len(), &[to_sign.clone()])?;
+    let signature = signed
+        .signatures
+        .first()
+        .expect("one signature is returned for the one output");
+    let inputs = scan
+        .inputs
+        .iter()
+        .map(|input| Ok(input_json(input, &signer.sign_script_message(&input.script_message)?)))
+        .collect::<Result<Vec<_>, SweepError>>()?;
+    let document = json!({
+        "version": SWEEP_FORMAT_VERSION,
+        "fee": signed.fee,
+        "inputs": inputs,
+        "outputs": [{
+            "value": output.value,
+            "recipient_address": output.address.to_hex(),
+            "script": to_hex(&output.script().to_bytes()),
+            "script_challenge": to_hex(to_sign.challenge.as_bytes()),
+            "script_signature": signature_json(signature),
+        }],
+    });
+    Ok(serde_json::to_string_pretty(&document).expect("a JSON value always serializes"))
+}
+
+pub(crate) fn input_json(input: &SweepInput, signature: &LedgerSignature) -> Value {
+    json!({
+        "commitment": to_hex(input.commitment.as_bytes()),
+        "value": input.value,
+        "script_signature": signature_json(signature),
+    })
+}
+
+pub(crate) fn signature_json(signature: &LedgerSignature) -> Value {
+    json!({
+        "public_key": to_hex(signature.public_key.as_bytes()),
+        "public_nonce": to_hex(signature.public_nonce.as_bytes()),
+        "signature": to_hex(signature.s.as_bytes()),
+    })
+}
@@ -0,0 +1,18 @@
+//! Fixtures shared by the unit tests of the crate
+
+use ledger_transport::APDUCommand;
+use tari_ledger_protocol::CLA;
+
+use crate::device::Instruction;
+
+/// A command of the Tari app without data, for the tests that only look at what it asks for. Other classes and data
+/// go in with struct update syntax.
+pub fn command(instruction: Instruction, p1: u8) -> APDUCommand<Vec<u8>> {
+    APDUCommand {
+        cla: CLA,
+        ins: instruction.as_byte(),
+        p1,
+        p2: 0,
+        data: Vec::new(),
+    }
+}
@@ -0,0 +1,1072 @@
+//! The interactive transaction protocol
+//! A Tari transaction to another wallet is built by the sender and the receiver together. The sender sends a
+//! [`SenderMessage`] with the amount, the terms of the kernel and its share of it, the receiver creates its output,
+//! signs its share of the kernel and answers with a [`ReceiverReply`], and the sender finishes the transaction. The
+//! messages travel as JSON over whatever channel the two wallets share. Nothing in them is secret, but every key and
+//! signature is checked when it is read.
+//!
+//! [`receive`] plays the receiver with the device: the commitment mask and the script key of the new output are the
+//! device's, and so is the partial kernel signature, so the host never holds a secret key of the output. The script key
+//! is bound to the commitment, see [`script_keys`](crate::script_keys), so it can be recovered from the commitment
+//! alone. The range proof of the output is not part of the reply.
+//!
+//! [`send`] plays the sender: the user approves the payment and the change on the device before the message goes out,
+//! because the app only takes the masks of the inputs into the excess of an approved transaction. The host takes a
+//! random kernel offset out of the device's excess, so that the kernel does not reveal which inputs and outputs it
+//! belongs to. [`PendingSend::finish`] then has the device sign its share of the kernel, the sender half of the
+//! metadata signature of each output and the script offset, signs the outputs within the approval and checks the
+//! [`FinalizedTransaction`] the way a base node would. The receiver adds no commitment half to the metadata signatures,
+//! so they are made with the identity as the ephemeral commitment, and the transaction does not carry range proofs or
+//! the script signatures of its inputs yet.
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tari_crypto::{
+    commitment::HomomorphicCommitmentFactory,
+    keys::PublicKey,
+    ristretto::{
+        pedersen::{extended_commitment_factory::ExtendedPedersenCommitmentFactory, PedersenCommitment},
+        RistrettoPublicKey,
+        RistrettoSchnorr,
+        RistrettoSecretKey,
+    },
+    tari_utilities::{
+        hex::{from_hex, to_hex},
+        ByteArray,
+    },
+};
+
+use crate::{
+    address::TariAddress,
+    commitment::{batch_commitments_with_version, CommitmentRequest},
+    device::{DerivationVersion, KeyBranch, LedgerDevice},
+    domains::{CHANGE_OUTPUT_LABEL, OUTPUT_METADATA_LABEL, RECEIVER_OUTPUT_LABEL},
+    errors::{DeviceError, SignerError, StoreError, TransactionProtocolError},
+    export::{branch_path, public_key_with_version},
+    hashing::{Challenge, DomainSeparatedConsensusHasher, TransactionHashDomain},
+    kernel::{kernel_challenge, kernel_message, kernel_nonce, sign_kernel, verify_partial_signature, KernelShare},
+    script_keys::bound_script_public_key,
+    sender_offset::{
+        script_offset,
+        sign_sender_offset,
+        verify_sender_offset_signature,
+        ScriptOffsetInput,
+        SenderOffsetSignature,
+    },
+    signature::LedgerSignature,
+    signer::{
+        change_output_size,
+        AwaitingSignatures,
+        ChangeOutput,
+        LedgerTransactionSigner,
+        OutputToSign,
+        TransactionSession,
+        DEFAULT_OUTPUT_FEATURES,
+    },
+    state_store::LedgerStateStore,
+    verify::verify_challenge_signature,
+};
+
+/// The version of the message format this module understands
+pub const TRANSACTION_PROTOCOL_VERSION: u64 = 1;
+
+/// What the sender of a transaction tells its receiver
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SenderMessage {
+    /// Chosen by the sender, the reply has to carry the same id
+    pub tx_id: u64,
+    /// The value of the receiver's output, in microTari
+    pub amount: u64,
+    /// In microTari
+    pub fee: u64,
+    pub lock_height: u64,
+    /// The sender's share of the kernel
+    pub kernel: KernelShare,
+    /// The sender offset public key of the receiver's output
+    pub sender_offset_public_key: RistrettoPublicKey,
+}
+
+/// The output the receiver created
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReceiverOutput {
+    pub commitment: PedersenCommitment,
+    pub script_public_key: RistrettoPublicKey,
+    pub sender_offset_public_key: RistrettoPublicKey,
+}
+
+/// What the receiver answers a [`SenderMessage`] with
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReceiverReply {
+    pub tx_id: u64,
+    pub output: ReceiverOutput,
+    /// The receiver's share of the kernel
+    pub kernel: KernelShare,
+    pub partial_signature: RistrettoSecretKey,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SenderMessageJson {
+    version: u64,
+    tx_id: u64,
+    amount: u64,
+    fee: u64,
+    lock_height: u64,
+    public_excess: String,
+    public_nonce: String,
+    sender_offset_public_key: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ReceiverReplyJson {
+    version: u64,
+    tx_id: u64,
+    commitment: String,
+    script_public_key: String,
+    sender_offset_public_key: String,
+    public_excess: String,
+    public_nonce: String,
+    partial_signature: String,
+}
+
+impl SenderMessage {
+    /// The message the kernel signature covers
+    pub fn kernel_message(&self) -> [u8; 32] {
+        kernel_message(self.fee, self.lock_height)
+    }
+
+    pub fn to_json(&self) -> String {
+        let message = SenderMessageJson {
+            version: TRANSACTION_PROTOCOL_VERSION,
+            tx_id: self.tx_id,
+            amount: self.amount,
+            fee: self.fee,
+            lock_height: self.lock_height,
+            public_excess: to_hex(self.kernel.public_excess.as_bytes()),
+            public_nonce: to_hex(self.kernel.public_nonce.as_bytes()),
+            sender_offset_public_key: to_hex(self.sender_offset_public_key.as_bytes()),
+        };
+        serde_json::to_string_pretty(&message).expect("a sender message always serializes")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, TransactionProtocolError> {
+        let message: SenderMessageJson =
+            serde_json::from_str(json).map_err(|e| TransactionProtocolError::Parse(e.to_string()))?;
+        if message.version != TRANSACTION_PROTOCOL_VERSION {
+            return Err(TransactionProtocolError::UnsupportedVersion(message.version));
+        }
+        Ok(Self {
+            tx_id: message.tx_id,
+            amount: message.amount,
+            fee: message.fee,
+            lock_height: message.lock_height,
+            kernel: KernelShare {
+                public_excess: parse_key(&message.public_excess)?,
+                public_nonce: parse_key(&message.public_nonce)?,
+            },
+            sender_offset_public_key: parse_key(&message.sender_offset_public_key)?,
+        })
+    }
+}
+
+impl ReceiverReply {
+    /// The sums of the public nonces and excesses of both parties, which the kernel is signed under
+    pub fn totals(&self, sender: &KernelShare) -> (RistrettoPublicKey, RistrettoPublicKey) {
+        (
+            &sender.public_nonce + &self.kernel.public_nonce,
+            &sender.public_excess + &self.kernel.public_excess,
+        )
+    }
+
+    /// Check that the reply answers `message` and that the receiver's partial signature verifies
+    pub fn verify(&self, message: &SenderMessage) -> Result<(), TransactionProtocolError> {
+        if self.tx_id != message.tx_id {
+            return Err(TransactionProtocolError::WrongTransaction {
+                expected: message.tx_id,
+                actual: self.tx_id,
+            });
+        }
+        if self.output.sender_offset_public_key != message.sender_offset_public_key {
+            return Err(TransactionProtocolError::Mismatch("sender offset public key"));
+        }
+        let (total_nonce, total_excess) = self.totals(&message.kernel);
+        if !verify_partial_signature(
+            &self.kernel,
+            &self.partial_signature,
+            &total_nonce,
+            &total_excess,
+            &message.kernel_message(),
+        ) {
+            return Err(TransactionProtocolError::InvalidSignature(
+                "receiver's partial kernel signature",
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> String {
+        let reply = ReceiverReplyJson {
+            version: TRANSACTION_PROTOCOL_VERSION,
+            tx_id: self.tx_id,
+            commitment: to_hex(self.output.commitment.as_bytes()),
+            script_public_key: to_hex(self.output.script_public_key.as_bytes()),
+            sender_offset_public_key: to_hex(self.output.sender_offset_public_key.as_bytes()),
+            public_excess: to_hex(self.kernel.public_excess.as_bytes()),
+            public_nonce: to_hex(self.kernel.public_nonce.as_bytes()),
+            partial_signature: to_hex(self.partial_signature.as_bytes()),
+        };
+        serde_json::to_string_pretty(&reply).expect("a receiver reply always serializes")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, TransactionProtocolError> {
+        let reply: ReceiverReplyJson =
+            serde_json::from_str(json).map_err(|e| TransactionProtocolError::Parse(e.to_string()))?;
+        if reply.version != TRANSACTION_PROTOCOL_VERSION {
+            return Err(TransactionProtocolError::UnsupportedVersion(reply.version));
+        }
+        Ok(Self {
+            tx_id: reply.tx_id,
+            output: ReceiverOutput {
+                commitment: PedersenCommitment::from_bytes(&parse_bytes(&reply.commitment)?).map_err(|_| {
+                    TransactionProtocolError::Parse(format!("'{}' is not a commitment", reply.commitment))
+                })?,
+                script_public_key: parse_key(&reply.script_public_key)?,
+                sender_offset_public_key: parse_key(&reply.sender_offset_public_key)?,
+            },
+            kernel: KernelShare {
+                public_excess: parse_key(&reply.public_excess)?,
+                public_nonce: parse_key(&reply.public_nonce)?,
+            },
+            partial_signature: RistrettoSecretKey::from_bytes(&parse_bytes(&reply.partial_signature)?).map_err(
+                |_| TransactionProtocolError::Parse(format!("'{}' is not a scalar", reply.partial_signature)),
+            )?,
+        })
+    }
+}
+
+/// Receive `message` into an output masked with the key at `mask_index` and locked with the script key at
+/// `script_key_index` of `account`, signing the receiver's share of the kernel on the device
+pub fn receive(
+    device: &LedgerDevice,
+    message: &SenderMessage,
+    account: u32,
+    mask_index: u32,
+    script_key_index: u32,
+    version: DerivationVersion,
+) -> Result<ReceiverReply, TransactionProtocolError> {
+    let request = CommitmentRequest {
+        value: message.amount,
+        index: mask_index,
+    };
+    let commitment = batch_commitments_with_version(device, &[request], version)?
+        .pop()
+        .ok_or(DeviceError::InvalidResponse("the device returned no commitment"))?;
+    let script_key = public_key_with_version(device, account, KeyBranch::ScriptKey, script_key_index, version)?;
+
+    // The receiver only creates its output, so its excess is the mask of that output
+    let kernel = kernel_nonce(device, &[], &[mask_index], message.fee, message.lock_height, version)?;
+    let total_nonce = &message.kernel.public_nonce + &kernel.public_nonce;
+    let total_excess = &message.kernel.public_excess + &kernel.public_excess;
+    let partial_signature = sign_kernel(device, &kernel, &total_nonce, &total_excess, &message.kernel_message())?;
+    Ok(ReceiverReply {
+        tx_id: message.tx_id,
+        output: ReceiverOutput {
+            script_public_key: bound_script_public_key(&script_key, &commitment),
+            commitment,
+            sender_offset_public_key: message.sender_offset_public_key.clone(),
+        },
+        kernel,
+        partial_signature,
+    })
+}
+
+/// An output of account 0 for the sender to spend
+#[derive(Clone, Debug)]
+pub struct SenderInput {
+    pub commitment: PedersenCommitment,
+    /// In microTari
+    pub value: u64,
+    /// The index of its mask in the commitment mask branch
+    pub mask_index: u32,
+    pub script_key: ScriptOffsetInput,
+}
+
+/// A payment to another wallet for [`send`] to build
+#[derive(Clone, Debug)]
+pub struct SendRequest {
+    /// Sent to the receiver, whose reply has to carry it
+    pub tx_id: u64,
+    /// In microTari
+    pub amount: u64,
+    pub lock_height: u64,
+    /// The address the device shows before signing the payment, see [`OutputToSign::recipient`]
+    pub recipient: Option<TariAddress>,
+    pub inputs: Vec<SenderInput>,
+}
+
+/// A spent output of a [`FinalizedTransaction`]
+#[derive(Clone, Debug)]
+pub struct TransactionInput {
+    pub commitment: PedersenCommitment,
+    /// The script public key the spent output carries
+    pub script_public_key: RistrettoPublicKey,
+}
+
+/// A new output of a [`FinalizedTransaction`]
+#[derive(Clone, Debug)]
+pub struct TransactionOutput {
+    pub commitment: PedersenCommitment,
+    pub script_public_key: RistrettoPublicKey,
+    pub features: [u8; 16],
+    /// The sender half of the metadata signature, over [`TransactionOutput::metadata_message`]
+    pub metadata_signature: SenderOffsetSignature,
+    /// The device's signature over the challenge of the output the user approved
+    pub signature: LedgerSignature,
+}
+
+impl TransactionOutput {
+    pub fn metadata_message(&self) -> [u8; 32] {
+        output_metadata_message(&self.script_public_key, &self.features)
+    }
+}
+
+/// The kernel of a [`FinalizedTransaction`], signed by both parties
+#[derive(Clone, Debug)]
+pub struct TransactionKernel {
+    /// In microTari
+    pub fee: u64,
+    pub lock_height: u64,
+    pub excess: RistrettoPublicKey,
+    pub signature: RistrettoSchnorr,
+}
+
+/// A finished transaction with every signature in place, ready to broadcast
+#[derive(Clone, Debug)]
+pub struct FinalizedTransaction {
+    pub tx_id: u64,
+    pub inputs: Vec<TransactionInput>,
+    /// The receiver's output first, then the change output if there is one
+    pub outputs: Vec<TransactionOutput>,
+    pub kernel: TransactionKernel,
+    /// The part of the excess that is not in the kernel
+    pub offset: RistrettoSecretKey,
+    pub script_offset: RistrettoSecretKey,
+    /// The change output, for the wallet to keep track of
+    pub change: Option<ChangeOutput>,
+}
+
+impl FinalizedTransaction {
+    /// Check that the outputs balance the inputs and the fee, and that the kernel signature, the metadata signatures
+    /// and the script offset verify
+    pub fn validate(&self) -> Result<(), TransactionProtocolError> {
+        let fee =
+            ExtendedPedersenCommitmentFactory::default().commit_value(&RistrettoSecretKey::default(), self.kernel.fee);
+        let outputs = self.outputs.iter().fold(fee.as_public_key().clone(), |sum, output| {
+            &sum + output.commitment.as_public_key()
+        });
+        let excess = &self.kernel.excess + &RistrettoPublicKey::from_secret_key(&self.offset);
+        let inputs = self
+            .inputs
+            .iter()
+            .fold(excess, |sum, input| &sum + input.commitment.as_public_key());
+        if outputs != inputs {
+            return Err(TransactionProtocolError::InvalidTransaction(
+                "the outputs and the fee do not balance the inputs and the excess",
+            ));
+        }
+
+        let challenge = kernel_challenge(
+            self.kernel.signature.get_public_nonce(),
+            &self.kernel.excess,
+            &kernel_message(self.kernel.fee, self.kernel.lock_height),
+        );
+        if !verify_challenge_signature(&self.kernel.excess, &self.kernel.signature, &challenge) {
+            return Err(TransactionProtocolError::InvalidSignature("kernel signature"));
+        }
+        for output in &self.outputs {
+            if !verify_sender_offset_signature(
+                &output.metadata_signature,
+                &output.commitment,
+                &no_ephemeral_commitment(),
+                &output.metadata_message(),
+            ) {
+                return Err(TransactionProtocolError::InvalidSignature("metadata signature"));
+            }
+        }
+
+        let script_keys = self.inputs.iter().fold(RistrettoPublicKey::default(), |sum, input| {
+            &sum + &input.script_public_key
+        });
+        let offset = self.outputs.iter().fold(
+            RistrettoPublicKey::from_secret_key(&self.script_offset),
+            |sum, output| &sum + &output.metadata_signature.sender_offset_public_key,
+        );
+        if offset != script_keys {
+            return Err(TransactionProtocolError::InvalidTransaction(
+                "the script offset does not match the script and sender offset keys",
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> String {
+        let inputs = self
+            .inputs
+            .iter()
+            .map(|input| {
+                json!({
+                    "commitment": to_hex(input.commitment.as_bytes()),
+                    "script_public_key": to_hex(input.script_public_key.as_bytes()),
+                })
+            })
+            .collect::<Vec<_>>();
+        let outputs = self
+            .outputs
+            .iter()
+            .map(|output| {
+                let metadata_signature = &output.metadata_signature.signature;
+                json!({
+                    "commitment": to_hex(output.commitment.as_bytes()),
+                    "script_public_key": to_hex(output.script_public_key.as_bytes()),
+                    "features": to_hex(&output.features),
+                    "sender_offset_public_key": to_hex(output.metadata_signature.sender_offset_public_key.as_bytes()),
+                    "metadata_signature": {
+                        "public_nonce": to_hex(metadata_signature.get_public_nonce().as_bytes()),
+                        "signature": to_hex(metadata_signature.get_signature().as_bytes()),
+                    },
+                    "output_signature": {
+                        "public_key": to_hex(output.signature.public_key.as_bytes()),
+                        "public_nonce": to_hex(output.signature.public_nonce.as_bytes()),
+                        "signature": to_hex(output.signature.s.as_bytes()),
+                    },
+                })
+            })
+            .collect::<Vec<_>>();
+        let transaction = json!({
+            "version": TRANSACTION_PROTOCOL_VERSION,
+            "tx_id": self.tx_id,
+            "inputs": inputs,
+            "outputs": outputs,
+            "kernel": {
+                "fee": self.kernel.fee,
+                "lock_height": self.kernel.lock_height,
+                "excess": to_hex(self.kernel.excess.as_bytes()),
+                "public_nonce": to_hex(self.kernel.signature.get_public_nonce().as_bytes()),
+                "signature": to_hex(self.kernel.signature.get_signature().as_bytes()),
+            },
+            "offset": to_hex(self.offset.as_bytes()),
+            "script_offset": to_hex(self.script_offset.as_bytes()),
+        });
+        serde_json::to_string_pretty(&transaction).expect("a transaction always serializes")
+    }
+}
+
+/// A payment the user approved on the device, waiting for the receiver's reply to its [`SenderMessage`]. The approval
+/// lapses with the signer's session expiry, so the reply has to come back within it.
+pub struct PendingSend<'a> {
+    device: &'a LedgerDevice,
+    version: DerivationVersion,
+    message: SenderMessage,
+    session: TransactionSession<AwaitingSignatures<'a>>,
+    inputs: Vec<SenderInput>,
+    change: Option<ChangeOutput>,
+    /// The share the device took, before the kernel offset was taken out of its excess
+    device_share: KernelShare,
+    kernel_offset: RistrettoSecretKey,
+    /// The indices of the sender offset keys of the outputs, in order
+    sender_offset_indices: Vec<u32>,
+}
+
+impl PendingSend<'_> {
+    /// The message to send to the receiver
+    pub fn message(&self) -> &SenderMessage {
+        &self.message
+    }
+
+    pub fn change(&self) -> Option<&ChangeOutput> {
+        self.change.as_ref()
+    }
+
+    /// Finish the transaction with the receiver's `reply`
+    pub fn finish(self, reply: &ReceiverReply) -> Result<FinalizedTransaction, TransactionProtocolError> {
+        reply.verify(&self.message)?;
+        let message = self.message.kernel_message();
+        let (total_nonce, total_excess) = reply.totals(&self.message.kernel);
+        let s = sign_kernel(self.device, &self.device_share, &total_nonce, &total_excess, &message)?;
+        // The device signed with the whole excess, the kernel offset is taken back out of its share
+        let e = RistrettoSecretKey::from_bytes(&kernel_challenge(&total_nonce, &total_excess, &message))
+            .expect("a 32 byte hash always reduces to a scalar");
+        let sender_signature = &s - &(&e * &self.kernel_offset);
+        let kernel = TransactionKernel {
+            fee: self.message.fee,
+            lock_height: self.message.lock_height,
+            excess: total_excess,
+            signature: RistrettoSchnorr::new(total_nonce, &sender_signature + &reply.partial_signature),
+        };
+
+        let mut outputs = vec![(
+            reply.output.commitment.clone(),
+            reply.output.script_public_key.clone(),
+            DEFAULT_OUTPUT_FEATURES,
+        )];
+        if let Some(change) = &self.change {
+            outputs.push((
+                change.commitment.clone(),
+                change.script_public_key.clone(),
+                change.features,
+            ));
+        }
+        let mut metadata_signatures = Vec::with_capacity(outputs.len());
+        for ((commitment, script_public_key, features), index) in outputs.iter().zip(&self.sender_offset_indices) {
+            let signature = sign_sender_offset(
+                self.device,
+                0,
+                *index,
+                commitment,
+                &no_ephemeral_commitment(),
+                &output_metadata_message(script_public_key, features),
+                self.version,
+            )?;
+            metadata_signatures.push(signature);
+        }
+        if metadata_signatures[0].sender_offset_public_key != self.message.sender_offset_public_key {
+            return Err(DeviceError::InvalidResponse(
+                "the sender offset key does not match the one sent to the receiver",
+            )
+            .into());
+        }
+        // The app only takes the script offset before the last output of the approved transaction is signed
+        let script_keys = self
+            .inputs
+            .iter()
+            .map(|input| input.script_key.clone())
+            .collect::<Vec<_>>();
+        let script_offset = script_offset(
+            self.device,
+            self.session.nonce(),
+            0,
+            &script_keys,
+            &metadata_signatures,
+            self.version,
+        )?;
+        let signatures = self.session.sign_all()?.into_signed_outputs().signatures;
+
+        let transaction = FinalizedTransaction {
+            tx_id: self.message.tx_id,
+            inputs: self
+                .inputs
+                .iter()
+                .map(|input| TransactionInput {
+                    commitment: input.commitment.clone(),
+                    script_public_key: input.script_key.output_script_public_key(),
+                })
+                .collect(),
+            outputs: outputs
+                .into_iter()
+                .zip(metadata_signatures)
+                .zip(signatures)
+                .map(
+                    |(((commitment, script_public_key, features), metadata_signature), signature)| TransactionOutput {
+                        commitment,
+                        script_public_key,
+                        features,
+                        metadata_signature,
+                        signature,
+                    },
+                )
+                .collect(),
+            kernel,
+            offset: self.kernel_offset,
+            script_offset,
+            change: self.change,
+        };
+        transaction.validate()?;
+        Ok(transaction)
+    }
+}
+
+/// The challenge the device signs for the receiver's output. The receiver creates the output after the user approved
+/// the payment, so it covers what the sender knows of it.
+pub fn receiver_output_challenge(tx_id: u64, amount: u64, sender_offset_public_key: &RistrettoPublicKey) -> Challenge {
+    DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(RECEIVER_OUTPUT_LABEL)
+        .chain(&tx_id)
+        .chain(&amount)
+        .chain(sender_offset_public_key)
+        .chain(&DEFAULT_OUTPUT_FEATURES)
+        .finalize_challenge()
+}
+
+/// The message the metadata signature of an output with `script_public_key` and `features` covers
+pub fn output_metadata_message(script_public_key: &RistrettoPublicKey, features: &[u8; 16]) -> [u8; 32] {
+    DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(OUTPUT_METADATA_LABEL)
+        .chain(script_public_key)
+        .chain(features)
+        .finalize()
+}
+
+/// Start paying `request` from the inputs of account 0, adding a change output with the next keys in `store` for
+/// whatever the payment and the fee leave over. The user approves the payment on the device before this returns.
+pub fn send<'a>(
+    signer: &'a LedgerTransactionSigner<'a>,
+    store: &dyn LedgerStateStore,
+    request: SendRequest,
+) -> Result<PendingSend<'a>, TransactionProtocolError> {
+    let device = signer.device();
+    let version = signer.derivation_version();
+    let mut sender_offset_indices = vec![reserve_sender_offset_index(store)?];
+    let sender_offset_public_key =
+        public_key_with_version(device, 0, KeyBranch::SenderOffset, sender_offset_indices[0], version)?;
+    let payment = OutputToSign {
+        value: request.amount,
+        is_change: false,
+        // The receiver locks its output to its script key, the same script a change output has
+        features_and_scripts_size: change_output_size(),
+        challenge: receiver_output_challenge(request.tx_id, request.amount, &sender_offset_public_key),
+        recipient: request.recipient.clone(),
+        features_title: None,
+    };
+
+    let num_inputs = request.inputs.len();
+    let input_value = request
+        .inputs
+        .iter()
+        .try_fold(0u64, |total, input| total.checked_add(input.value))
+        .ok_or(SignerError::ValueOverflow)?;
+    let mut outputs = vec![payment];
+    let mut change = None;
+    if Some(input_value) != request.amount.checked_add(signer.fee(num_inputs, &outputs)) {
+        let placeholder = OutputToSign {
+            value: 0,
+            is_change: true,
+            features_and_scripts_size: change_output_size(),
+            challenge: Challenge::from_hashed(CHANGE_OUTPUT_LABEL, [0u8; 32]),
+            recipient: None,
+            features_title: None,
+        };
+        let fee = signer.fee(num_inputs, &[outputs[0].clone(), placeholder]);
+        let required = request.amount.checked_add(fee).ok_or(SignerError::ValueOverflow)?;
+        let value = match input_value.checked_sub(required) {
+            Some(value) if value > 0 => value,
+            // A change output has to hold something
+            _ => {
+                return Err(SignerError::InsufficientFunds {
+                    available: input_value,
+                    required: required.saturating_add(1),
+                }
+                .into())
+            },
+        };
+        let output = signer
+            .change_outputs(store, &[value])?
+            .pop()
+            .ok_or(DeviceError::InvalidResponse("the device returned no change commitment"))?;
+        outputs.push(output.to_output());
+        sender_offset_indices.push(reserve_sender_offset_index(store)?);
+        change = Some(output);
+    }
+
+    let session = signer.transaction_session(num_inputs, outputs).approve()?;
+    let spent = request.inputs.iter().map(|input| input.mask_index).collect::<Vec<_>>();
+    let created = change.iter().map(|change| change.key_index).collect::<Vec<_>>();
+    let device_share = kernel_nonce(device, &spent, &created, session.fee(), request.lock_height, version)?;
+    let kernel_offset = device.random_secret();
+    let message = SenderMessage {
+        tx_id: request.tx_id,
+        amount: request.amount,
+        fee: session.fee(),
+        lock_height: request.lock_height,
+        kernel: KernelShare {
+            public_excess: &device_share.public_excess - &RistrettoPublicKey::from_secret_key(&kernel_offset),
+            public_nonce: device_share.public_nonce.clone(),
+        },
+        sender_offset_public_key,
+    };
+    Ok(PendingSend {
+        device,
+        version,
+        message,
+        session,
+        inputs: request.inputs,
+        change,
+        device_share,
+        kernel_offset,
+        sender_offset_indices,
+    })
+}
+
+/// The receiver adds no commitment half to the metadata signatures yet, so their ephemeral commitment is the identity
+fn no_ephemeral_commitment() -> PedersenCommitment {
+    PedersenCommitment::from_public_key(&RistrettoPublicKey::default())
+}
+
+fn reserve_sender_offset_index(store: &dyn LedgerStateStore) -> Result<u32, SignerError> {
+    let index = store.next_key_index(&branch_path(0, KeyBranch::SenderOffset))?;
+    let out_of_range = StoreError::Corrupt("the sender offset key index is out of range");
+    // Key indices must stay below the hardened range
+    u32::try_from(index)
+        .ok()
+        .filter(|index| *index < 0x8000_0000)
+        .ok_or(SignerError::Store(out_of_range))
+}
+
+fn parse_bytes(hex: &str) -> Result<Vec<u8>, TransactionProtocolError> {
+    from_hex(hex).map_err(|_| TransactionProtocolError::Parse(format!("'{}' is not hex", hex)))
+}
+
+fn parse_key(hex: &str) -> Result<RistrettoPublicKey, TransactionProtocolError> {
+    RistrettoPublicKey::from_bytes(&parse_bytes(hex)?)
+        .map_err(|_| TransactionProtocolError::Parse(format!("'{}' is not a public key", hex)))
+}
+
+#[cfg(test)]
+mod test {
+    use tari_ledger_protocol::P1_KERNEL_SIGN;
+
+    use super::*;
+    use crate::{
+        address::Network,
+        device::Instruction,
+        dry_run::{DryRunLog, DryRunTransport},
+        fee::FeeCalculator,
+        sender_offset::metadata_challenge,
+        state_store::FileStateStore,
+    };
+
+    fn scalar(n: u64) -> RistrettoSecretKey {
+        RistrettoSecretKey::from(n)
+    }
+
+    fn public(k: &RistrettoSecretKey) -> RistrettoPublicKey {
+        RistrettoPublicKey::from_secret_key(k)
+    }
+
+    fn commit(mask: &RistrettoSecretKey, value: u64) -> PedersenCommitment {
+        ExtendedPedersenCommitmentFactory::default().commit_value(mask, value)
+    }
+
+    fn challenge_scalar(challenge: &[u8; 32]) -> RistrettoSecretKey {
+        RistrettoSecretKey::from_bytes(challenge).unwrap()
+    }
+
+    /// An output masked with `mask`, locked with the script key `script_key` and signed with the sender offset key
+    /// `sender_offset`
+    fn output(mask: u64, value: u64, script_key: u64, sender_offset: u64) -> TransactionOutput {
+        let commitment = commit(&scalar(mask), value);
+        let script_public_key = public(&scalar(script_key));
+        let features = DEFAULT_OUTPUT_FEATURES;
+        let (k, r) = (scalar(sender_offset), scalar(sender_offset + 1000));
+        let message = output_metadata_message(&script_public_key, &features);
+        let e = metadata_challenge(
+            &no_ephemeral_commitment(),
+            &public(&r),
+            &public(&k),
+            &commitment,
+            &message,
+        );
+        let metadata_signature = SenderOffsetSignature {
+            index: 0,
+            sender_offset_public_key: public(&k),
+            signature: RistrettoSchnorr::new(public(&r), &r + &(&challenge_scalar(&e) * &k)),
+        };
+        TransactionOutput {
+            commitment,
+            script_public_key,
+            features,
+            metadata_signature,
+            signature: LedgerSignature::new(
+                public(&scalar(1)),
+                RistrettoSchnorr::new(public(&scalar(2)), scalar(3)),
+                RECEIVER_OUTPUT_LABEL,
+            ),
+        }
+    }
+
+    /// A transaction of an input of 10 000 µT masked with 5 and locked with the script key 7, into a payment of
+    /// 6 000 µT and a change output of 3 000 µT, with every secret in software
+    fn transaction() -> FinalizedTransaction {
+        let (fee, lock_height) = (1_000, 0);
+        let offset = scalar(40);
+        // The masks of the outputs less the mask of the input and the offset
+        let excess = &(&(&scalar(11) + &scalar(12)) - &scalar(5)) - &offset;
+        let nonce = scalar(50);
+        let challenge = kernel_challenge(&public(&nonce), &public(&excess), &kernel_message(fee, lock_height));
+        let signature = &nonce + &(&challenge_scalar(&challenge) * &excess);
+        FinalizedTransaction {
+            tx_id: 1,
+            inputs: vec![TransactionInput {
+                commitment: commit(&scalar(5), 10_000),
+                script_public_key: public(&scalar(7)),
+            }],
+            outputs: vec![output(11, 6_000, 21, 31), output(12, 3_000, 22, 32)],
+            kernel: TransactionKernel {
+                fee,
+                lock_height,
+                excess: public(&excess),
+                signature: RistrettoSchnorr::new(public(&nonce), signature),
+            },
+            offset,
+            // The script key of the input less the sender offset keys of the outputs
+            script_offset: &(&scalar(7) - &scalar(31)) - &scalar(32),
+            change: None,
+        }
+    }
+
+    fn invalid(transaction: FinalizedTransaction) -> &'static str {
+        match transaction.validate() {
+            Err(TransactionProtocolError::InvalidTransaction(check)) => check,
+            Err(TransactionProtocolError::InvalidSignature(signature)) => signature,
+            other => panic!("the transaction was not refused: {:?}", other),
+        }
+    }
+
+    /// The sender's share of a kernel and the message it sends, with the sender offset key 31
+    fn message(tx_id: u64) -> SenderMessage {
+        SenderMessage {
+            tx_id,
+            amount: 6_000,
+            fee: 1_000,
+            lock_height: 10,
+            kernel: KernelShare {
+                public_excess: public(&scalar(60)),
+                public_nonce: public(&scalar(61)),
+            },
+            sender_offset_public_key: public(&scalar(31)),
+        }
+    }
+
+    /// The receiver's reply to `message`, signed with the excess 70 and the nonce 71
+    fn reply(message: &SenderMessage) -> ReceiverReply {
+        let (excess, nonce) = (scalar(70), scalar(71));
+        let mut reply = ReceiverReply {
+            tx_id: message.tx_id,
+            output: ReceiverOutput {
+                commitment: commit(&scalar(70), message.amount),
+                script_public_key: public(&scalar(72)),
+                sender_offset_public_key: message.sender_offset_public_key.clone(),
+            },
+            kernel: KernelShare {
+                public_excess: public(&excess),
+                public_nonce: public(&nonce),
+            },
+            partial_signature: RistrettoSecretKey::default(),
+        };
+        let (total_nonce, total_excess) = reply.totals(&message.kernel);
+        let challenge = kernel_challenge(&total_nonce, &total_excess, &message.kernel_message());
+        reply.partial_signature = &nonce + &(&challenge_scalar(&challenge) * &excess);
+        reply
+    }
+
+    #[test]
+    fn a_finished_transaction_validates() {
+        assert!(transaction().validate().is_ok());
+        // Every signature and the balance carry over to the JSON
+        let json: serde_json::Value = serde_json::from_str(&transaction().to_json()).unwrap();
+        assert_eq!(json["version"], TRANSACTION_PROTOCOL_VERSION);
+        assert_eq!(json["outputs"].as_array().unwrap().len(), 2);
+        assert_eq!(json["kernel"]["fee"], 1_000);
+    }
+
+    #[test]
+    fn a_transaction_that_does_not_hold_together_is_refused() {
+        let balance = "the outputs and the fee do not balance the inputs and the excess";
+        let mut transaction = self::transaction();
+        transaction.kernel.fee += 1;
+        assert_eq!(invalid(transaction), balance);
+        let mut transaction = self::transaction();
+        transaction.offset = scalar(41);
+        assert_eq!(invalid(transaction), balance);
+        let mut transaction = self::transaction();
+        transaction.outputs.pop();
+        assert_eq!(invalid(transaction), balance);
+
+        // The kernel signs its lock height
+        let mut transaction = self::transaction();
+        transaction.kernel.lock_height = 1;
+        assert_eq!(invalid(transaction), "kernel signature");
+
+        // The metadata signature covers the features and the script key
+        let mut transaction = self::transaction();
+        transaction.outputs[1].features[0] ^= 1;
+        assert_eq!(invalid(transaction), "metadata signature");
+        let mut transaction = self::transaction();
+        transaction.outputs[0].script_public_key = public(&scalar(99));
+        assert_eq!(invalid(transaction), "metadata signature");
+
+        let mut transaction = self::transaction();
+        transaction.script_offset = scalar(1);
+        assert_eq!(
+            invalid(transaction),
+            "the script offset does not match the script and sender offset keys"
+        );
+        let mut transaction = self::transaction();
+        transaction.inputs[0].script_public_key = public(&scalar(8));
+        assert_eq!(
+            invalid(transaction),
+            "the script offset does not match the script and sender offset keys"
+        );
+    }
+
+    #[test]
+    fn messages_round_trip_through_json() {
+        let message = message(7);
+        assert_eq!(SenderMessage::from_json(&message.to_json()).unwrap(), message);
+        let reply = reply(&message);
+        assert_eq!(ReceiverReply::from_json(&reply.to_json()).unwrap(), reply);
+    }
+
+    #[test]
+    fn malformed_messages_are_refused() {
+        let json: serde_json::Value = serde_json::from_str(&message(7).to_json()).unwrap();
+        let with = |field: &str, value: serde_json::Value| {
+            let mut json = json.clone();
+            json[field] = value;
+            SenderMessage::from_json(&json.to_string())
+        };
+        assert!(matches!(
+            with("version", json!(2)),
+            Err(TransactionProtocolError::UnsupportedVersion(2))
+        ));
+        assert!(matches!(
+            with("public_nonce", json!("zz")),
+            Err(TransactionProtocolError::Parse(_))
+        ));
+        assert!(matches!(
+            with("public_excess", json!("ff".repeat(32))),
+            Err(TransactionProtocolError::Parse(_))
+        ));
+        assert!(matches!(
+            with("amount", json!("6000")),
+            Err(TransactionProtocolError::Parse(_))
+        ));
+        assert!(matches!(
+            SenderMessage::from_json("{}"),
+            Err(TransactionProtocolError::Parse(_))
+        ));
+
+        let json: serde_json::Value = serde_json::from_str(&reply(&message(7)).to_json()).unwrap();
+        let with = |field: &str, value: serde_json::Value| {
+            let mut json = json.clone();
+            json[field] = value;
+            ReceiverReply::from_json(&json.to_string())
+        };
+        assert!(matches!(
+            with("version", json!(0)),
+            Err(TransactionProtocolError::UnsupportedVersion(0))
+        ));
+        assert!(matches!(
+            with("commitment", json!("ff".repeat(32))),
+            Err(TransactionProtocolError::Parse(_))
+        ));
+        assert!(matches!(
+            with("partial_signature", json!("00".repeat(31))),
+            Err(TransactionProtocolError::Parse(_))
+        ));
+        assert!(matches!(
+            with("script_public_key", json!("00")),
+            Err(TransactionProtocolError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn a_reply_has_to_answer_the_message() {
+        let message = message(7);
+        assert!(reply(&message).verify(&message).is_ok());
+
+        assert!(matches!(
+            reply(&self::message(8)).verify(&message),
+            Err(TransactionProtocolError::WrongTransaction { expected: 7, actual: 8 })
+        ));
+        let mut elsewhere = reply(&message);
+        elsewhere.output.sender_offset_public_key = public(&scalar(32));
+        assert!(matches!(
+            elsewhere.verify(&message),
+            Err(TransactionProtocolError::Mismatch("sender offset public key"))
+        ));
+        let mut tampered = reply(&message);
+        tampered.partial_signature = &tampered.partial_signature + &scalar(1);
+        assert!(matches!(
+            tampered.verify(&message),
+            Err(TransactionProtocolError::InvalidSignature(_))
+        ));
+        // A signature over other terms does not answer these
+        let other_fee = SenderMessage {
+            fee: message.fee + 1,
+            ..message.clone()
+        };
+        assert!(matches!(
+            reply(&other_fee).verify(&message),
+            Err(TransactionProtocolError::InvalidSignature(_))
+        ));
+    }
+
+    #[test]
+    fn finishing_refuses_a_tampered_reply_before_the_kernel_is_signed() {
+        let path = std::env::temp_dir().join(format!("tari-ledger-send-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let store = FileStateStore::open(&path).unwrap();
+        let log = DryRunLog::new();
+        let device = LedgerDevice::from_transport(DryRunTransport::new(log.clone()).with_software_keys(7));
+        let signer = LedgerTransactionSigner::new(&device, FeeCalculator::new(5));
+        let recipient = TariAddress::new(public(&scalar(80)), Network::Esmeralda);
+        let send_request = || SendRequest {
+            tx_id: 7,
+            amount: 6_000,
+            lock_height: 0,
+            recipient: Some(recipient.clone()),
+            inputs: vec![SenderInput {
+                commitment: commit(&scalar(5), 10_000),
+                value: 10_000,
+                mask_index: 0,
+                script_key: ScriptOffsetInput {
+                    index: 0,
+                    script_public_key: public(&scalar(7)),
+                    bound_to: None,
+                },
+            }],
+        };
+        let kernel_signed = || {
+            log.exchanges().iter().any(|exchange| {
+                exchange.command[1] == Instruction::KernelSignature.as_byte() && exchange.command[2] == P1_KERNEL_SIGN
+            })
+        };
+
+        let pending = send(&signer, &store, send_request()).unwrap();
+        let message = pending.message().clone();
+        assert_eq!((message.tx_id, message.amount), (7, 6_000));
+        assert!(pending.change().is_some());
+        let mut tampered = reply(&message);
+        tampered.partial_signature = &tampered.partial_signature + &scalar(1);
+        assert!(matches!(
+            pending.finish(&tampered),
+            Err(TransactionProtocolError::InvalidSignature(_))
+        ));
+        assert!(!kernel_signed());
+
+        let pending = send(&signer, &store, send_request()).unwrap();
+        let mut redirected = reply(pending.message());
+        redirected.output.sender_offset_public_key = public(&scalar(81));
+        assert!(matches!(
+            pending.finish(&redirected),
+            Err(TransactionProtocolError::Mismatch(_))
+        ));
+        let pending = send(&signer, &store, send_request()).unwrap();
+        let other = reply(&SenderMessage {
+            tx_id: 8,
+            ..pending.message().clone()
+        });
+        assert!(matches!(
+            pending.finish(&other),
+            Err(TransactionProtocolError::WrongTransaction { expected: 7, actual: 8 })
+        ));
+        assert!(!kernel_signed());
+    }
+
+    #[test]
+    fn the_receiver_output_challenge_covers_what_the_sender_knows() {
+        let key = public(&scalar(31));
+        let challenge = receiver_output_challenge(1, 6_000, &key);
+        assert_eq!(challenge.purpose(), RECEIVER_OUTPUT_LABEL);
+        assert_ne!(receiver_output_challenge(2, 6_000, &key), challenge);
+        assert_ne!(receiver_output_challenge(1, 6_001, &key), challenge);
+        assert_ne!(receiver_output_challenge(1, 6_000, &public(&scalar(32))), challenge);
+    }
+}
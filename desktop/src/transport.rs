@@ -0,0 +1,104 @@
+//! The transports a [`LedgerDevice`](crate::device::LedgerDevice) can talk over
+//! An APDU is split into frames by the transport, so how much payload fits in one APDU without wasting a frame depends
+//! on the transport: HID always uses 64 byte frames, a BLE link negotiates its MTU when it connects and TCP (e.g. to a
+//! simulator) is not framed at all. Every transport reports the chunk size that suits it and long payloads are sliced
+//! accordingly.
+
+use ledger_transport::{APDUAnswer, APDUCommand};
+#[cfg(feature = "hid")]
+use ledger_transport_hid::TransportNativeHID;
+use tari_ledger_protocol::{APDU_HEADER_LENGTH, MAX_CHUNK_LENGTH};
+
+use crate::errors::DeviceError;
+
+/// Frame header of the Ledger framing protocol: tag and 2-byte sequence index. The first frame of an APDU also
+/// carries the 2-byte APDU length.
+const FRAME_HEADER_LENGTH: usize = 3;
+const FIRST_FRAME_EXTRA_LENGTH: usize = 2;
+/// The USB vendor id of every Ledger device
+pub const LEDGER_VENDOR_ID: u16 = 0x2c97;
+/// The HID usage page Ledger devices carry APDUs on, the other interfaces of a device are for U2F and the like
+pub const LEDGER_USAGE_PAGE: u16 = 0xffa0;
+
+/// Which HID interfaces are taken for a Ledger device during enumeration. The default matches every Ledger model,
+/// overrides let developer boards, models newer than this crate or compatible clones be used without recompiling.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct HidFilter {
+    pub vendor_id: u16,
+    /// Any product of the vendor when not set. Ledger product ids carry the model in the high byte.
+    pub product_id: Option<u16>,
+    pub usage_page: u16,
+}
+
+impl Default for HidFilter {
+    fn default() -> Self {
+        Self {
+            vendor_id: LEDGER_VENDOR_ID,
+            product_id: None,
+            usage_page: LEDGER_USAGE_PAGE,
+        }
+    }
+}
+
+impl HidFilter {
+    /// Whether the USB ids of a device match, whatever its interfaces
+    pub fn matches_device(&self, vendor_id: u16, product_id: u16) -> bool {
+        vendor_id == self.vendor_id && self.product_id.map(|id| id == product_id).unwrap_or(true)
+    }
+
+    /// Whether an interface of a device is the one to send APDUs to
+    pub fn matches(&self, vendor_id: u16, product_id: u16, usage_page: u16) -> bool {
+        self.matches_device(vendor_id, product_id) && usage_page == self.usage_page
+    }
+}
+
+pub trait LedgerTransport {
+    fn exchange(&self, command: &APDUCommand<Vec<u8>>) -> Result<APDUAnswer<Vec<u8>>, DeviceError>;
+
+    /// The largest payload to send in a single APDU. The device never uses more than [`MAX_CHUNK_LENGTH`], whatever
+    /// this returns.
+    fn max_chunk_size(&self) -> usize {
+        MAX_CHUNK_LENGTH
+    }
+
+    /// How many commands [`LedgerTransport::exchange_pipelined`] is worth handing at once
+    fn pipeline_depth(&self) -> usize {
+        1
+    }
+
+    /// Send every command before reading the first answer, and return the answers in order. The default exchanges
+    /// them one at a time, as HID has to: the device drops an APDU that arrives while it is still answering one.
+    fn exchange_pipelined(&self, commands: &[APDUCommand<Vec<u8>>]) -> Result<Vec<APDUAnswer<Vec<u8>>>, DeviceError> {
+        commands.iter().map(|command| self.exchange(command)).collect()
+    }
+}
+
+/// HID frames are cheap, a round trip per APDU is what costs, so HID sends the largest chunks the app accepts
+#[cfg(feature = "hid")]
+impl LedgerTransport for TransportNativeHID {
+    fn exchange(&self, command: &APDUCommand<Vec<u8>>) -> Result<APDUAnswer<Vec<u8>>, DeviceError> {
+        Ok(TransportNativeHID::exchange(self, command)?)
+    }
+}
+
+/// The chunk size for a transport with `mtu` byte frames, each prefixed with `channel_length` bytes of transport
+/// specific header. On slow links such as BLE every frame costs a radio round trip, so a chunk that spills a few
+/// bytes into an extra frame is trimmed to end on a frame boundary, unless that would more than halve it.
+pub fn frame_aligned_chunk_size(mtu: usize, channel_length: usize) -> usize {
+    let header = channel_length + FRAME_HEADER_LENGTH;
+    let max_apdu = APDU_HEADER_LENGTH + MAX_CHUNK_LENGTH;
+    if mtu <= header + FIRST_FRAME_EXTRA_LENGTH || mtu - header - FIRST_FRAME_EXTRA_LENGTH >= max_apdu {
+        return MAX_CHUNK_LENGTH;
+    }
+    let first_frame = mtu - header - FIRST_FRAME_EXTRA_LENGTH;
+    let next_frames = mtu - header;
+
+    let frames = (max_apdu - first_frame) / next_frames;
+    let aligned = (first_frame + frames * next_frames).saturating_sub(APDU_HEADER_LENGTH);
+    if aligned * 2 < MAX_CHUNK_LENGTH {
+        MAX_CHUNK_LENGTH
+    } else {
+        aligned
+    }
+}
@@ -0,0 +1,385 @@
+//! Amounts in Tari, milliTari and microTari
+//! Every amount the crate handles is in microTari, the unit of the chain. An [`AmountFormat`] writes them for the user
+//! in the [`Unit`] they picked, with the digit grouping and decimal separator of their locale, and reads amounts the
+//! user typed back into microTari. A number typed without a unit is in microTari whatever the format's unit, so that an
+//! amount in a script does not change its meaning when the configuration does.
+//!
+//! The standard library knows no locales, so [`Separators::from_env`] picks the separators from the language and
+//! territory of `LC_ALL`, `LC_NUMERIC` or `LANG`, for the locales whose conventions differ from English. The `C` and
+//! `POSIX` locales, and an environment that sets none, write plain numbers.
+
+use std::{env, fmt, str::FromStr};
+
+use crate::errors::AmountError;
+
+/// The microTari in one Tari
+pub const MICRO_TARI_PER_TARI: u64 = 1_000_000;
+/// The microTari in one milliTari
+pub const MICRO_TARI_PER_MILLI_TARI: u64 = 1_000;
+
+/// The unit amounts are shown in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Unit {
+    #[cfg_attr(feature = "serde", serde(rename = "T", alias = "tari"))]
+    Tari,
+    #[cfg_attr(feature = "serde", serde(rename = "mT", alias = "millitari"))]
+    MilliTari,
+    /// The default, as the chain counts
+    #[default]
+    #[cfg_attr(feature = "serde", serde(rename = "uT", alias = "µT", alias = "microtari"))]
+    MicroTari,
+}
+
+impl Unit {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Unit::Tari => "T",
+            Unit::MilliTari => "mT",
+            Unit::MicroTari => "µT",
+        }
+    }
+
+    /// The microTari in one of the unit
+    pub fn micro_tari(self) -> u64 {
+        match self {
+            Unit::Tari => MICRO_TARI_PER_TARI,
+            Unit::MilliTari => MICRO_TARI_PER_MILLI_TARI,
+            Unit::MicroTari => 1,
+        }
+    }
+
+    /// The decimal places an amount in the unit can have
+    pub fn decimals(self) -> usize {
+        match self {
+            Unit::Tari => 6,
+            Unit::MilliTari => 3,
+            Unit::MicroTari => 0,
+        }
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.symbol())
+    }
+}
+
+impl FromStr for Unit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "T" => return Ok(Unit::Tari),
+            "mT" => return Ok(Unit::MilliTari),
+            // The micro sign and the Greek small letter mu look the same
+            "uT" | "µT" | "μT" => return Ok(Unit::MicroTari),
+            _ => {},
+        }
+        match s.to_lowercase().as_str() {
+            "tari" => Ok(Unit::Tari),
+            "millitari" => Ok(Unit::MilliTari),
+            "microtari" => Ok(Unit::MicroTari),
+            _ => Err(format!("unknown unit '{}', use T, mT or uT", s)),
+        }
+    }
+}
+
+/// How the digits of an amount are grouped and its fraction set apart
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Separators {
+    /// Written between every three digits of the whole part, none for plain numbers
+    pub grouping: Option<char>,
+    pub decimal: char,
+}
+
+impl Separators {
+    /// No grouping and a decimal point, as the `C` locale writes numbers
+    pub const PLAIN: Self = Self {
+        grouping: None,
+        decimal: '.',
+    };
+
+    /// The separators of `locale`, e.g. `de_CH.UTF-8`. Locales this crate does not know are written as in English.
+    pub fn for_locale(locale: &str) -> Self {
+        let name = locale.split(['.', '@']).next().unwrap_or_default();
+        let mut parts = name.split(['_', '-']);
+        let language = parts.next().unwrap_or_default().to_lowercase();
+        let territory = parts.next().unwrap_or_default().to_uppercase();
+        let separators = |grouping, decimal| Self {
+            grouping: Some(grouping),
+            decimal,
+        };
+        match (language.as_str(), territory.as_str()) {
+            ("" | "c" | "posix", _) => Self::PLAIN,
+            ("de" | "it" | "fr", "CH") | (_, "LI") => separators('\'', '.'),
+            ("de" | "nl" | "es" | "it" | "pt" | "id" | "tr" | "da" | "el" | "ro" | "hr" | "sl" | "sr", _) => {
+                separators('.', ',')
+            },
+            ("fr" | "ru" | "pl" | "cs" | "sk" | "sv" | "fi" | "nb" | "nn" | "no" | "uk" | "hu" | "bg" | "et", _) => {
+                separators('\u{202f}', ',')
+            },
+            _ => separators(',', '.'),
+        }
+    }
+
+    /// The separators of the locale the environment sets for numbers
+    pub fn from_env() -> Self {
+        ["LC_ALL", "LC_NUMERIC", "LANG"]
+            .iter()
+            .filter_map(|name| env::var(name).ok())
+            .find(|locale| !locale.is_empty())
+            .map_or(Self::PLAIN, |locale| Self::for_locale(&locale))
+    }
+}
+
+impl Default for Separators {
+    fn default() -> Self {
+        Self::PLAIN
+    }
+}
+
+/// How amounts are written for and read from the user
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AmountFormat {
+    pub unit: Unit,
+    pub separators: Separators,
+}
+
+impl AmountFormat {
+    pub fn new(unit: Unit, separators: Separators) -> Self {
+        Self { unit, separators }
+    }
+
+    /// `micro_tari` in the unit of the format, with its symbol. Trailing zeros of the fraction are left out.
+    pub fn format(&self, micro_tari: u64) -> String {
+        format!("{} {}", self.format_number(micro_tari), self.unit.symbol())
+    }
+
+    /// `micro_tari` in the unit of the format, without the symbol
+    pub fn format_number(&self, micro_tari: u64) -> String {
+        let per_unit = self.unit.micro_tari();
+        let whole = (micro_tari / per_unit).to_string();
+        let mut number = String::with_capacity(whole.len() * 4 / 3 + self.unit.decimals() + 1);
+        for (i, digit) in whole.chars().enumerate() {
+            if i > 0 && (whole.len() - i) % 3 == 0 {
+                if let Some(grouping) = self.separators.grouping {
+                    number.push(grouping);
+                }
+            }
+            number.push(digit);
+        }
+        let fraction = micro_tari % per_unit;
+        if fraction > 0 {
+            let fraction = format!("{:0width$}", fraction, width = self.unit.decimals());
+            number.push(self.separators.decimal);
+            number.push_str(fraction.trim_end_matches('0'));
+        }
+        number
+    }
+
+    /// An amount the user typed, e.g. `1.5 T` or `250mT`, in microTari. The number may be grouped with the grouping
+    /// separator of the format and has no unit if it is in microTari.
+    pub fn parse(&self, amount: &str) -> Result<u64, AmountError> {
+        let amount = amount.trim();
+        let unit_start = amount.find(char::is_alphabetic).unwrap_or(amount.len());
+        let (number, unit) = amount.split_at(unit_start);
+        let unit = match unit.trim() {
+            "" => Unit::MicroTari,
+            unit => unit.parse::<Unit>().map_err(AmountError::UnknownUnit)?,
+        };
+        let digits = number
+            .trim()
+            .chars()
+            .filter(|&c| Some(c) != self.separators.grouping && !(c.is_whitespace() && self.groups_with_space()))
+            .collect::<String>();
+        let (whole, fraction) = match digits.split_once(self.separators.decimal) {
+            Some((whole, fraction)) => (whole, fraction),
+            None => (digits.as_str(), ""),
+        };
+        let is_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+        if (whole.is_empty() && fraction.is_empty()) || !is_digits(whole) || !is_digits(fraction) {
+            return Err(AmountError::Invalid(amount.to_string()));
+        }
+        if fraction.len() > unit.decimals() {
+            return Err(AmountError::TooPrecise { unit });
+        }
+        let whole = if whole.is_empty() {
+            0
+        } else {
+            whole.parse::<u64>().map_err(|_| AmountError::Overflow)?
+        };
+        let fraction = if fraction.is_empty() {
+            0
+        } else {
+            let scale = 10u64.pow((unit.decimals() - fraction.len()) as u32);
+            fraction.parse::<u64>().map_err(|_| AmountError::Overflow)? * scale
+        };
+        whole
+            .checked_mul(unit.micro_tari())
+            .and_then(|whole| whole.checked_add(fraction))
+            .ok_or(AmountError::Overflow)
+    }
+
+    fn groups_with_space(&self) -> bool {
+        self.separators.grouping.is_some_and(char::is_whitespace)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SWISS: Separators = Separators {
+        grouping: Some('\''),
+        decimal: '.',
+    };
+    const GERMAN: Separators = Separators {
+        grouping: Some('.'),
+        decimal: ',',
+    };
+
+    #[test]
+    fn units_parse_by_symbol_and_name() {
+        assert_eq!("T".parse::<Unit>(), Ok(Unit::Tari));
+        assert_eq!("mT".parse::<Unit>(), Ok(Unit::MilliTari));
+        for micro in ["uT", "µT", "μT", "MicroTari"] {
+            assert_eq!(micro.parse::<Unit>(), Ok(Unit::MicroTari), "{}", micro);
+        }
+        assert_eq!("TARI".parse::<Unit>(), Ok(Unit::Tari));
+        // Symbols are case sensitive, a megaTari is no milliTari
+        assert!("MT".parse::<Unit>().is_err());
+        assert!("t".parse::<Unit>().is_err());
+    }
+
+    #[test]
+    fn amounts_are_formatted_without_trailing_zeros() {
+        let tari = AmountFormat::new(Unit::Tari, Separators::PLAIN);
+        assert_eq!(tari.format(1_500_000), "1.5 T");
+        assert_eq!(tari.format(1), "0.000001 T");
+        assert_eq!(tari.format(0), "0 T");
+        assert_eq!(tari.format(2_000_000), "2 T");
+        let milli = AmountFormat::new(Unit::MilliTari, Separators::PLAIN);
+        assert_eq!(milli.format(1_234), "1.234 mT");
+        assert_eq!(milli.format(1_230), "1.23 mT");
+        assert_eq!(AmountFormat::default().format(1_234), "1234 µT");
+    }
+
+    #[test]
+    fn digits_are_grouped_by_the_locale() {
+        let swiss = AmountFormat::new(Unit::Tari, SWISS);
+        assert_eq!(swiss.format_number(1_234_567_890_000), "1'234'567.89");
+        assert_eq!(swiss.format_number(123_000_000), "123");
+        let german = AmountFormat::new(Unit::MicroTari, GERMAN);
+        assert_eq!(german.format_number(1_000), "1.000");
+        assert_eq!(german.format_number(999), "999");
+        assert_eq!(
+            AmountFormat::new(Unit::Tari, GERMAN).format_number(u64::MAX),
+            "18.446.744.073.709,551615"
+        );
+    }
+
+    #[test]
+    fn typed_amounts_are_in_micro_tari() {
+        let format = AmountFormat::new(Unit::Tari, Separators::PLAIN);
+        assert_eq!(format.parse("1.5 T").unwrap(), 1_500_000);
+        assert_eq!(format.parse("250mT").unwrap(), 250_000);
+        assert_eq!(format.parse(".5 T").unwrap(), 500_000);
+        assert_eq!(format.parse("3. T").unwrap(), 3_000_000);
+        // No unit is microTari, whatever unit the format writes
+        assert_eq!(format.parse("42").unwrap(), 42);
+        assert_eq!(format.parse("  7 uT ").unwrap(), 7);
+
+        let german = AmountFormat::new(Unit::Tari, GERMAN);
+        assert_eq!(german.parse("1.234,5 T").unwrap(), 1_234_500_000);
+        let spaced = AmountFormat::new(Unit::Tari, Separators::for_locale("fr_FR.UTF-8"));
+        assert_eq!(spaced.parse("1 000,25 T").unwrap(), 1_000_250_000);
+    }
+
+    #[test]
+    fn formatted_amounts_parse_back() {
+        for separators in [Separators::PLAIN, SWISS, GERMAN, Separators::for_locale("sv_SE")] {
+            for unit in [Unit::Tari, Unit::MilliTari, Unit::MicroTari] {
+                let format = AmountFormat::new(unit, separators);
+                for micro_tari in [0, 1, 999, 1_000, 1_000_001, 123_456_789_012, u64::MAX] {
+                    assert_eq!(
+                        format.parse(&format.format(micro_tari)).unwrap(),
+                        micro_tari,
+                        "{:?}",
+                        format
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn the_precision_of_the_unit_is_a_limit() {
+        let format = AmountFormat::default();
+        assert_eq!(format.parse("0.000001 T").unwrap(), 1);
+        assert!(matches!(
+            format.parse("0.0000001 T"),
+            Err(AmountError::TooPrecise { unit: Unit::Tari })
+        ));
+        assert_eq!(format.parse("0.001 mT").unwrap(), 1);
+        assert!(matches!(
+            format.parse("0.0001 mT"),
+            Err(AmountError::TooPrecise { unit: Unit::MilliTari })
+        ));
+        assert!(matches!(
+            format.parse("1.5"),
+            Err(AmountError::TooPrecise { unit: Unit::MicroTari })
+        ));
+        // Trailing zeros do not add precision the user meant, but they are still refused rather than rounded
+        assert!(matches!(
+            format.parse("1.0000000 T"),
+            Err(AmountError::TooPrecise { unit: Unit::Tari })
+        ));
+    }
+
+    #[test]
+    fn amounts_that_do_not_fit_overflow() {
+        let format = AmountFormat::default();
+        assert_eq!(format.parse(&u64::MAX.to_string()).unwrap(), u64::MAX);
+        assert!(matches!(
+            format.parse("18446744073709551616"),
+            Err(AmountError::Overflow)
+        ));
+        assert_eq!(format.parse("18446744073709.551615 T").unwrap(), u64::MAX);
+        assert!(matches!(
+            format.parse("18446744073709.551616 T"),
+            Err(AmountError::Overflow)
+        ));
+        assert!(matches!(format.parse("18446744073710 T"), Err(AmountError::Overflow)));
+    }
+
+    #[test]
+    fn malformed_amounts_are_refused() {
+        let format = AmountFormat::default();
+        for amount in ["", "T", ".", "-1", "1.2.3", "1e6", "0x10", "1,5 T"] {
+            assert!(
+                matches!(
+                    format.parse(amount),
+                    Err(AmountError::Invalid(_) | AmountError::UnknownUnit(_))
+                ),
+                "{} was accepted",
+                amount
+            );
+        }
+        assert!(matches!(format.parse("5 XT"), Err(AmountError::UnknownUnit(_))));
+        // A grouping separator of another locale is not skipped
+        assert!(matches!(format.parse("1'000"), Err(AmountError::Invalid(_))));
+    }
+
+    #[test]
+    fn separators_follow_the_locale() {
+        assert_eq!(Separators::for_locale("C"), Separators::PLAIN);
+        assert_eq!(Separators::for_locale("POSIX"), Separators::PLAIN);
+        assert_eq!(Separators::for_locale(""), Separators::PLAIN);
+        assert_eq!(Separators::for_locale("de_CH.UTF-8"), SWISS);
+        assert_eq!(Separators::for_locale("de_DE.UTF-8"), GERMAN);
+        assert_eq!(Separators::for_locale("en_US.UTF-8").grouping, Some(','));
+        assert_eq!(Separators::for_locale("fr-FR").grouping, Some('\u{202f}'));
+        assert_eq!(Separators::for_locale("sr_RS@latin").decimal, ',');
+    }
+}
@@ -0,0 +1,74 @@
+//! Signature checks that need no device
+//! Everything the device signs is a Schnorr signature over the domain separated script challenge
+//! `H(public key || public nonce || message)`. These functions let anyone holding the public key, e.g. a merchant
+//! checking a payment proof, validate such a signature without hardware.
+
+use tari_crypto::{
+    hash::blake2::Blake256,
+    ristretto::{RistrettoPublicKey, RistrettoSchnorr, RistrettoSecretKey},
+    tari_utilities::ByteArray,
+};
+use tari_ledger_protocol::SCRIPT_CHALLENGE_LABEL;
+
+use crate::hashing::{ConsensusHasher, DomainSeparatedConsensusHasher, Preimage, TransactionHashDomain};
+
+/// The challenge the device signs for `message`
+pub fn script_challenge(
+    public_key: &RistrettoPublicKey,
+    public_nonce: &RistrettoPublicKey,
+    message: &[u8; 32],
+) -> [u8; 32] {
+    let hasher = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(SCRIPT_CHALLENGE_LABEL);
+    chain_script_challenge(hasher, public_key, public_nonce, message).finalize()
+}
+
+/// The preimage of [`script_challenge`], to show when a signature over it does not verify
+pub fn script_challenge_preimage(
+    public_key: &RistrettoPublicKey,
+    public_nonce: &RistrettoPublicKey,
+    message: &[u8; 32],
+) -> Preimage {
+    let hasher = DomainSeparatedConsensusHasher::<TransactionHashDomain>::traced(SCRIPT_CHALLENGE_LABEL);
+    chain_script_challenge(hasher, public_key, public_nonce, message).preimage()
+}
+
+fn chain_script_challenge(
+    hasher: ConsensusHasher<Blake256>,
+    public_key: &RistrettoPublicKey,
+    public_nonce: &RistrettoPublicKey,
+    message: &[u8; 32],
+) -> ConsensusHasher<Blake256> {
+    hasher.chain(public_key).chain(public_nonce).chain(message)
+}
+
+/// Check a device signature over `message` as produced by `Instruction::Sign`
+pub fn verify_script_signature(
+    public_key: &RistrettoPublicKey,
+    signature: &RistrettoSchnorr,
+    message: &[u8; 32],
+) -> bool {
+    let challenge = script_challenge(public_key, signature.get_public_nonce(), message);
+    verify_challenge_signature(public_key, signature, &challenge)
+}
+
+/// Check a signature over an already computed `challenge`
+pub fn verify_challenge_signature(
+    public_key: &RistrettoPublicKey,
+    signature: &RistrettoSchnorr,
+    challenge: &[u8; 32],
+) -> bool {
+    match RistrettoSecretKey::from_bytes(challenge) {
+        Ok(e) => signature.verify(public_key, &e),
+        Err(_) => false,
+    }
+}
+
+/// Decode a `public nonce || s` signature, the encoding used in payment proofs and by the `verify` command
+pub fn signature_from_bytes(bytes: &[u8]) -> Option<RistrettoSchnorr> {
+    if bytes.len() != 64 {
+        return None;
+    }
+    let public_nonce = RistrettoPublicKey::from_bytes(&bytes[0..32]).ok()?;
+    let s = RistrettoSecretKey::from_bytes(&bytes[32..64]).ok()?;
+    Some(RistrettoSchnorr::new(public_nonce, s))
+}
@@ -0,0 +1,82 @@
+//! Telling apart the wallets a device holds
+//! Besides its main seed a Ledger can hold a passphrase protected one behind a second PIN, a hidden wallet that can
+//! be kept back, or a decoy that can be given up, under duress. Which one is in use is picked when the device is
+//! unlocked and never reported to the host, so the host identifies the seed by a fingerprint of its first account key.
+//! A profile can be pinned to a fingerprint, so that it refuses to work with any other seed, and [`ScopedStateStore`]
+//! keeps the state of every seed apart, so that key indices and scan checkpoints of one never leak into another.
+
+use sha2::{Digest, Sha256};
+use tari_crypto::tari_utilities::{hex::to_hex, ByteArray};
+
+use crate::{
+    device::{Capabilities, KeyBranch, LedgerDevice},
+    errors::{DeviceError, StoreError},
+    export::public_key,
+    state_store::{LedgerStateStore, ScanCheckpoint},
+};
+
+/// The fingerprint of the seed the device is unlocked with: the first 8 bytes of the SHA-256 hash of the first
+/// commitment mask key of account 0, in hex. It identifies the seed without revealing any key.
+pub fn wallet_fingerprint(device: &LedgerDevice) -> Result<String, DeviceError> {
+    device.require(Capabilities::PUBLIC_KEY_EXPORT)?;
+    let public_key = public_key(device, 0, KeyBranch::CommitmentMask, 0)?;
+    let hash = Sha256::digest(public_key.as_bytes());
+    Ok(to_hex(&hash[..8]))
+}
+
+/// Fail unless the device is unlocked with the seed of `expected`
+pub fn check_wallet(device: &LedgerDevice, expected: &str) -> Result<(), DeviceError> {
+    let actual = wallet_fingerprint(device)?;
+    if actual != expected {
+        return Err(DeviceError::WrongWallet {
+            expected: expected.to_string(),
+            actual,
+        });
+    }
+    Ok(())
+}
+
+/// A [`LedgerStateStore`] that keeps the state of one seed apart from that of any other seed in the same store
+pub struct ScopedStateStore<'a> {
+    inner: &'a dyn LedgerStateStore,
+    prefix: String,
+}
+
+impl<'a> ScopedStateStore<'a> {
+    pub fn new(inner: &'a dyn LedgerStateStore, fingerprint: &str) -> Self {
+        Self {
+            inner,
+            prefix: format!("wallet/{}/", fingerprint),
+        }
+    }
+
+    fn scoped(&self, name: &str) -> String {
+        format!("{}{}", self.prefix, name)
+    }
+}
+
+impl LedgerStateStore for ScopedStateStore<'_> {
+    fn key_index(&self, branch: &str) -> Result<u64, StoreError> {
+        self.inner.key_index(&self.scoped(branch))
+    }
+
+    fn set_key_index(&self, branch: &str, index: u64) -> Result<(), StoreError> {
+        self.inner.set_key_index(&self.scoped(branch), index)
+    }
+
+    fn cached_public_key(&self, branch: &str, index: u64) -> Result<Option<[u8; 32]>, StoreError> {
+        self.inner.cached_public_key(&self.scoped(branch), index)
+    }
+
+    fn cache_public_key(&self, branch: &str, index: u64, public_key: &[u8; 32]) -> Result<(), StoreError> {
+        self.inner.cache_public_key(&self.scoped(branch), index, public_key)
+    }
+
+    fn checkpoint(&self, name: &str) -> Result<Option<ScanCheckpoint>, StoreError> {
+        self.inner.checkpoint(&self.scoped(name))
+    }
+
+    fn set_checkpoint(&self, name: &str, checkpoint: &ScanCheckpoint) -> Result<(), StoreError> {
+        self.inner.set_checkpoint(&self.scoped(name), checkpoint)
+    }
+}
@@ -0,0 +1,140 @@
+//! Unsigned transactions exported by the console wallet
+//! The console wallet can build a transaction without access to the keys and write it out as JSON for an external
+//! signer. Only what needs a device signature is read here: the script challenge and recipient address of every
+//! output and the script signature message of every input. Everything else in the file is passed through untouched,
+//! with a `script_signature` object added to every output and input, so the wallet can import the result and finish the
+//! transaction.
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tari_crypto::tari_utilities::{
+    hex::{from_hex, to_hex},
+    ByteArray,
+};
+
+use crate::{
+    address::TariAddress,
+    domains::{SCRIPT_CHALLENGE_LABEL, SCRIPT_MESSAGE_LABEL},
+    errors::WalletTxError,
+    hashing::Challenge,
+    signature::LedgerSignature,
+    signer::{LedgerTransactionSigner, OutputToSign},
+};
+
+/// The version of the export format this module understands
+pub const WALLET_TX_VERSION: u64 = 1;
+
+#[derive(Deserialize)]
+struct WalletTransaction {
+    version: u64,
+    /// The fee the wallet built the kernel with, in microTari
+    fee: u64,
+    inputs: Vec<WalletInput>,
+    outputs: Vec<WalletOutput>,
+}
+
+#[derive(Deserialize)]
+struct WalletInput {
+    script_message: String,
+}
+
+#[derive(Deserialize)]
+struct WalletOutput {
+    value: u64,
+    #[serde(default)]
+    is_change: bool,
+    features_and_scripts_size: usize,
+    script_challenge: String,
+    /// Hex encoded, shown on the device before the output is signed
+    #[serde(default)]
+    recipient_address: Option<String>,
+}
+
+/// A transaction read from the wallet's export, ready to be signed
+pub struct UnsignedTransaction {
+    document: Value,
+    pub fee: u64,
+    pub outputs: Vec<OutputToSign>,
+    /// The script signature message of every input, in order
+    pub input_messages: Vec<Challenge>,
+}
+
+impl UnsignedTransaction {
+    pub fn from_json(json: &str) -> Result<Self, WalletTxError> {
+        let document: Value = serde_json::from_str(json).map_err(|e| WalletTxError::Parse(e.to_string()))?;
+        let transaction = WalletTransaction::deserialize(&document).map_err(|e| WalletTxError::Parse(e.to_string()))?;
+        if transaction.version != WALLET_TX_VERSION {
+            return Err(WalletTxError::UnsupportedVersion(transaction.version));
+        }
+        let outputs = transaction
+            .outputs
+            .iter()
+            .map(|output| {
+                Ok(OutputToSign {
+                    value: output.value,
+                    is_change: output.is_change,
+                    features_and_scripts_size: output.features_and_scripts_size,
+                    challenge: parse_challenge(SCRIPT_CHALLENGE_LABEL, &output.script_challenge)?,
+                    recipient: output.recipient_address.as_deref().map(parse_address).transpose()?,
+                })
+            })
+            .collect::<Result<_, WalletTxError>>()?;
+        let input_messages = transaction
+            .inputs
+            .iter()
+            .map(|input| parse_challenge(SCRIPT_MESSAGE_LABEL, &input.script_message))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            document,
+            fee: transaction.fee,
+            outputs,
+            input_messages,
+        })
+    }
+
+    /// Sign the outputs under a single confirmation, then every input script message, and return the wallet's file
+    /// with the signatures added. The fee the wallet used has to be the one the device will show.
+    pub fn sign(mut self, signer: &LedgerTransactionSigner) -> Result<String, WalletTxError> {
+        let num_inputs = self.input_messages.len();
+        let fee = signer.fee(num_inputs, &self.outputs);
+        if fee != self.fee {
+            return Err(WalletTxError::FeeMismatch {
+                wallet: self.fee,
+                computed: fee,
+            });
+        }
+        let signed = signer.sign_outputs(num_inputs, &self.outputs)?;
+        for (i, signature) in signed.signatures.iter().enumerate() {
+            self.document["outputs"][i]["script_signature"] = signature_json(signature);
+        }
+        for (i, message) in self.input_messages.iter().enumerate() {
+            let signature = signer.sign_script_message(message)?;
+            self.document["inputs"][i]["script_signature"] = signature_json(&signature);
+        }
+        Ok(serde_json::to_string_pretty(&self.document).expect("a JSON value always serializes"))
+    }
+}
+
+fn signature_json(signature: &LedgerSignature) -> Value {
+    json!({
+        "public_key": to_hex(signature.public_key.as_bytes()),
+        "public_nonce": to_hex(signature.public_nonce.as_bytes()),
+        "signature": to_hex(signature.s.as_bytes()),
+    })
+}
+
+fn parse_address(hex: &str) -> Result<TariAddress, WalletTxError> {
+    from_hex(hex)
+        .ok()
+        .and_then(|bytes| TariAddress::from_bytes(&bytes).ok())
+        .ok_or_else(|| WalletTxError::Parse(format!("'{}' is not a hex encoded Tari address", hex)))
+}
+
+/// A challenge the wallet hashed under `purpose`
+fn parse_challenge(purpose: &'static str, hex: &str) -> Result<Challenge, WalletTxError> {
+    from_hex(hex)
+        .ok()
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .map(|hash| Challenge::from_hashed(purpose, hash))
+        .ok_or_else(|| WalletTxError::Parse(format!("'{}' is not a hex encoded 32-byte hash", hex)))
+}
@@ -0,0 +1,101 @@
+//! Watch-only wallet bundles
+//! A bundle holds what a wallet needs to follow the Ledger wallet on chain without the device: the view key, which
+//! identifies its outputs, the public keys of the first indices of the branches its outputs are built from, the wallet
+//! birthday to start scanning at and the network. The view key reveals every output of the wallet, so a bundle is only
+//! ever written encrypted with a passphrase, laid out like an encrypted configuration file: [`WATCH_ONLY_MAGIC`], an
+//! Argon2 salt, an XChaCha20-Poly1305 nonce and the encrypted JSON.
+
+use serde_json::json;
+use tari_crypto::{ristretto::RistrettoSecretKey, tari_utilities::hex::Hex};
+
+use crate::{
+    address::Network,
+    birthday::{wallet_metadata, WalletMetadata},
+    config::encrypt,
+    device::{KeyBranch, LedgerDevice},
+    errors::{ConfigError, DeviceError},
+    export::{export_private_key, export_public_keys, key_path, KeyExport, SensitiveKey},
+};
+
+/// Prefix of an encrypted watch-only bundle
+pub const WATCH_ONLY_MAGIC: &[u8; 8] = b"TLWATCH\x01";
+/// The version of the bundle's JSON
+pub const WATCH_ONLY_FORMAT_VERSION: u64 = 1;
+/// The branches whose public keys go into a bundle, those of the masks and the script keys of the wallet's outputs
+pub const WATCH_ONLY_BRANCHES: [KeyBranch; 2] = [KeyBranch::CommitmentMask, KeyBranch::ScriptKey];
+
+/// Everything a watch-only wallet imports
+pub struct WatchOnlyBundle {
+    pub network: Network,
+    pub metadata: WalletMetadata,
+    pub view_key: RistrettoSecretKey,
+    /// One export per branch of [`WATCH_ONLY_BRANCHES`]
+    pub keys: Vec<KeyExport>,
+}
+
+impl WatchOnlyBundle {
+    /// The bundle encrypted with `passphrase`
+    pub fn encrypt(&self, passphrase: &str) -> Result<Vec<u8>, ConfigError> {
+        encrypt(WATCH_ONLY_MAGIC, self.to_json().as_bytes(), passphrase)
+    }
+
+    /// The bundle in the clear, which never leaves this module unencrypted
+    fn to_json(&self) -> String {
+        let branches = self
+            .keys
+            .iter()
+            .map(|export| {
+                let keys = export
+                    .keys
+                    .iter()
+                    .map(|key| {
+                        json!({
+                            "index": key.index,
+                            "path": key_path(export.account, export.branch, key.index),
+                            "public_key": key.public_key.to_hex(),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                json!({ "branch": export.branch.name(), "keys": keys })
+            })
+            .collect::<Vec<_>>();
+        let bundle = json!({
+            "version": WATCH_ONLY_FORMAT_VERSION,
+            "network": self.network.to_string(),
+            "wallet_fingerprint": self.metadata.fingerprint,
+            "account": self.metadata.account,
+            "birthday": self.metadata.birthday,
+            "view_key": self.view_key.to_hex(),
+            "branches": branches,
+        });
+        serde_json::to_string_pretty(&bundle).expect("a JSON value always serializes")
+    }
+}
+
+/// Gather the bundle of `account` on `network` with the first `count` public keys of every branch. The user confirms
+/// the export of the view key on the device. `progress` is called with the number of keys exported so far and the
+/// total.
+pub fn watch_only_bundle<P: FnMut(usize, usize)>(
+    device: &LedgerDevice,
+    account: u32,
+    network: Network,
+    count: u32,
+    mut progress: P,
+) -> Result<WatchOnlyBundle, DeviceError> {
+    let metadata = wallet_metadata(device, account)?;
+    let view_key = export_private_key(device, account, SensitiveKey::ViewKey, &device.random_secret())?;
+    let total = WATCH_ONLY_BRANCHES.len() * count as usize;
+    let mut keys = Vec::with_capacity(WATCH_ONLY_BRANCHES.len());
+    for branch in WATCH_ONLY_BRANCHES {
+        let done = keys.iter().map(|export: &KeyExport| export.keys.len()).sum::<usize>();
+        keys.push(export_public_keys(device, account, branch, 0..count, |exported, _| {
+            progress(done + exported, total)
+        })?);
+    }
+    Ok(WatchOnlyBundle {
+        network,
+        metadata,
+        view_key,
+        keys,
+    })
+}
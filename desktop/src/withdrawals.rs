@@ -0,0 +1,419 @@
+//! Batched withdrawals for exchanges
+//! An exchange pays out many withdrawals at once from a CSV of `address,amount` rows, amounts in microTari. Every row
+//! is checked before anything is signed, then the withdrawals are signed in batches of one transaction each. The
+//! operator confirms each batch once on the device, by comparing the digest fingerprint it shows with the one printed
+//! for the batch, instead of confirming every output, and the digest binds the address and amount of every output in
+//! the batch. The report lists every signed output, and the batch that failed if one did.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+use tari_crypto::tari_utilities::hex::{to_hex, Hex};
+
+use crate::{
+    address::{Network, TariAddress},
+    device::Capabilities,
+    domains::WITHDRAWAL_OUTPUT_LABEL,
+    errors::{SignerError, WithdrawalError},
+    hashing::{Challenge, DomainSeparatedConsensusHasher, TransactionHashDomain},
+    script::{Opcode, TariScript},
+    signer::{LedgerTransactionSigner, OutputToSign, DEFAULT_OUTPUT_FEATURES},
+};
+
+/// Withdrawals signed together under one confirmation unless told otherwise
+pub const DEFAULT_BATCH_SIZE: usize = 16;
+/// The device protocol limits a transaction to 255 outputs
+pub const MAX_BATCH_SIZE: usize = 255;
+
+/// One row of the withdrawal file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Withdrawal {
+    /// The line of the file, counting from 1, so the report can point back to it
+    pub line: usize,
+    pub address: TariAddress,
+    /// In microTari
+    pub amount: u64,
+}
+
+impl Withdrawal {
+    /// The output paying the withdrawal, spendable by the key of the address
+    pub fn script(&self) -> TariScript {
+        TariScript::new(vec![Opcode::PushPubKey(self.address.public_key().clone())])
+    }
+
+    /// The challenge signed for the output, binding the address, the amount, the script and the features
+    pub fn challenge(&self) -> Challenge {
+        DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(WITHDRAWAL_OUTPUT_LABEL)
+            .chain_fixed(&self.address.to_bytes())
+            .chain(&self.amount)
+            .chain(&self.script())
+            .chain(&DEFAULT_OUTPUT_FEATURES)
+            .finalize_challenge()
+    }
+
+    /// The output for the signer. It carries no recipient so that the batch is confirmed once, by its digest.
+    pub fn to_output(&self) -> OutputToSign {
+        OutputToSign {
+            value: self.amount,
+            is_change: false,
+            features_and_scripts_size: DEFAULT_OUTPUT_FEATURES.len() + self.script().encoded_len(),
+            challenge: self.challenge(),
+            recipient: None,
+            features_title: None,
+        }
+    }
+}
+
+/// Read a withdrawal file. Addresses are hex or Emoji IDs on `network`, amounts are whole microTari and a leading
+/// `address,amount` header is skipped. The file is refused as a whole if any row is invalid, or if two rows pay the
+/// same address, which is more likely a mistake than two withdrawals.
+pub fn parse_withdrawals(csv: &str, network: Network) -> Result<Vec<Withdrawal>, WithdrawalError> {
+    let mut withdrawals = Vec::new();
+    let mut seen = HashMap::new();
+    for (i, row) in csv.lines().enumerate() {
+        let line = i + 1;
+        let row = row.trim();
+        if row.is_empty() || (line == 1 && row.eq_ignore_ascii_case("address,amount")) {
+            continue;
+        }
+        let invalid = |reason: &str| WithdrawalError::Invalid {
+            line,
+            reason: reason.to_string(),
+        };
+        let (address, amount) = row
+            .split_once(',')
+            .ok_or_else(|| invalid("expected `address,amount`"))?;
+        let address = address
+            .trim()
+            .parse::<TariAddress>()
+            .map_err(|e| invalid(&e.to_string()))?;
+        if address.network() != network {
+            return Err(invalid(&format!(
+                "the address is for {}, not {}",
+                address.network(),
+                network
+            )));
+        }
+        let amount = amount
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| invalid("the amount is not a whole number of microTari"))?;
+        if amount == 0 {
+            return Err(invalid("the amount is zero"));
+        }
+        if let Some(first) = seen.insert(address.to_bytes(), line) {
+            return Err(WithdrawalError::DuplicateAddress { line, first });
+        }
+        withdrawals.push(Withdrawal { line, address, amount });
+    }
+    if withdrawals.is_empty() {
+        return Err(WithdrawalError::Empty);
+    }
+    Ok(withdrawals)
+}
+
+/// What was signed for one withdrawal
+#[derive(Clone, Debug, Serialize)]
+pub struct SignedWithdrawal {
+    pub line: usize,
+    pub address: String,
+    pub amount: u64,
+    pub script: String,
+    pub challenge: String,
+    pub public_key: String,
+    pub public_nonce: String,
+    pub signature: String,
+}
+
+/// One batch, signed as a transaction of its own
+#[derive(Clone, Debug, Serialize)]
+pub struct SignedBatch {
+    pub fee: u64,
+    /// The fingerprint the device showed for the operator to compare, if it was sent display hints
+    pub fingerprint: Option<String>,
+    pub withdrawals: Vec<SignedWithdrawal>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct WithdrawalReport {
+    pub batches: Vec<SignedBatch>,
+    /// Why the batch after the last signed one failed. Later batches were not attempted.
+    pub error: Option<String>,
+}
+
+impl WithdrawalReport {
+    pub fn signed_count(&self) -> usize {
+        self.batches.iter().map(|batch| batch.withdrawals.len()).sum()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("a withdrawal report always serializes")
+    }
+}
+
+/// Sign `withdrawals` in batches of `batch_size`, each batch a transaction funded by `num_inputs` inputs. The signer
+/// has to allow silent outputs and always send display hints, see [`LedgerTransactionSigner::with_silent_outputs`]
+/// and [`LedgerTransactionSigner::with_display_hints`], and `on_batch` is called before the operator confirms each
+/// batch. Signing stops at the first batch that fails, the report holds everything signed up to it.
+pub fn process_withdrawals(
+    signer: &LedgerTransactionSigner,
+    withdrawals: &[Withdrawal],
+    batch_size: usize,
+    num_inputs: usize,
+    mut on_batch: impl FnMut(usize, &[Withdrawal]),
+) -> Result<WithdrawalReport, WithdrawalError> {
+    if batch_size == 0 || batch_size > MAX_BATCH_SIZE {
+        return Err(WithdrawalError::InvalidBatchSize(batch_size));
+    }
+    // Without display hints the device could only show the totals of a batch
+    signer
+        .device()
+        .require(Capabilities::DISPLAY_HINTS)
+        .map_err(SignerError::from)?;
+    let mut report = WithdrawalReport::default();
+    for (i, batch) in withdrawals.chunks(batch_size).enumerate() {
+        on_batch(i, batch);
+        match sign_batch(signer, batch, num_inputs) {
+            Ok(signed) => report.batches.push(signed),
+            Err(e) => {
+                report.error = Some(format!("batch {}: {}", i, e));
+                break;
+            },
+        }
+    }
+    Ok(report)
+}
+
+fn sign_batch(
+    signer: &LedgerTransactionSigner,
+    batch: &[Withdrawal],
+    num_inputs: usize,
+) -> Result<SignedBatch, SignerError> {
+    let outputs = batch.iter().map(Withdrawal::to_output).collect::<Vec<_>>();
+    let session = signer.transaction_session(num_inputs, outputs).approve()?.sign_all()?;
+    let fingerprint = session.display_summary().map(|display| display.fingerprint());
+    let mut withdrawals = Vec::with_capacity(batch.len());
+    for ((withdrawal, output), signed) in batch.iter().zip(session.outputs()).zip(session.signatures()) {
+        withdrawals.push(SignedWithdrawal {
+            line: withdrawal.line,
+            address: withdrawal.address.to_hex(),
+            amount: withdrawal.amount,
+            script: to_hex(&withdrawal.script().to_bytes()),
+            challenge: to_hex(output.challenge.as_bytes()),
+            public_key: signed.public_key.to_hex(),
+            public_nonce: signed.public_nonce.to_hex(),
+            signature: signed.s.to_hex(),
+        });
+    }
+    Ok(SignedBatch {
+        fee: session.fee(),
+        fingerprint,
+        withdrawals,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use tari_crypto::{
+        keys::PublicKey,
+        ristretto::{RistrettoPublicKey, RistrettoSecretKey},
+    };
+
+    use super::*;
+    use crate::{
+        device::LedgerDevice,
+        dry_run::{DryRunLog, DryRunTransport},
+        fee::FeeCalculator,
+        signature::LedgerSignature,
+    };
+
+    fn address(n: u64, network: Network) -> TariAddress {
+        TariAddress::new(
+            RistrettoPublicKey::from_secret_key(&RistrettoSecretKey::from(n)),
+            network,
+        )
+    }
+
+    fn withdrawals(count: u64) -> Vec<Withdrawal> {
+        (1..=count)
+            .map(|n| Withdrawal {
+                line: n as usize,
+                address: address(n, Network::Esmeralda),
+                amount: 1_000 * n,
+            })
+            .collect()
+    }
+
+    /// Why `csv` was refused, if for a row
+    fn invalid(csv: &str) -> (usize, String) {
+        match parse_withdrawals(csv, Network::Esmeralda) {
+            Err(WithdrawalError::Invalid { line, reason }) => (line, reason),
+            other => panic!("the file was not refused for a row: {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn a_file_is_read_row_by_row() {
+        let first = address(1, Network::Esmeralda);
+        let second = address(2, Network::Esmeralda);
+        let csv = format!(
+            "address,amount\n{},1000\n\n  {} , 2500  \n",
+            first.to_hex(),
+            second.to_emoji_string()
+        );
+        let withdrawals = parse_withdrawals(&csv, Network::Esmeralda).unwrap();
+        assert_eq!(withdrawals, vec![
+            Withdrawal {
+                line: 2,
+                address: first,
+                amount: 1_000
+            },
+            Withdrawal {
+                line: 4,
+                address: second,
+                amount: 2_500
+            },
+        ]);
+    }
+
+    #[test]
+    fn invalid_rows_refuse_the_file() {
+        let hex = address(1, Network::Esmeralda).to_hex();
+        assert_eq!(invalid(&hex).0, 1);
+        assert!(invalid(&hex).1.contains("address,amount"));
+        assert!(invalid("zz,5").1.contains("Emoji ID"));
+        assert_eq!(
+            invalid(&format!("{},5", address(1, Network::Mainnet).to_hex())).1,
+            "the address is for mainnet, not esmeralda"
+        );
+        assert_eq!(invalid(&format!("{},0", hex)).1, "the amount is zero");
+        assert_eq!(
+            invalid(&format!("{},1.5", hex)).1,
+            "the amount is not a whole number of microTari"
+        );
+        assert_eq!(
+            invalid(&format!("{},-1", hex)).1,
+            "the amount is not a whole number of microTari"
+        );
+        // A header after the first line is a row like any other
+        assert_eq!(invalid(&format!("{},1\naddress,amount", hex)).0, 2);
+    }
+
+    #[test]
+    fn an_address_is_paid_once() {
+        let address = address(1, Network::Esmeralda);
+        let csv = format!("{},1\n{},2", address.to_hex(), address.to_emoji_string());
+        assert!(matches!(
+            parse_withdrawals(&csv, Network::Esmeralda),
+            Err(WithdrawalError::DuplicateAddress { line: 2, first: 1 })
+        ));
+        assert!(matches!(
+            parse_withdrawals("address,amount\n\n", Network::Esmeralda),
+            Err(WithdrawalError::Empty)
+        ));
+        assert!(matches!(
+            parse_withdrawals("", Network::Esmeralda),
+            Err(WithdrawalError::Empty)
+        ));
+    }
+
+    #[test]
+    fn the_challenge_binds_the_address_and_amount() {
+        let withdrawal = &withdrawals(1)[0];
+        let challenge = withdrawal.challenge();
+        assert_eq!(challenge.purpose(), WITHDRAWAL_OUTPUT_LABEL);
+        let more = Withdrawal {
+            amount: withdrawal.amount + 1,
+            ..withdrawal.clone()
+        };
+        let elsewhere = Withdrawal {
+            address: address(2, Network::Esmeralda),
+            ..withdrawal.clone()
+        };
+        assert_ne!(more.challenge(), challenge);
+        assert_ne!(elsewhere.challenge(), challenge);
+
+        let output = withdrawal.to_output();
+        assert_eq!(output.value, withdrawal.amount);
+        assert_eq!(output.challenge, challenge);
+        assert!(output.recipient.is_none());
+    }
+
+    #[test]
+    fn withdrawals_are_signed_in_batches() {
+        let log = DryRunLog::new();
+        let device = LedgerDevice::from_transport(DryRunTransport::new(log).with_software_keys(7));
+        let signer = LedgerTransactionSigner::new(&device, FeeCalculator::new(5))
+            .with_silent_outputs(true)
+            .with_display_hints(true);
+        let withdrawals = withdrawals(3);
+        let mut batches = Vec::new();
+        let report =
+            process_withdrawals(&signer, &withdrawals, 2, 1, |i, batch| batches.push((i, batch.len()))).unwrap();
+        assert_eq!(batches, [(0, 2), (1, 1)]);
+        assert_eq!(report.error, None);
+        assert_eq!(report.batches.len(), 2);
+        assert_eq!(report.signed_count(), 3);
+
+        let outputs = withdrawals.iter().map(Withdrawal::to_output).collect::<Vec<_>>();
+        assert_eq!(report.batches[0].fee, signer.fee(1, &outputs[..2]));
+        assert_eq!(report.batches[1].fee, signer.fee(1, &outputs[2..]));
+        let signed = report.batches.iter().flat_map(|batch| &batch.withdrawals);
+        for (withdrawal, signed) in withdrawals.iter().zip(signed) {
+            assert_eq!(signed.line, withdrawal.line);
+            assert_eq!(signed.address, withdrawal.address.to_hex());
+            assert_eq!(signed.challenge, to_hex(withdrawal.challenge().as_bytes()));
+            let signature = LedgerSignature::from_hex(
+                WITHDRAWAL_OUTPUT_LABEL,
+                &format!("{}{}{}", signed.public_key, signed.public_nonce, signed.signature),
+            )
+            .unwrap();
+            assert!(signature.verify(&withdrawal.challenge()));
+        }
+        assert!(report.batches.iter().all(|batch| batch.fingerprint.is_some()));
+        assert!(report
+            .to_json()
+            .contains(&to_hex(withdrawals[2].challenge().as_bytes())));
+    }
+
+    #[test]
+    fn signing_stops_at_the_first_failed_batch() {
+        let device = LedgerDevice::from_transport(DryRunTransport::new(DryRunLog::new()).with_software_keys(7));
+        let withdrawals = withdrawals(3);
+        let outputs = withdrawals.iter().map(Withdrawal::to_output).collect::<Vec<_>>();
+        let signer = LedgerTransactionSigner::new(&device, FeeCalculator::new(5));
+        let max_fee = signer.fee(1, &outputs[..2]) - 1;
+        let signer = signer
+            .with_silent_outputs(true)
+            .with_display_hints(true)
+            .with_max_fee(max_fee);
+        let mut attempted = 0;
+        let report = process_withdrawals(&signer, &withdrawals, 2, 1, |_, _| attempted += 1).unwrap();
+        assert_eq!(attempted, 1);
+        assert_eq!(report.signed_count(), 0);
+        assert!(report.error.unwrap().starts_with("batch 0: "));
+    }
+
+    #[test]
+    fn a_batch_needs_a_size_and_display_hints() {
+        let device = LedgerDevice::from_transport(DryRunTransport::new(DryRunLog::new()).with_software_keys(7));
+        let signer = LedgerTransactionSigner::new(&device, FeeCalculator::new(5))
+            .with_silent_outputs(true)
+            .with_display_hints(true);
+        let withdrawals = withdrawals(1);
+        for size in [0, MAX_BATCH_SIZE + 1] {
+            assert!(matches!(
+                process_withdrawals(&signer, &withdrawals, size, 1, |_, _| {}),
+                Err(WithdrawalError::InvalidBatchSize(refused)) if refused == size
+            ));
+        }
+
+        device.cache_capabilities(Capabilities::empty());
+        assert!(matches!(
+            process_withdrawals(&signer, &withdrawals, 1, 1, |_, _| panic!(
+                "a batch was signed without hints"
+            )),
+            Err(WithdrawalError::Signer(_))
+        ));
+    }
+}
@@ -0,0 +1,79 @@
+//! The app in the Speculos emulator
+//! These tests start the app's ELF file, taken from `TARI_LEDGER_APP`, in the `speculos` executable on the path or in
+//! `SPECULOS`, and are skipped when `TARI_LEDGER_APP` is not set. With `TARI_LEDGER_VECTORS` set to a recording
+//! written by `tari-ledger speculos --record`, the app's answers have to match it byte for byte.
+
+use std::{env, path::PathBuf};
+
+use serial_test::serial;
+use tari_ledger::speculos::{check_vectors, record_vectors, Speculos, SpeculosOptions, TEST_SEED};
+
+/// Another public seed, which derives none of the keys of [`TEST_SEED`]
+const OTHER_SEED: &str =
+    "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+
+/// The emulator started with `seed`, or `None` to skip the test
+fn launch(seed: &str) -> Option<Speculos> {
+    let Some(app) = env::var_os("TARI_LEDGER_APP") else {
+        eprintln!("TARI_LEDGER_APP is not set, skipping");
+        return None;
+    };
+    let mut options = SpeculosOptions::new(PathBuf::from(app)).with_seed(seed.to_string());
+    if let Some(binary) = env::var_os("SPECULOS") {
+        options = options.with_binary(PathBuf::from(binary));
+    }
+    Some(options.launch().expect("Speculos starts"))
+}
+
+fn record(emulator: &Speculos) -> String {
+    record_vectors(&emulator.device().expect("Speculos accepts connections")).expect("the app answers")
+}
+
+fn check(emulator: &Speculos, recording: &str) -> Result<usize, String> {
+    check_vectors(&emulator.device().expect("Speculos accepts connections"), recording)
+        .map_err(|failures| failures.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n"))
+}
+
+#[test]
+#[serial]
+fn the_answers_match_the_pinned_recording() {
+    let Some(pinned) = env::var_os("TARI_LEDGER_VECTORS") else {
+        eprintln!("TARI_LEDGER_VECTORS is not set, skipping");
+        return;
+    };
+    let Some(emulator) = launch(TEST_SEED) else {
+        return;
+    };
+    let recording = std::fs::read_to_string(&pinned).expect("the pinned recording is readable");
+    if let Err(failures) = check(&emulator, &recording) {
+        panic!("the app no longer answers as recorded:\n{}", failures);
+    }
+}
+
+#[test]
+#[serial]
+fn a_fixed_seed_answers_the_same_after_a_restart() {
+    let Some(emulator) = launch(TEST_SEED) else {
+        return;
+    };
+    let recording = record(&emulator);
+    drop(emulator);
+
+    let restarted = launch(TEST_SEED).expect("the app is still set");
+    if let Err(failures) = check(&restarted, &recording) {
+        panic!("the app answered differently after a restart:\n{}", failures);
+    }
+}
+
+#[test]
+#[serial]
+fn another_seed_answers_differently() {
+    let Some(emulator) = launch(TEST_SEED) else {
+        return;
+    };
+    let recording = record(&emulator);
+    drop(emulator);
+
+    let other = launch(OTHER_SEED).expect("the app is still set");
+    assert!(check(&other, &recording).is_err());
+}
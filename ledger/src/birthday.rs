@@ -0,0 +1,41 @@
+use alloc::format;
+
+use nanos_sdk::{nvm::AtomicStorage, NVMData};
+use nanos_ui::ui;
+use tari_crypto::{ristretto::RistrettoPublicKey, tari_utilities::ByteArray};
+
+/// The wallet birthday, a little-endian `u64` block height followed by the app public key of the seed it belongs to.
+/// It lives in flash so that it survives restarts, and a device unlocked with another seed, e.g. behind a passphrase,
+/// reports no birthday instead of the one of a different wallet.
+#[link_section = ".nvm_data"]
+static mut WALLET_BIRTHDAY: NVMData<AtomicStorage<[u8; 40]>> = NVMData::new(AtomicStorage::new(&[0u8; 40]));
+
+/// The birthday recorded for the seed of `app_public_key`, 0 if there is none
+pub fn wallet_birthday(app_public_key: &RistrettoPublicKey) -> u64 {
+    let stored = unsafe { *WALLET_BIRTHDAY.get_ref().get_ref() };
+    if &stored[8..] != app_public_key.as_bytes() {
+        return 0;
+    }
+    let mut height = [0u8; 8];
+    height.clone_from_slice(&stored[..8]);
+    u64::from_le_bytes(height)
+}
+
+/// Ask the user to confirm `height` and record it as the birthday of the seed of `app_public_key`
+pub fn set_wallet_birthday(app_public_key: &RistrettoPublicKey, height: u64) -> bool {
+    if height == 0 {
+        if !ui::Validator::new("Clear birthday?").ask() {
+            return false;
+        }
+    } else {
+        ui::SingleMessage::new(&format!("Block {}", height)).show_and_wait();
+        if !ui::Validator::new("Set birthday?").ask() {
+            return false;
+        }
+    }
+    let mut stored = [0u8; 40];
+    stored[..8].clone_from_slice(&height.to_le_bytes());
+    stored[8..].clone_from_slice(app_public_key.as_bytes());
+    unsafe { WALLET_BIRTHDAY.get_mut().update(&stored) };
+    true
+}
@@ -0,0 +1,63 @@
+use tari_crypto::{
+    keys::PublicKey,
+    ristretto::{RistrettoPublicKey, RistrettoSecretKey},
+    tari_utilities::ByteArray,
+};
+use tari_ledger_protocol::{KeyBranch, BLINDED_KEY_LABEL, BLINDED_PATH_LABEL, BLINDED_PATH_LENGTH};
+
+use crate::{DomainSeparatedConsensusHasher, TransactionHashDomain};
+
+/// A `GetBlindedPublicKey` request, unmasked with the app key
+pub struct BlindedRequest {
+    host_key: RistrettoPublicKey,
+    shared_secret: RistrettoPublicKey,
+    path: [u8; BLINDED_PATH_LENGTH],
+}
+
+impl BlindedRequest {
+    /// Parse a `[host public key][masked path]` request, `None` if the host key is invalid
+    pub fn from_request(app_key: &RistrettoSecretKey, data: &[u8]) -> Option<Self> {
+        let host_key = RistrettoPublicKey::from_bytes(&data[0..32]).ok()?;
+        let shared_secret = &host_key * app_key;
+        let mask = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(BLINDED_PATH_LABEL)
+            .chain(&shared_secret)
+            .chain(&host_key)
+            .finalize();
+        let mut path = [0u8; BLINDED_PATH_LENGTH];
+        for ((p, c), m) in path.iter_mut().zip(&data[32..]).zip(mask.iter()) {
+            *p = c ^ m;
+        }
+        Some(Self {
+            host_key,
+            shared_secret,
+            path,
+        })
+    }
+
+    pub fn branch(&self) -> Option<KeyBranch> {
+        KeyBranch::try_from(self.path[0]).ok()
+    }
+
+    pub fn account(&self) -> u32 {
+        let mut bytes = [0u8; 4];
+        bytes.clone_from_slice(&self.path[1..5]);
+        u32::from_le_bytes(bytes)
+    }
+
+    pub fn index(&self) -> u32 {
+        let mut bytes = [0u8; 4];
+        bytes.clone_from_slice(&self.path[5..9]);
+        u32::from_le_bytes(bytes)
+    }
+
+    /// `public_key` offset by a factor only the host can derive
+    pub fn blind(&self, public_key: &RistrettoPublicKey) -> RistrettoPublicKey {
+        let factor = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(BLINDED_KEY_LABEL)
+            .chain(&self.shared_secret)
+            .chain(&self.host_key)
+            .chain(&self.path)
+            .finalize();
+        let factor = RistrettoSecretKey::from_bytes(&factor).unwrap();
+        public_key + &RistrettoPublicKey::from_secret_key(&factor)
+    }
+}
@@ -0,0 +1,32 @@
+use nanos_sdk::{nvm::AtomicStorage, NVMData};
+
+/// The number of signatures this app has produced. It lives in flash so that it survives restarts, and only ever
+/// goes up, so a host that logs the counter with every signature it asked for can spot signatures it did not ask for.
+#[link_section = ".nvm_data"]
+static mut SIGNING_COUNTER: NVMData<AtomicStorage<u64>> = NVMData::new(AtomicStorage::new(&0));
+
+/// The number of outputs this app has signed in approved transactions, kept like the signing counter. A host that
+/// records every output it had signed can tell from it whether the device co-signed outputs it knows nothing of.
+#[link_section = ".nvm_data"]
+static mut OUTPUT_COUNTER: NVMData<AtomicStorage<u64>> = NVMData::new(AtomicStorage::new(&0));
+
+pub fn signing_counter() -> u64 {
+    unsafe { *SIGNING_COUNTER.get_ref().get_ref() }
+}
+
+/// Count a signature that is about to be returned. The counter is written before the signature leaves the device, so
+/// an interrupted exchange can only overcount.
+pub fn count_signature() {
+    let next = signing_counter().saturating_add(1);
+    unsafe { SIGNING_COUNTER.get_mut().update(&next) };
+}
+
+pub fn output_counter() -> u64 {
+    unsafe { *OUTPUT_COUNTER.get_ref().get_ref() }
+}
+
+/// Count an output signature that is about to be returned, on top of [`count_signature`]
+pub fn count_output() {
+    let next = output_counter().saturating_add(1);
+    unsafe { OUTPUT_COUNTER.get_mut().update(&next) };
+}
@@ -0,0 +1,65 @@
+use tari_ledger_protocol::{
+    digest_fingerprint,
+    is_display_title,
+    TransactionSummary,
+    DIGEST_FINGERPRINT_LENGTH,
+    DISPLAY_DIGEST_LABEL,
+    DISPLAY_TITLE_LENGTH,
+    MAX_DISPLAY_PAGES,
+};
+
+use crate::{DomainSeparatedConsensusHasher, TransactionHashDomain};
+
+/// The pages announced by `Instruction::DisplayHints` for the next transaction summary
+pub struct DisplayHints {
+    pub digest: [u8; 32],
+    titles: [[u8; DISPLAY_TITLE_LENGTH]; MAX_DISPLAY_PAGES as usize],
+    count: usize,
+}
+
+impl DisplayHints {
+    /// Parse a `[digest][page count][titles]` request, `None` unless every title is printable
+    pub fn from_request(data: &[u8]) -> Option<Self> {
+        let mut digest = [0u8; 32];
+        digest.clone_from_slice(&data[..32]);
+        let mut titles = [[0u8; DISPLAY_TITLE_LENGTH]; MAX_DISPLAY_PAGES as usize];
+        let mut count = 0;
+        for (title, bytes) in titles.iter_mut().zip(data[33..].chunks(DISPLAY_TITLE_LENGTH)) {
+            if bytes.len() != DISPLAY_TITLE_LENGTH || !is_display_title(bytes) {
+                return None;
+            }
+            title.clone_from_slice(bytes);
+            count += 1;
+        }
+        Some(Self { digest, titles, count })
+    }
+
+    pub fn titles(&self) -> impl Iterator<Item = &str> {
+        self.titles[..self.count].iter().map(|title| {
+            let length = title.iter().position(|c| *c == 0).unwrap_or(DISPLAY_TITLE_LENGTH);
+            // Checked to be printable ASCII when parsed
+            core::str::from_utf8(&title[..length]).unwrap_or("")
+        })
+    }
+
+    pub fn fingerprint(&self) -> [u8; DIGEST_FINGERPRINT_LENGTH] {
+        digest_fingerprint(&self.digest)
+    }
+}
+
+/// Where the display digest of the outputs signed under `summary` starts
+pub fn summary_digest(summary: &TransactionSummary) -> [u8; 32] {
+    DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(DISPLAY_DIGEST_LABEL)
+        .chain(&summary.to_le_bytes())
+        .finalize()
+}
+
+/// Advance the display digest by one signed output
+pub fn next_digest(digest: &[u8; 32], kind: u8, value: u64, challenge: &[u8; 32]) -> [u8; 32] {
+    DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(DISPLAY_DIGEST_LABEL)
+        .chain(digest)
+        .chain(&kind)
+        .chain(&value)
+        .chain(challenge)
+        .finalize()
+}
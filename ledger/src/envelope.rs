@@ -0,0 +1,56 @@
+use nanos_sdk::random::LedgerRng;
+use rand_core::RngCore;
+use tari_crypto::{
+    keys::PublicKey,
+    ristretto::{RistrettoPublicKey, RistrettoSecretKey},
+    tari_utilities::ByteArray,
+};
+use tari_ledger_protocol::{ENVELOPE_KEY_LABEL, ENVELOPE_TAG_LABEL, ENVELOPE_TAG_LENGTH};
+
+use crate::{DomainSeparatedConsensusHasher, TransactionHashDomain};
+
+/// A private key encrypted to a host key, so that it never crosses the USB link in the clear
+pub struct Envelope {
+    pub ephemeral_public_key: RistrettoPublicKey,
+    pub ciphertext: [u8; 32],
+    pub tag: [u8; ENVELOPE_TAG_LENGTH],
+}
+
+impl Envelope {
+    pub fn seal(app_key: &RistrettoSecretKey, host_key: &RistrettoPublicKey, secret: &RistrettoSecretKey) -> Self {
+        // Bytes of the device RNG make the ephemeral key fresh, and the keys keep it from repeating should the RNG fail
+        let mut entropy = [0u8; 32];
+        LedgerRng.fill_bytes(&mut entropy);
+        let ephemeral = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(ENVELOPE_KEY_LABEL)
+            .chain(app_key)
+            .chain(host_key)
+            .chain(secret)
+            .chain(&entropy)
+            .finalize();
+        let ephemeral = RistrettoSecretKey::from_bytes(&ephemeral).unwrap();
+        let ephemeral_public_key = RistrettoPublicKey::from_secret_key(&ephemeral);
+        let shared_secret = host_key * &ephemeral;
+
+        let pad = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(ENVELOPE_KEY_LABEL)
+            .chain(&shared_secret)
+            .chain(host_key)
+            .chain(&ephemeral_public_key)
+            .finalize();
+        let mut ciphertext = [0u8; 32];
+        for ((c, k), p) in ciphertext.iter_mut().zip(secret.as_bytes()).zip(pad.iter()) {
+            *c = k ^ p;
+        }
+        let hash = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(ENVELOPE_TAG_LABEL)
+            .chain(&shared_secret)
+            .chain(&ephemeral_public_key)
+            .chain(&ciphertext)
+            .finalize();
+        let mut tag = [0u8; ENVELOPE_TAG_LENGTH];
+        tag.clone_from_slice(&hash[..ENVELOPE_TAG_LENGTH]);
+        Self {
+            ephemeral_public_key,
+            ciphertext,
+            tag,
+        }
+    }
+}
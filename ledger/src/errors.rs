@@ -1,19 +1,50 @@
 use nanos_sdk::io::Reply;
+use tari_ledger_protocol::{
+    SW_CLIENT_VERSION_REJECTED,
+    SW_CONVERSION_ERROR,
+    SW_DECRYPT_FAILED,
+    SW_INCORRECT_BYTE_LENGTH,
+    SW_INVALID_CHALLENGE,
+    SW_NONCE_NOT_ISSUED,
+    SW_PAIRING_FAILED,
+    SW_SESSION_MAC_FAILED,
+    SW_SETTING_DISABLED,
+    SW_TRANSACTION_NOT_APPROVED,
+    SW_UPLOAD_CORRUPTED,
+    SW_USER_REJECTED,
+};
+
 #[derive(Debug)]
 pub enum Error {
     IncorrectByteLength,
     InvalidChallenge,
     ConversionError,
     DecryptFailed,
+    UnsupportedClientVersion,
+    UserRejected,
+    TransactionNotApproved,
+    SettingDisabled,
+    PairingFailed,
+    UploadCorrupted,
+    NonceNotIssued,
+    SessionMacFailed,
 }
 
 impl Into<Reply> for Error {
     fn into(self) -> Reply {
         match self {
-            Error::IncorrectByteLength => Reply(0x69f0_u16),
-            Error::InvalidChallenge => Reply(0x9210_u16),
-            Error::ConversionError => Reply(0x6a88_u16),
-            Error::DecryptFailed => Reply(0x9d60_u16),
+            Error::IncorrectByteLength => Reply(SW_INCORRECT_BYTE_LENGTH),
+            Error::InvalidChallenge => Reply(SW_INVALID_CHALLENGE),
+            Error::ConversionError => Reply(SW_CONVERSION_ERROR),
+            Error::DecryptFailed => Reply(SW_DECRYPT_FAILED),
+            Error::UnsupportedClientVersion => Reply(SW_CLIENT_VERSION_REJECTED),
+            Error::UserRejected => Reply(SW_USER_REJECTED),
+            Error::TransactionNotApproved => Reply(SW_TRANSACTION_NOT_APPROVED),
+            Error::SettingDisabled => Reply(SW_SETTING_DISABLED),
+            Error::PairingFailed => Reply(SW_PAIRING_FAILED),
+            Error::UploadCorrupted => Reply(SW_UPLOAD_CORRUPTED),
+            Error::NonceNotIssued => Reply(SW_NONCE_NOT_ISSUED),
+            Error::SessionMacFailed => Reply(SW_SESSION_MAC_FAILED),
         }
     }
 }
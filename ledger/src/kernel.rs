@@ -0,0 +1,152 @@
+use nanos_sdk::random::LedgerRng;
+use rand_core::RngCore;
+use tari_crypto::{
+    keys::PublicKey,
+    ristretto::{RistrettoPublicKey, RistrettoSchnorr, RistrettoSecretKey},
+    tari_utilities::ByteArray,
+};
+use tari_ledger_protocol::{
+    KERNEL_MESSAGE_LABEL,
+    KERNEL_NONCE_LABEL,
+    KERNEL_SIGNATURE_LABEL,
+    MAX_POOLED_NONCES,
+    NONCE_POOL_SEED_LABEL,
+    POOLED_NONCE_LABEL,
+};
+
+use crate::{DomainSeparatedConsensusHasher, TransactionHashDomain};
+
+/// The excess, message and nonce of the next partial kernel signature. The public nonce goes out before the challenge
+/// is known, and a second signature with the same nonce under another challenge would reveal the excess, so each one
+/// signs only once, and only the message it was taken for.
+pub struct PendingKernel {
+    excess: RistrettoSecretKey,
+    message: [u8; 32],
+    nonce: RistrettoSecretKey,
+}
+
+impl PendingKernel {
+    /// `counter` is the signing counter. It starts again when the app's storage is wiped, so bytes of the device RNG
+    /// go into the nonce as well, and the excess, the message and the counter keep it from repeating should the RNG
+    /// fail.
+    pub fn new(excess: RistrettoSecretKey, message: [u8; 32], counter: u64) -> Self {
+        let mut entropy = [0u8; 32];
+        LedgerRng.fill_bytes(&mut entropy);
+        let nonce = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(KERNEL_NONCE_LABEL)
+            .chain(&excess)
+            .chain(&message)
+            .chain(&counter)
+            .chain(&entropy)
+            .finalize();
+        let nonce = RistrettoSecretKey::from_bytes(&nonce).unwrap();
+        Self { excess, message, nonce }
+    }
+
+    /// The excess with a nonce taken from the [`NoncePool`], to sign `message`
+    pub fn pooled(excess: RistrettoSecretKey, message: [u8; 32], nonce: RistrettoSecretKey) -> Self {
+        Self { excess, message, nonce }
+    }
+
+    /// The kernel message the nonce was taken for
+    pub fn message(&self) -> &[u8; 32] {
+        &self.message
+    }
+
+    pub fn public_excess(&self) -> RistrettoPublicKey {
+        RistrettoPublicKey::from_secret_key(&self.excess)
+    }
+
+    pub fn public_nonce(&self) -> RistrettoPublicKey {
+        RistrettoPublicKey::from_secret_key(&self.nonce)
+    }
+
+    /// The partial signature of the kernel message with the nonces and excesses of all parties summed up in
+    /// `total_nonce` and `total_excess`
+    pub fn sign(self, total_nonce: &RistrettoPublicKey, total_excess: &RistrettoPublicKey) -> RistrettoSecretKey {
+        let challenge = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(KERNEL_SIGNATURE_LABEL)
+            .chain(total_nonce)
+            .chain(total_excess)
+            .chain(&self.message)
+            .finalize();
+        let signature = RistrettoSchnorr::sign_raw(&self.excess, self.nonce, &challenge).unwrap();
+        signature.get_signature().clone()
+    }
+}
+
+/// The message of a kernel with `fee` that can be mined from `lock_height` on
+pub fn kernel_message(fee: u64, lock_height: u64) -> [u8; 32] {
+    DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(KERNEL_MESSAGE_LABEL)
+        .chain(&fee)
+        .chain(&lock_height)
+        .finalize()
+}
+
+/// The nonces issued ahead of the kernels they sign. Only the seed is kept, every nonce is derived from it again when
+/// it signs, and one bit per nonce records those used up.
+pub struct NoncePool {
+    id: u64,
+    seed: [u8; 32],
+    issued: usize,
+    used: u64,
+}
+
+impl NoncePool {
+    /// `key` is the kernel nonce key and `id` the signing counter, which names the pool. The counter starts again when
+    /// the app's storage is wiped, so bytes of the device RNG go into the seed as well, and a pool that gets the id of
+    /// an earlier one still issues other nonces.
+    pub fn new(key: &RistrettoSecretKey, id: u64) -> Self {
+        let mut entropy = [0u8; 32];
+        LedgerRng.fill_bytes(&mut entropy);
+        let seed = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(NONCE_POOL_SEED_LABEL)
+            .chain(key)
+            .chain(&id)
+            .chain(&entropy)
+            .finalize();
+        Self {
+            id,
+            seed,
+            issued: 0,
+            used: 0,
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The nonces the pool can still issue
+    pub fn remaining(&self) -> usize {
+        MAX_POOLED_NONCES - self.issued
+    }
+
+    /// Issue the next nonce of the pool, with its id
+    pub fn issue(&mut self) -> Option<(u8, RistrettoPublicKey)> {
+        if self.remaining() == 0 {
+            return None;
+        }
+        let id = self.issued as u8;
+        self.issued += 1;
+        Some((id, RistrettoPublicKey::from_secret_key(&self.nonce(id))))
+    }
+
+    /// Use up nonce `id` of the pool named `pool_id`, if the pool issued it and it has not signed yet
+    pub fn take(&mut self, pool_id: u64, id: u8) -> Option<RistrettoSecretKey> {
+        if pool_id != self.id || usize::from(id) >= self.issued {
+            return None;
+        }
+        let bit = 1u64 << id;
+        if self.used & bit != 0 {
+            return None;
+        }
+        self.used |= bit;
+        Some(self.nonce(id))
+    }
+
+    fn nonce(&self, id: u8) -> RistrettoSecretKey {
+        let nonce = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(POOLED_NONCE_LABEL)
+            .chain(&self.seed)
+            .chain(&id)
+            .finalize();
+        RistrettoSecretKey::from_bytes(&nonce).unwrap()
+    }
+}
@@ -27,12 +27,24 @@
 // #[macro_use]
 // mod macros;
 // mod blake2;
-// mod errors;
+mod birthday;
+mod blinding;
+mod counter;
+mod display;
+mod envelope;
+mod errors;
+mod kernel;
+mod pairing;
 // mod ristretto_keys;
 // mod schnorr;
+mod session;
+mod settings;
+mod transaction;
+mod upload;
 
 extern crate alloc;
-use core::{convert::TryFrom, marker::PhantomData};
+use alloc::{format, string::String};
+use core::marker::PhantomData;
 use digest::Update;
 
 use borsh::{
@@ -40,8 +52,14 @@ use borsh::{
     BorshSerialize,
 };
 use curve25519_dalek::Scalar;
-use nanos_sdk::{buttons::ButtonEvent, ecc, io, random::LedgerRng};
+use nanos_sdk::{
+    buttons::ButtonEvent,
+    ecc,
+    io::{self, Reply},
+    random::LedgerRng,
+};
 use nanos_ui::ui;
+use sha2::Sha256;
 use tari_crypto::{
     commitment::HomomorphicCommitmentFactory,
     keys::{PublicKey},
@@ -55,48 +73,197 @@ use tari_crypto::{
 };
 nanos_sdk::set_panic!(nanos_sdk::exiting_panic);
 use tari_crypto::{hash::blake2::Blake256, hash_domain, hashing::DomainSeparation};
+use tari_ledger_protocol::{
+    batch_commitment_request_length,
+    display_hints_request_length,
+    kernel_nonce_request_length,
+    nonce_pool_sign_request_length,
+    script_offset_request_length,
+    Capabilities,
+    DerivationVersion,
+    Instruction,
+    KeyBranch,
+    SemanticVersion,
+    SensitiveKey,
+    TransactionSummary,
+    APDU_HEADER_LENGTH,
+    BATCH_COMMITMENT_ENTRY_LENGTH,
+    BP_SCALAR_LENGTH,
+    COMMITMENT_VALUE_LENGTH,
+    DEFAULT_BIP32_PATH,
+    DERIVED_KEY_LABEL,
+    EXPORT_PRIVATE_KEY_REQUEST_LENGTH,
+    GET_BLINDED_PUBLIC_KEY_REQUEST_LENGTH,
+    GET_PUBLIC_KEYS_REQUEST_LENGTH,
+    KERNEL_NONCE_HEADER_LENGTH,
+    KERNEL_SIGN_REQUEST_LENGTH,
+    MAX_COMMITMENTS_PER_REQUEST,
+    MAX_DISPLAY_PAGES,
+    MAX_KERNEL_EXCESS_KEYS,
+    MAX_NONCE_POOL_FETCH,
+    MAX_POOLED_KERNEL_KEYS,
+    MAX_PUBLIC_KEYS_PER_REQUEST,
+    MAX_SCRIPT_OFFSET_KEYS,
+    METADATA_SIGNATURE_LABEL,
+    NONCE_POOL_FETCH_REQUEST_LENGTH,
+    NONCE_POOL_SIGN_HEADER_LENGTH,
+    OUTPUT_KIND_CHANGE,
+    P1_BIRTHDAY_GET,
+    P1_BIRTHDAY_SET,
+    P1_CHUNK_ADD,
+    P1_CHUNK_LAST,
+    P1_COUNTER_OUTPUTS,
+    P1_COUNTER_SIGNATURES,
+    P1_KERNEL_NONCE,
+    P1_KERNEL_SIGN,
+    P1_NONCE_POOL_FETCH,
+    P1_NONCE_POOL_INVALIDATE,
+    P1_NONCE_POOL_SIGN,
+    P1_PAIRING_REGISTER,
+    P1_PAIRING_VERIFY,
+    P1_SCRIPT_OFFSET,
+    P1_SENDER_OFFSET_SIGN,
+    P1_SETTINGS_GET,
+    P1_SETTINGS_SET,
+    P1_TRANSACTION_CANCEL,
+    PAIRING_PROOF_LENGTH,
+    PAIRING_SECRET_LENGTH,
+    RESPONSE_FORMAT_VERSION,
+    SCRIPT_CHALLENGE_LABEL,
+    SCRIPT_NONCE_LABEL,
+    SCRIPT_OFFSET_HEADER_LENGTH,
+    SENDER_OFFSET_NONCE_LABEL,
+    SENDER_OFFSET_SIGN_REQUEST_LENGTH,
+    SESSION_MAC_LENGTH,
+    SESSION_PUBLIC_KEY_LENGTH,
+    SETTINGS_ALL,
+    SETTINGS_HOST_ENABLED,
+    SETTING_BLIND_SIGNING,
+    SETTING_EXPERT_MODE,
+    SETTING_MICRO_TARI,
+    SET_SETTINGS_REQUEST_LENGTH,
+    SIGN_CHALLENGE_LENGTH,
+    SIGN_CONFIRMED_OUTPUT_LENGTH,
+    SIGN_OUTPUT_LENGTH,
+    SWAP_ID_LENGTH,
+    SWAP_PREIMAGE_LABEL,
+    SW_OK,
+    TRANSACTION_HASH_DOMAIN,
+    TRANSACTION_HASH_DOMAIN_VERSION,
+    WALLET_BIRTHDAY_LENGTH,
+};
+
+use crate::{
+    birthday::{set_wallet_birthday, wallet_birthday},
+    blinding::BlindedRequest,
+    counter::{count_output, count_signature, output_counter, signing_counter},
+    display::DisplayHints,
+    envelope::Envelope,
+    errors::Error,
+    kernel::{kernel_message, NoncePool, PendingKernel},
+    session::SecureSession,
+    settings::{confirm_settings, is_enabled, settings, show_settings_menu},
+    transaction::ApprovedTransaction,
+    upload::ChunkedUpload,
+};
 
 /// App Version parameters
 const NAME: &str = env!("CARGO_PKG_NAME");
 const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// The oldest host client version this app accepts in the `ClientVersion` handshake
+const MIN_CLIENT_VERSION: SemanticVersion = SemanticVersion::new(0, 1, 0);
+/// The optional features this app implements, reported by `Instruction::GetCapabilities`
+const APP_CAPABILITIES: Capabilities = Capabilities::BULLETPROOF_COSIGNING
+    .union(Capabilities::BATCH_SIGNING)
+    .union(Capabilities::ATOMIC_SWAP)
+    .union(Capabilities::PUBLIC_KEY_EXPORT)
+    .union(Capabilities::BATCH_COMMITMENTS)
+    .union(Capabilities::SIGNING_COUNTER)
+    .union(Capabilities::AUTHENTICATED_SESSION)
+    .union(Capabilities::ENCRYPTED_KEY_EXPORT)
+    .union(Capabilities::DISPLAY_HINTS)
+    .union(Capabilities::BLINDED_KEYS)
+    .union(Capabilities::APP_SETTINGS)
+    .union(Capabilities::OUTPUT_CONFIRMATION)
+    .union(Capabilities::PAIRING)
+    .union(Capabilities::WALLET_BIRTHDAY)
+    .union(Capabilities::DERIVATION_VERSIONS)
+    .union(Capabilities::FRAMED_UPLOADS)
+    .union(Capabilities::SENDER_OFFSETS)
+    .union(Capabilities::KERNEL_SIGNATURES)
+    .union(Capabilities::NONCE_POOL)
+    .union(Capabilities::SETTINGS_CHANGES)
+    .union(Capabilities::OUTPUT_COUNTER)
+    .union(Capabilities::TRANSACTION_CANCEL);
+/// BIP44 purpose and Tari coin type, the hardened prefix of every account path
+const BIP44_PURPOSE: u32 = 44;
+const TARI_COIN_TYPE: u32 = 535348;
+const HARDENED: u32 = 0x8000_0000;
 
-enum Instruction {
-    GetVersion,
-    Sign,
-    Commitment,
-    BPData,
-}
-
-impl TryFrom<u8> for Instruction {
-    type Error = ();
-
-    fn try_from(v: u8) -> Result<Self, Self::Error> {
-        match v {
-            0x01 => Ok(Self::GetVersion),
-            0x02 => Ok(Self::Sign),
-            0x03 => Ok(Self::Commitment),
-            0x04 => Ok(Self::BPData),
-            _ => Err(()),
-        }
-    }
-}
-
-hash_domain!(TransactionHashDomain, "com.tari.base_layer.core.transactions", 0);
+hash_domain!(
+    TransactionHashDomain,
+    TRANSACTION_HASH_DOMAIN,
+    TRANSACTION_HASH_DOMAIN_VERSION
+);
 
 #[no_mangle]
 extern "C" fn sample_main() {
     let mut comm = io::Comm::new();
     init();
     ui::SingleMessage::new("Tari test app").show();
+    let mut approved_transaction: Option<ApprovedTransaction> = None;
+    let mut display_hints: Option<DisplayHints> = None;
+    let mut nonce_pool: Option<NoncePool> = None;
+    let mut pending_kernel: Option<PendingKernel> = None;
+    let mut session: Option<SecureSession> = None;
+    // Set when a command fails its MAC, and cleared only by a new session, so the host cannot carry on in the clear
+    let mut session_failed = false;
+    let mut upload = ChunkedUpload::new();
     loop {
-        match comm.next_event() {
+        let event = comm.next_event();
+        if let io::Event::Command(instruction) = &event {
+            let authenticated = !session_failed && session.as_ref().map(|s| s.verify_command(&comm)).unwrap_or(true);
+            if *instruction != Instruction::OpenSession && !authenticated {
+                // The channel has been tampered with, nothing else is accepted until a new session is opened
+                session_failed = true;
+                session = None;
+                approved_transaction = None;
+                display_hints = None;
+                nonce_pool = None;
+                pending_kernel = None;
+                comm.reply(Error::SessionMacFailed);
+                continue;
+            }
+        }
+        match event {
             io::Event::Button(ButtonEvent::BothButtonsRelease) => nanos_sdk::exit_app(0),
             io::Event::Button(ButtonEvent::RightButtonRelease) => {
                 display_infos();
             },
             io::Event::Button(ButtonEvent::LeftButtonPress) => {},
+            io::Event::Button(ButtonEvent::LeftButtonRelease) => {
+                show_settings_menu();
+                ui::SingleMessage::new("Tari test app").show();
+            },
             io::Event::Button(_) => {},
             io::Event::Command(Instruction::GetVersion) => {
+                // Hosts check chunked uploads with this instruction, every chunk is answered with the version
+                let crc = match comm.get_p1() {
+                    p1 @ (P1_CHUNK_ADD | P1_CHUNK_LAST) => {
+                        let end = comm.rx - session.as_ref().map(|_| SESSION_MAC_LENGTH).unwrap_or(0);
+                        match upload.receive(comm.get(APDU_HEADER_LENGTH, end), p1 == P1_CHUNK_LAST) {
+                            Ok(crc) => crc,
+                            Err(e) => {
+                                reply(&mut comm, &mut session, e);
+                                continue;
+                            },
+                        }
+                    },
+                    _ => {
+                        upload.start();
+                        None
+                    },
+                };
                 let name_bytes = NAME.as_bytes();
                 let version_bytes = VERSION.as_bytes();
                 comm.append(&[1]); // Format
@@ -104,61 +271,48 @@ extern "C" fn sample_main() {
                 comm.append(name_bytes);
                 comm.append(&[version_bytes.len() as u8]);
                 comm.append(version_bytes);
-                comm.append(&[0]); // No flags
-                comm.reply_ok();
+                comm.append(&[settings()]); // Settings
+                if let Some(crc) = crc {
+                    comm.append(&crc.to_le_bytes());
+                }
+                reply(&mut comm, &mut session, Reply(SW_OK));
             },
             io::Event::Command(Instruction::Sign) => {
                 // first bytes are instruction details
 
-                let offset = 5;
-                let challenge = ArrayString::<32>::from_bytes(comm.get(offset, offset + 32));
-                // THIS IS BROKEN
-                // let k = RistrettoSecretKey::random(&mut LedgerRng);
-                // let n = RistrettoSecretKey::random(&mut LedgerRng);
-                let path: [u32; 5] = nanos_sdk::ecc::make_bip32_path(b"m/44'/535348'/0'/0/0");
-                let mut raw_key = [0u8; 32];
-                unsafe {
-                    os_perso_derive_node_bip32(
-                        CurvesId::Ed25519 as u8,
-                        (&path).as_ptr(),
-                        (&path).len() as u32,
-                        (&mut raw_key).as_mut_ptr(),
-                        core::ptr::null_mut(),
-                    )
+                let offset = APDU_HEADER_LENGTH;
+                // Nothing about a raw challenge can be shown, the user has to opt in to signing blind
+                if !is_enabled(SETTING_BLIND_SIGNING) {
+                    reply(&mut comm, &mut session, Error::SettingDisabled);
+                    continue;
+                }
+                let version = match DerivationVersion::try_from(comm.get_p2()) {
+                    Ok(version) => version,
+                    Err(_) => {
+                        reply(&mut comm, &mut session, Error::ConversionError);
+                        continue;
+                    },
                 };
-                let k = RistrettoSecretKey::from_bytes(&raw_key).unwrap();
-                let n = Blake256::new().chain(k.as_bytes()).finalize().to_vec();
-                let n = RistrettoSecretKey::from_bytes(&n).unwrap();
-                let public_key = RistrettoPublicKey::from_secret_key(&k);
-                let public_nonce = RistrettoPublicKey::from_secret_key(&n);
-                // let e = Blake256::new()
-                //     .chain(public_key.as_bytes())
-                //     .chain(public_nonce.as_bytes())
-                //     .chain(challenge.bytes())
-                //     .finalize().to_vec();
-                let hash = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new("script_challenge")
-                    .chain(&public_key)
-                    .chain(&public_nonce)
-                    .chain(challenge.bytes())
-                    .finalize();
-                let signature = RistrettoSchnorr::sign_raw(&k, n, &hash).unwrap();
+                let challenge = ArrayString::<32>::from_bytes(comm.get(offset, offset + SIGN_CHALLENGE_LENGTH));
+                count_signature();
+                let k = derive_versioned_key(&nanos_sdk::ecc::make_bip32_path(DEFAULT_BIP32_PATH), version);
+                let (public_key, signature) = sign_script_challenge_with(&k, challenge.bytes());
                 let sig = signature.get_signature().as_bytes();
                 let nonce = signature.get_public_nonce().as_bytes();
 
-
-                comm.append(&[1]); // version
+                comm.append(&[RESPONSE_FORMAT_VERSION]); // version
                 comm.append(public_key.as_bytes());
                 comm.append(sig);
                 comm.append(nonce);
-                comm.reply_ok();
+                reply(&mut comm, &mut session, Reply(SW_OK));
             },
             io::Event::Command(Instruction::Commitment) => {
                 // first bytes are instruction details
-                let offset = 5;
+                let offset = APDU_HEADER_LENGTH;
                 let mut value_bytes = [0u8; 8];
-                value_bytes.clone_from_slice(comm.get(offset, offset + 8));
+                value_bytes.clone_from_slice(comm.get(offset, offset + COMMITMENT_VALUE_LENGTH));
                 let value = u64::from_le_bytes(value_bytes);
-                let path: [u32; 5] = nanos_sdk::ecc::make_bip32_path(b"m/44'/535348'/0'/0/0");
+                let path: [u32; 5] = nanos_sdk::ecc::make_bip32_path(DEFAULT_BIP32_PATH);
                 let mut raw_key = [0u8; 32];
                 unsafe {
                     os_perso_derive_node_bip32(
@@ -172,18 +326,23 @@ extern "C" fn sample_main() {
                 let k = RistrettoSecretKey::from_bytes(&raw_key).unwrap();
                 let com_factories = ExtendedPedersenCommitmentFactory::default();
                 let commitment = com_factories.commit_value(&k, value);
-                comm.append(&[1]); // version
+                comm.append(&[RESPONSE_FORMAT_VERSION]); // version
                 comm.append(commitment.as_bytes());
                 // comm.append(pkey.as_ref());
-                comm.reply_ok();
+                reply(&mut comm, &mut session, Reply(SW_OK));
             },
             io::Event::Command(Instruction::BPData) => {
                 // first bytes are instruction details
-                let offset = 5;
+                let offset = APDU_HEADER_LENGTH;
+                // Any scalar times the app key, only for users who know what they are co-signing
+                if !is_enabled(SETTING_EXPERT_MODE) {
+                    reply(&mut comm, &mut session, Error::SettingDisabled);
+                    continue;
+                }
                 let mut scalar_bytes = [0u8; 32];
-                scalar_bytes.clone_from_slice(comm.get(offset, offset + 32));
+                scalar_bytes.clone_from_slice(comm.get(offset, offset + BP_SCALAR_LENGTH));
                 let scalar = Scalar::from_bits(scalar_bytes);
-                let path: [u32; 5] = ecc::make_bip32_path(b"m/44'/535348'/0'/0/0");
+                let path: [u32; 5] = ecc::make_bip32_path(DEFAULT_BIP32_PATH);
                 let mut raw_key = [0u8; 32];
                 unsafe {
                     os_perso_derive_node_bip32(
@@ -199,44 +358,1097 @@ extern "C" fn sample_main() {
                 k_scalar_bytes.clone_from_slice(k.as_bytes());
                 let k_scalar = Scalar::from_bits(k_scalar_bytes);
                 let blinded = k_scalar * &scalar;
-                comm.append(&[1]); // version
+                comm.append(&[RESPONSE_FORMAT_VERSION]); // version
                 comm.append(blinded.as_bytes());
+                reply(&mut comm, &mut session, Reply(SW_OK));
+            },
+            io::Event::Command(Instruction::ClientVersion) => {
+                // first bytes are instruction details
+                let offset = APDU_HEADER_LENGTH;
+                let client_version =
+                    SemanticVersion::from_le_bytes(comm.get(offset, offset + SemanticVersion::ENCODED_LENGTH));
+                comm.append(&[RESPONSE_FORMAT_VERSION]); // format
+                comm.append(&app_version().to_le_bytes());
+                comm.append(&MIN_CLIENT_VERSION.to_le_bytes());
+                if client_version.map(|v| v < MIN_CLIENT_VERSION).unwrap_or(true) {
+                    reply(&mut comm, &mut session, Error::UnsupportedClientVersion);
+                } else {
+                    reply(&mut comm, &mut session, Reply(SW_OK));
+                }
+            },
+            io::Event::Command(Instruction::DisplayHints) => {
+                // first bytes are instruction details
+                let offset = APDU_HEADER_LENGTH;
+                let pages = comm.get(offset + 32, offset + 33)[0];
+                if pages == 0 || pages > MAX_DISPLAY_PAGES {
+                    display_hints = None;
+                    reply(&mut comm, &mut session, Error::ConversionError);
+                    continue;
+                }
+                let request = comm.get(offset, offset + display_hints_request_length(pages));
+                display_hints = DisplayHints::from_request(request);
+                if display_hints.is_none() {
+                    reply(&mut comm, &mut session, Error::ConversionError);
+                    continue;
+                }
+                comm.append(&[RESPONSE_FORMAT_VERSION]); // version
+                reply(&mut comm, &mut session, Reply(SW_OK));
+            },
+            io::Event::Command(Instruction::TransactionSummary) => {
+                // first bytes are instruction details
+                let offset = APDU_HEADER_LENGTH;
+                // A new summary always replaces whatever was approved before, with the kernel nonce taken under it,
+                // and uses up the hints sent for it
+                approved_transaction = None;
+                pending_kernel = None;
+                if comm.get_p1() == P1_TRANSACTION_CANCEL {
+                    display_hints = None;
+                    comm.append(&[RESPONSE_FORMAT_VERSION]); // version
+                    reply(&mut comm, &mut session, Reply(SW_OK));
+                    continue;
+                }
+                let hints = display_hints.take();
+                let summary = match TransactionSummary::from_le_bytes(
+                    comm.get(offset, offset + TransactionSummary::ENCODED_LENGTH),
+                ) {
+                    Ok(summary) => summary,
+                    Err(_) => {
+                        reply(&mut comm, &mut session, Error::IncorrectByteLength);
+                        continue;
+                    },
+                };
+                if confirm_transaction(&summary, hints.as_ref()) {
+                    approved_transaction = Some(ApprovedTransaction::new(&summary, hints.map(|h| h.digest)));
+                    comm.append(&[RESPONSE_FORMAT_VERSION]); // version
+                    reply(&mut comm, &mut session, Reply(SW_OK));
+                } else {
+                    reply(&mut comm, &mut session, Error::UserRejected);
+                }
+                ui::SingleMessage::new("Tari test app").show();
+            },
+            io::Event::Command(Instruction::SignOutput) => {
+                // first bytes are instruction details
+                let offset = APDU_HEADER_LENGTH;
+                let data = comm.get(offset, offset + SIGN_OUTPUT_LENGTH);
+                let challenge = match approve_output(&mut approved_transaction, data) {
+                    Ok((_, _, challenge)) => challenge,
+                    Err(e) => {
+                        reply(&mut comm, &mut session, e);
+                        continue;
+                    },
+                };
+
+                count_signature();
+                count_output();
+                let (public_key, signature) = sign_script_challenge(&challenge);
+                comm.append(&[RESPONSE_FORMAT_VERSION]); // version
+                comm.append(public_key.as_bytes());
+                comm.append(signature.get_signature().as_bytes());
+                comm.append(signature.get_public_nonce().as_bytes());
+                reply(&mut comm, &mut session, Reply(SW_OK));
+            },
+            io::Event::Command(Instruction::SignConfirmedOutput) => {
+                // first bytes are instruction details
+                let offset = APDU_HEADER_LENGTH;
+                let data = comm.get(offset, offset + SIGN_CONFIRMED_OUTPUT_LENGTH);
+                let (kind, value, challenge) =
+                    match approve_output(&mut approved_transaction, &data[..SIGN_OUTPUT_LENGTH]) {
+                        Ok(output) => output,
+                        Err(e) => {
+                            reply(&mut comm, &mut session, e);
+                            continue;
+                        },
+                    };
+                let confirmed = confirm_output(kind, value, &data[SIGN_OUTPUT_LENGTH..]);
+                ui::SingleMessage::new("Tari test app").show();
+                if !confirmed {
+                    approved_transaction = None;
+                    reply(&mut comm, &mut session, Error::UserRejected);
+                    continue;
+                }
+
+                count_signature();
+                count_output();
+                let (public_key, signature) = sign_script_challenge(&challenge);
+                comm.append(&[RESPONSE_FORMAT_VERSION]); // version
+                comm.append(public_key.as_bytes());
+                comm.append(signature.get_signature().as_bytes());
+                comm.append(signature.get_public_nonce().as_bytes());
+                reply(&mut comm, &mut session, Reply(SW_OK));
+            },
+            io::Event::Command(Instruction::SwapLock) => {
+                // first bytes are instruction details
+                let offset = APDU_HEADER_LENGTH;
+                let mut swap_id = [0u8; 32];
+                swap_id.clone_from_slice(comm.get(offset, offset + SWAP_ID_LENGTH));
+                let k = app_secret_key();
+                let lock_hash = Sha256::new().chain(swap_preimage(&k, &swap_id)).finalize();
+                comm.append(&[RESPONSE_FORMAT_VERSION]); // version
+                comm.append(&lock_hash);
+                comm.append(RistrettoPublicKey::from_secret_key(&k).as_bytes());
+                reply(&mut comm, &mut session, Reply(SW_OK));
+            },
+            io::Event::Command(Instruction::SwapPreimage) => {
+                // first bytes are instruction details
+                let offset = APDU_HEADER_LENGTH;
+                let mut swap_id = [0u8; 32];
+                swap_id.clone_from_slice(comm.get(offset, offset + SWAP_ID_LENGTH));
+                // Revealing the preimage lets the counterparty claim their side of the swap
+                if ui::Validator::new("Reveal swap secret?").ask() {
+                    comm.append(&[RESPONSE_FORMAT_VERSION]); // version
+                    comm.append(&swap_preimage(&app_secret_key(), &swap_id));
+                    reply(&mut comm, &mut session, Reply(SW_OK));
+                } else {
+                    reply(&mut comm, &mut session, Error::UserRejected);
+                }
+                ui::SingleMessage::new("Tari test app").show();
+            },
+            io::Event::Command(Instruction::GetCapabilities) => {
+                comm.append(&[RESPONSE_FORMAT_VERSION]); // version
+                comm.append(&APP_CAPABILITIES.to_le_bytes());
+                reply(&mut comm, &mut session, Reply(SW_OK));
+            },
+            io::Event::Command(Instruction::GetPublicKeys) => {
+                // first bytes are instruction details
+                let offset = APDU_HEADER_LENGTH;
+                let data = comm.get(offset, offset + GET_PUBLIC_KEYS_REQUEST_LENGTH);
+                let mut account_bytes = [0u8; 4];
+                account_bytes.clone_from_slice(&data[0..4]);
+                let mut index_bytes = [0u8; 4];
+                index_bytes.clone_from_slice(&data[4..8]);
+                let account = u32::from_le_bytes(account_bytes);
+                let first_index = u32::from_le_bytes(index_bytes);
+                let count = data[8];
+                let branch = match KeyBranch::try_from(comm.get_p1()) {
+                    Ok(branch) => branch,
+                    Err(_) => {
+                        reply(&mut comm, &mut session, Error::ConversionError);
+                        continue;
+                    },
+                };
+                let version = match DerivationVersion::try_from(comm.get_p2()) {
+                    Ok(version) => version,
+                    Err(_) => {
+                        reply(&mut comm, &mut session, Error::ConversionError);
+                        continue;
+                    },
+                };
+
+                // Both the account and the indices must fit below the hardened range
+                let end = first_index.checked_add(u32::from(count)).filter(|end| *end <= HARDENED);
+                if count == 0 || count > MAX_PUBLIC_KEYS_PER_REQUEST || account >= HARDENED || end.is_none() {
+                    reply(&mut comm, &mut session, Error::ConversionError);
+                    continue;
+                }
+                comm.append(&[RESPONSE_FORMAT_VERSION]); // version
+                for index in first_index..first_index + u32::from(count) {
+                    let k = derive_versioned_key(&branch_key_path(account, branch, index), version);
+                    comm.append(RistrettoPublicKey::from_secret_key(&k).as_bytes());
+                }
+                reply(&mut comm, &mut session, Reply(SW_OK));
+            },
+            io::Event::Command(Instruction::BatchCommitment) => {
+                // first bytes are instruction details
+                let offset = APDU_HEADER_LENGTH;
+                let count = comm.get(offset, offset + 1)[0];
+                if count == 0 || count > MAX_COMMITMENTS_PER_REQUEST {
+                    reply(&mut comm, &mut session, Error::ConversionError);
+                    continue;
+                }
+                let data = comm.get(offset + 1, offset + batch_commitment_request_length(count));
+                let mut entries = [(0u64, 0u32); MAX_COMMITMENTS_PER_REQUEST as usize];
+                for (entry, bytes) in entries.iter_mut().zip(data.chunks(BATCH_COMMITMENT_ENTRY_LENGTH)) {
+                    let mut value_bytes = [0u8; 8];
+                    value_bytes.clone_from_slice(&bytes[0..8]);
+                    let mut index_bytes = [0u8; 4];
+                    index_bytes.clone_from_slice(&bytes[8..12]);
+                    *entry = (u64::from_le_bytes(value_bytes), u32::from_le_bytes(index_bytes));
+                }
+                let entries = &entries[..count as usize];
+                if entries.iter().any(|(_, index)| *index >= HARDENED) {
+                    reply(&mut comm, &mut session, Error::ConversionError);
+                    continue;
+                }
+                let version = match DerivationVersion::try_from(comm.get_p2()) {
+                    Ok(version) => version,
+                    Err(_) => {
+                        reply(&mut comm, &mut session, Error::ConversionError);
+                        continue;
+                    },
+                };
+
+                let com_factories = ExtendedPedersenCommitmentFactory::default();
+                comm.append(&[RESPONSE_FORMAT_VERSION]); // version
+                for (value, index) in entries {
+                    let k = derive_versioned_key(&branch_key_path(0, KeyBranch::CommitmentMask, *index), version);
+                    comm.append(com_factories.commit_value(&k, *value).as_bytes());
+                }
+                reply(&mut comm, &mut session, Reply(SW_OK));
+            },
+            io::Event::Command(Instruction::GetSigningCounter) => {
+                let counter = match comm.get_p1() {
+                    P1_COUNTER_SIGNATURES => signing_counter(),
+                    P1_COUNTER_OUTPUTS => output_counter(),
+                    _ => {
+                        reply(&mut comm, &mut session, Error::ConversionError);
+                        continue;
+                    },
+                };
+                comm.append(&[RESPONSE_FORMAT_VERSION]); // version
+                comm.append(&counter.to_le_bytes());
+                reply(&mut comm, &mut session, Reply(SW_OK));
+            },
+            io::Event::Command(Instruction::OpenSession) => {
+                // first bytes are instruction details
+                let offset = APDU_HEADER_LENGTH;
+                let host_key = comm.get(offset, offset + SESSION_PUBLIC_KEY_LENGTH);
+                let host_key = match RistrettoPublicKey::from_bytes(host_key) {
+                    Ok(host_key) => host_key,
+                    Err(_) => {
+                        reply(&mut comm, &mut session, Error::ConversionError);
+                        continue;
+                    },
+                };
+                // Whatever was approved or prepared for the host of the old session is not for the next one
+                approved_transaction = None;
+                display_hints = None;
+                nonce_pool = None;
+                pending_kernel = None;
+                let (new_session, ephemeral_public_key, challenge) = SecureSession::open(&app_secret_key(), &host_key);
+                let (public_key, signature) = sign_script_challenge(&challenge);
+                comm.append(&[RESPONSE_FORMAT_VERSION]); // version
+                comm.append(ephemeral_public_key.as_bytes());
+                comm.append(public_key.as_bytes());
+                comm.append(signature.get_signature().as_bytes());
+                comm.append(signature.get_public_nonce().as_bytes());
+                // The response opens the new session, so it is not authenticated by the old one
                 comm.reply_ok();
+                session = Some(new_session);
+                session_failed = false;
+            },
+            io::Event::Command(Instruction::ExportPrivateKey) => {
+                // first bytes are instruction details
+                let offset = APDU_HEADER_LENGTH;
+                let data = comm.get(offset, offset + EXPORT_PRIVATE_KEY_REQUEST_LENGTH);
+                let mut account_bytes = [0u8; 4];
+                account_bytes.clone_from_slice(&data[0..4]);
+                let account = u32::from_le_bytes(account_bytes);
+                let host_key = RistrettoPublicKey::from_bytes(&data[4..36]);
+                let (key, host_key) = match (SensitiveKey::try_from(comm.get_p1()), host_key) {
+                    (Ok(key), Ok(host_key)) if account < HARDENED => (key, host_key),
+                    _ => {
+                        reply(&mut comm, &mut session, Error::ConversionError);
+                        continue;
+                    },
+                };
+                // Whoever holds these keys can follow the whole wallet, the user has to agree to hand them out
+                if ui::Validator::new(&format!("Export {}?", key.name())).ask() {
+                    let secret = derive_secret_key(&sensitive_key_path(account, key));
+                    let envelope = Envelope::seal(&app_secret_key(), &host_key, &secret);
+                    comm.append(&[RESPONSE_FORMAT_VERSION]); // version
+                    comm.append(envelope.ephemeral_public_key.as_bytes());
+                    comm.append(&envelope.ciphertext);
+                    comm.append(&envelope.tag);
+                    reply(&mut comm, &mut session, Reply(SW_OK));
+                } else {
+                    reply(&mut comm, &mut session, Error::UserRejected);
+                }
+                ui::SingleMessage::new("Tari test app").show();
+            },
+            io::Event::Command(Instruction::GetBlindedPublicKey) => {
+                // first bytes are instruction details
+                let offset = APDU_HEADER_LENGTH;
+                let data = comm.get(offset, offset + GET_BLINDED_PUBLIC_KEY_REQUEST_LENGTH);
+                let request = match BlindedRequest::from_request(&app_secret_key(), data) {
+                    Some(request) => request,
+                    None => {
+                        reply(&mut comm, &mut session, Error::ConversionError);
+                        continue;
+                    },
+                };
+                let (branch, account, index) = match request.branch() {
+                    Some(branch) if request.account() < HARDENED && request.index() < HARDENED => {
+                        (branch, request.account(), request.index())
+                    },
+                    _ => {
+                        reply(&mut comm, &mut session, Error::ConversionError);
+                        continue;
+                    },
+                };
+                let k = derive_secret_key(&branch_key_path(account, branch, index));
+                comm.append(&[RESPONSE_FORMAT_VERSION]); // version
+                comm.append(request.blind(&RistrettoPublicKey::from_secret_key(&k)).as_bytes());
+                reply(&mut comm, &mut session, Reply(SW_OK));
+            },
+            io::Event::Command(Instruction::Pairing) => {
+                // first bytes are instruction details
+                let offset = APDU_HEADER_LENGTH;
+                // The session ties the proof to a fresh challenge and keeps the secret off the link
+                let (challenge, mask) = match session.as_ref() {
+                    Some(session) => (*session.challenge(), session.pairing_mask()),
+                    None => {
+                        reply(&mut comm, &mut session, Error::PairingFailed);
+                        continue;
+                    },
+                };
+                match comm.get_p1() {
+                    P1_PAIRING_REGISTER => {
+                        let masked = comm.get(offset, offset + PAIRING_SECRET_LENGTH);
+                        if pairing::register(masked, &mask) {
+                            comm.append(&[RESPONSE_FORMAT_VERSION]); // version
+                            reply(&mut comm, &mut session, Reply(SW_OK));
+                        } else {
+                            reply(&mut comm, &mut session, Error::UserRejected);
+                        }
+                    },
+                    P1_PAIRING_VERIFY => {
+                        let proof = comm.get(offset, offset + PAIRING_PROOF_LENGTH);
+                        match pairing::verify(proof, &challenge) {
+                            Some(response) => {
+                                comm.append(&[RESPONSE_FORMAT_VERSION]); // version
+                                comm.append(&response);
+                                reply(&mut comm, &mut session, Reply(SW_OK));
+                            },
+                            None => reply(&mut comm, &mut session, Error::PairingFailed),
+                        }
+                    },
+                    _ => reply(&mut comm, &mut session, Error::ConversionError),
+                }
+                ui::SingleMessage::new("Tari test app").show();
+            },
+            io::Event::Command(Instruction::WalletBirthday) => {
+                // first bytes are instruction details
+                let offset = APDU_HEADER_LENGTH;
+                let app_public_key = RistrettoPublicKey::from_secret_key(&app_secret_key());
+                match comm.get_p1() {
+                    P1_BIRTHDAY_GET => {
+                        comm.append(&[RESPONSE_FORMAT_VERSION]); // version
+                        comm.append(&wallet_birthday(&app_public_key).to_le_bytes());
+                        reply(&mut comm, &mut session, Reply(SW_OK));
+                    },
+                    P1_BIRTHDAY_SET => {
+                        let mut height_bytes = [0u8; 8];
+                        height_bytes.clone_from_slice(comm.get(offset, offset + WALLET_BIRTHDAY_LENGTH));
+                        if set_wallet_birthday(&app_public_key, u64::from_le_bytes(height_bytes)) {
+                            comm.append(&[RESPONSE_FORMAT_VERSION]); // version
+                            reply(&mut comm, &mut session, Reply(SW_OK));
+                        } else {
+                            reply(&mut comm, &mut session, Error::UserRejected);
+                        }
+                        ui::SingleMessage::new("Tari test app").show();
+                    },
+                    _ => reply(&mut comm, &mut session, Error::ConversionError),
+                }
+            },
+            io::Event::Command(Instruction::SenderOffset) => {
+                // first bytes are instruction details
+                let offset = APDU_HEADER_LENGTH;
+                let version = match DerivationVersion::try_from(comm.get_p2()) {
+                    Ok(version) => version,
+                    Err(_) => {
+                        reply(&mut comm, &mut session, Error::ConversionError);
+                        continue;
+                    },
+                };
+                match comm.get_p1() {
+                    P1_SENDER_OFFSET_SIGN => {
+                        let data = comm.get(offset, offset + SENDER_OFFSET_SIGN_REQUEST_LENGTH);
+                        let mut account_bytes = [0u8; 4];
+                        account_bytes.clone_from_slice(&data[0..4]);
+                        let mut index_bytes = [0u8; 4];
+                        index_bytes.clone_from_slice(&data[4..8]);
+                        let account = u32::from_le_bytes(account_bytes);
+                        let index = u32::from_le_bytes(index_bytes);
+                        let commitments = (
+                            RistrettoPublicKey::from_bytes(&data[8..40]),
+                            RistrettoPublicKey::from_bytes(&data[40..72]),
+                        );
+                        let (commitment, ephemeral_commitment) = match commitments {
+                            (Ok(commitment), Ok(ephemeral)) if account < HARDENED && index < HARDENED => {
+                                (commitment, ephemeral)
+                            },
+                            _ => {
+                                reply(&mut comm, &mut session, Error::ConversionError);
+                                continue;
+                            },
+                        };
+                        let mut message = [0u8; 32];
+                        message.clone_from_slice(&data[72..104]);
+                        // Only the outputs of an approved transaction get a sender offset, one each
+                        if let Err(e) = approved_transaction
+                            .as_mut()
+                            .map_or(Err(Error::TransactionNotApproved), |t| t.take_sender_offset())
+                        {
+                            reply(&mut comm, &mut session, e);
+                            continue;
+                        }
+
+                        count_signature();
+                        let k =
+                            derive_versioned_key(&branch_key_path(account, KeyBranch::SenderOffset, index), version);
+                        let (public_key, signature) =
+                            sign_sender_offset(&k, &commitment, &ephemeral_commitment, &message);
+                        comm.append(&[RESPONSE_FORMAT_VERSION]); // version
+                        comm.append(public_key.as_bytes());
+                        comm.append(signature.get_signature().as_bytes());
+                        comm.append(signature.get_public_nonce().as_bytes());
+                        reply(&mut comm, &mut session, Reply(SW_OK));
+                    },
+                    P1_SCRIPT_OFFSET => {
+                        let header = comm.get(offset, offset + SCRIPT_OFFSET_HEADER_LENGTH);
+                        let mut nonce_bytes = [0u8; 8];
+                        nonce_bytes.clone_from_slice(&header[0..8]);
+                        let mut account_bytes = [0u8; 4];
+                        account_bytes.clone_from_slice(&header[8..12]);
+                        let account = u32::from_le_bytes(account_bytes);
+                        let (inputs, outputs) = (header[12], header[13]);
+                        // Without an input the offset is the negated sum of the sender offset keys
+                        let count = usize::from(inputs) + usize::from(outputs);
+                        if inputs == 0 || count > MAX_SCRIPT_OFFSET_KEYS || account >= HARDENED {
+                            reply(&mut comm, &mut session, Error::ConversionError);
+                            continue;
+                        }
+                        let data = comm.get(
+                            offset + SCRIPT_OFFSET_HEADER_LENGTH,
+                            offset + script_offset_request_length(inputs, outputs),
+                        );
+                        let mut indices = [0u32; MAX_SCRIPT_OFFSET_KEYS];
+                        for (index, bytes) in indices.iter_mut().zip(data.chunks(4)) {
+                            let mut index_bytes = [0u8; 4];
+                            index_bytes.clone_from_slice(bytes);
+                            *index = u32::from_le_bytes(index_bytes);
+                        }
+                        let indices = &indices[..count];
+                        if indices.iter().any(|index| *index >= HARDENED) {
+                            reply(&mut comm, &mut session, Error::ConversionError);
+                            continue;
+                        }
+                        let taken = match approved_transaction.as_mut() {
+                            Some(transaction) => {
+                                transaction.take_script_offset(u64::from_le_bytes(nonce_bytes), outputs)
+                            },
+                            None => Err(Error::TransactionNotApproved),
+                        };
+                        if let Err(e) = taken {
+                            approved_transaction = None;
+                            reply(&mut comm, &mut session, e);
+                            continue;
+                        }
+
+                        let (script_keys, sender_offsets) = indices.split_at(usize::from(inputs));
+                        let mut script_offset = RistrettoSecretKey::default();
+                        for index in script_keys {
+                            let k =
+                                derive_versioned_key(&branch_key_path(account, KeyBranch::ScriptKey, *index), version);
+                            script_offset = &script_offset + &k;
+                        }
+                        for index in sender_offsets {
+                            let k = derive_versioned_key(
+                                &branch_key_path(account, KeyBranch::SenderOffset, *index),
+                                version,
+                            );
+                            script_offset = &script_offset - &k;
+                        }
+                        comm.append(&[RESPONSE_FORMAT_VERSION]); // version
+                        comm.append(script_offset.as_bytes());
+                        reply(&mut comm, &mut session, Reply(SW_OK));
+                    },
+                    _ => reply(&mut comm, &mut session, Error::ConversionError),
+                }
+            },
+            io::Event::Command(Instruction::KernelSignature) => {
+                // first bytes are instruction details
+                let offset = APDU_HEADER_LENGTH;
+                match comm.get_p1() {
+                    P1_KERNEL_NONCE => {
+                        let version = match DerivationVersion::try_from(comm.get_p2()) {
+                            Ok(version) => version,
+                            Err(_) => {
+                                reply(&mut comm, &mut session, Error::ConversionError);
+                                continue;
+                            },
+                        };
+                        let header = comm.get(offset, offset + KERNEL_NONCE_HEADER_LENGTH);
+                        let (inputs, outputs) = (header[0], header[1]);
+                        let mut fee = [0u8; 8];
+                        fee.clone_from_slice(&header[2..10]);
+                        let fee = u64::from_le_bytes(fee);
+                        let mut lock_height = [0u8; 8];
+                        lock_height.clone_from_slice(&header[10..18]);
+                        let lock_height = u64::from_le_bytes(lock_height);
+                        let count = usize::from(inputs) + usize::from(outputs);
+                        if count == 0 || count > MAX_KERNEL_EXCESS_KEYS {
+                            reply(&mut comm, &mut session, Error::ConversionError);
+                            continue;
+                        }
+                        let data = comm.get(
+                            offset + KERNEL_NONCE_HEADER_LENGTH,
+                            offset + kernel_nonce_request_length(inputs, outputs),
+                        );
+                        let excess = match kernel_excess(data, inputs, version) {
+                            Some(excess) => excess,
+                            None => {
+                                reply(&mut comm, &mut session, Error::ConversionError);
+                                continue;
+                            },
+                        };
+                        // An excess over inputs spends them under the approved fee, and receiving is confirmed here,
+                        // so that no kernel is signed with a mask the user has not seen
+                        if inputs > 0 {
+                            if let Err(e) = bind_kernel(&mut approved_transaction, &excess, fee, lock_height) {
+                                reply(&mut comm, &mut session, e);
+                                continue;
+                            }
+                        } else {
+                            let confirmed = confirm_kernel(outputs, fee, lock_height);
+                            ui::SingleMessage::new("Tari test app").show();
+                            if !confirmed {
+                                reply(&mut comm, &mut session, Error::UserRejected);
+                                continue;
+                            }
+                        }
+
+                        let kernel = PendingKernel::new(excess, kernel_message(fee, lock_height), signing_counter());
+                        comm.append(&[RESPONSE_FORMAT_VERSION]); // version
+                        comm.append(kernel.public_excess().as_bytes());
+                        comm.append(kernel.public_nonce().as_bytes());
+                        pending_kernel = Some(kernel);
+                        reply(&mut comm, &mut session, Reply(SW_OK));
+                    },
+                    P1_KERNEL_SIGN => {
+                        let data = comm.get(offset, offset + KERNEL_SIGN_REQUEST_LENGTH);
+                        let totals = (
+                            RistrettoPublicKey::from_bytes(&data[0..32]),
+                            RistrettoPublicKey::from_bytes(&data[32..64]),
+                        );
+                        let (total_nonce, total_excess) = match totals {
+                            (Ok(total_nonce), Ok(total_excess)) => (total_nonce, total_excess),
+                            _ => {
+                                reply(&mut comm, &mut session, Error::ConversionError);
+                                continue;
+                            },
+                        };
+                        let mut message = [0u8; 32];
+                        message.clone_from_slice(&data[64..96]);
+                        // The kept nonce is used up by any request, so that a refused one cannot be retried
+                        let kernel = match pending_kernel.take() {
+                            Some(kernel) if kernel.message() == &message => kernel,
+                            _ => {
+                                reply(&mut comm, &mut session, Error::TransactionNotApproved);
+                                continue;
+                            },
+                        };
+
+                        sign_bound_kernel(&mut approved_transaction, &kernel.public_excess());
+                        count_signature();
+                        let s = kernel.sign(&total_nonce, &total_excess);
+                        comm.append(&[RESPONSE_FORMAT_VERSION]); // version
+                        comm.append(s.as_bytes());
+                        reply(&mut comm, &mut session, Reply(SW_OK));
+                    },
+                    _ => reply(&mut comm, &mut session, Error::ConversionError),
+                }
+            },
+            io::Event::Command(Instruction::NoncePool) => {
+                // first bytes are instruction details
+                let offset = APDU_HEADER_LENGTH;
+                match comm.get_p1() {
+                    P1_NONCE_POOL_FETCH => {
+                        let count = usize::from(comm.get(offset, offset + NONCE_POOL_FETCH_REQUEST_LENGTH)[0]);
+                        if count == 0 || count > MAX_NONCE_POOL_FETCH {
+                            reply(&mut comm, &mut session, Error::ConversionError);
+                            continue;
+                        }
+                        // A pool that cannot issue all of them makes way for a new one
+                        let remaining = nonce_pool.as_ref().map(|pool| pool.remaining()).unwrap_or(0);
+                        if remaining < count {
+                            let key = derive_versioned_key(
+                                &branch_key_path(0, KeyBranch::KernelNonce, 0),
+                                DerivationVersion::LATEST,
+                            );
+                            nonce_pool = Some(NoncePool::new(&key, signing_counter()));
+                        }
+                        let pool = nonce_pool.as_mut().unwrap();
+                        comm.append(&[RESPONSE_FORMAT_VERSION]); // version
+                        comm.append(&pool.id().to_le_bytes());
+                        for n in 0..count {
+                            let (id, public_nonce) = pool.issue().unwrap();
+                            if n == 0 {
+                                comm.append(&[id]);
+                            }
+                            comm.append(public_nonce.as_bytes());
+                        }
+                        reply(&mut comm, &mut session, Reply(SW_OK));
+                    },
+                    P1_NONCE_POOL_SIGN => {
+                        let version = match DerivationVersion::try_from(comm.get_p2()) {
+                            Ok(version) => version,
+                            Err(_) => {
+                                reply(&mut comm, &mut session, Error::ConversionError);
+                                continue;
+                            },
+                        };
+                        let header = comm.get(offset, offset + NONCE_POOL_SIGN_HEADER_LENGTH);
+                        let mut pool_id = [0u8; 8];
+                        pool_id.clone_from_slice(&header[0..8]);
+                        let pool_id = u64::from_le_bytes(pool_id);
+                        let (nonce_id, inputs, outputs) = (header[8], header[9], header[10]);
+                        let count = usize::from(inputs) + usize::from(outputs);
+                        if count == 0 || count > MAX_POOLED_KERNEL_KEYS {
+                            reply(&mut comm, &mut session, Error::ConversionError);
+                            continue;
+                        }
+                        let data = comm.get(
+                            offset + NONCE_POOL_SIGN_HEADER_LENGTH,
+                            offset + nonce_pool_sign_request_length(inputs, outputs),
+                        );
+                        let (indices, data) = data.split_at(4 * count);
+                        let excess = match kernel_excess(indices, inputs, version) {
+                            Some(excess) => excess,
+                            None => {
+                                reply(&mut comm, &mut session, Error::ConversionError);
+                                continue;
+                            },
+                        };
+                        let totals = (
+                            RistrettoPublicKey::from_bytes(&data[0..32]),
+                            RistrettoPublicKey::from_bytes(&data[32..64]),
+                        );
+                        let (total_nonce, total_excess) = match totals {
+                            (Ok(total_nonce), Ok(total_excess)) => (total_nonce, total_excess),
+                            _ => {
+                                reply(&mut comm, &mut session, Error::ConversionError);
+                                continue;
+                            },
+                        };
+                        let mut fee = [0u8; 8];
+                        fee.clone_from_slice(&data[64..72]);
+                        let fee = u64::from_le_bytes(fee);
+                        let mut lock_height = [0u8; 8];
+                        lock_height.clone_from_slice(&data[72..80]);
+                        let lock_height = u64::from_le_bytes(lock_height);
+                        // The nonce is used up before it signs, so that no second request can sign with it again
+                        let nonce = match nonce_pool.as_mut().and_then(|pool| pool.take(pool_id, nonce_id)) {
+                            Some(nonce) => nonce,
+                            None => {
+                                reply(&mut comm, &mut session, Error::NonceNotIssued);
+                                continue;
+                            },
+                        };
+                        // As for a kernel nonce, spending needs the approved fee and receiving is confirmed here
+                        if inputs > 0 {
+                            if let Err(e) = bind_kernel(&mut approved_transaction, &excess, fee, lock_height) {
+                                reply(&mut comm, &mut session, e);
+                                continue;
+                            }
+                            sign_bound_kernel(&mut approved_transaction, &RistrettoPublicKey::from_secret_key(&excess));
+                        } else {
+                            let confirmed = confirm_kernel(outputs, fee, lock_height);
+                            ui::SingleMessage::new("Tari test app").show();
+                            if !confirmed {
+                                reply(&mut comm, &mut session, Error::UserRejected);
+                                continue;
+                            }
+                        }
+
+                        count_signature();
+                        let message = kernel_message(fee, lock_height);
+                        let s = PendingKernel::pooled(excess, message, nonce).sign(&total_nonce, &total_excess);
+                        comm.append(&[RESPONSE_FORMAT_VERSION]); // version
+                        comm.append(s.as_bytes());
+                        reply(&mut comm, &mut session, Reply(SW_OK));
+                    },
+                    P1_NONCE_POOL_INVALIDATE => {
+                        nonce_pool = None;
+                        comm.append(&[RESPONSE_FORMAT_VERSION]); // version
+                        reply(&mut comm, &mut session, Reply(SW_OK));
+                    },
+                    _ => reply(&mut comm, &mut session, Error::ConversionError),
+                }
+            },
+            io::Event::Command(Instruction::AppSettings) => {
+                // first bytes are instruction details
+                let offset = APDU_HEADER_LENGTH;
+                match comm.get_p1() {
+                    P1_SETTINGS_GET => {
+                        comm.append(&[RESPONSE_FORMAT_VERSION]); // version
+                        comm.append(&[settings(), SETTINGS_HOST_ENABLED]);
+                        reply(&mut comm, &mut session, Reply(SW_OK));
+                    },
+                    P1_SETTINGS_SET => {
+                        let data = comm.get(offset, offset + SET_SETTINGS_REQUEST_LENGTH);
+                        let (requested, mask) = (data[0], data[1]);
+                        let current = settings();
+                        let updated = (current & !mask) | (requested & mask);
+                        // Expert mode is only ever turned on from the settings menu
+                        if mask & !SETTINGS_ALL != 0 || updated & !current & !SETTINGS_HOST_ENABLED != 0 {
+                            reply(&mut comm, &mut session, Error::ConversionError);
+                            continue;
+                        }
+                        if !confirm_settings(updated) {
+                            reply(&mut comm, &mut session, Error::UserRejected);
+                            continue;
+                        }
+                        comm.append(&[RESPONSE_FORMAT_VERSION]); // version
+                        comm.append(&[settings()]);
+                        reply(&mut comm, &mut session, Reply(SW_OK));
+                    },
+                    _ => reply(&mut comm, &mut session, Error::ConversionError),
+                }
             },
             io::Event::Ticker => {},
         }
     }
 }
+
+/// Send the response in `comm`, with a MAC when a session is open
+fn reply<R: Into<Reply>>(comm: &mut io::Comm, session: &mut Option<SecureSession>, status: R) {
+    let status = status.into();
+    if let Some(session) = session.as_mut() {
+        session.append_response_mac(comm, status.0);
+    }
+    comm.reply(status);
+}
+
+/// Walk the user through the transaction totals and ask for a single confirmation covering all of its outputs. With
+/// display hints the pages the host announced follow, then the digest to compare with the one the host shows.
+fn confirm_transaction(summary: &TransactionSummary, hints: Option<&DisplayHints>) -> bool {
+    ui::SingleMessage::new(&format!("Send {}", format_amount(summary.total_out))).show_and_wait();
+    ui::SingleMessage::new(&format!("To {} recipient(s)", summary.recipient_count)).show_and_wait();
+    ui::SingleMessage::new(&format!("Fee {}", format_amount(summary.fee))).show_and_wait();
+    if let Some(hints) = hints {
+        for title in hints.titles() {
+            ui::SingleMessage::new(title).show_and_wait();
+        }
+        let fingerprint = hints.fingerprint();
+        ui::SingleMessage::new("Compare digest").show_and_wait();
+        ui::SingleMessage::new(core::str::from_utf8(&fingerprint).unwrap_or("")).show_and_wait();
+    }
+    ui::Validator::new("Sign transaction?").ask()
+}
+
+/// Parse a `[kind][value][session nonce][challenge]` output and count it against the approved transaction, returning
+/// its kind, value and challenge. Anything unexpected voids the approval, the host has to start over with a new
+/// summary.
+fn approve_output(
+    approved_transaction: &mut Option<ApprovedTransaction>,
+    data: &[u8],
+) -> Result<(u8, u64, [u8; 32]), Error> {
+    let kind = data[0];
+    let mut value_bytes = [0u8; 8];
+    value_bytes.clone_from_slice(&data[1..9]);
+    let value = u64::from_le_bytes(value_bytes);
+    let mut nonce_bytes = [0u8; 8];
+    nonce_bytes.clone_from_slice(&data[9..17]);
+    let mut challenge = [0u8; 32];
+    challenge.clone_from_slice(&data[17..17 + SIGN_CHALLENGE_LENGTH]);
+
+    let result = match approved_transaction.as_mut() {
+        Some(transaction) => transaction.consume(u64::from_le_bytes(nonce_bytes), kind, value, &challenge),
+        None => Err(Error::TransactionNotApproved),
+    };
+    if let Err(e) = result {
+        *approved_transaction = None;
+        return Err(e);
+    }
+    if approved_transaction.as_ref().map(|t| t.is_finished()).unwrap_or(false) {
+        *approved_transaction = None;
+    }
+    Ok((kind, value, challenge))
+}
+
+/// Allow a kernel nonce for `excess` that spends inputs under the approved transaction, with its fee and the lock
+/// height and excess of the first kernel taken under it
+fn bind_kernel(
+    approved_transaction: &mut Option<ApprovedTransaction>,
+    excess: &RistrettoSecretKey,
+    fee: u64,
+    lock_height: u64,
+) -> Result<(), Error> {
+    let public_excess = public_key_bytes(&RistrettoPublicKey::from_secret_key(excess));
+    match approved_transaction.as_mut() {
+        Some(transaction) => transaction.bind_kernel(fee, lock_height, &public_excess),
+        None => Err(Error::TransactionNotApproved),
+    }
+}
+
+/// Use up the approval of the kernel with `public_excess` if it spends under it, and drop the approval once nothing is
+/// left to sign under it. A kernel that only receives was confirmed by the user and leaves the approval alone.
+fn sign_bound_kernel(approved_transaction: &mut Option<ApprovedTransaction>, public_excess: &RistrettoPublicKey) {
+    if let Some(transaction) = approved_transaction.as_mut() {
+        if transaction.sign_kernel(&public_key_bytes(public_excess)) && transaction.is_finished() {
+            *approved_transaction = None;
+        }
+    }
+}
+
+fn public_key_bytes(key: &RistrettoPublicKey) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    bytes.copy_from_slice(key.as_bytes());
+    bytes
+}
+
+/// `value` in the unit the user chose, microTari or whole Tari with all six decimals so that no amount is ever rounded
+fn format_amount(value: u64) -> String {
+    if is_enabled(SETTING_MICRO_TARI) {
+        format!("{} uT", value)
+    } else {
+        format!("{}.{:06} T", value / 1_000_000, value % 1_000_000)
+    }
+}
+
+/// Show the value of an output and, unless it is change, the address it pays, 16 hex digits per page, and ask the
+/// user to confirm it
+fn confirm_output(kind: u8, value: u64, address: &[u8]) -> bool {
+    let amount = format_amount(value);
+    if kind == OUTPUT_KIND_CHANGE {
+        ui::SingleMessage::new(&format!("Change {}", amount)).show_and_wait();
+        return true;
+    }
+    ui::SingleMessage::new(&format!("Send {}", amount)).show_and_wait();
+    ui::SingleMessage::new("To address").show_and_wait();
+    const HEX: &[u8; 16] = b"0123456789abcdef";
+    for chunk in address.chunks(8) {
+        let mut line = [0u8; 16];
+        for (i, byte) in chunk.iter().enumerate() {
+            line[2 * i] = HEX[usize::from(byte >> 4)];
+            line[2 * i + 1] = HEX[usize::from(byte & 0x0f)];
+        }
+        ui::SingleMessage::new(core::str::from_utf8(&line[..2 * chunk.len()]).unwrap_or("")).show_and_wait();
+    }
+    ui::Validator::new("Sign output?").ask()
+}
+
+/// Show the fee and lock height of a kernel receiving into `outputs` outputs, and ask the user to confirm it
+fn confirm_kernel(outputs: u8, fee: u64, lock_height: u64) -> bool {
+    ui::SingleMessage::new(&format!("Receive {} output(s)", outputs)).show_and_wait();
+    ui::SingleMessage::new(&format!("Fee {}", format_amount(fee))).show_and_wait();
+    ui::SingleMessage::new(&format!("Lock height {}", lock_height)).show_and_wait();
+    ui::Validator::new("Sign kernel?").ask()
+}
+
+/// The key at `DEFAULT_BIP32_PATH`
+fn app_secret_key() -> RistrettoSecretKey {
+    derive_secret_key(&nanos_sdk::ecc::make_bip32_path(DEFAULT_BIP32_PATH))
+}
+
+/// `m/44'/535348'/account'/branch/index`
+fn branch_key_path(account: u32, branch: KeyBranch, index: u32) -> [u32; 5] {
+    [
+        BIP44_PURPOSE | HARDENED,
+        TARI_COIN_TYPE | HARDENED,
+        account | HARDENED,
+        u32::from(branch.as_byte()),
+        index,
+    ]
+}
+
+/// The excess `Σ output masks - Σ input masks` over the commitment masks of account 0 at `indices`, little-endian
+/// `u32`s of the `inputs` spent followed by the outputs created, or `None` if any index is hardened
+fn kernel_excess(indices: &[u8], inputs: u8, version: DerivationVersion) -> Option<RistrettoSecretKey> {
+    let mut excess = RistrettoSecretKey::default();
+    for (n, bytes) in indices.chunks(4).enumerate() {
+        let mut index_bytes = [0u8; 4];
+        index_bytes.clone_from_slice(bytes);
+        let index = u32::from_le_bytes(index_bytes);
+        if index >= HARDENED {
+            return None;
+        }
+        let k = derive_versioned_key(&branch_key_path(0, KeyBranch::CommitmentMask, index), version);
+        excess = if n < usize::from(inputs) {
+            &excess - &k
+        } else {
+            &excess + &k
+        };
+    }
+    Some(excess)
+}
+
+/// `m/44'/535348'/account'/branch/0`, the branch being the one of `key`
+fn sensitive_key_path(account: u32, key: SensitiveKey) -> [u32; 5] {
+    [
+        BIP44_PURPOSE | HARDENED,
+        TARI_COIN_TYPE | HARDENED,
+        account | HARDENED,
+        u32::from(key.as_byte()),
+        0,
+    ]
+}
+
+fn derive_secret_key(path: &[u32; 5]) -> RistrettoSecretKey {
+    RistrettoSecretKey::from_bytes(&derive_node_key(path)).unwrap()
+}
+
+/// The secret key at `path` under `version` of the derivation scheme
+fn derive_versioned_key(path: &[u32; 5], version: DerivationVersion) -> RistrettoSecretKey {
+    match version {
+        DerivationVersion::Legacy => derive_secret_key(path),
+        DerivationVersion::Wide => {
+            let node_key = derive_node_key(path);
+            let mut wide = [0u8; 64];
+            for (counter, half) in (0u8..).zip(wide.chunks_mut(32)) {
+                let hash = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(DERIVED_KEY_LABEL)
+                    .chain(&node_key)
+                    .chain(&counter)
+                    .finalize();
+                half.copy_from_slice(&hash);
+            }
+            RistrettoSecretKey::from_bytes(Scalar::from_bytes_mod_order_wide(&wide).as_bytes()).unwrap()
+        },
+    }
+}
+
+/// The raw BIP32 node key at `path`
+fn derive_node_key(path: &[u32; 5]) -> [u8; 32] {
+    let mut raw_key = [0u8; 32];
+    unsafe {
+        os_perso_derive_node_bip32(
+            CurvesId::Ed25519 as u8,
+            path.as_ptr(),
+            path.len() as u32,
+            (&mut raw_key).as_mut_ptr(),
+            core::ptr::null_mut(),
+        )
+    };
+    raw_key
+}
+
+/// The atomic swap preimage for `swap_id`. It is derived from the app key so that it never has to be stored, and can
+/// be recovered on any device holding the same seed.
+fn swap_preimage(k: &RistrettoSecretKey, swap_id: &[u8; 32]) -> [u8; 32] {
+    DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(SWAP_PREIMAGE_LABEL)
+        .chain(k)
+        .chain(swap_id)
+        .finalize()
+}
+
+/// Sign the script challenge over `challenge` with the app key, returning the public key alongside the signature
+fn sign_script_challenge(challenge: &[u8; 32]) -> (RistrettoPublicKey, RistrettoSchnorr) {
+    sign_script_challenge_with(&app_secret_key(), challenge)
+}
+
+/// Sign the script challenge over `challenge` with `k`, returning the public key alongside the signature. The nonce
+/// is derived from the key and the challenge, so signing another challenge never reuses it.
+fn sign_script_challenge_with(k: &RistrettoSecretKey, challenge: &[u8; 32]) -> (RistrettoPublicKey, RistrettoSchnorr) {
+    let public_key = RistrettoPublicKey::from_secret_key(k);
+    let n = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(SCRIPT_NONCE_LABEL)
+        .chain(k)
+        .chain(&public_key)
+        .chain(challenge)
+        .finalize();
+    let n = RistrettoSecretKey::from_bytes(&n).unwrap();
+    let public_nonce = RistrettoPublicKey::from_secret_key(&n);
+    let hash = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(SCRIPT_CHALLENGE_LABEL)
+        .chain(&public_key)
+        .chain(&public_nonce)
+        .chain(challenge)
+        .finalize();
+    let signature = RistrettoSchnorr::sign_raw(k, n, &hash).unwrap();
+    (public_key, signature)
+}
+
+/// Sign the sender half of the metadata signature over `message` with the sender offset key `k`, returning the public
+/// key alongside the signature. The nonce is derived from the key and everything signed, so it is never reused for
+/// another message.
+fn sign_sender_offset(
+    k: &RistrettoSecretKey,
+    commitment: &RistrettoPublicKey,
+    ephemeral_commitment: &RistrettoPublicKey,
+    message: &[u8; 32],
+) -> (RistrettoPublicKey, RistrettoSchnorr) {
+    let n = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(SENDER_OFFSET_NONCE_LABEL)
+        .chain(k)
+        .chain(commitment)
+        .chain(ephemeral_commitment)
+        .chain(message)
+        .finalize();
+    let n = RistrettoSecretKey::from_bytes(&n).unwrap();
+    let public_key = RistrettoPublicKey::from_secret_key(k);
+    let public_nonce = RistrettoPublicKey::from_secret_key(&n);
+    let hash = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(METADATA_SIGNATURE_LABEL)
+        .chain(ephemeral_commitment)
+        .chain(&public_nonce)
+        .chain(&public_key)
+        .chain(commitment)
+        .chain(message)
+        .finalize();
+    let signature = RistrettoSchnorr::sign_raw(k, n, &hash).unwrap();
+    (public_key, signature)
+}
+
+/// The version of this app
+fn app_version() -> SemanticVersion {
+    SemanticVersion::new(
+        env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap_or(0),
+        env!("CARGO_PKG_VERSION_MINOR").parse().unwrap_or(0),
+        env!("CARGO_PKG_VERSION_PATCH").parse().unwrap_or(0),
+    )
+}
+
 pub struct DomainSeparatedConsensusHasher<M>(PhantomData<M>);
 
 impl<M: DomainSeparation> DomainSeparatedConsensusHasher<M> {
     #[allow(clippy::new_ret_no_self)]
     pub fn new(label: &'static str) -> ConsensusHasher<Blake256> {
-        let mut digest = Blake256::new();
+        Self::with_digest(label)
+    }
+
+    /// A hasher over the digest `D`, whose output the truncation policy `P` cuts down to 32 bytes
+    pub fn with_digest<D, P>(label: &'static str) -> ConsensusHasher<D, P>
+    where
+        D: Digest,
+        P: Truncation<D::OutputSize>,
+    {
+        let mut digest = D::new();
         M::add_domain_separation_tag(&mut digest, label);
         ConsensusHasher::from_digest(digest)
     }
 }
 
-use digest::{consts::U32, Digest};
+use digest::{
+    consts::U32,
+    generic_array::{ArrayLength, GenericArray},
+    Digest,
+};
+
+/// How the output of a digest is cut down to the 32 bytes of a hash. Every label is hashed with Blake256, the host
+/// has the policies for the wider digests a label may move to.
+pub trait Truncation<N: ArrayLength<u8>> {
+    fn truncate(digest: GenericArray<u8, N>) -> [u8; 32];
+}
+
+/// A 32-byte digest, taken as is
+#[derive(Clone, Copy)]
+pub struct Exact;
+
+impl Truncation<U32> for Exact {
+    fn truncate(digest: GenericArray<u8, U32>) -> [u8; 32] {
+        digest.into()
+    }
+}
+
 #[derive(Clone)]
-pub struct ConsensusHasher<D> {
+pub struct ConsensusHasher<D, P = Exact> {
     writer: WriteHashWrapper<D>,
+    truncation: PhantomData<P>,
 }
 
-impl<D: Digest> ConsensusHasher<D> {
+impl<D: Digest, P> ConsensusHasher<D, P> {
     fn from_digest(digest: D) -> Self {
         Self {
             writer: WriteHashWrapper(digest),
+            truncation: PhantomData,
         }
     }
 }
 
-impl<D> ConsensusHasher<D>
-where D: Digest<OutputSize = U32>
+impl<D, P> ConsensusHasher<D, P>
+where
+    D: Digest,
+    P: Truncation<D::OutputSize>,
 {
     pub fn finalize(self) -> [u8; 32] {
-        self.writer.0.finalize().into()
+        P::truncate(self.writer.0.finalize())
     }
 
     pub fn update_consensus_encode<T: BorshSerialize>(&mut self, data: &T) {
@@ -0,0 +1,74 @@
+use nanos_sdk::{nvm::AtomicStorage, NVMData};
+use nanos_ui::ui;
+use tari_ledger_protocol::{pairing_words, PAIRING_PROOF_LABEL, PAIRING_RESPONSE_LABEL, PAIRING_WORD_LABEL};
+
+use crate::{DomainSeparatedConsensusHasher, TransactionHashDomain};
+
+/// The pairing secret of the host this app was paired with, all zeros until it is paired. It lives in flash so that
+/// the pairing survives restarts.
+#[link_section = ".nvm_data"]
+static mut PAIRING_SECRET: NVMData<AtomicStorage<[u8; 32]>> = NVMData::new(AtomicStorage::new(&[0u8; 32]));
+
+fn pairing_secret() -> Option<[u8; 32]> {
+    let secret = unsafe { *PAIRING_SECRET.get_ref().get_ref() };
+    if secret == [0u8; 32] {
+        None
+    } else {
+        Some(secret)
+    }
+}
+
+/// Unmask the secret the host sent, show its pairing words and, once the user confirms them, pair with the host in
+/// place of any earlier host
+pub fn register(masked: &[u8], mask: &[u8; 32]) -> bool {
+    let mut secret = [0u8; 32];
+    for ((s, m), k) in secret.iter_mut().zip(masked).zip(mask) {
+        *s = m ^ k;
+    }
+    ui::SingleMessage::new("Pairing words").show_and_wait();
+    show_words(&secret);
+    if !ui::Validator::new("Pair with host?").ask() {
+        return false;
+    }
+    unsafe { PAIRING_SECRET.get_mut().update(&secret) };
+    true
+}
+
+/// Check the host's proof for the session `challenge`, returning the app's proof in turn. The user sees the pairing
+/// words if the host is the one this app was paired with, and a warning otherwise.
+pub fn verify(proof: &[u8], challenge: &[u8; 32]) -> Option<[u8; 32]> {
+    let secret = pairing_secret().filter(|secret| {
+        let expected = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(PAIRING_PROOF_LABEL)
+            .chain(secret)
+            .chain(challenge)
+            .finalize();
+        expected == proof
+    });
+    match secret {
+        Some(secret) => {
+            ui::SingleMessage::new("Paired host").show_and_wait();
+            show_words(&secret);
+            Some(
+                DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(PAIRING_RESPONSE_LABEL)
+                    .chain(&secret)
+                    .chain(challenge)
+                    .finalize(),
+            )
+        },
+        None => {
+            // Whatever is driving the device now does not hold the secret of the paired host
+            ui::SingleMessage::new("Unknown host!").show_and_wait();
+            None
+        },
+    }
+}
+
+/// One word per page, so that the longest word fits the smallest screen
+fn show_words(secret: &[u8; 32]) {
+    let digest = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(PAIRING_WORD_LABEL)
+        .chain(secret)
+        .finalize();
+    for word in pairing_words(&digest) {
+        ui::SingleMessage::new(word).show_and_wait();
+    }
+}
@@ -0,0 +1,108 @@
+use nanos_sdk::{io, random::LedgerRng};
+use rand_core::RngCore;
+use tari_crypto::{
+    keys::PublicKey,
+    ristretto::{RistrettoPublicKey, RistrettoSecretKey},
+    tari_utilities::ByteArray,
+};
+use tari_ledger_protocol::{
+    APDU_HEADER_LENGTH,
+    MAC_DIRECTION_COMMAND,
+    MAC_DIRECTION_RESPONSE,
+    PAIRING_SECRET_LABEL,
+    SESSION_AUTH_LABEL,
+    SESSION_KEY_LABEL,
+    SESSION_MAC_LABEL,
+    SESSION_MAC_LENGTH,
+};
+
+use crate::{DomainSeparatedConsensusHasher, TransactionHashDomain};
+
+/// An authenticated session opened by `Instruction::OpenSession`. Every command must carry a MAC under the session
+/// key and every response gets one, so that nothing between the host and the app can alter either unnoticed.
+pub struct SecureSession {
+    key: [u8; 32],
+    counter: u32,
+    challenge: [u8; 32],
+}
+
+impl SecureSession {
+    /// Agree on a session key with the host's ephemeral key. Returns the session, the app's ephemeral public key and
+    /// the challenge the app key has to sign so the host knows which device it is talking to.
+    pub fn open(app_key: &RistrettoSecretKey, host_key: &RistrettoPublicKey) -> (Self, RistrettoPublicKey, [u8; 32]) {
+        // Bytes of the device RNG make the ephemeral key fresh, and the app key and the host's fresh key keep it from
+        // repeating should the RNG fail
+        let mut entropy = [0u8; 32];
+        LedgerRng.fill_bytes(&mut entropy);
+        let ephemeral = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(SESSION_KEY_LABEL)
+            .chain(app_key)
+            .chain(host_key)
+            .chain(&entropy)
+            .finalize();
+        let ephemeral = RistrettoSecretKey::from_bytes(&ephemeral).unwrap();
+        let ephemeral_public_key = RistrettoPublicKey::from_secret_key(&ephemeral);
+        let shared_secret = host_key * &ephemeral;
+
+        let key = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(SESSION_KEY_LABEL)
+            .chain(&shared_secret)
+            .chain(host_key)
+            .chain(&ephemeral_public_key)
+            .finalize();
+        let challenge = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(SESSION_AUTH_LABEL)
+            .chain(host_key)
+            .chain(&ephemeral_public_key)
+            .finalize();
+        let session = Self {
+            key,
+            counter: 0,
+            challenge,
+        };
+        (session, ephemeral_public_key, challenge)
+    }
+
+    /// The challenge the app signed to open the session, fresh for every session
+    pub fn challenge(&self) -> &[u8; 32] {
+        &self.challenge
+    }
+
+    /// The mask of a pairing secret the host sends in this session
+    pub fn pairing_mask(&self) -> [u8; 32] {
+        DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(PAIRING_SECRET_LABEL)
+            .chain(&self.key)
+            .finalize()
+    }
+
+    /// Check the MAC at the end of the command in `comm`
+    pub fn verify_command(&self, comm: &io::Comm) -> bool {
+        let length = comm.rx;
+        if length < APDU_HEADER_LENGTH + SESSION_MAC_LENGTH {
+            return false;
+        }
+        let (apdu, mac) = comm.apdu_buffer[..length].split_at(length - SESSION_MAC_LENGTH);
+        let mut header = [0u8; 4];
+        header.clone_from_slice(&apdu[..4]);
+        // Not constant time, but a wrong MAC closes the session so it cannot be guessed byte by byte
+        self.mac(MAC_DIRECTION_COMMAND, &header, &apdu[APDU_HEADER_LENGTH..]) == mac
+    }
+
+    /// Append the MAC of the response in `comm` with status word `sw`, and move on to the next exchange
+    pub fn append_response_mac(&mut self, comm: &mut io::Comm, sw: u16) {
+        let [sw1, sw2] = sw.to_be_bytes();
+        let mac = self.mac(MAC_DIRECTION_RESPONSE, &[sw1, sw2, 0, 0], &comm.apdu_buffer[..comm.tx]);
+        comm.append(&mac);
+        self.counter = self.counter.wrapping_add(1);
+    }
+
+    fn mac(&self, direction: u8, header: &[u8; 4], data: &[u8]) -> [u8; SESSION_MAC_LENGTH] {
+        let hash = DomainSeparatedConsensusHasher::<TransactionHashDomain>::new(SESSION_MAC_LABEL)
+            .chain(&self.key)
+            .chain(&self.counter)
+            .chain(&direction)
+            .chain(header)
+            .chain(&data)
+            .finalize();
+        let mut mac = [0u8; SESSION_MAC_LENGTH];
+        mac.clone_from_slice(&hash[..SESSION_MAC_LENGTH]);
+        mac
+    }
+}
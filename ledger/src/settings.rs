@@ -0,0 +1,57 @@
+use nanos_sdk::{nvm::AtomicStorage, NVMData};
+use nanos_ui::ui;
+use tari_ledger_protocol::{SETTING_BLIND_SIGNING, SETTING_EXPERT_MODE, SETTING_MICRO_TARI};
+
+/// Every setting with the questions that enable and disable it
+const SETTING_PROMPTS: [(u8, &str, &str); 3] = [
+    (SETTING_BLIND_SIGNING, "Enable blind signing?", "Disable blind signing?"),
+    (SETTING_EXPERT_MODE, "Enable expert mode?", "Disable expert mode?"),
+    (SETTING_MICRO_TARI, "Show microTari?", "Show Tari?"),
+];
+
+/// The `SETTING_*` flags the user enabled. They live in flash so that they survive restarts, and start out disabled.
+#[link_section = ".nvm_data"]
+static mut SETTINGS: NVMData<AtomicStorage<u8>> = NVMData::new(AtomicStorage::new(&0));
+
+pub fn settings() -> u8 {
+    unsafe { *SETTINGS.get_ref().get_ref() }
+}
+
+pub fn is_enabled(setting: u8) -> bool {
+    settings() & setting == setting
+}
+
+/// Ask the user about every setting in turn and store the answers
+pub fn show_settings_menu() {
+    let mut settings = 0;
+    if ui::Validator::new("Blind signing?").ask() {
+        settings |= SETTING_BLIND_SIGNING;
+    }
+    if ui::Validator::new("Expert mode?").ask() {
+        settings |= SETTING_EXPERT_MODE;
+    }
+    if ui::Validator::new("Show microTari?").ask() {
+        settings |= SETTING_MICRO_TARI;
+    }
+    store_settings(settings);
+}
+
+/// Ask the user to confirm every setting `requested` changes, and store it if they confirm all of them
+pub fn confirm_settings(requested: u8) -> bool {
+    let current = settings();
+    let confirmed = SETTING_PROMPTS
+        .iter()
+        .filter(|(setting, _, _)| (current ^ requested) & setting != 0)
+        .all(|(setting, enable, disable)| {
+            let prompt = if requested & setting != 0 { enable } else { disable };
+            ui::Validator::new(prompt).ask()
+        });
+    if confirmed {
+        store_settings(requested);
+    }
+    confirmed
+}
+
+fn store_settings(settings: u8) {
+    unsafe { SETTINGS.get_mut().update(&settings) };
+}
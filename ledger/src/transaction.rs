@@ -0,0 +1,129 @@
+use tari_ledger_protocol::{TransactionSummary, OUTPUT_KIND_CHANGE, OUTPUT_KIND_RECIPIENT};
+
+use crate::{
+    display::{next_digest, summary_digest},
+    errors::Error,
+};
+
+/// What is left of a transaction the user has confirmed. Every `SignOutput` request is counted against it, so the host
+/// can never get more signatures, or send more value to recipients, than was shown on screen. It lasts until every
+/// output is signed and so is the kernel that spends the inputs, whichever comes last.
+pub struct ApprovedTransaction {
+    session_nonce: u64,
+    remaining_outputs: u8,
+    remaining_recipients: u8,
+    remaining_value: u64,
+    fee: u64,
+    output_count: u8,
+    script_offset_taken: bool,
+    /// The sender offset signatures taken so far, at most one for each output
+    sender_offsets_taken: u8,
+    /// The lock height and public excess of the kernel, fixed by the first kernel nonce taken under the approval
+    kernel: Option<(u64, [u8; 32])>,
+    kernel_signed: bool,
+    /// The digest announced with the display hints, and the digest of the outputs signed so far
+    display_digest: Option<([u8; 32], [u8; 32])>,
+}
+
+impl ApprovedTransaction {
+    /// `expected_digest` is the digest of the display hints shown with the summary, if any
+    pub fn new(summary: &TransactionSummary, expected_digest: Option<[u8; 32]>) -> Self {
+        Self {
+            session_nonce: summary.session_nonce,
+            remaining_outputs: summary.output_count,
+            remaining_recipients: summary.recipient_count,
+            remaining_value: summary.total_out,
+            fee: summary.fee,
+            output_count: summary.output_count,
+            script_offset_taken: false,
+            sender_offsets_taken: 0,
+            kernel: None,
+            kernel_signed: false,
+            display_digest: expected_digest.map(|expected| (expected, summary_digest(summary))),
+        }
+    }
+
+    /// Account for one output of `kind` paying `value` under `challenge`, failing if it belongs to another session or
+    /// falls outside what the user approved. The last output is refused if the outputs do not match the display hints.
+    pub fn consume(&mut self, session_nonce: u64, kind: u8, value: u64, challenge: &[u8; 32]) -> Result<(), Error> {
+        if session_nonce != self.session_nonce || self.remaining_outputs == 0 {
+            return Err(Error::TransactionNotApproved);
+        }
+        match kind {
+            OUTPUT_KIND_RECIPIENT => {
+                if self.remaining_recipients == 0 || value > self.remaining_value {
+                    return Err(Error::TransactionNotApproved);
+                }
+                // Only the change outputs may remain once the last recipient has been signed
+                if self.remaining_recipients == 1 && value != self.remaining_value {
+                    return Err(Error::TransactionNotApproved);
+                }
+                self.remaining_recipients -= 1;
+                self.remaining_value -= value;
+            },
+            OUTPUT_KIND_CHANGE => {
+                if self.remaining_outputs <= self.remaining_recipients {
+                    return Err(Error::TransactionNotApproved);
+                }
+            },
+            _ => return Err(Error::ConversionError),
+        }
+        self.remaining_outputs -= 1;
+        if let Some((expected, digest)) = self.display_digest.as_mut() {
+            *digest = next_digest(digest, kind, value, challenge);
+            if self.remaining_outputs == 0 && digest != expected {
+                return Err(Error::TransactionNotApproved);
+            }
+        }
+        Ok(())
+    }
+
+    /// Allow the one script offset of the transaction, over `outputs` sender offset keys. Every output needs one, and a
+    /// second offset would reveal the difference of the keys that differ between the two requests.
+    pub fn take_script_offset(&mut self, session_nonce: u64, outputs: u8) -> Result<(), Error> {
+        if session_nonce != self.session_nonce || outputs != self.output_count || self.script_offset_taken {
+            return Err(Error::TransactionNotApproved);
+        }
+        self.script_offset_taken = true;
+        Ok(())
+    }
+
+    /// Allow one more sender offset signature, one for each output the user approved
+    pub fn take_sender_offset(&mut self) -> Result<(), Error> {
+        if self.sender_offsets_taken == self.output_count {
+            return Err(Error::TransactionNotApproved);
+        }
+        self.sender_offsets_taken += 1;
+        Ok(())
+    }
+
+    /// Allow a nonce for the kernel that spends the inputs, which has to pay the fee the user approved. The first one
+    /// fixes its lock height and `public_excess`, so that a later nonce can only be for the same kernel, and none is
+    /// allowed once it is signed.
+    pub fn bind_kernel(&mut self, fee: u64, lock_height: u64, public_excess: &[u8; 32]) -> Result<(), Error> {
+        if fee != self.fee || self.kernel_signed {
+            return Err(Error::TransactionNotApproved);
+        }
+        match self.kernel {
+            Some(kernel) if kernel != (lock_height, *public_excess) => Err(Error::TransactionNotApproved),
+            _ => {
+                self.kernel = Some((lock_height, *public_excess));
+                Ok(())
+            },
+        }
+    }
+
+    /// Use up the approval of the kernel with `public_excess`, and whether it was the one bound to the approval
+    pub fn sign_kernel(&mut self, public_excess: &[u8; 32]) -> bool {
+        if self.kernel_signed || self.kernel.map(|(_, excess)| excess) != Some(*public_excess) {
+            return false;
+        }
+        self.kernel_signed = true;
+        true
+    }
+
+    /// Whether every output and the kernel are signed, and nothing more can be signed under the approval
+    pub fn is_finished(&self) -> bool {
+        self.remaining_outputs == 0 && self.kernel_signed
+    }
+}
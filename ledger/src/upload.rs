@@ -0,0 +1,71 @@
+#[cfg(not(target_os = "nanos"))]
+use tari_ledger_protocol::MAX_UPLOAD_LENGTH;
+#[cfg(target_os = "nanos")]
+use tari_ledger_protocol::MAX_UPLOAD_LENGTH_NANO_S as MAX_UPLOAD_LENGTH;
+use tari_ledger_protocol::{crc32, CHUNK_SEQUENCE_LENGTH, UPLOAD_CRC_LENGTH};
+
+use crate::errors::Error;
+
+/// Reassembles a framed chunked upload. Every chunk has to arrive in sequence and the payload has to match the CRC it
+/// ends with, so a chunk lost or reordered on the way fails the upload instead of changing what the app works on.
+pub struct ChunkedUpload {
+    buffer: [u8; MAX_UPLOAD_LENGTH + UPLOAD_CRC_LENGTH],
+    length: usize,
+    next_sequence: u16,
+}
+
+impl ChunkedUpload {
+    pub const fn new() -> Self {
+        Self {
+            buffer: [0u8; MAX_UPLOAD_LENGTH + UPLOAD_CRC_LENGTH],
+            length: 0,
+            next_sequence: 0,
+        }
+    }
+
+    /// Start over, dropping whatever an earlier upload left behind
+    pub fn start(&mut self) {
+        self.length = 0;
+        self.next_sequence = 0;
+    }
+
+    /// Add the chunk `data`, `[sequence][chunk]`. Once the last chunk is in, returns the CRC of the payload for the
+    /// app to echo back. Any error drops the upload.
+    pub fn receive(&mut self, data: &[u8], last: bool) -> Result<Option<u32>, Error> {
+        let result = self.append(data, last);
+        if result.is_err() {
+            self.start();
+        }
+        result
+    }
+
+    fn append(&mut self, data: &[u8], last: bool) -> Result<Option<u32>, Error> {
+        if data.len() < CHUNK_SEQUENCE_LENGTH {
+            return Err(Error::UploadCorrupted);
+        }
+        let (sequence, chunk) = data.split_at(CHUNK_SEQUENCE_LENGTH);
+        if u16::from_le_bytes([sequence[0], sequence[1]]) != self.next_sequence {
+            return Err(Error::UploadCorrupted);
+        }
+        let end = self.length + chunk.len();
+        if end > self.buffer.len() {
+            return Err(Error::UploadCorrupted);
+        }
+        self.buffer[self.length..end].copy_from_slice(chunk);
+        self.length = end;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        if !last {
+            return Ok(None);
+        }
+
+        if self.length < UPLOAD_CRC_LENGTH {
+            return Err(Error::UploadCorrupted);
+        }
+        let (payload, expected) = self.buffer[..self.length].split_at(self.length - UPLOAD_CRC_LENGTH);
+        let crc = crc32(payload);
+        if crc.to_le_bytes() != expected {
+            return Err(Error::UploadCorrupted);
+        }
+        Ok(Some(crc))
+    }
+}
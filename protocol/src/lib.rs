@@ -0,0 +1,1990 @@
+// Copyright 2023 The Tari Project
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! The wire format shared by the Tari Ledger app and its host clients
+//! Everything that both sides must agree on byte-for-byte lives here: instruction codes, status words, payload
+//! layouts and hash domains. The crate is `no_std` and dependency free so that the embedded app can use it as is.
+
+#![no_std]
+
+use core::{convert::TryFrom, fmt, ops::BitOr, str::FromStr};
+
+/// The APDU class used by the Tari Ledger app
+pub const CLA: u8 = 0x80;
+/// Offset of the payload in a received APDU (CLA, INS, P1, P2, Lc)
+pub const APDU_HEADER_LENGTH: usize = 5;
+/// The largest payload the app accepts in a single APDU. Longer payloads are split into chunks of at most this size.
+pub const MAX_CHUNK_LENGTH: usize = 250;
+
+/// P1 of a chunked upload: the first APDU, carrying the instruction's own data, e.g. a BIP32 path
+pub const P1_CHUNK_INIT: u8 = 0x00;
+/// P1 of every chunk except the last
+pub const P1_CHUNK_ADD: u8 = 0x01;
+/// P1 of the final chunk, the app only replies with a result to this one
+pub const P1_CHUNK_LAST: u8 = 0x02;
+/// With [`Capabilities::FRAMED_UPLOADS`], every chunk after the init APDU starts with its sequence number, a
+/// little-endian `u16` counting from 0, and the payload is followed by its [`crc32`] before it is sliced into chunks.
+/// The app answers a chunk out of sequence, or a payload whose CRC does not match, with `SW_UPLOAD_CORRUPTED` and drops
+/// the upload, and appends the CRC of the payload it reassembled to its response to the last chunk.
+pub const CHUNK_SEQUENCE_LENGTH: usize = 2;
+pub const UPLOAD_CRC_LENGTH: usize = 4;
+/// The longest payload the app reassembles from a framed upload
+pub const MAX_UPLOAD_LENGTH: usize = 512;
+/// [`MAX_UPLOAD_LENGTH`] on the Nano S, which has a fraction of the RAM of the later models
+pub const MAX_UPLOAD_LENGTH_NANO_S: usize = 256;
+
+/// The BIP32 path of the key the app currently signs with
+pub const DEFAULT_BIP32_PATH: &[u8] = b"m/44'/535348'/0'/0/0";
+
+//--------------------------------------------- Hash domains ---------------------------------------------------------//
+
+/// The hash domain used for transaction related challenges
+pub const TRANSACTION_HASH_DOMAIN: &str = "com.tari.base_layer.core.transactions";
+pub const TRANSACTION_HASH_DOMAIN_VERSION: u8 = 0;
+/// The label of the script challenge signed by `Instruction::Sign`
+pub const SCRIPT_CHALLENGE_LABEL: &str = "script_challenge";
+/// The label of the nonce of a script signature, derived from the key and the challenge it signs
+pub const SCRIPT_NONCE_LABEL: &str = "script_nonce";
+/// The labels of the authenticated session key, the challenge the app signs to prove it holds the app key, and the
+/// MAC of every command and response
+pub const SESSION_KEY_LABEL: &str = "session_key";
+pub const SESSION_AUTH_LABEL: &str = "session_auth";
+pub const SESSION_MAC_LABEL: &str = "session_mac";
+/// The labels of the envelope key and tag of `Instruction::ExportPrivateKey`
+pub const ENVELOPE_KEY_LABEL: &str = "envelope_key";
+pub const ENVELOPE_TAG_LABEL: &str = "envelope_tag";
+/// The label of the digest announced by `Instruction::DisplayHints`
+pub const DISPLAY_DIGEST_LABEL: &str = "display_digest";
+/// The labels of the path mask and the blinding factor of `Instruction::GetBlindedPublicKey`
+pub const BLINDED_PATH_LABEL: &str = "blinded_path";
+pub const BLINDED_KEY_LABEL: &str = "blinded_key";
+/// The label atomic swap preimages are derived under
+pub const SWAP_PREIMAGE_LABEL: &str = "swap_preimage";
+/// The labels of the mask of a registered pairing secret, the proofs the host and the app exchange with it, and the
+/// pairing words both of them show, see `Instruction::Pairing`
+pub const PAIRING_SECRET_LABEL: &str = "pairing_secret";
+pub const PAIRING_PROOF_LABEL: &str = "pairing_proof";
+pub const PAIRING_RESPONSE_LABEL: &str = "pairing_response";
+pub const PAIRING_WORD_LABEL: &str = "pairing_word";
+/// The label BIP32 node keys are hashed under by [`DerivationVersion::Wide`]
+pub const DERIVED_KEY_LABEL: &str = "derived_key";
+/// The labels of the nonce the app derives for a sender offset signature and of the challenge of the sender half of
+/// the metadata signature, see `Instruction::SenderOffset`
+pub const SENDER_OFFSET_NONCE_LABEL: &str = "sender_offset_nonce";
+pub const METADATA_SIGNATURE_LABEL: &str = "metadata_signature";
+/// The labels of the message a kernel covers, of the nonce of a partial kernel signature and of the kernel signature
+/// challenge, see `Instruction::KernelSignature`
+pub const KERNEL_MESSAGE_LABEL: &str = "kernel_message";
+pub const KERNEL_NONCE_LABEL: &str = "kernel_nonce";
+pub const KERNEL_SIGNATURE_LABEL: &str = "kernel_signature";
+/// The labels of the seed of a nonce pool and of each nonce drawn from it, see `Instruction::NoncePool`
+pub const NONCE_POOL_SEED_LABEL: &str = "nonce_pool_seed";
+pub const POOLED_NONCE_LABEL: &str = "pooled_nonce";
+/// Every label the app hashes under the transaction hash domain. Changing any of them, or the domain or its version,
+/// invalidates every signature and key derived under it.
+pub const APP_HASH_LABELS: [&str; 23] = [
+    SCRIPT_CHALLENGE_LABEL,
+    SCRIPT_NONCE_LABEL,
+    SESSION_KEY_LABEL,
+    SESSION_AUTH_LABEL,
+    SESSION_MAC_LABEL,
+    ENVELOPE_KEY_LABEL,
+    ENVELOPE_TAG_LABEL,
+    DISPLAY_DIGEST_LABEL,
+    BLINDED_PATH_LABEL,
+    BLINDED_KEY_LABEL,
+    SWAP_PREIMAGE_LABEL,
+    PAIRING_SECRET_LABEL,
+    PAIRING_PROOF_LABEL,
+    PAIRING_RESPONSE_LABEL,
+    PAIRING_WORD_LABEL,
+    DERIVED_KEY_LABEL,
+    SENDER_OFFSET_NONCE_LABEL,
+    METADATA_SIGNATURE_LABEL,
+    KERNEL_MESSAGE_LABEL,
+    KERNEL_NONCE_LABEL,
+    KERNEL_SIGNATURE_LABEL,
+    NONCE_POOL_SEED_LABEL,
+    POOLED_NONCE_LABEL,
+];
+
+//--------------------------------------------- Status words ---------------------------------------------------------//
+
+pub const SW_OK: u16 = 0x9000;
+pub const SW_INCORRECT_BYTE_LENGTH: u16 = 0x69f0;
+pub const SW_INVALID_CHALLENGE: u16 = 0x9210;
+pub const SW_CONVERSION_ERROR: u16 = 0x6a88;
+pub const SW_DECRYPT_FAILED: u16 = 0x9d60;
+/// The app refused the client version sent in `Instruction::ClientVersion`
+pub const SW_CLIENT_VERSION_REJECTED: u16 = 0x6a90;
+/// The app does not know the instruction, e.g. because it predates it
+pub const SW_INS_NOT_SUPPORTED: u16 = 0x6d00;
+/// The user declined the request on the device
+pub const SW_USER_REJECTED: u16 = 0x6985;
+/// `Instruction::SignOutput` or `Instruction::SignConfirmedOutput` was sent without an approved transaction, or for an
+/// output the user did not approve
+pub const SW_TRANSACTION_NOT_APPROVED: u16 = 0x6986;
+/// Reported by the device OS rather than the app while the device is locked
+pub const SW_DEVICE_LOCKED: u16 = 0x5515;
+/// What older device firmware reports while locked
+pub const SW_DEVICE_LOCKED_LEGACY: u16 = 0x6b0c;
+/// "Security status not satisfied", what some device firmware reports while locked. The app never answers with it.
+pub const SW_SECURITY_STATUS_NOT_SATISFIED: u16 = 0x6982;
+/// The request needs a setting the user has not enabled on the device, see the `SETTING_*` flags
+pub const SW_SETTING_DISABLED: u16 = 0x6a91;
+/// `Instruction::Pairing` was sent outside of an authenticated session, or the host could not prove it holds the
+/// pairing secret
+pub const SW_PAIRING_FAILED: u16 = 0x6a92;
+/// A chunk of a framed upload arrived out of sequence, or the reassembled payload does not match its CRC
+pub const SW_UPLOAD_CORRUPTED: u16 = 0x6a93;
+/// A pooled nonce that the app never issued, has already signed with, or dropped with its pool
+pub const SW_NONCE_NOT_ISSUED: u16 = 0x6a94;
+/// A command of an authenticated session had a missing or wrong MAC. The app closes the session.
+pub const SW_SESSION_MAC_FAILED: u16 = 0x6a95;
+/// The daemon does not let the client that sent the command use its class of instructions. The app never answers with
+/// it.
+pub const SW_CLIENT_NOT_PERMITTED: u16 = 0x6a96;
+/// The client that sent the command has used up its quota of commands of the daemon for now. The app never answers
+/// with it.
+pub const SW_CLIENT_QUOTA_EXCEEDED: u16 = 0x6a97;
+/// An approval hook of the daemon, or its operator, refused the command before it reached the device. The app never
+/// answers with it.
+pub const SW_APPROVAL_REFUSED: u16 = 0x6a98;
+
+//--------------------------------------------- Instructions ---------------------------------------------------------//
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    /// Returns the app name and version
+    GetVersion = 0x01,
+    /// Signs a 32-byte challenge with the app key
+    Sign = 0x02,
+    /// Returns a commitment to a `u64` value, masked with the app key
+    Commitment = 0x03,
+    /// Multiplies a bulletproof scalar with the app key
+    BPData = 0x04,
+    /// Exchanges client and app versions
+    ClientVersion = 0x05,
+    /// Shows a [`TransactionSummary`] to the user and, once confirmed, allows its outputs to be signed
+    TransactionSummary = 0x06,
+    /// Signs the script challenge of one output of the approved transaction
+    SignOutput = 0x07,
+    /// Returns the hash lock of an atomic swap, derived on the device from the swap id
+    SwapLock = 0x08,
+    /// Reveals the preimage of a `SwapLock` hash once the user confirms
+    SwapPreimage = 0x09,
+    /// Returns the [`Capabilities`] of the app
+    GetCapabilities = 0x0a,
+    /// Returns a run of consecutive public keys of an account
+    GetPublicKeys = 0x0b,
+    /// Returns commitments to a list of values, each masked with its own key
+    BatchCommitment = 0x0c,
+    /// Returns how many signatures the app has produced since it was installed
+    GetSigningCounter = 0x0d,
+    /// Starts an authenticated session, after which every command and response carries a MAC
+    OpenSession = 0x0e,
+    /// Returns one of the [`SensitiveKey`]s, encrypted to a host key
+    ExportPrivateKey = 0x0f,
+    /// Announces the pages shown for the next transaction summary, for transactions too long to review in full
+    DisplayHints = 0x10,
+    /// Returns a public key without revealing its path or the key itself to anything watching the link
+    GetBlindedPublicKey = 0x11,
+    /// Shows the amount and recipient of one output of the approved transaction and signs its script challenge once
+    /// the user confirms
+    SignConfirmedOutput = 0x12,
+    /// Registers the pairing secret of a host, or checks that the host holds it and shows the pairing words
+    Pairing = 0x13,
+    /// Returns or records the block height the wallet was created at
+    WalletBirthday = 0x14,
+    /// Signs the sender half of a metadata signature with a sender offset key, or returns the script offset of the
+    /// approved transaction, without either key leaving the device
+    SenderOffset = 0x15,
+    /// Takes the excess of a set of commitment masks and signs a kernel with it once
+    KernelSignature = 0x16,
+    /// Issues public nonces ahead of the kernel signatures that use them
+    NoncePool = 0x17,
+    /// Returns the app settings, or changes them once the user confirms
+    AppSettings = 0x18,
+}
+
+impl Instruction {
+    pub const fn as_byte(self) -> u8 {
+        self as u8
+    }
+}
+
+impl TryFrom<u8> for Instruction {
+    type Error = ();
+
+    fn try_from(v: u8) -> Result<Self, Self::Error> {
+        match v {
+            0x01 => Ok(Self::GetVersion),
+            0x02 => Ok(Self::Sign),
+            0x03 => Ok(Self::Commitment),
+            0x04 => Ok(Self::BPData),
+            0x05 => Ok(Self::ClientVersion),
+            0x06 => Ok(Self::TransactionSummary),
+            0x07 => Ok(Self::SignOutput),
+            0x08 => Ok(Self::SwapLock),
+            0x09 => Ok(Self::SwapPreimage),
+            0x0a => Ok(Self::GetCapabilities),
+            0x0b => Ok(Self::GetPublicKeys),
+            0x0c => Ok(Self::BatchCommitment),
+            0x0d => Ok(Self::GetSigningCounter),
+            0x0e => Ok(Self::OpenSession),
+            0x0f => Ok(Self::ExportPrivateKey),
+            0x10 => Ok(Self::DisplayHints),
+            0x11 => Ok(Self::GetBlindedPublicKey),
+            0x12 => Ok(Self::SignConfirmedOutput),
+            0x13 => Ok(Self::Pairing),
+            0x14 => Ok(Self::WalletBirthday),
+            0x15 => Ok(Self::SenderOffset),
+            0x16 => Ok(Self::KernelSignature),
+            0x17 => Ok(Self::NoncePool),
+            0x18 => Ok(Self::AppSettings),
+            _ => Err(()),
+        }
+    }
+}
+
+//--------------------------------------------- Key branches ---------------------------------------------------------//
+
+/// The key trees of the Tari wallet key manager. Keys are derived at `m/44'/535348'/account'/branch/index`, and the
+/// instructions that derive keys carry the branch in P1 so that the host and the app always agree on the tree a key
+/// comes from.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyBranch {
+    /// Blinds output commitments. This is also the branch of the app key, `m/44'/535348'/0'/0/0`.
+    CommitmentMask = 0x00,
+    ScriptKey = 0x01,
+    SenderOffset = 0x02,
+    Nonce = 0x03,
+    KernelNonce = 0x04,
+}
+
+impl KeyBranch {
+    pub const ALL: [Self; 5] = [
+        Self::CommitmentMask,
+        Self::ScriptKey,
+        Self::SenderOffset,
+        Self::Nonce,
+        Self::KernelNonce,
+    ];
+
+    pub const fn as_byte(self) -> u8 {
+        self as u8
+    }
+
+    /// The branch key the Tari wallet key manager uses for this branch
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::CommitmentMask => "commitment mask",
+            Self::ScriptKey => "script key",
+            Self::SenderOffset => "sender offset",
+            Self::Nonce => "nonce",
+            Self::KernelNonce => "kernel nonce",
+        }
+    }
+}
+
+impl TryFrom<u8> for KeyBranch {
+    type Error = ();
+
+    fn try_from(v: u8) -> Result<Self, Self::Error> {
+        Self::ALL.iter().copied().find(|branch| branch.as_byte() == v).ok_or(())
+    }
+}
+
+impl FromStr for KeyBranch {
+    type Err = ();
+
+    /// Accepts the key manager name, with either spaces or underscores, e.g. `commitment_mask`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|branch| matches_name(branch.name(), s))
+            .ok_or(())
+    }
+}
+
+impl fmt::Display for KeyBranch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+//--------------------------------------------- Derivation versions --------------------------------------------------//
+
+/// How the app turns the BIP32 node key at a path into a secret key. The instructions that derive output keys carry the
+/// version in P2, with 0 being the legacy scheme that hosts unaware of versions send, so that outputs created under an
+/// older scheme stay spendable after the app moves on and can be swept into keys of the latest one.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DerivationVersion {
+    /// The 32-byte node key reduced modulo the group order, which slightly biases the keys
+    Legacy = 0x00,
+    /// The node key hashed under [`DERIVED_KEY_LABEL`] into 64 bytes, which reduce to a uniform secret key
+    Wide = 0x01,
+}
+
+impl DerivationVersion {
+    pub const ALL: [Self; 2] = [Self::Legacy, Self::Wide];
+    /// The scheme new outputs are derived with
+    pub const LATEST: Self = Self::Wide;
+
+    pub const fn as_byte(self) -> u8 {
+        self as u8
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Legacy => "legacy",
+            Self::Wide => "wide",
+        }
+    }
+}
+
+impl TryFrom<u8> for DerivationVersion {
+    type Error = ();
+
+    fn try_from(v: u8) -> Result<Self, Self::Error> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|version| version.as_byte() == v)
+            .ok_or(())
+    }
+}
+
+impl FromStr for DerivationVersion {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|version| matches_name(version.name(), s))
+            .ok_or(())
+    }
+}
+
+impl fmt::Display for DerivationVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Keys that give away more than a single output, such as the ability to scan or recover every output of the wallet.
+/// They only ever leave the app encrypted to a host key, see `Instruction::ExportPrivateKey`, and are derived at
+/// `m/44'/535348'/account'/branch/0` on branches past those of [`KeyBranch`].
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SensitiveKey {
+    /// Identifies the outputs that belong to the wallet
+    ViewKey = 0x10,
+    /// Decrypts the value and mask stored with every output, to recover the wallet from the chain
+    RewindKey = 0x11,
+}
+
+impl SensitiveKey {
+    pub const ALL: [Self; 2] = [Self::ViewKey, Self::RewindKey];
+
+    pub const fn as_byte(self) -> u8 {
+        self as u8
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::ViewKey => "view key",
+            Self::RewindKey => "rewind key",
+        }
+    }
+}
+
+impl TryFrom<u8> for SensitiveKey {
+    type Error = ();
+
+    fn try_from(v: u8) -> Result<Self, Self::Error> {
+        Self::ALL.iter().copied().find(|key| key.as_byte() == v).ok_or(())
+    }
+}
+
+impl FromStr for SensitiveKey {
+    type Err = ();
+
+    /// Accepts the name with either spaces or underscores, e.g. `view_key`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|key| matches_name(key.name(), s))
+            .ok_or(())
+    }
+}
+
+impl fmt::Display for SensitiveKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Whether `s` is `name`, ignoring case and with underscores in place of spaces
+fn matches_name(name: &str, s: &str) -> bool {
+    name.len() == s.len() &&
+        name.bytes()
+            .zip(s.bytes())
+            .all(|(n, c)| n == c.to_ascii_lowercase() || (n == b' ' && c == b'_'))
+}
+
+//--------------------------------------------- Payloads -------------------------------------------------------------//
+
+/// The format byte that prefixes every response
+pub const RESPONSE_FORMAT_VERSION: u8 = 1;
+
+/// `Instruction::GetVersion`: the response is `[format][name length][name][version length][version][settings]` where
+/// the settings are the `SETTING_*` flags the user enabled in the app's settings menu. Apps without
+/// [`Capabilities::APP_SETTINGS`] report none, and refuse nothing. Others answer a request that needs a setting the
+/// user has not enabled with `SW_SETTING_DISABLED`.
+pub const SETTINGS_LENGTH: usize = 1;
+/// `Instruction::Sign` signs challenges the device cannot show the user anything about
+pub const SETTING_BLIND_SIGNING: u8 = 1 << 0;
+/// `Instruction::BPData` multiplies any scalar the host sends with the app key
+pub const SETTING_EXPERT_MODE: u8 = 1 << 1;
+/// Amounts are shown in microTari rather than in Tari
+pub const SETTING_MICRO_TARI: u8 = 1 << 2;
+/// Every setting this protocol knows of
+pub const SETTINGS_ALL: u8 = SETTING_BLIND_SIGNING | SETTING_EXPERT_MODE | SETTING_MICRO_TARI;
+
+/// `Instruction::AppSettings`: with `P1_SETTINGS_GET` the response is `[format][settings][host enabled]`, the
+/// `SETTING_*` flags the user enabled and those a host may ask to enable. With `P1_SETTINGS_SET` the request is
+/// `[settings][mask]`: the app shows every setting in `mask` that `settings` changes and asks the user to confirm it,
+/// then stores the flags of `settings` in `mask` and keeps the others. The response is `[format][settings]` with the
+/// flags now enabled. Any setting can be disabled from the host, but only those of `SETTINGS_HOST_ENABLED` enabled,
+/// expert mode has to be turned on in the settings menu on the device. A request to enable any other, or with flags
+/// outside of `SETTINGS_ALL`, is answered with `SW_CONVERSION_ERROR`.
+pub const P1_SETTINGS_GET: u8 = 0x00;
+pub const P1_SETTINGS_SET: u8 = 0x01;
+pub const SETTINGS_HOST_ENABLED: u8 = SETTING_BLIND_SIGNING | SETTING_MICRO_TARI;
+pub const GET_SETTINGS_RESPONSE_LENGTH: usize = 1 + 2 * SETTINGS_LENGTH;
+pub const SET_SETTINGS_REQUEST_LENGTH: usize = 2 * SETTINGS_LENGTH;
+pub const SET_SETTINGS_RESPONSE_LENGTH: usize = 1 + SETTINGS_LENGTH;
+
+/// `Instruction::Sign`: the request is a 32-byte challenge, the response is `[format][public key][s][public nonce]`.
+/// The nonce is `SCRIPT_NONCE_LABEL(key, public key, challenge)`, as for every script signature of the app.
+/// With [`Capabilities::DERIVATION_VERSIONS`], P2 is the [`DerivationVersion`] of the app key to sign with.
+pub const SIGN_CHALLENGE_LENGTH: usize = 32;
+pub const SIGN_RESPONSE_LENGTH: usize = 1 + 3 * 32;
+
+/// `Instruction::Commitment`: the request is a little-endian `u64` value, the response is `[format][commitment]`
+pub const COMMITMENT_VALUE_LENGTH: usize = 8;
+pub const COMMITMENT_RESPONSE_LENGTH: usize = 1 + 32;
+
+/// `Instruction::BPData`: the request is a 32-byte scalar, the response is `[format][scalar]`
+pub const BP_SCALAR_LENGTH: usize = 32;
+pub const BP_RESPONSE_LENGTH: usize = 1 + 32;
+
+/// `Instruction::ClientVersion`: the request is the client [`SemanticVersion`], the response is
+/// `[format][app version][min client version]`
+pub const CLIENT_VERSION_RESPONSE_LENGTH: usize = 1 + 2 * SemanticVersion::ENCODED_LENGTH;
+
+/// `Instruction::TransactionSummary`: with `P1_TRANSACTION_APPROVE` the request is a [`TransactionSummary`], the
+/// response is `[format]` once the user has confirmed. With [`Capabilities::TRANSACTION_CANCEL`] and
+/// `P1_TRANSACTION_CANCEL` the request is empty, the app drops the approved transaction, the display hints sent for the
+/// next one and the kernel it keeps for the next signature, and the response is `[format]`. A host that gives up on a
+/// confirmation, e.g. because the client that asked for it went away, cancels once the user has answered it, so that
+/// nothing approved there is signed for anyone else.
+pub const P1_TRANSACTION_APPROVE: u8 = 0x00;
+pub const P1_TRANSACTION_CANCEL: u8 = 0x01;
+pub const TRANSACTION_SUMMARY_RESPONSE_LENGTH: usize = 1;
+pub const TRANSACTION_CANCEL_RESPONSE_LENGTH: usize = 1;
+
+/// `Instruction::DisplayHints`: sent right before a [`TransactionSummary`] with more outputs than the user can review
+/// on screen. The request is `[digest][page count]` followed by `page count` titles of `DISPLAY_TITLE_LENGTH` bytes of
+/// printable ASCII, zero padded, and the response is `[format]`. After the totals the app shows every title and the
+/// [`digest_fingerprint`] for the user to compare with the host, and refuses to sign the last output of the summary
+/// unless the outputs it signed chain up to the digest: starting from `DISPLAY_DIGEST_LABEL(summary)` every output
+/// in signing order advances it to `DISPLAY_DIGEST_LABEL(digest, kind, value, challenge)`.
+pub const DISPLAY_TITLE_LENGTH: usize = 16;
+pub const MAX_DISPLAY_PAGES: u8 = 8;
+pub const DISPLAY_HINTS_RESPONSE_LENGTH: usize = 1;
+/// `abcd ef01 2345`, the first six bytes of a digest in hex, fits on a single line of the smallest screen
+pub const DIGEST_FINGERPRINT_LENGTH: usize = 14;
+
+pub const fn display_hints_request_length(pages: u8) -> usize {
+    32 + 1 + DISPLAY_TITLE_LENGTH * pages as usize
+}
+
+/// The short form of a display digest that both the host and the app show
+pub fn digest_fingerprint(digest: &[u8; 32]) -> [u8; DIGEST_FINGERPRINT_LENGTH] {
+    const HEX: &[u8; 16] = b"0123456789abcdef";
+    let mut fingerprint = [b' '; DIGEST_FINGERPRINT_LENGTH];
+    for (i, byte) in digest[..6].iter().enumerate() {
+        // Two bytes per group, groups separated by a space
+        let at = i * 2 + i / 2;
+        fingerprint[at] = HEX[usize::from(byte >> 4)];
+        fingerprint[at + 1] = HEX[usize::from(byte & 0x0f)];
+    }
+    fingerprint
+}
+
+/// `text` as a display title. Titles that are too long keep their start and end around `..`, anything outside of
+/// printable ASCII becomes `?`.
+pub fn display_title(text: &str) -> [u8; DISPLAY_TITLE_LENGTH] {
+    let printable = |c: u8| if (0x20..0x7f).contains(&c) { c } else { b'?' };
+    let bytes = text.as_bytes();
+    let mut title = [0u8; DISPLAY_TITLE_LENGTH];
+    if bytes.len() <= DISPLAY_TITLE_LENGTH {
+        for (t, c) in title.iter_mut().zip(bytes) {
+            *t = printable(*c);
+        }
+        return title;
+    }
+    let half = (DISPLAY_TITLE_LENGTH - 2) / 2;
+    let tail = &bytes[bytes.len() - half..];
+    for (t, c) in title.iter_mut().zip(bytes[..half].iter().chain(b"..").chain(tail)) {
+        *t = printable(*c);
+    }
+    title
+}
+
+/// Whether `title` is printable ASCII followed by zero padding, as the app requires
+pub fn is_display_title(title: &[u8]) -> bool {
+    let length = title.iter().position(|c| *c == 0).unwrap_or(title.len());
+    title[..length].iter().all(|c| (0x20..0x7f).contains(c)) && title[length..].iter().all(|c| *c == 0)
+}
+
+/// `Instruction::SignOutput`: the request is `[kind][value][session nonce][challenge]` where `kind` is one of the
+/// `OUTPUT_KIND_*` values, `value` a little-endian `u64` and the session nonce the one of the approved
+/// [`TransactionSummary`]. The response has the same layout as `Instruction::Sign`.
+pub const SIGN_OUTPUT_LENGTH: usize = 1 + 8 + 8 + SIGN_CHALLENGE_LENGTH;
+/// An output paying one of the recipients shown in the summary
+pub const OUTPUT_KIND_RECIPIENT: u8 = 0x00;
+/// An output returning funds to this wallet
+pub const OUTPUT_KIND_CHANGE: u8 = 0x01;
+
+/// `Instruction::SignConfirmedOutput`: the request is a `SignOutput` request followed by the encoded Tari address of
+/// the recipient, all zeros for change. The app shows the value in Tari and, for recipient outputs, the address in hex
+/// and only signs once the user confirms. A rejected output voids the approval like any other error. The response has
+/// the same layout as `Instruction::Sign`.
+pub const OUTPUT_ADDRESS_LENGTH: usize = 33;
+pub const SIGN_CONFIRMED_OUTPUT_LENGTH: usize = SIGN_OUTPUT_LENGTH + OUTPUT_ADDRESS_LENGTH;
+
+/// `Instruction::SwapLock` and `Instruction::SwapPreimage`: the request is a 32-byte swap id. The responses are
+/// `[format][SHA-256 lock hash][public key]` and `[format][preimage]`.
+pub const SWAP_ID_LENGTH: usize = 32;
+pub const SWAP_LOCK_RESPONSE_LENGTH: usize = 1 + 2 * 32;
+pub const SWAP_PREIMAGE_RESPONSE_LENGTH: usize = 1 + 32;
+
+/// `Instruction::GetCapabilities`: the response is `[format][capabilities]`, see [`Capabilities`]
+pub const CAPABILITIES_RESPONSE_LENGTH: usize = 1 + Capabilities::ENCODED_LENGTH;
+
+/// `Instruction::GetPublicKeys`: P1 is a [`KeyBranch`] and the request is `[account][first index][count]`,
+/// little-endian `u32`s and a `u8`, for the keys at `m/44'/535348'/account'/branch/index`. The response is `[format]`
+/// followed by `count` public keys. With [`Capabilities::DERIVATION_VERSIONS`], P2 is the [`DerivationVersion`] of the
+/// keys.
+pub const GET_PUBLIC_KEYS_REQUEST_LENGTH: usize = 4 + 4 + 1;
+/// The most keys a single request can return without exceeding the response buffer
+pub const MAX_PUBLIC_KEYS_PER_REQUEST: u8 = 7;
+
+pub const fn public_keys_response_length(count: u8) -> usize {
+    1 + 32 * count as usize
+}
+
+/// `Instruction::GetBlindedPublicKey`: the request is `[host public key][masked path]`, an ephemeral key of the host
+/// and the `[branch][account][index]` of the key, a [`KeyBranch`] and little-endian `u32`s, XOR the first
+/// `BLINDED_PATH_LENGTH` bytes of `BLINDED_PATH_LABEL(shared secret, host key)`. The response is `[format][blinded
+/// key]`, the key plus `BLINDED_KEY_LABEL(shared secret, host key, path)` times the generator, where the shared secret
+/// is the Diffie-Hellman secret of the host key and the app key. Only the host can unmask the key, and the same key
+/// requested twice looks different every time.
+pub const BLINDED_PATH_LENGTH: usize = 1 + 4 + 4;
+pub const GET_BLINDED_PUBLIC_KEY_REQUEST_LENGTH: usize = 32 + BLINDED_PATH_LENGTH;
+pub const GET_BLINDED_PUBLIC_KEY_RESPONSE_LENGTH: usize = 1 + 32;
+
+/// `Instruction::ExportPrivateKey`: P1 is a [`SensitiveKey`] and the request is `[account][host public key]`, a
+/// little-endian `u32` and the ephemeral key the host will decrypt with. Once the user confirms, the response is
+/// `[format][app ephemeral public key][ciphertext][tag]`: the key XOR `ENVELOPE_KEY_LABEL(shared secret, host key, app
+/// ephemeral key)`, authenticated by the first `ENVELOPE_TAG_LENGTH` bytes of `ENVELOPE_TAG_LABEL(shared secret, app
+/// ephemeral key, ciphertext)`, where the shared secret is the Diffie-Hellman secret of the two ephemeral keys.
+pub const EXPORT_PRIVATE_KEY_REQUEST_LENGTH: usize = 4 + 32;
+pub const ENVELOPE_TAG_LENGTH: usize = 16;
+pub const EXPORT_PRIVATE_KEY_RESPONSE_LENGTH: usize = 1 + 32 + 32 + ENVELOPE_TAG_LENGTH;
+
+/// `Instruction::BatchCommitment`: the request is `[count]` followed by `count` entries of `[value][index]`, a
+/// little-endian `u64` and `u32`, where the mask of each commitment is the [`KeyBranch::CommitmentMask`] key at
+/// `m/44'/535348'/0'/0/index`. The
+/// response is `[format]` followed by `count` commitments. With [`Capabilities::DERIVATION_VERSIONS`], P2 is the
+/// [`DerivationVersion`] of the masks.
+pub const BATCH_COMMITMENT_ENTRY_LENGTH: usize = 8 + 4;
+/// The most commitments a single request can return without exceeding the response buffer
+pub const MAX_COMMITMENTS_PER_REQUEST: u8 = 7;
+
+pub const fn batch_commitment_request_length(count: u8) -> usize {
+    1 + BATCH_COMMITMENT_ENTRY_LENGTH * count as usize
+}
+
+pub const fn batch_commitment_response_length(count: u8) -> usize {
+    1 + 32 * count as usize
+}
+
+/// `Instruction::GetSigningCounter`: the response is `[format][counter]`, a little-endian `u64` that the app persists
+/// and increments before every signature it returns. With `P1_COUNTER_OUTPUTS` the counter is that of the outputs the
+/// app signed in approved transactions instead, with `SignOutput` or `SignConfirmedOutput`, also incremented before the
+/// signature is returned.
+pub const P1_COUNTER_SIGNATURES: u8 = 0x00;
+pub const P1_COUNTER_OUTPUTS: u8 = 0x01;
+pub const SIGNING_COUNTER_RESPONSE_LENGTH: usize = 1 + 8;
+
+/// `Instruction::OpenSession`: the request is an ephemeral public key of the host, the response is
+/// `[format][app ephemeral public key][app public key][s][public nonce]` where the signature is the script signature
+/// of the app key over `SESSION_AUTH_LABEL(host key, app ephemeral key)`. Both sides derive the session key as
+/// `SESSION_KEY_LABEL(shared secret, host key, app ephemeral key)` from the Diffie-Hellman shared secret of the two
+/// ephemeral keys.
+///
+/// From then on every command ends in a MAC of `[CLA][INS][P1][P2]` and its data, and every response ends in a MAC of
+/// `[SW1][SW2][0][0]` and its data. Each MAC is the first `SESSION_MAC_LENGTH` bytes of
+/// `SESSION_MAC_LABEL(session key, counter, direction, header, data)`, where the `u32` counter counts the commands of
+/// the session, so both MACs of an exchange share it, and the direction is one of the `MAC_DIRECTION_*` values. A
+/// command with a missing or wrong MAC closes the session and is answered with `SW_SESSION_MAC_FAILED` and no MAC.
+pub const SESSION_PUBLIC_KEY_LENGTH: usize = 32;
+pub const OPEN_SESSION_RESPONSE_LENGTH: usize = 1 + 4 * 32;
+pub const SESSION_MAC_LENGTH: usize = 16;
+pub const MAC_DIRECTION_COMMAND: u8 = 0x00;
+pub const MAC_DIRECTION_RESPONSE: u8 = 0x01;
+
+/// `Instruction::Pairing`, only accepted inside an authenticated session. With `P1_PAIRING_REGISTER` the request is a
+/// 32-byte secret of the host XOR `PAIRING_SECRET_LABEL(session key)`, and once the user confirms the pairing words
+/// the app stores the secret in place of any earlier one. The response is `[format]`.
+///
+/// With `P1_PAIRING_VERIFY` the request is `PAIRING_PROOF_LABEL(secret, session challenge)`, where the session
+/// challenge is the one the app signed to open the session. If it matches, the app shows the pairing words and the
+/// response is `[format][PAIRING_RESPONSE_LABEL(secret, session challenge)]`, proving to the host in turn that this is
+/// the device it paired with. Otherwise the app warns the user of an unknown host and answers `SW_PAIRING_FAILED`.
+/// Both sides show the [`pairing_words`] of `PAIRING_WORD_LABEL(secret)`, so a host that does not hold the secret
+/// cannot show the words the device shows.
+pub const P1_PAIRING_REGISTER: u8 = 0x00;
+pub const P1_PAIRING_VERIFY: u8 = 0x01;
+pub const PAIRING_SECRET_LENGTH: usize = 32;
+pub const PAIRING_PROOF_LENGTH: usize = 32;
+pub const PAIRING_REGISTER_RESPONSE_LENGTH: usize = 1;
+pub const PAIRING_VERIFY_RESPONSE_LENGTH: usize = 1 + PAIRING_PROOF_LENGTH;
+/// Three words of six bits each, 18 bits a rogue host would have to guess
+pub const PAIRING_WORD_COUNT: usize = 3;
+/// Short and easy to tell apart, the screens cannot show emoji
+pub const PAIRING_WORDS: [&str; 64] = [
+    "acorn", "anchor", "apple", "arrow", "badge", "bamboo", "banjo", "beacon", "bison", "bottle", "bridge", "cactus",
+    "camel", "candle", "canoe", "cherry", "cobra", "comet", "coral", "crown", "daisy", "dragon", "eagle", "falcon",
+    "feather", "forest", "galaxy", "garlic", "glacier", "hammer", "harbor", "helmet", "igloo", "island", "jacket",
+    "jungle", "kettle", "koala", "ladder", "lemon", "lizard", "magnet", "maple", "meadow", "mirror", "nectar", "oasis",
+    "orbit", "otter", "panda", "pepper", "piano", "pirate", "quartz", "rabbit", "rocket", "saddle", "salmon", "tiger",
+    "tulip", "violin", "walnut", "wizard", "zebra",
+];
+
+/// The pairing words of `digest`, the `PAIRING_WORD_LABEL` hash of the pairing secret
+pub fn pairing_words(digest: &[u8; 32]) -> [&'static str; PAIRING_WORD_COUNT] {
+    let mut words = [""; PAIRING_WORD_COUNT];
+    for (word, byte) in words.iter_mut().zip(digest) {
+        *word = PAIRING_WORDS[usize::from(byte & 0x3f)];
+    }
+    words
+}
+
+/// `Instruction::WalletBirthday`: with `P1_BIRTHDAY_GET` the response is `[format][height]`, the little-endian `u64`
+/// block height the wallet was created at, so that a recovery scan can start there instead of at genesis. It is 0 if
+/// no birthday was recorded for the seed the device is unlocked with. With `P1_BIRTHDAY_SET` the request is
+/// `[height]` and, once the user confirms it, the app records it for the current seed in place of any earlier
+/// birthday. The response is `[format]`. A height of 0 clears the birthday.
+pub const P1_BIRTHDAY_GET: u8 = 0x00;
+pub const P1_BIRTHDAY_SET: u8 = 0x01;
+pub const WALLET_BIRTHDAY_LENGTH: usize = 8;
+pub const GET_BIRTHDAY_RESPONSE_LENGTH: usize = 1 + WALLET_BIRTHDAY_LENGTH;
+pub const SET_BIRTHDAY_RESPONSE_LENGTH: usize = 1;
+
+/// `Instruction::SenderOffset`, with the [`DerivationVersion`] of the keys in P2. With `P1_SENDER_OFFSET_SIGN` the
+/// request is `[account u32][index u32][commitment 32][ephemeral commitment 32][message 32]` and the app signs with the
+/// sender offset key `k_O` at that index of the account. Its nonce is `r = SENDER_OFFSET_NONCE_LABEL(k_O, commitment,
+/// ephemeral commitment, message)`, so signing the same output again gives the same signature, and the response is
+/// `[format][sender offset public key][s][public nonce]` with `s = r + e·k_O` and the challenge
+/// `e = METADATA_SIGNATURE_LABEL(ephemeral commitment, public nonce, sender offset public key, commitment, message)`.
+/// It needs an approved transaction, which answers one sender offset for each of its outputs, and is otherwise
+/// answered with `SW_TRANSACTION_NOT_APPROVED`.
+///
+/// With `P1_SCRIPT_OFFSET` the request is `[session nonce u64][account u32][input count u8][output count u8]` followed
+/// by the index of the script key of every input and then the index of the sender offset key of every output, each a
+/// little-endian `u32`. The response is `[format][script offset 32]`, the sum of the script keys minus the sum of the
+/// sender offset keys. It is only answered once per approved transaction, before its last output is signed, for as
+/// many outputs as it has and at least one input, so that the difference of two offsets never reveals a sender offset
+/// key.
+pub const P1_SENDER_OFFSET_SIGN: u8 = 0x00;
+pub const P1_SCRIPT_OFFSET: u8 = 0x01;
+pub const SENDER_OFFSET_SIGN_REQUEST_LENGTH: usize = 4 + 4 + 3 * 32;
+pub const SENDER_OFFSET_SIGN_RESPONSE_LENGTH: usize = 1 + 3 * 32;
+pub const SCRIPT_OFFSET_HEADER_LENGTH: usize = 8 + 4 + 1 + 1;
+/// The inputs and outputs of one script offset request, as many indices as fit into a single APDU
+pub const MAX_SCRIPT_OFFSET_KEYS: usize = 56;
+pub const SCRIPT_OFFSET_RESPONSE_LENGTH: usize = 1 + 32;
+
+pub const fn script_offset_request_length(inputs: u8, outputs: u8) -> usize {
+    SCRIPT_OFFSET_HEADER_LENGTH + 4 * (inputs as usize + outputs as usize)
+}
+
+/// `Instruction::KernelSignature`, with the [`DerivationVersion`] of the masks in P2. With `P1_KERNEL_NONCE` the
+/// request is `[input count u8][output count u8][fee u64][lock height u64]` followed by the index of the commitment
+/// mask of every input and then of every output, each a little-endian `u32` of account 0 as for
+/// `Instruction::BatchCommitment`. The app takes the excess `x = Σ output masks - Σ input masks`, the kernel message
+/// `m = KERNEL_MESSAGE_LABEL(fee, lock height)` and the nonce `r = KERNEL_NONCE_LABEL(x, m, signing counter, 32 bytes
+/// of the device RNG)` and keeps them for the next signature, in place of any kept before. The response is
+/// `[format][public excess 32][public nonce 32]`. Spending inputs needs an approved transaction with the same fee, and
+/// the first such nonce binds the lock height and public excess to it, so that no other kernel spends under it.
+/// Receiving into outputs alone needs the user to confirm the fee and lock height on the device. The public nonce goes
+/// out before the other parties' nonces and excesses exist, so those cannot go into it, and the RNG keeps it fresh
+/// where the signing counter alone would repeat once the app's storage is wiped.
+///
+/// With `P1_KERNEL_SIGN` the request is `[total public nonce 32][total public excess 32][kernel message 32]`, the sums
+/// over every party of the kernel, and the response is `[format][s 32]`, the partial signature `s = r + e·x` under the
+/// challenge `e = KERNEL_SIGNATURE_LABEL(total public nonce, total public excess, kernel message)`. A message other
+/// than the kept `m` is answered with `SW_TRANSACTION_NOT_APPROVED`. The signature uses up the kept excess and nonce
+/// and moves the signing counter on, so that no nonce ever signs two challenges. Signing the kernel bound to an
+/// approved transaction uses up its approval, which ends once its outputs are signed as well, and a new
+/// `TransactionSummary` drops the kept excess and nonce.
+pub const P1_KERNEL_NONCE: u8 = 0x00;
+pub const P1_KERNEL_SIGN: u8 = 0x01;
+pub const KERNEL_NONCE_HEADER_LENGTH: usize = 1 + 1 + 8 + 8;
+/// The inputs and outputs of one kernel excess, as many indices as fit into a single APDU
+pub const MAX_KERNEL_EXCESS_KEYS: usize = 58;
+pub const KERNEL_NONCE_RESPONSE_LENGTH: usize = 1 + 2 * 32;
+pub const KERNEL_SIGN_REQUEST_LENGTH: usize = 3 * 32;
+pub const KERNEL_SIGN_RESPONSE_LENGTH: usize = 1 + 32;
+
+pub const fn kernel_nonce_request_length(inputs: u8, outputs: u8) -> usize {
+    KERNEL_NONCE_HEADER_LENGTH + 4 * (inputs as usize + outputs as usize)
+}
+
+/// `Instruction::NoncePool`, with the [`DerivationVersion`] of the masks in P2 when signing. The app keeps one pool of
+/// nonces, named by its pool id, the signing counter when it was started, and issues them ahead of the kernels they
+/// sign so that a party can hand out its public nonce without a round trip to the device. Nonce `i` of a pool is
+/// `r_i = POOLED_NONCE_LABEL(seed, i)` with `seed = NONCE_POOL_SEED_LABEL(k, pool id, 32 bytes of the device RNG)` and
+/// `k` the kernel nonce key at index 0 of account 0. The pool id starts again when the app's storage is wiped, and the
+/// RNG keeps a pool started then from issuing the nonces of an earlier one.
+///
+/// With `P1_NONCE_POOL_FETCH` the request is `[count u8]` and the app issues the next `count` nonces of its pool,
+/// starting a new pool if it has none or has issued all `MAX_POOLED_NONCES` of it. The response is `[format][pool id
+/// u64][first nonce id u8]` followed by the `count` public nonces.
+///
+/// With `P1_NONCE_POOL_SIGN` the request is `[pool id u64][nonce id u8][input count u8][output count u8]` followed by
+/// the mask indices of the excess as for `P1_KERNEL_NONCE` of `Instruction::KernelSignature` and then `[total public
+/// nonce 32][total public excess 32][fee u64][lock height u64]`. The app signs the kernel message of the fee and lock
+/// height with the issued nonce and the excess as `P1_KERNEL_SIGN` does, and the response is `[format][s 32]`. Each
+/// nonce signs once, and a nonce the app did not issue or has already used is answered with `SW_NONCE_NOT_ISSUED`. As
+/// for `P1_KERNEL_NONCE`, spending inputs needs an approved transaction with the same fee, whose kernel the signature
+/// uses up, and receiving into outputs alone needs the user to confirm the fee and lock height on the device. The
+/// nonce is used up even if the signature is refused.
+///
+/// With `P1_NONCE_POOL_INVALIDATE` the request is empty, the app drops its pool and every nonce it issued that has not
+/// signed yet, and the response is `[format]`. The pool is dropped with the session as well, and does not survive the
+/// app being closed.
+pub const P1_NONCE_POOL_FETCH: u8 = 0x00;
+pub const P1_NONCE_POOL_SIGN: u8 = 0x01;
+pub const P1_NONCE_POOL_INVALIDATE: u8 = 0x02;
+/// The nonces issued from a single pool, one bit each of the app's record of those used
+pub const MAX_POOLED_NONCES: usize = 64;
+/// The nonces of one fetch, as many as fit into a single response
+pub const MAX_NONCE_POOL_FETCH: usize = 7;
+pub const NONCE_POOL_FETCH_REQUEST_LENGTH: usize = 1;
+pub const NONCE_POOL_SIGN_HEADER_LENGTH: usize = 8 + 1 + 1 + 1;
+/// The inputs and outputs of one pooled kernel signature, as many indices as fit into a single APDU next to the totals
+/// and the fee and lock height
+pub const MAX_POOLED_KERNEL_KEYS: usize = 39;
+pub const NONCE_POOL_SIGN_RESPONSE_LENGTH: usize = 1 + 32;
+pub const NONCE_POOL_INVALIDATE_RESPONSE_LENGTH: usize = 1;
+
+pub const fn nonce_pool_fetch_response_length(count: u8) -> usize {
+    1 + 8 + 1 + 32 * count as usize
+}
+
+pub const fn nonce_pool_sign_request_length(inputs: u8, outputs: u8) -> usize {
+    NONCE_POOL_SIGN_HEADER_LENGTH + 4 * (inputs as usize + outputs as usize) + 2 * 32 + 8 + 8
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProtocolError {
+    IncorrectLength {
+        expected: usize,
+        actual: usize,
+    },
+    UnsupportedFormat(u8),
+    /// A strictly parsed response continues past its documented end
+    TrailingBytes {
+        expected: usize,
+        actual: usize,
+    },
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProtocolError::IncorrectLength { expected, actual } => {
+                write!(f, "expected {} bytes, got {}", expected, actual)
+            },
+            ProtocolError::UnsupportedFormat(format) => write!(f, "unsupported response format {}", format),
+            ProtocolError::TrailingBytes { expected, actual } => {
+                write!(
+                    f,
+                    "expected {} bytes, got {} including trailing bytes",
+                    expected, actual
+                )
+            },
+        }
+    }
+}
+
+/// How to treat bytes after the documented end of a response
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Strictness {
+    /// Ignore them, so that a newer app can append fields without breaking older clients
+    #[default]
+    Lenient,
+    /// Fail with [`ProtocolError::TrailingBytes`], to catch protocol regressions while developing the app
+    Strict,
+}
+
+/// Check the length and format byte of a response, returning the payload that follows the format byte. The response
+/// has to be exactly `expected_length` bytes long.
+pub fn response_payload(response: &[u8], expected_length: usize) -> Result<&[u8], ProtocolError> {
+    parse_response(response, expected_length, Strictness::Strict)
+}
+
+/// [`response_payload`] for a response that may, unless parsed strictly, be longer than `expected_length`. The
+/// payload never includes the trailing bytes.
+pub fn parse_response(response: &[u8], expected_length: usize, strictness: Strictness) -> Result<&[u8], ProtocolError> {
+    if response.len() < expected_length || expected_length == 0 {
+        return Err(ProtocolError::IncorrectLength {
+            expected: expected_length,
+            actual: response.len(),
+        });
+    }
+    if response.len() > expected_length && strictness == Strictness::Strict {
+        return Err(ProtocolError::TrailingBytes {
+            expected: expected_length,
+            actual: response.len(),
+        });
+    }
+    if response[0] != RESPONSE_FORMAT_VERSION {
+        return Err(ProtocolError::UnsupportedFormat(response[0]));
+    }
+    Ok(&response[1..expected_length])
+}
+
+/// The CRC-32 of `bytes` as used by Ethernet and zlib, which framed uploads end with
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffff_u32;
+    for byte in bytes {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/// A `major.minor.patch` version, encoded on the wire as three little-endian `u16`s
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SemanticVersion {
+    pub major: u16,
+    pub minor: u16,
+    pub patch: u16,
+}
+
+impl SemanticVersion {
+    pub const ENCODED_LENGTH: usize = 6;
+
+    pub const fn new(major: u16, minor: u16, patch: u16) -> Self {
+        Self { major, minor, patch }
+    }
+
+    pub fn to_le_bytes(self) -> [u8; Self::ENCODED_LENGTH] {
+        let mut bytes = [0u8; Self::ENCODED_LENGTH];
+        bytes[0..2].copy_from_slice(&self.major.to_le_bytes());
+        bytes[2..4].copy_from_slice(&self.minor.to_le_bytes());
+        bytes[4..6].copy_from_slice(&self.patch.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_le_bytes(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        if bytes.len() != Self::ENCODED_LENGTH {
+            return Err(ProtocolError::IncorrectLength {
+                expected: Self::ENCODED_LENGTH,
+                actual: bytes.len(),
+            });
+        }
+        Ok(Self {
+            major: u16::from_le_bytes([bytes[0], bytes[1]]),
+            minor: u16::from_le_bytes([bytes[2], bytes[3]]),
+            patch: u16::from_le_bytes([bytes[4], bytes[5]]),
+        })
+    }
+}
+
+impl FromStr for SemanticVersion {
+    type Err = core::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Ignore pre-release and build metadata, e.g. `1.2.3-rc.1+abc`
+        let core = s.split(['-', '+']).next().unwrap_or(s);
+        let mut parts = core.splitn(3, '.');
+        let major = parts.next().unwrap_or("0").parse()?;
+        let minor = parts.next().unwrap_or("0").parse()?;
+        let patch = parts.next().unwrap_or("0").parse()?;
+        Ok(Self { major, minor, patch })
+    }
+}
+
+impl fmt::Display for SemanticVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// What the user confirms before a multi-output transaction is signed. Amounts are in microTari; `total_out` and
+/// `recipient_count` only cover the recipient outputs, `output_count` includes change.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TransactionSummary {
+    pub total_out: u64,
+    pub fee: u64,
+    pub recipient_count: u8,
+    pub output_count: u8,
+    /// Chosen by the host for every signing session. The app only signs outputs that carry the nonce of the summary
+    /// the user approved, so outputs of an older session can never be signed under a newer approval.
+    pub session_nonce: u64,
+}
+
+impl TransactionSummary {
+    pub const ENCODED_LENGTH: usize = 26;
+
+    pub fn to_le_bytes(self) -> [u8; Self::ENCODED_LENGTH] {
+        let mut bytes = [0u8; Self::ENCODED_LENGTH];
+        bytes[0..8].copy_from_slice(&self.total_out.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.fee.to_le_bytes());
+        bytes[16] = self.recipient_count;
+        bytes[17] = self.output_count;
+        bytes[18..26].copy_from_slice(&self.session_nonce.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_le_bytes(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        if bytes.len() != Self::ENCODED_LENGTH {
+            return Err(ProtocolError::IncorrectLength {
+                expected: Self::ENCODED_LENGTH,
+                actual: bytes.len(),
+            });
+        }
+        let mut total_out = [0u8; 8];
+        total_out.copy_from_slice(&bytes[0..8]);
+        let mut fee = [0u8; 8];
+        fee.copy_from_slice(&bytes[8..16]);
+        let mut session_nonce = [0u8; 8];
+        session_nonce.copy_from_slice(&bytes[18..26]);
+        Ok(Self {
+            total_out: u64::from_le_bytes(total_out),
+            fee: u64::from_le_bytes(fee),
+            recipient_count: bytes[16],
+            output_count: bytes[17],
+            session_nonce: u64::from_le_bytes(session_nonce),
+        })
+    }
+}
+
+impl fmt::Display for TransactionSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} uT to {} recipients in {} outputs, fee {} uT",
+            self.total_out, self.recipient_count, self.output_count, self.fee
+        )
+    }
+}
+
+/// The optional features an app supports, encoded on the wire as a little-endian `u32` bitfield. Unknown bits are kept
+/// so that older clients can still pass them on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    pub const APP_SETTINGS: Self = Self(1 << 13);
+    pub const ATOMIC_SWAP: Self = Self(1 << 5);
+    pub const AUTHENTICATED_SESSION: Self = Self(1 << 9);
+    pub const BATCH_COMMITMENTS: Self = Self(1 << 7);
+    pub const BATCH_SIGNING: Self = Self(1 << 4);
+    pub const BLINDED_KEYS: Self = Self(1 << 12);
+    pub const BULLETPROOF_COSIGNING: Self = Self(1 << 1);
+    /// The instructions that derive output keys take a [`DerivationVersion`] in P2
+    pub const DERIVATION_VERSIONS: Self = Self(1 << 17);
+    pub const DISPLAY_HINTS: Self = Self(1 << 11);
+    pub const ENCODED_LENGTH: usize = 4;
+    pub const ENCRYPTED_KEY_EXPORT: Self = Self(1 << 10);
+    /// Chunked uploads carry sequence numbers and a CRC, see [`CHUNK_SEQUENCE_LENGTH`]
+    pub const FRAMED_UPLOADS: Self = Self(1 << 18);
+    /// Partial kernel signatures with excesses that stay on the device, see `Instruction::KernelSignature`
+    pub const KERNEL_SIGNATURES: Self = Self(1 << 20);
+    pub const MESSAGE_SIGNING: Self = Self(1 << 3);
+    pub const MULTISIG: Self = Self(1 << 2);
+    /// Every known capability with its display name
+    pub const NAMED: [(Self, &'static str); 25] = [
+        (Self::STEALTH_ADDRESSES, "stealth addresses"),
+        (Self::BULLETPROOF_COSIGNING, "bulletproof co-signing"),
+        (Self::MULTISIG, "multisig"),
+        (Self::MESSAGE_SIGNING, "message signing"),
+        (Self::BATCH_SIGNING, "batch signing"),
+        (Self::ATOMIC_SWAP, "atomic swaps"),
+        (Self::PUBLIC_KEY_EXPORT, "public key export"),
+        (Self::BATCH_COMMITMENTS, "batch commitments"),
+        (Self::SIGNING_COUNTER, "signing counter"),
+        (Self::AUTHENTICATED_SESSION, "authenticated sessions"),
+        (Self::ENCRYPTED_KEY_EXPORT, "encrypted key export"),
+        (Self::DISPLAY_HINTS, "display hints"),
+        (Self::BLINDED_KEYS, "blinded keys"),
+        (Self::APP_SETTINGS, "app settings"),
+        (Self::OUTPUT_CONFIRMATION, "output confirmation"),
+        (Self::PAIRING, "host pairing"),
+        (Self::WALLET_BIRTHDAY, "wallet birthday"),
+        (Self::DERIVATION_VERSIONS, "derivation versions"),
+        (Self::FRAMED_UPLOADS, "framed uploads"),
+        (Self::SENDER_OFFSETS, "sender offsets"),
+        (Self::KERNEL_SIGNATURES, "kernel signatures"),
+        (Self::NONCE_POOL, "nonce pool"),
+        (Self::SETTINGS_CHANGES, "settings changes"),
+        (Self::OUTPUT_COUNTER, "output counter"),
+        (Self::TRANSACTION_CANCEL, "transaction cancel"),
+    ];
+    /// Public nonces issued ahead of the kernel signatures that use them, see `Instruction::NoncePool`
+    pub const NONCE_POOL: Self = Self(1 << 21);
+    pub const OUTPUT_CONFIRMATION: Self = Self(1 << 14);
+    /// The app counts the outputs it signs, see `P1_COUNTER_OUTPUTS`
+    pub const OUTPUT_COUNTER: Self = Self(1 << 23);
+    pub const PAIRING: Self = Self(1 << 15);
+    pub const PUBLIC_KEY_EXPORT: Self = Self(1 << 6);
+    /// Sender offset keys stay on the device, see `Instruction::SenderOffset`
+    pub const SENDER_OFFSETS: Self = Self(1 << 19);
+    /// The host can read and change the app settings, see `Instruction::AppSettings`
+    pub const SETTINGS_CHANGES: Self = Self(1 << 22);
+    pub const SIGNING_COUNTER: Self = Self(1 << 8);
+    pub const STEALTH_ADDRESSES: Self = Self(1 << 0);
+    /// The host can drop an approved transaction, see `P1_TRANSACTION_CANCEL`
+    pub const TRANSACTION_CANCEL: Self = Self(1 << 24);
+    pub const WALLET_BIRTHDAY: Self = Self(1 << 16);
+
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    /// Whether every capability in `other` is also in `self`
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The display name of a single capability
+    pub fn name(self) -> &'static str {
+        Self::NAMED
+            .iter()
+            .find(|(capability, _)| *capability == self)
+            .map(|(_, name)| *name)
+            .unwrap_or("unknown capability")
+    }
+
+    pub fn to_le_bytes(self) -> [u8; Self::ENCODED_LENGTH] {
+        self.0.to_le_bytes()
+    }
+
+    pub fn from_le_bytes(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        if bytes.len() != Self::ENCODED_LENGTH {
+            return Err(ProtocolError::IncorrectLength {
+                expected: Self::ENCODED_LENGTH,
+                actual: bytes.len(),
+            });
+        }
+        Ok(Self(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])))
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        self.union(rhs)
+    }
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut names = Self::NAMED
+            .iter()
+            .filter(|(capability, _)| self.contains(*capability))
+            .map(|(_, name)| name);
+        match names.next() {
+            Some(first) => {
+                write!(f, "{}", first)?;
+                names.try_for_each(|name| write!(f, ", {}", name))
+            },
+            None => write!(f, "none"),
+        }
+    }
+}
+
+//--------------------------------------------- Specification --------------------------------------------------------//
+
+/// A status word with its name and what it means
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StatusWordSpec {
+    pub code: u16,
+    pub name: &'static str,
+    pub meaning: &'static str,
+}
+
+/// Every status word of the protocol
+pub const STATUS_WORDS: [StatusWordSpec; 20] = [
+    StatusWordSpec {
+        code: SW_OK,
+        name: "SW_OK",
+        meaning: "success",
+    },
+    StatusWordSpec {
+        code: SW_INCORRECT_BYTE_LENGTH,
+        name: "SW_INCORRECT_BYTE_LENGTH",
+        meaning: "the request is not as long as its layout",
+    },
+    StatusWordSpec {
+        code: SW_INVALID_CHALLENGE,
+        name: "SW_INVALID_CHALLENGE",
+        meaning: "the challenge is not a valid scalar",
+    },
+    StatusWordSpec {
+        code: SW_CONVERSION_ERROR,
+        name: "SW_CONVERSION_ERROR",
+        meaning: "a field of the request, P1 or P2 is out of range",
+    },
+    StatusWordSpec {
+        code: SW_DECRYPT_FAILED,
+        name: "SW_DECRYPT_FAILED",
+        meaning: "the request could not be decrypted",
+    },
+    StatusWordSpec {
+        code: SW_CLIENT_VERSION_REJECTED,
+        name: "SW_CLIENT_VERSION_REJECTED",
+        meaning: "the app refused the client version",
+    },
+    StatusWordSpec {
+        code: SW_INS_NOT_SUPPORTED,
+        name: "SW_INS_NOT_SUPPORTED",
+        meaning: "the app does not know the instruction",
+    },
+    StatusWordSpec {
+        code: SW_USER_REJECTED,
+        name: "SW_USER_REJECTED",
+        meaning: "the user declined the request on the device",
+    },
+    StatusWordSpec {
+        code: SW_TRANSACTION_NOT_APPROVED,
+        name: "SW_TRANSACTION_NOT_APPROVED",
+        meaning: "no approved transaction, or an output the user did not approve",
+    },
+    StatusWordSpec {
+        code: SW_DEVICE_LOCKED,
+        name: "SW_DEVICE_LOCKED",
+        meaning: "the device is locked, reported by the device OS",
+    },
+    StatusWordSpec {
+        code: SW_DEVICE_LOCKED_LEGACY,
+        name: "SW_DEVICE_LOCKED_LEGACY",
+        meaning: "the device is locked, reported by older firmware",
+    },
+    StatusWordSpec {
+        code: SW_SECURITY_STATUS_NOT_SATISFIED,
+        name: "SW_SECURITY_STATUS_NOT_SATISFIED",
+        meaning: "the device is locked, reported by some firmware",
+    },
+    StatusWordSpec {
+        code: SW_SETTING_DISABLED,
+        name: "SW_SETTING_DISABLED",
+        meaning: "the request needs a setting the user has not enabled",
+    },
+    StatusWordSpec {
+        code: SW_PAIRING_FAILED,
+        name: "SW_PAIRING_FAILED",
+        meaning: "no authenticated session, or the host does not hold the pairing secret",
+    },
+    StatusWordSpec {
+        code: SW_UPLOAD_CORRUPTED,
+        name: "SW_UPLOAD_CORRUPTED",
+        meaning: "a chunk arrived out of sequence, or the upload does not match its CRC",
+    },
+    StatusWordSpec {
+        code: SW_NONCE_NOT_ISSUED,
+        name: "SW_NONCE_NOT_ISSUED",
+        meaning: "the pooled nonce was never issued, has signed already or was dropped",
+    },
+    StatusWordSpec {
+        code: SW_SESSION_MAC_FAILED,
+        name: "SW_SESSION_MAC_FAILED",
+        meaning: "a command of the session had a missing or wrong MAC, the session is closed",
+    },
+    StatusWordSpec {
+        code: SW_CLIENT_NOT_PERMITTED,
+        name: "SW_CLIENT_NOT_PERMITTED",
+        meaning: "the daemon refused the command, its client may not use the instruction, never sent by the app",
+    },
+    StatusWordSpec {
+        code: SW_CLIENT_QUOTA_EXCEEDED,
+        name: "SW_CLIENT_QUOTA_EXCEEDED",
+        meaning: "the daemon refused the command, its client used up its quota for now, never sent by the app",
+    },
+    StatusWordSpec {
+        code: SW_APPROVAL_REFUSED,
+        name: "SW_APPROVAL_REFUSED",
+        meaning: "the daemon refused the command, an approval hook or its operator did, never sent by the app",
+    },
+];
+
+/// The status words any instruction can be answered with
+pub const COMMON_STATUS_WORDS: [u16; 6] = [
+    SW_OK,
+    SW_INS_NOT_SUPPORTED,
+    SW_SESSION_MAC_FAILED,
+    SW_DEVICE_LOCKED,
+    SW_DEVICE_LOCKED_LEGACY,
+    SW_SECURITY_STATUS_NOT_SATISFIED,
+];
+
+/// The name and meaning of `code`, if it is a status word of the protocol
+pub fn status_word_spec(code: u16) -> Option<&'static StatusWordSpec> {
+    STATUS_WORDS.iter().find(|status_word| status_word.code == code)
+}
+
+/// One request an instruction takes and the response the app answers it with. Layouts are written as the payload
+/// docs write them, `[field]` for a byte and `[field n]` for `n` bytes, without the APDU header or a session MAC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MessageSpec {
+    /// The P1 that selects the message, `None` if P1 does not
+    pub p1: Option<u8>,
+    /// What the message does, empty for instructions with a single one
+    pub name: &'static str,
+    pub request: &'static str,
+    pub response: &'static str,
+    /// In bytes, `None` where a count in the request sets the length
+    pub request_length: Option<usize>,
+    pub response_length: Option<usize>,
+}
+
+/// Everything the host and the app agree on about an instruction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InstructionSpec {
+    pub instruction: Instruction,
+    pub name: &'static str,
+    pub summary: &'static str,
+    /// The capability of apps that answer it, `None` for those every app answers
+    pub capability: Option<Capabilities>,
+    /// What P1 carries when it does not select a message
+    pub p1: Option<&'static str>,
+    /// What P2 carries, `None` if it is always 0
+    pub p2: Option<&'static str>,
+    pub messages: &'static [MessageSpec],
+    /// The status words of the instruction on top of [`COMMON_STATUS_WORDS`]
+    pub status_words: &'static [u16],
+}
+
+impl InstructionSpec {
+    /// The message sent with `p1`
+    pub fn message(&self, p1: u8) -> Option<&'static MessageSpec> {
+        self.messages
+            .iter()
+            .find(|message| message.p1.is_none() || message.p1 == Some(p1))
+    }
+}
+
+/// A message whose P1 does not select it
+const fn message(
+    request: &'static str,
+    response: &'static str,
+    request_length: Option<usize>,
+    response_length: Option<usize>,
+) -> MessageSpec {
+    MessageSpec {
+        p1: None,
+        name: "",
+        request,
+        response,
+        request_length,
+        response_length,
+    }
+}
+
+/// A message selected by `p1`
+const fn selected(
+    p1: u8,
+    name: &'static str,
+    request: &'static str,
+    response: &'static str,
+    request_length: Option<usize>,
+    response_length: Option<usize>,
+) -> MessageSpec {
+    MessageSpec {
+        p1: Some(p1),
+        name,
+        request,
+        response,
+        request_length,
+        response_length,
+    }
+}
+
+const DERIVATION_VERSION_P2: &str = "the DerivationVersion of the keys, with the derivation versions capability";
+const SIGNATURE_RESPONSE: &str = "[format][public key 32][s 32][public nonce 32]";
+const VERSION_RESPONSE: &str = "[format][name length][name][version length][version][settings]";
+const UPLOAD_CHUNK_REQUEST: &str = "[sequence u16 LE, with framed uploads][chunk]";
+
+const GET_VERSION_MESSAGES: [MessageSpec; 3] = [
+    selected(
+        P1_CHUNK_INIT,
+        "version",
+        "empty, or the start of an upload",
+        VERSION_RESPONSE,
+        None,
+        None,
+    ),
+    selected(
+        P1_CHUNK_ADD,
+        "upload chunk",
+        UPLOAD_CHUNK_REQUEST,
+        VERSION_RESPONSE,
+        None,
+        None,
+    ),
+    selected(
+        P1_CHUNK_LAST,
+        "last upload chunk",
+        UPLOAD_CHUNK_REQUEST,
+        "[format][name length][name][version length][version][settings][CRC u32 LE, with framed uploads]",
+        None,
+        None,
+    ),
+];
+const SIGN_MESSAGES: [MessageSpec; 1] = [message(
+    "[challenge 32]",
+    SIGNATURE_RESPONSE,
+    Some(SIGN_CHALLENGE_LENGTH),
+    Some(SIGN_RESPONSE_LENGTH),
+)];
+const COMMITMENT_MESSAGES: [MessageSpec; 1] = [message(
+    "[value u64 LE]",
+    "[format][commitment 32]",
+    Some(COMMITMENT_VALUE_LENGTH),
+    Some(COMMITMENT_RESPONSE_LENGTH),
+)];
+const BP_DATA_MESSAGES: [MessageSpec; 1] = [message(
+    "[scalar 32]",
+    "[format][scalar 32]",
+    Some(BP_SCALAR_LENGTH),
+    Some(BP_RESPONSE_LENGTH),
+)];
+const CLIENT_VERSION_MESSAGES: [MessageSpec; 1] = [message(
+    "[major u16 LE][minor u16 LE][patch u16 LE]",
+    "[format][app version 6][min client version 6]",
+    Some(SemanticVersion::ENCODED_LENGTH),
+    Some(CLIENT_VERSION_RESPONSE_LENGTH),
+)];
+const TRANSACTION_SUMMARY_MESSAGES: [MessageSpec; 2] = [
+    selected(
+        P1_TRANSACTION_APPROVE,
+        "approve",
+        "[total out u64 LE][fee u64 LE][recipient count][output count][session nonce u64 LE]",
+        "[format], once the user confirms",
+        Some(TransactionSummary::ENCODED_LENGTH),
+        Some(TRANSACTION_SUMMARY_RESPONSE_LENGTH),
+    ),
+    selected(
+        P1_TRANSACTION_CANCEL,
+        "cancel",
+        "empty",
+        "[format]",
+        Some(0),
+        Some(TRANSACTION_CANCEL_RESPONSE_LENGTH),
+    ),
+];
+const SIGN_OUTPUT_MESSAGES: [MessageSpec; 1] = [message(
+    "[kind][value u64 LE][session nonce u64 LE][challenge 32]",
+    SIGNATURE_RESPONSE,
+    Some(SIGN_OUTPUT_LENGTH),
+    Some(SIGN_RESPONSE_LENGTH),
+)];
+const SWAP_LOCK_MESSAGES: [MessageSpec; 1] = [message(
+    "[swap id 32]",
+    "[format][SHA-256 lock hash 32][public key 32]",
+    Some(SWAP_ID_LENGTH),
+    Some(SWAP_LOCK_RESPONSE_LENGTH),
+)];
+const SWAP_PREIMAGE_MESSAGES: [MessageSpec; 1] = [message(
+    "[swap id 32]",
+    "[format][preimage 32], once the user confirms",
+    Some(SWAP_ID_LENGTH),
+    Some(SWAP_PREIMAGE_RESPONSE_LENGTH),
+)];
+const GET_CAPABILITIES_MESSAGES: [MessageSpec; 1] = [message(
+    "empty",
+    "[format][capabilities u32 LE]",
+    Some(0),
+    Some(CAPABILITIES_RESPONSE_LENGTH),
+)];
+const GET_PUBLIC_KEYS_MESSAGES: [MessageSpec; 1] = [message(
+    "[account u32 LE][first index u32 LE][count]",
+    "[format][public key 32] * count",
+    Some(GET_PUBLIC_KEYS_REQUEST_LENGTH),
+    None,
+)];
+const BATCH_COMMITMENT_MESSAGES: [MessageSpec; 1] = [message(
+    "[count][value u64 LE][index u32 LE] * count",
+    "[format][commitment 32] * count",
+    None,
+    None,
+)];
+const GET_SIGNING_COUNTER_MESSAGES: [MessageSpec; 2] = [
+    selected(
+        P1_COUNTER_SIGNATURES,
+        "signatures",
+        "empty",
+        "[format][counter u64 LE]",
+        Some(0),
+        Some(SIGNING_COUNTER_RESPONSE_LENGTH),
+    ),
+    selected(
+        P1_COUNTER_OUTPUTS,
+        "outputs of approved transactions, with the output counter capability",
+        "empty",
+        "[format][counter u64 LE]",
+        Some(0),
+        Some(SIGNING_COUNTER_RESPONSE_LENGTH),
+    ),
+];
+const OPEN_SESSION_MESSAGES: [MessageSpec; 1] = [message(
+    "[host ephemeral public key 32]",
+    "[format][app ephemeral public key 32][app public key 32][s 32][public nonce 32]",
+    Some(SESSION_PUBLIC_KEY_LENGTH),
+    Some(OPEN_SESSION_RESPONSE_LENGTH),
+)];
+const EXPORT_PRIVATE_KEY_MESSAGES: [MessageSpec; 1] = [message(
+    "[account u32 LE][host ephemeral public key 32]",
+    "[format][app ephemeral public key 32][ciphertext 32][tag 16], once the user confirms",
+    Some(EXPORT_PRIVATE_KEY_REQUEST_LENGTH),
+    Some(EXPORT_PRIVATE_KEY_RESPONSE_LENGTH),
+)];
+const DISPLAY_HINTS_MESSAGES: [MessageSpec; 1] = [message(
+    "[digest 32][page count][title 16] * page count",
+    "[format]",
+    None,
+    Some(DISPLAY_HINTS_RESPONSE_LENGTH),
+)];
+const GET_BLINDED_PUBLIC_KEY_MESSAGES: [MessageSpec; 1] = [message(
+    "[host ephemeral public key 32][masked branch][masked account u32 LE][masked index u32 LE]",
+    "[format][blinded public key 32]",
+    Some(GET_BLINDED_PUBLIC_KEY_REQUEST_LENGTH),
+    Some(GET_BLINDED_PUBLIC_KEY_RESPONSE_LENGTH),
+)];
+const SIGN_CONFIRMED_OUTPUT_MESSAGES: [MessageSpec; 1] = [message(
+    "[kind][value u64 LE][session nonce u64 LE][challenge 32][recipient address 33]",
+    "[format][public key 32][s 32][public nonce 32], once the user confirms",
+    Some(SIGN_CONFIRMED_OUTPUT_LENGTH),
+    Some(SIGN_RESPONSE_LENGTH),
+)];
+const PAIRING_MESSAGES: [MessageSpec; 2] = [
+    selected(
+        P1_PAIRING_REGISTER,
+        "register",
+        "[masked pairing secret 32]",
+        "[format], once the user confirms the pairing words",
+        Some(PAIRING_SECRET_LENGTH),
+        Some(PAIRING_REGISTER_RESPONSE_LENGTH),
+    ),
+    selected(
+        P1_PAIRING_VERIFY,
+        "verify",
+        "[host proof 32]",
+        "[format][app proof 32]",
+        Some(PAIRING_PROOF_LENGTH),
+        Some(PAIRING_VERIFY_RESPONSE_LENGTH),
+    ),
+];
+const WALLET_BIRTHDAY_MESSAGES: [MessageSpec; 2] = [
+    selected(
+        P1_BIRTHDAY_GET,
+        "read",
+        "empty",
+        "[format][height u64 LE]",
+        Some(0),
+        Some(GET_BIRTHDAY_RESPONSE_LENGTH),
+    ),
+    selected(
+        P1_BIRTHDAY_SET,
+        "record",
+        "[height u64 LE]",
+        "[format], once the user confirms",
+        Some(WALLET_BIRTHDAY_LENGTH),
+        Some(SET_BIRTHDAY_RESPONSE_LENGTH),
+    ),
+];
+const SENDER_OFFSET_MESSAGES: [MessageSpec; 2] = [
+    selected(
+        P1_SENDER_OFFSET_SIGN,
+        "sign",
+        "[account u32 LE][index u32 LE][commitment 32][ephemeral commitment 32][message 32]",
+        "[format][sender offset public key 32][s 32][public nonce 32]",
+        Some(SENDER_OFFSET_SIGN_REQUEST_LENGTH),
+        Some(SENDER_OFFSET_SIGN_RESPONSE_LENGTH),
+    ),
+    selected(
+        P1_SCRIPT_OFFSET,
+        "script offset",
+        "[session nonce u64 LE][account u32 LE][input count][output count][script key index u32 LE] * input \
+         count[sender offset key index u32 LE] * output count",
+        "[format][script offset 32]",
+        None,
+        Some(SCRIPT_OFFSET_RESPONSE_LENGTH),
+    ),
+];
+const KERNEL_SIGNATURE_MESSAGES: [MessageSpec; 2] = [
+    selected(
+        P1_KERNEL_NONCE,
+        "nonce",
+        "[input count][output count][fee u64 LE][lock height u64 LE][mask index u32 LE] * (input count + output count)",
+        "[format][public excess 32][public nonce 32]",
+        None,
+        Some(KERNEL_NONCE_RESPONSE_LENGTH),
+    ),
+    selected(
+        P1_KERNEL_SIGN,
+        "sign",
+        "[total public nonce 32][total public excess 32][kernel message 32]",
+        "[format][s 32]",
+        Some(KERNEL_SIGN_REQUEST_LENGTH),
+        Some(KERNEL_SIGN_RESPONSE_LENGTH),
+    ),
+];
+const NONCE_POOL_MESSAGES: [MessageSpec; 3] = [
+    selected(
+        P1_NONCE_POOL_FETCH,
+        "fetch",
+        "[count]",
+        "[format][pool id u64 LE][first nonce id][public nonce 32] * count",
+        Some(NONCE_POOL_FETCH_REQUEST_LENGTH),
+        None,
+    ),
+    selected(
+        P1_NONCE_POOL_SIGN,
+        "sign",
+        "[pool id u64 LE][nonce id][input count][output count][mask index u32 LE] * (input count + output \
+         count)[total public nonce 32][total public excess 32][fee u64 LE][lock height u64 LE]",
+        "[format][s 32]",
+        None,
+        Some(NONCE_POOL_SIGN_RESPONSE_LENGTH),
+    ),
+    selected(
+        P1_NONCE_POOL_INVALIDATE,
+        "invalidate",
+        "empty",
+        "[format]",
+        Some(0),
+        Some(NONCE_POOL_INVALIDATE_RESPONSE_LENGTH),
+    ),
+];
+const APP_SETTINGS_MESSAGES: [MessageSpec; 2] = [
+    selected(
+        P1_SETTINGS_GET,
+        "read",
+        "empty",
+        "[format][settings][host enabled]",
+        Some(0),
+        Some(GET_SETTINGS_RESPONSE_LENGTH),
+    ),
+    selected(
+        P1_SETTINGS_SET,
+        "change",
+        "[settings][mask]",
+        "[format][settings], once the user confirms",
+        Some(SET_SETTINGS_REQUEST_LENGTH),
+        Some(SET_SETTINGS_RESPONSE_LENGTH),
+    ),
+];
+
+impl Instruction {
+    /// Every instruction, in the order of their codes
+    pub const ALL: [Self; 24] = [
+        Self::GetVersion,
+        Self::Sign,
+        Self::Commitment,
+        Self::BPData,
+        Self::ClientVersion,
+        Self::TransactionSummary,
+        Self::SignOutput,
+        Self::SwapLock,
+        Self::SwapPreimage,
+        Self::GetCapabilities,
+        Self::GetPublicKeys,
+        Self::BatchCommitment,
+        Self::GetSigningCounter,
+        Self::OpenSession,
+        Self::ExportPrivateKey,
+        Self::DisplayHints,
+        Self::GetBlindedPublicKey,
+        Self::SignConfirmedOutput,
+        Self::Pairing,
+        Self::WalletBirthday,
+        Self::SenderOffset,
+        Self::KernelSignature,
+        Self::NoncePool,
+        Self::AppSettings,
+    ];
+
+    /// The P1 and P2 the instruction takes, the layouts of its requests and responses and the status words it is
+    /// answered with, from which the protocol documentation is generated
+    pub fn spec(self) -> InstructionSpec {
+        let spec = |name, summary, capability, messages, status_words| InstructionSpec {
+            instruction: self,
+            name,
+            summary,
+            capability,
+            p1: None,
+            p2: None,
+            messages,
+            status_words,
+        };
+        match self {
+            Self::GetVersion => InstructionSpec {
+                p1: Some("a chunk of an upload the host checks the link with, see P1_CHUNK_*"),
+                ..spec(
+                    "GetVersion",
+                    "Returns the app name and version",
+                    None,
+                    &GET_VERSION_MESSAGES,
+                    &[SW_UPLOAD_CORRUPTED],
+                )
+            },
+            Self::Sign => InstructionSpec {
+                p2: Some("the DerivationVersion of the app key, with the derivation versions capability"),
+                ..spec(
+                    "Sign",
+                    "Signs a 32-byte challenge with the app key",
+                    None,
+                    &SIGN_MESSAGES,
+                    &[SW_SETTING_DISABLED, SW_CONVERSION_ERROR],
+                )
+            },
+            Self::Commitment => spec(
+                "Commitment",
+                "Returns a commitment to a u64 value, masked with the app key",
+                None,
+                &COMMITMENT_MESSAGES,
+                &[],
+            ),
+            Self::BPData => spec(
+                "BPData",
+                "Multiplies a bulletproof scalar with the app key",
+                Some(Capabilities::BULLETPROOF_COSIGNING),
+                &BP_DATA_MESSAGES,
+                &[SW_SETTING_DISABLED],
+            ),
+            Self::ClientVersion => spec(
+                "ClientVersion",
+                "Exchanges client and app versions",
+                None,
+                &CLIENT_VERSION_MESSAGES,
+                &[SW_CLIENT_VERSION_REJECTED],
+            ),
+            Self::TransactionSummary => spec(
+                "TransactionSummary",
+                "Shows a transaction summary to the user and, once confirmed, allows its outputs to be signed, or \
+                 drops the approved one",
+                Some(Capabilities::BATCH_SIGNING),
+                &TRANSACTION_SUMMARY_MESSAGES,
+                &[SW_INCORRECT_BYTE_LENGTH, SW_USER_REJECTED],
+            ),
+            Self::SignOutput => spec(
+                "SignOutput",
+                "Signs the script challenge of one output of the approved transaction",
+                Some(Capabilities::BATCH_SIGNING),
+                &SIGN_OUTPUT_MESSAGES,
+                &[SW_TRANSACTION_NOT_APPROVED],
+            ),
+            Self::SwapLock => spec(
+                "SwapLock",
+                "Returns the hash lock of an atomic swap, derived on the device from the swap id",
+                Some(Capabilities::ATOMIC_SWAP),
+                &SWAP_LOCK_MESSAGES,
+                &[],
+            ),
+            Self::SwapPreimage => spec(
+                "SwapPreimage",
+                "Reveals the preimage of a SwapLock hash once the user confirms",
+                Some(Capabilities::ATOMIC_SWAP),
+                &SWAP_PREIMAGE_MESSAGES,
+                &[SW_USER_REJECTED],
+            ),
+            Self::GetCapabilities => spec(
+                "GetCapabilities",
+                "Returns the capabilities of the app",
+                None,
+                &GET_CAPABILITIES_MESSAGES,
+                &[],
+            ),
+            Self::GetPublicKeys => InstructionSpec {
+                p1: Some("the KeyBranch of the keys"),
+                p2: Some(DERIVATION_VERSION_P2),
+                ..spec(
+                    "GetPublicKeys",
+                    "Returns a run of consecutive public keys of an account",
+                    Some(Capabilities::PUBLIC_KEY_EXPORT),
+                    &GET_PUBLIC_KEYS_MESSAGES,
+                    &[SW_CONVERSION_ERROR],
+                )
+            },
+            Self::BatchCommitment => InstructionSpec {
+                p2: Some(DERIVATION_VERSION_P2),
+                ..spec(
+                    "BatchCommitment",
+                    "Returns commitments to a list of values, each masked with its own key",
+                    Some(Capabilities::BATCH_COMMITMENTS),
+                    &BATCH_COMMITMENT_MESSAGES,
+                    &[SW_CONVERSION_ERROR],
+                )
+            },
+            Self::GetSigningCounter => spec(
+                "GetSigningCounter",
+                "Returns how many signatures the app has produced since it was installed",
+                Some(Capabilities::SIGNING_COUNTER),
+                &GET_SIGNING_COUNTER_MESSAGES,
+                &[SW_CONVERSION_ERROR],
+            ),
+            Self::OpenSession => spec(
+                "OpenSession",
+                "Starts an authenticated session, after which every command and response carries a MAC",
+                Some(Capabilities::AUTHENTICATED_SESSION),
+                &OPEN_SESSION_MESSAGES,
+                &[SW_CONVERSION_ERROR],
+            ),
+            Self::ExportPrivateKey => InstructionSpec {
+                p1: Some("the SensitiveKey to export"),
+                ..spec(
+                    "ExportPrivateKey",
+                    "Returns one of the sensitive keys, encrypted to a host key",
+                    Some(Capabilities::ENCRYPTED_KEY_EXPORT),
+                    &EXPORT_PRIVATE_KEY_MESSAGES,
+                    &[SW_CONVERSION_ERROR, SW_USER_REJECTED],
+                )
+            },
+            Self::DisplayHints => spec(
+                "DisplayHints",
+                "Announces the pages shown for the next transaction summary",
+                Some(Capabilities::DISPLAY_HINTS),
+                &DISPLAY_HINTS_MESSAGES,
+                &[SW_CONVERSION_ERROR],
+            ),
+            Self::GetBlindedPublicKey => spec(
+                "GetBlindedPublicKey",
+                "Returns a public key without revealing its path or the key itself to anything watching the link",
+                Some(Capabilities::BLINDED_KEYS),
+                &GET_BLINDED_PUBLIC_KEY_MESSAGES,
+                &[SW_CONVERSION_ERROR],
+            ),
+            Self::SignConfirmedOutput => spec(
+                "SignConfirmedOutput",
+                "Shows the amount and recipient of one output of the approved transaction and signs it once the user \
+                 confirms",
+                Some(Capabilities::OUTPUT_CONFIRMATION),
+                &SIGN_CONFIRMED_OUTPUT_MESSAGES,
+                &[SW_TRANSACTION_NOT_APPROVED, SW_USER_REJECTED],
+            ),
+            Self::Pairing => spec(
+                "Pairing",
+                "Registers the pairing secret of a host, or checks that the host holds it, inside a session",
+                Some(Capabilities::PAIRING),
+                &PAIRING_MESSAGES,
+                &[SW_PAIRING_FAILED, SW_USER_REJECTED, SW_CONVERSION_ERROR],
+            ),
+            Self::WalletBirthday => spec(
+                "WalletBirthday",
+                "Returns or records the block height the wallet was created at",
+                Some(Capabilities::WALLET_BIRTHDAY),
+                &WALLET_BIRTHDAY_MESSAGES,
+                &[SW_USER_REJECTED, SW_CONVERSION_ERROR],
+            ),
+            Self::SenderOffset => InstructionSpec {
+                p2: Some(DERIVATION_VERSION_P2),
+                ..spec(
+                    "SenderOffset",
+                    "Signs with a sender offset key, or returns the script offset of the approved transaction",
+                    Some(Capabilities::SENDER_OFFSETS),
+                    &SENDER_OFFSET_MESSAGES,
+                    &[SW_CONVERSION_ERROR, SW_TRANSACTION_NOT_APPROVED],
+                )
+            },
+            Self::KernelSignature => InstructionSpec {
+                p2: Some(DERIVATION_VERSION_P2),
+                ..spec(
+                    "KernelSignature",
+                    "Takes the excess of a set of commitment masks and signs a kernel with it once",
+                    Some(Capabilities::KERNEL_SIGNATURES),
+                    &KERNEL_SIGNATURE_MESSAGES,
+                    &[SW_CONVERSION_ERROR, SW_TRANSACTION_NOT_APPROVED, SW_USER_REJECTED],
+                )
+            },
+            Self::NoncePool => InstructionSpec {
+                p2: Some("the DerivationVersion of the masks when signing"),
+                ..spec(
+                    "NoncePool",
+                    "Issues public nonces ahead of the kernel signatures that use them",
+                    Some(Capabilities::NONCE_POOL),
+                    &NONCE_POOL_MESSAGES,
+                    &[
+                        SW_CONVERSION_ERROR,
+                        SW_TRANSACTION_NOT_APPROVED,
+                        SW_USER_REJECTED,
+                        SW_NONCE_NOT_ISSUED,
+                    ],
+                )
+            },
+            Self::AppSettings => spec(
+                "AppSettings",
+                "Returns the app settings, or changes them once the user confirms",
+                Some(Capabilities::SETTINGS_CHANGES),
+                &APP_SETTINGS_MESSAGES,
+                &[SW_CONVERSION_ERROR, SW_USER_REJECTED],
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use std::string::ToString;
+
+    use super::*;
+
+    #[test]
+    fn crc32_matches_the_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn responses_are_parsed_by_length_and_format() {
+        let response = [RESPONSE_FORMAT_VERSION, 1, 2, 3];
+        assert_eq!(response_payload(&response, 4), Ok(&response[1..]));
+        assert_eq!(
+            response_payload(&response, 3),
+            Err(ProtocolError::TrailingBytes { expected: 3, actual: 4 })
+        );
+        assert_eq!(parse_response(&response, 3, Strictness::Lenient), Ok(&response[1..3]));
+        assert_eq!(
+            parse_response(&response, 5, Strictness::Lenient),
+            Err(ProtocolError::IncorrectLength { expected: 5, actual: 4 })
+        );
+        assert_eq!(
+            parse_response(&response, 0, Strictness::Lenient),
+            Err(ProtocolError::IncorrectLength { expected: 0, actual: 4 })
+        );
+        assert_eq!(
+            response_payload(&[RESPONSE_FORMAT_VERSION + 1, 1], 2),
+            Err(ProtocolError::UnsupportedFormat(RESPONSE_FORMAT_VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn versions_round_trip() {
+        let version = SemanticVersion::new(1, 258, 65535);
+        assert_eq!(version.to_le_bytes(), [1, 0, 2, 1, 255, 255]);
+        assert_eq!(SemanticVersion::from_le_bytes(&version.to_le_bytes()), Ok(version));
+        assert_eq!(
+            SemanticVersion::from_le_bytes(&[0; 5]),
+            Err(ProtocolError::IncorrectLength { expected: 6, actual: 5 })
+        );
+        assert_eq!("1.2.3-rc.1+abc".parse(), Ok(SemanticVersion::new(1, 2, 3)));
+        assert_eq!("1.2".parse(), Ok(SemanticVersion::new(1, 2, 0)));
+        assert!("1.x.3".parse::<SemanticVersion>().is_err());
+        assert_eq!(SemanticVersion::new(1, 2, 3).to_string(), "1.2.3");
+    }
+
+    #[test]
+    fn versions_are_ordered_by_major_minor_patch() {
+        assert!(SemanticVersion::new(1, 0, 0) > SemanticVersion::new(0, 9, 9));
+        assert!(SemanticVersion::new(0, 10, 0) > SemanticVersion::new(0, 9, 9));
+        assert!(SemanticVersion::new(0, 9, 10) > SemanticVersion::new(0, 9, 9));
+    }
+
+    #[test]
+    fn summaries_and_capabilities_round_trip() {
+        let summary = TransactionSummary {
+            total_out: 1_000_000,
+            fee: 25,
+            recipient_count: 2,
+            output_count: 3,
+            session_nonce: u64::MAX - 1,
+        };
+        assert_eq!(TransactionSummary::from_le_bytes(&summary.to_le_bytes()), Ok(summary));
+        assert!(TransactionSummary::from_le_bytes(&[0; 25]).is_err());
+
+        // Bits this version does not name are kept
+        let capabilities = Capabilities::NONCE_POOL | Capabilities::from_bits(1 << 31);
+        assert_eq!(
+            Capabilities::from_le_bytes(&capabilities.to_le_bytes()),
+            Ok(capabilities)
+        );
+        assert!(capabilities.contains(Capabilities::NONCE_POOL));
+        assert!(!capabilities.contains(Capabilities::ATOMIC_SWAP));
+    }
+
+    #[test]
+    fn instructions_round_trip_and_document_their_status_words() {
+        for instruction in Instruction::ALL {
+            assert_eq!(Instruction::try_from(instruction.as_byte()), Ok(instruction));
+            let spec = instruction.spec();
+            assert_eq!(spec.instruction, instruction);
+            assert!(!spec.messages.is_empty(), "{} has no messages", spec.name);
+            for code in spec.status_words.iter().chain(COMMON_STATUS_WORDS.iter()) {
+                assert!(
+                    status_word_spec(*code).is_some(),
+                    "{} answers an unknown {:04x}",
+                    spec.name,
+                    code
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn hash_labels_are_unique() {
+        for (i, label) in APP_HASH_LABELS.iter().enumerate() {
+            assert!(!APP_HASH_LABELS[i + 1..].contains(label), "{} is used twice", label);
+        }
+    }
+
+    #[test]
+    fn the_largest_requests_fit_into_a_single_apdu() {
+        assert!(script_offset_request_length(1, MAX_SCRIPT_OFFSET_KEYS as u8 - 1) <= MAX_CHUNK_LENGTH);
+        assert!(kernel_nonce_request_length(1, MAX_KERNEL_EXCESS_KEYS as u8 - 1) <= MAX_CHUNK_LENGTH);
+        assert!(nonce_pool_sign_request_length(1, MAX_POOLED_KERNEL_KEYS as u8 - 1) <= MAX_CHUNK_LENGTH);
+        assert!(nonce_pool_fetch_response_length(MAX_NONCE_POOL_FETCH as u8) <= MAX_CHUNK_LENGTH);
+    }
+}